@@ -0,0 +1,13 @@
+CREATE TABLE audit_logs
+(
+    id         SERIAL PRIMARY KEY,
+    actor      TEXT        NOT NULL,
+    action     TEXT        NOT NULL,
+    entity     TEXT        NOT NULL,
+    entity_id  INTEGER     NOT NULL,
+    old_value  JSONB,
+    new_value  JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_logs_entity_idx ON audit_logs (entity, entity_id, created_at);
@@ -0,0 +1,11 @@
+CREATE TABLE todo_revisions
+(
+    id         SERIAL PRIMARY KEY,
+    todo_id    INTEGER     NOT NULL REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+    text       TEXT        NOT NULL,
+    completed  BOOLEAN     NOT NULL,
+    labels     JSONB       NOT NULL DEFAULT '[]',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX todo_revisions_todo_id_idx ON todo_revisions (todo_id, id);
@@ -0,0 +1,4 @@
+ALTER TABLE todos
+    ADD COLUMN remind_at TIMESTAMPTZ;
+
+CREATE INDEX todos_remind_at_idx ON todos (remind_at) WHERE remind_at IS NOT NULL;
@@ -0,0 +1,8 @@
+CREATE TABLE users
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+ALTER TABLE todos
+    ADD COLUMN assignee_id INTEGER REFERENCES users (id);
@@ -0,0 +1,10 @@
+CREATE TABLE projects
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+ALTER TABLE todos
+    ADD COLUMN project_id INTEGER REFERENCES projects (id) ON DELETE SET NULL;
+
+CREATE INDEX todos_project_id_idx ON todos (project_id);
@@ -0,0 +1,34 @@
+ALTER TABLE todos
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'backlog';
+
+UPDATE todos
+SET status = 'done'
+WHERE completed;
+
+ALTER TABLE todos
+    DROP COLUMN completed;
+
+ALTER TABLE todo_revisions
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'backlog';
+
+UPDATE todo_revisions
+SET status = 'done'
+WHERE completed;
+
+ALTER TABLE todo_revisions
+    DROP COLUMN completed;
+
+-- 取り消し時に状態を復元できるよう、記録済みのtodoにもstatusを補う
+UPDATE audit_logs
+SET old_value = old_value || jsonb_build_object('status',
+        CASE WHEN (old_value ->> 'completed')::boolean THEN 'done' ELSE 'backlog' END)
+WHERE entity = 'todo'
+  AND old_value IS NOT NULL
+  AND NOT old_value ? 'status';
+
+UPDATE audit_logs
+SET new_value = new_value || jsonb_build_object('status',
+        CASE WHEN (new_value ->> 'completed')::boolean THEN 'done' ELSE 'backlog' END)
+WHERE entity = 'todo'
+  AND new_value IS NOT NULL
+  AND NOT new_value ? 'status';
@@ -0,0 +1,20 @@
+-- 時刻順に並ぶUUID v7を生成する
+CREATE FUNCTION uuid_generate_v7() RETURNS uuid AS
+$$
+SELECT encode(
+               set_bit(
+                       set_bit(
+                               overlay(uuid_send(gen_random_uuid())
+                                       placing substring(int8send(floor(extract(epoch FROM clock_timestamp()) * 1000)::bigint) FROM 3)
+                                       FROM 1 FOR 6),
+                               52, 1),
+                       53, 1),
+               'hex')::uuid;
+$$ LANGUAGE sql VOLATILE;
+
+-- 連番のidは外部キーとしてそのまま使い、リージョンをまたいでも衝突しない識別子を別に持つ
+ALTER TABLE todos
+    ADD COLUMN uuid UUID NOT NULL UNIQUE DEFAULT uuid_generate_v7();
+
+ALTER TABLE labels
+    ADD COLUMN uuid UUID NOT NULL UNIQUE DEFAULT uuid_generate_v7();
@@ -0,0 +1,45 @@
+ALTER TABLE todos
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp();
+
+CREATE FUNCTION touch_updated_at() RETURNS trigger AS
+$$
+BEGIN
+    NEW.updated_at = clock_timestamp();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_touch_updated_at
+    BEFORE UPDATE
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION touch_updated_at();
+
+-- 削除も含めてtodoの一覧が最後に変わった時刻
+CREATE TABLE todos_modified
+(
+    modified_at TIMESTAMPTZ NOT NULL
+);
+
+INSERT INTO todos_modified (modified_at)
+VALUES (clock_timestamp());
+
+CREATE FUNCTION touch_todos_modified() RETURNS trigger AS
+$$
+BEGIN
+    UPDATE todos_modified SET modified_at = clock_timestamp();
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_touch_modified
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todos
+    FOR EACH STATEMENT
+EXECUTE FUNCTION touch_todos_modified();
+
+CREATE TRIGGER todo_labels_touch_modified
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todo_labels
+    FOR EACH STATEMENT
+EXECUTE FUNCTION touch_todos_modified();
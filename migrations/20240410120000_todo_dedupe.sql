@@ -0,0 +1,10 @@
+ALTER TABLE todos
+    ADD COLUMN deduplicated BOOLEAN NOT NULL DEFAULT false;
+
+-- 未完了のtodoを本文で探す
+CREATE INDEX todos_open_text ON todos (text)
+    WHERE status IN ('backlog', 'in_progress');
+
+-- 重複を許さないモードで作成した未完了のtodoは、同時に作成されても本文が重ならない
+CREATE UNIQUE INDEX todos_open_text_unique ON todos (text)
+    WHERE deduplicated AND status IN ('backlog', 'in_progress');
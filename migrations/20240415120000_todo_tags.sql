@@ -0,0 +1,5 @@
+-- ラベルを作らずに付けられる自由入力のタグ
+ALTER TABLE todos
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX todos_tags ON todos USING GIN (tags);
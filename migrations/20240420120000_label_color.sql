@@ -0,0 +1,4 @@
+-- ラベルの表示色(#RRGGBB)と任意の説明
+ALTER TABLE labels
+    ADD COLUMN color       TEXT NOT NULL DEFAULT '#808080' CHECK (color ~ '^#[0-9a-f]{6}$'),
+    ADD COLUMN description TEXT;
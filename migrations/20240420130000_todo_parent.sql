@@ -0,0 +1,5 @@
+-- todoの親子関係。親を削除すると子は最上位に戻る
+ALTER TABLE todos
+    ADD COLUMN parent_id INTEGER REFERENCES todos (id) ON DELETE SET NULL;
+
+CREATE INDEX todos_parent_id ON todos (parent_id);
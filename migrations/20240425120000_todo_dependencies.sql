@@ -0,0 +1,53 @@
+-- blocker_idのtodoが完了するまでblocked_idのtodoに取りかかれない
+CREATE TABLE todo_dependencies
+(
+    blocker_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    blocked_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    PRIMARY KEY (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX todo_dependencies_blocked_id ON todo_dependencies (blocked_id);
+
+CREATE TRIGGER todo_dependencies_touch_modified
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todo_dependencies
+    FOR EACH STATEMENT
+EXECUTE FUNCTION touch_todos_modified();
+
+-- blockedは依存関係とブロックしているtodoの状態から導くので、
+-- どちらかが変わったらブロックされているtodoの更新時刻も進める
+CREATE FUNCTION touch_blocked_todo() RETURNS trigger AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE todos SET updated_at = clock_timestamp() WHERE id = OLD.blocked_id;
+    ELSE
+        UPDATE todos SET updated_at = clock_timestamp() WHERE id = NEW.blocked_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todo_dependencies_touch_blocked
+    AFTER INSERT OR DELETE
+    ON todo_dependencies
+    FOR EACH ROW
+EXECUTE FUNCTION touch_blocked_todo();
+
+CREATE FUNCTION touch_dependent_todos() RETURNS trigger AS
+$$
+BEGIN
+    UPDATE todos
+    SET updated_at = clock_timestamp()
+    WHERE id IN (SELECT blocked_id FROM todo_dependencies WHERE blocker_id = NEW.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_touch_dependents
+    AFTER UPDATE OF status
+    ON todos
+    FOR EACH ROW
+    WHEN (OLD.status IS DISTINCT FROM NEW.status)
+EXECUTE FUNCTION touch_dependent_todos();
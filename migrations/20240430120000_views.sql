@@ -0,0 +1,7 @@
+-- 名前を付けて保存したtodoの絞り込み条件
+CREATE TABLE views
+(
+    id     SERIAL PRIMARY KEY,
+    name   TEXT  NOT NULL,
+    filter JSONB NOT NULL DEFAULT '{}'
+);
@@ -0,0 +1,36 @@
+-- todoの作成日時と完了日時。作成から完了までの時間を集計するのに使う
+ALTER TABLE todos
+    ADD COLUMN created_at   TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp(),
+    ADD COLUMN completed_at TIMESTAMPTZ;
+
+-- 記録済みのtodoは最初の版の時刻を作成日時とし、完了済みのものは最後の更新日時を完了日時とする
+-- 補うだけで変更ではないので更新日時は進めない
+ALTER TABLE todos DISABLE TRIGGER todos_touch_updated_at;
+
+UPDATE todos
+SET created_at   = coalesce((SELECT min(created_at) FROM todo_revisions WHERE todo_id = todos.id), updated_at),
+    completed_at = CASE WHEN status = 'done' THEN updated_at END;
+
+ALTER TABLE todos ENABLE TRIGGER todos_touch_updated_at;
+
+-- 完了にしたときに完了日時を記録し、完了以外に戻したときは消す
+-- 完了のまま更新した場合は最初に完了した日時を残す
+CREATE FUNCTION set_completed_at() RETURNS trigger AS
+$$
+BEGIN
+    IF NEW.status <> 'done' THEN
+        NEW.completed_at = NULL;
+    ELSIF TG_OP = 'INSERT' OR OLD.status <> 'done' THEN
+        NEW.completed_at = clock_timestamp();
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_set_completed_at
+    BEFORE INSERT OR UPDATE OF status
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION set_completed_at();
+
+CREATE INDEX todos_completed_at ON todos (completed_at) WHERE completed_at IS NOT NULL;
@@ -0,0 +1,14 @@
+-- イベントソーシングで保存するtodoの変更
+-- 1回の操作でまとめて起きた変更を1行とし、追記するだけで書き換えない
+CREATE TABLE todo_events
+(
+    seq         BIGSERIAL PRIMARY KEY,
+    todo_id     INTEGER     NOT NULL,
+    events      JSONB       NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX todo_events_todo_id ON todo_events (todo_id);
+
+-- todoのidはイベントより先に採番する
+CREATE SEQUENCE todo_event_ids;
@@ -0,0 +1,17 @@
+-- 繰り返し作るtodoのひな形。本文の {{name}} は作成時に置き換える
+CREATE TABLE templates
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT   NOT NULL,
+    text TEXT   NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}'
+);
+
+-- 作成するtodoに付けるラベル
+CREATE TABLE template_labels
+(
+    id          SERIAL PRIMARY KEY,
+    template_id INTEGER NOT NULL REFERENCES templates (id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED,
+    label_id    INTEGER NOT NULL REFERENCES labels (id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED,
+    UNIQUE (template_id, label_id)
+);
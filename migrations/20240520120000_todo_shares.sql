@@ -0,0 +1,21 @@
+-- todoの所有者。所有者のいないtodoは従来どおり全員に見える
+ALTER TABLE todos
+    ADD COLUMN owner_id INTEGER REFERENCES users (id);
+
+-- 所有者以外のユーザーへの共有
+CREATE TABLE todo_shares
+(
+    todo_id    INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    permission TEXT    NOT NULL CHECK (permission IN ('read', 'write')),
+    PRIMARY KEY (todo_id, user_id)
+);
+
+CREATE INDEX todo_shares_user_id ON todo_shares (user_id);
+
+-- 共有が変わると見えるtodoも変わるため、一覧の変更時刻を進める
+CREATE TRIGGER todo_shares_touch_modified
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todo_shares
+    FOR EACH STATEMENT
+EXECUTE FUNCTION touch_todos_modified();
@@ -0,0 +1,11 @@
+-- アカウントを持たない相手にtodoを見せるための公開リンク
+-- イベントで保存する場合もあるため、todosへの外部キーは張らずに参照時に存在を確かめる
+CREATE TABLE share_links
+(
+    token      TEXT PRIMARY KEY,
+    todo_id    INTEGER     NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX share_links_todo_id ON share_links (todo_id);
@@ -0,0 +1,9 @@
+-- ラベル一覧のETagを作るため、ラベルの更新時刻を記録する
+ALTER TABLE labels
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp();
+
+CREATE TRIGGER labels_touch_updated_at
+    BEFORE UPDATE
+    ON labels
+    FOR EACH ROW
+EXECUTE FUNCTION touch_updated_at();
@@ -0,0 +1,5 @@
+-- 入力途中の文字列からtodoを探す候補表示のため、本文にトライグラムの索引を張る
+-- テストはスキーマごとにマイグレーションするので、拡張はpublicにまとめて入れる
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+CREATE INDEX todos_text_trgm_idx ON todos USING gin (text public.gin_trgm_ops);
@@ -0,0 +1,8 @@
+-- リクエスト主体ごとの1日のリクエスト数
+CREATE TABLE api_usage
+(
+    principal TEXT   NOT NULL,
+    day       DATE   NOT NULL,
+    requests  BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (principal, day)
+);
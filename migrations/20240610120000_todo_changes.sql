@@ -0,0 +1,73 @@
+-- 差分同期のためのtodoの変更ログ
+-- 同期トークンはトランザクションIDなので、コミット順と採番順が前後しても取りこぼさない
+CREATE TABLE todo_changes
+(
+    seq        BIGSERIAL PRIMARY KEY,
+    xact_id    XID8        NOT NULL DEFAULT pg_current_xact_id(),
+    todo_id    INTEGER     NOT NULL,
+    uuid       UUID        NOT NULL,
+    operation  TEXT        NOT NULL CHECK (operation IN ('insert', 'update', 'delete')),
+    -- 変更時点の所有者。削除後も見える範囲を決められるよう残す
+    owner_id   INTEGER,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX todo_changes_xact_id ON todo_changes (xact_id);
+
+-- 既存のtodoは作成として記録する
+INSERT INTO todo_changes (todo_id, uuid, operation, owner_id)
+SELECT id, uuid, 'insert', owner_id
+FROM todos
+ORDER BY id;
+
+CREATE FUNCTION record_todo_change() RETURNS trigger AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO todo_changes (todo_id, uuid, operation, owner_id)
+        VALUES (OLD.id, OLD.uuid, 'delete', OLD.owner_id);
+    ELSE
+        INSERT INTO todo_changes (todo_id, uuid, operation, owner_id)
+        VALUES (NEW.id, NEW.uuid, lower(TG_OP), NEW.owner_id);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_record_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION record_todo_change();
+
+-- ラベルや共有の付け外しはtodoの変更として記録する
+-- todoごと削除した場合はtodoが見つからないため記録しない
+CREATE FUNCTION record_todo_relation_change() RETURNS trigger AS
+$$
+DECLARE
+    changed_id INTEGER;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed_id = OLD.todo_id;
+    ELSE
+        changed_id = NEW.todo_id;
+    END IF;
+    INSERT INTO todo_changes (todo_id, uuid, operation, owner_id)
+    SELECT id, uuid, 'update', owner_id
+    FROM todos
+    WHERE id = changed_id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todo_labels_record_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todo_labels
+    FOR EACH ROW
+EXECUTE FUNCTION record_todo_relation_change();
+
+CREATE TRIGGER todo_shares_record_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todo_shares
+    FOR EACH ROW
+EXECUTE FUNCTION record_todo_relation_change();
@@ -0,0 +1,6 @@
+-- 一覧で表示するtodoのアイコン(絵文字1文字)と色(#RRGGBB)
+ALTER TABLE todos
+    ADD COLUMN icon  TEXT,
+    ADD COLUMN color TEXT CHECK (color ~ '^#[0-9a-f]{6}$');
+
+CREATE INDEX todos_color_idx ON todos (color) WHERE color IS NOT NULL;
@@ -0,0 +1,3 @@
+-- 一覧の先頭に固定するtodo
+ALTER TABLE todos
+    ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,32 @@
+-- ラベルの名前は前後の空白を除き、続いた空白を1つにそろえる
+UPDATE labels
+SET name = regexp_replace(btrim(name), '\s+', ' ', 'g')
+WHERE name <> regexp_replace(btrim(name), '\s+', ' ', 'g');
+
+-- 大文字と小文字の違いだけのラベルは、最も古いものにまとめる
+CREATE TEMPORARY TABLE label_duplicates AS
+SELECT id, canonical_id
+FROM (SELECT id, min(id) OVER (PARTITION BY lower(name)) AS canonical_id FROM labels) grouped
+WHERE id <> canonical_id;
+
+INSERT INTO todo_labels (todo_id, label_id)
+SELECT DISTINCT tl.todo_id, d.canonical_id
+FROM todo_labels tl
+         JOIN label_duplicates d ON d.id = tl.label_id
+WHERE NOT EXISTS (SELECT 1 FROM todo_labels t WHERE t.todo_id = tl.todo_id AND t.label_id = d.canonical_id);
+DELETE FROM todo_labels WHERE label_id IN (SELECT id FROM label_duplicates);
+
+INSERT INTO template_labels (template_id, label_id)
+SELECT DISTINCT tl.template_id, d.canonical_id
+FROM template_labels tl
+         JOIN label_duplicates d ON d.id = tl.label_id
+WHERE NOT EXISTS (SELECT 1
+                  FROM template_labels t
+                  WHERE t.template_id = tl.template_id AND t.label_id = d.canonical_id);
+DELETE FROM template_labels WHERE label_id IN (SELECT id FROM label_duplicates);
+
+DELETE FROM labels WHERE id IN (SELECT id FROM label_duplicates);
+DROP TABLE label_duplicates;
+
+-- 同時に作成されても、大文字と小文字の違いだけのラベルはできない
+CREATE UNIQUE INDEX labels_name_lower_unique ON labels (lower(name));
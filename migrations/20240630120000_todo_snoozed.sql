@@ -0,0 +1,3 @@
+-- 指定した時刻まで一覧から外すtodo
+ALTER TABLE todos
+    ADD COLUMN snoozed_until TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- todoを終えるまでにかかると見積もった時間(分)
+ALTER TABLE todos
+    ADD COLUMN estimate_minutes INTEGER CHECK (estimate_minutes > 0);
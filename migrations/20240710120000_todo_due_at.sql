@@ -0,0 +1,3 @@
+-- todoの期限。過ぎても完了していないものを期限切れとして扱う
+ALTER TABLE todos
+    ADD COLUMN due_at TIMESTAMPTZ;
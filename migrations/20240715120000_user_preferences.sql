@@ -0,0 +1,14 @@
+-- ユーザーごとの設定。項目を増やしてもマイグレーションが要らないようJSONで持つ
+CREATE TABLE user_preferences
+(
+    user_id     INTEGER     NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    preferences JSONB       NOT NULL DEFAULT '{}',
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- 既定の並び順が変わると一覧の返し方も変わるため、一覧の変更時刻を進める
+CREATE TRIGGER user_preferences_touch_modified
+    AFTER INSERT OR UPDATE OR DELETE
+    ON user_preferences
+    FOR EACH STATEMENT
+EXECUTE FUNCTION touch_todos_modified();
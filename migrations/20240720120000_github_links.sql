@@ -0,0 +1,11 @@
+-- todoと、それが写しているGitHubのissueの紐付け
+-- イベントで保存する場合もあるため、todosへの外部キーは張らずに参照時に存在を確かめる
+CREATE TABLE github_links
+(
+    todo_id      INTEGER PRIMARY KEY,
+    repository   TEXT        NOT NULL,
+    issue_number INTEGER     NOT NULL,
+    linked_at    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX github_links_issue ON github_links (repository, issue_number);
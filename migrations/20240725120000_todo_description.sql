@@ -0,0 +1,3 @@
+-- 本文に収まらないtodoの詳細
+ALTER TABLE todos
+    ADD COLUMN description TEXT;
@@ -0,0 +1,7 @@
+-- 受け付けたGitHubのwebhookの配信ID
+-- GitHubの署名は時刻を含まず期限がないので、再送を拒否できるよう配信IDを消さずに残す
+CREATE TABLE github_deliveries
+(
+    delivery_id TEXT PRIMARY KEY,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+-- スヌーズが明けた時刻を一覧の変更時刻に含めるため、最新の明けた時刻を索引で引く
+CREATE INDEX todos_snoozed_until ON todos (snoozed_until);
@@ -0,0 +1,14 @@
+-- 一覧の変更時刻は1行の表に書かず、各行の更新時刻と変更ログから求める
+-- 1行の表を書き込みのたびに更新すると、その行のロックでtodoへの書き込みがすべて直列になる
+DROP TRIGGER todos_touch_modified ON todos;
+DROP TRIGGER todo_labels_touch_modified ON todo_labels;
+DROP TRIGGER todo_dependencies_touch_modified ON todo_dependencies;
+DROP TRIGGER todo_shares_touch_modified ON todo_shares;
+DROP TRIGGER user_preferences_touch_modified ON user_preferences;
+DROP FUNCTION touch_todos_modified();
+DROP TABLE todos_modified;
+
+-- 最新の時刻を索引で引く
+-- 削除や共有の変更はtodoの行に時刻が残らないので、変更ログの時刻を使う
+CREATE INDEX todos_updated_at ON todos (updated_at);
+CREATE INDEX todo_changes_changed_at ON todo_changes (changed_at);
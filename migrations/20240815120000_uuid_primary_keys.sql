@@ -0,0 +1,104 @@
+-- todoとラベルの主キーを、TODO_ID_TYPE=uuid の場合だけUUID v7にする
+-- migrate を実行するときに todos.id_type を設定して渡す。未設定なら連番のまま何もしない
+-- 既にデータがある場合は変換できないので、最初にマイグレーションを適用するときに決める
+
+-- 付け外しで変わったtodoのidは、主キーと同じ型で持つ
+CREATE OR REPLACE FUNCTION record_todo_relation_change() RETURNS trigger AS
+$$
+DECLARE
+    changed_id todos.id%TYPE;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed_id = OLD.todo_id;
+    ELSE
+        changed_id = NEW.todo_id;
+    END IF;
+    INSERT INTO todo_changes (todo_id, uuid, operation, owner_id)
+    SELECT id, uuid, 'update', owner_id
+    FROM todos
+    WHERE id = changed_id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- UUIDの主キーはuuid列と同じ値にする。同期で送られたUUIDもそのままidになる
+CREATE FUNCTION assign_uuid_id() RETURNS trigger AS
+$$
+BEGIN
+    NEW.id = NEW.uuid;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DO
+$$
+BEGIN
+    IF coalesce(current_setting('todos.id_type', true), '') NOT IN ('', 'serial', 'uuid') THEN
+        RAISE EXCEPTION 'unknown todos.id_type [%]', current_setting('todos.id_type', true);
+    END IF;
+    IF coalesce(current_setting('todos.id_type', true), '') <> 'uuid' THEN
+        RETURN;
+    END IF;
+    IF EXISTS (SELECT 1 FROM todos) OR EXISTS (SELECT 1 FROM labels) OR EXISTS (SELECT 1 FROM todo_events) THEN
+        RAISE EXCEPTION 'todos and labels must be empty to switch to uuid keys';
+    END IF;
+
+    ALTER TABLE todo_labels DROP CONSTRAINT todo_labels_todo_id_fkey;
+    ALTER TABLE todo_labels DROP CONSTRAINT todo_labels_label_id_fkey;
+    ALTER TABLE todo_revisions DROP CONSTRAINT todo_revisions_todo_id_fkey;
+    ALTER TABLE todos DROP CONSTRAINT todos_parent_id_fkey;
+    ALTER TABLE todo_dependencies DROP CONSTRAINT todo_dependencies_blocker_id_fkey;
+    ALTER TABLE todo_dependencies DROP CONSTRAINT todo_dependencies_blocked_id_fkey;
+    ALTER TABLE template_labels DROP CONSTRAINT template_labels_label_id_fkey;
+    ALTER TABLE todo_shares DROP CONSTRAINT todo_shares_todo_id_fkey;
+
+    ALTER TABLE todos ALTER COLUMN id DROP DEFAULT;
+    ALTER TABLE labels ALTER COLUMN id DROP DEFAULT;
+    DROP SEQUENCE todos_id_seq;
+    DROP SEQUENCE labels_id_seq;
+    ALTER TABLE todos ALTER COLUMN id TYPE UUID USING NULL;
+    ALTER TABLE labels ALTER COLUMN id TYPE UUID USING NULL;
+    ALTER TABLE todos ALTER COLUMN parent_id TYPE UUID USING NULL;
+    ALTER TABLE todo_labels
+        ALTER COLUMN todo_id TYPE UUID USING NULL,
+        ALTER COLUMN label_id TYPE UUID USING NULL;
+    ALTER TABLE todo_revisions ALTER COLUMN todo_id TYPE UUID USING NULL;
+    -- blocker_id <> blocked_id の制約があるので、2つの列を一度に変える
+    ALTER TABLE todo_dependencies
+        ALTER COLUMN blocker_id TYPE UUID USING NULL,
+        ALTER COLUMN blocked_id TYPE UUID USING NULL;
+    ALTER TABLE template_labels ALTER COLUMN label_id TYPE UUID USING NULL;
+    ALTER TABLE todo_shares ALTER COLUMN todo_id TYPE UUID USING NULL;
+    ALTER TABLE audit_logs ALTER COLUMN entity_id TYPE UUID USING NULL;
+    ALTER TABLE todo_events ALTER COLUMN todo_id TYPE UUID USING NULL;
+    ALTER TABLE todo_changes ALTER COLUMN todo_id TYPE UUID USING NULL;
+    ALTER TABLE share_links ALTER COLUMN todo_id TYPE UUID USING NULL;
+    ALTER TABLE github_links ALTER COLUMN todo_id TYPE UUID USING NULL;
+
+    CREATE TRIGGER todos_assign_uuid_id
+        BEFORE INSERT
+        ON todos
+        FOR EACH ROW
+    EXECUTE FUNCTION assign_uuid_id();
+    CREATE TRIGGER labels_assign_uuid_id
+        BEFORE INSERT
+        ON labels
+        FOR EACH ROW
+    EXECUTE FUNCTION assign_uuid_id();
+
+    ALTER TABLE todo_labels
+        ADD CONSTRAINT todo_labels_todo_id_fkey FOREIGN KEY (todo_id) REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+        ADD CONSTRAINT todo_labels_label_id_fkey FOREIGN KEY (label_id) REFERENCES labels (id) DEFERRABLE INITIALLY DEFERRED;
+    ALTER TABLE todo_revisions
+        ADD CONSTRAINT todo_revisions_todo_id_fkey FOREIGN KEY (todo_id) REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED;
+    ALTER TABLE todos
+        ADD CONSTRAINT todos_parent_id_fkey FOREIGN KEY (parent_id) REFERENCES todos (id) ON DELETE SET NULL;
+    ALTER TABLE todo_dependencies
+        ADD CONSTRAINT todo_dependencies_blocker_id_fkey FOREIGN KEY (blocker_id) REFERENCES todos (id) ON DELETE CASCADE,
+        ADD CONSTRAINT todo_dependencies_blocked_id_fkey FOREIGN KEY (blocked_id) REFERENCES todos (id) ON DELETE CASCADE;
+    ALTER TABLE template_labels
+        ADD CONSTRAINT template_labels_label_id_fkey FOREIGN KEY (label_id) REFERENCES labels (id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED;
+    ALTER TABLE todo_shares
+        ADD CONSTRAINT todo_shares_todo_id_fkey FOREIGN KEY (todo_id) REFERENCES todos (id) ON DELETE CASCADE;
+END;
+$$;
@@ -0,0 +1,5 @@
+-- 重複を許さないモードでも、本文が重なってはいけないのは同じ所有者のtodoどうしだけ
+-- 所有者のいないtodoどうしも重ならないよう、所有者なしを0として扱う
+DROP INDEX todos_open_text_unique;
+CREATE UNIQUE INDEX todos_open_text_unique ON todos (coalesce(owner_id, 0), text)
+    WHERE deduplicated AND status IN ('backlog', 'in_progress');
@@ -0,0 +1,144 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+
+// Atom(RFC 4287)の書き出し
+// フィードリーダーから一覧の更新を追えるようにする
+
+pub const CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feed {
+    pub id: String,
+    pub title: String,
+    pub updated: DateTime<Utc>,
+    pub self_link: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    pub updated: DateTime<Utc>,
+    pub link: String,
+    pub categories: Vec<String>,
+    pub content: String,
+}
+
+impl Feed {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push('\n');
+        write_element(&mut xml, 1, "id", &self.id);
+        write_element(&mut xml, 1, "title", &self.title);
+        write_element(&mut xml, 1, "updated", &format_datetime(self.updated));
+        writeln!(
+            xml,
+            r#"  <link rel="self" href="{}"/>"#,
+            escape(&self.self_link)
+        )
+        .unwrap();
+        xml.push_str("  <author><name>rust-simple-api</name></author>\n");
+        for entry in self.entries.iter() {
+            xml.push_str("  <entry>\n");
+            write_element(&mut xml, 2, "id", &entry.id);
+            write_element(&mut xml, 2, "title", &entry.title);
+            write_element(&mut xml, 2, "updated", &format_datetime(entry.updated));
+            writeln!(
+                xml,
+                r#"    <link rel="alternate" href="{}"/>"#,
+                escape(&entry.link)
+            )
+            .unwrap();
+            for category in entry.categories.iter() {
+                writeln!(xml, r#"    <category term="{}"/>"#, escape(category)).unwrap();
+            }
+            writeln!(
+                xml,
+                r#"    <content type="text">{}</content>"#,
+                escape(&entry.content)
+            )
+            .unwrap();
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+fn write_element(xml: &mut String, depth: usize, name: &str, text: &str) {
+    writeln!(
+        xml,
+        "{}<{name}>{}</{name}>",
+        "  ".repeat(depth),
+        escape(text)
+    )
+    .unwrap();
+}
+
+fn format_datetime(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// 要素の中身と属性値のどちらにも使えるようにエスケープする
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn should_write_feed() {
+        let updated = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let feed = Feed {
+            id: "urn:example:todos".to_string(),
+            title: "todos".to_string(),
+            updated,
+            self_link: "/todos/feed.atom?tag=a&b".to_string(),
+            entries: vec![Entry {
+                id: "urn:uuid:1".to_string(),
+                title: "<b>bold</b> & \"quoted\"".to_string(),
+                updated,
+                link: "/todos/1".to_string(),
+                categories: vec!["home".to_string()],
+                content: "status: backlog".to_string(),
+            }],
+        };
+        assert_eq!(
+            feed.to_xml(),
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>urn:example:todos</id>
+  <title>todos</title>
+  <updated>2024-05-01T09:30:00Z</updated>
+  <link rel="self" href="/todos/feed.atom?tag=a&amp;b"/>
+  <author><name>rust-simple-api</name></author>
+  <entry>
+    <id>urn:uuid:1</id>
+    <title>&lt;b&gt;bold&lt;/b&gt; &amp; &quot;quoted&quot;</title>
+    <updated>2024-05-01T09:30:00Z</updated>
+    <link rel="alternate" href="/todos/1"/>
+    <category term="home"/>
+    <content type="text">status: backlog</content>
+  </entry>
+</feed>
+"#
+        );
+    }
+}
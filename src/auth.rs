@@ -0,0 +1,374 @@
+use crate::handlers::calendar::CALENDAR_PATH;
+use crate::handlers::dav::{DAV_PREFIX, WELL_KNOWN_CALDAV_PATH};
+use crate::handlers::feed::FEED_PATH;
+use crate::handlers::{ApiError, HEALTH_PATH, INTEGRATIONS_PREFIX};
+use axum::body::Body;
+use axum::http::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(AuthError::UnknownRole(s.to_string())),
+        }
+    }
+}
+
+impl Role {
+    // ロールごとのアクセス可否を判定する
+    // viewerは参照のみ、editorはtodoの更新まで、ラベルなどその他のリソースの更新はadminのみ
+    // 監査ログと管理用のエンドポイントは参照もadminのみ
+    // 自分の設定はどのロールでも変えられる
+    // CalDAVのPROPFINDとREPORTは参照として扱う
+    pub fn can_access(&self, method: &Method, path: &str) -> bool {
+        if is_admin_only(path) {
+            return *self == Role::Admin;
+        }
+        if path == "/me" {
+            return true;
+        }
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+            || matches!(method.as_str(), "PROPFIND" | "REPORT")
+        {
+            return true;
+        }
+        match self {
+            Role::Admin => true,
+            Role::Editor => path == "/todos" || path.starts_with("/todos/"),
+            Role::Viewer => false,
+        }
+    }
+}
+
+// 監査ログと管理用のエンドポイント
+fn is_admin_only(path: &str) -> bool {
+    path.starts_with("/audit-logs") || path.starts_with("/admin/")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Principal {
+    // 認証が無効な場合のリクエスト主体
+    // 監査ログと管理用のエンドポイントには、この主体では入れない
+    pub fn anonymous() -> Self {
+        Principal {
+            name: String::from("anonymous"),
+            role: Role::Admin,
+        }
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_PRINCIPAL: Principal;
+}
+
+// リクエスト処理中のタスクであれば、そのリクエスト主体を返す
+pub fn current_principal() -> Option<Principal> {
+    CURRENT_PRINCIPAL
+        .try_with(|principal| principal.clone())
+        .ok()
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("Invalid API key definition: [{0}]")]
+    InvalidDefinition(String),
+    #[error("Unknown role: [{0}]")]
+    UnknownRole(String),
+    #[error("Missing or invalid API key")]
+    Unauthorized,
+    #[error("Role [{0:?}] is not allowed to access this resource")]
+    Forbidden(Role),
+}
+
+impl AuthError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn message_key(&self) -> &'static str {
+        match self {
+            AuthError::Forbidden(_) => "auth.forbidden",
+            _ => "auth.unauthorized",
+        }
+    }
+}
+
+// APIキーとリクエスト主体の対応表
+// API_KEYS="<name>:<key>:<role>,..." の形式で定義し、未定義の場合は認証を行わない
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, Principal>,
+}
+
+impl ApiKeys {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("API_KEYS") {
+            Ok(value) => Ok(ApiKeys::parse(&value)?),
+            Err(_) => Ok(ApiKeys::default()),
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, AuthError> {
+        let mut keys = HashMap::new();
+        for definition in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parts: Vec<&str> = definition.split(':').collect();
+            let [name, key, role] = parts[..] else {
+                return Err(AuthError::InvalidDefinition(definition.to_string()));
+            };
+            let principal = Principal {
+                name: name.to_string(),
+                role: role.parse()?,
+            };
+            keys.insert(key.to_string(), principal);
+        }
+        Ok(ApiKeys { keys })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Principal, AuthError> {
+        if !self.is_enabled() {
+            return Ok(Principal::anonymous());
+        }
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|key| self.keys.get(key.trim()))
+            .cloned()
+            .ok_or(AuthError::Unauthorized)
+    }
+
+    // キーが未定義だと誰でも管理者として通るので、管理用のエンドポイントは閉じたままにする
+    pub fn authenticate_for(
+        &self,
+        authorization: Option<&str>,
+        path: &str,
+    ) -> Result<Principal, AuthError> {
+        if !self.is_enabled() && is_admin_only(path) {
+            return Err(AuthError::Unauthorized);
+        }
+        self.authenticate(authorization)
+    }
+}
+
+pub fn authorize(principal: &Principal, method: &Method, path: &str) -> Result<(), AuthError> {
+    if principal.role.can_access(method, path) {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden(principal.role))
+    }
+}
+
+// カレンダーアプリやフィードリーダーは購読時にヘッダーを付けられないので、
+// 購読用のURLだけはクエリのtokenも受け付ける
+fn query_token(req: &Request<Body>) -> Option<&str> {
+    if ![CALENDAR_PATH, FEED_PATH].contains(&req.uri().path()) {
+        return None;
+    }
+    req.uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+fn is_dav(req: &Request<Body>) -> bool {
+    req.uri().path().starts_with(DAV_PREFIX) || req.uri().path() == WELL_KNOWN_CALDAV_PATH
+}
+
+// CalDAVのクライアントはBearerを送れないので、DAVの入り口だけは
+// Basic認証のパスワードとして送られたAPIキーも受け付ける。ユーザー名は見ない
+fn basic_token(req: &Request<Body>) -> Option<String> {
+    if !is_dav(req) {
+        return None;
+    }
+    let encoded = req
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (_, key) = decoded.split_once(':')?;
+    Some(key.to_string())
+}
+
+// 認証・認可を行い、成功した場合はリクエスト主体をExtensionとしてハンドラに渡す
+pub async fn require_role(mut req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    // 公開リンクはアカウントを持たない相手に渡すので認証しない
+    // 死活監視もキーを持たないロードバランサーなどから呼ばれるので認証しない
+    // 連携の受け口はハンドラーで署名を確かめる
+    if req.uri().path().starts_with("/shared/")
+        || req.uri().path() == HEALTH_PATH
+        || req.uri().path().starts_with(INTEGRATIONS_PREFIX)
+    {
+        return Ok(next.run(req).await);
+    }
+    let api_keys = req
+        .extensions()
+        .get::<Arc<ApiKeys>>()
+        .cloned()
+        .unwrap_or_default();
+    let authorization = basic_token(&req)
+        .map(|key| format!("Bearer {}", key))
+        .or_else(|| {
+            req.headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        })
+        .or_else(|| query_token(&req).map(|token| format!("Bearer {}", token)));
+
+    let principal = api_keys
+        .authenticate_for(authorization.as_deref(), req.uri().path())
+        .and_then(|principal| {
+            authorize(&principal, req.method(), req.uri().path())?;
+            Ok(principal)
+        })
+        .map_err(|e| {
+            let mut res = ApiError::new(e.status(), e.message_key()).into_response();
+            // CalDAVのクライアントはこのヘッダーを見てパスワードを尋ねる
+            if is_dav(&req) && e == AuthError::Unauthorized {
+                res.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="todos""#),
+                );
+            }
+            res
+        })?;
+
+    req.extensions_mut().insert(principal.clone());
+    Ok::<_, Response>(CURRENT_PRINCIPAL.scope(principal, next.run(req)).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn principal(role: Role) -> Principal {
+        Principal {
+            name: String::from("tester"),
+            role,
+        }
+    }
+
+    #[test]
+    fn viewer_can_only_read() {
+        let viewer = principal(Role::Viewer);
+        assert!(authorize(&viewer, &Method::GET, "/todos").is_ok());
+        assert!(authorize(&viewer, &Method::GET, "/labels").is_ok());
+        assert_eq!(
+            authorize(&viewer, &Method::POST, "/todos"),
+            Err(AuthError::Forbidden(Role::Viewer))
+        );
+        assert!(authorize(&viewer, &Method::DELETE, "/labels/1").is_err());
+        assert!(authorize(&viewer, &Method::GET, "/audit-logs").is_err());
+        assert!(authorize(&viewer, &Method::PATCH, "/me").is_ok());
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        assert!(authorize(&viewer, &propfind, "/dav/todos/").is_ok());
+        assert!(authorize(&viewer, &Method::PUT, "/dav/todos/a.ics").is_err());
+    }
+
+    #[test]
+    fn editor_can_mutate_todos_but_not_labels() {
+        let editor = principal(Role::Editor);
+        assert!(authorize(&editor, &Method::POST, "/todos").is_ok());
+        assert!(authorize(&editor, &Method::PATCH, "/todos/1").is_ok());
+        assert!(authorize(&editor, &Method::DELETE, "/todos/1").is_ok());
+        assert!(authorize(&editor, &Method::POST, "/labels").is_err());
+        assert!(authorize(&editor, &Method::DELETE, "/labels/1").is_err());
+        assert!(authorize(&editor, &Method::POST, "/todosx").is_err());
+        assert!(authorize(&editor, &Method::GET, "/audit-logs").is_err());
+        assert!(authorize(&editor, &Method::GET, "/admin/faults").is_err());
+    }
+
+    #[test]
+    fn admin_can_mutate_everything() {
+        let admin = principal(Role::Admin);
+        assert!(authorize(&admin, &Method::POST, "/todos").is_ok());
+        assert!(authorize(&admin, &Method::POST, "/labels").is_ok());
+        assert!(authorize(&admin, &Method::DELETE, "/labels/1").is_ok());
+        assert!(authorize(&admin, &Method::GET, "/audit-logs").is_ok());
+        assert!(authorize(&admin, &Method::PUT, "/admin/faults").is_ok());
+    }
+
+    #[test]
+    fn anonymous_cannot_reach_admin_endpoints() {
+        let api_keys = ApiKeys::default();
+        assert_eq!(
+            api_keys.authenticate_for(None, "/todos"),
+            Ok(Principal::anonymous())
+        );
+        assert_eq!(
+            api_keys.authenticate_for(None, "/audit-logs"),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(
+            api_keys.authenticate_for(None, "/admin/config"),
+            Err(AuthError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn parse_api_keys() {
+        let api_keys = ApiKeys::parse("alice:secret-a:admin, bob:secret-b:viewer").unwrap();
+        assert_eq!(
+            api_keys.authenticate(Some("Bearer secret-b")),
+            Ok(Principal {
+                name: String::from("bob"),
+                role: Role::Viewer,
+            })
+        );
+        assert_eq!(
+            api_keys.authenticate(Some("Bearer unknown")),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(api_keys.authenticate(None), Err(AuthError::Unauthorized));
+
+        assert!(ApiKeys::parse("alice:secret-a").is_err());
+        assert!(api_keys
+            .authenticate_for(Some("Bearer secret-a"), "/admin/config")
+            .is_ok());
+        assert_eq!(
+            ApiKeys::parse("alice:secret-a:owner").unwrap_err(),
+            AuthError::UnknownRole(String::from("owner"))
+        );
+    }
+
+    #[test]
+    fn anonymous_admin_when_disabled() {
+        let api_keys = ApiKeys::default();
+        assert_eq!(api_keys.authenticate(None), Ok(Principal::anonymous()));
+    }
+}
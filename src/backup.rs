@@ -0,0 +1,797 @@
+use crate::events::DomainEvent;
+use crate::handlers::{ApiError, ValidateJson};
+use crate::repositories::labels::Label;
+use crate::repositories::projects::Project;
+use crate::repositories::todo::{Share, TodoStatus};
+use crate::repositories::users::User;
+use crate::state::State;
+use axum::async_trait;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use std::env;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::instrument;
+use validator::{Validate, ValidationError};
+
+// スナップショットの形式を変えたら上げる
+// 復元できるのは同じ版のものだけ
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// 復元に必要なデータ一式
+// 関連はidで持ち、復元時もidをそのまま使う
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub users: Vec<User>,
+    pub projects: Vec<Project>,
+    pub labels: Vec<Label>,
+    pub todos: Vec<TodoRecord>,
+    pub dependencies: Vec<Dependency>,
+    // 共有を導入する前のスナップショットには含まれない
+    #[serde(default)]
+    pub shares: Vec<Share>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct TodoRecord {
+    pub id: i32,
+    pub uuid: uuid::Uuid,
+    pub text: String,
+    pub status: TodoStatus,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub assignee_id: Option<i32>,
+    pub project_id: Option<i32>,
+    pub tags: Vec<String>,
+    pub parent_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub labels: Vec<i32>,
+    #[serde(default)]
+    pub owner_id: Option<i32>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub estimate_minutes: Option<i32>,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Dependency {
+    pub blocker_id: i32,
+    pub blocked_id: i32,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BackupError {
+    #[error("Unsupported snapshot version [{0}], expected [{SNAPSHOT_VERSION}]")]
+    UnsupportedVersion(u32),
+    #[error("Backup not found: [{0}]")]
+    NotFound(String),
+}
+
+// スナップショットの取得と復元
+#[async_trait]
+pub trait SnapshotRepository: Send + Sync + 'static {
+    async fn export(&self) -> anyhow::Result<Snapshot>;
+    // 現在のデータをすべて置き換える
+    async fn restore(&self, snapshot: &Snapshot) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotRepositoryForDb {
+    pool: PgPool,
+}
+
+impl SnapshotRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SnapshotRepository for SnapshotRepositoryForDb {
+    #[instrument(skip_all)]
+    async fn export(&self) -> anyhow::Result<Snapshot> {
+        // 読み込みの途中で書き込まれても食い違わないよう、1つのスナップショットから読む
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("set transaction isolation level repeatable read read only")
+            .execute(&mut tx)
+            .await?;
+        let users = sqlx::query_as::<_, User>(r#"select id, name from users order by id"#)
+            .fetch_all(&mut tx)
+            .await?;
+        let projects = sqlx::query_as::<_, Project>(r#"select id, name from projects order by id"#)
+            .fetch_all(&mut tx)
+            .await?;
+        let labels = sqlx::query_as::<_, Label>(r#"select * from labels order by id"#)
+            .fetch_all(&mut tx)
+            .await?;
+        let todos = sqlx::query_as::<_, TodoRecord>(
+            r#"
+select id, uuid, text, status, remind_at, assignee_id, project_id, tags, parent_id, created_at, completed_at, owner_id, icon, color, pinned, snoozed_until, estimate_minutes, due_at, description,
+       coalesce((select array_agg(label_id order by label_id) from todo_labels where todo_id = todos.id), '{}') as labels
+from todos
+order by id
+        "#,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let dependencies = sqlx::query_as::<_, Dependency>(
+            r#"select blocker_id, blocked_id from todo_dependencies order by blocker_id, blocked_id"#,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let shares = sqlx::query_as::<_, Share>(
+            r#"select todo_id, user_id, permission from todo_shares order by todo_id, user_id"#,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: Utc::now(),
+            users,
+            projects,
+            labels,
+            todos,
+            dependencies,
+            shares,
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        if let Err(e) = replace_all(&mut tx, snapshot).await {
+            tx.rollback().await?;
+            return Err(e);
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+async fn replace_all(
+    tx: &mut Transaction<'_, Postgres>,
+    snapshot: &Snapshot,
+) -> anyhow::Result<()> {
+    // テンプレートは対象外なので、ラベルを消しても付いていたラベルが残るよう控えておく
+    let template_labels: Vec<(i32, i32)> =
+        sqlx::query_as(r#"select template_id, label_id from template_labels"#)
+            .fetch_all(&mut *tx)
+            .await?;
+    // ユーザーの設定も対象外なので、同じidのユーザーが復元されたら戻す
+    let preferences: Vec<(i32, sqlx::types::Json<serde_json::Value>)> =
+        sqlx::query_as(r#"select user_id, preferences from user_preferences"#)
+            .fetch_all(&mut *tx)
+            .await?;
+    for table in [
+        "todo_shares",
+        "todo_dependencies",
+        "todo_labels",
+        "todo_revisions",
+        "todos",
+        "labels",
+        "projects",
+        "users",
+    ] {
+        sqlx::query(&format!("delete from {}", table))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    for user in snapshot.users.iter() {
+        sqlx::query(r#"insert into users (id, name) values ($1, $2)"#)
+            .bind(user.id)
+            .bind(&user.name)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (user_id, preferences) in preferences.iter() {
+        sqlx::query(
+            r#"insert into user_preferences (user_id, preferences) select id, $2 from users where id = $1"#,
+        )
+        .bind(user_id)
+        .bind(preferences)
+        .execute(&mut *tx)
+        .await?;
+    }
+    for project in snapshot.projects.iter() {
+        sqlx::query(r#"insert into projects (id, name) values ($1, $2)"#)
+            .bind(project.id)
+            .bind(&project.name)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for label in snapshot.labels.iter() {
+        sqlx::query(
+            r#"insert into labels (id, uuid, name, color, description) values ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(label.id)
+        .bind(label.uuid)
+        .bind(&label.name)
+        .bind(&label.color)
+        .bind(&label.description)
+        .execute(&mut *tx)
+        .await?;
+    }
+    let (template_ids, label_ids): (Vec<i32>, Vec<i32>) = template_labels.into_iter().unzip();
+    sqlx::query(
+        r#"
+insert into template_labels (template_id, label_id)
+select t.template_id, t.label_id
+from unnest($1::int[], $2::int[]) as t(template_id, label_id)
+where exists (select 1 from labels where labels.id = t.label_id)
+        "#,
+    )
+    .bind(template_ids)
+    .bind(label_ids)
+    .execute(&mut *tx)
+    .await?;
+    // 親は後から付け、完了日時は状態のトリガーで上書きされないよう最後に戻す
+    for todo in snapshot.todos.iter() {
+        sqlx::query(
+            r#"
+insert into todos (id, uuid, text, status, remind_at, assignee_id, project_id, tags, created_at, owner_id, icon, color, pinned, snoozed_until, estimate_minutes, due_at, description)
+values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            "#,
+        )
+        .bind(todo.id)
+        .bind(todo.uuid)
+        .bind(&todo.text)
+        .bind(todo.status)
+        .bind(todo.remind_at)
+        .bind(todo.assignee_id)
+        .bind(todo.project_id)
+        .bind(&todo.tags)
+        .bind(todo.created_at)
+        .bind(todo.owner_id)
+        .bind(&todo.icon)
+        .bind(&todo.color)
+        .bind(todo.pinned)
+        .bind(todo.snoozed_until)
+        .bind(todo.estimate_minutes)
+        .bind(todo.due_at)
+        .bind(&todo.description)
+        .execute(&mut *tx)
+        .await?;
+        for label_id in todo.labels.iter() {
+            sqlx::query(r#"insert into todo_labels (todo_id, label_id) values ($1, $2)"#)
+                .bind(todo.id)
+                .bind(label_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    for todo in snapshot.todos.iter() {
+        sqlx::query(r#"update todos set parent_id = $1, completed_at = $2 where id = $3"#)
+            .bind(todo.parent_id)
+            .bind(todo.completed_at)
+            .bind(todo.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for dependency in snapshot.dependencies.iter() {
+        sqlx::query(r#"insert into todo_dependencies (blocker_id, blocked_id) values ($1, $2)"#)
+            .bind(dependency.blocker_id)
+            .bind(dependency.blocked_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for share in snapshot.shares.iter() {
+        sqlx::query(
+            r#"insert into todo_shares (todo_id, user_id, permission) values ($1, $2, $3)"#,
+        )
+        .bind(share.todo_id)
+        .bind(share.user_id)
+        .bind(share.permission)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // 履歴は復元した状態を最初の版とする
+    sqlx::query(
+        r#"
+insert into todo_revisions (todo_id, text, status, labels)
+select todos.id, todos.text, todos.status,
+       coalesce((select jsonb_agg(jsonb_build_object('id', labels.id, 'uuid', labels.uuid, 'name', labels.name, 'color', labels.color, 'description', labels.description) order by labels.id)
+                 from todo_labels tl
+                 join labels on labels.id = tl.label_id
+                 where tl.todo_id = todos.id), '[]')
+from todos
+order by todos.id
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // 復元後に作成するものがidを重複させないよう連番を進める
+    for table in ["users", "projects", "labels", "todos"] {
+        sqlx::query(&format!(
+            "select setval(pg_get_serial_sequence('{0}', 'id'), coalesce(max(id), 0) + 1, false) from {0}",
+            table
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+// バックアップファイルの保存先
+#[async_trait]
+pub trait BackupStorage: Send + Sync + 'static {
+    async fn put(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()>;
+    async fn get(&self, name: &str) -> anyhow::Result<Vec<u8>>;
+    // 名前の昇順
+    async fn list(&self) -> anyhow::Result<Vec<String>>;
+}
+
+// ローカルのディレクトリに保存する
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl BackupStorage for LocalStorage {
+    // 書きかけのファイルを復元に使わないよう、別名で書いてから置き換える
+    async fn put(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let partial = self.dir.join(format!(".{}.partial", name));
+        tokio::fs::write(&partial, bytes).await?;
+        tokio::fs::rename(&partial, self.dir.join(name)).await?;
+        Ok(())
+    }
+
+    async fn get(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        match tokio::fs::read(self.dir.join(name)).await {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Err(BackupError::NotFound(name.to_string()).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = vec![];
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(names),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_backup_name(&name) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+// バックアップの件数などの概要
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSummary {
+    pub name: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub users: usize,
+    pub projects: usize,
+    pub labels: usize,
+    pub todos: usize,
+}
+
+impl BackupSummary {
+    fn new(name: String, snapshot: &Snapshot) -> Self {
+        BackupSummary {
+            name,
+            version: snapshot.version,
+            created_at: snapshot.created_at,
+            users: snapshot.users.len(),
+            projects: snapshot.projects.len(),
+            labels: snapshot.labels.len(),
+            todos: snapshot.todos.len(),
+        }
+    }
+}
+
+// スナップショットをファイルにして保存し、保存したファイルから復元する
+pub struct Backups {
+    snapshots: Arc<dyn SnapshotRepository>,
+    storage: Arc<dyn BackupStorage>,
+}
+
+impl Backups {
+    pub fn new(snapshots: Arc<dyn SnapshotRepository>, storage: Arc<dyn BackupStorage>) -> Self {
+        Self { snapshots, storage }
+    }
+
+    pub async fn backup(&self) -> anyhow::Result<BackupSummary> {
+        let snapshot = self.snapshots.export().await?;
+        // 名前の順が作成順になるようにする
+        let name = format!(
+            "backup-v{}-{}.json",
+            snapshot.version,
+            snapshot.created_at.format("%Y%m%dT%H%M%S%3fZ")
+        );
+        self.storage
+            .put(&name, serde_json::to_vec_pretty(&snapshot)?)
+            .await?;
+        Ok(BackupSummary::new(name, &snapshot))
+    }
+
+    pub async fn restore(&self, name: &str) -> anyhow::Result<BackupSummary> {
+        let bytes = self.storage.get(name).await?;
+        // 形式が変わっていても版だけは読めるよう、先に版を確かめる
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let Versioned { version } = serde_json::from_slice(&bytes)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(BackupError::UnsupportedVersion(version).into());
+        }
+        let snapshot: Snapshot = serde_json::from_slice(&bytes)?;
+        self.snapshots.restore(&snapshot).await?;
+        Ok(BackupSummary::new(name.to_string(), &snapshot))
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<String>> {
+        self.storage.list().await
+    }
+}
+
+// BACKUP_INTERVAL_SECS で定期的にバックアップする間隔を指定する。未指定か0の場合はしない
+pub fn backup_interval_from_env() -> anyhow::Result<Option<Duration>> {
+    match env::var("BACKUP_INTERVAL_SECS") {
+        Ok(secs) if !secs.is_empty() => {
+            let secs: u64 = secs.parse()?;
+            Ok((secs > 0).then(|| Duration::from_secs(secs)))
+        }
+        _ => Ok(None),
+    }
+}
+
+// BACKUP_DIR で保存先のディレクトリを指定する
+pub fn backup_dir_from_env() -> PathBuf {
+    env::var("BACKUP_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| "backups".to_string())
+        .into()
+}
+
+pub fn spawn_backup_scheduler(backups: Arc<Backups>, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // 起動直後にはバックアップしない
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match backups.backup().await {
+                Ok(summary) => tracing::info!("backed up to {}", summary.name),
+                Err(e) => tracing::error!("failed to back up: {}", e),
+            }
+        }
+    })
+}
+
+fn is_backup_name(name: &str) -> bool {
+    name.starts_with("backup-")
+        && name.ends_with(".json")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+}
+
+// 保存先の外を読めないよう、バックアップで作った名前だけを受け付ける
+fn validate_backup_name(name: &str) -> Result<(), ValidationError> {
+    if is_backup_name(name) {
+        Ok(())
+    } else {
+        let mut error = ValidationError::new("backup_name");
+        error.message = Some("validation.backup_name".into());
+        Err(error)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct RestoreBackup {
+    #[validate(custom = "validate_backup_name")]
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupList {
+    pub backups: Vec<String>,
+}
+
+fn configured(backups: Option<Extension<Arc<Backups>>>) -> Result<Arc<Backups>, ApiError> {
+    backups
+        .map(|Extension(backups)| backups)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into())
+}
+
+fn backup_error(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<BackupError>() {
+        Some(BackupError::NotFound(_)) => StatusCode::NOT_FOUND.into(),
+        Some(BackupError::UnsupportedVersion(_)) => ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "backup.unsupported_version",
+        ),
+        None => {
+            tracing::error!("backup failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into()
+        }
+    }
+}
+
+pub async fn create_backup(
+    backups: Option<Extension<Arc<Backups>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let summary = configured(backups)?.backup().await.map_err(backup_error)?;
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+pub async fn all_backups(
+    backups: Option<Extension<Arc<Backups>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let backups = configured(backups)?.list().await.map_err(backup_error)?;
+    Ok(Json(BackupList { backups }))
+}
+
+// 復元したらキャッシュなどが古いデータを返さないよう購読側に知らせる
+pub async fn restore_backup<S: State>(
+    ValidateJson(payload): ValidateJson<RestoreBackup>,
+    Extension(state): Extension<S>,
+    backups: Option<Extension<Arc<Backups>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let summary = configured(backups)?
+        .restore(&payload.name)
+        .await
+        .map_err(backup_error)?;
+    state.events().publish(DomainEvent::SnapshotRestored {
+        name: summary.name.clone(),
+    });
+    Ok(Json(summary))
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::sync::RwLock;
+
+    // 復元したスナップショットをそのまま返す
+    #[derive(Debug)]
+    pub struct SnapshotRepositoryForMemory {
+        snapshot: RwLock<Snapshot>,
+    }
+
+    impl SnapshotRepositoryForMemory {
+        pub fn new(snapshot: Snapshot) -> Self {
+            Self {
+                snapshot: RwLock::new(snapshot),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SnapshotRepository for SnapshotRepositoryForMemory {
+        async fn export(&self) -> anyhow::Result<Snapshot> {
+            Ok(Snapshot {
+                created_at: Utc::now(),
+                ..self.snapshot.read().unwrap().clone()
+            })
+        }
+
+        async fn restore(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+            *self.snapshot.write().unwrap() = snapshot.clone();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::SnapshotRepositoryForMemory;
+    use super::*;
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    fn temp_dir() -> PathBuf {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+        env::temp_dir().join(format!("rust-simple-api-backup-{}", suffix))
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: Utc::now(),
+            users: vec![User {
+                id: 1,
+                name: "alice".to_string(),
+            }],
+            projects: vec![],
+            labels: vec![],
+            todos: vec![],
+            dependencies: vec![],
+            shares: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn should_restore_saved_backup() {
+        let dir = temp_dir();
+        let snapshots = Arc::new(SnapshotRepositoryForMemory::new(snapshot()));
+        let backups = Backups::new(snapshots.clone(), Arc::new(LocalStorage::new(&dir)));
+
+        let summary = backups.backup().await.unwrap();
+        assert!(summary.name.starts_with("backup-v1-"));
+        assert_eq!(summary.users, 1);
+        assert_eq!(backups.list().await.unwrap(), vec![summary.name.clone()]);
+
+        snapshots
+            .restore(&Snapshot {
+                users: vec![],
+                ..snapshot()
+            })
+            .await
+            .unwrap();
+        let restored = backups.restore(&summary.name).await.unwrap();
+        assert_eq!(restored.users, 1);
+        assert_eq!(snapshots.export().await.unwrap().users.len(), 1);
+
+        let e = backups.restore("backup-missing.json").await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<BackupError>(),
+            Some(&BackupError::NotFound("backup-missing.json".to_string()))
+        );
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_reject_other_snapshot_versions() {
+        let dir = temp_dir();
+        let storage = LocalStorage::new(&dir);
+        storage
+            .put(
+                "backup-v2-future.json",
+                br#"{ "version": 2, "todos": "changed format" }"#.to_vec(),
+            )
+            .await
+            .unwrap();
+        let backups = Backups::new(
+            Arc::new(SnapshotRepositoryForMemory::new(snapshot())),
+            Arc::new(storage),
+        );
+
+        let e = backups.restore("backup-v2-future.json").await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<BackupError>(),
+            Some(&BackupError::UnsupportedVersion(2))
+        );
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    fn should_accept_backup_names_only() {
+        assert!(is_backup_name("backup-v1-20240101T000000000Z.json"));
+        assert!(!is_backup_name("../backup-v1.json"));
+        assert!(!is_backup_name("backup-v1/../../etc/passwd.json"));
+        assert!(!is_backup_name(".backup-v1.json.partial"));
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod database_test {
+    use super::*;
+    use crate::repositories::labels::{CreateLabel, LabelRepository, LabelRepositoryForDb};
+    use crate::repositories::test_db::TestDatabase;
+    use crate::repositories::todo::{
+        CreateTodo, TodoRepository, TodoRepositoryForDb, TodoSort, UpdateTodo,
+    };
+    use crate::repositories::users::{Preferences, UserRepository, UserRepositoryForDb};
+
+    #[tokio::test]
+    async fn should_round_trip_snapshot() {
+        let db = TestDatabase::new().await;
+        let labels = LabelRepositoryForDb::new(db.pool.clone());
+        let todos = TodoRepositoryForDb::new(db.pool.clone());
+        let label = labels
+            .create(CreateLabel::new("backup".to_string()))
+            .await
+            .unwrap();
+        let parent = todos
+            .create(CreateTodo::new("parent".to_string(), vec![label.id]))
+            .await
+            .unwrap();
+        let child = todos
+            .create(CreateTodo::new("child".to_string(), vec![]))
+            .await
+            .unwrap();
+        todos.set_parent(child.id, Some(parent.id)).await.unwrap();
+        todos.add_dependency(child.id, parent.id).await.unwrap();
+        todos
+            .update(child.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        let users = UserRepositoryForDb::new(db.pool.clone());
+        let user = users.create("backup".to_string()).await.unwrap();
+        let preferences = Preferences {
+            default_sort: Some(TodoSort::Oldest),
+            ..Default::default()
+        };
+        users
+            .update_preferences(user.id, preferences.clone())
+            .await
+            .unwrap();
+
+        let repository = SnapshotRepositoryForDb::new(db.pool.clone());
+        let snapshot = repository.export().await.unwrap();
+        assert_eq!(snapshot.todos.len(), 2);
+        assert_eq!(snapshot.todos[0].labels, vec![label.id]);
+
+        // 復元すると、その後の変更はなかったことになる
+        todos.delete(parent.id).await.unwrap();
+        repository.restore(&snapshot).await.unwrap();
+        let restored = repository.export().await.unwrap();
+        assert_eq!(
+            Snapshot {
+                created_at: snapshot.created_at,
+                ..restored
+            },
+            snapshot
+        );
+        assert_eq!(
+            todos.find(child.id).await.unwrap().parent_id,
+            Some(parent.id)
+        );
+        assert_eq!(todos.history(parent.id).await.unwrap().len(), 1);
+        // 設定はスナップショットに含めないが、消えずに残る
+        assert_eq!(users.preferences(user.id).await.unwrap(), preferences);
+
+        // 連番も進んでいる
+        let created = todos
+            .create(CreateTodo::new("after restore".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert!(created.id > child.id);
+
+        db.teardown().await;
+    }
+}
@@ -0,0 +1,436 @@
+use crate::config::SharedConfig;
+use crate::events::{spawn_subscriber, EventBus};
+use crate::metrics::Metrics;
+use crate::repositories::todo::{
+    CompletedRange, CreateTodo, CycleTime, ListedTodo, Permission, Share, TodoChanges,
+    TodoDependencies, TodoEntity, TodoFilter, TodoPage, TodoRepository, TodoRevision, TodoStream,
+    TodoSuggestion, UpdateTodo, Visibility,
+};
+use crate::repositories::users::User;
+use crate::repositories::EntityId;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+// TODO_CACHE_TTL_SECS でキャッシュの有効期間を指定する。0の場合はキャッシュしない
+pub fn cache_ttl_from_env() -> anyhow::Result<Duration> {
+    match env::var("TODO_CACHE_TTL_SECS") {
+        Ok(secs) if !secs.is_empty() => Ok(Duration::from_secs(secs.parse()?)),
+        _ => Ok(DEFAULT_TTL),
+    }
+}
+
+#[derive(Debug)]
+struct Store<I> {
+    // 書き込みのたびに進め、読み込み中に書き込まれた結果はキャッシュしない
+    generation: u64,
+    todos: HashMap<I, (Instant, TodoEntity<I>)>,
+    lists: HashMap<TodoFilter<I>, (Instant, Vec<TodoEntity<I>>)>,
+}
+
+impl<I> Default for Store<I> {
+    fn default() -> Self {
+        Self {
+            generation: 0,
+            todos: HashMap::new(),
+            lists: HashMap::new(),
+        }
+    }
+}
+
+// todoの読み込みをキャッシュするデコレーター
+// いずれかの書き込みがあればキャッシュ全体を破棄する
+// 有効期間は実行中に変えられるよう、読むたびに設定から取る
+#[derive(Debug, Clone)]
+pub struct Cached<R, I = i32> {
+    inner: R,
+    config: SharedConfig,
+    store: Arc<Mutex<Store<I>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<R, I> Cached<R, I> {
+    pub fn new(inner: R, config: SharedConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            inner,
+            config,
+            store: Arc::default(),
+            metrics,
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        self.config.read().unwrap().cache_ttl()
+    }
+
+    fn record(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.metrics.increment(
+            "cache_requests_total",
+            &[("cache", "todos"), ("result", result)],
+        );
+    }
+
+    pub fn invalidate(&self) {
+        let mut store = self.store.lock().unwrap();
+        store.generation += 1;
+        store.todos.clear();
+        store.lists.clear();
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>> TodoRepository<I> for Cached<R, I> {
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.create(payload).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        let ttl = self.ttl();
+        let generation = {
+            let store = self.store.lock().unwrap();
+            if let Some((cached_at, todo)) = store.todos.get(&id) {
+                if cached_at.elapsed() < ttl {
+                    self.record(true);
+                    return Ok(todo.clone());
+                }
+            }
+            store.generation
+        };
+        self.record(false);
+
+        let todo = self.inner.find(id).await?;
+        let mut store = self.store.lock().unwrap();
+        if store.generation == generation && !ttl.is_zero() {
+            store.todos.insert(id, (Instant::now(), todo.clone()));
+        }
+        Ok(todo)
+    }
+
+    // ロックして読むので、キャッシュは使わない
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_for_update(id).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        self.inner.find_by_text(text, visible_to).await
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let ttl = self.ttl();
+        let generation = {
+            let store = self.store.lock().unwrap();
+            if let Some((cached_at, todos)) = store.lists.get(&filter) {
+                if cached_at.elapsed() < ttl {
+                    self.record(true);
+                    return Ok(todos.clone());
+                }
+            }
+            store.generation
+        };
+        self.record(false);
+
+        let todos = self.inner.all(filter.clone()).await?;
+        let mut store = self.store.lock().unwrap();
+        if store.generation == generation && !ttl.is_zero() {
+            store.lists.insert(filter, (Instant::now(), todos.clone()));
+        }
+        Ok(todos)
+    }
+
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        self.inner.stream(filter).await
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        self.inner.count(filter).await
+    }
+
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        self.inner.page(filter, page).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.update(id, payload).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        let result = self.inner.delete(id).await;
+        self.invalidate();
+        result
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        self.inner.history(id).await
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.set_reminder(id, remind_at).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.reminders().await
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let todos = self.inner.take_due_reminders(now).await;
+        self.invalidate();
+        todos
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.assign(id, assignee).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.move_to_project(id, project_id).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.set_parent(id, parent_id).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.pin(id, pinned).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.snooze(id, until).await;
+        self.invalidate();
+        todo
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let ids = self.inner.attach_label(label_id, todo_ids).await;
+        self.invalidate();
+        ids
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let ids = self.inner.detach_label(label_id, todo_ids).await;
+        self.invalidate();
+        ids
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.children(id).await
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.descendants(id).await
+    }
+
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let result = self.inner.add_dependency(blocker_id, blocked_id).await;
+        self.invalidate();
+        result
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let result = self.inner.remove_dependency(blocker_id, blocked_id).await;
+        self.invalidate();
+        result
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        self.inner.dependencies(id).await
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.dependents(id).await
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.modified_at(id).await
+    }
+
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.list_modified_at().await
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        self.inner.cycle_time(range).await
+    }
+
+    // 共有が変わると一覧に見えるtodoも変わる
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        let share = self.inner.share(id, user_id, permission).await;
+        self.invalidate();
+        share
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        let result = self.inner.unshare(id, user_id).await;
+        self.invalidate();
+        result
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        self.inner.shares(id).await
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        self.inner.suggest(query, visible_to, limit).await
+    }
+
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        self.inner.changes(since, visible_to).await
+    }
+}
+
+// todoに埋め込んだラベルが古くならないよう、ラベルが変わったらキャッシュを破棄する
+// 作業単位での書き込みはキャッシュを通らないため、todoが変わった場合も破棄する
+pub fn spawn_invalidation_subscriber<I: EntityId, R: TodoRepository<I>>(
+    events: &EventBus<I>,
+    cache: Cached<R, I>,
+) -> JoinHandle<()> {
+    spawn_subscriber(events, "cache invalidation", move |_| {
+        cache.invalidate();
+        async { Ok(()) }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+
+    #[tokio::test]
+    async fn should_serve_reads_from_cache_until_write() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let metrics = Arc::new(Metrics::default());
+        let repository = Cached::new(
+            inner.clone(),
+            Config {
+                cache_ttl_secs: 60,
+                ..Config::default()
+            }
+            .shared(),
+            metrics.clone(),
+        );
+
+        let todo = repository
+            .create(CreateTodo::new("cached".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(repository.find(todo.id).await.unwrap(), todo);
+        assert_eq!(
+            repository.all(TodoFilter::default()).await.unwrap(),
+            vec![todo.clone()]
+        );
+
+        // キャッシュを経由しない書き込みはTTLが切れるまで見えない
+        inner
+            .create(CreateTodo::new("bypass".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.all(TodoFilter::default()).await.unwrap().len(),
+            1
+        );
+        assert_eq!(repository.find(todo.id).await.unwrap(), todo);
+
+        // 書き込みがあれば読み直す
+        let updated = repository
+            .update(todo.id, UpdateTodo::status(Default::default()))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.all(TodoFilter::default()).await.unwrap().len(),
+            2
+        );
+        assert_eq!(repository.find(todo.id).await.unwrap(), updated);
+
+        let text = metrics.render();
+        assert!(text.contains("cache_requests_total{cache=\"todos\",result=\"hit\"} 2"));
+        assert!(text.contains("cache_requests_total{cache=\"todos\",result=\"miss\"} 4"));
+    }
+
+    #[tokio::test]
+    async fn should_follow_ttl_changed_at_runtime() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let config = Config {
+            cache_ttl_secs: 60,
+            ..Config::default()
+        }
+        .shared();
+        let repository = Cached::new(inner.clone(), config.clone(), Arc::new(Metrics::default()));
+
+        assert!(repository
+            .all(TodoFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+        inner
+            .create(CreateTodo::new("bypass".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert!(repository
+            .all(TodoFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // 0にすると以降はキャッシュを使わない
+        config.write().unwrap().cache_ttl_secs = 0;
+        assert_eq!(
+            repository.all(TodoFilter::default()).await.unwrap().len(),
+            1
+        );
+        inner
+            .create(CreateTodo::new("bypass again".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.all(TodoFilter::default()).await.unwrap().len(),
+            2
+        );
+    }
+}
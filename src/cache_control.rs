@@ -0,0 +1,101 @@
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderValue, Method, Request};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+
+// ルート単位のCache-Controlの設定
+// 既定ではラベル一覧だけを、ETagで確認してから使い回せるようにする
+#[derive(Debug, Clone)]
+pub struct CacheControl {
+    routes: HashMap<String, HeaderValue>,
+}
+
+impl Default for CacheControl {
+    fn default() -> Self {
+        Self::new().route("/labels", HeaderValue::from_static("no-cache"))
+    }
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self {
+            routes: HashMap::new(),
+        }
+    }
+
+    // CACHE_CONTROL_ROUTES="<path>=<directives>;..." で既定の設定を置き換える
+    // ディレクティブはカンマを含むので、ルートの区切りにはセミコロンを使う
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(routes) = env::var("CACHE_CONTROL_ROUTES") else {
+            return Ok(Self::default());
+        };
+        let mut cache_control = Self::new();
+        for entry in routes.split(';').filter(|entry| !entry.trim().is_empty()) {
+            let (path, directives) = entry
+                .trim()
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("invalid route cache control: [{}]", entry))?;
+            cache_control = cache_control.route(path, directives.trim().parse()?);
+        }
+        Ok(cache_control)
+    }
+
+    pub fn route(mut self, path: &str, directives: HeaderValue) -> Self {
+        self.routes.insert(path.to_string(), directives);
+        self
+    }
+
+    fn for_route(&self, path: &str) -> Option<&HeaderValue> {
+        self.routes.get(path)
+    }
+}
+
+// ルーティング後に適用し、GETとHEADの応答にだけ付ける
+// ハンドラが自分でCache-Controlを付けた場合はそちらを優先する
+pub async fn set_cache_control(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let cache_control = req
+        .extensions()
+        .get::<Arc<CacheControl>>()
+        .cloned()
+        .unwrap_or_default();
+    let directives = req
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| cache_control.for_route(path.as_str()))
+        .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD))
+        .cloned();
+
+    let mut res = next.run(req).await;
+    if let Some(directives) = directives {
+        if !res.headers().contains_key(CACHE_CONTROL) {
+            res.headers_mut().insert(CACHE_CONTROL, directives);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_set_directives_per_route() {
+        let cache_control = CacheControl::default()
+            .route("/projects", HeaderValue::from_static("private, max-age=60"));
+
+        assert_eq!(
+            cache_control.for_route("/labels"),
+            Some(&HeaderValue::from_static("no-cache"))
+        );
+        assert_eq!(
+            cache_control.for_route("/projects"),
+            Some(&HeaderValue::from_static("private, max-age=60"))
+        );
+        assert_eq!(cache_control.for_route("/todos"), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::handlers::INTEGRATIONS_PREFIX;
+use axum::body::{boxed, Body, Full};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::uri::{PathAndQuery, Uri};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{Map, Value};
+use std::env;
+
+// リクエスト単位でJSONのフィールド名の形式を指定するヘッダー
+pub const X_JSON_CASE: &str = "x-json-case";
+
+// 値が利用者の決めた名前をキーに持つマップで、フィールド名ではないもの
+const PRESERVED_KEYS: [&str; 1] = ["substitutions"];
+
+// JSONのフィールド名をsnake_caseのまま返すか、camelCaseにするか
+// JSON_CASE=camel で既定にし、X-Json-Case: camel|snake でリクエストごとに上書きできる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl JsonCase {
+    pub fn from_env() -> Self {
+        env::var("JSON_CASE")
+            .ok()
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("camel") {
+            Some(JsonCase::Camel)
+        } else if value.eq_ignore_ascii_case("snake") {
+            Some(JsonCase::Snake)
+        } else {
+            None
+        }
+    }
+}
+
+fn requested_case(req: &Request<Body>) -> JsonCase {
+    req.headers()
+        .get(X_JSON_CASE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(JsonCase::parse)
+        .or_else(|| req.extensions().get::<JsonCase>().copied())
+        .unwrap_or_default()
+}
+
+// due_date -> dueDate
+fn to_camel(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for (i, c) in key.chars().enumerate() {
+        if c == '_' && i > 0 {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+// dueDate -> due_date
+fn to_snake(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+// オブジェクトのキーを入れ子の中までたどって変換する
+fn rename_keys(value: Value, rename: fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    if PRESERVED_KEYS.contains(&to_snake(&key).as_str()) {
+                        (rename(&key), value)
+                    } else {
+                        (rename(&key), rename_keys(value, rename))
+                    }
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| rename_keys(value, rename))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+// application/x-www-form-urlencoded 形式のキーを変換する
+// 読み取れない場合はハンドラーで通常どおりエラーにするため、そのまま返す
+fn rename_form_keys(encoded: &str) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(encoded).ok()?;
+    let pairs: Vec<(String, String)> = pairs
+        .into_iter()
+        .map(|(key, value)| (to_snake(&key), value))
+        .collect();
+    serde_urlencoded::to_string(pairs).ok()
+}
+
+fn content_type_is(headers: &HeaderMap, mime: &mime::Mime) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(mime.as_ref()))
+}
+
+fn rename_query(uri: &Uri) -> Option<Uri> {
+    let query = rename_form_keys(uri.query()?)?;
+    let mut parts = uri.clone().into_parts();
+    let path_and_query = format!("{}?{}", uri.path(), query);
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+// camelCaseで送られたクエリと本文のキーをsnake_caseに戻してハンドラーに渡す
+async fn rename_request(req: Request<Body>) -> Result<Request<Body>, Response> {
+    let (mut parts, body) = req.into_parts();
+    if let Some(uri) = rename_query(&parts.uri) {
+        parts.uri = uri;
+    }
+    let is_json = content_type_is(&parts.headers, &mime::APPLICATION_JSON);
+    let is_form = content_type_is(&parts.headers, &mime::APPLICATION_WWW_FORM_URLENCODED);
+    if !is_json && !is_form {
+        return Ok(Request::from_parts(parts, body));
+    }
+
+    let bytes = hyper::body::to_bytes(body)
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+    let renamed = if is_json {
+        serde_json::from_slice::<Value>(&bytes)
+            .ok()
+            .map(|value| rename_keys(value, to_snake).to_string())
+    } else {
+        std::str::from_utf8(&bytes).ok().and_then(rename_form_keys)
+    };
+    let body = match renamed {
+        Some(renamed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::from(renamed)
+        }
+        None => Body::from(bytes),
+    };
+    Ok(Request::from_parts(parts, body))
+}
+
+// ハンドラーや型ごとにrenameを付けず、ここでまとめてフィールド名を変換する
+// 封筒のmetaも変換するため、wrap_envelopeより外側に重ねる
+pub async fn convert_json_case(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    // 連携の受け口は署名した本文のまま渡す
+    if requested_case(&req) == JsonCase::Snake || req.uri().path().starts_with(INTEGRATIONS_PREFIX)
+    {
+        return next.run(req).await;
+    }
+    let is_head = req.method() == Method::HEAD;
+    let req = match rename_request(req).await {
+        Ok(req) => req,
+        Err(res) => return res,
+    };
+    let res = next.run(req).await;
+    if is_head || !content_type_is(res.headers(), &mime::APPLICATION_JSON) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            rename_keys(value, to_camel).to_string().into()
+        }
+        Err(_) => bytes,
+    };
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_convert_between_cases() {
+        for (snake, camel) in [
+            ("text", "text"),
+            ("remind_at", "remindAt"),
+            ("shared_with_me", "sharedWithMe"),
+            ("_links", "_links"),
+        ] {
+            assert_eq!(to_camel(snake), camel);
+            assert_eq!(to_snake(camel), snake);
+        }
+    }
+
+    #[test]
+    fn should_rename_nested_keys_except_user_defined_ones() {
+        let value = json!({
+            "project_id": 1,
+            "labels": [{ "id": 1, "created_at": "now" }],
+            "substitutions": { "due_day": "friday" },
+            "status": "in_progress",
+        });
+        let camel = rename_keys(value.clone(), to_camel);
+        assert_eq!(
+            camel,
+            json!({
+                "projectId": 1,
+                "labels": [{ "id": 1, "createdAt": "now" }],
+                "substitutions": { "due_day": "friday" },
+                "status": "in_progress",
+            })
+        );
+        assert_eq!(rename_keys(camel, to_snake), value);
+    }
+
+    #[test]
+    fn should_rename_query_keys() {
+        let uri: Uri = "/todos?perPage=2&shared_with_me=true&tag=a%20b"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rename_query(&uri).unwrap().to_string(),
+            "/todos?per_page=2&shared_with_me=true&tag=a+b"
+        );
+        assert!(rename_query(&"/todos".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::config::SharedConfig;
+use crate::handlers::ValidateJson;
+use crate::metrics::Metrics;
+use axum::body::Body;
+use axum::extract::{Extension, Query};
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::Json;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::sleep;
+use validator::{Validate, ValidationError};
+
+// 遅延の上限。これより長い遅延は指定できない
+const MAX_LATENCY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ChaosError {
+    #[error("Failure rate must be between 0 and 1: [{0}]")]
+    FailureRate(f64),
+    #[error("Invalid latency, expected <ms> or <min ms>-<max ms> up to 60000: [{0}]")]
+    Latency(String),
+}
+
+// 障害試験用のエンドポイントの振る舞い
+// 失敗させる割合と、応答までの遅延の範囲を持つ
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ChaosSettings", into = "ChaosSettings")]
+pub struct Chaos {
+    failure_rate: f64,
+    min_latency: Duration,
+    max_latency: Duration,
+}
+
+// 未設定の場合は1〜7秒待って半分の確率で失敗する
+impl Default for Chaos {
+    fn default() -> Self {
+        Chaos {
+            failure_rate: 0.5,
+            min_latency: Duration::from_secs(1),
+            max_latency: Duration::from_secs(7),
+        }
+    }
+}
+
+impl Chaos {
+    // CHAOS_FAILURE_RATE で失敗させる割合を、
+    // CHAOS_LATENCY_MS="<ms>" または "<min ms>-<max ms>" で遅延を指定する
+    pub fn from_env() -> anyhow::Result<Self> {
+        let chaos = Chaos::default();
+        let chaos = match env::var("CHAOS_FAILURE_RATE") {
+            Ok(rate) if !rate.is_empty() => chaos.failure_rate(rate.parse()?)?,
+            _ => chaos,
+        };
+        let chaos = match env::var("CHAOS_LATENCY_MS") {
+            Ok(latency) if !latency.is_empty() => chaos.latency(&latency)?,
+            _ => chaos,
+        };
+        Ok(chaos)
+    }
+
+    pub fn failure_rate(self, failure_rate: f64) -> Result<Self, ChaosError> {
+        if !(0.0..=1.0).contains(&failure_rate) {
+            return Err(ChaosError::FailureRate(failure_rate));
+        }
+        Ok(Chaos {
+            failure_rate,
+            ..self
+        })
+    }
+
+    pub fn latency(self, latency: &str) -> Result<Self, ChaosError> {
+        let invalid = || ChaosError::Latency(latency.to_string());
+        let (min, max) = match latency.split_once('-') {
+            Some((min, max)) => (min.trim(), max.trim()),
+            None => (latency.trim(), latency.trim()),
+        };
+        let min = Duration::from_millis(min.parse().map_err(|_| invalid())?);
+        let max = Duration::from_millis(max.parse().map_err(|_| invalid())?);
+        if min > max || max > MAX_LATENCY {
+            return Err(invalid());
+        }
+        Ok(Chaos {
+            min_latency: min,
+            max_latency: max,
+            ..self
+        })
+    }
+
+    // クエリパラメータで指定された値で上書きする
+    fn with_query(self, query: &ChaosQuery) -> Result<Self, ChaosError> {
+        let chaos = match query.failure_rate {
+            Some(rate) => self.failure_rate(rate)?,
+            None => self,
+        };
+        match &query.latency_ms {
+            Some(latency) => chaos.latency(latency),
+            None => Ok(chaos),
+        }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> (Duration, bool) {
+        let latency = if self.min_latency == self.max_latency {
+            self.min_latency
+        } else {
+            rng.gen_range(self.min_latency..=self.max_latency)
+        };
+        (latency, rng.gen_bool(self.failure_rate))
+    }
+}
+
+// 設定として読み書きする形式
+// 遅延は環境変数と同じく "<ms>" または "<min ms>-<max ms>" で表す
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChaosSettings {
+    failure_rate: f64,
+    latency_ms: String,
+}
+
+impl TryFrom<ChaosSettings> for Chaos {
+    type Error = ChaosError;
+
+    fn try_from(settings: ChaosSettings) -> Result<Self, Self::Error> {
+        Chaos::default()
+            .failure_rate(settings.failure_rate)?
+            .latency(&settings.latency_ms)
+    }
+}
+
+impl From<Chaos> for ChaosSettings {
+    fn from(chaos: Chaos) -> Self {
+        let (min, max) = (chaos.min_latency.as_millis(), chaos.max_latency.as_millis());
+        ChaosSettings {
+            failure_rate: chaos.failure_rate,
+            latency_ms: if min == max {
+                min.to_string()
+            } else {
+                format!("{}-{}", min, max)
+            },
+        }
+    }
+}
+
+// GET /flaky のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosQuery {
+    failure_rate: Option<f64>,
+    latency_ms: Option<String>,
+}
+
+// 設定された割合で500を返し、設定された範囲で応答を遅らせる
+// SLOやアラートの試験に使えるよう、注入した遅延と失敗をメトリクスに記録する
+pub async fn flaky(
+    Query(query): Query<ChaosQuery>,
+    config: Option<Extension<SharedConfig>>,
+    metrics: Option<Extension<Arc<Metrics>>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let chaos = config
+        .map(|Extension(config)| config.read().unwrap().chaos)
+        .unwrap_or_default()
+        .with_query(&query)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let (latency, fail) = chaos.sample(&mut rand::thread_rng());
+    sleep(latency).await;
+
+    if let Some(Extension(metrics)) = metrics {
+        metrics.observe("chaos_injected_latency_seconds", &[], latency.as_secs_f64());
+        let outcome = if fail { "failure" } else { "success" };
+        metrics.increment("chaos_requests_total", &[("outcome", outcome)]);
+    }
+    if fail {
+        Ok(StatusCode::INTERNAL_SERVER_ERROR)
+    } else {
+        Ok(StatusCode::OK)
+    }
+}
+
+// 任意のパスのリクエストに遅延や失敗を注入するルール
+// pathの `*` は任意の文字列にマッチする
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRule {
+    #[validate(length(min = 1, message = "validation.empty"))]
+    path: String,
+    #[serde(default)]
+    #[validate(range(min = 0.0, max = 1.0))]
+    failure_rate: f64,
+    #[validate(custom = "validate_latency")]
+    latency_ms: Option<String>,
+    #[serde(default = "default_fault_status")]
+    #[validate(range(min = 400, max = 599))]
+    status: u16,
+}
+
+fn default_fault_status() -> u16 {
+    StatusCode::SERVICE_UNAVAILABLE.as_u16()
+}
+
+fn validate_latency(latency: &str) -> Result<(), ValidationError> {
+    Chaos::default()
+        .latency(latency)
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("latency"))
+}
+
+impl FaultRule {
+    fn matches(&self, path: &str) -> bool {
+        matches_pattern(&self.path, path)
+    }
+
+    // 検証済みのルールから注入する遅延と失敗の割合を作る
+    fn chaos(&self) -> Chaos {
+        Chaos::default()
+            .failure_rate(self.failure_rate)
+            .and_then(|chaos| chaos.latency(self.latency_ms.as_deref().unwrap_or("0")))
+            .unwrap_or(Chaos {
+                failure_rate: 0.0,
+                min_latency: Duration::ZERO,
+                max_latency: Duration::ZERO,
+            })
+    }
+}
+
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return pattern == path;
+    }
+    if path.len() < first.len() + last.len() || !path.starts_with(first) || !path.ends_with(last) {
+        return false;
+    }
+    let mut rest = &path[first.len()..path.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRules {
+    #[validate]
+    rules: Vec<FaultRule>,
+}
+
+// 実行中に管理用のエンドポイントからルールを差し替えられるフォールトインジェクション
+// CHAOS_FAULTS_ENABLED="true" の場合だけ有効にする
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    enabled: bool,
+    rules: RwLock<Vec<FaultRule>>,
+}
+
+impl FaultInjector {
+    pub fn new(enabled: bool) -> Self {
+        FaultInjector {
+            enabled,
+            rules: RwLock::default(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        FaultInjector::new(env::var("CHAOS_FAULTS_ENABLED").is_ok_and(|value| value == "true"))
+    }
+
+    pub fn rules(&self) -> Vec<FaultRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, rules: Vec<FaultRule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    // 最初にマッチしたルールを使う
+    fn find(&self, path: &str) -> Option<FaultRule> {
+        if !self.enabled {
+            return None;
+        }
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .find(|rule| rule.matches(path))
+            .cloned()
+    }
+}
+
+// ルールにマッチしたリクエストを遅らせ、一定の割合で失敗させる
+// ルールを戻せなくならないよう、管理用のエンドポイントには注入しない
+pub async fn inject_faults(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let path = req.uri().path();
+    let rule = req
+        .extensions()
+        .get::<Arc<FaultInjector>>()
+        .filter(|_| !path.starts_with("/admin/"))
+        .and_then(|injector| injector.find(path));
+    let Some(rule) = rule else {
+        return next.run(req).await;
+    };
+
+    let (latency, fail) = rule.chaos().sample(&mut rand::thread_rng());
+    sleep(latency).await;
+    if fail {
+        let status = StatusCode::from_u16(rule.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        return (status, "Injected fault").into_response();
+    }
+    next.run(req).await
+}
+
+// 無効な場合は管理用のエンドポイントも存在しないものとして扱う
+fn enabled_injector(
+    injector: Option<Extension<Arc<FaultInjector>>>,
+) -> Result<Arc<FaultInjector>, StatusCode> {
+    injector
+        .map(|Extension(injector)| injector)
+        .filter(|injector| injector.enabled)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn all_faults(
+    injector: Option<Extension<Arc<FaultInjector>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let injector = enabled_injector(injector)?;
+    Ok(Json(FaultRules {
+        rules: injector.rules(),
+    }))
+}
+
+pub async fn replace_faults(
+    ValidateJson(payload): ValidateJson<FaultRules>,
+    injector: Option<Extension<Arc<FaultInjector>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let injector = enabled_injector(injector)?;
+    injector.replace(payload.rules);
+    Ok(Json(FaultRules {
+        rules: injector.rules(),
+    }))
+}
+
+pub async fn clear_faults(
+    injector: Option<Extension<Arc<FaultInjector>>>,
+) -> Result<StatusCode, StatusCode> {
+    enabled_injector(injector)?.replace(vec![]);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_match_path_patterns() {
+        assert!(matches_pattern("/todos", "/todos"));
+        assert!(!matches_pattern("/todos", "/todos/1"));
+        assert!(matches_pattern("/todos*", "/todos/1"));
+        assert!(matches_pattern("/todos/*/history", "/todos/1/history"));
+        assert!(!matches_pattern("/todos/*/history", "/todos/1/status"));
+        assert!(!matches_pattern("/todos/*/history", "/todos/1/history/2"));
+        assert!(matches_pattern("*", "/labels"));
+        assert!(!matches_pattern("/labels*", "/todos"));
+    }
+
+    #[test]
+    fn should_find_rules_only_when_enabled() {
+        let rule = |path: &str, status| FaultRule {
+            path: path.to_string(),
+            failure_rate: 1.0,
+            latency_ms: None,
+            status,
+        };
+        let injector = FaultInjector::new(true);
+        injector.replace(vec![rule("/todos*", 503), rule("*", 500)]);
+        assert_eq!(injector.find("/todos/1"), Some(rule("/todos*", 503)));
+        assert_eq!(injector.find("/labels").map(|rule| rule.status), Some(500));
+
+        let disabled = FaultInjector::new(false);
+        disabled.replace(vec![rule("/todos*", 503)]);
+        assert_eq!(disabled.find("/todos/1"), None);
+    }
+
+    #[test]
+    fn should_parse_latency_range() {
+        let chaos = Chaos::default().latency("100-250").unwrap();
+        assert_eq!(chaos.min_latency, Duration::from_millis(100));
+        assert_eq!(chaos.max_latency, Duration::from_millis(250));
+
+        let chaos = Chaos::default().latency("0").unwrap();
+        assert_eq!(chaos.sample(&mut rand::thread_rng()).0, Duration::ZERO);
+
+        for latency in ["", "fast", "300-100", "0-60001"] {
+            assert_eq!(
+                Chaos::default().latency(latency),
+                Err(ChaosError::Latency(latency.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn should_override_with_query() {
+        let query = ChaosQuery {
+            failure_rate: Some(1.0),
+            latency_ms: Some("10-20".to_string()),
+        };
+        let chaos = Chaos::default().with_query(&query).unwrap();
+        for _ in 0..10 {
+            let (latency, fail) = chaos.sample(&mut rand::thread_rng());
+            assert!((Duration::from_millis(10)..=Duration::from_millis(20)).contains(&latency));
+            assert!(fail);
+        }
+
+        let query = ChaosQuery {
+            failure_rate: Some(1.5),
+            latency_ms: None,
+        };
+        assert_eq!(
+            Chaos::default().with_query(&query),
+            Err(ChaosError::FailureRate(1.5))
+        );
+    }
+}
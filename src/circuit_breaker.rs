@@ -0,0 +1,466 @@
+use crate::repositories::labels::{
+    CreateLabel, Label, LabelFilter, LabelRepository, LabelsVersion, UpdateLabel,
+};
+use crate::repositories::todo::{
+    CompletedRange, CreateTodo, CycleTime, ListedTodo, Permission, Share, TodoChanges,
+    TodoDependencies, TodoEntity, TodoFilter, TodoPage, TodoRepository, TodoRevision, TodoStream,
+    TodoSuggestion, UpdateTodo, Visibility,
+};
+use crate::repositories::users::User;
+use crate::repositories::{EntityId, RepositoryError};
+use axum::async_trait;
+use axum::body::Body;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Headers, IntoResponse};
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const DEFAULT_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // 試しに1件だけ通している状態
+    HalfOpen { since: Instant },
+}
+
+// 連続して失敗したらしばらく呼び出しを止め、時間をおいて1件ずつ試す
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    // 遮断中であれば再試行までの残り時間を返す
+    pub fn retry_after(&self) -> Option<Duration> {
+        let now = Instant::now();
+        match *self.state.lock().unwrap() {
+            State::Open { until } if now < until => Some(until - now),
+            State::HalfOpen { since } if now < since + self.cooldown => {
+                Some(since + self.cooldown - now)
+            }
+            _ => None,
+        }
+    }
+
+    // 呼び出してよいかを判定する
+    // 遮断期間が明けていれば半開状態に移り、その呼び出しだけを通す
+    pub(crate) fn acquire(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now < until => Err(until - now),
+            State::HalfOpen { since } if now < since + self.cooldown => {
+                Err(since + self.cooldown - now)
+            }
+            // 試行中の呼び出しが戻らなかった場合も、時間をおいて次を試す
+            State::Open { .. } | State::HalfOpen { .. } => {
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            _ => self.threshold,
+        };
+        *state = if failures >= self.threshold {
+            tracing::warn!("circuit opened after {} consecutive failures", failures);
+            State::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
+
+// 遮断中はハンドラを呼ばずに503を返す
+pub async fn reject_while_open(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let retry_after = req
+        .extensions()
+        .get::<Arc<CircuitBreaker>>()
+        .and_then(|breaker| breaker.retry_after());
+    match retry_after {
+        Some(retry_after) => {
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Headers(vec![(RETRY_AFTER, secs.to_string())]),
+            )
+                .into_response()
+        }
+        None => next.run(req).await,
+    }
+}
+
+// データベースの障害だけを失敗として数える
+// 存在しない、重複しているといった結果はデータベースが応答できている
+fn is_failure(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RepositoryError>(),
+        None | Some(RepositoryError::Unexpected(_))
+    )
+}
+
+// リポジトリの呼び出しをサーキットブレーカー越しに行うデコレーター
+#[derive(Debug, Clone)]
+pub struct CircuitBreaking<R> {
+    inner: R,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<R> CircuitBreaking<R> {
+    pub fn new(inner: R, breaker: Arc<CircuitBreaker>) -> Self {
+        Self { inner, breaker }
+    }
+
+    async fn call<T>(
+        &self,
+        f: impl Future<Output = anyhow::Result<T>> + Send,
+    ) -> anyhow::Result<T> {
+        if let Err(retry_after) = self.breaker.acquire() {
+            return Err(RepositoryError::Unavailable(retry_after.as_secs()).into());
+        }
+        let result = f.await;
+        match &result {
+            Err(e) if is_failure(e) => {
+                #[cfg(feature = "sentry")]
+                crate::reporting::capture_error(e);
+                self.breaker.record_failure()
+            }
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>> TodoRepository<I> for CircuitBreaking<R> {
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.create(payload)).await
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.find(id)).await
+    }
+
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.find_for_update(id)).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.find_by_uuid(uuid)).await
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        self.call(self.inner.find_by_text(text, visible_to)).await
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.call(self.inner.all(filter)).await
+    }
+
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        self.call(self.inner.stream(filter)).await
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        self.call(self.inner.count(filter)).await
+    }
+
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        self.call(self.inner.page(filter, page)).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.update(id, payload)).await
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        self.call(self.inner.delete(id)).await
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        self.call(self.inner.history(id)).await
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.set_reminder(id, remind_at)).await
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.call(self.inner.reminders()).await
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.call(self.inner.take_due_reminders(now)).await
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.assign(id, assignee)).await
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.move_to_project(id, project_id)).await
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.set_parent(id, parent_id)).await
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.pin(id, pinned)).await
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        self.call(self.inner.snooze(id, until)).await
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        self.call(self.inner.attach_label(label_id, todo_ids)).await
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        self.call(self.inner.detach_label(label_id, todo_ids)).await
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.call(self.inner.children(id)).await
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.call(self.inner.descendants(id)).await
+    }
+
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        self.call(self.inner.add_dependency(blocker_id, blocked_id))
+            .await
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        self.call(self.inner.remove_dependency(blocker_id, blocked_id))
+            .await
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        self.call(self.inner.dependencies(id)).await
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.call(self.inner.dependents(id)).await
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        self.call(self.inner.modified_at(id)).await
+    }
+
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        self.call(self.inner.list_modified_at()).await
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        self.call(self.inner.cycle_time(range)).await
+    }
+
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        self.call(self.inner.share(id, user_id, permission)).await
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        self.call(self.inner.unshare(id, user_id)).await
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        self.call(self.inner.shares(id)).await
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        self.call(self.inner.suggest(query, visible_to, limit))
+            .await
+    }
+
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        self.call(self.inner.changes(since, visible_to)).await
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: LabelRepository<I>> LabelRepository<I> for CircuitBreaking<R> {
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>> {
+        self.call(self.inner.create(payload)).await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Label<I>>> {
+        self.call(self.inner.all()).await
+    }
+
+    async fn search(&self, filter: LabelFilter) -> anyhow::Result<Vec<Label<I>>> {
+        self.call(self.inner.search(filter)).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>> {
+        self.call(self.inner.update(id, payload)).await
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        self.call(self.inner.delete(id)).await
+    }
+
+    async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64> {
+        self.call(self.inner.merge(id, target_id)).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>> {
+        self.call(self.inner.find_by_uuid(uuid)).await
+    }
+
+    async fn version(&self) -> anyhow::Result<LabelsVersion> {
+        self.call(self.inner.version()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // down が立っている間はデータベースに繋がらないものとして失敗する
+    #[derive(Debug, Clone, Default)]
+    struct FlakyLabelRepository {
+        down: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl LabelRepository for FlakyLabelRepository {
+        async fn create(&self, _payload: CreateLabel) -> anyhow::Result<Label> {
+            Ok(Label::new(1, "flaky".to_string()))
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<Label>> {
+            if self.down.load(Ordering::SeqCst) {
+                anyhow::bail!("connection refused");
+            }
+            Ok(vec![])
+        }
+
+        async fn update(&self, id: i32, _payload: UpdateLabel) -> anyhow::Result<Label> {
+            Err(RepositoryError::NotFound(id).into())
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            Err(RepositoryError::NotFound(id).into())
+        }
+
+        async fn merge(&self, id: i32, _target_id: i32) -> anyhow::Result<u64> {
+            Err(RepositoryError::NotFound(id).into())
+        }
+
+        async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label> {
+            Err(RepositoryError::NotFoundUuid(uuid).into())
+        }
+
+        async fn version(&self) -> anyhow::Result<LabelsVersion> {
+            if self.down.load(Ordering::SeqCst) {
+                anyhow::bail!("connection refused");
+            }
+            Ok(LabelsVersion::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_open_after_consecutive_failures() {
+        let inner = FlakyLabelRepository::default();
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_millis(50)));
+        let repository = CircuitBreaking::new(inner.clone(), breaker.clone());
+
+        // 見つからないだけなら失敗として数えない
+        assert!(repository.delete(1).await.is_err());
+        assert!(repository.delete(1).await.is_err());
+        assert_eq!(breaker.retry_after(), None);
+
+        inner.down.store(true, Ordering::SeqCst);
+        assert!(repository.all().await.is_err());
+        assert_eq!(breaker.retry_after(), None);
+        assert!(repository.all().await.is_err());
+        assert!(breaker.retry_after().is_some());
+
+        // 遮断中は復旧していても呼び出さない
+        inner.down.store(false, Ordering::SeqCst);
+        let err = repository.all().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Unavailable(_))
+        ));
+
+        // 半開状態での試行が成功すれば閉じる
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(repository.all().await.is_ok());
+        assert_eq!(breaker.retry_after(), None);
+    }
+
+    #[tokio::test]
+    async fn should_reopen_when_trial_fails() {
+        let inner = FlakyLabelRepository::default();
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_millis(50)));
+        let repository = CircuitBreaking::new(inner.clone(), breaker.clone());
+
+        inner.down.store(true, Ordering::SeqCst);
+        assert!(repository.all().await.is_err());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(repository.all().await.is_err());
+        assert!(breaker.retry_after().is_some());
+    }
+}
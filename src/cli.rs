@@ -0,0 +1,449 @@
+use crate::repositories::labels::{CreateLabel, LabelRepository};
+use crate::repositories::todo::{CreateTodo, TodoEntity, TodoFilter, TodoRepository, Visibility};
+use crate::repositories::users::{User, UserRepository};
+use crate::repositories::{EntityId, IdType};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_json::json;
+use sqlx::PgPool;
+use std::io::Write;
+use std::str::FromStr;
+use thiserror::Error;
+use validator::Validate;
+
+pub const USAGE: &str = "usage: rust-simple-api [serve | migrate | seed | export [--format csv|json] | create-admin-user <name> | todo add <text>]";
+
+// 管理用のサブコマンド。psqlで直接操作せずにリポジトリを通して行う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // 省略した場合もサーバーを起動する
+    Serve,
+    Migrate,
+    Seed,
+    Export { format: ExportFormat },
+    CreateAdminUser { name: String },
+    AddTodo { text: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CliError {
+    #[error("unknown command [{0}]")]
+    UnknownCommand(String),
+    #[error("unexpected argument [{0}]")]
+    UnexpectedArgument(String),
+    #[error("[{0}] requires a value")]
+    MissingValue(&'static str),
+    #[error("unknown format [{0}], use csv or json")]
+    UnknownFormat(String),
+}
+
+impl FromStr for ExportFormat {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(CliError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+impl Command {
+    // プログラム名を除いた引数から読み取る
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut args = args.into_iter();
+        let command = match args.next().as_deref() {
+            None | Some("serve") => Command::Serve,
+            Some("migrate") => Command::Migrate,
+            Some("seed") => Command::Seed,
+            Some("export") => {
+                let mut format = ExportFormat::Csv;
+                while let Some(arg) = args.next() {
+                    format = match arg.strip_prefix("--format=") {
+                        Some(value) => value.parse()?,
+                        None if arg == "--format" => args
+                            .next()
+                            .ok_or(CliError::MissingValue("--format"))?
+                            .parse()?,
+                        None => return Err(CliError::UnexpectedArgument(arg)),
+                    };
+                }
+                Command::Export { format }
+            }
+            Some("create-admin-user") => Command::CreateAdminUser {
+                name: args
+                    .next()
+                    .ok_or(CliError::MissingValue("create-admin-user"))?,
+            },
+            Some("todo") => match args.next().as_deref() {
+                Some("add") => Command::AddTodo {
+                    text: args.next().ok_or(CliError::MissingValue("todo add"))?,
+                },
+                Some(command) => return Err(CliError::UnknownCommand(format!("todo {}", command))),
+                None => return Err(CliError::MissingValue("todo")),
+            },
+            Some(command) => return Err(CliError::UnknownCommand(command.to_string())),
+        };
+        match args.next() {
+            Some(arg) => Err(CliError::UnexpectedArgument(arg)),
+            None => Ok(command),
+        }
+    }
+
+    // 未適用のマイグレーションがあっても実行できるコマンドか
+    pub fn requires_current_schema(&self) -> bool {
+        !matches!(self, Command::Migrate)
+    }
+}
+
+// 未適用のマイグレーションを適用する
+// idの型はマイグレーションの中で todos.id_type を見て決めるので、同じ接続に設定してから適用する
+pub async fn migrate(pool: &PgPool, id_type: IdType) -> anyhow::Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query("select set_config('todos.id_type', $1, false)")
+        .bind(id_type.as_setting())
+        .execute(&mut conn)
+        .await?;
+    sqlx::migrate!("./migrations").run(&mut conn).await?;
+    Ok(())
+}
+
+// 開発用のサンプルデータ。APIに送るのと同じ形で作る
+const SEED_LABELS: [&str; 3] = ["home", "work", "errand"];
+const SEED_TODOS: [(&str, &[&str]); 4] = [
+    ("Buy milk", &["home", "errand"]),
+    ("Write weekly report", &["work"]),
+    ("Clean the kitchen", &["home"]),
+    ("Read a book", &[]),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeedSummary {
+    pub labels: usize,
+    pub todos: usize,
+}
+
+// サンプルのラベルとtodoを作る
+// 何度実行しても増えないよう、同じ名前のラベルと同じ本文の未完了のtodoは作らない
+pub async fn seed<I: EntityId>(
+    labels: &impl LabelRepository<I>,
+    todos: &impl TodoRepository<I>,
+) -> anyhow::Result<SeedSummary> {
+    let mut summary = SeedSummary::default();
+    let mut existing = labels.all().await?;
+    for name in SEED_LABELS {
+        if existing
+            .iter()
+            .any(|label| label.name.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        let payload: CreateLabel = serde_json::from_value(json!({ "name": name }))?;
+        payload.validate()?;
+        existing.push(labels.create(payload).await?);
+        summary.labels += 1;
+    }
+
+    for (text, names) in SEED_TODOS {
+        if todos
+            .find_by_text(text, Visibility::Unowned)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        let label_ids: Vec<I> = existing
+            .iter()
+            .filter(|label| names.contains(&label.name.as_str()))
+            .map(|label| label.id)
+            .collect();
+        let payload: CreateTodo<I> =
+            serde_json::from_value(json!({ "text": text, "labels": label_ids }))?;
+        payload.validate()?;
+        todos.create(payload).await?;
+        summary.todos += 1;
+    }
+    Ok(summary)
+}
+
+const CSV_HEADER: [&str; 12] = [
+    "id",
+    "uuid",
+    "text",
+    "status",
+    "labels",
+    "tags",
+    "project_id",
+    "parent_id",
+    "assignee",
+    "remind_at",
+    "completed_at",
+    "pinned",
+];
+
+// カンマや引用符、改行を含む値は引用符で囲み、引用符は重ねる
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row<I: EntityId>(todo: &TodoEntity<I>) -> anyhow::Result<String> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let status = serde_json::to_value(todo.status)?;
+    let fields = [
+        todo.id.to_string(),
+        todo.uuid.to_string(),
+        todo.text.clone(),
+        status.as_str().unwrap_or_default().to_string(),
+        // 複数の値は;で区切る
+        todo.labels
+            .iter()
+            .map(|label| label.name.as_str())
+            .collect::<Vec<_>>()
+            .join(";"),
+        todo.tags.join(";"),
+        optional(todo.project_id.map(|id| id.to_string())),
+        optional(todo.parent_id.map(|id| id.to_string())),
+        optional(todo.assignee.as_ref().map(|user| user.name.clone())),
+        optional(todo.remind_at.map(|at| at.to_rfc3339())),
+        optional(todo.completed_at.map(|at| at.to_rfc3339())),
+        todo.pinned.to_string(),
+    ];
+    Ok(fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+// すべてのtodoを書き出し、件数を返す
+pub async fn export<I: EntityId>(
+    todos: &impl TodoRepository<I>,
+    format: ExportFormat,
+    out: &mut impl Write,
+) -> anyhow::Result<usize> {
+    let todos = todos.all(TodoFilter::default()).await?;
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER.join(","))?;
+            for todo in todos.iter() {
+                writeln!(out, "{}", csv_row(todo)?)?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &todos)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(todos.len())
+}
+
+// HTTPのサーバーを通さずにtodoを1件作る
+// APIと同じ検証をするため、リクエストと同じ形の本文から作る
+pub async fn add_todo<I: EntityId>(
+    todos: &impl TodoRepository<I>,
+    text: &str,
+) -> anyhow::Result<TodoEntity<I>> {
+    let payload: CreateTodo<I> = serde_json::from_value(json!({ "text": text, "labels": [] }))?;
+    payload.validate()?;
+    todos.create(payload).await
+}
+
+const API_KEY_LENGTH: usize = 32;
+
+// 管理者のユーザーを作り、API_KEYSに追加する定義を返す
+// キーはどこにも保存しないので、表示されたものを設定する
+pub async fn create_admin_user(
+    users: &impl UserRepository,
+    name: &str,
+) -> anyhow::Result<(User, String)> {
+    let name = name.trim();
+    // API_KEYSの区切り文字を含む名前は定義に書けない
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains([':', ',']),
+        "invalid user name [{}]",
+        name
+    );
+    let user = users.create(name.to_string()).await?;
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_LENGTH)
+        .map(char::from)
+        .collect();
+    Ok((user, format!("{}:{}:admin", name, key)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::{ApiKeys, Role};
+    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::users::test_utils::UserRepositoryForMemory;
+
+    fn parse(args: &[&str]) -> Result<Command, CliError> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        assert_eq!(parse(&[]), Ok(Command::Serve));
+        assert_eq!(parse(&["migrate"]), Ok(Command::Migrate));
+        assert_eq!(
+            parse(&["export"]),
+            Ok(Command::Export {
+                format: ExportFormat::Csv
+            })
+        );
+        assert_eq!(
+            parse(&["export", "--format", "json"]),
+            Ok(Command::Export {
+                format: ExportFormat::Json
+            })
+        );
+        assert_eq!(
+            parse(&["export", "--format=csv"]),
+            Ok(Command::Export {
+                format: ExportFormat::Csv
+            })
+        );
+        assert_eq!(
+            parse(&["create-admin-user", "alice"]),
+            Ok(Command::CreateAdminUser {
+                name: "alice".to_string()
+            })
+        );
+
+        assert_eq!(
+            parse(&["todo", "add", "buy milk"]),
+            Ok(Command::AddTodo {
+                text: "buy milk".to_string()
+            })
+        );
+
+        assert_eq!(
+            parse(&["export", "--format", "xml"]),
+            Err(CliError::UnknownFormat("xml".to_string()))
+        );
+        assert_eq!(
+            parse(&["export", "--format"]),
+            Err(CliError::MissingValue("--format"))
+        );
+        assert_eq!(
+            parse(&["create-admin-user"]),
+            Err(CliError::MissingValue("create-admin-user"))
+        );
+        assert_eq!(
+            parse(&["seed", "now"]),
+            Err(CliError::UnexpectedArgument("now".to_string()))
+        );
+        assert_eq!(
+            parse(&["todo", "add"]),
+            Err(CliError::MissingValue("todo add"))
+        );
+        assert_eq!(
+            parse(&["todo", "rm", "1"]),
+            Err(CliError::UnknownCommand("todo rm".to_string()))
+        );
+        assert_eq!(
+            parse(&["drop"]),
+            Err(CliError::UnknownCommand("drop".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_seed_only_once() {
+        // メモリのtodoのリポジトリは作成時に渡したラベルしか参照できないので、先に作っておく
+        let labels = LabelRepositoryForMemory::new();
+        let mut created = vec![];
+        for name in ["home", "work", "errand"] {
+            let payload: CreateLabel = serde_json::from_value(json!({ "name": name })).unwrap();
+            created.push(labels.create(payload).await.unwrap());
+        }
+        let todos = TodoRepositoryForMemory::new(created);
+
+        let summary = seed(&labels, &todos).await.unwrap();
+        assert_eq!(
+            summary,
+            SeedSummary {
+                labels: 0,
+                todos: SEED_TODOS.len()
+            }
+        );
+        assert_eq!(labels.all().await.unwrap().len(), SEED_LABELS.len());
+        let summary = seed(&labels, &todos).await.unwrap();
+        assert_eq!(summary, SeedSummary::default());
+        assert_eq!(
+            todos.all(TodoFilter::default()).await.unwrap().len(),
+            SEED_TODOS.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_export_todos_as_csv() {
+        let todos = TodoRepositoryForMemory::new(vec![]);
+        for text in ["plain", "with, comma", "say \"hi\""] {
+            let payload: CreateTodo =
+                serde_json::from_value(json!({ "text": text, "labels": [] })).unwrap();
+            todos.create(payload).await.unwrap();
+        }
+
+        let mut out = vec![];
+        let count = export(&todos, ExportFormat::Csv, &mut out).await.unwrap();
+        assert_eq!(count, 3);
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert!(lines.iter().any(|line| line.contains(",plain,backlog,")));
+        assert!(lines.iter().any(|line| line.contains(",\"with, comma\",")));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains(",\"say \"\"hi\"\"\",")));
+
+        let mut out = vec![];
+        export(&todos, ExportFormat::Json, &mut out).await.unwrap();
+        let exported: Vec<TodoEntity> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(exported.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_add_todo_with_validation() {
+        let todos = TodoRepositoryForMemory::new(vec![]);
+        let todo = add_todo(&todos, "water the plants").await.unwrap();
+        assert_eq!(todo.text, "water the plants");
+        assert_eq!(todos.find(todo.id).await.unwrap(), todo);
+
+        assert!(add_todo(&todos, "").await.is_err());
+        assert_eq!(todos.all(TodoFilter::default()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_create_admin_user_with_api_key() {
+        let users = UserRepositoryForMemory::new();
+        let (user, definition) = create_admin_user(&users, "ops").await.unwrap();
+        assert_eq!(user.name, "ops");
+
+        let key = definition.split(':').nth(1).unwrap();
+        assert_eq!(key.len(), API_KEY_LENGTH);
+        let principal = ApiKeys::parse(&definition)
+            .unwrap()
+            .authenticate(Some(&format!("Bearer {}", key)))
+            .unwrap();
+        assert_eq!(principal.name, "ops");
+        assert_eq!(principal.role, Role::Admin);
+
+        assert!(create_admin_user(&users, "ops").await.is_err());
+        assert!(create_admin_user(&users, "a:b").await.is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::handlers::export::EXPORT_PDF_PATH;
+use crate::metrics::Metrics;
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+// ルートごとの同時実行数の上限
+// 上限に達したルートへのリクエストは、先に来たものが終わるまで待たせる
+// 重いエクスポートやインポートが接続を使い切り、他のリクエストを止めないようにする
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimits {
+    routes: HashMap<String, RouteLimit>,
+}
+
+#[derive(Debug, Clone)]
+struct RouteLimit {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Default for ConcurrencyLimits {
+    // バックアップの書き出しと復元はどちらも全件を読み書きするので、1つずつ処理する
+    // PDFの書き出しは当てはまるtodoをすべて読んで描くので、2つまでにする
+    fn default() -> Self {
+        Self::new()
+            .route("/admin/backup", 1)
+            .route("/admin/restore", 1)
+            .route(EXPORT_PDF_PATH, 2)
+    }
+}
+
+impl ConcurrencyLimits {
+    pub fn new() -> Self {
+        Self {
+            routes: HashMap::new(),
+        }
+    }
+
+    // CONCURRENCY_LIMIT_ROUTES="<path>=<n>,..." で既定の上限を上書きする
+    // 0を指定したルートは制限しない
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut limits = Self::default();
+        if let Ok(routes) = env::var("CONCURRENCY_LIMIT_ROUTES") {
+            for entry in routes.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let (path, limit) = entry
+                    .trim()
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid concurrency limit: [{}]", entry))?;
+                limits = limits.route(path, limit.parse()?);
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn route(mut self, path: &str, limit: usize) -> Self {
+        if limit == 0 {
+            self.routes.remove(path);
+        } else {
+            let semaphore = Arc::new(Semaphore::new(limit));
+            self.routes
+                .insert(path.to_string(), RouteLimit { limit, semaphore });
+        }
+        self
+    }
+
+    fn for_route(&self, path: &str) -> Option<&RouteLimit> {
+        self.routes.get(path)
+    }
+}
+
+// 上限と処理中の数をメトリクスに記録する
+fn record_in_flight(metrics: Option<&Metrics>, route: &str, limit: &RouteLimit) {
+    if let Some(metrics) = metrics {
+        let in_flight = limit.limit - limit.semaphore.available_permits();
+        metrics.set_gauge(
+            "http_concurrency_limit",
+            &[("route", route)],
+            limit.limit as f64,
+        );
+        metrics.set_gauge(
+            "http_requests_in_flight",
+            &[("route", route)],
+            in_flight as f64,
+        );
+    }
+}
+
+// ルーティング後に適用し、上限のあるルートでは空くまで待ってから処理する
+// 待っている時間もリクエストのタイムアウトに含めるため、enforce_timeoutより内側に重ねる
+pub async fn limit_concurrency(req: Request<Body>, next: Next<Body>) -> Response {
+    let limits = req.extensions().get::<Arc<ConcurrencyLimits>>().cloned();
+    let metrics = req.extensions().get::<Arc<Metrics>>().cloned();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let Some(limit) = limits
+        .as_deref()
+        .and_then(|limits| limits.for_route(&route))
+        .cloned()
+    else {
+        return next.run(req).await;
+    };
+
+    let permit = match limit.semaphore.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            tracing::debug!("{} reached its concurrency limit of {}", route, limit.limit);
+            if let Some(metrics) = &metrics {
+                metrics.increment("http_concurrency_limited_total", &[("route", &route)]);
+            }
+            limit
+                .semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed")
+        }
+    };
+    record_in_flight(metrics.as_deref(), &route, &limit);
+    let res = next.run(req).await;
+    drop(permit);
+    record_in_flight(metrics.as_deref(), &route, &limit);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::extract::Extension;
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[derive(Default)]
+    struct Running {
+        now: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    async fn slow(Extension(running): Extension<Arc<Running>>) -> &'static str {
+        let now = running.now.fetch_add(1, Ordering::SeqCst) + 1;
+        running.max.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        running.now.fetch_sub(1, Ordering::SeqCst);
+        "ok"
+    }
+
+    #[tokio::test]
+    async fn should_run_limited_route_one_at_a_time() {
+        let metrics = Arc::new(Metrics::default());
+        // リクエストごとにアプリを作るので、上限は共有する
+        let limits = Arc::new(ConcurrencyLimits::new().route("/export", 1));
+        let app = |running: Arc<Running>| {
+            Router::new()
+                .route("/export", get(slow))
+                .route("/todos", get(slow))
+                .route_layer(from_fn(limit_concurrency))
+                .layer(Extension(running))
+                .layer(Extension(limits.clone()))
+                .layer(Extension(metrics.clone()))
+        };
+        let run = |path: &'static str, running: Arc<Running>| {
+            let app = app(running);
+            async move {
+                let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let running = Arc::new(Running::default());
+        let results = futures_util::future::join_all(
+            (0..3).map(|_| tokio::spawn(run("/export", running.clone()))),
+        )
+        .await;
+        assert!(results
+            .into_iter()
+            .all(|res| res.unwrap().status().is_success()));
+        assert_eq!(running.max.load(Ordering::SeqCst), 1);
+
+        // 上限のないルートは同時に処理する
+        let running = Arc::new(Running::default());
+        futures_util::future::join_all(
+            (0..3).map(|_| tokio::spawn(run("/todos", running.clone()))),
+        )
+        .await;
+        assert_eq!(running.max.load(Ordering::SeqCst), 3);
+
+        let text = metrics.render();
+        assert!(text.contains("http_concurrency_limit{route=\"/export\"} 1"));
+        assert!(text.contains("http_requests_in_flight{route=\"/export\"} 0"));
+        assert!(text.contains("http_concurrency_limited_total{route=\"/export\"} 2"));
+        assert!(!text.contains("route=\"/todos\""));
+    }
+
+    #[tokio::test]
+    async fn should_limit_pdf_export_by_default() {
+        let limits = Arc::new(ConcurrencyLimits::default());
+        let running = Arc::new(Running::default());
+        let app = Router::new()
+            .route(EXPORT_PDF_PATH, get(slow))
+            .route_layer(from_fn(limit_concurrency))
+            .layer(Extension(running.clone()))
+            .layer(Extension(limits));
+
+        let results = futures_util::future::join_all((0..4).map(|_| {
+            let req = Request::builder()
+                .uri(EXPORT_PDF_PATH)
+                .body(Body::empty())
+                .unwrap();
+            tokio::spawn(app.clone().oneshot(req))
+        }))
+        .await;
+        assert!(results
+            .into_iter()
+            .all(|res| res.unwrap().unwrap().status().is_success()));
+        assert_eq!(running.max.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_override_limit_per_route() {
+        let limits = ConcurrencyLimits::default()
+            .route("/admin/restore", 0)
+            .route("/todos/sync", 3);
+
+        assert_eq!(limits.for_route("/admin/backup").unwrap().limit, 1);
+        assert_eq!(limits.for_route("/todos/export.pdf").unwrap().limit, 2);
+        assert!(limits.for_route("/admin/restore").is_none());
+        assert_eq!(limits.for_route("/todos/sync").unwrap().limit, 3);
+        assert!(limits.for_route("/todos").is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::cache::DEFAULT_TTL;
+use crate::chaos::Chaos;
+use crate::handlers::ValidateJson;
+use crate::logging::LogFilter;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing_subscriber::EnvFilter;
+use validator::{Validate, ValidationError};
+
+// 再起動せずに変えられる設定
+// 起動時は環境変数から作り、PUT /admin/config で差し替える
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // todoのキャッシュの有効期間。0の場合はキャッシュしない
+    pub cache_ttl_secs: u64,
+    // GET /flaky の振る舞い
+    pub chaos: Chaos,
+    // RUST_LOGと同じ書式で指定する
+    #[validate(custom = "validate_log_level")]
+    pub log_level: String,
+}
+
+// 各レイヤーはリクエストのたびにここから読む
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cache_ttl_secs: DEFAULT_TTL.as_secs(),
+            chaos: Chaos::default(),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl Config {
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl_secs)
+    }
+
+    pub fn shared(self) -> SharedConfig {
+        Arc::new(RwLock::new(self))
+    }
+}
+
+fn validate_log_level(log_level: &str) -> Result<(), ValidationError> {
+    match EnvFilter::try_new(log_level) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("log_level")),
+    }
+}
+
+fn configured(config: Option<Extension<SharedConfig>>) -> Result<SharedConfig, StatusCode> {
+    config
+        .map(|Extension(config)| config)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn find_config(
+    config: Option<Extension<SharedConfig>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let config = configured(config)?;
+    let config = config.read().unwrap().clone();
+    Ok(Json(config))
+}
+
+fn reload_error(e: anyhow::Error) -> StatusCode {
+    tracing::error!("failed to reload log filter: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// ログのレベルは出力中のフィルターにも反映してから保存する
+pub async fn replace_config(
+    ValidateJson(payload): ValidateJson<Config>,
+    config: Option<Extension<SharedConfig>>,
+    log_filter: Option<Extension<LogFilter>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let config = configured(config)?;
+    let current = config.read().unwrap().log_level.clone();
+    if let Some(Extension(log_filter)) = log_filter.filter(|_| current != payload.log_level) {
+        log_filter.set(&payload.log_level).map_err(reload_error)?;
+    }
+    tracing::info!("config replaced: {:?}", payload);
+    *config.write().unwrap() = payload.clone();
+    Ok(Json(payload))
+}
+
+// PUT /admin/log-level のボディ
+// duration_secsを指定した場合は、その秒数が過ぎたら元のレベルに戻す
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct SetLogLevel {
+    #[validate(custom = "validate_log_level")]
+    level: String,
+    #[validate(range(min = 1, max = 86400))]
+    duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LogLevel {
+    pub level: String,
+    // 一時的に変えた場合の戻す先と戻す日時
+    pub revert_to: Option<String>,
+    pub revert_at: Option<DateTime<Utc>>,
+}
+
+// 不具合の調査中だけdebugにするなど、再デプロイせずにログのレベルを変える
+pub async fn set_log_level(
+    ValidateJson(payload): ValidateJson<SetLogLevel>,
+    log_filter: Option<Extension<LogFilter>>,
+    config: Option<Extension<SharedConfig>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let Extension(log_filter) = log_filter.ok_or(StatusCode::NOT_FOUND)?;
+    let config = config.map(|Extension(config)| config);
+    let previous = log_filter.current();
+    let generation = log_filter.set(&payload.level).map_err(reload_error)?;
+    record_log_level(config.as_ref(), &payload.level);
+    tracing::info!("log level changed from {} to {}", previous, payload.level);
+
+    let revert_at = payload.duration_secs.map(|secs| {
+        spawn_log_level_revert(
+            log_filter,
+            config,
+            generation,
+            previous.clone(),
+            Duration::from_secs(secs),
+        );
+        Utc::now() + chrono::Duration::seconds(secs as i64)
+    });
+    Ok(Json(LogLevel {
+        level: payload.level,
+        revert_to: revert_at.map(|_| previous),
+        revert_at,
+    }))
+}
+
+fn record_log_level(config: Option<&SharedConfig>, level: &str) {
+    if let Some(config) = config {
+        config.write().unwrap().log_level = level.to_string();
+    }
+}
+
+// 期間が過ぎたら元に戻す。その間に別の変更があればそちらを優先する
+fn spawn_log_level_revert(
+    log_filter: LogFilter,
+    config: Option<SharedConfig>,
+    generation: u64,
+    previous: String,
+    after: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        sleep(after).await;
+        match log_filter.set_if_unchanged(generation, &previous) {
+            Ok(true) => {
+                record_log_level(config.as_ref(), &previous);
+                tracing::info!("log level reverted to {}", previous);
+            }
+            Ok(false) => {}
+            Err(e) => tracing::error!("failed to revert log level: {}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_read_and_write_config_as_json() {
+        let config = Config {
+            cache_ttl_secs: 10,
+            chaos: Chaos::default()
+                .failure_rate(0.1)
+                .unwrap()
+                .latency("20")
+                .unwrap(),
+            log_level: "rust_simple_api=debug,info".to_string(),
+        };
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "cache_ttl_secs": 10,
+                "chaos": { "failure_rate": 0.1, "latency_ms": "20" },
+                "log_level": "rust_simple_api=debug,info",
+            })
+        );
+        assert_eq!(serde_json::from_value::<Config>(value).unwrap(), config);
+        assert_eq!(
+            serde_json::to_value(Config::default()).unwrap()["chaos"]["latency_ms"],
+            "1000-7000"
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_config() {
+        let invalid_chaos = json!({
+            "cache_ttl_secs": 10,
+            "chaos": { "failure_rate": 2, "latency_ms": "0" },
+            "log_level": "info",
+        });
+        assert!(serde_json::from_value::<Config>(invalid_chaos).is_err());
+
+        let config = Config {
+            log_level: "rust_simple_api=loud".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_revert_temporary_log_level() {
+        let log_filter = LogFilter::detached("info");
+        let config = Config::default().shared();
+
+        let generation = log_filter.set("debug").unwrap();
+        let revert = spawn_log_level_revert(
+            log_filter.clone(),
+            Some(config.clone()),
+            generation,
+            "info".to_string(),
+            Duration::from_millis(10),
+        );
+        revert.await.unwrap();
+        assert_eq!(log_filter.current(), "info");
+        assert_eq!(config.read().unwrap().log_level, "info");
+
+        // 戻す前に別の変更があれば戻さない
+        let generation = log_filter.set("debug").unwrap();
+        let revert = spawn_log_level_revert(
+            log_filter.clone(),
+            None,
+            generation,
+            "info".to_string(),
+            Duration::from_millis(10),
+        );
+        log_filter.set("trace").unwrap();
+        revert.await.unwrap();
+        assert_eq!(log_filter.current(), "trace");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::atom::escape;
+use std::fmt::Write;
+
+// WebDAV(RFC 4918)とCalDAV(RFC 4791)の応答の書き出し
+// カレンダーアプリがtodoの一覧をVTODOとして読めるだけの最小限のプロパティを返す
+
+pub const CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceType {
+    Collection,
+    Calendar,
+    Item,
+}
+
+// 要求されたプロパティによらず、リソースごとに決まったものを返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prop {
+    ResourceType(ResourceType),
+    DisplayName(String),
+    CurrentUserPrincipal(String),
+    CalendarHomeSet(String),
+    // 今は読み取りだけを許す
+    ReadOnly,
+    SupportedTodos,
+    Ctag(String),
+    Etag(String),
+    ContentType(&'static str),
+    CalendarData(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub href: String,
+    pub props: Vec<Prop>,
+}
+
+pub fn multistatus(responses: &[Response]) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    xml.push('\n');
+    xml.push_str(r#"<D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav" xmlns:CS="http://calendarserver.org/ns/">"#);
+    xml.push('\n');
+    for response in responses.iter() {
+        xml.push_str("  <D:response>\n");
+        writeln!(xml, "    <D:href>{}</D:href>", escape(&response.href)).unwrap();
+        xml.push_str("    <D:propstat>\n      <D:prop>\n");
+        for prop in response.props.iter() {
+            xml.push_str("        ");
+            write_prop(&mut xml, prop);
+            xml.push('\n');
+        }
+        xml.push_str("      </D:prop>\n");
+        xml.push_str("      <D:status>HTTP/1.1 200 OK</D:status>\n");
+        xml.push_str("    </D:propstat>\n  </D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+fn write_prop(xml: &mut String, prop: &Prop) {
+    match prop {
+        Prop::ResourceType(ResourceType::Collection) => {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>")
+        }
+        Prop::ResourceType(ResourceType::Calendar) => {
+            xml.push_str("<D:resourcetype><D:collection/><C:calendar/></D:resourcetype>")
+        }
+        Prop::ResourceType(ResourceType::Item) => xml.push_str("<D:resourcetype/>"),
+        Prop::DisplayName(name) => {
+            write!(xml, "<D:displayname>{}</D:displayname>", escape(name)).unwrap()
+        }
+        Prop::CurrentUserPrincipal(href) => write!(
+            xml,
+            "<D:current-user-principal><D:href>{}</D:href></D:current-user-principal>",
+            escape(href)
+        )
+        .unwrap(),
+        Prop::CalendarHomeSet(href) => write!(
+            xml,
+            "<C:calendar-home-set><D:href>{}</D:href></C:calendar-home-set>",
+            escape(href)
+        )
+        .unwrap(),
+        Prop::ReadOnly => xml.push_str(
+            "<D:current-user-privilege-set><D:privilege><D:read/></D:privilege></D:current-user-privilege-set>",
+        ),
+        Prop::SupportedTodos => xml.push_str(
+            r#"<C:supported-calendar-component-set><C:comp name="VTODO"/></C:supported-calendar-component-set>"#,
+        ),
+        Prop::Ctag(ctag) => write!(xml, "<CS:getctag>{}</CS:getctag>", escape(ctag)).unwrap(),
+        Prop::Etag(etag) => write!(xml, "<D:getetag>{}</D:getetag>", escape(etag)).unwrap(),
+        Prop::ContentType(content_type) => write!(
+            xml,
+            "<D:getcontenttype>{}</D:getcontenttype>",
+            escape(content_type)
+        )
+        .unwrap(),
+        Prop::CalendarData(ics) => write!(
+            xml,
+            "<C:calendar-data>{}</C:calendar-data>",
+            escape(ics)
+        )
+        .unwrap(),
+    }
+}
+
+// calendar-multigetで指定されたhrefを取り出す
+// 名前空間の接頭辞はクライアントによって違うので、要素名の後ろだけで見分ける
+pub fn requested_hrefs(body: &str) -> Vec<String> {
+    body.split('<')
+        .filter_map(|tag| {
+            let (name, text) = tag.split_once('>')?;
+            let local = name.split_whitespace().next()?.rsplit(':').next()?;
+            (local == "href").then(|| text.trim().to_string())
+        })
+        .filter(|href| !href.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_write_multistatus() {
+        let responses = vec![Response {
+            href: "/dav/todos/".to_string(),
+            props: vec![
+                Prop::ResourceType(ResourceType::Calendar),
+                Prop::DisplayName("todos & more".to_string()),
+                Prop::SupportedTodos,
+            ],
+        }];
+        assert_eq!(
+            multistatus(&responses),
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav" xmlns:CS="http://calendarserver.org/ns/">
+  <D:response>
+    <D:href>/dav/todos/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/><C:calendar/></D:resourcetype>
+        <D:displayname>todos &amp; more</D:displayname>
+        <C:supported-calendar-component-set><C:comp name="VTODO"/></C:supported-calendar-component-set>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>
+"#
+        );
+    }
+
+    #[test]
+    fn should_read_hrefs_with_any_prefix() {
+        let body = r#"<?xml version="1.0"?>
+<C:calendar-multiget xmlns:d="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <d:prop><d:getetag/></d:prop>
+  <d:href>/dav/todos/a.ics</d:href>
+  <href xmlns="DAV:"> /dav/todos/b.ics </href>
+</C:calendar-multiget>"#;
+        assert_eq!(
+            requested_hrefs(body),
+            vec!["/dav/todos/a.ics", "/dav/todos/b.ics"]
+        );
+    }
+}
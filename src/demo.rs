@@ -0,0 +1,328 @@
+use crate::handlers::ApiError;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::header::{HeaderValue, RETRY_AFTER};
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(any(test, feature = "test-utils"))]
+pub use store::{spawn_demo_reset, DemoStore};
+
+const DEFAULT_RESET_MINUTES: u64 = 30;
+const DEFAULT_RATE_LIMIT: u32 = 60;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+// 公開する試験環境の設定。データはメモリに置き、定期的に見本のデータへ戻す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DemoMode {
+    pub reset_interval: Option<Duration>,
+    // 接続元ごとの1分あたりのリクエスト数の上限
+    pub rate_limit: Option<u32>,
+}
+
+impl DemoMode {
+    // DEMO_MODE=true で有効にする
+    // DEMO_RESET_MINUTES で作り直す間隔を、DEMO_RATE_LIMIT で上限を指定する。どちらも0で無効にする
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        if !env::var("DEMO_MODE").is_ok_and(|value| value == "true") {
+            return Ok(None);
+        }
+        let reset_minutes = match env::var("DEMO_RESET_MINUTES") {
+            Ok(minutes) if !minutes.is_empty() => minutes.parse()?,
+            _ => DEFAULT_RESET_MINUTES,
+        };
+        let rate_limit = match env::var("DEMO_RATE_LIMIT") {
+            Ok(requests) if !requests.is_empty() => requests.parse()?,
+            _ => DEFAULT_RATE_LIMIT,
+        };
+        Ok(Some(Self {
+            reset_interval: (reset_minutes > 0).then(|| Duration::from_secs(reset_minutes * 60)),
+            rate_limit: Some(rate_limit).filter(|requests| *requests > 0),
+        }))
+    }
+}
+
+// 接続元のアドレスごとに1分単位でリクエストを数える
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: u32,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            windows: Mutex::default(),
+        }
+    }
+
+    // 上限を超えた場合は次の区切りまでの時間を返す
+    fn check(&self, addr: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap();
+        // 訪問者が入れ替わっても増え続けないよう、終わった区切りを捨てる
+        windows.retain(|_, (started_at, _)| now.duration_since(*started_at) < RATE_LIMIT_WINDOW);
+        let (started_at, requests) = windows.entry(addr).or_insert((now, 0));
+        *requests += 1;
+        if *requests > self.limit {
+            return Err(RATE_LIMIT_WINDOW.saturating_sub(now.duration_since(*started_at)));
+        }
+        Ok(())
+    }
+}
+
+// デモで使う制限。Extensionで渡したアプリだけに効く
+#[derive(Debug, Default)]
+pub struct DemoGuard {
+    limiter: Option<RateLimiter>,
+}
+
+impl DemoGuard {
+    pub fn new(demo: DemoMode) -> Self {
+        Self {
+            limiter: demo.rate_limit.map(RateLimiter::new),
+        }
+    }
+}
+
+// バックアップや設定の変更など、/admin/* への書き込みは403にする
+// 接続元ごとの上限を超えたら429を返す。接続元の分からないリクエストは数えない
+pub async fn guard_demo(req: Request<Body>, next: Next<Body>) -> Response {
+    let Some(guard) = req.extensions().get::<Arc<DemoGuard>>().cloned() else {
+        return next.run(req).await;
+    };
+    if req.uri().path().starts_with("/admin/") && req.method() != Method::GET {
+        return ApiError::new(StatusCode::FORBIDDEN, "demo.disabled").into_response();
+    }
+    let addr = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let (Some(limiter), Some(addr)) = (&guard.limiter, addr) {
+        if let Err(retry_after) = limiter.check(addr, Instant::now()) {
+            let mut res =
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, "demo.rate_limited").into_response();
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+            return res;
+        }
+    }
+    next.run(req).await
+}
+
+// メモリ上のリポジトリはtest-utilsでだけ使える
+#[cfg(any(test, feature = "test-utils"))]
+mod store {
+    use crate::auth::ApiKeys;
+    use crate::cli::{self, SeedSummary};
+    use crate::create_apps;
+    use crate::events::EventBus;
+    use crate::repositories::audit::test_utils::AuditLogRepositoryForMemory;
+    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
+    use crate::repositories::projects::test_utils::ProjectRepositoryForMemory;
+    use crate::repositories::share_links::test_utils::ShareLinkRepositoryForMemory;
+    use crate::repositories::templates::test_utils::TemplateRepositoryForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::users::test_utils::UserRepositoryForMemory;
+    use crate::repositories::views::test_utils::ViewRepositoryForMemory;
+    use axum::Router;
+    use std::time::Duration;
+    use tokio::task::JoinHandle;
+
+    // デモで使うメモリ上のリポジトリ。クローンは中身を共有する
+    #[derive(Debug, Clone)]
+    pub struct DemoStore {
+        labels: LabelRepositoryForMemory,
+        todos: TodoRepositoryForMemory,
+        audit_logs: AuditLogRepositoryForMemory,
+        users: UserRepositoryForMemory,
+        projects: ProjectRepositoryForMemory,
+        views: ViewRepositoryForMemory,
+        templates: TemplateRepositoryForMemory,
+        share_links: ShareLinkRepositoryForMemory,
+    }
+
+    impl Default for DemoStore {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl DemoStore {
+        pub fn new() -> Self {
+            let labels = LabelRepositoryForMemory::new();
+            Self {
+                todos: TodoRepositoryForMemory::with_labels(labels.clone()),
+                labels,
+                audit_logs: AuditLogRepositoryForMemory::new(),
+                users: UserRepositoryForMemory::new(),
+                projects: ProjectRepositoryForMemory::new(),
+                views: ViewRepositoryForMemory::new(),
+                templates: TemplateRepositoryForMemory::new(),
+                share_links: ShareLinkRepositoryForMemory::new(),
+            }
+        }
+
+        pub fn todos(&self) -> &TodoRepositoryForMemory {
+            &self.todos
+        }
+
+        pub fn labels(&self) -> &LabelRepositoryForMemory {
+            &self.labels
+        }
+
+        // 訪問者が作ったものをすべて捨て、見本のデータだけに戻す
+        pub async fn reset(&self) -> anyhow::Result<SeedSummary> {
+            self.share_links.clear();
+            self.templates.clear();
+            self.views.clear();
+            self.projects.clear();
+            self.users.clear();
+            self.audit_logs.clear();
+            self.todos.clear();
+            self.labels.clear();
+            cli::seed(&self.labels, &self.todos).await
+        }
+
+        pub fn create_apps(
+            &self,
+            events: EventBus,
+            api_keys: ApiKeys,
+            separate_admin: bool,
+        ) -> (Router, Option<Router>) {
+            create_apps(
+                self.todos.clone(),
+                self.labels.clone(),
+                self.audit_logs.clone(),
+                self.users.clone(),
+                self.projects.clone(),
+                self.views.clone(),
+                self.templates.clone(),
+                self.share_links.clone(),
+                events,
+                api_keys,
+                separate_admin,
+            )
+        }
+    }
+
+    pub fn spawn_demo_reset(store: DemoStore, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // 起動時に作ったばかりなので、最初の1回は待つだけにする
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match store.reset().await {
+                    Ok(summary) => tracing::info!(
+                        "reset demo data with {} labels and {} todos",
+                        summary.labels,
+                        summary.todos
+                    ),
+                    Err(e) => tracing::error!("failed to reset demo data: {}", e),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::ApiKeys;
+    use crate::events::EventBus;
+    use crate::repositories::labels::LabelRepository;
+    use crate::repositories::todo::{TodoFilter, TodoRepository};
+    use axum::extract::Extension;
+    use axum::middleware::from_fn;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn should_reset_to_seeded_data() {
+        let store = DemoStore::new();
+        let seeded = store.reset().await.unwrap();
+        let payload =
+            serde_json::from_value(serde_json::json!({ "text": "visitor", "labels": [] })).unwrap();
+        let created = store.todos().create(payload).await.unwrap();
+        assert_eq!(seeded.todos + 1, created.id as usize);
+
+        let summary = store.reset().await.unwrap();
+        assert_eq!(seeded, summary);
+        let todos = store.todos().all(TodoFilter::default()).await.unwrap();
+        assert_eq!(seeded.todos, todos.len());
+        assert!(todos.iter().all(|todo| todo.text != "visitor"));
+        let labels = store.labels().all().await.unwrap();
+        assert_eq!(
+            (1..=seeded.labels as i32).collect::<Vec<_>>(),
+            labels.iter().map(|label| label.id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_limit_requests_per_address_and_window() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        let alice: IpAddr = "192.0.2.1".parse().unwrap();
+        let bob: IpAddr = "192.0.2.2".parse().unwrap();
+        assert_eq!(Ok(()), limiter.check(alice, now));
+        assert_eq!(Ok(()), limiter.check(alice, now + Duration::from_secs(10)));
+        assert_eq!(
+            Err(Duration::from_secs(40)),
+            limiter.check(alice, now + Duration::from_secs(20))
+        );
+        assert_eq!(Ok(()), limiter.check(bob, now + Duration::from_secs(20)));
+        assert_eq!(Ok(()), limiter.check(alice, now + RATE_LIMIT_WINDOW));
+    }
+
+    #[tokio::test]
+    async fn should_reject_admin_writes_and_busy_clients() {
+        let store = DemoStore::new();
+        store.reset().await.unwrap();
+        let (app, _) = store.create_apps(EventBus::default(), ApiKeys::default(), false);
+        let guard = DemoGuard::new(DemoMode {
+            reset_interval: None,
+            rate_limit: Some(2),
+        });
+        let app = app
+            .layer(from_fn(guard_demo))
+            .layer(Extension(Arc::new(guard)));
+        let request = |method: Method, uri: &str| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 4000))));
+            req
+        };
+
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, "/admin/restore"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app.oneshot(request(Method::GET, "/todos")).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        assert!(res.headers().contains_key(RETRY_AFTER));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::notifier::{Notification, Notifier};
+use crate::repositories::todo::{TodoEntity, TodoFilter, TodoRepository, TodoStatus};
+use crate::repositories::EntityId;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const DIGEST_DAYS: i64 = 7;
+const DEFAULT_DIGEST_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+
+// まとめに載せるtodo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestItem<I = i32> {
+    pub id: I,
+    pub text: String,
+    pub due_at: DateTime<Utc>,
+}
+
+impl<I: EntityId> From<&TodoEntity<I>> for DigestItem<I> {
+    fn from(todo: &TodoEntity<I>) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text.clone(),
+            due_at: todo.due_at.unwrap_or_default(),
+        }
+    }
+}
+
+// 直近1週間のまとめ。recipientは担当者の名前で、Noneはワークスペース全体
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Digest<I = i32> {
+    pub recipient: Option<String>,
+    // 1週間以内に完了した数
+    pub completed: usize,
+    // 期限を過ぎても完了も中止もしていないもの
+    pub overdue: Vec<DigestItem<I>>,
+    // 1週間以内に期限が来るもの
+    pub upcoming: Vec<DigestItem<I>>,
+}
+
+impl<I: EntityId> Digest<I> {
+    fn compile(recipient: Option<String>, todos: &[&TodoEntity<I>], now: DateTime<Utc>) -> Self {
+        let week = ChronoDuration::days(DIGEST_DAYS);
+        let completed = todos
+            .iter()
+            .filter(|todo| todo.status.is_completed())
+            .filter(|todo| todo.completed_at.is_some_and(|at| at > now - week))
+            .count();
+        let mut open: Vec<&TodoEntity<I>> = todos
+            .iter()
+            .copied()
+            .filter(|todo| !matches!(todo.status, TodoStatus::Done | TodoStatus::Cancelled))
+            .filter(|todo| todo.due_at.is_some())
+            .collect();
+        open.sort_by_key(|todo| (todo.due_at, todo.id));
+        let overdue = open
+            .iter()
+            .filter(|todo| todo.due_at < Some(now))
+            .map(|todo| DigestItem::from(*todo))
+            .collect();
+        let upcoming = open
+            .iter()
+            .filter(|todo| todo.due_at >= Some(now) && todo.due_at < Some(now + week))
+            .map(|todo| DigestItem::from(*todo))
+            .collect();
+        Self {
+            recipient,
+            completed,
+            overdue,
+            upcoming,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.completed == 0 && self.overdue.is_empty() && self.upcoming.is_empty()
+    }
+
+    fn notification(&self) -> Notification {
+        let mut body = format!("Completed this week: {}\n", self.completed);
+        for (title, items) in [("Overdue", &self.overdue), ("Upcoming", &self.upcoming)] {
+            if items.is_empty() {
+                continue;
+            }
+            let _ = writeln!(body, "{} ({}):", title, items.len());
+            for item in items {
+                let _ = writeln!(
+                    body,
+                    "- #{} {} (due {})",
+                    item.id,
+                    item.text,
+                    item.due_at.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+        }
+        Notification {
+            subject: format!(
+                "Weekly digest for {}",
+                self.recipient.as_deref().unwrap_or("workspace")
+            ),
+            body,
+        }
+    }
+}
+
+// ワークスペース全体と担当者ごとのまとめを作る。報告することのないものは除く
+pub async fn compile_digests<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<Digest<I>>> {
+    let todos = repository.all(TodoFilter::default()).await?;
+    let mut assigned: BTreeMap<(String, i32), Vec<&TodoEntity<I>>> = BTreeMap::new();
+    for todo in todos.iter() {
+        if let Some(user) = &todo.assignee {
+            assigned
+                .entry((user.name.clone(), user.id))
+                .or_default()
+                .push(todo);
+        }
+    }
+    let workspace = Digest::compile(None, &todos.iter().collect::<Vec<_>>(), now);
+    let digests = std::iter::once(workspace)
+        .chain(
+            assigned
+                .into_iter()
+                .map(|((name, _), todos)| Digest::compile(Some(name), &todos, now)),
+        )
+        .filter(|digest| !digest.is_empty())
+        .collect();
+    Ok(digests)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestRun {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+// まとめを作って通知する。送れなかったものは記録して残りを続ける
+pub async fn dispatch_digests<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    notifier: &dyn Notifier,
+) -> anyhow::Result<DigestRun> {
+    let mut run = DigestRun::default();
+    for digest in compile_digests(repository, Utc::now()).await? {
+        let notification = digest.notification();
+        match notifier.notify(&notification).await {
+            Ok(()) => run.sent += 1,
+            Err(e) => {
+                tracing::error!("failed to send {}: {}", notification.subject, e);
+                run.failed += 1;
+            }
+        }
+    }
+    Ok(run)
+}
+
+// DIGEST_INTERVAL_SECS で送る間隔を指定する。未定義なら1週間ごと、0で送らない
+pub fn digest_interval_from_env() -> anyhow::Result<Option<Duration>> {
+    match env::var("DIGEST_INTERVAL_SECS") {
+        Ok(secs) if !secs.is_empty() => {
+            let secs: u64 = secs.parse()?;
+            Ok((secs > 0).then(|| Duration::from_secs(secs)))
+        }
+        _ => Ok(Some(Duration::from_secs(DEFAULT_DIGEST_INTERVAL_SECS))),
+    }
+}
+
+pub fn spawn_digest_scheduler<I: EntityId, T: TodoRepository<I>>(
+    repository: T,
+    notifier: Arc<dyn Notifier>,
+    period: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // 起動のたびに送らないよう、最初の1回は待つだけにする
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match dispatch_digests(&repository, notifier.as_ref()).await {
+                Ok(run) => tracing::info!("dispatched {} digests", run.sent),
+                Err(e) => tracing::error!("failed to compile digests: {}", e),
+            }
+        }
+    })
+}
+
+// POST /admin/digest/run で次の予定を待たずに送る
+pub async fn run_digest<S: State>(
+    Extension(state): Extension<S>,
+    notifier: Option<Extension<Arc<dyn Notifier>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let Extension(notifier) = notifier.ok_or(StatusCode::NOT_FOUND)?;
+    let run = dispatch_digests(state.todos(), notifier.as_ref())
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(run)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notifier::test_utils::NotifierForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::todo::{CreateTodo, UpdateTodo};
+    use crate::repositories::users::User;
+
+    async fn create(repository: &TodoRepositoryForMemory, text: &str, due_in_days: i64) -> i32 {
+        let payload: CreateTodo = serde_json::from_value(serde_json::json!({
+            "text": text,
+            "labels": [],
+            "due_at": Utc::now() + ChronoDuration::days(due_in_days),
+        }))
+        .unwrap();
+        repository.create(payload).await.unwrap().id
+    }
+
+    #[tokio::test]
+    async fn should_dispatch_digest_per_assignee() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let overdue = create(&repository, "overdue", -1).await;
+        let upcoming = create(&repository, "upcoming", 3).await;
+        let later = create(&repository, "later", 30).await;
+        let done = create(&repository, "done", -2).await;
+        let alice = User {
+            id: 1,
+            name: "alice".to_string(),
+        };
+        for id in [overdue, done] {
+            repository.assign(id, Some(alice.clone())).await.unwrap();
+        }
+        let update: UpdateTodo = serde_json::from_value(serde_json::json!({ "status": "done" }))
+            .expect("failed to build update");
+        repository.update(done, update).await.unwrap();
+
+        let digests = compile_digests(&repository, Utc::now()).await.unwrap();
+        let summary: Vec<_> = digests
+            .iter()
+            .map(|digest| {
+                (
+                    digest.recipient.as_deref(),
+                    digest.completed,
+                    digest
+                        .overdue
+                        .iter()
+                        .map(|item| item.id)
+                        .collect::<Vec<_>>(),
+                    digest
+                        .upcoming
+                        .iter()
+                        .map(|item| item.id)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (None, 1, vec![overdue], vec![upcoming]),
+                (Some("alice"), 1, vec![overdue], vec![]),
+            ],
+            summary
+        );
+        assert!(!digests[0].upcoming.iter().any(|item| item.id == later));
+
+        let notifier = NotifierForMemory::default();
+        let run = dispatch_digests(&repository, &notifier).await.unwrap();
+        assert_eq!(DigestRun { sent: 2, failed: 0 }, run);
+        let sent = notifier.sent.lock().unwrap();
+        assert_eq!("Weekly digest for workspace", sent[0].subject);
+        assert_eq!("Weekly digest for alice", sent[1].subject);
+        assert!(sent[1].body.contains(&format!("- #{} overdue", overdue)));
+    }
+
+    #[tokio::test]
+    async fn should_skip_digest_without_news() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        create(&repository, "later", 30).await;
+        let digests = compile_digests(&repository, Utc::now()).await.unwrap();
+        assert!(digests.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::handlers::ApiError;
+use crate::replay::{is_recent, verify_hmac_sha256, ReplayCache};
+use crate::repositories::todo::{CreateTodo, TodoEntity, TodoRepository};
+use crate::repositories::users::UserRepository;
+use crate::state::State;
+use axum::body::Bytes;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+use validator::Validate;
+
+// 受信したメールからtodoを作る連携
+// メール配信サービス(Mailgunのroute形式)が転送してきたメールの件名を本文に、メール本文を説明にする
+// 添付ファイルを保存する仕組みはまだないので、添付は受け取っても捨てる
+pub const EMAIL_INBOUND_PATH: &str = "/integrations/email";
+
+// todoの本文の上限に合わせて件名を切り詰める
+const MAX_SUBJECT_CHARS: usize = 100;
+
+// 配信サービスの署名キーと、送信元アドレスからAPIのユーザー名への対応表
+// 対応表にないアドレスからのメールは受け付けない
+#[derive(Debug, Clone)]
+pub struct EmailIntegration {
+    signing_key: String,
+    users: HashMap<String, String>,
+    tokens: ReplayCache,
+}
+
+impl EmailIntegration {
+    pub fn new(signing_key: impl Into<String>) -> Self {
+        Self {
+            signing_key: signing_key.into(),
+            users: HashMap::new(),
+            tokens: ReplayCache::new(),
+        }
+    }
+
+    // INBOUND_EMAIL_SIGNING_KEY で署名キーを、
+    // INBOUND_EMAIL_USERS="<address>=<user name>,..." で送信元の対応を指定する
+    // 署名キーを指定しない場合は連携しない
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let mut email = match env::var("INBOUND_EMAIL_SIGNING_KEY") {
+            Ok(key) if !key.is_empty() => Self::new(key),
+            _ => return Ok(None),
+        };
+        if let Ok(users) = env::var("INBOUND_EMAIL_USERS") {
+            for entry in users.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let (address, name) = entry
+                    .trim()
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid inbound email user: [{}]", entry))?;
+                email = email.user(address, name);
+            }
+        }
+        Ok(Some(email))
+    }
+
+    // アドレスは大文字と小文字を区別しない
+    pub fn user(mut self, address: &str, name: &str) -> Self {
+        self.users
+            .insert(address.trim().to_lowercase(), name.to_string());
+        self
+    }
+
+    // 署名は "<timestamp><token>" に対するHMAC-SHA256
+    // 古い時刻の署名と、一度受け付けたtokenは拒否する
+    fn verify(&self, mail: &InboundEmail) -> Result<(), ApiError> {
+        let unauthorized = ApiError::new(StatusCode::UNAUTHORIZED, "auth.unauthorized");
+        let message: [&[u8]; 2] = [mail.timestamp.as_bytes(), mail.token.as_bytes()];
+        if !verify_hmac_sha256(&self.signing_key, &message, &mail.signature) {
+            return Err(unauthorized);
+        }
+        match mail.timestamp.parse() {
+            Ok(signed_at) if is_recent(signed_at, Utc::now().timestamp()) => {}
+            _ => return Err(unauthorized),
+        }
+        if !self.tokens.first_seen(&mail.token, Instant::now()) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "integration.replayed"));
+        }
+        Ok(())
+    }
+}
+
+// 配信サービスがフォームで送るペイロードのうち、使う項目だけ
+#[derive(Debug, Deserialize)]
+struct InboundEmail {
+    sender: String,
+    #[serde(default)]
+    subject: String,
+    // 引用や署名を除いた本文があればそちらを使う
+    #[serde(default, rename = "body-plain")]
+    body_plain: String,
+    #[serde(default, rename = "stripped-text")]
+    stripped_text: Option<String>,
+    timestamp: String,
+    token: String,
+    signature: String,
+}
+
+impl InboundEmail {
+    fn text(&self) -> String {
+        self.subject
+            .trim()
+            .chars()
+            .take(MAX_SUBJECT_CHARS)
+            .collect()
+    }
+
+    fn description(&self) -> Option<String> {
+        let body = self.stripped_text.as_deref().unwrap_or(&self.body_plain);
+        Some(body.trim().to_string()).filter(|body| !body.is_empty())
+    }
+}
+
+// POST /integrations/email
+// 配信サービスは4xxの多くを再送するため、受け付けられないメールは再送しない406で返す
+pub async fn receive_email<S: State>(
+    body: Bytes,
+    Extension(state): Extension<S>,
+    email: Option<Extension<Arc<EmailIntegration>>>,
+) -> Result<(StatusCode, Json<TodoEntity<S::Id>>), ApiError> {
+    let Some(Extension(email)) = email else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    let mail: InboundEmail =
+        serde_urlencoded::from_bytes(&body).or(Err(StatusCode::BAD_REQUEST))?;
+    email.verify(&mail)?;
+
+    let not_acceptable = |key| ApiError::new(StatusCode::NOT_ACCEPTABLE, key);
+    let Some(name) = email.users.get(&mail.sender.trim().to_lowercase()) else {
+        return Err(not_acceptable("integration.unknown_sender"));
+    };
+    let user = state
+        .users()
+        .find_by_name(name)
+        .await
+        .or(Err(not_acceptable("integration.unknown_sender")))?;
+    let payload = CreateTodo::from_template(mail.text(), vec![], vec![])
+        .owned_by(Some(user.id))
+        .described(mail.description());
+    if payload.validate().is_err() {
+        return Err(not_acceptable("integration.invalid_todo"));
+    }
+    let todo = state
+        .todos()
+        .create(payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mail(subject: &str, body_plain: &str, stripped_text: Option<&str>) -> InboundEmail {
+        InboundEmail {
+            sender: String::from("alice@example.com"),
+            subject: subject.to_string(),
+            body_plain: body_plain.to_string(),
+            stripped_text: stripped_text.map(str::to_string),
+            timestamp: String::new(),
+            token: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn should_map_email_to_todo_fields() {
+        let m = mail("  Buy milk ", "2 bottles\n\n> quoted", Some("2 bottles"));
+        assert_eq!(m.text(), "Buy milk");
+        assert_eq!(m.description().as_deref(), Some("2 bottles"));
+
+        let m = mail(&"a".repeat(120), "  \n", None);
+        assert_eq!(m.text().chars().count(), MAX_SUBJECT_CHARS);
+        assert_eq!(m.description(), None);
+    }
+}
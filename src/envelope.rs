@@ -0,0 +1,165 @@
+use crate::handlers::{INTEGRATIONS_PREFIX, X_TOTAL_COUNT};
+use axum::body::{boxed, Body, Full};
+use axum::http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, LINK};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Map, Value};
+use std::env;
+
+// リクエスト単位で封筒形式にするかを指定するヘッダー
+pub const X_ENVELOPE: &str = "x-envelope";
+
+// レスポンスを {"data": ..., "meta": ...} / {"error": ...} の形にそろえるか
+// RESPONSE_ENVELOPE=true で既定にし、X-Envelope: true|false でリクエストごとに上書きできる
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResponseEnvelope(pub bool);
+
+impl ResponseEnvelope {
+    pub fn from_env() -> Self {
+        ResponseEnvelope(env::var("RESPONSE_ENVELOPE").is_ok_and(|value| value == "true"))
+    }
+}
+
+fn is_enabled(req: &Request<Body>) -> bool {
+    match req
+        .headers()
+        .get(X_ENVELOPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => value.eq_ignore_ascii_case("true"),
+        None => req
+            .extensions()
+            .get::<ResponseEnvelope>()
+            .is_some_and(|envelope| envelope.0),
+    }
+}
+
+// ハンドラーごとに形式を作らず、ここでまとめて封筒に包む
+// 成功時はJSONのレスポンスだけを包み、失敗時はボディの有無にかかわらず包む
+// 連携の受け口は呼び出し元のサービスが決めた形式で返すので包まない
+pub async fn wrap_envelope(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    if req.method() == Method::HEAD
+        || !is_enabled(&req)
+        || req.uri().path().starts_with(INTEGRATIONS_PREFIX)
+    {
+        return next.run(req).await;
+    }
+    let res = next.run(req).await;
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() && !is_json(res.headers()) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let value = serde_json::from_slice::<Value>(&bytes).ok();
+    let wrapped = if status.is_client_error() || status.is_server_error() {
+        json!({ "error": error(status, value, &bytes) })
+    } else {
+        let mut wrapped = Map::new();
+        wrapped.insert("data".to_string(), value.unwrap_or(Value::Null));
+        if let Some(meta) = meta(&parts.headers) {
+            wrapped.insert("meta".to_string(), meta);
+        }
+        Value::Object(wrapped)
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
+    );
+    Response::from_parts(parts, boxed(Full::from(wrapped.to_string())))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(mime::APPLICATION_JSON.as_ref()))
+}
+
+// JSONのオブジェクトであればそのまま、そうでなければ本文かステータスの説明をメッセージにする
+fn error(status: StatusCode, value: Option<Value>, bytes: &[u8]) -> Value {
+    let mut error = match value {
+        Some(Value::Object(object)) => object,
+        _ => {
+            let message = match std::str::from_utf8(bytes) {
+                Ok(text) if !text.trim().is_empty() => text.to_string(),
+                _ => status.canonical_reason().unwrap_or_default().to_string(),
+            };
+            let mut object = Map::new();
+            object.insert("message".to_string(), Value::String(message));
+            object
+        }
+    };
+    error.insert("status".to_string(), json!(status.as_u16()));
+    Value::Object(error)
+}
+
+// 一覧の総件数とページのリンクをヘッダーから読み取る
+fn meta(headers: &HeaderMap) -> Option<Value> {
+    let total = headers
+        .get(X_TOTAL_COUNT)?
+        .to_str()
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+    let mut meta = Map::new();
+    meta.insert("total_count".to_string(), json!(total));
+    if let Some(link) = headers.get(LINK).and_then(|value| value.to_str().ok()) {
+        meta.insert("links".to_string(), Value::Object(links(link)));
+    }
+    Some(Value::Object(meta))
+}
+
+// <url>; rel="next" の並びを {"next": url} にする
+fn links(link: &str) -> Map<String, Value> {
+    link.split(", ")
+        .filter_map(|entry| {
+            let (url, rel) = entry.split_once(';')?;
+            let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+            let rel = rel.trim().strip_prefix("rel=\"")?.strip_suffix('"')?;
+            Some((rel.to_string(), Value::String(url.to_string())))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_pagination_links() {
+        let links = links(
+            r#"</todos?page=1&per_page=2>; rel="first", </todos?page=2&per_page=2>; rel="next""#,
+        );
+        assert_eq!(links["first"], "/todos?page=1&per_page=2");
+        assert_eq!(links["next"], "/todos?page=2&per_page=2");
+        assert_eq!(links.len(), 2);
+    }
+
+    #[test]
+    fn should_describe_errors_without_json_body() {
+        assert_eq!(
+            error(StatusCode::NOT_FOUND, None, b""),
+            json!({ "status": 404, "message": "Not Found" })
+        );
+        assert_eq!(
+            error(StatusCode::BAD_REQUEST, None, b"Json parse error"),
+            json!({ "status": 400, "message": "Json parse error" })
+        );
+        assert_eq!(
+            error(
+                StatusCode::CONFLICT,
+                Some(json!({ "key": "repository.duplicate" })),
+                b"",
+            ),
+            json!({ "status": 409, "key": "repository.duplicate" })
+        );
+    }
+}
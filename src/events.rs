@@ -0,0 +1,647 @@
+use crate::auth::current_principal;
+use crate::notifier::{Notification, Notifier};
+use crate::repositories::audit::UndoTodoRepository;
+use crate::repositories::labels::{
+    CreateLabel, Label, LabelFilter, LabelRepository, LabelsVersion, UpdateLabel,
+};
+use crate::repositories::todo::{
+    find_existing, CompletedRange, CreateTodo, CycleTime, ListedTodo, Permission, Share,
+    TodoChanges, TodoDependencies, TodoEntity, TodoFilter, TodoPage, TodoRepository, TodoRevision,
+    TodoStream, TodoSuggestion, UpdateTodo, Visibility,
+};
+use crate::repositories::users::User;
+use crate::repositories::EntityId;
+use crate::trace_context::{current_trace, TraceContext};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+// 購読側の処理が追いつかない場合に溜めておく件数
+const DEFAULT_CAPACITY: usize = 256;
+
+// リポジトリへの書き込みで起きたこと
+// ハンドラーごとに通知やキャッシュの破棄を書かず、購読側でまとめて扱う
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent<I = i32> {
+    TodoCreated {
+        todo: TodoEntity<I>,
+    },
+    // ほかの変種と大きさをそろえるため箱に入れる
+    TodoUpdated {
+        before: Box<TodoEntity<I>>,
+        after: Box<TodoEntity<I>>,
+    },
+    TodoDeleted {
+        todo: TodoEntity<I>,
+    },
+    LabelCreated {
+        label: Label<I>,
+    },
+    LabelUpdated {
+        label: Label<I>,
+    },
+    LabelDeleted {
+        id: I,
+    },
+    LabelsMerged {
+        id: I,
+        target_id: I,
+    },
+    // バックアップから全体を置き換えた
+    SnapshotRestored {
+        name: String,
+    },
+}
+
+impl<I> DomainEvent<I> {
+    // SSEのイベント名などに使う
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::TodoCreated { .. } => "todo_created",
+            DomainEvent::TodoUpdated { .. } => "todo_updated",
+            DomainEvent::TodoDeleted { .. } => "todo_deleted",
+            DomainEvent::LabelCreated { .. } => "label_created",
+            DomainEvent::LabelUpdated { .. } => "label_updated",
+            DomainEvent::LabelDeleted { .. } => "label_deleted",
+            DomainEvent::LabelsMerged { .. } => "labels_merged",
+            DomainEvent::SnapshotRestored { .. } => "snapshot_restored",
+        }
+    }
+
+    // 復元ではラベルも置き換わる
+    pub fn is_label(&self) -> bool {
+        matches!(
+            self,
+            DomainEvent::LabelCreated { .. }
+                | DomainEvent::LabelUpdated { .. }
+                | DomainEvent::LabelDeleted { .. }
+                | DomainEvent::LabelsMerged { .. }
+                | DomainEvent::SnapshotRestored { .. }
+        )
+    }
+}
+
+// 発行時の操作者と日時を付けたイベント
+// 購読側は別のタスクで動くため、リクエスト中の認証情報はここで写しておく
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PublishedEvent<I = i32> {
+    pub actor: String,
+    pub occurred_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: DomainEvent<I>,
+    // 発行したリクエストのトレース。Webhookへのリクエストに引き継ぐ
+    #[serde(skip)]
+    pub trace: Option<TraceContext>,
+}
+
+// 確定するまで配信しないイベント
+type PendingEvents<I> = Arc<Mutex<Vec<Arc<PublishedEvent<I>>>>>;
+
+// プロセス内のイベントの配信先
+// 購読者がいなければ捨て、遅れた購読者は古いイベントを取りこぼす
+#[derive(Debug, Clone)]
+pub struct EventBus<I = i32> {
+    sender: broadcast::Sender<Arc<PublishedEvent<I>>>,
+    // 作業単位の中では確定するまで配信せずに溜めておく
+    pending: Option<PendingEvents<I>>,
+}
+
+impl<I: EntityId> EventBus<I> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            pending: None,
+        }
+    }
+
+    // 同じ配信先に、flushするまで配信を遅らせて発行する
+    pub fn deferred(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            pending: Some(Arc::new(Mutex::new(Vec::new()))),
+        }
+    }
+
+    // 溜めておいたイベントを発行した順に配信する
+    pub fn flush(&self) {
+        if let Some(pending) = &self.pending {
+            for event in pending.lock().unwrap().drain(..) {
+                let _ = self.sender.send(event);
+            }
+        }
+    }
+
+    pub fn publish(&self, event: DomainEvent<I>) {
+        let actor = current_principal()
+            .map(|principal| principal.name)
+            .unwrap_or_else(|| String::from("system"));
+        let event = PublishedEvent {
+            actor,
+            occurred_at: Utc::now(),
+            event,
+            trace: current_trace(),
+        };
+        tracing::debug!("publish {}", event.event.name());
+        match &self.pending {
+            Some(pending) => pending.lock().unwrap().push(Arc::new(event)),
+            None => {
+                let _ = self.sender.send(Arc::new(event));
+            }
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<PublishedEvent<I>>> {
+        self.sender.subscribe()
+    }
+}
+
+impl<I: EntityId> Default for EventBus<I> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+// 呼び出した時点から購読を始め、イベントごとにhandleを実行する
+// 失敗してもログに残して次のイベントへ進む
+pub fn spawn_subscriber<I, F, Fut>(
+    events: &EventBus<I>,
+    name: &'static str,
+    mut handle: F,
+) -> JoinHandle<()>
+where
+    I: EntityId,
+    F: FnMut(Arc<PublishedEvent<I>>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    let mut receiver = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if let Err(e) = handle(event).await {
+                        tracing::error!("{} failed to handle event: {}", name, e);
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("{} skipped {} events", name, skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+// 担当者が変わったtodoを通知する
+pub fn spawn_assignment_notifier<I: EntityId>(
+    events: &EventBus<I>,
+    notifier: Arc<dyn Notifier>,
+) -> JoinHandle<()> {
+    spawn_subscriber(events, "assignment notifier", move |event| {
+        let notifier = notifier.clone();
+        async move {
+            let DomainEvent::TodoUpdated { before, after } = &event.event else {
+                return Ok(());
+            };
+            if before.assignee == after.assignee {
+                return Ok(());
+            }
+            let body = match &after.assignee {
+                Some(user) => format!("{} is assigned to \"{}\"", user.name, after.text),
+                None => format!("\"{}\" is unassigned", after.text),
+            };
+            let notification = Notification {
+                subject: format!("Assignment changed: todo #{}", after.id),
+                body,
+            };
+            notifier.notify(&notification).await
+        }
+    })
+}
+
+// すべてのイベントをJSONでPOSTする
+pub fn spawn_webhook_subscriber<I: EntityId>(events: &EventBus<I>, url: String) -> JoinHandle<()> {
+    let client = Client::new();
+    spawn_subscriber(events, "event webhook", move |event| {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(&url)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(
+                serde_json::to_vec(event.as_ref()).unwrap_or_default(),
+            ))
+            .map(|mut req| {
+                if let Some(trace) = &event.trace {
+                    trace.inject(req.headers_mut());
+                }
+                req
+            });
+        let client = client.clone();
+        async move {
+            let res = client.request(req?).await?;
+            if !res.status().is_success() {
+                anyhow::bail!("webhook returned [{}]", res.status());
+            }
+            Ok(())
+        }
+    })
+}
+
+// 書き込みが成功したらイベントを発行するリポジトリのデコレーター
+#[derive(Debug, Clone)]
+pub struct Publishing<R, I = i32> {
+    inner: R,
+    events: EventBus<I>,
+}
+
+impl<R, I> Publishing<R, I> {
+    pub fn new(inner: R, events: EventBus<I>) -> Self {
+        Self { inner, events }
+    }
+}
+
+impl<I: EntityId, R: TodoRepository<I>> Publishing<R, I> {
+    fn updated(&self, before: TodoEntity<I>, after: &TodoEntity<I>) {
+        self.events.publish(DomainEvent::TodoUpdated {
+            before: Box::new(before),
+            after: Box::new(after.clone()),
+        });
+    }
+
+    // まとめて変更したtodoのうち、idsのものを通知する
+    async fn updated_all(&self, before: Vec<TodoEntity<I>>, ids: &[I]) -> anyhow::Result<()> {
+        for before in before.into_iter().filter(|todo| ids.contains(&todo.id)) {
+            let after = self.inner.find(before.id).await?;
+            self.updated(before, &after);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>> TodoRepository<I> for Publishing<R, I> {
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.create(payload).await?;
+        self.events
+            .publish(DomainEvent::TodoCreated { todo: todo.clone() });
+        Ok(todo)
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find(id).await
+    }
+
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_for_update(id).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        self.inner.find_by_text(text, visible_to).await
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.all(filter).await
+    }
+
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        self.inner.stream(filter).await
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        self.inner.count(filter).await
+    }
+
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        self.inner.page(filter, page).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.update(id, payload).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        let todo = self.inner.find(id).await?;
+        self.inner.delete(id).await?;
+        self.events.publish(DomainEvent::TodoDeleted { todo });
+        Ok(())
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        self.inner.history(id).await
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.set_reminder(id, remind_at).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.reminders().await
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.take_due_reminders(now).await
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.assign(id, assignee).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.move_to_project(id, project_id).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.set_parent(id, parent_id).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.pin(id, pinned).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await?;
+        let todo = self.inner.snooze(id, until).await?;
+        self.updated(before, &todo);
+        Ok(todo)
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let before = find_existing(&self.inner, &todo_ids).await?;
+        let ids = self.inner.attach_label(label_id, todo_ids).await?;
+        self.updated_all(before, &ids).await?;
+        Ok(ids)
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let before = find_existing(&self.inner, &todo_ids).await?;
+        let ids = self.inner.detach_label(label_id, todo_ids).await?;
+        self.updated_all(before, &ids).await?;
+        Ok(ids)
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.children(id).await
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.descendants(id).await
+    }
+
+    // 監査ログと同じく、依存関係はブロックされる側のtodoの変更とする
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let before = self.inner.find(blocked_id).await?;
+        self.inner.add_dependency(blocker_id, blocked_id).await?;
+        let todo = self.inner.find(blocked_id).await?;
+        self.updated(before, &todo);
+        Ok(())
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let before = self.inner.find(blocked_id).await?;
+        self.inner.remove_dependency(blocker_id, blocked_id).await?;
+        let todo = self.inner.find(blocked_id).await?;
+        self.updated(before, &todo);
+        Ok(())
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        self.inner.dependencies(id).await
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.dependents(id).await
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.modified_at(id).await
+    }
+
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.list_modified_at().await
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        self.inner.cycle_time(range).await
+    }
+
+    // 共有はtodoそのものを変えないのでイベントにしない
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        self.inner.share(id, user_id, permission).await
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        self.inner.unshare(id, user_id).await
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        self.inner.shares(id).await
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        self.inner.suggest(query, visible_to, limit).await
+    }
+
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        self.inner.changes(since, visible_to).await
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: UndoTodoRepository<I>> UndoTodoRepository<I> for Publishing<R, I> {
+    // 取り消せない場合のエラーを優先して返す
+    async fn undo(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        let before = self.inner.find(id).await;
+        let todo = self.inner.undo(id).await?;
+        self.updated(before?, &todo);
+        Ok(todo)
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: LabelRepository<I>> LabelRepository<I> for Publishing<R, I> {
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>> {
+        let label = self.inner.create(payload).await?;
+        self.events.publish(DomainEvent::LabelCreated {
+            label: label.clone(),
+        });
+        Ok(label)
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Label<I>>> {
+        self.inner.all().await
+    }
+
+    async fn search(&self, filter: LabelFilter) -> anyhow::Result<Vec<Label<I>>> {
+        self.inner.search(filter).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>> {
+        let label = self.inner.update(id, payload).await?;
+        self.events.publish(DomainEvent::LabelUpdated {
+            label: label.clone(),
+        });
+        Ok(label)
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        self.inner.delete(id).await?;
+        self.events.publish(DomainEvent::LabelDeleted { id });
+        Ok(())
+    }
+
+    async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64> {
+        let merged = self.inner.merge(id, target_id).await?;
+        self.events
+            .publish(DomainEvent::LabelsMerged { id, target_id });
+        Ok(merged)
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn version(&self) -> anyhow::Result<LabelsVersion> {
+        self.inner.version().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notifier::test_utils::NotifierForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::users::User;
+
+    #[tokio::test]
+    async fn should_publish_successful_writes_only() {
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        let repository = Publishing::new(TodoRepositoryForMemory::new(vec![]), events.clone());
+
+        let todo = repository
+            .create(CreateTodo::new("publish".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert!(repository
+            .update(99, UpdateTodo::status(Default::default()))
+            .await
+            .is_err());
+        let user = User {
+            id: 1,
+            name: "alice".to_string(),
+        };
+        let assigned = repository.assign(todo.id, Some(user)).await.unwrap();
+        repository.delete(todo.id).await.unwrap();
+
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.actor, "system");
+        assert_eq!(
+            received.event,
+            DomainEvent::TodoCreated { todo: todo.clone() }
+        );
+        assert_eq!(
+            receiver.recv().await.unwrap().event,
+            DomainEvent::TodoUpdated {
+                before: Box::new(todo),
+                after: Box::new(assigned.clone()),
+            }
+        );
+        assert_eq!(
+            receiver.recv().await.unwrap().event,
+            DomainEvent::TodoDeleted { todo: assigned }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_notify_assignment_changes() {
+        let events = EventBus::default();
+        let notifier = Arc::new(NotifierForMemory::default());
+        let subscriber = spawn_assignment_notifier(&events, notifier.clone());
+        let repository = Publishing::new(TodoRepositoryForMemory::new(vec![]), events.clone());
+        let todo = repository
+            .create(CreateTodo::new("assign".to_string(), vec![]))
+            .await
+            .unwrap();
+        let user = User {
+            id: 1,
+            name: "alice".to_string(),
+        };
+        repository
+            .assign(todo.id, Some(user.clone()))
+            .await
+            .unwrap();
+        repository.assign(todo.id, Some(user)).await.unwrap();
+        repository.assign(todo.id, None).await.unwrap();
+
+        // 送信側をすべて落とすと購読が終わる
+        drop(repository);
+        drop(events);
+        subscriber.await.unwrap();
+        let sent = notifier.sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![
+                Notification {
+                    subject: format!("Assignment changed: todo #{}", todo.id),
+                    body: "alice is assigned to \"assign\"".to_string(),
+                },
+                Notification {
+                    subject: format!("Assignment changed: todo #{}", todo.id),
+                    body: "\"assign\" is unassigned".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use serde::de::value::{Error, MapDeserializer, SeqDeserializer, StringDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+// application/x-www-form-urlencoded の本文を、キーごとの値の一覧として読み込んだもの
+// 同じキーを繰り返すか、キーの末尾に[]を付けると配列として受け取れる
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormFields(Vec<(String, Vec<String>)>);
+
+impl FormFields {
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        let pairs: Vec<(String, String)> =
+            serde_urlencoded::from_bytes(body).map_err(de::Error::custom)?;
+        let mut fields: Vec<(String, Vec<String>)> = vec![];
+        for (key, value) in pairs {
+            let key = key.strip_suffix("[]").unwrap_or(&key).to_string();
+            match fields.iter_mut().find(|(name, _)| *name == key) {
+                Some((_, values)) => values.push(value),
+                None => fields.push((key, vec![value])),
+            }
+        }
+        Ok(FormFields(fields))
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.0.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    // 値はすべて文字列なので、受け取る型に合わせて解釈する
+    pub fn deserialize<T: DeserializeOwned>(self) -> Result<T, Error> {
+        let fields = self
+            .0
+            .into_iter()
+            .map(|(key, values)| (key, FormValue(values)));
+        T::deserialize(MapDeserializer::new(fields))
+    }
+}
+
+// 1つのキーに送られた値
+struct FormValue(Vec<String>);
+
+impl FormValue {
+    // 1つだけ受け取る型には、最後に送られた値を使う
+    fn last(self) -> String {
+        self.0.into_iter().last().unwrap_or_default()
+    }
+
+    fn parse<T: std::str::FromStr>(self) -> Result<T, Error>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let value = self.last();
+        value
+            .parse()
+            .map_err(|e| de::Error::custom(format!("invalid value [{}]: {}", value, e)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for FormValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for FormValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0.len() {
+            1 => visitor.visit_string(self.last()),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.last())
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.last())
+    }
+
+    // フォームは未入力の欄も空文字で送るので、空なら指定しなかったものとする
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.iter().all(String::is_empty) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let values = self.0.into_iter().map(|value| FormValue(vec![value]));
+        visitor.visit_seq(SeqDeserializer::new(values))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let value: StringDeserializer<Error> = self.last().into_deserializer();
+        de::Deserializer::deserialize_enum(value, name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    #[serde(deny_unknown_fields)]
+    struct Payload {
+        text: String,
+        labels: Vec<i32>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        project_id: Option<i32>,
+        #[serde(default)]
+        done: bool,
+    }
+
+    #[test]
+    fn should_read_lists_and_typed_values() {
+        let fields =
+            FormFields::parse(b"text=buy+milk%21&labels=1&labels=2&tags[]=home&done=true").unwrap();
+        assert_eq!(fields.keys(), vec!["text", "labels", "tags", "done"]);
+        assert_eq!(
+            fields.deserialize::<Payload>().unwrap(),
+            Payload {
+                text: "buy milk!".to_string(),
+                labels: vec![1, 2],
+                tags: vec!["home".to_string()],
+                project_id: None,
+                done: true,
+            }
+        );
+    }
+
+    #[test]
+    fn should_treat_empty_optional_value_as_missing() {
+        let fields = FormFields::parse(b"text=a&labels=1&project_id=").unwrap();
+        let payload = fields.deserialize::<Payload>().unwrap();
+        assert_eq!(payload.project_id, None);
+
+        let fields = FormFields::parse(b"text=a&labels=1&project_id=3").unwrap();
+        let payload = fields.deserialize::<Payload>().unwrap();
+        assert_eq!(payload.project_id, Some(3));
+    }
+
+    #[test]
+    fn should_reject_values_of_wrong_type() {
+        let fields = FormFields::parse(b"text=a&labels=one").unwrap();
+        let e = fields.deserialize::<Payload>().unwrap_err();
+        assert!(e.to_string().contains("[one]"));
+
+        let fields = FormFields::parse(b"text=a&labels=1&nmae=b").unwrap();
+        assert!(fields.deserialize::<Payload>().is_err());
+    }
+}
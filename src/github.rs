@@ -0,0 +1,252 @@
+use crate::handlers::{ApiError, ValidateJson, ValidatePath};
+use crate::replay::verify_hmac_sha256;
+use crate::repositories::github_links::GithubLinkRepository;
+use crate::repositories::todo::{TodoRepository, TodoStatus, UpdateTodo};
+use crate::repositories::{EntityId, Key};
+use crate::state::State;
+use axum::body::Bytes;
+use axum::extract::Extension;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::Arc;
+use validator::{Validate, ValidationError};
+
+// GitHubのissueを写したtodoを、issueの状態に合わせて更新する連携
+// 紐付けは POST /todos/:id/link-github で行い、状態の変化はwebhookで受け取る
+pub const GITHUB_WEBHOOK_PATH: &str = "/integrations/github";
+
+const X_HUB_SIGNATURE_256: &str = "x-hub-signature-256";
+const X_GITHUB_EVENT: &str = "x-github-event";
+const X_GITHUB_DELIVERY: &str = "x-github-delivery";
+
+// 紐付けと受け付けた配信IDの保存先、webhookの共有シークレット
+// シークレットを指定しない場合は紐付けだけができ、webhookは受け付けない
+#[derive(Clone)]
+pub struct GithubIntegration<I = i32> {
+    links: Arc<dyn GithubLinkRepository<I>>,
+    webhook_secret: Option<String>,
+}
+
+impl<I: EntityId> GithubIntegration<I> {
+    pub fn new(links: impl GithubLinkRepository<I>) -> Self {
+        Self {
+            links: Arc::new(links),
+            webhook_secret: None,
+        }
+    }
+
+    // GITHUB_WEBHOOK_SECRET でGitHubのwebhookに設定したシークレットを指定する
+    pub fn from_env(links: impl GithubLinkRepository<I>) -> Self {
+        let integration = Self::new(links);
+        match env::var("GITHUB_WEBHOOK_SECRET") {
+            Ok(secret) if !secret.is_empty() => integration.with_webhook_secret(secret),
+            _ => integration,
+        }
+    }
+
+    pub fn with_webhook_secret(mut self, secret: impl Into<String>) -> Self {
+        self.webhook_secret = Some(secret.into());
+        self
+    }
+}
+
+// owner/name の形式だけを受け付ける
+fn validate_repository(repository: &str) -> Result<(), ValidationError> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    };
+    match repository.split_once('/') {
+        Some((owner, name)) if is_name(owner) && is_name(name) => Ok(()),
+        _ => {
+            let mut error = ValidationError::new("github_repository");
+            error.message = Some("validation.github_repository".into());
+            Err(error)
+        }
+    }
+}
+
+// POST /todos/:id/link-github のボディ
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct LinkGithub {
+    #[validate(custom = "validate_repository")]
+    repository: String,
+    #[validate(range(min = 1, message = "validation.positive"))]
+    issue_number: i32,
+}
+
+fn configured<I>(
+    github: Option<Extension<Arc<GithubIntegration<I>>>>,
+) -> Result<Arc<GithubIntegration<I>>, ApiError> {
+    github
+        .map(|Extension(github)| github)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into())
+}
+
+pub async fn link_github<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<LinkGithub>,
+    Extension(state): Extension<S>,
+    github: Option<Extension<Arc<GithubIntegration<S::Id>>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let github = configured(github)?;
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let link = github
+        .links
+        .link(
+            id,
+            &payload.repository.to_ascii_lowercase(),
+            payload.issue_number,
+        )
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(Json(link))
+}
+
+// webhookで受け取るissuesイベントのうち、使う項目だけ
+#[derive(Debug, Deserialize)]
+struct IssuesEvent {
+    action: String,
+    issue: Issue,
+    repository: Repository,
+}
+
+#[derive(Debug, Deserialize)]
+struct Issue {
+    number: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+impl IssuesEvent {
+    // issueを閉じたら完了に、開き直したら完了したtodoだけ未着手に戻す
+    // 中止したtodoはissueの状態によらずそのままにする
+    fn mirror(&self, current: TodoStatus) -> Option<TodoStatus> {
+        match (self.action.as_str(), current) {
+            (_, TodoStatus::Cancelled) => None,
+            ("closed", status) if status != TodoStatus::Done => Some(TodoStatus::Done),
+            ("reopened", TodoStatus::Done) => Some(TodoStatus::Backlog),
+            _ => None,
+        }
+    }
+}
+
+// POST /integrations/github
+// APIキーの代わりに本文の署名で送り主を確かめる。GitHubは署名に時刻を含めず
+// 署名がいつまでも有効なので、盗み見たリクエストの再送は配信IDを永続化して拒否する
+pub async fn receive_github_webhook<S: State>(
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(state): Extension<S>,
+    github: Option<Extension<Arc<GithubIntegration<S::Id>>>>,
+) -> Result<StatusCode, ApiError> {
+    let github = configured(github)?;
+    let secret = github
+        .webhook_secret
+        .as_deref()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let signature = header(X_HUB_SIGNATURE_256)
+        .and_then(|value| value.strip_prefix("sha256="))
+        .unwrap_or_default();
+    if !verify_hmac_sha256(secret, &[&body], signature) {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "auth.unauthorized"));
+    }
+    let delivery = header(X_GITHUB_DELIVERY).ok_or(StatusCode::BAD_REQUEST)?;
+    let first_seen = github
+        .links
+        .record_delivery(delivery)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if !first_seen {
+        return Err(ApiError::new(StatusCode::CONFLICT, "integration.replayed"));
+    }
+    // ping など、issues以外のイベントは受け取るだけにする
+    if header(X_GITHUB_EVENT) != Some("issues") {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    let event: IssuesEvent = serde_json::from_slice(&body).or(Err(StatusCode::BAD_REQUEST))?;
+
+    let links = github
+        .links
+        .by_issue(
+            &event.repository.full_name.to_ascii_lowercase(),
+            event.issue.number,
+        )
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let repository = state.todos();
+    for link in links {
+        // 削除されたtodoは飛ばす
+        let Ok(todo) = repository.find(link.todo_id).await else {
+            continue;
+        };
+        let Some(status) = event.mirror(todo.status) else {
+            continue;
+        };
+        if let Err(e) = repository
+            .update(link.todo_id, UpdateTodo::status(status))
+            .await
+        {
+            tracing::warn!(
+                "failed to mirror {}#{} to todo [{}]: {}",
+                link.repository,
+                link.issue_number,
+                link.todo_id,
+                e
+            );
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_mirror_issue_state_to_todo_status() {
+        let event = |action: &str| IssuesEvent {
+            action: action.to_string(),
+            issue: Issue { number: 1 },
+            repository: Repository {
+                full_name: String::from("octo/api"),
+            },
+        };
+        let closed = event("closed");
+        assert_eq!(
+            closed.mirror(TodoStatus::InProgress),
+            Some(TodoStatus::Done)
+        );
+        assert_eq!(closed.mirror(TodoStatus::Done), None);
+        assert_eq!(closed.mirror(TodoStatus::Cancelled), None);
+        let reopened = event("reopened");
+        assert_eq!(reopened.mirror(TodoStatus::Done), Some(TodoStatus::Backlog));
+        assert_eq!(reopened.mirror(TodoStatus::InProgress), None);
+        assert_eq!(event("labeled").mirror(TodoStatus::Backlog), None);
+    }
+
+    #[test]
+    fn should_accept_owner_and_name_only() {
+        for repository in ["octo/api", "Octo-Org/api.rs", "a_b/c"] {
+            assert!(validate_repository(repository).is_ok(), "{}", repository);
+        }
+        for repository in ["octo", "octo/", "/api", "octo/api/issues", "octo/a pi"] {
+            assert!(validate_repository(repository).is_err(), "{}", repository);
+        }
+    }
+}
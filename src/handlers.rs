@@ -1,15 +1,161 @@
-use axum::extract::{FromRequest, RequestParts};
-use axum::http::StatusCode;
+use crate::form::FormFields;
+use crate::i18n::Locale;
+use crate::repositories::RepositoryError;
+use axum::body::{Body, Bytes};
+use axum::extract::path::ErrorKind;
+use axum::extract::rejection::PathRejection;
+use axum::extract::{FromRequest, Path, Query, RequestParts};
+use axum::http::header::{ALLOW, CONTENT_TYPE};
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use axum::{async_trait, BoxError, Json};
+use mime::Mime;
 use serde::de::DeserializeOwned;
-use validator::Validate;
+use serde::{Deserialize, Serialize};
+use serde_aux::serde_introspection::serde_introspect;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use validator::{Validate, ValidationErrors};
 
+// 一覧の総件数を返すヘッダー
+pub const X_TOTAL_COUNT: &str = "x-total-count";
+
+// 死活監視のエンドポイント
+pub const HEALTH_PATH: &str = "/health";
+
+// 外部のサービスから呼ばれる連携の受け口
+// APIキーではなく連携ごとの署名で送り主を確かめ、署名した本文をそのまま受け取る
+pub const INTEGRATIONS_PREFIX: &str = "/integrations/";
+
+pub mod audit;
+pub mod calendar;
+pub mod conditional;
+pub mod dav;
+pub mod events;
+pub mod export;
+pub mod feed;
 pub mod label;
+pub mod pagination;
+pub mod projects;
+pub mod reminder;
+pub mod reports;
+pub mod share_links;
+pub mod shares;
+pub mod sync;
+pub mod templates;
 pub mod todo;
+pub mod users;
+pub mod views;
+
+// バリデーションに失敗したときのレスポンスボディ。
+// フロントエンドが入力欄ごとにエラーを表示できるよう、フィールド名ごとにメッセージを返す。
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ValidationErrorBody {
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+impl From<ValidationErrors> for ValidationErrorBody {
+    fn from(errors: ValidationErrors) -> Self {
+        let locale = Locale::current();
+        let errors = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let messages = errors
+                    .iter()
+                    // メッセージキーを翻訳し、キーが指定されていないルールはコードを返す
+                    .map(|error| {
+                        let key = error.message.as_ref().unwrap_or(&error.code);
+                        locale.translate(key).to_string()
+                    })
+                    .collect();
+                (field.to_string(), messages)
+            })
+            .collect();
+        ValidationErrorBody { errors }
+    }
+}
+
+// エラーレスポンス。文言ではなくメッセージキーを持ち、レスポンスにするときにリクエストの言語へ翻訳する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub key: &'static str,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApiErrorBody {
+    pub key: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, key: &'static str) -> Self {
+        ApiError { status, key }
+    }
+
+    // リポジトリのエラーを種類ごとのメッセージキーに変換する
+    pub fn repository(status: StatusCode, e: &anyhow::Error) -> Self {
+        let key = e
+            .downcast_ref::<RepositoryError>()
+            .map_or("repository.unexpected", RepositoryError::message_key);
+        ApiError { status, key }
+    }
+}
+
+// ステータスコードだけで返していたエラーはステータスに応じたキーにする
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        let key = match status {
+            StatusCode::NOT_FOUND => "repository.not_found",
+            StatusCode::CONFLICT => "repository.duplicate",
+            StatusCode::BAD_REQUEST => "error.bad_request",
+            StatusCode::UNPROCESSABLE_ENTITY => "error.unprocessable",
+            StatusCode::SERVICE_UNAVAILABLE => "repository.unavailable",
+            _ => "repository.unexpected",
+        };
+        ApiError { status, key }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiErrorBody {
+            key: self.key.to_string(),
+            message: Locale::current().translate(self.key).to_string(),
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+// プロセスが応答できることだけを返す
+pub async fn health() -> &'static str {
+    "ok"
+}
+
+// どのルートにも当てはまらない場合
+pub async fn route_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "error.route_not_found")
+}
+
+// メソッドが合わない場合の405は本文が空なので、Allowヘッダーを残したままエラーの本文にする
+pub async fn method_not_allowed(req: Request<Body>, next: Next<Body>) -> Response {
+    let res = next.run(req).await;
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED || res.headers().contains_key(CONTENT_TYPE) {
+        return res;
+    }
+    let mut error =
+        ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "error.method_not_allowed").into_response();
+    if let Some(allow) = res.headers().get(ALLOW) {
+        error.headers_mut().insert(ALLOW, allow.clone());
+    }
+    error
+}
 
 // ジェネリック型 `T` をラップするタプル構造体。
 #[derive(Debug)]
-pub struct ValidateJson<T>(T);
+pub struct ValidateJson<T>(pub T);
 
 // `FromRequest` トレイトを `ValidateJson<T>` 構造体のために非同期で実装。
 // この実装は、HTTP リクエストから `ValidateJson<T>` インスタンスを生成する方法を提供。
@@ -28,25 +174,389 @@ where
     B::Error: Into<BoxError>,
 {
     // リクエストからの変換が失敗した場合に返されるエラーの型を定義。
-    type Rejection = (StatusCode, String);
+    type Rejection = Response;
 
     // `from_request` は、HTTP リクエストから `ValidateJson<T>` インスタンスを生成。
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        // `Json::<T>` の `from_request` 関数を呼び出してリクエストから値をデシリアライズし、
-        // 失敗した場合はエラーメッセージを設定して `BAD_REQUEST` ステータスを返す。
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+        // JSONとして送られていなければ、本文を読まずに `UNSUPPORTED_MEDIA_TYPE` を返す。
+        let content_type = req.headers().and_then(|headers| headers.get(CONTENT_TYPE));
+        if !has_content_type(content_type, &mime::APPLICATION_JSON) {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "error.unsupported_media_type",
+            )
+            .into_response());
+        }
+
+        // 一度 `Value` として読み込み、JSONとして不正であればエラーメッセージを設定して
+        // `BAD_REQUEST` ステータスを返す。
+        let Json(value) = Json::<Value>::from_request(req)
+            .await
+            .map_err(|rejection| {
+                let message = format!(
+                    "{}: [{}]",
+                    Locale::current().translate("validation.json_parse"),
+                    rejection
+                );
+                (StatusCode::BAD_REQUEST, message).into_response()
+            })?;
+
+        // `T` への変換に失敗した場合、知らないキーがあればそれらを一覧にして返す。
+        // `deny_unknown_fields` は最初の1つしか教えてくれないため、フィールド名と突き合わせる。
+        let keys: Vec<String> = value
+            .as_object()
+            .map(|object| object.keys().cloned().collect())
+            .unwrap_or_default();
+        let value = serde_json::from_value::<T>(value)
+            .map_err(|e| deserialize_error::<T>(keys, "validation.json_parse", e))?;
 
         // デシリアライズされた値に対してバリデーションを実行し、
-        // 失敗した場合はエラーメッセージを設定して `BAD_REQUEST` ステータスを返す。
-        value.validate().map_err(|rejection| {
-            let message = format!("Validation error: [{}]", rejection).replace('\n', ",");
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+        // 失敗した場合はフィールドごとのエラーをJSONにして `UNPROCESSABLE_ENTITY` ステータスを返す。
+        value.validate().map_err(validation_error)?;
 
         // バリデーションに成功した場合、`ValidateJson(value)` を `Ok` でラップして返す。
         Ok(ValidateJson(value))
     }
 }
+
+// 本文を `T` に変換できなかったときのレスポンス
+// 知らないキーがあればそれらを一覧にし、なければ解析エラーのメッセージを返す
+fn deserialize_error<T: DeserializeOwned>(
+    keys: Vec<String>,
+    parse_error_key: &str,
+    e: impl std::fmt::Display,
+) -> Response {
+    let fields = serde_introspect::<T>();
+    let unknown: Vec<String> = keys
+        .into_iter()
+        .filter(|key| !fields.is_empty() && !fields.contains(&key.as_str()))
+        .collect();
+    if unknown.is_empty() {
+        let message = format!("{}: [{}]", Locale::current().translate(parse_error_key), e);
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let message = Locale::current().translate("validation.unknown_field");
+    let errors = unknown
+        .into_iter()
+        .map(|key| (key, vec![message.to_string()]))
+        .collect();
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ValidationErrorBody { errors }),
+    )
+        .into_response()
+}
+
+fn validation_error(errors: ValidationErrors) -> Response {
+    let body = ValidationErrorBody::from(errors);
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response()
+}
+
+// JSONかフォーム(application/x-www-form-urlencoded)の本文を、Content-Typeに応じて読み込むエクストラクタ
+// どちらで送られても、ValidateJsonと同じバリデーションを行い、同じエラーを返す
+#[derive(Debug)]
+pub struct ValidatePayload<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatePayload<T>
+where
+    T: DeserializeOwned + Validate,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let content_type = req.headers().and_then(|headers| headers.get(CONTENT_TYPE));
+        if has_content_type(content_type, &mime::APPLICATION_JSON) {
+            let ValidateJson(value) = ValidateJson::<T>::from_request(req).await?;
+            return Ok(ValidatePayload(value));
+        }
+        if !has_content_type(content_type, &mime::APPLICATION_WWW_FORM_URLENCODED) {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "error.unsupported_payload_type",
+            )
+            .into_response());
+        }
+
+        let form_parse_error = |e: &dyn std::fmt::Display| {
+            let message = format!(
+                "{}: [{}]",
+                Locale::current().translate("validation.form_parse"),
+                e
+            );
+            (StatusCode::BAD_REQUEST, message).into_response()
+        };
+        let body = Bytes::from_request(req)
+            .await
+            .map_err(|rejection| form_parse_error(&rejection))?;
+        let fields = FormFields::parse(&body).map_err(|e| form_parse_error(&e))?;
+        let keys = fields.keys();
+        let value = fields
+            .deserialize::<T>()
+            .map_err(|e| deserialize_error::<T>(keys, "validation.form_parse", e))?;
+        value.validate().map_err(validation_error)?;
+        Ok(ValidatePayload(value))
+    }
+}
+
+// Content-Typeが期待する種類かどうか
+// charsetはUTF-8だけを、大文字と小文字を区別せずに受け付ける
+fn has_content_type(content_type: Option<&HeaderValue>, expected: &Mime) -> bool {
+    let Some(mime) = content_type
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+    else {
+        return false;
+    };
+    mime.essence_str() == expected.essence_str()
+        && mime
+            .get_param(mime::CHARSET)
+            .is_none_or(|charset| charset.as_str().eq_ignore_ascii_case("utf-8"))
+}
+
+// パスパラメータを取り出すエクストラクタ
+// 解釈できない値や0以下のidは、パラメータごとのエラーにして400を返す
+#[derive(Debug)]
+pub struct ValidatePath<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatePath<T>
+where
+    T: DeserializeOwned + Send,
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let rejection = match Path::<T>::from_request(req).await {
+            Ok(Path(value)) => return Ok(ValidatePath(value)),
+            Err(PathRejection::FailedToDeserializePathParams(rejection)) => rejection,
+            Err(rejection) => return Err(rejection.into_response()),
+        };
+        let locale = Locale::current();
+        let (field, message) = match rejection.into_kind() {
+            ErrorKind::ParseErrorAtKey { key, .. } => {
+                (key, locale.translate("validation.invalid_path").to_string())
+            }
+            // 識別子のデシリアライズで返したメッセージキーを翻訳する
+            ErrorKind::Message(message) => {
+                ("path".to_string(), locale.translate(&message).to_string())
+            }
+            _ => (
+                "path".to_string(),
+                locale.translate("validation.invalid_path").to_string(),
+            ),
+        };
+        let errors = BTreeMap::from([(field, vec![message])]);
+        Err((
+            StatusCode::BAD_REQUEST,
+            Json(ValidationErrorBody { errors }),
+        )
+            .into_response())
+    }
+}
+
+// クエリパラメータを取り出してバリデーションを行うエクストラクタ
+// URLの誤りなので、解釈できない場合もバリデーションに失敗した場合も400を返す
+#[derive(Debug)]
+pub struct ValidateQuery<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidateQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request(req).await.map_err(|rejection| {
+            let message = format!(
+                "{}: [{}]",
+                Locale::current().translate("validation.invalid_query"),
+                rejection
+            );
+            let errors = BTreeMap::from([("query".to_string(), vec![message])]);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ValidationErrorBody { errors }),
+            )
+                .into_response()
+        })?;
+        value.validate().map_err(|errors| {
+            let body = ValidationErrorBody::from(errors);
+            (StatusCode::BAD_REQUEST, Json(body)).into_response()
+        })?;
+        Ok(ValidateQuery(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::Request;
+
+    #[derive(Debug, Deserialize, Validate)]
+    #[serde(deny_unknown_fields)]
+    struct Signup {
+        #[serde(deserialize_with = "crate::trim::string")]
+        #[validate(length(min = 1, message = "validation.empty"))]
+        #[validate(length(max = 5, message = "validation.too_long"))]
+        name: String,
+        #[validate(range(min = 1))]
+        age: u32,
+    }
+
+    async fn reject(json: &str) -> (StatusCode, String) {
+        let req = Request::builder()
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(json.to_string()))
+            .unwrap();
+        let res = ValidateJson::<Signup>::from_request(&mut RequestParts::new(req))
+            .await
+            .expect_err("should be rejected");
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_report_errors_per_field() {
+        let (status, body) = reject(r#"{ "name": "", "age": 0 }"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: ValidationErrorBody = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body.errors,
+            BTreeMap::from([
+                ("age".to_string(), vec!["range".to_string()]),
+                ("name".to_string(), vec!["Can not be empty".to_string()]),
+            ])
+        );
+
+        let (status, body) = reject(r#"{ "name": "too long", "age": 20 }"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body, r#"{"errors":{"name":["Over test length"]}}"#);
+    }
+
+    #[tokio::test]
+    async fn should_list_unknown_fields() {
+        let (status, body) = reject(r#"{ "name": "a", "age": 1, "nmae": "b", "agee": 2 }"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            r#"{"errors":{"agee":["Unknown field"],"nmae":["Unknown field"]}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn should_trim_before_validation() {
+        let (status, body) = reject(r#"{ "name": "   ", "age": 1 }"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body, r#"{"errors":{"name":["Can not be empty"]}}"#);
+
+        let req = Request::builder()
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(r#"{ "name": " alice ", "age": 1 }"#))
+            .unwrap();
+        let ValidateJson(signup) =
+            ValidateJson::<Signup>::from_request(&mut RequestParts::new(req))
+                .await
+                .unwrap();
+        assert_eq!(signup.name, "alice");
+    }
+
+    #[tokio::test]
+    async fn should_require_json_content_type() {
+        async fn parse(content_type: Option<&str>) -> Result<ValidateJson<Signup>, Response> {
+            let mut req = Request::builder();
+            if let Some(content_type) = content_type {
+                req = req.header(CONTENT_TYPE, content_type);
+            }
+            let req = req
+                .body(Body::from(r#"{ "name": "alice", "age": 1 }"#))
+                .unwrap();
+            ValidateJson::<Signup>::from_request(&mut RequestParts::new(req)).await
+        }
+
+        for content_type in [
+            "application/json",
+            "application/json; charset=utf-8",
+            "Application/JSON; charset=\"UTF-8\"",
+        ] {
+            assert!(parse(Some(content_type)).await.is_ok(), "{}", content_type);
+        }
+        for content_type in [
+            None,
+            Some("text/plain"),
+            Some("application/x-www-form-urlencoded"),
+            Some("application/json; charset=iso-8859-1"),
+            Some("application/jsonp"),
+        ] {
+            let res = parse(content_type).await.expect_err("should be rejected");
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body.key, "error.unsupported_media_type");
+            assert!(body.message.contains("application/json"));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_accept_json_or_form_payload() {
+        async fn parse(content_type: &str, body: &str) -> Result<Signup, Response> {
+            let req = Request::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let ValidatePayload(signup) =
+                ValidatePayload::<Signup>::from_request(&mut RequestParts::new(req)).await?;
+            Ok(signup)
+        }
+        async fn reject(content_type: &str, body: &str) -> (StatusCode, String) {
+            let res = parse(content_type, body)
+                .await
+                .expect_err("should be rejected");
+            let status = res.status();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            (status, String::from_utf8(bytes.to_vec()).unwrap())
+        }
+
+        let signup = parse("application/json", r#"{ "name": " alice ", "age": 1 }"#)
+            .await
+            .unwrap();
+        assert_eq!((signup.name.as_str(), signup.age), ("alice", 1));
+        let signup = parse("application/x-www-form-urlencoded", "name=+alice+&age=1")
+            .await
+            .unwrap();
+        assert_eq!((signup.name.as_str(), signup.age), ("alice", 1));
+
+        // フォームでもJSONと同じエラーを返す
+        let form = "application/x-www-form-urlencoded; charset=UTF-8";
+        let (status, body) = reject(form, "name=too+long&age=20").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body, r#"{"errors":{"name":["Over test length"]}}"#);
+        let (status, body) = reject(form, "name=a&age=1&nmae=b").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, r#"{"errors":{"nmae":["Unknown field"]}}"#);
+        let (status, body) = reject(form, "name=a&age=old").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with("Form parse error"));
+
+        let (status, body) = reject("text/plain", "name=a&age=1").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: ApiErrorBody = serde_json::from_str(&body).unwrap();
+        assert_eq!(body.key, "error.unsupported_payload_type");
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_json_as_bad_request() {
+        let (status, body) = reject(r#"{ "name": "#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with("Json parse error"));
+    }
+}
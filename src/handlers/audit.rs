@@ -0,0 +1,19 @@
+use crate::handlers::ValidateQuery;
+use crate::repositories::audit::{AuditLogFilter, AuditLogRepository};
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+
+pub async fn all_audit_logs<S: State>(
+    ValidateQuery(filter): ValidateQuery<AuditLogFilter<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.audit_logs();
+    let logs = repository
+        .all(filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(logs)))
+}
@@ -0,0 +1,62 @@
+use crate::auth::Principal;
+use crate::handlers::shares::{visibility, visible_todos};
+use crate::ics::{self, CalendarEvent};
+use crate::repositories::todo::{TodoEntity, TodoRepository, TodoStatus};
+use crate::state::State;
+use axum::body::StreamBody;
+use axum::extract::Extension;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{Headers, IntoResponse};
+use chrono::Utc;
+use futures_util::stream;
+use std::convert::Infallible;
+
+// 購読用のURL。APIキーはヘッダーの代わりに ?token= で渡せる
+pub const CALENDAR_PATH: &str = "/todos/calendar.ics";
+
+// 期日の代わりにリマインダーの時刻を予定の日時にする
+fn calendar_event<I>(todo: TodoEntity<I>) -> Option<CalendarEvent> {
+    let starts_at = todo.remind_at?;
+    let mut categories: Vec<String> = todo.labels.into_iter().map(|label| label.name).collect();
+    categories.extend(todo.tags);
+    Some(CalendarEvent {
+        uid: todo.uuid,
+        starts_at,
+        summary: todo.text,
+        categories,
+        cancelled: todo.status == TodoStatus::Cancelled,
+    })
+}
+
+// リマインダーを設定したtodoを予定としてiCalendarで返す
+// 件数が多くても溜め込まないよう、予定ごとに書き出す
+pub async fn todo_calendar<S: State>(
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todos = state
+        .todos()
+        .reminders()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let todos = visible_todos(&state, visibility(&state, &principal).await, todos)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let stamped_at = Utc::now();
+    let events = todos
+        .into_iter()
+        .filter_map(calendar_event)
+        .map(move |event| event.to_ics(stamped_at));
+    let lines = stream::iter(
+        std::iter::once(ics::begin_calendar("todos"))
+            .chain(events)
+            .chain(std::iter::once(ics::end_calendar()))
+            .map(Ok::<_, Infallible>),
+    );
+    Ok((
+        Headers(vec![(CONTENT_TYPE, ics::CONTENT_TYPE)]),
+        StreamBody::new(lines),
+    ))
+}
@@ -0,0 +1,100 @@
+use axum::http::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Headers, IntoResponse, Response};
+use chrono::{DateTime, Utc};
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+// If-Modified-Since より後に変更されていればtrue
+// HTTP-dateは秒単位なので、秒未満を切り捨てて比べる
+pub fn is_modified_since(headers: &HeaderMap, modified_at: DateTime<Utc>) -> bool {
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+    match since {
+        Some(since) => modified_at.timestamp() > since.timestamp(),
+        None => true,
+    }
+}
+
+pub fn last_modified(modified_at: DateTime<Utc>) -> Headers<Vec<(HeaderName, String)>> {
+    Headers(vec![(
+        LAST_MODIFIED,
+        modified_at.format(HTTP_DATE).to_string(),
+    )])
+}
+
+pub fn not_modified(modified_at: DateTime<Utc>) -> Response {
+    (StatusCode::NOT_MODIFIED, last_modified(modified_at)).into_response()
+}
+
+// If-None-Match のいずれかがETagと一致すればtrue
+// GETでは弱い比較をするので、W/ の有無は区別しない
+pub fn is_none_match_satisfied(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+pub fn etag(etag: &str) -> Headers<Vec<(HeaderName, String)>> {
+    Headers(vec![(ETAG, etag.to_string())])
+}
+
+pub fn not_modified_etag(value: &str) -> Response {
+    (StatusCode::NOT_MODIFIED, etag(value)).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn should_compare_in_whole_seconds() {
+        let modified_at = Utc.with_ymd_and_hms(2024, 4, 1, 9, 30, 0).unwrap()
+            + chrono::Duration::milliseconds(500);
+        let mut headers = HeaderMap::new();
+        assert!(is_modified_since(&headers, modified_at));
+
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            "Mon, 01 Apr 2024 09:30:00 GMT".parse().unwrap(),
+        );
+        assert!(!is_modified_since(&headers, modified_at));
+
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            "Mon, 01 Apr 2024 09:29:59 GMT".parse().unwrap(),
+        );
+        assert!(is_modified_since(&headers, modified_at));
+
+        // 解釈できない日付は無視する
+        headers.insert(IF_MODIFIED_SINCE, "yesterday".parse().unwrap());
+        assert!(is_modified_since(&headers, modified_at));
+    }
+
+    #[test]
+    fn should_match_any_etag_in_if_none_match() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_none_match_satisfied(&headers, "\"2-100\""));
+
+        headers.insert(IF_NONE_MATCH, "\"1-50\", \"2-100\"".parse().unwrap());
+        assert!(is_none_match_satisfied(&headers, "\"2-100\""));
+        assert!(!is_none_match_satisfied(&headers, "\"3-100\""));
+
+        headers.insert(IF_NONE_MATCH, "W/\"2-100\"".parse().unwrap());
+        assert!(is_none_match_satisfied(&headers, "\"2-100\""));
+
+        headers.insert(IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(is_none_match_satisfied(&headers, "\"3-100\""));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::auth::Principal;
+use crate::dav::{self, Prop, ResourceType};
+use crate::handlers::todo::{list_todos, TodoQuery};
+use crate::ics::{self, CalendarTodo, TodoState};
+use crate::repositories::todo::{TodoEntity, TodoRepository, TodoStatus};
+use crate::state::State;
+use axum::body::Bytes;
+use axum::extract::{Extension, Path};
+use axum::http::header::{HeaderName, ALLOW, CONTENT_TYPE, ETAG};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{Headers, IntoResponse, Redirect, Response};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// CalDAVのクライアントからtodoをVTODOとして読むための入り口
+// 今は読み取りだけで、PUTなどの書き込みは受け付けない
+// APIキーはBasic認証のパスワードとしても渡せる
+pub const DAV_PREFIX: &str = "/dav/";
+pub const DAV_TODOS_PATH: &str = "/dav/todos/";
+// クライアントはここからホームを探す
+pub const WELL_KNOWN_CALDAV_PATH: &str = "/.well-known/caldav";
+
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND, REPORT";
+
+fn href<I>(todo: &TodoEntity<I>) -> String {
+    format!("{}{}.ics", DAV_TODOS_PATH, todo.uuid)
+}
+
+fn etag(modified_at: DateTime<Utc>) -> String {
+    format!("\"{}\"", modified_at.timestamp_millis())
+}
+
+fn calendar_todo<I>(todo: TodoEntity<I>, modified_at: DateTime<Utc>) -> CalendarTodo {
+    let state = match todo.status {
+        TodoStatus::Backlog => TodoState::NeedsAction,
+        TodoStatus::InProgress => TodoState::InProcess,
+        TodoStatus::Done => TodoState::Completed,
+        TodoStatus::Cancelled => TodoState::Cancelled,
+    };
+    let mut categories: Vec<String> = todo.labels.into_iter().map(|label| label.name).collect();
+    categories.extend(todo.tags);
+    CalendarTodo {
+        uid: todo.uuid,
+        summary: todo.text,
+        description: todo.description,
+        due_at: todo.due_at,
+        state,
+        completed_at: todo.completed_at,
+        categories,
+        modified_at,
+    }
+}
+
+// 1件ずつVCALENDARで包んで返す
+fn calendar_data<I>(todo: TodoEntity<I>, modified_at: DateTime<Utc>) -> String {
+    [
+        ics::begin_calendar("todos"),
+        calendar_todo(todo, modified_at).to_ics(Utc::now()),
+        ics::end_calendar(),
+    ]
+    .concat()
+}
+
+// GET /todos と同じく見えるtodoだけを、更新日時と一緒に返す
+async fn visible_todos<S: State>(
+    state: &S,
+    principal: &Principal,
+) -> Result<Vec<(TodoEntity<S::Id>, DateTime<Utc>)>, StatusCode> {
+    let todos = list_todos(state, principal, TodoQuery::default()).await?;
+    let mut visible = Vec::with_capacity(todos.len());
+    for todo in todos {
+        let modified_at = state
+            .todos()
+            .modified_at(todo.id)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        visible.push((todo, modified_at));
+    }
+    Ok(visible)
+}
+
+// Depth: 0 の場合は子を返さない。指定がなければ子まで返す
+fn includes_children(headers: &HeaderMap) -> bool {
+    headers
+        .get("depth")
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|depth| depth.trim() != "0")
+}
+
+fn options() -> Response {
+    Headers(vec![
+        (HeaderName::from_static("dav"), "1, calendar-access"),
+        (ALLOW, ALLOWED_METHODS),
+    ])
+    .into_response()
+}
+
+fn multistatus(responses: Vec<dav::Response>) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        Headers(vec![(CONTENT_TYPE, dav::CONTENT_TYPE)]),
+        dav::multistatus(&responses),
+    )
+        .into_response()
+}
+
+fn not_allowed() -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        Headers(vec![(ALLOW, ALLOWED_METHODS)]),
+    )
+        .into_response()
+}
+
+fn todos_collection(ctag: DateTime<Utc>) -> dav::Response {
+    dav::Response {
+        href: DAV_TODOS_PATH.to_string(),
+        props: vec![
+            Prop::ResourceType(ResourceType::Calendar),
+            Prop::DisplayName("todos".to_string()),
+            Prop::SupportedTodos,
+            Prop::ReadOnly,
+            Prop::Ctag(etag(ctag)),
+        ],
+    }
+}
+
+fn todo_item<I>(todo: &TodoEntity<I>, modified_at: DateTime<Utc>) -> dav::Response {
+    dav::Response {
+        href: href(todo),
+        props: vec![
+            Prop::ResourceType(ResourceType::Item),
+            Prop::Etag(etag(modified_at)),
+            Prop::ContentType(ics::CONTENT_TYPE),
+        ],
+    }
+}
+
+// /.well-known/caldav
+pub async fn well_known_caldav() -> Redirect {
+    Redirect::permanent(Uri::from_static(DAV_PREFIX))
+}
+
+// /dav/
+// 利用者ごとの区別はなく、ここを利用者とカレンダーのホームの両方として見せる
+pub async fn dav_home<S: State>(
+    method: Method,
+    headers: HeaderMap,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Response, StatusCode> {
+    match method.as_str() {
+        "OPTIONS" => return Ok(options()),
+        "PROPFIND" => {}
+        _ => return Ok(not_allowed()),
+    }
+    let mut responses = vec![dav::Response {
+        href: DAV_PREFIX.to_string(),
+        props: vec![
+            Prop::ResourceType(ResourceType::Collection),
+            Prop::DisplayName(principal.name),
+            Prop::CurrentUserPrincipal(DAV_PREFIX.to_string()),
+            Prop::CalendarHomeSet(DAV_PREFIX.to_string()),
+        ],
+    }];
+    if includes_children(&headers) {
+        let ctag = state
+            .todos()
+            .list_modified_at()
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        responses.push(todos_collection(ctag));
+    }
+    Ok(multistatus(responses))
+}
+
+// /dav/todos/
+// PROPFINDでは各todoのETagを、REPORTではVTODOの中身も返す
+// REPORTのcalendar-multigetは指定されたものだけ、calendar-queryの絞り込みは見ずに全件返す
+pub async fn dav_todos<S: State>(
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Response, StatusCode> {
+    match method.as_str() {
+        "OPTIONS" => Ok(options()),
+        "PROPFIND" => {
+            let ctag = state
+                .todos()
+                .list_modified_at()
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            let mut responses = vec![todos_collection(ctag)];
+            if includes_children(&headers) {
+                let todos = visible_todos(&state, &principal).await?;
+                responses.extend(
+                    todos
+                        .iter()
+                        .map(|(todo, modified_at)| todo_item(todo, *modified_at)),
+                );
+            }
+            Ok(multistatus(responses))
+        }
+        "REPORT" => {
+            let hrefs = dav::requested_hrefs(&String::from_utf8_lossy(&body));
+            let todos = visible_todos(&state, &principal).await?;
+            let responses = todos
+                .into_iter()
+                .filter(|(todo, _)| hrefs.is_empty() || hrefs.contains(&href(todo)))
+                .map(|(todo, modified_at)| {
+                    let mut response = todo_item(&todo, modified_at);
+                    response
+                        .props
+                        .push(Prop::CalendarData(calendar_data(todo, modified_at)));
+                    response
+                })
+                .collect();
+            Ok(multistatus(responses))
+        }
+        _ => Ok(not_allowed()),
+    }
+}
+
+// /dav/todos/<uuid>.ics
+pub async fn dav_todo<S: State>(
+    method: Method,
+    Path(name): Path<String>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Response, StatusCode> {
+    match method.as_str() {
+        "OPTIONS" => return Ok(options()),
+        "GET" | "HEAD" | "PROPFIND" => {}
+        _ => return Ok(not_allowed()),
+    }
+    let uuid: Uuid = name
+        .strip_suffix(".ics")
+        .and_then(|uuid| uuid.parse().ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let (todo, modified_at) = visible_todos(&state, &principal)
+        .await?
+        .into_iter()
+        .find(|(todo, _)| todo.uuid == uuid)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if method.as_str() == "PROPFIND" {
+        return Ok(multistatus(vec![todo_item(&todo, modified_at)]));
+    }
+    Ok((
+        Headers(vec![
+            (CONTENT_TYPE, ics::CONTENT_TYPE.to_string()),
+            (ETAG, etag(modified_at)),
+        ]),
+        calendar_data(todo, modified_at),
+    )
+        .into_response())
+}
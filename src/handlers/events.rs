@@ -0,0 +1,32 @@
+use crate::state::State;
+use axum::extract::Extension;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+
+// 発行されたイベントをServer-Sent Eventsで流し続ける
+// 接続してから発行されたものだけを送り、取りこぼした分は飛ばす
+pub async fn stream_events<S: State>(
+    Extension(state): Extension<S>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.events().subscribe();
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let sse = Event::default()
+                        .event(event.event.name())
+                        .json_data(event.as_ref())
+                        .unwrap_or_default();
+                    return Some((Ok(sse), receiver));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("event stream skipped {} events", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
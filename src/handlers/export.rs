@@ -0,0 +1,70 @@
+use crate::auth::Principal;
+use crate::handlers::conditional::{is_modified_since, last_modified, not_modified};
+use crate::handlers::todo::{list_todos, TodoQuery};
+use crate::handlers::ValidateQuery;
+use crate::pdf::{self, Checklist, ChecklistItem};
+use crate::repositories::todo::{TodoEntity, TodoRepository, TodoStatus};
+use crate::state::State;
+use axum::body::StreamBody;
+use axum::extract::Extension;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Headers, IntoResponse, Response};
+use chrono::Utc;
+use futures_util::stream;
+use std::convert::Infallible;
+
+// 印刷用のチェックリスト
+pub const EXPORT_PDF_PATH: &str = "/todos/export.pdf";
+
+fn item<I>(todo: TodoEntity<I>) -> ChecklistItem {
+    let status = serde_json::to_value(todo.status)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut note = vec![status];
+    if let Some(due_at) = todo.due_at {
+        note.push(format!("due {}", due_at.format("%Y-%m-%d %H:%M UTC")));
+    }
+    let labels: Vec<String> = todo.labels.into_iter().map(|label| label.name).collect();
+    if !labels.is_empty() {
+        note.push(format!("labels: {}", labels.join(", ")));
+    }
+    ChecklistItem {
+        text: todo.text,
+        done: todo.status == TodoStatus::Done,
+        note: Some(note.join(" - ")),
+    }
+}
+
+// GET /todos と同じ条件と並び順で、ページに分けずにすべてをPDFにする
+pub async fn export_pdf<S: State>(
+    ValidateQuery(query): ValidateQuery<TodoQuery<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let modified_at = state
+        .todos()
+        .list_modified_at()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if !is_modified_since(&headers, modified_at) {
+        return Ok(not_modified(modified_at));
+    }
+
+    let todos = list_todos(&state, &principal, query).await?;
+    let checklist = Checklist {
+        title: "Todos".to_string(),
+        generated_at: Utc::now(),
+        items: todos.into_iter().map(item).collect(),
+    };
+    let chunks = checklist.to_pdf().into_iter().map(Ok::<_, Infallible>);
+    let Headers(mut headers) = last_modified(modified_at);
+    headers.push((CONTENT_TYPE, pdf::CONTENT_TYPE.to_string()));
+    headers.push((
+        CONTENT_DISPOSITION,
+        "inline; filename=\"todos.pdf\"".to_string(),
+    ));
+    Ok((Headers(headers), StreamBody::new(stream::iter(chunks))).into_response())
+}
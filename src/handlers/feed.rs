@@ -0,0 +1,73 @@
+use crate::atom::{self, Entry, Feed};
+use crate::auth::Principal;
+use crate::handlers::conditional::{is_modified_since, last_modified, not_modified};
+use crate::handlers::pagination::{page_headers, Pagination};
+use crate::handlers::todo::{list_todo_page, TodoQuery};
+use crate::handlers::ValidateQuery;
+use crate::repositories::todo::{TodoEntity, TodoRepository};
+use crate::repositories::EntityId;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::{Headers, IntoResponse, Response};
+use chrono::{DateTime, Utc};
+
+// フィードリーダー向けの一覧。APIキーはヘッダーの代わりに ?token= で渡せる
+pub const FEED_PATH: &str = "/todos/feed.atom";
+
+fn entry<I: EntityId>(todo: TodoEntity<I>, updated: DateTime<Utc>) -> Entry {
+    let status = serde_json::to_value(todo.status)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut categories: Vec<String> = todo.labels.into_iter().map(|label| label.name).collect();
+    categories.extend(todo.tags);
+    Entry {
+        id: format!("urn:uuid:{}", todo.uuid),
+        title: todo.text,
+        updated,
+        link: format!("/todos/{}", todo.id),
+        categories,
+        content: format!("status: {}", status),
+    }
+}
+
+// GET /todos と同じ条件で絞り込み、新しいものからAtomで返す
+// ページを指定しなければ最初のページだけを返す
+pub async fn todo_feed<S: State>(
+    uri: Uri,
+    ValidateQuery(query): ValidateQuery<TodoQuery<S::Id>>,
+    ValidateQuery(pagination): ValidateQuery<Pagination>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let repository = state.todos();
+    let modified_at = repository
+        .list_modified_at()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if !is_modified_since(&headers, modified_at) {
+        return Ok(not_modified(modified_at));
+    }
+
+    let pagination = pagination.or_first_page();
+    let (offset, limit) = pagination.range()?.unwrap_or_default();
+    let (listed, total) = list_todo_page(&state, &principal, query, offset, limit).await?;
+    let entries = listed
+        .into_iter()
+        .map(|listed| entry(listed.todo, listed.updated_at))
+        .collect();
+    let feed = Feed {
+        id: "urn:rust-simple-api:todos".to_string(),
+        title: "todos".to_string(),
+        updated: modified_at,
+        self_link: uri.to_string(),
+        entries,
+    };
+    let Headers(mut headers) = last_modified(modified_at);
+    headers.extend(page_headers(&uri, pagination, total)?);
+    headers.push((CONTENT_TYPE, atom::CONTENT_TYPE.to_string()));
+    Ok((Headers(headers), feed.to_xml()).into_response())
+}
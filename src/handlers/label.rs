@@ -1,36 +1,146 @@
-use crate::handlers::ValidateJson;
-use crate::repositories::labels::LabelRepository;
-use axum::extract::{Extension, Path};
-use axum::response::IntoResponse;
+use crate::auth::Principal;
+use crate::handlers::conditional::{etag, is_none_match_satisfied, not_modified_etag};
+use crate::handlers::pagination::{paginate, Pagination};
+use crate::handlers::shares::{visibility, writable_todo_ids};
+use crate::handlers::{ApiError, ValidateJson, ValidatePath, ValidatePayload, ValidateQuery};
+use crate::repositories::labels::{
+    CreateLabel, LabelFilter, LabelRepository, LabelSort, UpdateLabel,
+};
+use crate::repositories::todo::{TodoFilter, TodoRepository};
+use crate::repositories::{EntityId, Key, KeyPair, RepositoryError};
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::{HeaderMap, Uri};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 use validator::Validate;
 
-pub async fn create_label<T: LabelRepository>(
-    ValidateJson(payload): ValidateJson<CreateLabel>,
-    Extension(repository): Extension<Arc<T>>,
+// 大文字と小文字や空白の違いだけの名前のラベルがあれば、作成せずにそれを409で返す
+pub async fn create_label<S: State>(
+    ValidatePayload(payload): ValidatePayload<CreateLabel>,
+    Extension(state): Extension<S>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let label = repository
-        .create(payload.name)
+    let repository = state.labels();
+    match repository.create(payload).await {
+        Ok(label) => Ok((StatusCode::CREATED, Json(label))),
+        Err(e) => match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::Duplicate(key)) => {
+                let label = repository
+                    .all()
+                    .await
+                    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+                    .into_iter()
+                    .find(|label| label.id.key() == *key)
+                    .ok_or(StatusCode::CONFLICT)?;
+                Ok((StatusCode::CONFLICT, Json(label)))
+            }
+            _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+    }
+}
+
+// GET /labels のクエリパラメータ
+// qで名前の前方一致、sortでid・nameの順(-を付けると降順)を指定する
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct LabelQuery {
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    q: Option<String>,
+    #[serde(default)]
+    sort: LabelSort,
+}
+
+// 一覧の版からETagを作り、変わっていなければ一覧を読まずに304を返す
+// 版は絞り込みや並び順によらないので、クエリごとのETagとしてもそのまま使える
+pub async fn all_label<S: State>(
+    uri: Uri,
+    headers: HeaderMap,
+    ValidateQuery(query): ValidateQuery<LabelQuery>,
+    ValidateQuery(pagination): ValidateQuery<Pagination>,
+    Extension(state): Extension<S>,
+) -> Result<Response, StatusCode> {
+    let repository = state.labels();
+    let version = repository
+        .version()
         .await
         .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let tag = version.etag();
+    if is_none_match_satisfied(&headers, &tag) {
+        return Ok(not_modified_etag(&tag));
+    }
+    let labels = repository
+        .search(LabelFilter {
+            prefix: query.q,
+            sort: query.sort,
+        })
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((etag(&tag), paginate(&uri, pagination, labels)?).into_response())
+}
+
+pub async fn update_label<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<UpdateLabel>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, ApiError> {
+    let repository = state.labels();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let label = repository.update(id, payload).await.map_err(|e| {
+        match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotFound(_)) => ApiError::repository(StatusCode::NOT_FOUND, &e),
+            Some(RepositoryError::Duplicate(_)) => ApiError::repository(StatusCode::CONFLICT, &e),
+            _ => ApiError::repository(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        }
+    })?;
 
-    Ok((StatusCode::CREATED, Json(label)))
+    Ok((StatusCode::OK, Json(label)))
 }
 
-pub async fn all_label<T: LabelRepository>(
-    Extension(repository): Extension<Arc<T>>,
+pub async fn label_todos<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    uri: Uri,
+    ValidateQuery(pagination): ValidateQuery<Pagination>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(labels)))
+    let labels = state.labels();
+    let repository = state.todos();
+    let id = labels.resolve(key).await.or(Err(StatusCode::NOT_FOUND))?;
+    let exists = labels
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+        .iter()
+        .any(|label| label.id == id);
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let todos = repository
+        .all(TodoFilter {
+            label_id: Some(id),
+            visible_to: visibility(&state, &principal).await,
+            ..Default::default()
+        })
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    paginate(&uri, pagination, todos)
 }
 
-pub async fn delete_label<T: LabelRepository>(
-    Path(id): Path<i32>,
-    Extension(repository): Extension<Arc<T>>,
+pub async fn delete_label<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
 ) -> StatusCode {
+    let repository = state.labels();
+    let id = match repository.resolve(key).await {
+        Ok(id) => id,
+        Err(_) => return StatusCode::NOT_FOUND,
+    };
     repository
         .delete(id)
         .await
@@ -38,9 +148,121 @@ pub async fn delete_label<T: LabelRepository>(
         .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
-pub struct CreateLabel {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over test length"))]
-    name: String,
+// 統合で付け替えたtodoの数
+#[derive(Debug, Serialize)]
+pub struct MergedLabel {
+    affected_todos: u64,
+}
+
+pub async fn merge_label<S: State>(
+    ValidatePath((key, target_key)): ValidatePath<KeyPair<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, ApiError> {
+    let repository = state.labels();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let target_id = repository
+        .resolve(target_key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    // 自分自身には統合できない
+    if id == target_id {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let affected_todos = repository.merge(id, target_id).await.map_err(|e| match e
+        .downcast_ref::<RepositoryError>()
+    {
+        Some(RepositoryError::NotFound(_)) => ApiError::repository(StatusCode::NOT_FOUND, &e),
+        _ => ApiError::repository(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    })?;
+
+    Ok((StatusCode::OK, Json(MergedLabel { affected_todos })))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(deny_unknown_fields, bound(deserialize = ""))]
+pub struct AssignLabel<I: EntityId = i32> {
+    #[validate(length(min = 1, max = 1000, message = "validation.assign_batch"))]
+    todo_ids: Vec<I>,
+}
+
+// 付け外しで変わったtodoの数
+#[derive(Debug, Serialize)]
+pub struct AssignedLabel {
+    affected_todos: usize,
+}
+
+// 書き込めないtodoと既に付いているtodoは数えない
+pub async fn assign_label<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<AssignLabel<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = state
+        .labels()
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo_ids = writable_todo_ids(
+        &state,
+        visibility(&state, &principal).await,
+        payload.todo_ids,
+    )
+    .await
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let ids = state
+        .todos()
+        .attach_label(id, todo_ids)
+        .await
+        .map_err(assignment_error)?;
+    Ok((
+        StatusCode::OK,
+        Json(AssignedLabel {
+            affected_todos: ids.len(),
+        }),
+    ))
+}
+
+pub async fn unassign_label<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<AssignLabel<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = state
+        .labels()
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo_ids = writable_todo_ids(
+        &state,
+        visibility(&state, &principal).await,
+        payload.todo_ids,
+    )
+    .await
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let ids = state
+        .todos()
+        .detach_label(id, todo_ids)
+        .await
+        .map_err(assignment_error)?;
+    Ok((
+        StatusCode::OK,
+        Json(AssignedLabel {
+            affected_todos: ids.len(),
+        }),
+    ))
+}
+
+fn assignment_error(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::NotFound(_)) => ApiError::repository(StatusCode::NOT_FOUND, &e),
+        Some(RepositoryError::TooManyLabels(_)) => {
+            ApiError::repository(StatusCode::UNPROCESSABLE_ENTITY, &e)
+        }
+        _ => ApiError::repository(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
 }
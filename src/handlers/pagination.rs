@@ -0,0 +1,180 @@
+use crate::handlers::X_TOTAL_COUNT;
+use axum::http::header::{HeaderName, LINK};
+use axum::http::{StatusCode, Uri};
+use axum::response::{Headers, IntoResponse};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+// validatorの上限と揃える
+const MAX_PAGE: usize = 1_000_000;
+
+// 一覧のページ指定
+// どちらも指定しなければ従来どおり全件を返す
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Validate)]
+pub struct Pagination {
+    #[validate(range(min = 1, max = 1000000, message = "validation.page"))]
+    page: Option<usize>,
+    #[validate(range(min = 1, message = "validation.positive"))]
+    per_page: Option<usize>,
+}
+
+impl Pagination {
+    // 1始まりのページ番号と1ページの件数
+    fn resolve(&self) -> Result<Option<(usize, usize)>, StatusCode> {
+        if self.page.is_none() && self.per_page.is_none() {
+            return Ok(None);
+        }
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page == 0 || per_page == 0 || page > MAX_PAGE {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Some((page, per_page.min(MAX_PER_PAGE))))
+    }
+
+    // 指定がなければ最初のページにする
+    pub fn or_first_page(self) -> Self {
+        Pagination {
+            page: self.page.or(Some(1)),
+            ..self
+        }
+    }
+
+    // 切り出す範囲の先頭の位置と件数。リポジトリで切り出す場合に使う
+    pub fn range(&self) -> Result<Option<(usize, usize)>, StatusCode> {
+        Ok(self
+            .resolve()?
+            .map(|(page, per_page)| ((page - 1).saturating_mul(per_page), per_page)))
+    }
+
+    // 指定されたページを切り出す
+    pub fn select<T>(&self, mut items: Vec<T>) -> Result<Vec<T>, StatusCode> {
+        if let Some((page, per_page)) = self.resolve()? {
+            // 範囲外のページは空にする
+            let start = (page - 1).saturating_mul(per_page).min(items.len());
+            items = items.drain(start..).take(per_page).collect();
+        }
+        Ok(items)
+    }
+}
+
+// ページ番号だけを差し替えたURLへのリンク
+fn link(uri: &Uri, page: usize, per_page: usize, rel: &str) -> String {
+    let paging = format!("page={}&per_page={}", page, per_page);
+    let mut params: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| {
+            !param.is_empty() && !param.starts_with("page=") && !param.starts_with("per_page=")
+        })
+        .collect();
+    params.push(&paging);
+    format!("<{}?{}>; rel=\"{}\"", uri.path(), params.join("&"), rel)
+}
+
+// 総件数とページ間のリンクのヘッダー
+pub fn page_headers(
+    uri: &Uri,
+    pagination: Pagination,
+    total: usize,
+) -> Result<Vec<(HeaderName, String)>, StatusCode> {
+    let mut headers: Vec<(HeaderName, String)> =
+        vec![(HeaderName::from_static(X_TOTAL_COUNT), total.to_string())];
+
+    if let Some((page, per_page)) = pagination.resolve()? {
+        let last = total.div_ceil(per_page).max(1);
+        let mut links = vec![link(uri, 1, per_page, "first")];
+        if page > 1 {
+            links.push(link(uri, (page - 1).min(last), per_page, "prev"));
+        }
+        if page < last {
+            links.push(link(uri, page + 1, per_page, "next"));
+        }
+        links.push(link(uri, last, per_page, "last"));
+        headers.push((LINK, links.join(", ")));
+    }
+    Ok(headers)
+}
+
+// 指定されたページを切り出し、総件数とページ間のリンクをヘッダーで返す
+pub fn paginate<T: Serialize>(
+    uri: &Uri,
+    pagination: Pagination,
+    items: Vec<T>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let headers = page_headers(uri, pagination, items.len())?;
+    let items = pagination.select(items)?;
+    Ok((StatusCode::OK, Headers(headers), Json(items)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn links(uri: &str, page: Option<usize>, per_page: Option<usize>) -> (String, String) {
+        let uri: Uri = uri.parse().unwrap();
+        let res = paginate(
+            &uri,
+            Pagination { page, per_page },
+            (1..=5).collect::<Vec<i32>>(),
+        )
+        .unwrap()
+        .into_response();
+        let header = |name: &str| {
+            res.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        (header(X_TOTAL_COUNT), header("link"))
+    }
+
+    #[test]
+    fn should_link_neighbouring_pages() {
+        let (total, link) = links("/todos?assignee=1&page=2&per_page=2", Some(2), Some(2));
+        assert_eq!(total, "5");
+        assert_eq!(
+            link,
+            [
+                "</todos?assignee=1&page=1&per_page=2>; rel=\"first\"",
+                "</todos?assignee=1&page=1&per_page=2>; rel=\"prev\"",
+                "</todos?assignee=1&page=3&per_page=2>; rel=\"next\"",
+                "</todos?assignee=1&page=3&per_page=2>; rel=\"last\"",
+            ]
+            .join(", ")
+        );
+
+        let (_, link) = links("/labels?page=3&per_page=2", Some(3), Some(2));
+        assert!(!link.contains("rel=\"next\""));
+    }
+
+    #[test]
+    fn should_not_link_without_pagination() {
+        let (total, link) = links("/todos", None, None);
+        assert_eq!(total, "5");
+        assert_eq!(link, "");
+    }
+
+    #[test]
+    fn should_reject_too_large_page() {
+        let pagination = |page| Pagination {
+            page: Some(page),
+            per_page: Some(MAX_PER_PAGE),
+        };
+        assert!(pagination(MAX_PAGE).validate().is_ok());
+        assert!(pagination(MAX_PAGE + 1).validate().is_err());
+        assert!(pagination(usize::MAX).validate().is_err());
+        assert_eq!(
+            pagination(MAX_PAGE).select((1..=5).collect::<Vec<i32>>()),
+            Ok(vec![])
+        );
+        assert_eq!(
+            pagination(usize::MAX).select((1..=5).collect::<Vec<i32>>()),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::auth::Principal;
+use crate::handlers::shares::visibility;
+use crate::handlers::{ValidateJson, ValidatePath};
+use crate::repositories::projects::ProjectRepository;
+use crate::repositories::todo::{TodoFilter, TodoRepository};
+use crate::repositories::Id;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub async fn create_project<S: State>(
+    ValidateJson(payload): ValidateJson<ProjectPayload>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.projects();
+    let project = repository
+        .create(payload.name)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::CREATED, Json(project)))
+}
+
+pub async fn find_project<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.projects();
+    let project = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(project)))
+}
+
+pub async fn all_projects<S: State>(
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.projects();
+    let projects = repository
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(projects)))
+}
+
+pub async fn update_project<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    ValidateJson(payload): ValidateJson<ProjectPayload>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.projects();
+    let project = repository
+        .update(id, payload.name)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(project)))
+}
+
+pub async fn delete_project<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let repository = state.projects();
+    repository
+        .delete(id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn project_todos<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let projects = state.projects();
+    let repository = state.todos();
+    projects.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let todos = repository
+        .all(TodoFilter {
+            project_id: Some(id),
+            visible_to: visibility(&state, &principal).await,
+            ..Default::default()
+        })
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+pub struct ProjectPayload {
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    name: String,
+}
@@ -0,0 +1,66 @@
+use crate::auth::Principal;
+use crate::handlers::shares::{visibility, visible_todos};
+use crate::handlers::{ValidateJson, ValidatePath};
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::Key;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub async fn set_reminder<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<SetReminder>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo = repository
+        .set_reminder(id, Some(payload.remind_at))
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+pub async fn cancel_reminder<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let repository = state.todos();
+    let id = match repository.resolve(key).await {
+        Ok(id) => id,
+        Err(_) => return StatusCode::NOT_FOUND,
+    };
+    repository
+        .set_reminder(id, None)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn all_reminders<S: State>(
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let todos = repository
+        .reminders()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let todos = visible_todos(&state, visibility(&state, &principal).await, todos)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+pub struct SetReminder {
+    remind_at: DateTime<Utc>,
+}
@@ -0,0 +1,88 @@
+use crate::handlers::ValidateQuery;
+use crate::repositories::audit::{AuditLogRepository, BurndownPoint, BurndownRange};
+use crate::repositories::projects::ProjectRepository;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+// 期間を省略したときは、今日までの2週間を集計する
+const DEFAULT_BURNDOWN_DAYS: i64 = 14;
+const MAX_REPORT_DAYS: i64 = 366;
+
+// GET /reports/burndown のクエリパラメータ。fromもtoも含む
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_report_range"))]
+pub struct BurndownQuery {
+    #[validate(range(min = 1, message = "validation.invalid_query"))]
+    project_id: i32,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl BurndownQuery {
+    fn range(&self) -> BurndownRange {
+        let to = self.to.unwrap_or_else(|| Utc::now().date_naive());
+        let from = self
+            .from
+            .unwrap_or(to - Duration::days(DEFAULT_BURNDOWN_DAYS - 1));
+        BurndownRange {
+            project_id: self.project_id,
+            from,
+            to,
+        }
+    }
+}
+
+fn validate_report_range(query: &BurndownQuery) -> Result<(), ValidationError> {
+    let range = query.range();
+    if range.from > range.to {
+        let mut error = ValidationError::new("date_range");
+        error.message = Some("validation.date_range".into());
+        return Err(error);
+    }
+    if (range.to - range.from).num_days() >= MAX_REPORT_DAYS {
+        let mut error = ValidationError::new("report_span");
+        error.message = Some("validation.report_span".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BurndownReport {
+    pub project_id: i32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub series: Vec<BurndownPoint>,
+}
+
+pub async fn burndown_report<S: State>(
+    ValidateQuery(query): ValidateQuery<BurndownQuery>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let range = query.range();
+    state
+        .projects()
+        .find(range.project_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let series = state
+        .audit_logs()
+        .burndown(range)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((
+        StatusCode::OK,
+        Json(BurndownReport {
+            project_id: range.project_id,
+            from: range.from,
+            to: range.to,
+            series,
+        }),
+    ))
+}
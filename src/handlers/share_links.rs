@@ -0,0 +1,147 @@
+use crate::handlers::{ValidateJson, ValidatePath};
+use crate::repositories::share_links::ShareLinkRepository;
+use crate::repositories::todo::{TodoEntity, TodoRepository, TodoStatus};
+use crate::repositories::{EntityId, Key};
+use crate::state::State;
+use axum::extract::{Extension, Path};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use validator::Validate;
+
+// POST /todos/:id/share-link のボディ
+// expires_in_secsを省略すると取り消すまで有効
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct CreateShareLink {
+    #[validate(range(min = 1, max = 2592000))]
+    expires_in_secs: Option<i64>,
+}
+
+pub async fn create_share_link<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<CreateShareLink>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let expires_at = payload
+        .expires_in_secs
+        .map(|secs| Utc::now() + Duration::seconds(secs));
+    let link = state
+        .share_links()
+        .create(id, expires_at)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::CREATED, Json(link)))
+}
+
+pub async fn todo_share_links<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let links = state
+        .share_links()
+        .all(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(links)))
+}
+
+pub async fn revoke_share_link<S: State>(
+    ValidatePath((key, token)): ValidatePath<(Key<S::Id>, String)>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let Ok(id) = state.todos().resolve(key).await else {
+        return StatusCode::NOT_FOUND;
+    };
+    state
+        .share_links()
+        .revoke(id, &token)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+// 公開リンクで見せる内容
+// 見せる相手はアカウントを持たないので、idや担当者などの内部の情報は含めない
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SharedTodo {
+    pub text: String,
+    pub status: TodoStatus,
+    pub labels: Vec<String>,
+    pub tags: Vec<String>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub subtasks: Vec<SharedTodo>,
+}
+
+impl SharedTodo {
+    fn build<I: EntityId>(
+        todo: TodoEntity<I>,
+        children: &mut HashMap<I, Vec<TodoEntity<I>>>,
+    ) -> Self {
+        let subtasks = children
+            .remove(&todo.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| SharedTodo::build(child, children))
+            .collect();
+        SharedTodo {
+            text: todo.text,
+            status: todo.status,
+            labels: todo.labels.into_iter().map(|label| label.name).collect(),
+            tags: todo.tags,
+            completed_at: todo.completed_at,
+            subtasks,
+        }
+    }
+}
+
+// 認証せずに読める。期限切れのリンクは410、取り消したものや不明なものは404を返す
+pub async fn shared_todo<S: State>(
+    Path(token): Path<String>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let link = state
+        .share_links()
+        .find(&token)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    if link.is_expired(Utc::now()) {
+        return Err(StatusCode::GONE);
+    }
+    let repository = state.todos();
+    let todo = repository
+        .find(link.todo_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+
+    // 子孫を親ごとに集めてから木にする
+    let mut children = HashMap::new();
+    let mut parents = vec![todo.id];
+    while let Some(parent_id) = parents.pop() {
+        if children.contains_key(&parent_id) {
+            continue;
+        }
+        let subtasks = repository
+            .children(parent_id)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        parents.extend(subtasks.iter().map(|subtask| subtask.id));
+        children.insert(parent_id, subtasks);
+    }
+    Ok((StatusCode::OK, Json(SharedTodo::build(todo, &mut children))))
+}
@@ -0,0 +1,280 @@
+use crate::auth::{Principal, Role};
+use crate::handlers::{ApiError, ValidateJson, ValidatePath};
+use crate::repositories::todo::{
+    find_existing, Access, Permission, TodoEntity, TodoRepository, Visibility,
+};
+use crate::repositories::users::UserRepository;
+use crate::repositories::{EntityId, Id, Key};
+use crate::state::State;
+use axum::body::Body;
+use axum::extract::Extension;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// リクエスト主体から見えるtodoの範囲
+// 管理者はすべて、ユーザーとして登録されていれば所有するものと共有されたものも見える
+pub async fn visibility<S: State>(state: &S, principal: &Principal) -> Visibility {
+    if principal.role == Role::Admin {
+        return Visibility::All;
+    }
+    match state.users().find_by_name(&principal.name).await {
+        Ok(user) => Visibility::User(user.id),
+        Err(_) => Visibility::Unowned,
+    }
+}
+
+// 作成したtodoの所有者にするユーザー
+// ユーザーとして登録されていない主体が作ったtodoは所有者なしにする
+pub async fn owner_id<S: State>(state: &S, principal: &Principal) -> Option<i32> {
+    state
+        .users()
+        .find_by_name(&principal.name)
+        .await
+        .ok()
+        .map(|user| user.id)
+}
+
+// todoに対する主体の権限。見えない場合はNone
+pub async fn todo_access<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    visibility: Visibility,
+    todo: &TodoEntity<I>,
+) -> anyhow::Result<Option<Access>> {
+    let shares = match (visibility, todo.owner_id) {
+        (Visibility::User(_), Some(_)) => repository.shares(todo.id).await?,
+        _ => vec![],
+    };
+    Ok(visibility.access(todo.owner_id, &shares))
+}
+
+// パス以外で指定されたtodoにも、パスのtodoと同じように権限を求める
+// 見えないtodoは存在しないものとして404、見えるが権限が足りない場合は403を返す
+pub async fn require_todo_access<S: State>(
+    state: &S,
+    principal: &Principal,
+    todo: &TodoEntity<S::Id>,
+    required: Access,
+) -> Result<(), ApiError> {
+    let access = todo_access(state.todos(), visibility(state, principal).await, todo)
+        .await
+        .map_err(|_| ApiError::from(StatusCode::INTERNAL_SERVER_ERROR))?;
+    match access {
+        None => Err(ApiError::from(StatusCode::NOT_FOUND)),
+        Some(access) if access < required => {
+            Err(ApiError::new(StatusCode::FORBIDDEN, "auth.forbidden"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+// 一覧以外でまとめて返すtodoを、見えるものだけに絞り込む
+pub async fn visible_todos<S: State>(
+    state: &S,
+    visibility: Visibility,
+    todos: Vec<TodoEntity<S::Id>>,
+) -> anyhow::Result<Vec<TodoEntity<S::Id>>> {
+    if visibility == Visibility::All {
+        return Ok(todos);
+    }
+    let mut visible = vec![];
+    for todo in todos {
+        if todo_access(state.todos(), visibility, &todo)
+            .await?
+            .is_some()
+        {
+            visible.push(todo);
+        }
+    }
+    Ok(visible)
+}
+
+// まとめて変更するtodoのidを、書き込めるものだけに絞り込む
+// 見えないものや存在しないものは飛ばす
+pub async fn writable_todo_ids<S: State>(
+    state: &S,
+    visibility: Visibility,
+    ids: Vec<S::Id>,
+) -> anyhow::Result<Vec<S::Id>> {
+    if visibility == Visibility::All {
+        return Ok(ids);
+    }
+    let mut writable = vec![];
+    for todo in find_existing(state.todos(), &ids).await? {
+        if todo_access(state.todos(), visibility, &todo).await? >= Some(Access::Write) {
+            writable.push(todo.id);
+        }
+    }
+    Ok(writable)
+}
+
+// /todos/:id 以下へのリクエストを、そのtodoに対する権限で制限する
+// 見えないtodoは存在しないものとして404、見えるが権限が足りない場合は403を返す
+pub async fn enforce_todo_access<S: State>(
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    let key = todo_key::<S::Id>(req.uri().path());
+    let (Some(key), Some(state), Some(principal)) = (
+        key,
+        req.extensions().get::<S>().cloned(),
+        req.extensions().get::<Principal>().cloned(),
+    ) else {
+        return Ok(next.run(req).await);
+    };
+    let visibility = visibility(&state, &principal).await;
+    if visibility == Visibility::All {
+        return Ok(next.run(req).await);
+    }
+    // 存在しない場合の応答はハンドラに任せる
+    let repository = state.todos();
+    let Ok(todo) = async { repository.find(repository.resolve(key).await?).await }.await else {
+        return Ok(next.run(req).await);
+    };
+    let shares = repository
+        .shares(todo.id)
+        .await
+        .map_err(|_| ApiError::from(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let required = required_access(req.method(), req.uri().path());
+    match visibility.access(todo.owner_id, &shares) {
+        None => Err(ApiError::from(StatusCode::NOT_FOUND)),
+        Some(access) if access < required => {
+            Err(ApiError::new(StatusCode::FORBIDDEN, "auth.forbidden"))
+        }
+        Some(_) => Ok(next.run(req).await),
+    }
+}
+
+// /todos/:id で始まるパスのtodoの識別子
+fn todo_key<I: EntityId>(path: &str) -> Option<Key<I>> {
+    let mut segments = path.strip_prefix("/todos/")?.split('/');
+    match segments.next()? {
+        "stats" | "from-template" | "sync" => None,
+        key => key.parse().ok(),
+    }
+}
+
+// 共有の変更は所有者だけができる
+fn required_access(method: &Method, path: &str) -> Access {
+    if path.contains("/share") && *method != Method::GET {
+        Access::Owner
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        Access::Read
+    } else {
+        Access::Write
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ShareTodo {
+    #[validate(range(min = 1, message = "validation.positive"))]
+    user_id: i32,
+    permission: Permission,
+}
+
+// 共有相手のユーザーが存在しない場合は422にする
+// 既に共有している場合は権限を置き換えて200を返す
+pub async fn share_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<ShareTodo>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    state
+        .users()
+        .find(payload.user_id)
+        .await
+        .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
+    if todo.owner_id == Some(payload.user_id) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let shares = repository
+        .shares(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let status = if shares.iter().any(|share| share.user_id == payload.user_id) {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    let share = repository
+        .share(id, payload.user_id, payload.permission)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((status, Json(share)))
+}
+
+pub async fn unshare_todo<S: State>(
+    ValidatePath((key, Id(user_id))): ValidatePath<(Key<S::Id>, Id)>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let repository = state.todos();
+    let Ok(id) = repository.resolve(key).await else {
+        return StatusCode::NOT_FOUND;
+    };
+    repository
+        .unshare(id, user_id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn todo_shares<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let shares = repository
+        .shares(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(shares)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_find_todo_key_in_path() {
+        assert_eq!(todo_key::<i32>("/todos/1"), Some(Key::Id(1)));
+        assert_eq!(todo_key::<i32>("/todos/1/share/2"), Some(Key::Id(1)));
+        assert_eq!(todo_key::<i32>("/todos/stats/cycle-time"), None);
+        assert_eq!(todo_key::<i32>("/todos/from-template/1"), None);
+        assert_eq!(todo_key::<i32>("/todos/sync"), None);
+        assert_eq!(todo_key::<i32>("/todos"), None);
+        assert_eq!(todo_key::<i32>("/labels/1"), None);
+    }
+
+    #[test]
+    fn should_require_owner_to_change_shares() {
+        assert_eq!(required_access(&Method::GET, "/todos/1"), Access::Read);
+        assert_eq!(required_access(&Method::PATCH, "/todos/1"), Access::Write);
+        assert_eq!(
+            required_access(&Method::GET, "/todos/1/shares"),
+            Access::Read
+        );
+        assert_eq!(
+            required_access(&Method::POST, "/todos/1/share"),
+            Access::Owner
+        );
+        assert_eq!(
+            required_access(&Method::DELETE, "/todos/1/share/2"),
+            Access::Owner
+        );
+    }
+}
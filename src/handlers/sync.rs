@@ -0,0 +1,305 @@
+use crate::auth::Principal;
+use crate::handlers::shares::{owner_id, visibility};
+use crate::handlers::{ValidateJson, ValidateQuery};
+use crate::repositories::labels::LabelRepository;
+use crate::repositories::todo::{
+    validate_tags, Access, CreateTodo, TodoEntity, TodoRepository, TodoStatus, UpdateTodo,
+    Visibility,
+};
+use crate::repositories::{EntityId, RepositoryError};
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+// PUT /todos/sync のボディ
+// オフラインで編集したtodoを、クライアントが振ったUUIDと最後に編集した時刻とともにまとめて送る
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields, bound(deserialize = ""))]
+pub struct SyncTodos<I: EntityId = i32> {
+    #[validate(length(min = 1, max = 100, message = "validation.sync_batch"))]
+    #[validate]
+    todos: Vec<SyncTodo<I>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields, bound(deserialize = ""))]
+pub struct SyncTodo<I: EntityId = i32> {
+    uuid: Uuid,
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    text: String,
+    #[serde(default)]
+    status: TodoStatus,
+    #[serde(default)]
+    labels: Vec<I>,
+    #[serde(default)]
+    #[validate(
+        length(max = 10, message = "validation.too_many_tags"),
+        custom = "validate_tags"
+    )]
+    tags: Vec<String>,
+    // クライアントで削除した
+    #[serde(default)]
+    deleted: bool,
+    modified_at: DateTime<Utc>,
+}
+
+impl<I: EntityId> SyncTodo<I> {
+    // サーバーの状態と同じであれば、古くても衝突として扱わない
+    fn differs(&self, todo: &TodoEntity<I>) -> bool {
+        let labels: Vec<I> = todo.labels.iter().map(|label| label.id).collect();
+        self.deleted
+            || self.text != todo.text
+            || self.status != todo.status
+            || self.labels != labels
+            || self.tags != todo.tags
+    }
+
+    fn to_create(&self) -> CreateTodo<I> {
+        CreateTodo::synced(
+            self.uuid,
+            self.text.clone(),
+            self.labels.clone(),
+            self.tags.clone(),
+        )
+    }
+
+    fn to_update(&self) -> UpdateTodo<I> {
+        UpdateTodo::replace(
+            self.text.clone(),
+            self.status,
+            self.labels.clone(),
+            self.tags.clone(),
+        )
+    }
+}
+
+// 同期後のサーバーの状態
+// クライアントは次の同期でこのmodified_atと比べる
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedTodo<I = i32> {
+    #[serde(flatten)]
+    pub todo: TodoEntity<I>,
+    pub modified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncConflictReason {
+    // サーバーの方が新しく、クライアントの変更を捨てた
+    Stale,
+    // 見えないか、書き込む権限がない
+    Forbidden,
+    // 存在しないラベルや中止から完了への変更など、受け付けられない内容
+    Rejected,
+}
+
+// 反映しなかった変更。サーバーの状態はtodosで返す
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub uuid: Uuid,
+    pub reason: SyncConflictReason,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncResult<I = i32> {
+    pub todos: Vec<SyncedTodo<I>>,
+    pub deleted: Vec<Uuid>,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+impl<I> SyncResult<I> {
+    fn conflict(&mut self, uuid: Uuid, reason: SyncConflictReason) {
+        self.conflicts.push(SyncConflict { uuid, reason });
+    }
+}
+
+// 最後に編集した時刻が新しい方を残す
+// 送られたtodoごとに反映し、反映できなかったものは衝突として返す
+pub async fn sync_todos<S: State>(
+    ValidateJson(payload): ValidateJson<SyncTodos<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let labels = state
+        .labels()
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let sync = SyncContext {
+        state: &state,
+        visibility: visibility(&state, &principal).await,
+        owner_id: owner_id(&state, &principal).await,
+    };
+    let mut result = SyncResult::<S::Id>::default();
+    for todo in payload.todos {
+        if !todo
+            .labels
+            .iter()
+            .all(|id| labels.iter().any(|label| label.id == *id))
+        {
+            result.conflict(todo.uuid, SyncConflictReason::Rejected);
+            continue;
+        }
+        sync.todo(todo, &mut result)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    }
+    Ok((StatusCode::OK, Json(result)))
+}
+
+struct SyncContext<'a, S> {
+    state: &'a S,
+    visibility: Visibility,
+    owner_id: Option<i32>,
+}
+
+impl<S: State> SyncContext<'_, S> {
+    async fn todo(
+        &self,
+        todo: SyncTodo<S::Id>,
+        result: &mut SyncResult<S::Id>,
+    ) -> anyhow::Result<()> {
+        let repository = self.state.todos();
+        let existing = match repository.find_by_uuid(todo.uuid).await {
+            Ok(existing) => existing,
+            Err(e) if is_not_found(&e) => {
+                if todo.deleted {
+                    result.deleted.push(todo.uuid);
+                    return Ok(());
+                }
+                match self.create(&todo).await {
+                    Ok(created) => return self.synced(created, result).await,
+                    // 読み込んだ後に同じUUIDで作られていた
+                    Err(e) => match e.downcast_ref::<RepositoryError>() {
+                        Some(RepositoryError::Duplicate(key)) => match S::Id::from_key(*key) {
+                            Some(id) => repository.find(id).await?,
+                            None => return Err(e),
+                        },
+                        // ラベルが多すぎるものは作らずに拒否する
+                        Some(RepositoryError::TooManyLabels(_)) => {
+                            result.conflict(todo.uuid, SyncConflictReason::Rejected);
+                            return Ok(());
+                        }
+                        _ => return Err(e),
+                    },
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        let shares = match existing.owner_id {
+            Some(_) => repository.shares(existing.id).await?,
+            None => vec![],
+        };
+        match self.visibility.access(existing.owner_id, &shares) {
+            None => {
+                result.conflict(todo.uuid, SyncConflictReason::Forbidden);
+                return Ok(());
+            }
+            Some(access) if access < Access::Write => {
+                result.conflict(todo.uuid, SyncConflictReason::Forbidden);
+                return self.synced(existing, result).await;
+            }
+            Some(_) => {}
+        }
+
+        let modified_at = repository.modified_at(existing.id).await?;
+        if todo.modified_at <= modified_at {
+            if todo.differs(&existing) {
+                result.conflict(todo.uuid, SyncConflictReason::Stale);
+            }
+            result.todos.push(SyncedTodo {
+                todo: existing,
+                modified_at,
+            });
+            return Ok(());
+        }
+        if todo.deleted {
+            repository.delete(existing.id).await?;
+            result.deleted.push(todo.uuid);
+            return Ok(());
+        }
+        if !existing.status.can_transition_to(todo.status) {
+            result.conflict(todo.uuid, SyncConflictReason::Rejected);
+            return self.synced(existing, result).await;
+        }
+        match repository.update(existing.id, todo.to_update()).await {
+            Ok(updated) => self.synced(updated, result).await,
+            // 本文が同じ未完了のtodoと重複するか、ラベルが多すぎる
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<RepositoryError>(),
+                    Some(RepositoryError::Duplicate(_) | RepositoryError::TooManyLabels(_))
+                ) =>
+            {
+                result.conflict(todo.uuid, SyncConflictReason::Rejected);
+                self.synced(existing, result).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // 作成時は未着手になるため、ほかの状態で作られていれば続けて変更する
+    async fn create(&self, todo: &SyncTodo<S::Id>) -> anyhow::Result<TodoEntity<S::Id>> {
+        let repository = self.state.todos();
+        let created = repository
+            .create(todo.to_create().owned_by(self.owner_id))
+            .await?;
+        if todo.status == created.status || !created.status.can_transition_to(todo.status) {
+            return Ok(created);
+        }
+        repository
+            .update(created.id, UpdateTodo::status(todo.status))
+            .await
+    }
+
+    async fn synced(
+        &self,
+        todo: TodoEntity<S::Id>,
+        result: &mut SyncResult<S::Id>,
+    ) -> anyhow::Result<()> {
+        let modified_at = self.state.todos().modified_at(todo.id).await?;
+        result.todos.push(SyncedTodo { todo, modified_at });
+        Ok(())
+    }
+}
+
+// GET /todos/changes のクエリパラメータ
+// sinceを省略すると最初からの変更を返す
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ChangesQuery {
+    #[serde(default)]
+    #[validate(range(min = 0, message = "validation.sync_token"))]
+    since: i64,
+}
+
+// 前回の同期トークンより後に作成・変更・削除されたtodoのidと、次の同期トークンを返す
+// クライアントは作成・変更されたtodoだけを取り直せばよい
+pub async fn todo_changes<S: State>(
+    ValidateQuery(query): ValidateQuery<ChangesQuery>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let changes = state
+        .todos()
+        .changes(query.since, visibility(&state, &principal).await)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(changes)))
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::NotFoundUuid(_))
+    )
+}
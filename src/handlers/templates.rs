@@ -0,0 +1,135 @@
+use crate::auth::Principal;
+use crate::handlers::shares::owner_id;
+use crate::handlers::{ApiError, ValidateJson, ValidatePath, ValidationErrorBody};
+use crate::repositories::labels::LabelRepository;
+use crate::repositories::templates::{TemplatePayload, TemplateRepository};
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::Id;
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use validator::Validate;
+
+// 存在しないラベルを指定した場合は422にする
+async fn check_labels<S: State>(
+    state: &S,
+    payload: &TemplatePayload<S::Id>,
+) -> Result<(), StatusCode> {
+    let labels = state
+        .labels()
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if payload
+        .labels
+        .iter()
+        .all(|id| labels.iter().any(|label| label.id == *id))
+    {
+        Ok(())
+    } else {
+        Err(StatusCode::UNPROCESSABLE_ENTITY)
+    }
+}
+
+pub async fn create_template<S: State>(
+    ValidateJson(payload): ValidateJson<TemplatePayload<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_labels(&state, &payload).await?;
+    let template = state
+        .templates()
+        .create(payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+pub async fn find_template<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let template = state
+        .templates()
+        .find(id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(template)))
+}
+
+pub async fn all_templates<S: State>(
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let templates = state
+        .templates()
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(templates)))
+}
+
+pub async fn update_template<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    ValidateJson(payload): ValidateJson<TemplatePayload<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_labels(&state, &payload).await?;
+    let template = state
+        .templates()
+        .update(id, payload)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(template)))
+}
+
+pub async fn delete_template<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    state
+        .templates()
+        .delete(id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+// POST /todos/from-template/:id のボディ
+// 本文の {{name}} をsubstitutionsの値で置き換える
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct InstantiateTemplate {
+    #[serde(default)]
+    substitutions: BTreeMap<String, String>,
+}
+
+// テンプレートからtodoを作る
+// 置き換え後の本文が不正な場合は通常の作成と同じく422を返す
+pub async fn create_todo_from_template<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    ValidateJson(payload): ValidateJson<InstantiateTemplate>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, Response> {
+    let template = state
+        .templates()
+        .find(id)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND.into_response())?;
+    let create = template
+        .instantiate(&payload.substitutions)
+        .map_err(|errors| {
+            let body = ValidationErrorBody::from(errors);
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response()
+        })?;
+    let todo = state
+        .todos()
+        .create(create.owned_by(owner_id(&state, &principal).await))
+        .await
+        .map_err(|e| ApiError::repository(StatusCode::UNPROCESSABLE_ENTITY, &e).into_response())?;
+    Ok((StatusCode::CREATED, Json(todo)))
+}
@@ -1,81 +1,1003 @@
-use crate::handlers::ValidateJson;
-use crate::repositories::todo::{CreateTodo, TodoRepository, UpdateTodo};
-use axum::extract::{Extension, Path};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use crate::auth::Principal;
+use crate::handlers::conditional::{is_modified_since, last_modified, not_modified};
+use crate::handlers::pagination::{page_headers, paginate, Pagination};
+use crate::handlers::shares::{
+    owner_id, require_todo_access, todo_access, visibility, visible_todos,
+};
+use crate::handlers::{ApiError, ValidateJson, ValidatePath, ValidatePayload, ValidateQuery};
+use crate::repositories::audit::UndoTodoRepository;
+use crate::repositories::labels::{normalize_color, validate_color};
+use crate::repositories::projects::ProjectRepository;
+use crate::repositories::todo::{
+    Access, CompletedRange, CreateTodo, ListedTodo, TodoEntity, TodoFilter, TodoPage,
+    TodoRepository, TodoSort, TodoStatus, TodoStream, UpdateTodo, Visibility,
+};
+use crate::repositories::users::UserRepository;
+use crate::repositories::{validate_id, EntityId, Key, KeyPair, RepositoryError};
+use crate::state::State;
+use crate::unit_of_work::Transactional;
+use axum::body::StreamBody;
+use axum::extract::{Extension, Query};
+use axum::http::header::{ALLOW, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::{Headers, IntoResponse, Response};
 use axum::Json;
-use rand::Rng;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::time::sleep;
+use validator::{Validate, ValidationError};
 
 // Extension抽出器
 // アプリケーションの状態や依存関係をハンドラに注入するために使用されます。
 // これにより、共有状態や他のリソースへのアクセスをハンドラ関数内で容易にできるようになります。
-// create_todoでは、Extension<Arc<T>>を使用して、TodoRepositoryのインスタンスをハンドラに注入しています。
-// Json(payload)では、リクエストボディをデシリアライズしてCreateTodo型に変換しています。
-pub async fn create_todo<T: TodoRepository>(
-    ValidateJson(payload): ValidateJson<CreateTodo>,
-    Extension(repository): Extension<Arc<T>>,
+// create_todoでは、Extension<S>を使用して、AppStateからTodoRepositoryのインスタンスを取り出しています。
+// ValidatePayload(payload)では、JSONかフォームのリクエストボディをデシリアライズしてCreateTodo型に変換しています。
+pub async fn create_todo<S: State>(
+    Query(query): Query<CreateTodoQuery>,
+    ValidatePayload(payload): ValidatePayload<CreateTodo<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+    dedupe: Option<Extension<DedupeTodos>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let repository = state.todos();
+    let payload = payload.owned_by(owner_id(&state, &principal).await);
+    if let Some(parent_id) = payload.parent_id() {
+        require_visible_parent(&state, &principal, parent_id).await?;
+    }
+    let dedupe = query
+        .dedupe
+        .unwrap_or_else(|| dedupe.is_some_and(|Extension(dedupe)| dedupe.0));
+    if !dedupe {
+        let todo = repository.create(payload).await.map_err(create_error)?;
+        return Ok((StatusCode::CREATED, Json(todo)));
+    }
+
+    // 本文が同じ未完了の見えるtodoがあれば、作成せずにそれを返す
+    let existing = repository
+        .find_by_text(payload.text(), visibility(&state, &principal).await)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if let Some(todo) = existing {
+        return Ok((StatusCode::CONFLICT, Json(todo)));
+    }
+    match repository.create(payload.deduplicated()).await {
+        Ok(todo) => Ok((StatusCode::CREATED, Json(todo))),
+        // 同時に作成された場合
+        Err(e) => match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::Duplicate(key)) => {
+                let id = S::Id::from_key(*key).ok_or(StatusCode::CONFLICT)?;
+                let todo = repository.find(id).await.or(Err(StatusCode::CONFLICT))?;
+                Ok((StatusCode::CONFLICT, Json(todo)))
+            }
+            _ => Err(create_error(e)),
+        },
+    }
+}
+
+// ラベルが多すぎる場合は422を返す
+fn create_error(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::TooManyLabels(_)) => {
+            ApiError::repository(StatusCode::UNPROCESSABLE_ENTITY, &e)
+        }
+        _ => ApiError::repository(StatusCode::NOT_FOUND, &e),
+    }
+}
+
+// POST /todos のクエリパラメータ
+// dedupeを指定すると TODO_DEDUPE の設定より優先する
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateTodoQuery {
+    dedupe: Option<bool>,
+}
+
+// 本文が同じ未完了のtodoの作成を既定で拒否するか
+// TODO_DEDUPE="true" で有効にする
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupeTodos(pub bool);
+
+impl DedupeTodos {
+    pub fn from_env() -> Self {
+        DedupeTodos(std::env::var("TODO_DEDUPE").is_ok_and(|value| value == "true"))
+    }
+}
+
+// 更新によって重複した場合は409を、ラベルが多すぎる場合は422を返す
+fn update_error(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::Duplicate(_)) => ApiError::repository(StatusCode::CONFLICT, &e),
+        Some(RepositoryError::TooManyLabels(_)) => {
+            ApiError::repository(StatusCode::UNPROCESSABLE_ENTITY, &e)
+        }
+        _ => ApiError::repository(StatusCode::NOT_FOUND, &e),
+    }
+}
+
+// HeaderMapはリクエストのヘッダーを取り出してしまうので最後に置く
+pub async fn find_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let modified_at = repository
+        .modified_at(id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    if !is_modified_since(&headers, modified_at) {
+        return Ok(not_modified(modified_at));
+    }
+    // ok_orはOptionをErrに変換して?で即時返却している
+    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, last_modified(modified_at), Json(todo)).into_response())
+}
+
+// GET /todos のクエリパラメータ
+// assigneeにはユーザーIDか、リクエスト主体自身を表す"me"を指定する
+// tagを指定するとそのタグが付いたTODOだけを返す
+// shared_with_me=true で他のユーザーから共有されたtodoだけを返す
+// label_idでラベル、completedで完了したかどうか、colorで色(%23を付けた#RRGGBB)でも絞り込める
+// スヌーズ中のtodoは返さず、snoozed=true でスヌーズ中のものだけを返す
+// sortで並び順を指定する。指定しなければユーザーの設定の並び順にする
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(bound(deserialize = ""))]
+pub struct TodoQuery<I: EntityId = i32> {
+    assignee: Option<String>,
+    #[validate(length(min = 1, max = 30, message = "validation.tag_length"))]
+    tag: Option<String>,
+    #[serde(default)]
+    shared_with_me: bool,
+    #[validate(custom = "validate_id")]
+    label_id: Option<I>,
+    completed: Option<bool>,
+    #[validate(custom = "validate_color")]
+    color: Option<String>,
+    #[serde(default)]
+    snoozed: bool,
+    sort: Option<TodoSort>,
+}
+
+pub async fn all_todos<S: State>(
+    uri: Uri,
+    ValidateQuery(query): ValidateQuery<TodoQuery<S::Id>>,
+    ValidateQuery(pagination): ValidateQuery<Pagination>,
+    Query(mode): Query<ListMode>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let modified_at = state
+        .todos()
+        .list_modified_at()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if !is_modified_since(&headers, modified_at) {
+        return Ok(not_modified(modified_at));
+    }
+    if mode.stream {
+        let todos = match todo_filter(&state, &principal, query).await? {
+            Some(filter) => state
+                .todos()
+                .stream(filter)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+            None => stream::empty().boxed(),
+        };
+        return Ok((last_modified(modified_at), json_array(todos)).into_response());
+    }
+
+    // ページを指定された場合はリポジトリで切り出す
+    if let Some((offset, limit)) = pagination.range()? {
+        let (listed, total) = list_todo_page(&state, &principal, query, offset, limit).await?;
+        let todos: Vec<TodoEntity<S::Id>> = listed.into_iter().map(|listed| listed.todo).collect();
+        let Headers(mut headers) = last_modified(modified_at);
+        headers.extend(page_headers(&uri, pagination, total)?);
+        return Ok((Headers(headers), Json(todos)).into_response());
+    }
+
+    let todos = list_todos(&state, &principal, query).await?;
+    // HEADの場合もボディを除いて件数をヘッダーで返す
+    let todos = paginate(&uri, pagination, todos)?;
+    Ok((last_modified(modified_at), todos).into_response())
+}
+
+// GET /todos の返し方
+// stream=true で全件を1件ずつ書き出す。件数が多い書き出し向けで、ページ指定・並び順と総件数のヘッダーは使わない
+#[derive(Debug, Default, Deserialize)]
+pub struct ListMode {
+    #[serde(default)]
+    stream: bool,
+}
+
+// todoをJSONの配列として1件ずつ書き出す
+// 途中で読めなくなった場合は、閉じていない配列のまま接続を切る
+fn json_array<I: EntityId>(todos: TodoStream<I>) -> impl IntoResponse {
+    let items = todos.enumerate().map(|(i, todo)| {
+        let json = todo.and_then(|todo| Ok(serde_json::to_string(&todo)?));
+        if let Err(e) = &json {
+            tracing::warn!("failed to stream todos: {}", e);
+        }
+        json.map(|json| if i == 0 { json } else { format!(",{}", json) })
+    });
+    let body = stream::once(async { Ok("[".to_string()) })
+        .chain(items)
+        .chain(stream::once(async { Ok("]".to_string()) }));
+    (
+        Headers(vec![(CONTENT_TYPE, "application/json")]),
+        StreamBody::new(body),
+    )
+}
+
+// GET /todos の条件でtodoを絞り込む。フィードなど他の形式の一覧でも使う
+pub async fn list_todos<S: State>(
+    state: &S,
+    principal: &Principal,
+    query: TodoQuery<S::Id>,
+) -> Result<Vec<TodoEntity<S::Id>>, StatusCode> {
+    let sort = query.sort;
+    let Some(filter) = todo_filter(state, principal, query).await? else {
+        return Ok(vec![]);
+    };
+    let mut todos = state
+        .todos()
+        .all(filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if let Some(sort) = list_sort(state, principal, sort).await {
+        sort.sort(&mut todos);
+    }
+    Ok(todos)
+}
+
+// list_todosの並び順でoffset件目からlimit件までを、更新時刻と絞り込み後の総件数と一緒に返す
+pub async fn list_todo_page<S: State>(
+    state: &S,
+    principal: &Principal,
+    query: TodoQuery<S::Id>,
+    offset: usize,
+    limit: usize,
+) -> Result<(Vec<ListedTodo<S::Id>>, usize), StatusCode> {
+    let sort = query.sort;
+    let Some(filter) = todo_filter(state, principal, query).await? else {
+        return Ok((vec![], 0));
+    };
+    let page = TodoPage {
+        sort: list_sort(state, principal, sort).await.unwrap_or_default(),
+        offset,
+        limit,
+    };
+    let repository = state.todos();
+    let total = repository
+        .count(filter.clone())
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let listed = repository
+        .page(filter, page)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((listed, total as usize))
+}
+
+// 指定がなければ、ユーザーが設定した既定の並び順にする
+// どちらもなければリポジトリの返した順のままにする
+async fn list_sort<S: State>(
+    state: &S,
+    principal: &Principal,
+    sort: Option<TodoSort>,
+) -> Option<TodoSort> {
+    if sort.is_some() {
+        return sort;
+    }
+    let users = state.users();
+    let user = users.find_by_name(&principal.name).await.ok()?;
+    users.preferences(user.id).await.ok()?.default_sort
+}
+
+// クエリパラメータを一覧の絞り込み条件にする
+// 当てはまるtodoがないと分かっている場合はNoneを返す
+async fn todo_filter<S: State>(
+    state: &S,
+    principal: &Principal,
+    query: TodoQuery<S::Id>,
+) -> Result<Option<TodoFilter<S::Id>>, StatusCode> {
+    let users = state.users();
+    let assignee_id = match query.assignee.as_deref() {
+        None => None,
+        Some("me") => match users.find_by_name(&principal.name).await {
+            Ok(user) => Some(user.id),
+            // ユーザーとして登録されていない場合は担当するtodoもない
+            Err(_) => return Ok(None),
+        },
+        Some(id) => Some(id.parse().or(Err(StatusCode::BAD_REQUEST))?),
+    };
+    let shared_with = match query.shared_with_me {
+        false => None,
+        true => match users.find_by_name(&principal.name).await {
+            Ok(user) => Some(user.id),
+            // ユーザーとして登録されていない場合は共有されたtodoもない
+            Err(_) => return Ok(None),
+        },
+    };
+    Ok(Some(TodoFilter {
+        assignee_id,
+        tag: query.tag,
+        label_id: query.label_id,
+        completed: query.completed,
+        color: query.color.as_deref().map(normalize_color),
+        snoozed: Some(query.snoozed),
+        visible_to: visibility(state, principal).await,
+        shared_with,
+        ..Default::default()
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TodoCount {
+    count: i64,
+}
+
+// バッジの表示用に、一覧と同じ条件で件数だけを返す
+pub async fn count_todos<S: State>(
+    ValidateQuery(query): ValidateQuery<TodoQuery<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
 ) -> Result<impl IntoResponse, StatusCode> {
+    let count = match todo_filter(&state, &principal, query).await? {
+        Some(filter) => state
+            .todos()
+            .count(filter)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+        None => 0,
+    };
+    Ok((StatusCode::OK, Json(TodoCount { count })))
+}
+
+// CORSのプリフライト以外のOPTIONSには、使えるメソッドを返す
+pub async fn todos_options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        Headers(vec![(ALLOW, "GET, HEAD, POST, OPTIONS")]),
+    )
+}
+
+// 完了にするときのクエリパラメータ
+// cascade=true で子孫のtodoもまとめて完了にする
+#[derive(Debug, Default, Deserialize)]
+pub struct CompleteQuery {
+    #[serde(default)]
+    cascade: bool,
+}
+
+// 中止したものと完了済みのものはそのままにする
+async fn complete_descendants<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    id: I,
+) -> anyhow::Result<()> {
+    for id in repository.descendants(id).await? {
+        let todo = repository.find(id).await?;
+        if todo.status != TodoStatus::Done && todo.status.can_transition_to(TodoStatus::Done) {
+            repository
+                .update(id, UpdateTodo::status(TodoStatus::Done))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn update_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Query(query): Query<CompleteQuery>,
+    ValidateJson(payload): ValidateJson<UpdateTodo<S::Id>>,
+    Extension(state): Extension<S>,
+    transactions: Option<Extension<Arc<dyn Transactional<S::Id>>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = state
+        .todos()
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo = match (query.cascade, transactions) {
+        // 子孫もまとめて完了にする場合は、途中で失敗したら親の変更も取り消す
+        (true, Some(Extension(transactions))) => {
+            let unit = transactions
+                .begin()
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            let todo = update_and_cascade(unit.todos(), id, payload, true).await?;
+            unit.commit()
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            todo
+        }
+        (cascade, _) => update_and_cascade(state.todos(), id, payload, cascade).await?,
+    };
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+async fn update_and_cascade<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    id: I,
+    payload: UpdateTodo<I>,
+    cascade: bool,
+) -> Result<TodoEntity<I>, ApiError> {
+    let old_todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    if !old_todo
+        .status
+        .can_transition_to(payload.next_status(old_todo.status))
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into());
+    }
+    let todo = repository.update(id, payload).await.map_err(update_error)?;
+    if cascade && todo.status.is_completed() {
+        complete_descendants(repository, id)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    }
+    Ok(todo)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct ChangeTodoStatus {
+    status: TodoStatus,
+}
+
+pub async fn change_todo_status<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Query(query): Query<CompleteQuery>,
+    ValidateJson(payload): ValidateJson<ChangeTodoStatus>,
+    Extension(state): Extension<S>,
+    transactions: Option<Extension<Arc<dyn Transactional<S::Id>>>>,
+) -> Result<impl IntoResponse, ApiError> {
+    update_todo(
+        ValidatePath(key),
+        Query(query),
+        ValidateJson(UpdateTodo::status(payload.status)),
+        Extension(state),
+        transactions,
+    )
+    .await
+}
+
+pub async fn delete_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let repository = state.todos();
+    let id = match repository.resolve(key).await {
+        Ok(id) => id,
+        Err(_) => return StatusCode::NOT_FOUND,
+    };
+    repository
+        .delete(id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn todo_history<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let revisions = repository
+        .history(id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(revisions)))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct AssignTodo {
+    assignee_id: Option<i32>,
+}
+
+pub async fn assign_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<AssignTodo>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let users = state.users();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let assignee = match payload.assignee_id {
+        Some(user_id) => Some(
+            users
+                .find(user_id)
+                .await
+                .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?,
+        ),
+        None => None,
+    };
     let todo = repository
-        .create(payload)
+        .assign(id, assignee)
         .await
         .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+    Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn find_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    Extension(repository): Extension<Arc<T>>,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct MoveTodo {
+    project_id: Option<i32>,
+}
+
+pub async fn move_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<MoveTodo>,
+    Extension(state): Extension<S>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    // ok_orはOptionをErrに変換して?で即時返却している
-    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let repository = state.todos();
+    let projects = state.projects();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    if let Some(project_id) = payload.project_id {
+        projects
+            .find(project_id)
+            .await
+            .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
+    }
+    let todo = repository
+        .move_to_project(id, payload.project_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+// 一覧の先頭に固定する
+pub async fn pin_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_pinned(key, true, &state).await
+}
+
+pub async fn unpin_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_pinned(key, false, &state).await
+}
+
+async fn set_pinned<S: State>(
+    key: Key<S::Id>,
+    pinned: bool,
+    state: &S,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo = repository
+        .pin(id, pinned)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
     Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn all_todos<T: TodoRepository>(
-    Extension(repository): Extension<Arc<T>>,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct SnoozeTodo {
+    #[validate(custom = "validate_snooze_until")]
+    until: DateTime<Utc>,
+}
+
+fn validate_snooze_until(until: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *until <= Utc::now() {
+        let mut error = ValidationError::new("snooze_until");
+        error.message = Some("validation.snooze_until".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// untilの時刻まで一覧から外す
+pub async fn snooze_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<SnoozeTodo>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_snoozed(key, Some(payload.until), &state).await
+}
+
+// 時刻を待たずに一覧に戻す
+pub async fn wake_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_snoozed(key, None, &state).await
+}
+
+async fn set_snoozed<S: State>(
+    key: Key<S::Id>,
+    until: Option<DateTime<Utc>>,
+    state: &S,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.all().await.unwrap();
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo = repository
+        .snooze(id, until)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
     Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn update_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    ValidateJson(payload): ValidateJson<UpdateTodo>,
-    Extension(repository): Extension<Arc<T>>,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct SetParent<I = i32> {
+    parent_id: Option<I>,
+}
+
+pub async fn set_parent<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateJson(payload): ValidateJson<SetParent<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
 ) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    if let Some(parent_id) = payload.parent_id {
+        require_visible_parent(&state, &principal, parent_id).await?;
+        // 自分自身や子孫を親にすると循環する
+        let descendants = repository
+            .descendants(id)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        if parent_id == id || descendants.contains(&parent_id) {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
     let todo = repository
-        .update(id, payload)
+        .set_parent(id, payload.parent_id)
         .await
         .or(Err(StatusCode::NOT_FOUND))?;
     Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn delete_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
+// 存在しない親と同じく、見えない親の下にも置けない
+async fn require_visible_parent<S: State>(
+    state: &S,
+    principal: &Principal,
+    parent_id: S::Id,
+) -> Result<(), StatusCode> {
+    let repository = state.todos();
+    let parent = repository
+        .find(parent_id)
+        .await
+        .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
+    let visibility = visibility(state, principal).await;
+    match todo_access(repository, visibility, &parent).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(StatusCode::UNPROCESSABLE_ENTITY),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn todo_children<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let todos = repository
+        .children(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let todos = visible_todos(&state, visibility(&state, &principal).await, todos)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+// POST /todos/:id/duplicate のクエリパラメータ
+// offset_daysを指定するとリマインダーの日時をその日数だけずらす
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct DuplicateQuery {
+    #[validate(range(min = -3650, max = 3650, message = "validation.offset_days"))]
+    offset_days: Option<i64>,
+}
+
+pub async fn duplicate_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    ValidateQuery(query): ValidateQuery<DuplicateQuery>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+    transactions: Option<Extension<Arc<dyn Transactional<S::Id>>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = state
+        .todos()
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    state
+        .todos()
+        .find(id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let offset = Duration::days(query.offset_days.unwrap_or_default());
+    let owner_id = owner_id(&state, &principal).await;
+    let visibility = visibility(&state, &principal).await;
+    // 子孫の複製に失敗したら、途中まで作ったものも取り消す
+    let todo = match transactions {
+        Some(Extension(transactions)) => {
+            let unit = transactions
+                .begin()
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            let todo = duplicate_tree(unit.todos(), id, offset, owner_id, visibility)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            unit.commit()
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            todo
+        }
+        None => duplicate_tree(state.todos(), id, offset, owner_id, visibility)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+    };
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+// 複製は元のtodoと同じ親の下に作り、子孫は複製した親の下に作り直す
+// 複製すると所有者になるので、見えない子孫は複製しない
+async fn duplicate_tree<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    id: I,
+    offset: Duration,
+    owner_id: Option<i32>,
+    visibility: Visibility,
+) -> anyhow::Result<TodoEntity<I>> {
+    let source = repository.find(id).await?;
+    let todo = duplicate_one(repository, &source, source.parent_id, offset, owner_id).await?;
+    let mut pending = vec![(source.id, todo.id)];
+    while let Some((from, to)) = pending.pop() {
+        for child in repository.children(from).await? {
+            if todo_access(repository, visibility, &child).await?.is_none() {
+                continue;
+            }
+            let copy = duplicate_one(repository, &child, Some(to), offset, owner_id).await?;
+            pending.push((child.id, copy.id));
+        }
+    }
+    Ok(todo)
+}
+
+async fn duplicate_one<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    source: &TodoEntity<I>,
+    parent_id: Option<I>,
+    offset: Duration,
+    owner_id: Option<i32>,
+) -> anyhow::Result<TodoEntity<I>> {
+    let todo = repository
+        .create(CreateTodo::copy_of(source, parent_id).owned_by(owner_id))
+        .await?;
+    match source.remind_at {
+        Some(remind_at) => {
+            repository
+                .set_reminder(todo.id, Some(remind_at + offset))
+                .await
+        }
+        None => Ok(todo),
+    }
+}
+
+pub async fn todo_dependencies<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let dependencies = repository
+        .dependencies(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(dependencies)))
+}
+
+// PUT /todos/:id/blocks/:blocked_id
+// ブロックされる側のtodoも書き込めなければならない
+pub async fn block_todo<S: State>(
+    ValidatePath((key, blocked_key)): ValidatePath<KeyPair<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<StatusCode, ApiError> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let blocked_id = repository
+        .resolve(blocked_key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let blocked = repository
+        .find(blocked_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    require_todo_access(&state, &principal, &blocked, Access::Write).await?;
+    // ブロックされる側がすでにこちらをブロックしていると循環する
+    let dependents = repository
+        .dependents(blocked_id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    if id == blocked_id || dependents.contains(&id) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into());
+    }
     repository
-        .delete(id)
+        .add_dependency(id, blocked_id)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn root() -> &'static str {
-    "Hello, World!"
+pub async fn unblock_todo<S: State>(
+    ValidatePath((key, blocked_key)): ValidatePath<KeyPair<S::Id>>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<StatusCode, ApiError> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let blocked_id = repository
+        .resolve(blocked_key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let blocked = repository
+        .find(blocked_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    require_todo_access(&state, &principal, &blocked, Access::Write).await?;
+    repository
+        .remove_dependency(id, blocked_id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-// This handler is flaky and will return a 500 Internal Server Error 50% of the time.
-pub async fn flaky() -> impl IntoResponse {
-    let delay = rand::thread_rng().gen_range(1..=7);
-    sleep(Duration::from_secs(delay)).await;
+// 完了日時で集計する期間。fromは含み、toは含まない
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_completed_range"))]
+pub struct CycleTimeQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
 
-    if rand::thread_rng().gen_bool(0.5) {
-        StatusCode::INTERNAL_SERVER_ERROR
-    } else {
-        StatusCode::OK
+fn validate_completed_range(query: &CycleTimeQuery) -> Result<(), ValidationError> {
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            let mut error = ValidationError::new("date_range");
+            error.message = Some("validation.date_range".into());
+            return Err(error);
+        }
     }
+    Ok(())
+}
+
+pub async fn todo_cycle_time<S: State>(
+    ValidateQuery(query): ValidateQuery<CycleTimeQuery>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.todos();
+    let cycle_time = repository
+        .cycle_time(CompletedRange {
+            from: query.from,
+            to: query.to,
+        })
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(cycle_time)))
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TodoStats {
+    // 見えるtodoのうち、スヌーズ中のものの数
+    snoozed: i64,
+}
+
+pub async fn todo_stats<S: State>(
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let filter = TodoFilter {
+        snoozed: Some(true),
+        visible_to: visibility(&state, &principal).await,
+        ..Default::default()
+    };
+    let snoozed = state
+        .todos()
+        .count(filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(TodoStats { snoozed })))
+}
+
+const DEFAULT_SUGGEST_LIMIT: i64 = 10;
+
+// GET /todos/suggest のクエリパラメータ
+#[derive(Debug, Deserialize, Validate)]
+pub struct SuggestQuery {
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    q: String,
+    #[validate(range(min = 1, max = 20, message = "validation.suggest_limit"))]
+    limit: Option<i64>,
+}
+
+// 入力中に呼ばれるので、一覧と違ってidと本文だけを件数を絞って返す
+pub async fn suggest_todos<S: State>(
+    ValidateQuery(query): ValidateQuery<SuggestQuery>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let visible_to = visibility(&state, &principal).await;
+    let suggestions = state
+        .todos()
+        .suggest(
+            &query.q,
+            visible_to,
+            query.limit.unwrap_or(DEFAULT_SUGGEST_LIMIT),
+        )
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(suggestions)))
+}
+
+pub async fn undo_todo<S: State>(
+    ValidatePath(key): ValidatePath<Key<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, ApiError> {
+    let repository = state.todos();
+    let id = repository
+        .resolve(key)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let todo =
+        repository
+            .undo(id)
+            .await
+            .map_err(|e| match e.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::NothingToUndo(_)) => {
+                    ApiError::repository(StatusCode::CONFLICT, &e)
+                }
+                _ => ApiError::repository(StatusCode::NOT_FOUND, &e),
+            })?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+pub async fn root() -> &'static str {
+    "Hello, World!"
 }
@@ -0,0 +1,151 @@
+use crate::auth::{Principal, Role};
+use crate::handlers::ValidateJson;
+use crate::i18n::Locale;
+use crate::repositories::todo::TodoSort;
+use crate::repositories::users::{Preferences, UserRepository};
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+pub async fn create_user<S: State>(
+    ValidateJson(payload): ValidateJson<CreateUser>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.users();
+    let user = repository
+        .create(payload.name)
+        .await
+        .or(Err(StatusCode::CONFLICT))?;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn all_users<S: State>(
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.users();
+    let users = repository
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(users)))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+pub struct CreateUser {
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    name: String,
+}
+
+// GET /me で返すリクエスト主体の情報
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Me {
+    // ユーザーとして登録されていない主体はnull
+    pub id: Option<i32>,
+    pub name: String,
+    pub role: Role,
+    pub preferences: Preferences,
+}
+
+pub async fn me<S: State>(
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.users();
+    let (id, preferences) = match repository.find_by_name(&principal.name).await {
+        Ok(user) => {
+            let preferences = repository
+                .preferences(user.id)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            (Some(user.id), preferences)
+        }
+        Err(_) => (None, Preferences::default()),
+    };
+    Ok(Json(Me {
+        id,
+        name: principal.name,
+        role: principal.role,
+        preferences,
+    }))
+}
+
+// 設定はユーザーとして登録されている主体だけが保存できる
+pub async fn update_me<S: State>(
+    ValidateJson(payload): ValidateJson<UpdateMe>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.users();
+    let user = repository
+        .find_by_name(&principal.name)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let current = repository
+        .preferences(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let preferences = repository
+        .update_preferences(user.id, payload.preferences.apply(current))
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok(Json(Me {
+        id: Some(user.id),
+        name: principal.name,
+        role: principal.role,
+        preferences,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateMe {
+    #[serde(default)]
+    #[validate]
+    preferences: UpdatePreferences,
+}
+
+// 指定した項目だけを変え、nullを指定した項目はサーバーの既定に戻す
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatePreferences {
+    #[serde(default, deserialize_with = "crate::patch::nullable")]
+    default_sort: Option<Option<TodoSort>>,
+    #[serde(default, deserialize_with = "crate::patch::nullable")]
+    #[validate(custom = "validate_timezone")]
+    timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "crate::patch::nullable")]
+    locale: Option<Option<Locale>>,
+}
+
+impl UpdatePreferences {
+    fn apply(self, current: Preferences) -> Preferences {
+        Preferences {
+            default_sort: self.default_sort.unwrap_or(current.default_sort),
+            timezone: self.timezone.unwrap_or(current.timezone),
+            locale: self.locale.unwrap_or(current.locale),
+        }
+    }
+}
+
+// タイムゾーンのデータベースは持たないので、IANAの名前として使える文字だけかを確かめる
+fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+    let valid = (1..=64).contains(&timezone.len())
+        && timezone
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.'))
+        && timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+    if !valid {
+        let mut error = ValidationError::new("timezone");
+        error.message = Some("validation.timezone".into());
+        return Err(error);
+    }
+    Ok(())
+}
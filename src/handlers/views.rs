@@ -0,0 +1,105 @@
+use crate::auth::Principal;
+use crate::handlers::pagination::{paginate, Pagination};
+use crate::handlers::shares::visibility;
+use crate::handlers::{ValidateJson, ValidatePath, ValidateQuery};
+use crate::repositories::todo::{TodoFilter, TodoRepository};
+use crate::repositories::views::ViewRepository;
+use crate::repositories::{EntityId, Id};
+use crate::state::State;
+use axum::extract::Extension;
+use axum::http::{StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub async fn create_view<S: State>(
+    ValidateJson(payload): ValidateJson<ViewPayload<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.views();
+    let view = repository
+        .create(payload.name, payload.filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::CREATED, Json(view)))
+}
+
+pub async fn find_view<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.views();
+    let view = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(view)))
+}
+
+pub async fn all_views<S: State>(
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.views();
+    let views = repository
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(views)))
+}
+
+pub async fn update_view<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    ValidateJson(payload): ValidateJson<ViewPayload<S::Id>>,
+    Extension(state): Extension<S>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let repository = state.views();
+    let view = repository
+        .update(id, payload.name, payload.filter)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(view)))
+}
+
+pub async fn delete_view<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    Extension(state): Extension<S>,
+) -> StatusCode {
+    let repository = state.views();
+    repository
+        .delete(id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or(StatusCode::NOT_FOUND)
+}
+
+// 保存された条件でtodoを絞り込む
+pub async fn view_todos<S: State>(
+    ValidatePath(Id(id)): ValidatePath<Id>,
+    uri: Uri,
+    ValidateQuery(pagination): ValidateQuery<Pagination>,
+    Extension(state): Extension<S>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let views = state.views();
+    let repository = state.todos();
+    let view = views.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+    let filter = TodoFilter {
+        visible_to: visibility(&state, &principal).await,
+        ..view.filter.0
+    };
+    let todos = repository
+        .all(filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    paginate(&uri, pagination, todos)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields, bound(deserialize = "I: EntityId"))]
+pub struct ViewPayload<I: EntityId = i32> {
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    name: String,
+    #[serde(default)]
+    filter: TodoFilter<I>,
+}
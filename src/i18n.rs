@@ -0,0 +1,337 @@
+use axum::body::Body;
+use axum::http::header::ACCEPT_LANGUAGE;
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+// エラーメッセージの表示言語
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ja,
+}
+
+// メッセージキーと各言語の文言の対応表
+// 未登録のキーはそのまま返す
+const CATALOG: &[(&str, &str, &str)] = &[
+    ("validation.empty", "Can not be empty", "空にはできません"),
+    ("validation.too_long", "Over test length", "長すぎます"),
+    (
+        "validation.too_many_tags",
+        "Too many tags",
+        "タグが多すぎます",
+    ),
+    (
+        "validation.tag_length",
+        "Each tag must be 1 to 30 characters",
+        "タグは1文字以上30文字以下で指定してください",
+    ),
+    (
+        "validation.color",
+        "Must be a hex color like #1e90ff",
+        "#1e90ff のような16進数の色を指定してください",
+    ),
+    (
+        "validation.icon",
+        "Must be a single emoji",
+        "絵文字1文字で指定してください",
+    ),
+    (
+        "validation.description_too_long",
+        "Over description length",
+        "説明が長すぎます",
+    ),
+    (
+        "validation.unknown_field",
+        "Unknown field",
+        "不明な項目です",
+    ),
+    (
+        "validation.positive",
+        "Must be a positive number",
+        "1以上の値を指定してください",
+    ),
+    (
+        "validation.page",
+        "page must be between 1 and 1000000",
+        "pageは1から1000000の間で指定してください",
+    ),
+    (
+        "validation.invalid_path",
+        "Invalid path parameter",
+        "パスパラメータが正しくありません",
+    ),
+    (
+        "validation.invalid_query",
+        "Invalid query parameter",
+        "クエリパラメータが正しくありません",
+    ),
+    (
+        "validation.date_range",
+        "from must be earlier than to",
+        "fromはtoより前の日時を指定してください",
+    ),
+    (
+        "validation.timezone",
+        "Specify an IANA time zone name such as Asia/Tokyo",
+        "Asia/Tokyo のようなIANAのタイムゾーン名を指定してください",
+    ),
+    (
+        "validation.report_span",
+        "The report period must be at most 366 days",
+        "レポートの期間は366日以内で指定してください",
+    ),
+    (
+        "validation.unresolved_placeholder",
+        "Some placeholders are not substituted",
+        "置き換えられていないプレースホルダーがあります",
+    ),
+    (
+        "validation.backup_name",
+        "Not a backup file name",
+        "バックアップのファイル名ではありません",
+    ),
+    (
+        "validation.suggest_limit",
+        "limit must be between 1 and 20",
+        "limitは1から20の間で指定してください",
+    ),
+    (
+        "validation.offset_days",
+        "offset_days must be between -3650 and 3650",
+        "offset_daysは-3650から3650の間で指定してください",
+    ),
+    (
+        "validation.assign_batch",
+        "Send between 1 and 1000 todo ids at a time",
+        "一度に送れるtodoのidは1件から1000件までです",
+    ),
+    (
+        "validation.sync_batch",
+        "Send between 1 and 100 todos at a time",
+        "一度に送れるtodoは1件から100件までです",
+    ),
+    (
+        "validation.sync_token",
+        "Invalid sync token",
+        "同期トークンが正しくありません",
+    ),
+    (
+        "validation.estimate_minutes",
+        "estimate_minutes must be between 1 and 10080",
+        "estimate_minutesは1から10080の間で指定してください",
+    ),
+    (
+        "validation.snooze_until",
+        "until must be in the future",
+        "untilには現在より後の日時を指定してください",
+    ),
+    (
+        "validation.github_repository",
+        "Must be a repository like owner/name",
+        "owner/name の形式のリポジトリを指定してください",
+    ),
+    (
+        "validation.json_parse",
+        "Json parse error",
+        "JSONの解析に失敗しました",
+    ),
+    (
+        "validation.form_parse",
+        "Form parse error",
+        "フォームの解析に失敗しました",
+    ),
+    (
+        "auth.unauthorized",
+        "Missing or invalid API key",
+        "APIキーがないか、正しくありません",
+    ),
+    (
+        "auth.forbidden",
+        "Not allowed to access this resource",
+        "このリソースにアクセスする権限がありません",
+    ),
+    (
+        "repository.unexpected",
+        "Unexpected error",
+        "予期しないエラーが発生しました",
+    ),
+    ("repository.not_found", "Not found", "見つかりません"),
+    (
+        "repository.duplicate",
+        "Duplicate data",
+        "データが重複しています",
+    ),
+    (
+        "repository.nothing_to_undo",
+        "Nothing to undo",
+        "元に戻せる変更がありません",
+    ),
+    (
+        "repository.unavailable",
+        "Temporarily unavailable",
+        "一時的に利用できません",
+    ),
+    (
+        "repository.too_many_labels",
+        "Too many labels on a todo",
+        "todoに付けられるラベルの数を超えています",
+    ),
+    (
+        "quota.exceeded",
+        "Daily request quota exceeded",
+        "1日のリクエスト数の上限を超えました",
+    ),
+    (
+        "integration.replayed",
+        "This request has already been received",
+        "このリクエストは既に受け付けています",
+    ),
+    (
+        "integration.unknown_sender",
+        "The sender is not linked to a user",
+        "送信元がユーザーに対応付けられていません",
+    ),
+    (
+        "integration.invalid_todo",
+        "Cannot create a todo from this request",
+        "このリクエストからはtodoを作れません",
+    ),
+    (
+        "demo.disabled",
+        "Not available in the demo",
+        "デモでは利用できません",
+    ),
+    (
+        "demo.rate_limited",
+        "Too many requests, try again later",
+        "リクエストが多すぎます。しばらくしてから再度お試しください",
+    ),
+    (
+        "backup.unsupported_version",
+        "Backup was made by an unsupported version",
+        "対応していない版のバックアップです",
+    ),
+    (
+        "error.bad_request",
+        "Bad request",
+        "リクエストが正しくありません",
+    ),
+    (
+        "error.unprocessable",
+        "Unprocessable request",
+        "リクエストを処理できません",
+    ),
+    (
+        "error.route_not_found",
+        "No such endpoint",
+        "該当するエンドポイントがありません",
+    ),
+    (
+        "error.unsupported_media_type",
+        "Content-Type must be application/json",
+        "Content-Typeはapplication/jsonにしてください",
+    ),
+    (
+        "error.unsupported_payload_type",
+        "Content-Type must be application/json or application/x-www-form-urlencoded",
+        "Content-Typeはapplication/jsonかapplication/x-www-form-urlencodedにしてください",
+    ),
+    (
+        "error.method_not_allowed",
+        "Method not allowed for this endpoint",
+        "このエンドポイントでは使えないメソッドです",
+    ),
+];
+
+impl Locale {
+    // Accept-Languageの中から品質値が最も高い対応言語を選ぶ
+    // 対応する言語がなければ英語にする
+    pub fn negotiate(accept_language: Option<&str>) -> Self {
+        let mut candidates: Vec<(f32, Locale)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next()?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                let primary = tag.split('-').next()?.to_ascii_lowercase();
+                let locale = match primary.as_str() {
+                    "en" => Locale::En,
+                    "ja" => Locale::Ja,
+                    _ => return None,
+                };
+                (quality > 0.0).then_some((quality, locale))
+            })
+            .collect();
+        // 同じ品質値の場合は先に書かれたものを優先する
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates
+            .first()
+            .map(|(_, locale)| *locale)
+            .unwrap_or_default()
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Locale::negotiate(
+            headers
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    // リクエスト処理中のタスクであればその言語を、そうでなければ英語を返す
+    pub fn current() -> Self {
+        CURRENT_LOCALE
+            .try_with(|locale| *locale)
+            .unwrap_or_default()
+    }
+
+    pub fn translate<'a>(&self, key: &'a str) -> &'a str {
+        CATALOG
+            .iter()
+            .find(|(k, _, _)| *k == key)
+            .map_or(key, |(_, en, ja)| match self {
+                Locale::En => en,
+                Locale::Ja => ja,
+            })
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_LOCALE: Locale;
+}
+
+// Accept-Languageからエラーメッセージの言語を決め、リクエストの処理中に参照できるようにする
+pub async fn negotiate_locale(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let locale = Locale::from_headers(req.headers());
+    CURRENT_LOCALE.scope(locale, next.run(req)).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_negotiate_locale_by_quality() {
+        assert_eq!(Locale::negotiate(None), Locale::En);
+        assert_eq!(Locale::negotiate(Some("ja")), Locale::Ja);
+        assert_eq!(Locale::negotiate(Some("ja-JP,en;q=0.5")), Locale::Ja);
+        assert_eq!(Locale::negotiate(Some("en;q=0.5, ja;q=0.8")), Locale::Ja);
+        assert_eq!(Locale::negotiate(Some("fr, en-US;q=0.9")), Locale::En);
+        assert_eq!(Locale::negotiate(Some("ja;q=0, fr")), Locale::En);
+        assert_eq!(Locale::negotiate(Some("de")), Locale::En);
+    }
+
+    #[test]
+    fn should_translate_known_keys_only() {
+        assert_eq!(Locale::En.translate("validation.empty"), "Can not be empty");
+        assert_eq!(Locale::Ja.translate("validation.empty"), "空にはできません");
+        assert_eq!(Locale::Ja.translate("range"), "range");
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// iCalendar(RFC 5545)の書き出し
+// カレンダーアプリから購読できるよう、todoを予定(VEVENT)として出力する
+// CalDAVのクライアントにはtodo(VTODO)として出力する
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+const PRODUCT_ID: &str = "-//rust-simple-api//todos//EN";
+// 1行の上限。超える場合は折り返す
+const MAX_LINE_OCTETS: usize = 75;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarEvent {
+    pub uid: Uuid,
+    pub starts_at: DateTime<Utc>,
+    pub summary: String,
+    pub categories: Vec<String>,
+    pub cancelled: bool,
+}
+
+impl CalendarEvent {
+    pub fn to_ics(&self, stamped_at: DateTime<Utc>) -> String {
+        let mut lines = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@rust-simple-api", self.uid),
+            format!("DTSTAMP:{}", format_datetime(stamped_at)),
+            format!("DTSTART:{}", format_datetime(self.starts_at)),
+            format!("SUMMARY:{}", escape(&self.summary)),
+        ];
+        if !self.categories.is_empty() {
+            let categories: Vec<String> = self.categories.iter().map(|c| escape(c)).collect();
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        let status = if self.cancelled {
+            "CANCELLED"
+        } else {
+            "CONFIRMED"
+        };
+        lines.push(format!("STATUS:{}", status));
+        lines.push("END:VEVENT".to_string());
+        lines.iter().map(|line| fold(line)).collect()
+    }
+}
+
+// VTODOの状態。未着手はNEEDS-ACTION、作業中はIN-PROCESSにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoState {
+    NeedsAction,
+    InProcess,
+    Completed,
+    Cancelled,
+}
+
+impl TodoState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TodoState::NeedsAction => "NEEDS-ACTION",
+            TodoState::InProcess => "IN-PROCESS",
+            TodoState::Completed => "COMPLETED",
+            TodoState::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarTodo {
+    pub uid: Uuid,
+    pub summary: String,
+    pub description: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub state: TodoState,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub categories: Vec<String>,
+    pub modified_at: DateTime<Utc>,
+}
+
+impl CalendarTodo {
+    pub fn to_ics(&self, stamped_at: DateTime<Utc>) -> String {
+        let mut lines = vec![
+            "BEGIN:VTODO".to_string(),
+            format!("UID:{}@rust-simple-api", self.uid),
+            format!("DTSTAMP:{}", format_datetime(stamped_at)),
+            format!("LAST-MODIFIED:{}", format_datetime(self.modified_at)),
+            format!("SUMMARY:{}", escape(&self.summary)),
+        ];
+        if let Some(description) = &self.description {
+            lines.push(format!("DESCRIPTION:{}", escape(description)));
+        }
+        if let Some(due_at) = self.due_at {
+            lines.push(format!("DUE:{}", format_datetime(due_at)));
+        }
+        if !self.categories.is_empty() {
+            let categories: Vec<String> = self.categories.iter().map(|c| escape(c)).collect();
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        lines.push(format!("STATUS:{}", self.state.as_str()));
+        if let Some(completed_at) = self.completed_at {
+            lines.push(format!("COMPLETED:{}", format_datetime(completed_at)));
+        }
+        lines.push("END:VTODO".to_string());
+        lines.iter().map(|line| fold(line)).collect()
+    }
+}
+
+// 予定の前に置く部分
+pub fn begin_calendar(name: &str) -> String {
+    [
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODUCT_ID),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ]
+    .iter()
+    .map(|line| fold(line))
+    .collect()
+}
+
+pub fn end_calendar() -> String {
+    fold("END:VCALENDAR")
+}
+
+fn format_datetime(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// 区切りに使う文字と改行をエスケープする
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 75オクテットごとに折り返し、続きの行は空白で始める
+// マルチバイト文字の途中では切らない
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn should_write_event() {
+        let event = CalendarEvent {
+            uid: Uuid::from_u128(1),
+            starts_at: Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap(),
+            summary: "buy milk, eggs; bread".to_string(),
+            categories: vec!["home".to_string(), "shopping".to_string()],
+            cancelled: false,
+        };
+        let stamped_at = Utc.with_ymd_and_hms(2024, 4, 30, 0, 0, 0).unwrap();
+        assert_eq!(
+            event.to_ics(stamped_at),
+            "BEGIN:VEVENT\r\n\
+             UID:00000000-0000-0000-0000-000000000001@rust-simple-api\r\n\
+             DTSTAMP:20240430T000000Z\r\n\
+             DTSTART:20240501T093000Z\r\n\
+             SUMMARY:buy milk\\, eggs\\; bread\r\n\
+             CATEGORIES:home,shopping\r\n\
+             STATUS:CONFIRMED\r\n\
+             END:VEVENT\r\n"
+        );
+    }
+
+    #[test]
+    fn should_write_todo() {
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let todo = CalendarTodo {
+            uid: Uuid::from_u128(1),
+            summary: "buy milk".to_string(),
+            description: Some("2 bottles\nlow fat".to_string()),
+            due_at: Some(at),
+            state: TodoState::Completed,
+            completed_at: Some(at),
+            categories: vec!["home".to_string()],
+            modified_at: at,
+        };
+        assert_eq!(
+            todo.to_ics(at),
+            "BEGIN:VTODO\r\n\
+             UID:00000000-0000-0000-0000-000000000001@rust-simple-api\r\n\
+             DTSTAMP:20240501T093000Z\r\n\
+             LAST-MODIFIED:20240501T093000Z\r\n\
+             SUMMARY:buy milk\r\n\
+             DESCRIPTION:2 bottles\\nlow fat\r\n\
+             DUE:20240501T093000Z\r\n\
+             CATEGORIES:home\r\n\
+             STATUS:COMPLETED\r\n\
+             COMPLETED:20240501T093000Z\r\n\
+             END:VTODO\r\n"
+        );
+    }
+
+    #[test]
+    fn should_fold_long_lines_on_char_boundaries() {
+        let line = format!("SUMMARY:{}", "あ".repeat(30));
+        let folded = fold(&line);
+        assert!(folded
+            .split("\r\n")
+            .all(|line| line.len() <= MAX_LINE_OCTETS));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+        assert_eq!(escape("a\\b\nc"), "a\\\\b\\nc");
+    }
+}
@@ -0,0 +1,4809 @@
+pub mod atom;
+pub mod auth;
+pub mod backup;
+pub mod cache;
+pub mod cache_control;
+pub mod casing;
+pub mod chaos;
+pub mod circuit_breaker;
+pub mod cli;
+pub mod concurrency;
+pub mod config;
+pub mod dav;
+pub mod demo;
+pub mod digest;
+pub mod email;
+pub mod envelope;
+pub mod events;
+pub mod form;
+pub mod github;
+pub mod handlers;
+pub mod i18n;
+pub mod ics;
+pub mod logging;
+pub mod metrics;
+pub mod notifier;
+pub mod patch;
+pub mod pdf;
+pub mod quota;
+#[cfg(feature = "redis")]
+pub mod redis_cache;
+pub mod replay;
+#[cfg(feature = "sentry")]
+pub mod reporting;
+pub mod repositories;
+pub mod scheduler;
+pub mod security_headers;
+pub mod slack;
+pub mod startup;
+pub mod state;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod timeout;
+pub mod trace_context;
+pub mod trim;
+pub mod unit_of_work;
+
+use crate::auth::{require_role, ApiKeys};
+use crate::backup::{all_backups, create_backup, restore_backup};
+use crate::cache_control::set_cache_control;
+use crate::casing::{convert_json_case, X_JSON_CASE};
+use crate::chaos::{all_faults, clear_faults, flaky, inject_faults, replace_faults};
+use crate::circuit_breaker::reject_while_open;
+use crate::concurrency::limit_concurrency;
+use crate::config::{find_config, replace_config, set_log_level};
+use crate::digest::run_digest;
+use crate::email::{receive_email, EMAIL_INBOUND_PATH};
+use crate::envelope::{wrap_envelope, X_ENVELOPE};
+use crate::events::{EventBus, Publishing};
+use crate::github::{link_github, receive_github_webhook, GITHUB_WEBHOOK_PATH};
+use crate::handlers::audit::all_audit_logs;
+use crate::handlers::calendar::{todo_calendar, CALENDAR_PATH};
+use crate::handlers::dav::{
+    dav_home, dav_todo, dav_todos, well_known_caldav, DAV_PREFIX, DAV_TODOS_PATH,
+    WELL_KNOWN_CALDAV_PATH,
+};
+use crate::handlers::events::stream_events;
+use crate::handlers::export::{export_pdf, EXPORT_PDF_PATH};
+use crate::handlers::feed::{todo_feed, FEED_PATH};
+use crate::handlers::label::{
+    all_label, assign_label, create_label, delete_label, label_todos, merge_label, unassign_label,
+    update_label,
+};
+use crate::handlers::projects::{
+    all_projects, create_project, delete_project, find_project, project_todos, update_project,
+};
+use crate::handlers::reminder::{all_reminders, cancel_reminder, set_reminder};
+use crate::handlers::reports::burndown_report;
+use crate::handlers::share_links::{
+    create_share_link, revoke_share_link, shared_todo, todo_share_links,
+};
+use crate::handlers::shares::{enforce_todo_access, share_todo, todo_shares, unshare_todo};
+use crate::handlers::sync::{sync_todos, todo_changes};
+use crate::handlers::templates::{
+    all_templates, create_template, create_todo_from_template, delete_template, find_template,
+    update_template,
+};
+use crate::handlers::todo::{
+    all_todos, assign_todo, block_todo, change_todo_status, count_todos, create_todo, delete_todo,
+    duplicate_todo, find_todo, move_todo, pin_todo, root, set_parent, snooze_todo, suggest_todos,
+    todo_children, todo_cycle_time, todo_dependencies, todo_history, todo_stats, todos_options,
+    unblock_todo, undo_todo, unpin_todo, update_todo, wake_todo,
+};
+use crate::handlers::users::{all_users, create_user, me, update_me};
+use crate::handlers::views::{
+    all_views, create_view, delete_view, find_view, update_view, view_todos,
+};
+use crate::handlers::{health, method_not_allowed, route_not_found, HEALTH_PATH, X_TOTAL_COUNT};
+use crate::i18n::negotiate_locale;
+use crate::logging::{log_requests, track_route, X_REQUEST_ID};
+use crate::metrics::metrics;
+use crate::quota::{
+    enforce_quota, my_usage, USAGE_PATH, X_RATELIMIT_LIMIT, X_RATELIMIT_REMAINING,
+    X_RATELIMIT_RESET,
+};
+use crate::repositories::audit::{AuditLogRepository, Audited};
+use crate::repositories::labels::LabelRepository;
+use crate::repositories::projects::ProjectRepository;
+use crate::repositories::share_links::ShareLinkRepository;
+use crate::repositories::templates::TemplateRepository;
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::users::UserRepository;
+use crate::repositories::views::ViewRepository;
+use crate::repositories::EntityId;
+use crate::slack::{slack_command, SLACK_COMMAND_PATH};
+use crate::state::{AppState, State};
+use crate::timeout::enforce_timeout;
+use axum::handler::Handler;
+use axum::middleware::from_fn;
+use axum::routing::{delete, patch, put};
+use axum::{extract::Extension, routing::get, routing::post, Router};
+use hyper::header::{HeaderName, AUTHORIZATION, CONTENT_TYPE, LINK};
+use std::sync::Arc;
+use tower_http::cors::{Any, CorsLayer, Origin};
+
+// リポジトリを差し替えてアプリケーションを組み立てる
+// 他のクレートやtests/からもプロセス内で動かせるよう公開する
+// リポジトリはAppStateの中でトレイトオブジェクトになるので、ルーターは1つの型だけで組み立てる
+#[allow(clippy::too_many_arguments)]
+pub fn create_app<I: EntityId>(
+    todo_repository: impl TodoRepository<I>,
+    label_repository: impl LabelRepository<I>,
+    audit_log_repository: impl AuditLogRepository<I> + Clone,
+    user_repository: impl UserRepository,
+    project_repository: impl ProjectRepository,
+    view_repository: impl ViewRepository<I>,
+    template_repository: impl TemplateRepository<I>,
+    share_link_repository: impl ShareLinkRepository<I>,
+    events: EventBus<I>,
+    api_keys: ApiKeys,
+) -> Router {
+    let state = create_state(
+        todo_repository,
+        label_repository,
+        audit_log_repository,
+        user_repository,
+        project_repository,
+        view_repository,
+        template_repository,
+        share_link_repository,
+        events,
+    );
+    create_router(state, api_keys, Surface::All)
+}
+
+// separate_adminを指定すると、公開用と運用用に分けて組み立てる
+// 運用用のエンドポイント(/metrics・/health・/admin/*)は公開用には含めず、
+// 内部のアドレスで待ち受ける2つ目のルーターだけで公開する。状態は両方で共有する
+#[allow(clippy::too_many_arguments)]
+pub fn create_apps<I: EntityId>(
+    todo_repository: impl TodoRepository<I>,
+    label_repository: impl LabelRepository<I>,
+    audit_log_repository: impl AuditLogRepository<I> + Clone,
+    user_repository: impl UserRepository,
+    project_repository: impl ProjectRepository,
+    view_repository: impl ViewRepository<I>,
+    template_repository: impl TemplateRepository<I>,
+    share_link_repository: impl ShareLinkRepository<I>,
+    events: EventBus<I>,
+    api_keys: ApiKeys,
+    separate_admin: bool,
+) -> (Router, Option<Router>) {
+    let state = create_state(
+        todo_repository,
+        label_repository,
+        audit_log_repository,
+        user_repository,
+        project_repository,
+        view_repository,
+        template_repository,
+        share_link_repository,
+        events,
+    );
+    if !separate_admin {
+        return (create_router(state, api_keys, Surface::All), None);
+    }
+    (
+        create_router(state.clone(), api_keys.clone(), Surface::Public),
+        Some(create_router(state, api_keys, Surface::Admin)),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_state<I: EntityId>(
+    todo_repository: impl TodoRepository<I>,
+    label_repository: impl LabelRepository<I>,
+    audit_log_repository: impl AuditLogRepository<I> + Clone,
+    user_repository: impl UserRepository,
+    project_repository: impl ProjectRepository,
+    view_repository: impl ViewRepository<I>,
+    template_repository: impl TemplateRepository<I>,
+    share_link_repository: impl ShareLinkRepository<I>,
+    events: EventBus<I>,
+) -> AppState<I> {
+    // 更新系の操作は監査ログに記録する
+    // 取り消しで直前の記録を読むため、監査ログはイベントを待たずに書き込む
+    let todo_repository = Audited::new(todo_repository, audit_log_repository.clone());
+    let label_repository = Audited::new(label_repository, audit_log_repository.clone());
+    // 通知やキャッシュの破棄などはイベントの購読側で行う
+    let todo_repository = Publishing::new(todo_repository, events.clone());
+    let label_repository = Publishing::new(label_repository, events.clone());
+    AppState::new(
+        todo_repository,
+        label_repository,
+        audit_log_repository,
+        user_repository,
+        project_repository,
+        view_repository,
+        template_repository,
+        share_link_repository,
+        events,
+    )
+}
+
+// ルーターに含めるエンドポイント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Surface {
+    All,
+    Public,
+    Admin,
+}
+
+impl Surface {
+    fn public(self) -> bool {
+        self != Surface::Admin
+    }
+
+    fn admin(self) -> bool {
+        self != Surface::Public
+    }
+}
+
+fn create_router<S: State>(state: S, api_keys: ApiKeys, surface: Surface) -> Router {
+    let mut state_routes = Router::new();
+    if surface.public() {
+        state_routes = state_routes.merge(
+            Router::new()
+                .route(
+                    "/todos",
+                    post(create_todo::<S>)
+                        .get(all_todos::<S>)
+                        .options(todos_options),
+                )
+                .route(
+                    "/todos/:id",
+                    get(find_todo::<S>)
+                        .delete(delete_todo::<S>)
+                        .patch(update_todo::<S>),
+                )
+                .route("/todos/stats", get(todo_stats::<S>))
+                .route("/todos/stats/cycle-time", get(todo_cycle_time::<S>))
+                .route("/todos/suggest", get(suggest_todos::<S>))
+                .route("/todos/count", get(count_todos::<S>))
+                .route("/todos/sync", put(sync_todos::<S>))
+                .route("/todos/changes", get(todo_changes::<S>))
+                .route(CALENDAR_PATH, get(todo_calendar::<S>))
+                .route(
+                    WELL_KNOWN_CALDAV_PATH,
+                    axum::routing::any(well_known_caldav),
+                )
+                .route(DAV_PREFIX, axum::routing::any(dav_home::<S>))
+                .route("/dav/todos", axum::routing::any(dav_todos::<S>))
+                .route(DAV_TODOS_PATH, axum::routing::any(dav_todos::<S>))
+                .route("/dav/todos/:name", axum::routing::any(dav_todo::<S>))
+                .route(FEED_PATH, get(todo_feed::<S>))
+                .route(EXPORT_PDF_PATH, get(export_pdf::<S>))
+                .route("/todos/:id/status", patch(change_todo_status::<S>))
+                .route("/todos/:id/history", get(todo_history::<S>))
+                .route("/todos/:id/undo", post(undo_todo::<S>))
+                .route(
+                    "/todos/:id/reminder",
+                    put(set_reminder::<S>).delete(cancel_reminder::<S>),
+                )
+                .route("/reminders", get(all_reminders::<S>))
+                .route("/todos/:id/assign", patch(assign_todo::<S>))
+                .route("/users", post(create_user::<S>).get(all_users::<S>))
+                .route("/me", get(me::<S>).patch(update_me::<S>))
+                .route("/todos/:id/project", patch(move_todo::<S>))
+                .route("/todos/:id/parent", patch(set_parent::<S>))
+                .route("/todos/:id/pin", patch(pin_todo::<S>))
+                .route("/todos/:id/unpin", patch(unpin_todo::<S>))
+                .route(
+                    "/todos/:id/snooze",
+                    post(snooze_todo::<S>).delete(wake_todo::<S>),
+                )
+                .route("/todos/:id/children", get(todo_children::<S>))
+                .route("/todos/:id/duplicate", post(duplicate_todo::<S>))
+                .route("/todos/:id/dependencies", get(todo_dependencies::<S>))
+                .route("/todos/:id/link-github", post(link_github::<S>))
+                .route(GITHUB_WEBHOOK_PATH, post(receive_github_webhook::<S>))
+                .route(SLACK_COMMAND_PATH, post(slack_command::<S>))
+                .route(EMAIL_INBOUND_PATH, post(receive_email::<S>))
+                .route("/todos/:id/share", post(share_todo::<S>))
+                .route("/todos/:id/share/:user_id", delete(unshare_todo::<S>))
+                .route("/todos/:id/shares", get(todo_shares::<S>))
+                .route("/todos/:id/share-link", post(create_share_link::<S>))
+                .route(
+                    "/todos/:id/share-link/:token",
+                    delete(revoke_share_link::<S>),
+                )
+                .route("/todos/:id/share-links", get(todo_share_links::<S>))
+                .route("/shared/:token", get(shared_todo::<S>))
+                .route(
+                    "/todos/:id/blocks/:blocked_id",
+                    put(block_todo::<S>).delete(unblock_todo::<S>),
+                )
+                .route(
+                    "/projects",
+                    post(create_project::<S>).get(all_projects::<S>),
+                )
+                .route(
+                    "/projects/:id",
+                    get(find_project::<S>)
+                        .patch(update_project::<S>)
+                        .delete(delete_project::<S>),
+                )
+                .route("/projects/:id/todos", get(project_todos::<S>))
+                .route("/views", post(create_view::<S>).get(all_views::<S>))
+                .route(
+                    "/views/:id",
+                    get(find_view::<S>)
+                        .patch(update_view::<S>)
+                        .delete(delete_view::<S>),
+                )
+                .route("/views/:id/todos", get(view_todos::<S>))
+                .route(
+                    "/templates",
+                    post(create_template::<S>).get(all_templates::<S>),
+                )
+                .route(
+                    "/templates/:id",
+                    get(find_template::<S>)
+                        .patch(update_template::<S>)
+                        .delete(delete_template::<S>),
+                )
+                .route(
+                    "/todos/from-template/:id",
+                    post(create_todo_from_template::<S>),
+                )
+                .route("/labels", post(create_label::<S>).get(all_label::<S>))
+                .route(
+                    "/labels/:id",
+                    delete(delete_label::<S>).patch(update_label::<S>),
+                )
+                .route("/labels/:id/todos", get(label_todos::<S>))
+                .route("/labels/:id/merge-into/:target_id", post(merge_label::<S>))
+                .route("/labels/:id/assign", post(assign_label::<S>))
+                .route("/labels/:id/unassign", post(unassign_label::<S>))
+                .route("/audit-logs", get(all_audit_logs::<S>))
+                .route("/reports/burndown", get(burndown_report::<S>))
+                .route("/events", get(stream_events::<S>)),
+        );
+    }
+    if surface.admin() {
+        state_routes = state_routes.merge(
+            Router::new()
+                .route("/admin/backup", post(create_backup))
+                .route("/admin/backups", get(all_backups))
+                .route("/admin/restore", post(restore_backup::<S>))
+                .route("/admin/digest/run", post(run_digest::<S>)),
+        );
+    }
+    let mut router = state_routes
+        .route_layer(from_fn(enforce_todo_access::<S>))
+        .route_layer(from_fn(reject_while_open));
+    if surface.public() {
+        router = router.merge(
+            Router::new()
+                .route("/", get(root))
+                .route("/flaky", get(flaky))
+                .route(USAGE_PATH, get(my_usage)),
+        );
+    }
+    if surface.admin() {
+        router = router.merge(
+            Router::new()
+                .route(
+                    "/admin/faults",
+                    get(all_faults).put(replace_faults).delete(clear_faults),
+                )
+                .route("/admin/config", get(find_config).put(replace_config))
+                .route("/admin/log-level", put(set_log_level))
+                .route("/metrics", get(metrics))
+                .route(HEALTH_PATH, get(health)),
+        );
+    }
+    let router = router
+        .route_layer(from_fn(limit_concurrency))
+        .route_layer(from_fn(enforce_timeout))
+        .route_layer(from_fn(set_cache_control))
+        .route_layer(from_fn(track_route))
+        .fallback(route_not_found.into_service());
+    // 5xxのレスポンスをリクエストの情報と一緒に送る
+    #[cfg(feature = "sentry")]
+    let router = router.layer(from_fn(reporting::report_errors));
+    router
+        .layer(from_fn(method_not_allowed))
+        .layer(from_fn(enforce_quota))
+        .layer(from_fn(require_role))
+        .layer(from_fn(wrap_envelope))
+        .layer(from_fn(convert_json_case))
+        .layer(from_fn(negotiate_locale))
+        .layer(Extension(Arc::new(api_keys)))
+        .layer(Extension(state))
+        .layer(from_fn(inject_faults))
+        .layer(from_fn(log_requests))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(Origin::exact("http://localhost:5173".parse().unwrap()))
+                .allow_methods(Any)
+                // APIキーはAuthorizationヘッダーで送る
+                .allow_headers(vec![
+                    CONTENT_TYPE,
+                    AUTHORIZATION,
+                    HeaderName::from_static(X_ENVELOPE),
+                    HeaderName::from_static(X_JSON_CASE),
+                ])
+                // ブラウザから一覧の総件数とページのリンク、リクエストIDを読めるようにする
+                .expose_headers(vec![
+                    HeaderName::from_static(X_TOTAL_COUNT),
+                    LINK,
+                    HeaderName::from_static(X_REQUEST_ID),
+                    HeaderName::from_static(X_RATELIMIT_LIMIT),
+                    HeaderName::from_static(X_RATELIMIT_REMAINING),
+                    HeaderName::from_static(X_RATELIMIT_RESET),
+                ]),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::Role;
+    use crate::backup::test_utils::SnapshotRepositoryForMemory;
+    use crate::backup::{
+        BackupList, BackupSummary, Backups, LocalStorage, Snapshot, SnapshotRepository,
+        SNAPSHOT_VERSION,
+    };
+    use crate::casing::JsonCase;
+    use crate::chaos::FaultInjector;
+    use crate::circuit_breaker::CircuitBreaker;
+    use crate::config::{Config, LogLevel};
+    use crate::digest::DigestRun;
+    use crate::email::EmailIntegration;
+    use crate::envelope::ResponseEnvelope;
+    use crate::events::{spawn_assignment_notifier, DomainEvent};
+    use crate::github::GithubIntegration;
+    use crate::handlers::reports::BurndownReport;
+    use crate::handlers::share_links::SharedTodo;
+    use crate::handlers::sync::{SyncConflict, SyncConflictReason, SyncResult};
+    use crate::handlers::todo::DedupeTodos;
+    use crate::handlers::users::Me;
+    use crate::handlers::{ApiErrorBody, ValidationErrorBody};
+    use crate::i18n::Locale;
+    use crate::logging::LogFilter;
+    use crate::metrics::Metrics;
+    use crate::notifier::test_utils::NotifierForMemory;
+    use crate::notifier::Notifier;
+    use crate::quota::{DailyQuotas, Quota, Usage};
+    use crate::repositories::audit::test_utils::AuditLogRepositoryForMemory;
+    use crate::repositories::audit::{AuditAction, AuditLog};
+    use crate::repositories::github_links::test_utils::GithubLinkRepositoryForMemory;
+    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
+    use crate::repositories::labels::{CreateLabel, Label};
+    use crate::repositories::projects::test_utils::ProjectRepositoryForMemory;
+    use crate::repositories::share_links::test_utils::ShareLinkRepositoryForMemory;
+    use crate::repositories::share_links::{ShareLink, ShareLinkRepository};
+    use crate::repositories::templates::test_utils::TemplateRepositoryForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::todo::TodoSort;
+    use crate::repositories::todo::{
+        ChangedTodo, CreateTodo, CycleTime, TodoChanges, TodoEntity, TodoRevision, TodoStatus,
+        UpdateTodo,
+    };
+    use crate::repositories::usage::test_utils::UsageRepositoryForMemory;
+    use crate::repositories::users::test_utils::UserRepositoryForMemory;
+    use crate::repositories::users::Preferences;
+    use crate::repositories::views::test_utils::ViewRepositoryForMemory;
+    use crate::slack::{SlackIntegration, SlackReply};
+    use crate::timeout::Timeouts;
+    use axum::http::header::{
+        ACCEPT_LANGUAGE, ALLOW, AUTHORIZATION, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE,
+        IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER, WWW_AUTHENTICATE,
+    };
+    use axum::http::{Method, StatusCode};
+    use axum::response::Response;
+    use axum::{body::Body, http::Request};
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn should_return_hello_world() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(body, "Hello, World!");
+    }
+
+    fn label_fixture() -> (Vec<Label>, Vec<i32>) {
+        let id = 999;
+        (vec![Label::new(id, String::from("test label"))], vec![id])
+    }
+
+    #[tokio::test]
+    async fn should_created_todo() {
+        let (labels, _label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_return_created_todo".to_string(), labels.clone());
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_return_created_todo", "labels": [999] }"#.to_string(),
+        );
+        // oneshotは擬似リクエストを送る
+        let res = create_app(
+            TodoRepositoryForMemory::new(labels),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_use_uuid_keys() {
+        let labels = LabelRepositoryForMemory::<Uuid>::with_ids();
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(labels.clone()),
+            labels,
+            AuditLogRepositoryForMemory::with_ids(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::with_ids(),
+            TemplateRepositoryForMemory::with_ids(),
+            ShareLinkRepositoryForMemory::with_ids(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "uuid" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label<Uuid> = serde_json::from_slice(&bytes).unwrap();
+
+        // idもラベルのidもUUIDで受け取り、UUIDで返す
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "uuid key", "labels": ["{}"] }}"#, label.id),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: TodoEntity<Uuid> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todo.id, todo.uuid);
+        assert_eq!(todo.labels, vec![label]);
+
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", todo.id));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        // 連番のidは受け付けない
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(json_body))
+            .unwrap()
+    }
+
+    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn res_to_todo(res: Response) -> TodoEntity {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: TodoEntity = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        todo
+    }
+
+    #[tokio::test]
+    async fn should_find_todo() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "Should_find_todo".to_string(), labels.clone());
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        let label_repository = LabelRepositoryForMemory::new();
+
+        todo_repository
+            .create(CreateTodo::new("Should_find_todo".to_string(), label_ids))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_find_todo_by_uuid() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let todo = todo_repository
+            .create(CreateTodo::new(
+                "should_find_todo_by_uuid".to_string(),
+                vec![],
+            ))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", todo.uuid));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(todo, res_to_todo(res).await);
+
+        let req =
+            build_todo_req_with_empty(Method::GET, "/todos/00000000-0000-0000-0000-00000000ffff");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/not-a-key");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_answer_not_modified() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(CreateTodo::new(
+                "should_answer_not_modified".to_string(),
+                vec![],
+            ))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        for path in ["/todos", "/todos/1"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let modified = res.headers()[LAST_MODIFIED].clone();
+
+            let req = Request::builder()
+                .uri(path)
+                .header(IF_MODIFIED_SINCE, modified)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+            let req = Request::builder()
+                .uri(path)
+                .header(IF_MODIFIED_SINCE, "Mon, 01 Jan 2001 00:00:00 GMT")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_todo() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(DedupeTodos(true)));
+        let body = r#"{ "text": "duplicated", "labels": [] }"#;
+
+        let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let created = res_to_todo(res).await;
+
+        let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert_eq!(created, res_to_todo(res).await);
+
+        // リクエスト単位で重複を許す
+        let req = build_todo_req_with_json("/todos?dedupe=false", Method::POST, body.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_get_all_todos() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_get_all_todos".to_string(), labels.clone());
+
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        let label_repository = LabelRepositoryForMemory::new();
+
+        todo_repository
+            .create(CreateTodo::new(
+                "should_get_all_todos".to_string(),
+                label_ids,
+            ))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: Vec<TodoEntity> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        assert_eq!(vec![expected], todo);
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_tag() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        for body in [
+            r#"{ "text": "tagged", "labels": [], "tags": ["home"] }"#,
+            r#"{ "text": "untagged", "labels": [] }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?tag=home");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].tags, vec!["home"]);
+
+        // タグは10個まで、それぞれ30文字まで
+        let tags: Vec<String> = (0..11).map(|i| format!("tag{}", i)).collect();
+        for tags in [tags, vec!["t".repeat(31)]] {
+            let body = serde_json::json!({ "text": "too many tags", "labels": [], "tags": tags });
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_decorate_todos_with_icon_and_color() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        for body in [
+            r##"{ "text": "red", "labels": [], "icon": "👨‍👩‍👧", "color": "#F00" }"##,
+            r#"{ "text": "plain", "labels": [] }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?color=%23ff0000");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todos = res_to_todos(res).await;
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].icon.as_deref(), Some("👨‍👩‍👧"));
+        assert_eq!(todos[0].color.as_deref(), Some("#ff0000"));
+
+        // 空文字列で外し、省略した項目は保つ
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", todos[0].id),
+            Method::PATCH,
+            r#"{ "icon": "" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.icon, None);
+        assert_eq!(todo.color.as_deref(), Some("#ff0000"));
+
+        // アイコンは1文字、色は16進数
+        for body in [
+            r#"{ "icon": "ab" }"#,
+            r#"{ "icon": " " }"#,
+            r#"{ "color": "red" }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos/1", Method::PATCH, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status(), "{}", body);
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos?color=red");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_pin_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let texts =
+            |todos: Vec<TodoEntity>| todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>();
+
+        let req = build_todo_req_with_empty(Method::PATCH, "/todos/1/pin");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(todo.pinned);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(texts(todos), vec!["first", "second"]);
+
+        let req = build_todo_req_with_empty(Method::PATCH, "/todos/1/unpin");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(!todo.pinned);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(texts(todos), vec!["second", "first"]);
+
+        let req = build_todo_req_with_empty(Method::PATCH, "/todos/99/pin");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_hide_snoozed_todos_until_woken() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let texts =
+            |todos: Vec<TodoEntity>| todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>();
+        let snoozed = || async {
+            let req = build_todo_req_with_empty(Method::GET, "/todos/stats");
+            let res = app.clone().oneshot(req).await.unwrap();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            body["snoozed"].as_i64().unwrap()
+        };
+
+        let until = chrono::Utc::now() + chrono::Duration::hours(1);
+        let req = build_todo_req_with_json(
+            "/todos/1/snooze",
+            Method::POST,
+            serde_json::json!({ "until": until }).to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.snoozed_until, Some(until));
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(texts(todos), vec!["second"]);
+        let req = build_todo_req_with_empty(Method::GET, "/todos?snoozed=true");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(texts(todos), vec!["first"]);
+        assert_eq!(snoozed().await, 1);
+
+        // 過去の時刻にはスヌーズできない
+        let req = build_todo_req_with_json(
+            "/todos/2/snooze",
+            Method::POST,
+            serde_json::json!({ "until": chrono::Utc::now() - chrono::Duration::hours(1) })
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/snooze");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.snoozed_until, None);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(texts(todos), vec!["second", "first"]);
+        assert_eq!(snoozed().await, 0);
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/99/snooze");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_duplicate_todo_with_subtasks() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("bug".to_string()))
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let parent = todo_repository
+            .create(CreateTodo::new("parent".to_string(), vec![label.id]))
+            .await
+            .expect("failed create todo");
+        let remind_at = chrono::Utc::now();
+        todo_repository
+            .set_reminder(parent.id, Some(remind_at))
+            .await
+            .expect("failed set reminder");
+        let child = todo_repository
+            .create(CreateTodo::new("child".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .set_parent(child.id, Some(parent.id))
+            .await
+            .expect("failed set parent");
+        let grandchild = todo_repository
+            .create(CreateTodo::new("grandchild".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .set_parent(grandchild.id, Some(child.id))
+            .await
+            .expect("failed set parent");
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/duplicate?offset_days=7");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let copy = res_to_todo(res).await;
+        assert_eq!(4, copy.id);
+        assert_eq!("parent", copy.text);
+        assert_eq!(vec![label], copy.labels);
+        assert_eq!(Some(remind_at + chrono::Duration::days(7)), copy.remind_at);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/4/children");
+        let children = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(1, children.len());
+        assert_eq!("child", children[0].text);
+        let path = format!("/todos/{}/children", children[0].id);
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let grandchildren = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(1, grandchildren.len());
+        assert_eq!("grandchild", grandchildren[0].text);
+
+        // 元のtodoはそのまま
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/children");
+        let children = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(
+            vec![2],
+            children.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/99/duplicate");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/duplicate?offset_days=9999");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_assign_label_to_many_todos() {
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(CreateLabel::new("bug".to_string()))
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let affected = |bytes: &[u8]| {
+            let body: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+            body["affected_todos"].as_u64().unwrap()
+        };
+        let labelled = |todos: Vec<TodoEntity>| {
+            todos
+                .into_iter()
+                .filter(|todo| !todo.labels.is_empty())
+                .map(|todo| todo.id)
+                .collect::<Vec<_>>()
+        };
+
+        let req = build_todo_req_with_json(
+            "/labels/1/assign",
+            Method::POST,
+            r#"{ "todo_ids": [1, 3, 99] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(2, affected(&bytes));
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(labelled(todos), vec![3, 1]);
+
+        let req = build_todo_req_with_json(
+            "/labels/1/unassign",
+            Method::POST,
+            r#"{ "todo_ids": [1, 2] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(1, affected(&bytes));
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(labelled(todos), vec![3]);
+
+        let req = build_todo_req_with_json(
+            "/labels/99/assign",
+            Method::POST,
+            r#"{ "todo_ids": [1] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_todo_req_with_json(
+            "/labels/1/assign",
+            Method::POST,
+            r#"{ "todo_ids": [] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_paginate_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["first", "second", "third"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req =
+            build_todo_req_with_empty(Method::GET, "/labels?page=18446744073709551615&per_page=2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?page=2&per_page=2");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()["x-total-count"], "3");
+        let link = res.headers()[LINK].to_str().unwrap().to_string();
+        assert!(link.contains("</labels?page=1&per_page=2>; rel=\"prev\""));
+        assert!(!link.contains("rel=\"next\""));
+
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(labels, vec![Label::new(3, "third".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn should_create_from_form_payload() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let home = label_repository
+            .create(CreateLabel::new("home".to_string()))
+            .await
+            .expect("failed create label");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![home.clone()]),
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let form_req = |path: &str, body: &str| {
+            Request::builder()
+                .uri(path)
+                .method(Method::POST)
+                .header(CONTENT_TYPE, mime::APPLICATION_WWW_FORM_URLENCODED.as_ref())
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let req = form_req("/labels", "name=work&color=%23F00&description=");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.name, "work");
+        assert_eq!(label.color, "#ff0000");
+        assert_eq!(label.description, None);
+
+        let req = form_req(
+            "/todos",
+            &format!("text=buy+milk&labels[]={}&tags=home&tags=shop", home.id),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(todo.text, "buy milk");
+        assert_eq!(todo.labels, vec![home]);
+        assert_eq!(todo.tags, vec!["home", "shop"]);
+
+        let req = form_req("/todos", "text=");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_search_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["bugfix", "feature", "Bug", "backend"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?q=b&sort=name&per_page=2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()["x-total-count"], "3");
+        let link = res.headers()[LINK].to_str().unwrap().to_string();
+        assert!(link.contains("</labels?q=b&sort=name&page=2&per_page=2>; rel=\"next\""));
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        let names: Vec<_> = labels.into_iter().map(|label| label.name).collect();
+        assert_eq!(names, vec!["backend", "Bug"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?sort=-id");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<_> = labels.into_iter().map(|label| label.id).collect();
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?sort=color");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_labels_with_etag() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "bug" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/labels"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[CACHE_CONTROL], "no-cache");
+        let etag = res.headers()[ETAG].clone();
+
+        // 変わっていなければ本文なしで304を返す
+        let mut req = build_todo_req_with_empty(Method::GET, "/labels");
+        req.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!(res.headers()[ETAG], etag);
+        assert_eq!(res.headers()[CACHE_CONTROL], "no-cache");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+
+        // 更新するとETagが変わる
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r##"{ "color": "#000" }"##.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let mut req = build_todo_req_with_empty(Method::GET, "/labels");
+        req.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_ne!(res.headers()[ETAG], etag);
+
+        // 設定のないルートには付けない
+        let res = app
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert!(!res.headers().contains_key(CACHE_CONTROL));
+    }
+
+    #[tokio::test]
+    async fn should_create_and_update_colored_label() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r##"{ "name": "bug", "color": "#F00", "description": "something is broken" }"##
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.color, "#ff0000");
+        assert_eq!(label.description.as_deref(), Some("something is broken"));
+
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r##"{ "color": "#1e90ff", "description": "" }"##.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.name, "bug");
+        assert_eq!(label.color, "#1e90ff");
+        assert_eq!(label.description, None);
+
+        // 16進数の色でなければ弾く
+        for color in ["red", "#12345", "#ggg"] {
+            let body = serde_json::json!({ "name": "invalid", "color": color });
+            let req = build_todo_req_with_json("/labels", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_return_existing_label_for_near_duplicate_name() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let create = |name: &str| {
+            let body = serde_json::json!({ "name": name });
+            build_todo_req_with_json("/labels", Method::POST, body.to_string())
+        };
+        let res = app.clone().oneshot(create("Work")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let work: Label = serde_json::from_slice(&bytes).unwrap();
+
+        // 大文字と小文字や空白の違いだけなら、作成せずに既存のラベルを返す
+        for name in ["work ", "  WORK", "wOrK"] {
+            let res = app.clone().oneshot(create(name)).await.unwrap();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let label: Label = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(label, work);
+        }
+
+        // 続いた空白は1つにまとめる
+        let res = app
+            .clone()
+            .oneshot(create(" Deep \t  work "))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let deep: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(deep.name, "Deep work");
+
+        let req = build_todo_req_with_json(
+            &format!("/labels/{}", deep.id),
+            Method::PATCH,
+            r#"{ "name": "work" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_too_many_labels_on_todo() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["first", "second", "third"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let todo_repository =
+            TodoRepositoryForMemory::with_labels(label_repository.clone()).with_max_labels(2);
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let assert_too_many = |res: Response| async move {
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body.key, "repository.too_many_labels");
+        };
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "too many", "labels": [1, 2, 3] }"#.to_string(),
+        );
+        assert_too_many(app.clone().oneshot(req).await.unwrap()).await;
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "enough", "labels": [1, 2] }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", todo.id),
+            Method::PATCH,
+            r#"{ "labels": [1, 2, 3] }"#.to_string(),
+        );
+        assert_too_many(app.clone().oneshot(req).await.unwrap()).await;
+        let req = build_todo_req_with_json(
+            "/labels/3/assign",
+            Method::POST,
+            format!(r#"{{ "todo_ids": [{}] }}"#, todo.id),
+        );
+        assert_too_many(app.clone().oneshot(req).await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn should_list_todos_by_label() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("bug".to_string()))
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        for (text, labels) in [("labeled", vec![label.id]), ("unlabeled", vec![])] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), labels))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/1/todos?page=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()["x-total-count"], "1");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            todos,
+            vec![TodoEntity::new(1, "labeled".to_string(), vec![label])]
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/2/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_merge_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["bugs", "bug"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository.clone(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        for (path, status) in [
+            ("/labels/1/merge-into/1", StatusCode::BAD_REQUEST),
+            ("/labels/1/merge-into/3", StatusCode::NOT_FOUND),
+            ("/labels/1/merge-into/2", StatusCode::OK),
+            ("/labels/1/merge-into/2", StatusCode::NOT_FOUND),
+        ] {
+            let req = build_todo_req_with_empty(Method::POST, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+        assert_eq!(
+            label_repository.all().await.unwrap(),
+            vec![Label::new(2, "bug".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_translate_errors_by_accept_language() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        for (language, expected) in [
+            (None, r#"{"errors":{"name":["Can not be empty"]}}"#),
+            (
+                Some("ja-JP,en;q=0.8"),
+                r#"{"errors":{"name":["空にはできません"]}}"#,
+            ),
+        ] {
+            let mut req =
+                build_todo_req_with_json("/labels", Method::POST, r#"{ "name": " " }"#.to_string());
+            if let Some(language) = language {
+                req.headers_mut()
+                    .insert(ACCEPT_LANGUAGE, language.parse().unwrap());
+            }
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(expected, String::from_utf8(bytes.to_vec()).unwrap());
+        }
+
+        let mut req = build_todo_req_with_empty(Method::POST, "/todos/1/undo");
+        req.headers_mut()
+            .insert(ACCEPT_LANGUAGE, "ja".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            ApiErrorBody {
+                key: "repository.nothing_to_undo".to_string(),
+                message: "元に戻せる変更がありません".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_json_for_unknown_route_and_method() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/no-such-route");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.key, "error.route_not_found");
+
+        let req = build_todo_req_with_empty(Method::PUT, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        assert_eq!(res.headers()[ALLOW], "POST,GET,HEAD,OPTIONS");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.key, "error.method_not_allowed");
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_path_and_query_parameters() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            admin_keys(),
+        );
+
+        for (path, expected) in [
+            (
+                "/todos/0",
+                r#"{"errors":{"path":["Must be a positive number"]}}"#,
+            ),
+            (
+                "/todos/-3/history",
+                r#"{"errors":{"path":["Must be a positive number"]}}"#,
+            ),
+            (
+                "/todos/abc",
+                r#"{"errors":{"path":["Invalid path parameter"]}}"#,
+            ),
+            (
+                "/projects/0",
+                r#"{"errors":{"path":["Must be a positive number"]}}"#,
+            ),
+            (
+                "/todos?page=0",
+                r#"{"errors":{"page":["page must be between 1 and 1000000"]}}"#,
+            ),
+            (
+                "/audit-logs?entity_id=-1",
+                r#"{"errors":{"entity_id":["Must be a positive number"]}}"#,
+            ),
+            (
+                "/todos/suggest?q=milk&limit=50",
+                r#"{"errors":{"limit":["limit must be between 1 and 20"]}}"#,
+            ),
+        ] {
+            let req = as_admin(build_todo_req_with_empty(Method::GET, path));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(
+                expected,
+                String::from_utf8(bytes.to_vec()).unwrap(),
+                "{}",
+                path
+            );
+        }
+
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/todos?page=one"));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ValidationErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert!(body.errors["query"][0].starts_with("Invalid query parameter"));
+    }
+
+    #[tokio::test]
+    async fn should_answer_head_and_options_on_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::HEAD, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()["x-total-count"], "2");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+
+        let req = build_todo_req_with_empty(Method::OPTIONS, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert_eq!(res.headers()[ALLOW], "GET, HEAD, POST, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn should_update_todo() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "before_update_todos".to_string(), labels.clone());
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        let label_repository = LabelRepositoryForMemory::new();
+
+        todo_repository
+            .create(CreateTodo::new(
+                "before_update_todos".to_string(),
+                label_ids,
+            ))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{
+        "text": "before_update_todos",
+        "completed": false 
+        }"#
+            .to_string(),
+        );
+        let res = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_attach_created_label_and_reject_unknown_label() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        todo_repository
+            .create(CreateTodo::new("labelled".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "created" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+
+        // APIで作ったラベルをそのまま付けられる
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            format!(r#"{{ "labels": [{}] }}"#, label.id),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.labels, vec![label.clone()]);
+
+        // 存在しないラベルは404で、todoは変わらない
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            format!(
+                r#"{{ "text": "changed", "labels": [{}, 12345] }}"#,
+                label.id
+            ),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ApiErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.key, "repository.not_found");
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let todo = res_to_todo(app.oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.text, "labelled");
+        assert_eq!(todo.labels, vec![label]);
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let label_repository = LabelRepositoryForMemory::new();
+
+        let labels = vec![];
+        todo_repository
+            .create(CreateTodo::new("should_delete_todos".to_string(), labels))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    // 監査ログや管理用のエンドポイントは、キーを定義しないと使えない
+    fn admin_keys() -> ApiKeys {
+        ApiKeys::parse("admin:a-key:admin").expect("failed parse api keys")
+    }
+
+    fn as_admin(mut req: Request<Body>) -> Request<Body> {
+        req.headers_mut()
+            .insert(AUTHORIZATION, "Bearer a-key".parse().unwrap());
+        req
+    }
+
+    fn build_req_with_api_key(method: Method, path: &str, api_key: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(AUTHORIZATION, format!("Bearer {}", api_key))
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(r#"{ "name": "test label" }"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_allow_api_key_in_cors_preflight() {
+        let api_keys = ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::OPTIONS)
+            .header("origin", "http://localhost:5173")
+            .header("access-control-request-method", "GET")
+            .header("access-control-request-headers", "authorization")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let allowed = res.headers()["access-control-allow-headers"]
+            .to_str()
+            .unwrap();
+        assert!(allowed
+            .split(',')
+            .any(|name| name.trim() == "authorization"));
+    }
+
+    #[tokio::test]
+    async fn should_enforce_roles() {
+        let api_keys = ApiKeys::parse("viewer:v-key:viewer,editor:e-key:editor,admin:a-key:admin")
+            .expect("failed parse api keys");
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("test label".to_string()))
+            .await
+            .expect("failed create label");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let req = build_req_with_api_key(Method::GET, "/labels", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_req_with_api_key(Method::POST, "/labels", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_req_with_api_key(Method::POST, "/labels", "e-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let path = format!("/labels/{}", label.id);
+        let req = build_req_with_api_key(Method::DELETE, &path, "a-key");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_record_audit_logs() {
+        let api_keys = ApiKeys::parse("admin:a-key:admin").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+
+        let req = build_req_with_api_key(Method::POST, "/labels", "a-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_req_with_api_key(Method::GET, "/audit-logs?entity=label", "a-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let logs: Vec<AuditLog> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, logs.len());
+        assert_eq!("admin", logs[0].actor);
+        assert_eq!(AuditAction::Create, logs[0].action);
+
+        let req = build_req_with_api_key(Method::GET, "/audit-logs?entity=todo", "a-key");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let logs: Vec<AuditLog> = serde_json::from_slice(&bytes).unwrap();
+        assert!(logs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_undo_todo() {
+        let (labels, label_ids) = label_fixture();
+        let app = create_app(
+            TodoRepositoryForMemory::new(labels.clone()),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "before_undo", "labels": [999] }"#.to_string(),
+        );
+        let created = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(created.labels.len(), label_ids.len());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/undo");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "text": "after_undo", "completed": true, "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/undo");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(created, todo);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/history");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let revisions: Vec<TodoRevision> = serde_json::from_slice(&bytes).unwrap();
+        let texts: Vec<&str> = revisions.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(vec!["before_undo", "after_undo", "before_undo"], texts);
+    }
+
+    #[tokio::test]
+    async fn should_not_undo_pin() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(CreateTodo::new("pinned".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::PATCH, "/todos/1/pin");
+        assert!(
+            res_to_todo(app.clone().oneshot(req).await.unwrap())
+                .await
+                .pinned
+        );
+
+        // 固定は取り消しで戻せないので、何も変えずに成功させない
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/undo");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        assert!(res_to_todo(app.oneshot(req).await.unwrap()).await.pinned);
+    }
+
+    #[tokio::test]
+    async fn should_set_and_cancel_reminder() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(CreateTodo::new("should_remind".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos/1/reminder",
+            Method::PUT,
+            r#"{ "remind_at": "2030-01-01T09:00:00Z" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(
+            todo.remind_at.map(|remind_at| remind_at.to_rfc3339()),
+            Some("2030-01-01T09:00:00+00:00".to_string())
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/reminders");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let reminders: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![todo], reminders);
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/reminder");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/reminders");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let reminders: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert!(reminders.is_empty());
+    }
+
+    async fn res_to_todos(res: Response) -> Vec<TodoEntity> {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).expect("cannot convert Todo instances")
+    }
+
+    #[tokio::test]
+    async fn should_assign_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let user_repository = UserRepositoryForMemory::new();
+        let notifier = Arc::new(NotifierForMemory::default());
+        let events = EventBus::default();
+        spawn_assignment_notifier(&events, notifier.clone());
+        for text in ["assigned", "not assigned"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let alice = user_repository.create("alice".to_string()).await.unwrap();
+        let api_keys = ApiKeys::parse("alice:a-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            events,
+            api_keys,
+        );
+
+        let mut req = build_todo_req_with_json(
+            "/todos/1/assign",
+            Method::PATCH,
+            r#"{ "assignee_id": 1 }"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(AUTHORIZATION, "Bearer a-key".parse().unwrap());
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(Some(alice.clone()), todo.assignee);
+
+        let mut req = build_todo_req_with_json(
+            "/todos/2/assign",
+            Method::PATCH,
+            r#"{ "assignee_id": 99 }"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(AUTHORIZATION, "Bearer a-key".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_api_key(Method::GET, "/todos?assignee=me", "a-key");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(vec![todo], todos);
+
+        let req = build_req_with_api_key(Method::GET, "/todos?assignee=2", "a-key");
+        let todos = res_to_todos(app.oneshot(req).await.unwrap()).await;
+        assert!(todos.is_empty());
+
+        // 通知は購読側のタスクで送られる
+        for _ in 0..100 {
+            if !notifier.sent.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let sent = notifier.sent.lock().unwrap();
+        assert_eq!(1, sent.len());
+        assert_eq!("Assignment changed: todo #1", sent[0].subject);
+    }
+
+    fn build_json_req_with_api_key(
+        method: Method,
+        path: &str,
+        json_body: &str,
+        api_key: &str,
+    ) -> Request<Body> {
+        let mut req = build_todo_req_with_json(path, method, json_body.to_string());
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {}", api_key).parse().unwrap(),
+        );
+        req
+    }
+
+    #[tokio::test]
+    async fn should_share_todo_with_another_user() {
+        let user_repository = UserRepositoryForMemory::new();
+        user_repository.create("alice".to_string()).await.unwrap();
+        let bob = user_repository.create("bob".to_string()).await.unwrap();
+        let api_keys =
+            ApiKeys::parse("alice:a-key:editor,bob:b-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        let send = |req: Request<Body>| app.clone().oneshot(req);
+
+        let req = build_json_req_with_api_key(
+            Method::POST,
+            "/todos",
+            r#"{ "text": "private", "labels": [] }"#,
+            "a-key",
+        );
+        let todo = res_to_todo(send(req).await.unwrap()).await;
+        assert_eq!(Some(1), todo.owner_id);
+
+        // 共有されるまでは他のユーザーからは存在しないように見える
+        let req = build_req_with_api_key(Method::GET, "/todos", "b-key");
+        assert!(res_to_todos(send(req).await.unwrap()).await.is_empty());
+        let req = build_req_with_api_key(Method::GET, "/todos/1", "b-key");
+        assert_eq!(StatusCode::NOT_FOUND, send(req).await.unwrap().status());
+
+        let share = format!(r#"{{ "user_id": {}, "permission": "read" }}"#, bob.id);
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", &share, "a-key");
+        assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+
+        let req = build_req_with_api_key(Method::GET, "/todos?shared_with_me=true", "b-key");
+        assert_eq!(
+            vec![todo.clone()],
+            res_to_todos(send(req).await.unwrap()).await
+        );
+        let req = build_req_with_api_key(Method::GET, "/todos?shared_with_me=true", "a-key");
+        assert!(res_to_todos(send(req).await.unwrap()).await.is_empty());
+
+        // 読み取りだけでは更新も共有もできない
+        let update = r#"{ "text": "changed by bob" }"#;
+        let req = build_json_req_with_api_key(Method::PATCH, "/todos/1", update, "b-key");
+        assert_eq!(StatusCode::FORBIDDEN, send(req).await.unwrap().status());
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", &share, "b-key");
+        assert_eq!(StatusCode::FORBIDDEN, send(req).await.unwrap().status());
+
+        let share = format!(r#"{{ "user_id": {}, "permission": "write" }}"#, bob.id);
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", &share, "a-key");
+        assert_eq!(StatusCode::OK, send(req).await.unwrap().status());
+        let req = build_json_req_with_api_key(Method::PATCH, "/todos/1", update, "b-key");
+        let updated = res_to_todo(send(req).await.unwrap()).await;
+        assert_eq!("changed by bob", updated.text);
+
+        let req = build_req_with_api_key(Method::DELETE, "/todos/1/share/2", "a-key");
+        assert_eq!(StatusCode::NO_CONTENT, send(req).await.unwrap().status());
+        let req = build_req_with_api_key(Method::GET, "/todos/1", "b-key");
+        assert_eq!(StatusCode::NOT_FOUND, send(req).await.unwrap().status());
+    }
+
+    // aliceとbobの二人のユーザーがいるアプリ
+    async fn create_app_for_two_users(todo_repository: TodoRepositoryForMemory<i32>) -> Router {
+        let user_repository = UserRepositoryForMemory::new();
+        user_repository.create("alice".to_string()).await.unwrap();
+        user_repository.create("bob".to_string()).await.unwrap();
+        let api_keys =
+            ApiKeys::parse("alice:a-key:editor,bob:b-key:editor").expect("failed parse api keys");
+        create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        )
+    }
+
+    #[tokio::test]
+    async fn should_not_block_todo_without_access() {
+        let app = create_app_for_two_users(TodoRepositoryForMemory::new(vec![])).await;
+        let send = |req: Request<Body>| app.clone().oneshot(req);
+        for (text, api_key) in [("alice's", "a-key"), ("bob's", "b-key")] {
+            let body = format!(r#"{{ "text": "{}", "labels": [] }}"#, text);
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, api_key);
+            assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        }
+
+        // 見えないtodoは存在しないものとして扱う
+        for method in [Method::PUT, Method::DELETE] {
+            let req = build_req_with_api_key(method, "/todos/2/blocks/1", "b-key");
+            assert_eq!(StatusCode::NOT_FOUND, send(req).await.unwrap().status());
+        }
+
+        // 読み取りだけでは依存関係を変えられない
+        let share = r#"{ "user_id": 2, "permission": "read" }"#;
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", share, "a-key");
+        assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        for method in [Method::PUT, Method::DELETE] {
+            let req = build_req_with_api_key(method, "/todos/2/blocks/1", "b-key");
+            assert_eq!(StatusCode::FORBIDDEN, send(req).await.unwrap().status());
+        }
+
+        let share = r#"{ "user_id": 2, "permission": "write" }"#;
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", share, "a-key");
+        assert_eq!(StatusCode::OK, send(req).await.unwrap().status());
+        for method in [Method::PUT, Method::DELETE] {
+            let req = build_req_with_api_key(method, "/todos/2/blocks/1", "b-key");
+            assert_eq!(StatusCode::NO_CONTENT, send(req).await.unwrap().status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_nest_todo_under_invisible_parent() {
+        let app = create_app_for_two_users(TodoRepositoryForMemory::new(vec![])).await;
+        let send = |req: Request<Body>| app.clone().oneshot(req);
+        for (text, api_key) in [("alice's", "a-key"), ("bob's", "b-key")] {
+            let body = format!(r#"{{ "text": "{}", "labels": [] }}"#, text);
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, api_key);
+            assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        }
+
+        // 見えない親は存在しない親と同じく422にする
+        let body = r#"{ "text": "child", "labels": [], "parent_id": 1 }"#;
+        let req = build_json_req_with_api_key(Method::POST, "/todos", body, "b-key");
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            send(req).await.unwrap().status()
+        );
+        let body = r#"{ "parent_id": 1 }"#;
+        let req = build_json_req_with_api_key(Method::PATCH, "/todos/2/parent", body, "b-key");
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            send(req).await.unwrap().status()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_dedupe_only_against_visible_todos() {
+        let app = create_app_for_two_users(TodoRepositoryForMemory::new(vec![])).await;
+        let send = |req: Request<Body>| app.clone().oneshot(req);
+        let body = r#"{ "text": "milk", "labels": [] }"#;
+        for (api_key, status, id) in [
+            ("a-key", StatusCode::CREATED, 1),
+            // 他のユーザーの見えないtodoは返さない
+            ("b-key", StatusCode::CREATED, 2),
+            ("b-key", StatusCode::CONFLICT, 2),
+        ] {
+            let req =
+                build_json_req_with_api_key(Method::POST, "/todos?dedupe=true", body, api_key);
+            let res = send(req).await.unwrap();
+            assert_eq!(status, res.status());
+            assert_eq!(id, res_to_todo(res).await.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_duplicate_invisible_children() {
+        let app = create_app_for_two_users(TodoRepositoryForMemory::new(vec![])).await;
+        let send = |req: Request<Body>| app.clone().oneshot(req);
+        let body = r#"{ "text": "trip", "labels": [] }"#;
+        let req = build_json_req_with_api_key(Method::POST, "/todos", body, "a-key");
+        assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        let share = r#"{ "user_id": 2, "permission": "write" }"#;
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share", share, "a-key");
+        assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        for (text, api_key) in [("bob's", "b-key"), ("alice's", "a-key")] {
+            let body = format!(r#"{{ "text": "{}", "labels": [], "parent_id": 1 }}"#, text);
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, api_key);
+            assert_eq!(StatusCode::CREATED, send(req).await.unwrap().status());
+        }
+
+        let req = build_req_with_api_key(Method::POST, "/todos/1/duplicate", "b-key");
+        let copy = res_to_todo(send(req).await.unwrap()).await;
+        assert_eq!(Some(2), copy.owner_id);
+        let path = format!("/todos/{}/children", copy.id);
+        let req = build_req_with_api_key(Method::GET, &path, "b-key");
+        let children = res_to_todos(send(req).await.unwrap()).await;
+        assert_eq!(
+            vec!["bob's"],
+            children
+                .iter()
+                .map(|todo| todo.text.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_move_todo_between_projects() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let project_repository = ProjectRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("should_move".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            project_repository,
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        for name in ["first", "second"] {
+            let req = build_todo_req_with_json(
+                "/projects",
+                Method::POST,
+                format!(r#"{{ "name": "{}" }}"#, name),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        for project_id in [1, 2] {
+            let req = build_todo_req_with_json(
+                "/todos/1/project",
+                Method::PATCH,
+                format!(r#"{{ "project_id": {} }}"#, project_id),
+            );
+            let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+            assert_eq!(Some(project_id), todo.project_id);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/projects/1/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(todos.is_empty());
+
+        let req = build_todo_req_with_empty(Method::GET, "/projects/2/todos");
+        let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(1, todos.len());
+
+        let req = build_todo_req_with_json(
+            "/todos/1/project",
+            Method::PATCH,
+            r#"{ "project_id": 3 }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/projects/3/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_show_todo_through_public_share_link() {
+        let share_link_repository = ShareLinkRepositoryForMemory::new();
+        let api_keys = ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            share_link_repository.clone(),
+            EventBus::default(),
+            api_keys,
+        );
+        for body in [
+            r#"{ "text": "trip", "labels": [] }"#,
+            r#"{ "text": "book hotel", "labels": [], "parent_id": 1 }"#,
+            r#"{ "text": "pick room", "labels": [], "parent_id": 2 }"#,
+        ] {
+            let req = build_json_req_with_api_key(Method::POST, "/todos", body, "e-key");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_json_req_with_api_key(Method::POST, "/todos/1/share-link", "{}", "e-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let link: ShareLink = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(None, link.expires_at);
+
+        // APIキーなしで読める
+        let path = format!("/shared/{}", link.token);
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let shared: SharedTodo = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("trip", shared.text);
+        assert_eq!("book hotel", shared.subtasks[0].text);
+        assert_eq!("pick room", shared.subtasks[0].subtasks[0].text);
+        // 読み取り専用なので更新はできない
+        let req = build_todo_req_with_json(&path, Method::PATCH, "{}".to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+
+        let expired = share_link_repository
+            .create(1, Some(chrono::Utc::now() - chrono::Duration::seconds(1)))
+            .await
+            .unwrap();
+        let req = build_todo_req_with_empty(Method::GET, &format!("/shared/{}", expired.token));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::GONE, res.status());
+
+        let revoke = format!("/todos/1/share-link/{}", link.token);
+        let req = build_req_with_api_key(Method::DELETE, &revoke, "e-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_stream_todos_as_json_array() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("export".to_string()))
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        for (text, labels) in [("first", vec![label.id]), ("second", vec![])] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), labels))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let expected = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?stream=true&label_id=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        assert!(res.headers().get(X_TOTAL_COUNT).is_none());
+        assert_eq!(res_to_todos(res).await, expected[1..]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?stream=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res_to_todos(res).await, expected);
+
+        // 当てはまるtodoがない場合も空の配列にする
+        let req = build_todo_req_with_empty(Method::GET, "/todos?stream=true&assignee=me");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"[]");
+    }
+
+    #[tokio::test]
+    async fn should_sync_offline_todos_with_last_write_wins() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let stale = todo_repository
+            .create(CreateTodo::new("edited on server".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let newer = todo_repository
+            .create(CreateTodo::new("edited offline".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let created = Uuid::from_u128(100);
+        let unknown_label = Uuid::from_u128(101);
+        let deleted = Uuid::from_u128(102);
+        let body = format!(
+            r#"{{ "todos": [
+                {{ "uuid": "{created}", "text": "made offline", "status": "done", "modified_at": "2024-06-01T00:00:00Z" }},
+                {{ "uuid": "{stale}", "text": "old edit", "modified_at": "2000-01-01T00:00:00Z" }},
+                {{ "uuid": "{newer}", "text": "new edit", "status": "in_progress", "tags": ["sync"], "modified_at": "2999-01-01T00:00:00Z" }},
+                {{ "uuid": "{unknown_label}", "text": "labelled", "labels": [99], "modified_at": "2024-06-01T00:00:00Z" }},
+                {{ "uuid": "{deleted}", "text": "gone", "deleted": true, "modified_at": "2024-06-01T00:00:00Z" }}
+            ] }}"#,
+            stale = stale.uuid,
+            newer = newer.uuid,
+        );
+        let req = build_todo_req_with_json("/todos/sync", Method::PUT, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let result: SyncResult = serde_json::from_slice(&bytes).unwrap();
+
+        let todos: Vec<(Uuid, &str, TodoStatus)> = result
+            .todos
+            .iter()
+            .map(|synced| {
+                (
+                    synced.todo.uuid,
+                    synced.todo.text.as_str(),
+                    synced.todo.status,
+                )
+            })
+            .collect();
+        assert_eq!(
+            todos,
+            vec![
+                (created, "made offline", TodoStatus::Done),
+                (stale.uuid, "edited on server", TodoStatus::Backlog),
+                (newer.uuid, "new edit", TodoStatus::InProgress),
+            ]
+        );
+        assert_eq!(result.deleted, vec![deleted]);
+        assert_eq!(
+            result.conflicts,
+            vec![
+                SyncConflict {
+                    uuid: stale.uuid,
+                    reason: SyncConflictReason::Stale,
+                },
+                SyncConflict {
+                    uuid: unknown_label,
+                    reason: SyncConflictReason::Rejected,
+                },
+            ]
+        );
+
+        // サーバーで振ったidでも、クライアントが振ったUUIDでも読める
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", created));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res_to_todo(res).await.text, "made offline");
+
+        let req =
+            build_todo_req_with_json("/todos/sync", Method::PUT, r#"{ "todos": [] }"#.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_return_todo_changes_since_sync_token() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let first = todo_repository
+            .create(CreateTodo::new("first".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let changes = |app: Router, path: String| async move {
+            let req = build_todo_req_with_empty(Method::GET, &path);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            serde_json::from_slice::<TodoChanges>(&bytes).unwrap()
+        };
+
+        let all = changes(app.clone(), "/todos/changes".to_string()).await;
+        assert_eq!(
+            all.created,
+            vec![ChangedTodo {
+                id: first.id,
+                uuid: first.uuid,
+            }]
+        );
+
+        todo_repository.delete(first.id).await.unwrap();
+        let delta = changes(
+            app.clone(),
+            format!("/todos/changes?since={}", all.sync_token),
+        )
+        .await;
+        assert!(delta.created.is_empty());
+        assert_eq!(delta.deleted, all.created);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/changes?since=latest");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_count_todos_for_badges() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("inbox".to_string()))
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        for (text, labels) in [
+            ("first", vec![label.id]),
+            ("second", vec![label.id]),
+            ("third", vec![]),
+        ] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), labels))
+                .await
+                .expect("failed create todo");
+        }
+        todo_repository
+            .update(1, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .expect("failed update todo");
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        for (path, expected) in [
+            ("/todos/count", r#"{"count":3}"#),
+            ("/todos/count?completed=false", r#"{"count":2}"#),
+            ("/todos/count?completed=false&label_id=1", r#"{"count":1}"#),
+            ("/todos/count?assignee=me", r#"{"count":0}"#),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(
+                String::from_utf8(bytes.to_vec()).unwrap(),
+                expected,
+                "{}",
+                path
+            );
+        }
+
+        // 一覧も同じ条件で絞り込める
+        let req = build_todo_req_with_empty(Method::GET, "/todos?completed=false&label_id=1");
+        let res = app.oneshot(req).await.unwrap();
+        let todos = res_to_todos(res).await;
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].text, "second");
+    }
+
+    #[tokio::test]
+    async fn should_limit_daily_requests_per_principal() {
+        let api_keys =
+            ApiKeys::parse("alice:a-key:editor,bob:b-key:editor").expect("failed parse api keys");
+        let quota = Quota::new(
+            DailyQuotas::new(Some(2)).principal("bob", None),
+            UsageRepositoryForMemory::new(),
+        );
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        )
+        .layer(Extension(Arc::new(quota)));
+
+        for remaining in ["1", "0"] {
+            let req = build_req_with_api_key(Method::GET, "/todos", "a-key");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!(res.headers()["x-ratelimit-limit"], "2");
+            assert_eq!(res.headers()["x-ratelimit-remaining"], remaining);
+            assert!(res.headers().contains_key("x-ratelimit-reset"));
+        }
+        let req = build_req_with_api_key(Method::GET, "/todos", "a-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        assert!(res.headers().contains_key(RETRY_AFTER));
+
+        // 上限を超えても使用量は確認できる
+        let req = build_req_with_api_key(Method::GET, "/me/usage", "a-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let usage: Usage = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(usage.principal, "alice");
+        assert_eq!(usage.used, 3);
+        assert_eq!(usage.limit, Some(2));
+        assert_eq!(usage.remaining, Some(0));
+
+        // 上限のない主体は数えるだけ
+        let req = build_req_with_api_key(Method::GET, "/todos", "b-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(!res.headers().contains_key("x-ratelimit-limit"));
+        let req = build_req_with_api_key(Method::GET, "/me/usage", "b-key");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let usage: Usage = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!((usage.used, usage.limit), (1, None));
+    }
+
+    fn build_github_webhook_req(secret: &str, delivery: &str, body: &str) -> Request<Body> {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        Request::builder()
+            .uri("/integrations/github")
+            .method(Method::POST)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header("x-github-event", "issues")
+            .header("x-github-delivery", delivery)
+            .header("x-hub-signature-256", format!("sha256={}", signature))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_complete_todo_when_linked_issue_is_closed() {
+        let api_keys = ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys");
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(CreateTodo::new("fix the login bug".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let github = GithubIntegration::new(GithubLinkRepositoryForMemory::new())
+            .with_webhook_secret("webhook-secret");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        )
+        .layer(Extension(Arc::new(github)));
+
+        let mut req = build_todo_req_with_json(
+            "/todos/1/link-github",
+            Method::POST,
+            r#"{ "repository": "Octo/API", "issue_number": 42 }"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(AUTHORIZATION, "Bearer e-key".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let mut req = build_todo_req_with_json(
+            "/todos/1/link-github",
+            Method::POST,
+            r#"{ "repository": "octo", "issue_number": 42 }"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(AUTHORIZATION, "Bearer e-key".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // APIキーの代わりに署名で確かめる
+        let body =
+            r#"{"action":"closed","issue":{"number":42},"repository":{"full_name":"octo/api"}}"#;
+        let req = build_github_webhook_req("wrong-secret", "delivery-1", body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!(
+            todo_repository.find(1).await.unwrap().status,
+            TodoStatus::Backlog
+        );
+
+        let req = build_github_webhook_req("webhook-secret", "delivery-1", body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let todo = todo_repository.find(1).await.unwrap();
+        assert_eq!(todo.status, TodoStatus::Done);
+
+        // 同じ配信をもう一度送られても受け付けない
+        todo_repository
+            .update(1, UpdateTodo::status(TodoStatus::InProgress))
+            .await
+            .unwrap();
+        let req = build_github_webhook_req("webhook-secret", "delivery-1", body);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let todo = todo_repository.find(1).await.unwrap();
+        assert_eq!(todo.status, TodoStatus::InProgress);
+    }
+
+    fn build_slack_command_req(secret: &str, timestamp: i64, body: &str) -> Request<Body> {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("v0:{}:{}", timestamp, body).as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        Request::builder()
+            .uri("/integrations/slack/command")
+            .method(Method::POST)
+            .header(CONTENT_TYPE, mime::APPLICATION_WWW_FORM_URLENCODED.as_ref())
+            .header("x-slack-request-timestamp", timestamp.to_string())
+            .header("x-slack-signature", format!("v0={}", signature))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn res_to_slack_reply(res: Response) -> SlackReply {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_add_todo_from_slack_command() {
+        let user_repository = UserRepositoryForMemory::new();
+        let alice = user_repository
+            .create(String::from("alice"))
+            .await
+            .expect("failed create user");
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let slack = SlackIntegration::new("signing-secret").user("U111", "alice");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys"),
+        )
+        .layer(Extension(JsonCase::Camel))
+        .layer(Extension(Arc::new(slack)));
+        let now = chrono::Utc::now().timestamp();
+        let body = "command=%2Ftodo&text=add+Buy+milk&user_id=U111&team_id=T1";
+
+        let req = build_slack_command_req("signing-secret", now, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res_to_slack_reply(res).await,
+            SlackReply {
+                response_type: String::from("ephemeral"),
+                text: String::from("Added todo #1: Buy milk"),
+            }
+        );
+        let todo = todo_repository.find(1).await.unwrap();
+        assert_eq!(todo.owner_id, Some(alice.id));
+
+        // 同じ署名の再送や、古い時刻の署名は受け付けない
+        let req = build_slack_command_req("signing-secret", now, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let req = build_slack_command_req("signing-secret", now - 600, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let req = build_slack_command_req("other-secret", now, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // 対応表にないユーザーと、知らないコマンドには返信で伝える
+        let body = "command=%2Ftodo&text=add+Call+mom&user_id=U222";
+        let req = build_slack_command_req("signing-secret", now, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res_to_slack_reply(res).await.text.contains("not linked"));
+        let body = "command=%2Ftodo&text=list&user_id=U111";
+        let req = build_slack_command_req("signing-secret", now, body);
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res_to_slack_reply(res).await.text.starts_with("Usage"));
+        assert!(todo_repository.find(2).await.is_err());
+    }
+
+    fn build_inbound_email_req(
+        key: &str,
+        timestamp: i64,
+        token: &str,
+        fields: &str,
+    ) -> Request<Body> {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(format!("{}{}", timestamp, token).as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        Request::builder()
+            .uri("/integrations/email")
+            .method(Method::POST)
+            .header(CONTENT_TYPE, mime::APPLICATION_WWW_FORM_URLENCODED.as_ref())
+            .body(Body::from(format!(
+                "{}&timestamp={}&token={}&signature={}",
+                fields, timestamp, token, signature
+            )))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_create_todo_from_inbound_email() {
+        let user_repository = UserRepositoryForMemory::new();
+        let alice = user_repository
+            .create(String::from("alice"))
+            .await
+            .expect("failed create user");
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let email = EmailIntegration::new("signing-key").user("Alice@Example.com", "alice");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys"),
+        )
+        .layer(Extension(JsonCase::Camel))
+        .layer(Extension(Arc::new(email)));
+        let now = chrono::Utc::now().timestamp();
+        let fields = "sender=alice%40example.com&subject=Buy+milk&body-plain=2+bottles%0A%0A--+alice&stripped-text=2+bottles&attachment-count=1";
+
+        let req = build_inbound_email_req("signing-key", now, "token-1", fields);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = todo_repository.find(1).await.unwrap();
+        assert_eq!(todo.text, "Buy milk");
+        assert_eq!(todo.description.as_deref(), Some("2 bottles"));
+        assert_eq!(todo.owner_id, Some(alice.id));
+
+        // 同じtokenの再送や、古い時刻・違うキーの署名は受け付けない
+        let req = build_inbound_email_req("signing-key", now, "token-1", fields);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let req = build_inbound_email_req("signing-key", now - 600, "token-2", fields);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let req = build_inbound_email_req("other-key", now, "token-3", fields);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // 対応表にない送信元と、件名のないメールは再送させずに断る
+        let fields = "sender=bob%40example.com&subject=Call+mom";
+        let req = build_inbound_email_req("signing-key", now, "token-4", fields);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, res.status());
+        let fields = "sender=alice%40example.com&subject=+&body-plain=no+subject";
+        let req = build_inbound_email_req("signing-key", now, "token-5", fields);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, res.status());
+        assert!(todo_repository.find(2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_suggest_todos_while_typing() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["buy milk", "build the shelf", "call mom"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/suggest?q=%20bu&limit=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            String::from_utf8(bytes.to_vec()).unwrap(),
+            r#"[{"id":1,"text":"buy milk"}]"#
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/suggest?q=%20");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_serve_calendar_of_reminders() {
+        let api_keys = ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        for text in ["dentist", "someday"] {
+            let body = format!(r#"{{ "text": "{}", "labels": [] }}"#, text);
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, "e-key");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_json_req_with_api_key(
+            Method::PUT,
+            "/todos/1/reminder",
+            r#"{ "remind_at": "2030-01-01T09:00:00Z" }"#,
+            "e-key",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // カレンダーアプリはヘッダーを付けられないのでクエリで認証する
+        let req = build_todo_req_with_empty(Method::GET, "/todos/calendar.ics");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos?token=e-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/calendar.ics?token=e-key");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "text/calendar; charset=utf-8",
+            res.headers().get(CONTENT_TYPE).unwrap()
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(body.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(1, body.matches("BEGIN:VEVENT").count());
+        assert!(body.contains("DTSTART:20300101T090000Z\r\nSUMMARY:dentist\r\n"));
+    }
+
+    fn build_dav_req(method: &str, uri: &str, body: &str, password: &str) -> Request<Body> {
+        use base64::Engine;
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("any:{}", password));
+        Request::builder()
+            .uri(uri)
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .header(AUTHORIZATION, format!("Basic {}", credentials))
+            .header(CONTENT_TYPE, "application/xml")
+            .header("depth", "1")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn res_to_string(res: Response) -> String {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_serve_todos_over_caldav() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let api_keys = ApiKeys::parse("viewer:v-key:viewer").expect("failed parse api keys");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        let milk = todo_repository
+            .create(CreateTodo::new("buy milk".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .create(CreateTodo::new("call mom".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .update(milk.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+
+        // パスワードが違えばBasic認証を求める
+        let req = build_dav_req("PROPFIND", "/dav/todos/", "", "wrong");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert!(res.headers().contains_key(WWW_AUTHENTICATE));
+
+        let req = build_dav_req("PROPFIND", "/dav/", "", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::MULTI_STATUS, res.status());
+        let body = res_to_string(res).await;
+        assert!(body.contains("<C:calendar-home-set><D:href>/dav/</D:href>"));
+        assert!(body.contains("<D:href>/dav/todos/</D:href>"));
+
+        let req = build_dav_req("PROPFIND", "/dav/todos/", "", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::MULTI_STATUS, res.status());
+        let body = res_to_string(res).await;
+        assert_eq!(2, body.matches("<D:getetag>").count());
+        let href = format!("/dav/todos/{}.ics", milk.uuid);
+        assert!(body.contains(&href));
+
+        // calendar-multigetでは指定したものだけを返す
+        let report = format!(
+            r#"<C:calendar-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav"><D:prop><D:getetag/><C:calendar-data/></D:prop><D:href>{}</D:href></C:calendar-multiget>"#,
+            href
+        );
+        let req = build_dav_req("REPORT", "/dav/todos/", &report, "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::MULTI_STATUS, res.status());
+        let body = res_to_string(res).await;
+        assert_eq!(1, body.matches("BEGIN:VTODO").count());
+        assert!(body.contains("SUMMARY:buy milk"));
+        assert!(body.contains("STATUS:COMPLETED"));
+
+        let req = build_dav_req("GET", &href, "", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().contains_key(ETAG));
+        assert!(res_to_string(res).await.contains("BEGIN:VTODO"));
+
+        // 今は読み取りだけ
+        let req = build_dav_req("PUT", &href, "", "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_dav_req("GET", "/dav/todos/unknown.ics", "", "v-key");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_export_filtered_todos_as_pdf() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        for text in ["buy milk (2L)", "file taxes"] {
+            let body = format!(r#"{{ "text": "{}", "labels": [] }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export.pdf?completed=false");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("application/pdf", res.headers().get(CONTENT_TYPE).unwrap());
+        assert!(res.headers().contains_key(LAST_MODIFIED));
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&bytes);
+        assert!(body.starts_with("%PDF-1.4\n"));
+        assert!(body.ends_with("%%EOF\n"));
+        assert!(body.contains("(buy milk \\(2L\\)) Tj"));
+        assert!(!body.contains("file taxes"));
+    }
+
+    #[tokio::test]
+    async fn should_serve_atom_feed_of_latest_todos() {
+        let api_keys = ApiKeys::parse("editor:e-key:editor").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        for text in ["first", "second", "third & last"] {
+            let body = format!(
+                r#"{{ "text": "{}", "labels": [], "tags": ["feed"] }}"#,
+                text
+            );
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, "e-key");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/feed.atom");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos/feed.atom?tag=feed&per_page=2&token=e-key",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "application/atom+xml; charset=utf-8",
+            res.headers().get(CONTENT_TYPE).unwrap()
+        );
+        assert!(res.headers().contains_key(LAST_MODIFIED));
+        // 一覧と同じく総件数とページ間のリンクを返す
+        assert_eq!("3", res.headers().get(X_TOTAL_COUNT).unwrap());
+        assert!(res
+            .headers()
+            .get(LINK)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains(r#"</todos/feed.atom?tag=feed&token=e-key&page=2&per_page=2>; rel="next""#));
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        // 新しい順に、指定した件数だけ返す
+        let titles: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("<title>"))
+            .collect();
+        assert_eq!(
+            vec![
+                "todos</title>",
+                "third &amp; last</title>",
+                "second</title>"
+            ],
+            titles
+        );
+        assert!(body.contains(r#"<link rel="alternate" href="/todos/3"/>"#));
+        assert!(body.contains(r#"<category term="feed"/>"#));
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos/feed.atom?tag=feed&page=2&per_page=2&token=e-key",
+        );
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.contains("<title>first</title>"));
+        assert!(!body.contains("<title>second</title>"));
+    }
+
+    #[tokio::test]
+    async fn should_nest_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        for body in [
+            r#"{ "text": "root", "labels": [] }"#,
+            r#"{ "text": "child", "labels": [], "parent_id": 1 }"#,
+            r#"{ "text": "grandchild", "labels": [], "parent_id": 2 }"#,
+            r#"{ "text": "cancelled", "labels": [], "parent_id": 1 }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        // 存在しない親は指定できない
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "orphan", "labels": [], "parent_id": 99 }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/children");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let children: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<i32> = children.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![2, 4]);
+
+        // 自分自身や子孫を親にはできない
+        for parent_id in [1, 3] {
+            let req = build_todo_req_with_json(
+                "/todos/1/parent",
+                Method::PATCH,
+                format!(r#"{{ "parent_id": {} }}"#, parent_id),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        }
+
+        let req = build_todo_req_with_json(
+            "/todos/4/status",
+            Method::PATCH,
+            r#"{ "status": "cancelled" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/1/status?cascade=true",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        for (id, status) in [
+            (2, TodoStatus::Done),
+            (3, TodoStatus::Done),
+            (4, TodoStatus::Cancelled),
+        ] {
+            assert_eq!(todo_repository.find(id).await.unwrap().status, status);
+        }
+
+        // 親を外すと最上位に戻る
+        let req = build_todo_req_with_json(
+            "/todos/2/parent",
+            Method::PATCH,
+            r#"{ "parent_id": null }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res_to_todo(res).await.parent_id, None);
+    }
+
+    #[tokio::test]
+    async fn should_block_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["design", "build", "ship"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let send = |method: Method, path: &str| {
+            app.clone().oneshot(build_todo_req_with_empty(method, path))
+        };
+
+        for (path, status) in [
+            ("/todos/1/blocks/2", StatusCode::NO_CONTENT),
+            ("/todos/2/blocks/3", StatusCode::NO_CONTENT),
+            // 循環する依存関係は作れない
+            ("/todos/3/blocks/1", StatusCode::UNPROCESSABLE_ENTITY),
+            ("/todos/2/blocks/2", StatusCode::UNPROCESSABLE_ENTITY),
+            ("/todos/1/blocks/99", StatusCode::NOT_FOUND),
+        ] {
+            let res = send(Method::PUT, path).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+
+        let res = send(Method::GET, "/todos/2/dependencies").await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let dependencies: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            dependencies,
+            serde_json::json!({ "blocked_by": [1], "blocks": [3] })
+        );
+        let res = send(Method::GET, "/todos/2").await.unwrap();
+        assert!(res_to_todo(res).await.blocked);
+
+        let req = build_todo_req_with_json(
+            "/todos/1/status",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let res = send(Method::GET, "/todos/2").await.unwrap();
+        assert!(!res_to_todo(res).await.blocked);
+
+        let res = send(Method::DELETE, "/todos/2/blocks/3").await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = send(Method::DELETE, "/todos/2/blocks/3").await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_list_todos_through_saved_view() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for (text, tags) in [("urgent", vec!["urgent"]), ("someday", vec![])] {
+            let body = serde_json::json!({ "text": text, "labels": [], "tags": tags });
+            todo_repository
+                .create(serde_json::from_value(body).unwrap())
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/views",
+            Method::POST,
+            r#"{ "name": "Urgent", "filter": { "tag": "urgent" } }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/views/1/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["urgent"]);
+
+        // 絞り込みに使えない条件は保存しない
+        let req = build_todo_req_with_json(
+            "/views",
+            Method::POST,
+            r#"{ "name": "Overdue", "filter": { "overdue": true } }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/views/2/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_create_todos_from_template() {
+        let labels = LabelRepositoryForMemory::new();
+        let label = labels
+            .create(CreateLabel::new("weekly".to_string()))
+            .await
+            .unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![label.clone()]),
+            labels,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/templates",
+            Method::POST,
+            r#"{ "name": "Weekly", "text": "Review week {{week}}", "labels": [99] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_json(
+            "/templates",
+            Method::POST,
+            format!(
+                r#"{{ "name": "Weekly", "text": "Review week {{{{week}}}}", "labels": [{}], "tags": ["review"] }}"#,
+                label.id
+            ),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_json(
+            "/todos/from-template/1",
+            Method::POST,
+            r#"{ "substitutions": { "week": "42" } }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(todo.text, "Review week 42");
+        assert_eq!(todo.labels, vec![label]);
+        assert_eq!(todo.tags, vec!["review".to_string()]);
+
+        // 置き換えていないプレースホルダーが残る
+        let req =
+            build_todo_req_with_json("/todos/from-template/1", Method::POST, "{}".to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: ValidationErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert!(body.errors.contains_key("substitutions"));
+
+        let req =
+            build_todo_req_with_json("/todos/from-template/2", Method::POST, "{}".to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_change_todo_status() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(CreateTodo::new("should_change_status".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos/1/status",
+            Method::PATCH,
+            r#"{ "status": "in_progress" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(TodoStatus::InProgress, todo.status);
+
+        let req = build_todo_req_with_json(
+            "/todos/1/status",
+            Method::PATCH,
+            r#"{ "status": "cancelled" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(TodoStatus::Cancelled, todo.status);
+
+        // 中止から完了へは直接移れない
+        let req = build_todo_req_with_json(
+            "/todos/1/status",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_json(
+            "/todos/1/status",
+            Method::PATCH,
+            r#"{ "status": "backlog" }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(TodoStatus::Done, todo.status);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["status"], "done");
+        assert_eq!(json["completed"], true);
+    }
+
+    #[tokio::test]
+    async fn should_serve_admin_routes_on_separate_router() {
+        let api_keys = ApiKeys::parse("admin:a-key:admin").expect("failed parse api keys");
+        let (public, admin) = create_apps(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+            true,
+        );
+        let admin = admin
+            .expect("admin router should be separated")
+            .layer(Extension(Arc::new(Metrics::default())))
+            .layer(Extension(Config::default().shared()));
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .uri(path)
+                .method(method)
+                .header(AUTHORIZATION, "Bearer a-key")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for path in ["/metrics", "/health", "/admin/config", "/admin/backups"] {
+            let res = public
+                .clone()
+                .oneshot(request(Method::GET, path))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status(), "{}", path);
+        }
+        let res = public
+            .clone()
+            .oneshot(request(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        for path in ["/metrics", "/admin/config"] {
+            let res = admin
+                .clone()
+                .oneshot(request(Method::GET, path))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+        }
+        let res = admin
+            .clone()
+            .oneshot(request(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // 死活監視はキーがなくても応答する
+        let req = build_todo_req_with_empty(Method::GET, "/health");
+        let res = admin.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"ok");
+    }
+
+    #[tokio::test]
+    async fn should_expose_route_latency_metrics() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(Arc::new(Metrics::default())));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/metrics");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body
+            .contains("http_requests_total{method=\"GET\",route=\"/todos/:id\",status=\"404\"} 1"));
+        assert!(body.contains(
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/todos/:id\"} 1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_time_out_slow_handler() {
+        let timeouts = Timeouts::default().route("/flaky", Some(Duration::from_millis(10)));
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(Arc::new(timeouts)));
+
+        // /flakyは最低でも1秒待つので必ずタイムアウトする
+        let req = build_todo_req_with_empty(Method::GET, "/flaky");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_close_admin_endpoints_without_api_keys() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(Config::default().shared()));
+
+        for path in ["/audit-logs", "/admin/config"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status(), "{}", path);
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_replace_config_at_runtime() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            admin_keys(),
+        );
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/admin/config"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let config = Config::default().shared();
+        let app = app.layer(Extension(config.clone()));
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/admin/config"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let found: Config = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(Config::default(), found);
+
+        let invalid = r#"{ "cache_ttl_secs": 0, "chaos": { "failure_rate": 1, "latency_ms": "0" }, "log_level": "debug=loud" }"#;
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/config",
+            Method::PUT,
+            invalid.to_string(),
+        ));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let valid = r#"{ "cache_ttl_secs": 0, "chaos": { "failure_rate": 1, "latency_ms": "0" }, "log_level": "debug" }"#;
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/config",
+            Method::PUT,
+            valid.to_string(),
+        ));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(0, config.read().unwrap().cache_ttl_secs);
+        assert_eq!("debug", config.read().unwrap().log_level);
+
+        // 再起動しなくても次のリクエストから使われる
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/flaky"));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_switch_log_level() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            admin_keys(),
+        );
+        let debug = r#"{ "level": "debug", "duration_secs": 600 }"#;
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/log-level",
+            Method::PUT,
+            debug.to_string(),
+        ));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let log_filter = LogFilter::detached("info");
+        let config = Config::default().shared();
+        let app = app
+            .layer(Extension(log_filter.clone()))
+            .layer(Extension(config.clone()));
+        for invalid in [
+            r#"{ "level": "debug=loud" }"#,
+            r#"{ "level": "debug", "duration_secs": 0 }"#,
+        ] {
+            let req = as_admin(build_todo_req_with_json(
+                "/admin/log-level",
+                Method::PUT,
+                invalid.to_string(),
+            ));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                res.status(),
+                "{}",
+                invalid
+            );
+        }
+
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/log-level",
+            Method::PUT,
+            debug.to_string(),
+        ));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let changed: LogLevel = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("debug", changed.level);
+        assert_eq!(Some("info".to_string()), changed.revert_to);
+        assert!(changed.revert_at.is_some());
+        assert_eq!("debug", log_filter.current());
+        assert_eq!("debug", config.read().unwrap().log_level);
+    }
+
+    #[tokio::test]
+    async fn should_inject_chaos_from_query() {
+        let metrics = Arc::new(Metrics::default());
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(Config::default().shared()))
+        .layer(Extension(metrics.clone()));
+
+        for (path, status) in [
+            (
+                "/flaky?failure_rate=1&latency_ms=0",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            ("/flaky?failure_rate=0&latency_ms=0-5", StatusCode::OK),
+            ("/flaky?failure_rate=2", StatusCode::BAD_REQUEST),
+            ("/flaky?latency_ms=500-100", StatusCode::BAD_REQUEST),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+        let text = metrics.render();
+        assert!(text.contains("chaos_requests_total{outcome=\"failure\"} 1"));
+        assert!(text.contains("chaos_requests_total{outcome=\"success\"} 1"));
+        assert!(text.contains("chaos_injected_latency_seconds_count{} 2"));
+        assert!(
+            text.contains("http_requests_total{method=\"GET\",route=\"/flaky\",status=\"500\"} 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_inject_faults_into_matching_routes() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            admin_keys(),
+        );
+        let disabled = app
+            .clone()
+            .layer(Extension(Arc::new(FaultInjector::new(false))));
+        let app = app.layer(Extension(Arc::new(FaultInjector::new(true))));
+
+        let rules =
+            r#"{ "rules": [{ "path": "/todos*", "failure_rate": 1, "latency_ms": "0-5" }] }"#;
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/faults",
+            Method::PUT,
+            rules.to_string(),
+        ));
+        let res = disabled.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let invalid = r#"{ "rules": [{ "path": "/todos", "failure_rate": 2, "status": 200 }] }"#;
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/faults",
+            Method::PUT,
+            invalid.to_string(),
+        ));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/faults",
+            Method::PUT,
+            rules.to_string(),
+        ));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        for (path, status) in [
+            ("/todos", StatusCode::SERVICE_UNAVAILABLE),
+            ("/todos/1/history", StatusCode::SERVICE_UNAVAILABLE),
+            ("/labels", StatusCode::OK),
+            ("/admin/faults", StatusCode::OK),
+        ] {
+            let req = as_admin(build_todo_req_with_empty(Method::GET, path));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+
+        let req = as_admin(build_todo_req_with_empty(Method::DELETE, "/admin/faults"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/todos"));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_backup_and_restore() {
+        let dir =
+            std::env::temp_dir().join(format!("rust-simple-api-backup-{}", rand::random::<u64>()));
+        let snapshots = Arc::new(SnapshotRepositoryForMemory::new(Snapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: chrono::Utc::now(),
+            users: vec![],
+            projects: vec![],
+            labels: vec![Label::new(1, "backup".to_string())],
+            todos: vec![],
+            dependencies: vec![],
+            shares: vec![],
+        }));
+        let events = EventBus::default();
+        let mut subscription = events.subscribe();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            events,
+            admin_keys(),
+        );
+        let req = as_admin(build_todo_req_with_empty(Method::POST, "/admin/backup"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let app = app.layer(Extension(Arc::new(Backups::new(
+            snapshots.clone(),
+            Arc::new(LocalStorage::new(&dir)),
+        ))));
+        let req = as_admin(build_todo_req_with_empty(Method::POST, "/admin/backup"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: BackupSummary = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(SNAPSHOT_VERSION, summary.version);
+        assert_eq!(1, summary.labels);
+
+        let req = as_admin(build_todo_req_with_empty(Method::GET, "/admin/backups"));
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let list: BackupList = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![summary.name.clone()], list.backups);
+
+        for (name, status) in [
+            ("../example.env", StatusCode::UNPROCESSABLE_ENTITY),
+            ("backup-v1-missing.json", StatusCode::NOT_FOUND),
+        ] {
+            let req = as_admin(build_todo_req_with_json(
+                "/admin/restore",
+                Method::POST,
+                format!(r#"{{ "name": "{}" }}"#, name),
+            ));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", name);
+        }
+
+        snapshots
+            .restore(&Snapshot {
+                labels: vec![],
+                ..snapshots.export().await.unwrap()
+            })
+            .await
+            .unwrap();
+        let req = as_admin(build_todo_req_with_json(
+            "/admin/restore",
+            Method::POST,
+            format!(r#"{{ "name": "{}" }}"#, summary.name),
+        ));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(1, snapshots.export().await.unwrap().labels.len());
+        let published = subscription.recv().await.unwrap();
+        assert_eq!(
+            DomainEvent::SnapshotRestored { name: summary.name },
+            published.event
+        );
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_reject_while_circuit_is_open() {
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_secs(30)));
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(breaker.clone()))
+        .layer(Extension(Arc::new(Metrics::default())));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        breaker.record_failure();
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert_eq!("30", res.headers().get("retry-after").unwrap());
+
+        // データベースを使わないルートは止めない
+        let req = build_todo_req_with_empty(Method::GET, "/metrics");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_wrap_responses_in_envelope() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        )
+        .layer(Extension(ResponseEnvelope(true)));
+        for text in ["first", "second"] {
+            let body = format!(r#"{{ "text": "{}", "labels": [] }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?page=1&per_page=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["meta"]["total_count"], 2);
+        assert_eq!(body["meta"]["links"]["next"], "/todos?page=2&per_page=1");
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/999");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": { "status": 404, "message": "Not Found" } })
+        );
+
+        // ヘッダーでリクエストごとに無効にできる
+        let req = Request::builder()
+            .uri("/todos/999")
+            .header(X_ENVELOPE, "false")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_convert_json_field_case() {
+        let labels = LabelRepositoryForMemory::new();
+        let label = labels
+            .create(CreateLabel::new("home".to_string()))
+            .await
+            .unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![label.clone()]),
+            labels,
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let camel_app = app.clone().layer(Extension(JsonCase::Camel));
+
+        // camelCaseで送ったフィールドも受け取り、camelCaseで返す
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(
+                r#"{{ "text": "camel", "labels": [{}], "projectId": null, "parentId": null }}"#,
+                label.id
+            ),
+        );
+        let res = camel_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body.get("remindAt").is_some());
+        assert!(body.get("remind_at").is_none());
+        assert!(body["labels"][0].get("name").is_some());
+
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos?labelId={}", label.id));
+        let res = camel_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert!(body[0].get("projectId").is_some());
+
+        // 既定のsnake_caseのまま、ヘッダーでリクエストごとにcamelCaseにできる
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body[0].get("remind_at").is_some());
+
+        let req = Request::builder()
+            .uri("/todos")
+            .header(X_JSON_CASE, "camel")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body[0].get("remindAt").is_some());
+
+        // ヘッダーで既定のcamelCaseを無効にできる
+        let req = Request::builder()
+            .uri("/todos")
+            .header(X_JSON_CASE, "snake")
+            .body(Body::empty())
+            .unwrap();
+        let res = camel_app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body[0].get("project_id").is_some());
+    }
+
+    #[tokio::test]
+    async fn should_return_cycle_time_of_completed_todos() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "cycle", "labels": [] }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(todo.completed_at, None);
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}/status", todo.id),
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(todo.completed_at.is_some());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/stats/cycle-time");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let cycle_time: CycleTime = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(cycle_time.count, 1);
+        assert!(cycle_time.p95_seconds.is_some());
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos/stats/cycle-time?from=2999-01-01T00:00:00Z",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let cycle_time: CycleTime = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(cycle_time, CycleTime::default());
+
+        // 期間の前後が逆の場合は400
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos/stats/cycle-time?from=2024-02-01T00:00:00Z&to=2024-01-01T00:00:00Z",
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_report_burndown_of_project() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            ApiKeys::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/projects",
+            Method::POST,
+            r#"{ "name": "release" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        for (id, estimate) in [(1, 30), (2, 15), (3, 60)] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "task", "labels": [], "estimate_minutes": {} }}"#,
+                    estimate
+                ),
+            );
+            let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+            assert_eq!(Some(estimate), todo.estimate_minutes);
+            let req = build_todo_req_with_json(
+                &format!("/todos/{}/project", id),
+                Method::PATCH,
+                r#"{ "project_id": 1 }"#.to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "estimate_minutes": null }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(None, todo.estimate_minutes);
+        let req = build_todo_req_with_json(
+            "/todos/3",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/reports/burndown?project_id=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let report: BurndownReport = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(14, report.series.len());
+        let today = report.series.last().unwrap();
+        assert_eq!(chrono::Utc::now().date_naive(), today.date);
+        assert_eq!((15, 2), (today.remaining_minutes, today.open_todos));
+        assert_eq!(0, report.series[0].open_todos);
+
+        let req = build_todo_req_with_empty(Method::GET, "/reports/burndown?project_id=2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        for query in [
+            "project_id=1&from=2024-02-01&to=2024-01-01",
+            "project_id=1&from=2023-01-01&to=2024-12-31",
+        ] {
+            let req =
+                build_todo_req_with_empty(Method::GET, &format!("/reports/burndown?{}", query));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "task", "labels": [], "estimate_minutes": 0 }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_run_weekly_digest_on_demand() {
+        let api_keys = ApiKeys::parse("admin:a-key:admin").expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        let req = build_req_with_api_key(Method::POST, "/admin/digest/run", "a-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let notifier = Arc::new(NotifierForMemory::default());
+        let app = app.layer(Extension(notifier.clone() as Arc<dyn Notifier>));
+        let due_at = chrono::Utc::now() + chrono::Duration::days(2);
+        for text in ["due soon", "no due"] {
+            let body = format!(
+                r#"{{ "text": "{}", "labels": [], "due_at": "{}" }}"#,
+                text,
+                due_at.to_rfc3339()
+            );
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, "a-key");
+            let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+            assert_eq!(Some(due_at), todo.due_at);
+        }
+        let req = build_json_req_with_api_key(
+            Method::PATCH,
+            "/todos/2",
+            r#"{ "due_at": null }"#,
+            "a-key",
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(None, todo.due_at);
+
+        let req = build_req_with_api_key(Method::POST, "/admin/digest/run", "a-key");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let run: DigestRun = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(DigestRun { sent: 1, failed: 0 }, run);
+        let sent = notifier.sent.lock().unwrap();
+        assert_eq!("Weekly digest for workspace", sent[0].subject);
+        assert!(sent[0].body.contains("- #1 due soon"));
+        assert!(!sent[0].body.contains("no due"));
+    }
+
+    #[tokio::test]
+    async fn should_keep_preferences_of_me() {
+        let user_repository = UserRepositoryForMemory::new();
+        user_repository
+            .create("alice".to_string())
+            .await
+            .expect("failed create user");
+        let api_keys = ApiKeys::parse("admin:a-key:admin,alice:v-key:viewer,bob:b-key:viewer")
+            .expect("failed parse api keys");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            AuditLogRepositoryForMemory::new(),
+            user_repository,
+            ProjectRepositoryForMemory::new(),
+            ViewRepositoryForMemory::new(),
+            TemplateRepositoryForMemory::new(),
+            ShareLinkRepositoryForMemory::new(),
+            EventBus::default(),
+            api_keys,
+        );
+        for days in [3, 1] {
+            let body = format!(
+                r#"{{ "text": "todo", "labels": [], "due_at": "{}" }}"#,
+                (chrono::Utc::now() + chrono::Duration::days(days)).to_rfc3339()
+            );
+            let req = build_json_req_with_api_key(Method::POST, "/todos", &body, "a-key");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let me = |res: Response| async {
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            serde_json::from_slice::<Me>(&bytes).unwrap()
+        };
+
+        // ユーザーとして登録されていない主体は設定を保存できない
+        let req = build_req_with_api_key(Method::GET, "/me", "b-key");
+        let bob = me(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!((None, Preferences::default()), (bob.id, bob.preferences));
+        let req = build_json_req_with_api_key(Method::PATCH, "/me", "{}", "b-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let body = r#"{ "preferences": { "default_sort": "oldest", "timezone": "Asia/Tokyo", "locale": "ja" } }"#;
+        let req = build_json_req_with_api_key(Method::PATCH, "/me", body, "v-key");
+        let alice = me(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(Some(1), alice.id);
+        assert_eq!(Role::Viewer, alice.role);
+        assert_eq!(Some(TodoSort::Oldest), alice.preferences.default_sort);
+
+        let body = r#"{ "preferences": { "timezone": "../etc/passwd" } }"#;
+        let req = build_json_req_with_api_key(Method::PATCH, "/me", body, "v-key");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = r#"{ "preferences": { "timezone": null } }"#;
+        let req = build_json_req_with_api_key(Method::PATCH, "/me", body, "v-key");
+        let alice = me(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(
+            Preferences {
+                default_sort: Some(TodoSort::Oldest),
+                timezone: None,
+                locale: Some(Locale::Ja),
+            },
+            alice.preferences
+        );
+        let req = build_req_with_api_key(Method::GET, "/me", "v-key");
+        assert_eq!(alice, me(app.clone().oneshot(req).await.unwrap()).await);
+
+        // 一覧は指定がなければ設定の並び順で返す
+        for (path, key, expected) in [
+            ("/todos", "v-key", vec![1, 2]),
+            ("/todos?sort=due", "v-key", vec![2, 1]),
+            ("/todos?sort=newest", "a-key", vec![2, 1]),
+        ] {
+            let req = build_req_with_api_key(Method::GET, path, key);
+            let todos = res_to_todos(app.clone().oneshot(req).await.unwrap()).await;
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(expected, ids, "{}", path);
+        }
+    }
+
+    // 任意のJSONを更新リクエストとして送り、ハンドラーから記憶領域のリポジトリまでのどこでもpanicしないことを確かめる
+    mod update_payload {
+        use super::*;
+        use proptest::collection::{hash_map, vec};
+        use proptest::prelude::*;
+        use serde_json::Value;
+
+        fn value_strategy() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::from),
+                any::<i64>().prop_map(Value::from),
+                any::<f64>().prop_map(Value::from),
+                any::<String>().prop_map(Value::from),
+            ];
+            leaf.prop_recursive(3, 32, 8, |inner| {
+                prop_oneof![
+                    vec(inner.clone(), 0..12).prop_map(Value::Array),
+                    hash_map("[a-z]{1,6}", inner, 0..4)
+                        .prop_map(|map| Value::Object(map.into_iter().collect())),
+                ]
+            })
+        }
+
+        // 多くは型の合う値にして、検証やリポジトリまで届くようにする
+        fn field_strategy(
+            typed: impl Strategy<Value = Value> + 'static,
+        ) -> impl Strategy<Value = Option<Value>> {
+            proptest::option::of(prop_oneof![4 => typed, 1 => value_strategy()])
+        }
+
+        fn payload_strategy() -> impl Strategy<Value = String> {
+            let text =
+                prop_oneof!["\\PC{0,120}", "\\s{0,3}", any::<String>()].prop_map(Value::from);
+            let status = prop::sample::select(vec!["backlog", "in_progress", "done", "cancelled"])
+                .prop_map(Value::from);
+            // 既存のラベルのidと存在しないidを混ぜる
+            let labels =
+                vec(prop_oneof![Just(999), -1..3, Just(i32::MAX)], 0..4).prop_map(Value::from);
+            let tags = vec("\\PC{0,40}", 0..12).prop_map(Value::from);
+            let object = (
+                field_strategy(text),
+                field_strategy(any::<bool>().prop_map(Value::from)),
+                field_strategy(status),
+                field_strategy(labels),
+                field_strategy(tags),
+                proptest::option::of(("[a-z_]{1,8}", value_strategy())),
+            )
+                .prop_map(|(text, completed, status, labels, tags, unknown)| {
+                    let mut object = serde_json::Map::new();
+                    for (name, value) in [
+                        ("text", text),
+                        ("completed", completed),
+                        ("status", status),
+                        ("labels", labels),
+                        ("tags", tags),
+                    ] {
+                        if let Some(value) = value {
+                            object.insert(name.to_string(), value);
+                        }
+                    }
+                    if let Some((name, value)) = unknown {
+                        object.insert(name, value);
+                    }
+                    Value::Object(object).to_string()
+                });
+            prop_oneof![
+                8 => object,
+                1 => value_strategy().prop_map(|value| value.to_string()),
+                1 => any::<String>(),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn should_not_panic_on_any_update_payload(
+                payload in payload_strategy(),
+                path in prop::sample::select(vec!["/todos/1", "/todos/2", "/todos/1?cascade=true"]),
+            ) {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                let status = runtime.block_on(async {
+                    let (labels, label_ids) = label_fixture();
+                    let todo_repository = TodoRepositoryForMemory::new(labels);
+                    todo_repository
+                        .create(CreateTodo::new("fuzzed".to_string(), label_ids))
+                        .await
+                        .expect("failed create todo");
+                    let req = build_todo_req_with_json(path, Method::PATCH, payload.clone());
+                    let res = create_app(
+                        todo_repository,
+                        LabelRepositoryForMemory::new(),
+                        AuditLogRepositoryForMemory::new(),
+                        UserRepositoryForMemory::new(),
+                        ProjectRepositoryForMemory::new(),
+                        ViewRepositoryForMemory::new(),
+                        TemplateRepositoryForMemory::new(),
+                        ShareLinkRepositoryForMemory::new(),
+                        EventBus::default(),
+                        ApiKeys::default(),
+                    )
+                    .oneshot(req)
+                    .await
+                    .unwrap();
+                    res.status()
+                });
+                prop_assert!(!status.is_server_error(), "{} for {}", status, payload);
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::metrics::{Metrics, RepositoryTimings};
+use crate::trace_context::TraceContext;
+use axum::body::{boxed, Body, Bytes, Full, HttpBody};
+use axum::extract::MatchedPath;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{Instrument, Level};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+use uuid::{Builder, Uuid, Variant, Version};
+
+// これより大きいボディはログに出さない
+const MAX_LOGGED_BODY: usize = 1024;
+
+// リクエストを識別するヘッダー
+// 指定されていなければ生成し、レスポンスにも付けて返す
+pub const X_REQUEST_ID: &str = "x-request-id";
+
+// これより長いリクエストIDは受け付けずに生成し直す
+const MAX_REQUEST_ID: usize = 128;
+
+// 内側のミドルウェアやハンドラへ渡すリクエストID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+// ログの出力形式
+// LOG_FORMAT="json|pretty|compact" で指定し、未定義の場合は従来の形式で出力する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Unknown log format: [{0}]")]
+pub struct UnknownLogFormat(String);
+
+impl FromStr for LogFormat {
+    type Err = UnknownLogFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(UnknownLogFormat(s.to_string())),
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("LOG_FORMAT") {
+            Ok(format) if !format.is_empty() => Ok(format.parse()?),
+            _ => Ok(LogFormat::default()),
+        }
+    }
+
+    // RUST_LOGのレベルでログの出力を始める
+    // JSONではリクエストIDなどのスパンのフィールドも項目として出力する
+    // 返したLogFilterで実行中にレベルを変えられる
+    // リポジトリの呼び出しにかかった時間はmetricsに記録する
+    pub fn init(self, metrics: Arc<Metrics>) -> anyhow::Result<LogFilter> {
+        let fmt = tracing_subscriber::fmt::layer();
+        let fmt = match self {
+            LogFormat::Full => fmt.boxed(),
+            LogFormat::Pretty => fmt.pretty().boxed(),
+            LogFormat::Compact => fmt.compact().boxed(),
+            LogFormat::Json => fmt
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        };
+        let directives = env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+        let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+        let registry = tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt)
+            .with(RepositoryTimings::new(metrics));
+        #[cfg(feature = "otel")]
+        let registry = registry.with(crate::telemetry::layer()?);
+        registry.try_init()?;
+        Ok(LogFilter::new(handle, directives))
+    }
+}
+
+// 出力するログのレベルを差し替える
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    // 差し替えるたびに世代を進め、一時的な変更を戻すときに後からの変更を上書きしないようにする
+    current: Arc<RwLock<(u64, String)>>,
+}
+
+impl LogFilter {
+    fn new(handle: reload::Handle<EnvFilter, Registry>, directives: String) -> Self {
+        LogFilter {
+            handle,
+            current: Arc::new(RwLock::new((0, directives))),
+        }
+    }
+
+    // 出力先に繋がず、フィルターだけを持つ
+    // レイヤーを破棄すると差し替えられなくなるため、テストの間は残しておく
+    #[cfg(test)]
+    pub(crate) fn detached(directives: &str) -> Self {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new(directives));
+        std::mem::forget(layer);
+        LogFilter::new(handle, directives.to_string())
+    }
+
+    pub fn current(&self) -> String {
+        self.current.read().unwrap().1.clone()
+    }
+
+    // RUST_LOGと同じ書式で指定し、差し替えた世代を返す
+    pub fn set(&self, directives: &str) -> anyhow::Result<u64> {
+        let mut current = self.current.write().unwrap();
+        self.replace(&mut current, directives)
+    }
+
+    // 指定した世代のまま変わっていなければ差し替える
+    pub fn set_if_unchanged(&self, generation: u64, directives: &str) -> anyhow::Result<bool> {
+        let mut current = self.current.write().unwrap();
+        if current.0 != generation {
+            return Ok(false);
+        }
+        self.replace(&mut current, directives)?;
+        Ok(true)
+    }
+
+    fn replace(&self, current: &mut (u64, String), directives: &str) -> anyhow::Result<u64> {
+        self.handle.reload(EnvFilter::try_new(directives)?)?;
+        current.0 += 1;
+        current.1 = directives.to_string();
+        Ok(current.0)
+    }
+}
+
+// ルーティングで確定したパス
+// ログやメトリクスのラベルにはIDを含まないこちらを使う
+#[derive(Debug, Clone)]
+struct MatchedRoute(String);
+
+// ルーティング後にだけ分かるマッチしたパスを、外側のミドルウェアへレスポンス経由で渡す
+pub async fn track_route(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    #[cfg(feature = "sentry")]
+    if let Some(route) = &route {
+        crate::reporting::set_route(route);
+    }
+    let mut res = next.run(req).await;
+    if let Some(route) = route {
+        res.extensions_mut().insert(MatchedRoute(route));
+    }
+    res
+}
+
+// 受け取ったリクエストIDが使えなければ新しく生成する
+fn request_id(req: &Request<Body>) -> String {
+    req.headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID)
+        .map(str::to_string)
+        .unwrap_or_else(|| generate_request_id().to_string())
+}
+
+fn generate_request_id() -> Uuid {
+    Builder::from_bytes(rand::random())
+        .set_variant(Variant::RFC4122)
+        .set_version(Version::Random)
+        .build()
+}
+
+// リクエストごとにIDを付けたスパンの中で処理し、その間のログをまとめて追えるようにする
+pub async fn log_requests(mut req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let request_id = request_id(&req);
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    #[cfg_attr(not(feature = "otel"), allow(unused_mut))]
+    let mut trace = TraceContext::from_headers(req.headers());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        trace_id = tracing::field::Empty,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+    #[cfg(feature = "otel")]
+    {
+        crate::telemetry::set_remote_parent(&span, req.headers());
+        crate::telemetry::use_span_ids(&span, &mut trace);
+    }
+    span.record("trace_id", trace.trace_id().as_str());
+    let mut res = trace
+        .scope(observe_request(req, next))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    res
+}
+
+async fn observe_request(req: Request<Body>, next: Next<Body>) -> Response {
+    let metrics = req.extensions().get::<Arc<Metrics>>().cloned();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let start = Instant::now();
+
+    let res = if tracing::enabled!(Level::DEBUG) {
+        // ボディを読み切ってログに出してから詰め直す
+        let (parts, body) = req.into_parts();
+        let bytes = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        tracing::debug!("{} {} request body: {}", method, path, redact(&bytes));
+        let res = next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+        // 流し続けるレスポンスや長さの分からないレスポンスは、溜め込まないよう読まずに流す
+        // 長さが分かっていても出さない大きさのものは読まずに大きさだけ出す
+        match response_length(&res) {
+            _ if is_event_stream(&res) => res,
+            None => {
+                tracing::debug!("{} {} response body: <streamed>", method, path);
+                res
+            }
+            Some(length) if length > MAX_LOGGED_BODY as u64 => {
+                tracing::debug!(
+                    "{} {} response body: <{} bytes redacted>",
+                    method,
+                    path,
+                    length
+                );
+                res
+            }
+            Some(_) => {
+                let (parts, body) = res.into_parts();
+                let bytes = match hyper::body::to_bytes(body).await {
+                    Ok(bytes) => bytes,
+                    Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                };
+                tracing::debug!("{} {} response body: {}", method, path, redact(&bytes));
+                Response::from_parts(parts, boxed(Full::from(bytes)))
+            }
+        }
+    } else {
+        next.run(req).await
+    };
+
+    let elapsed = start.elapsed();
+    let status = res.status();
+    tracing::info!(
+        "{} {} {} {}ms",
+        method,
+        path,
+        status.as_u16(),
+        elapsed.as_millis()
+    );
+    if let Some(metrics) = metrics {
+        let route = res
+            .extensions()
+            .get::<MatchedRoute>()
+            .map(|route| route.0.as_str())
+            .unwrap_or("unmatched");
+        metrics.observe_request(&method, route, status, elapsed);
+    }
+    res
+}
+
+// Content-Lengthかボディから分かるレスポンスの長さ
+fn response_length(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or_else(|| res.body().size_hint().exact())
+}
+
+fn is_event_stream(res: &Response) -> bool {
+    res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(mime::TEXT_EVENT_STREAM.as_ref()))
+}
+
+fn redact(bytes: &Bytes) -> String {
+    if bytes.len() > MAX_LOGGED_BODY {
+        format!("<{} bytes redacted>", bytes.len())
+    } else {
+        String::from_utf8_lossy(bytes).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::StreamBody;
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use axum::Router;
+    use futures_util::stream::{self, StreamExt};
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[test]
+    fn should_parse_log_format() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("compact".parse(), Ok(LogFormat::Compact));
+        assert_eq!(
+            "yaml".parse::<LogFormat>(),
+            Err(UnknownLogFormat("yaml".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_keep_or_generate_request_id() {
+        let req = Request::builder()
+            .header(X_REQUEST_ID, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(request_id(&req), "abc-123");
+
+        let req = Request::builder()
+            .header(X_REQUEST_ID, "a".repeat(MAX_REQUEST_ID + 1))
+            .body(Body::empty())
+            .unwrap();
+        let id = request_id(&req);
+        assert_eq!(
+            Uuid::parse_str(&id).unwrap().get_version(),
+            Some(Version::Random)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_buffer_streamed_response_body() {
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::DEBUG)
+            .with_test_writer()
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        // 最初の塊のあとは終わらないボディ
+        let app = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    let body =
+                        stream::once(async { Ok::<_, Infallible>("[") }).chain(stream::pending());
+                    StreamBody::new(body)
+                }),
+            )
+            .route("/small", get(|| async { "small" }))
+            .layer(from_fn(log_requests));
+
+        let req = Request::builder()
+            .uri("/stream")
+            .body(Body::empty())
+            .unwrap();
+        let res = tokio::time::timeout(Duration::from_secs(1), app.clone().oneshot(req))
+            .await
+            .expect("response body is buffered")
+            .unwrap();
+        let mut body = res.into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "[");
+
+        let req = Request::builder()
+            .uri("/small")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(bytes, "small");
+    }
+
+    #[test]
+    fn should_redact_large_body() {
+        let small = Bytes::from_static(b"{\"text\":\"small\"}");
+        assert_eq!(redact(&small), "{\"text\":\"small\"}");
+
+        let large = Bytes::from(vec![b'a'; MAX_LOGGED_BODY + 1]);
+        assert_eq!(redact(&large), "<1025 bytes redacted>");
+    }
+}
@@ -1,257 +1,629 @@
-mod handlers;
-mod repositories;
-
-use crate::handlers::label::{all_label, create_label, delete_label};
-use crate::handlers::todo::{
-    all_todos, create_todo, delete_todo, find_todo, flaky, root, update_todo,
-};
-use crate::repositories::labels::{LabelRepository, LabelRepositoryForDb};
-use crate::repositories::todo::{TodoRepository, TodoRepositoryForDb};
-use axum::routing::delete;
-use axum::{extract::Extension, routing::get, routing::post, Router};
+use axum::extract::Extension;
+#[cfg(feature = "test-utils")]
+use axum::middleware::from_fn;
+use axum::Router;
 use dotenv::dotenv;
-use hyper::header::CONTENT_TYPE;
+use rust_simple_api::auth::ApiKeys;
+use rust_simple_api::backup::{
+    backup_dir_from_env, backup_interval_from_env, spawn_backup_scheduler, Backups, LocalStorage,
+    SnapshotRepositoryForDb,
+};
+use rust_simple_api::cache::{cache_ttl_from_env, spawn_invalidation_subscriber, Cached};
+use rust_simple_api::cache_control::CacheControl;
+use rust_simple_api::casing::JsonCase;
+use rust_simple_api::chaos::{Chaos, FaultInjector};
+use rust_simple_api::circuit_breaker::{CircuitBreaker, CircuitBreaking};
+use rust_simple_api::cli::{self, Command, USAGE};
+use rust_simple_api::concurrency::ConcurrencyLimits;
+use rust_simple_api::config::{Config, SharedConfig};
+use rust_simple_api::create_apps;
+use rust_simple_api::demo::DemoMode;
+#[cfg(feature = "test-utils")]
+use rust_simple_api::demo::{guard_demo, spawn_demo_reset, DemoGuard, DemoStore};
+use rust_simple_api::digest::{digest_interval_from_env, spawn_digest_scheduler};
+use rust_simple_api::email::EmailIntegration;
+use rust_simple_api::envelope::ResponseEnvelope;
+use rust_simple_api::events::{spawn_assignment_notifier, spawn_webhook_subscriber, EventBus};
+use rust_simple_api::github::GithubIntegration;
+use rust_simple_api::handlers::todo::DedupeTodos;
+use rust_simple_api::logging::LogFormat;
+use rust_simple_api::metrics::{spawn_pool_sampler, Metrics};
+use rust_simple_api::notifier::notifier_from_env;
+use rust_simple_api::quota::{DailyQuotas, Quota};
+#[cfg(feature = "redis")]
+use rust_simple_api::redis_cache;
+#[cfg(feature = "sentry")]
+use rust_simple_api::reporting;
+use rust_simple_api::repositories::audit::AuditLogRepositoryForDb;
+use rust_simple_api::repositories::database::cancellable;
+use rust_simple_api::repositories::github_links::GithubLinkRepositoryForDb;
+use rust_simple_api::repositories::labels::LabelRepositoryForDb;
+use rust_simple_api::repositories::projects::ProjectRepositoryForDb;
+use rust_simple_api::repositories::schema::{verify_id_type, verify_schema};
+use rust_simple_api::repositories::share_links::ShareLinkRepositoryForDb;
+use rust_simple_api::repositories::templates::TemplateRepositoryForDb;
+use rust_simple_api::repositories::todo::event_sourced::{
+    TodoEventStoreForDb, TodoRepositoryEventSourced, TodoStore,
+};
+use rust_simple_api::repositories::todo::{
+    max_labels_from_env, TodoRepository, TodoRepositoryForDb,
+};
+#[cfg(feature = "test-utils")]
+use rust_simple_api::repositories::usage::test_utils::UsageRepositoryForMemory;
+#[cfg(not(feature = "redis"))]
+use rust_simple_api::repositories::usage::UsageRepositoryForDb;
+#[cfg(feature = "redis")]
+use rust_simple_api::repositories::usage::UsageRepositoryForRedis;
+use rust_simple_api::repositories::users::UserRepositoryForDb;
+use rust_simple_api::repositories::views::ViewRepositoryForDb;
+use rust_simple_api::repositories::{DatabaseOptions, EntityId, IdType, Replica};
+use rust_simple_api::scheduler::spawn_reminder_scheduler;
+use rust_simple_api::security_headers::{SecurityHeaders, SecurityHeadersLayer};
+use rust_simple_api::slack::SlackIntegration;
+use rust_simple_api::startup::{required_env, StartupError};
+#[cfg(feature = "otel")]
+use rust_simple_api::telemetry;
+use rust_simple_api::timeout::Timeouts;
+use rust_simple_api::unit_of_work::{Transactional, UnitOfWorkForDb};
 use sqlx::PgPool;
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer, Origin};
+use std::time::Duration;
+use tower::ServiceBuilder;
+use uuid::Uuid;
 
+// 起動に失敗した場合はエラーの内容を表示して0以外の終了コードで終了する
 #[tokio::main]
-async fn main() {
-    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
+async fn main() -> anyhow::Result<()> {
     dotenv().ok();
+    let command =
+        Command::parse(env::args().skip(1)).map_err(|e| anyhow::anyhow!("{}\n{}", e, USAGE))?;
+    if command != Command::Serve {
+        return run_command(command).await;
+    }
+    #[cfg(feature = "sentry")]
+    let _sentry = reporting::init();
+    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
+    env::set_var("RUST_LOG", &log_level);
+    let metrics = Arc::new(Metrics::default());
+    let log_filter = LogFormat::from_env()
+        .map_err(StartupError::invalid("LOG_FORMAT"))?
+        .init(metrics.clone())
+        .map_err(StartupError::invalid("OTEL_EXPORTER_OTLP_ENDPOINT"))?;
 
-    let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-
-    tracing::debug!("start connect database...");
+    let api_keys = ApiKeys::from_env().map_err(StartupError::invalid("API_KEYS"))?;
+    if !api_keys.is_enabled() {
+        tracing::warn!(
+            "[API_KEYS] is undefined, authentication is disabled and admin endpoints are closed"
+        );
+    }
 
-    let pool = PgPool::connect(database_url)
-        .await
-        .unwrap_or_else(|_| panic!("fail conect database ,url is [{}]", database_url));
+    let admin_addr = admin_addr_from_env().map_err(StartupError::invalid("ADMIN_ADDR"))?;
+    let timeouts = Timeouts::from_env().map_err(StartupError::invalid("REQUEST_TIMEOUT_SECS"))?;
+    let concurrency_limits =
+        ConcurrencyLimits::from_env().map_err(StartupError::invalid("CONCURRENCY_LIMIT_ROUTES"))?;
+    let cache_control =
+        CacheControl::from_env().map_err(StartupError::invalid("CACHE_CONTROL_ROUTES"))?;
+    let security_headers =
+        SecurityHeaders::from_env().map_err(StartupError::invalid("CONTENT_SECURITY_POLICY"))?;
+    let quotas = DailyQuotas::from_env().map_err(StartupError::invalid("DAILY_QUOTA"))?;
+    let chaos = Chaos::from_env().map_err(StartupError::invalid("CHAOS_FAILURE_RATE"))?;
+    let cache_ttl = cache_ttl_from_env().map_err(StartupError::invalid("TODO_CACHE_TTL_SECS"))?;
+    let slack = SlackIntegration::from_env()
+        .map_err(StartupError::invalid("SLACK_USERS"))?
+        .map(Arc::new);
+    let email = EmailIntegration::from_env()
+        .map_err(StartupError::invalid("INBOUND_EMAIL_USERS"))?
+        .map(Arc::new);
+    // 実行中に PUT /admin/config で変えられる設定
+    let config = Config {
+        cache_ttl_secs: cache_ttl.as_secs(),
+        chaos,
+        log_level,
+    }
+    .shared();
 
-    let app = create_app(
-        TodoRepositoryForDb::new(pool.clone()),
-        LabelRepositoryForDb::new(pool.clone()),
-    );
+    // データベースが落ちている間はリクエストを積み上げずに503を返す
+    let breaker = Arc::new(CircuitBreaker::default());
+    let demo = DemoMode::from_env().map_err(StartupError::invalid("DEMO_MODE"))?;
+    let Apps {
+        app,
+        admin,
+        backups,
+        quota,
+    } = match demo {
+        Some(demo) => build_demo_apps(demo, api_keys, admin_addr.is_some(), quotas).await?,
+        None => {
+            build_db_apps(
+                breaker.clone(),
+                metrics.clone(),
+                config.clone(),
+                api_keys,
+                admin_addr.is_some(),
+                quotas,
+            )
+            .await?
+        }
+    };
+    // 公開用と運用用のどちらにも同じ設定を渡す
+    let extensions = ServiceBuilder::new()
+        .layer(Extension(metrics))
+        .layer(Extension(breaker))
+        .layer(Extension(Arc::new(FaultInjector::from_env())))
+        .layer(Extension(log_filter))
+        .layer(Extension(config))
+        .layer(Extension(ResponseEnvelope::from_env()))
+        .layer(Extension(JsonCase::from_env()))
+        .layer(Extension(DedupeTodos::from_env()))
+        .layer(Extension(Arc::new(cache_control)))
+        .layer(Extension(Arc::new(concurrency_limits)))
+        .layer(Extension(Arc::new(timeouts)))
+        .layer(Extension(Arc::new(quota)));
+    let with_layers = |app: Router| {
+        let app = app.layer(extensions.clone());
+        let app = match &backups {
+            Some(backups) => app.layer(Extension(backups.clone())),
+            None => app,
+        };
+        let app = match &slack {
+            Some(slack) => app.layer(Extension(slack.clone())),
+            None => app,
+        };
+        let app = match &email {
+            Some(email) => app.layer(Extension(email.clone())),
+            None => app,
+        };
+        // ルーティングできなかった応答にもセキュリティヘッダーを付けるため、一番外側に重ねる
+        app.layer(SecurityHeadersLayer::new(security_headers.clone()))
+    };
+    let app = with_layers(app);
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::debug!("listening on {}", addr);
+    let admin = admin_addr.zip(admin.map(with_layers));
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let served = tokio::try_join!(listen(addr, app), async {
+        match admin {
+            Some((admin_addr, admin)) => listen(admin_addr, admin).await,
+            None => Ok(()),
+        }
+    });
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+    served?;
+    Ok(())
 }
 
-fn create_app<Todo: TodoRepository, Label: LabelRepository>(
-    todo_repository: Todo,
-    label_repository: Label,
-) -> Router {
-    Router::new()
-        .route("/", get(root))
-        .route("/todos", post(create_todo::<Todo>).get(all_todos::<Todo>))
-        .route(
-            "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>),
-        )
-        .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>),
-        )
-        .route("labels/:id", delete(delete_label::<Label>))
-        .route("/flaky", get(flaky))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Origin::exact("http://localhost:5173".parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
-        )
+// 各アプリと、アプリに共通で重ねる設定のうち保存先によって変わるもの
+struct Apps {
+    app: Router,
+    admin: Option<Router>,
+    backups: Option<Arc<Backups>>,
+    quota: Quota,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
-    use crate::repositories::labels::Label;
-    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
-    use crate::repositories::todo::{CreateTodo, TodoEntity};
-    use axum::http::{Method, StatusCode};
-    use axum::response::Response;
-    use axum::{body::Body, http::Request};
-    use tower::ServiceExt;
+// データベースに保存するアプリを組み立てる
+async fn build_db_apps(
+    breaker: Arc<CircuitBreaker>,
+    metrics: Arc<Metrics>,
+    config: SharedConfig,
+    api_keys: ApiKeys,
+    separate_admin: bool,
+    quotas: DailyQuotas,
+) -> anyhow::Result<Apps> {
+    tracing::debug!("start connect database...");
 
-    #[tokio::test]
-    async fn should_return_hello_world() {
-        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
-        let res = create_app(
-            TodoRepositoryForMemory::new(vec![]),
-            LabelRepositoryForMemory::new(),
-        )
-        .oneshot(req)
+    let database_options =
+        DatabaseOptions::from_env().map_err(StartupError::invalid("DATABASE_SLOW_STATEMENT_MS"))?;
+    let pool = connect_database(&database_options).await?;
+    verify_schema(&pool)
         .await
-        .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        assert_eq!(body, "Hello, World!");
-    }
+        .map_err(|source| StartupError::OutdatedSchema { source })?;
+    let id_type = IdType::from_env().map_err(StartupError::invalid("TODO_ID_TYPE"))?;
+    verify_id_type(&pool, id_type)
+        .await
+        .map_err(StartupError::invalid("TODO_ID_TYPE"))?;
+    // プールが埋まってリクエストがタイムアウトし始める前に気付けるよう、定期的に記録する
+    spawn_pool_sampler(
+        pool.clone(),
+        database_options.max_connections(),
+        metrics.clone(),
+        Duration::from_secs(15),
+    );
 
-    fn label_fixture() -> (Vec<Label>, Vec<i32>) {
-        let id = 999;
-        (
-            vec![Label {
-                id,
-                name: String::from("test label"),
-            }],
-            vec![id],
-        )
-    }
+    // 読み込みを送る複製。未指定の場合はすべて主に送る
+    let replica = match env::var("DATABASE_REPLICA_URL") {
+        Ok(replica_url) if !replica_url.is_empty() => Some(Replica::connect_lazy(
+            database_options
+                .connect_options(&replica_url)
+                .map_err(StartupError::invalid("DATABASE_REPLICA_URL"))?,
+        )),
+        _ => None,
+    };
 
-    #[tokio::test]
-    async fn should_created_todo() {
-        let (labels, _label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_return_created_todo".to_string(), labels.clone());
-        let req = build_todo_req_with_json(
-            "/todos",
-            Method::POST,
-            r#"{ "text": "should_return_created_todo", "labels": [999] }"#.to_string(),
-        );
-        // oneshotは擬似リクエストを送る
-        let res = create_app(
-            TodoRepositoryForMemory::new(labels),
-            LabelRepositoryForMemory::new(),
-        )
-        .oneshot(req)
-        .await
-        .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    let max_labels = max_labels_from_env().map_err(StartupError::invalid("TODO_MAX_LABELS"))?;
+    let store = TodoStore::from_env().map_err(StartupError::invalid("TODO_STORE"))?;
+    // イベントから組み立てる場合はtodosテーブルを使わないので、バックアップもしない
+    // スナップショットは連番のidで書き出すので、UUIDのidの場合もしない
+    let backups = match (store, id_type) {
+        (TodoStore::Table, IdType::Serial) => Some(Arc::new(Backups::new(
+            Arc::new(SnapshotRepositoryForDb::new(pool.clone())),
+            Arc::new(LocalStorage::new(backup_dir_from_env())),
+        ))),
+        _ => None,
+    };
+    let backup_interval =
+        backup_interval_from_env().map_err(StartupError::invalid("BACKUP_INTERVAL_SECS"))?;
+    let stores = Stores {
+        pool: &pool,
+        store,
+        replica,
+        max_labels,
+    };
+    let (app, admin) = match id_type {
+        IdType::Serial => {
+            build_store_apps::<i32>(stores, breaker, metrics, config, api_keys, separate_admin)
+                .await?
+        }
+        IdType::Uuid => {
+            build_store_apps::<Uuid>(stores, breaker, metrics, config, api_keys, separate_admin)
+                .await?
+        }
+    };
+    // 複数のインスタンスで動かす場合はリクエスト数もRedisで共有する
+    #[cfg(feature = "redis")]
+    let usage = UsageRepositoryForRedis::connect(
+        &redis::Client::open(required_env("REDIS_URL")?)
+            .map_err(StartupError::invalid("REDIS_URL"))?,
+    )
+    .await
+    .map_err(StartupError::connect("redis"))?;
+    #[cfg(not(feature = "redis"))]
+    let usage = UsageRepositoryForDb::new(pool.clone());
+    if let (Some(backups), Some(period)) = (&backups, backup_interval) {
+        spawn_backup_scheduler(backups.clone(), period);
     }
+    Ok(Apps {
+        app,
+        admin,
+        backups,
+        quota: Quota::new(quotas, usage),
+    })
+}
 
-    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-            .body(Body::from(json_body))
-            .unwrap()
+// データベースを使わず、メモリ上の見本のデータで公開用のデモを動かす
+#[cfg(feature = "test-utils")]
+async fn build_demo_apps(
+    demo: DemoMode,
+    api_keys: ApiKeys,
+    separate_admin: bool,
+    quotas: DailyQuotas,
+) -> anyhow::Result<Apps> {
+    tracing::warn!("[DEMO_MODE] is enabled, data is kept in memory and reset periodically");
+    let store = DemoStore::new();
+    store.reset().await?;
+    if let Some(period) = demo.reset_interval {
+        spawn_demo_reset(store.clone(), period);
     }
+    let (app, admin) = store.create_apps(EventBus::default(), api_keys, separate_admin);
+    let guard = Arc::new(DemoGuard::new(demo));
+    let with_guard = |router: Router| {
+        router
+            .layer(from_fn(guard_demo))
+            .layer(Extension(guard.clone()))
+    };
+    Ok(Apps {
+        app: with_guard(app),
+        admin: admin.map(with_guard),
+        backups: None,
+        quota: Quota::new(quotas, UsageRepositoryForMemory::new()),
+    })
+}
 
-    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .body(Body::empty())
-            .unwrap()
-    }
+#[cfg(not(feature = "test-utils"))]
+async fn build_demo_apps(
+    _demo: DemoMode,
+    _api_keys: ApiKeys,
+    _separate_admin: bool,
+    _quotas: DailyQuotas,
+) -> anyhow::Result<Apps> {
+    Err(StartupError::invalid("DEMO_MODE")(anyhow::anyhow!(
+        "demo mode requires the test-utils feature"
+    ))
+    .into())
+}
 
-    async fn res_to_todo(res: Response) -> TodoEntity {
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: TodoEntity = serde_json::from_str(&body)
-            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
-        todo
-    }
+// todoの保存先。idの型ごとにリポジトリを作る
+struct Stores<'a> {
+    pool: &'a PgPool,
+    store: TodoStore,
+    replica: Option<Replica>,
+    max_labels: usize,
+}
 
-    #[tokio::test]
-    async fn should_find_todo() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "Should_find_todo".to_string(), labels.clone());
-        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
-        let label_repository = LabelRepositoryForMemory::new();
+// idの型を決めて、todoの保存方式ごとにアプリを組み立てる
+async fn build_store_apps<I: EntityId>(
+    stores: Stores<'_>,
+    breaker: Arc<CircuitBreaker>,
+    metrics: Arc<Metrics>,
+    config: SharedConfig,
+    api_keys: ApiKeys,
+    separate_admin: bool,
+) -> anyhow::Result<(Router, Option<Router>)> {
+    let Stores {
+        pool,
+        store,
+        replica,
+        max_labels,
+    } = stores;
+    let (app, admin) = match store {
+        TodoStore::Table => {
+            let todo_repository =
+                TodoRepositoryForDb::<I>::with_ids(pool.clone()).with_max_labels(max_labels);
+            build_app(
+                match replica {
+                    Some(replica) => todo_repository.with_replica(replica),
+                    None => todo_repository,
+                },
+                pool,
+                store,
+                max_labels,
+                breaker,
+                metrics,
+                config,
+                api_keys,
+                separate_admin,
+            )
+            .await?
+        }
+        TodoStore::Events => {
+            build_app(
+                TodoRepositoryEventSourced::new(
+                    TodoEventStoreForDb::new(pool.clone()),
+                    LabelRepositoryForDb::<I>::with_ids(pool.clone()),
+                )
+                .with_max_labels(max_labels),
+                pool,
+                store,
+                max_labels,
+                breaker,
+                metrics,
+                config,
+                api_keys,
+                separate_admin,
+            )
+            .await?
+        }
+    };
+    let github = Arc::new(GithubIntegration::from_env(
+        GithubLinkRepositoryForDb::<I>::with_ids(pool.clone()),
+    ));
+    Ok((
+        app.layer(Extension(github.clone())),
+        admin.map(|admin| admin.layer(Extension(github))),
+    ))
+}
 
-        todo_repository
-            .create(CreateTodo::new("Should_find_todo".to_string(), label_ids))
+async fn connect_database(options: &DatabaseOptions) -> Result<PgPool, StartupError> {
+    let database_url = required_env("DATABASE_URL")?;
+    let connect_options = options
+        .connect_options(&database_url)
+        .map_err(StartupError::invalid("DATABASE_URL"))?;
+    cancellable(options.pool_options(), &connect_options)
+        .connect_with(connect_options)
+        .await
+        .map_err(StartupError::connect("database"))
+}
+
+// serve以外の管理用のコマンドを実行する
+// 書き出した内容にログが混ざらないよう、結果だけを標準出力に出す
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    let database_options =
+        DatabaseOptions::from_env().map_err(StartupError::invalid("DATABASE_SLOW_STATEMENT_MS"))?;
+    let pool = connect_database(&database_options).await?;
+    if command.requires_current_schema() {
+        verify_schema(&pool)
             .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(todo_repository, label_repository)
-            .oneshot(req)
+            .map_err(|source| StartupError::OutdatedSchema { source })?;
+    }
+    let id_type = IdType::from_env().map_err(StartupError::invalid("TODO_ID_TYPE"))?;
+    if command.requires_current_schema() {
+        verify_id_type(&pool, id_type)
             .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+            .map_err(StartupError::invalid("TODO_ID_TYPE"))?;
     }
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Migrate => {
+            cli::migrate(&pool, id_type).await?;
+            println!("applied all migrations");
+        }
+        Command::CreateAdminUser { name } => {
+            let (user, api_key) =
+                cli::create_admin_user(&UserRepositoryForDb::new(pool.clone()), &name).await?;
+            println!("created user #{} [{}]", user.id, user.name);
+            println!("add this entry to API_KEYS and restart: {}", api_key);
+        }
+        command => match id_type {
+            IdType::Serial => run_todo_command::<i32>(command, pool).await?,
+            IdType::Uuid => run_todo_command::<Uuid>(command, pool).await?,
+        },
+    }
+    Ok(())
+}
 
-    #[tokio::test]
-    async fn should_get_all_todos() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_get_all_todos".to_string(), labels.clone());
+// todoとラベルを扱うコマンド。idの型ごとにリポジトリを作る
+async fn run_todo_command<I: EntityId>(command: Command, pool: PgPool) -> anyhow::Result<()> {
+    let store = TodoStore::from_env().map_err(StartupError::invalid("TODO_STORE"))?;
+    let labels = LabelRepositoryForDb::<I>::with_ids(pool.clone());
+    match command {
+        Command::Seed => {
+            let summary = match store {
+                TodoStore::Table => {
+                    cli::seed(&labels, &TodoRepositoryForDb::<I>::with_ids(pool.clone())).await?
+                }
+                TodoStore::Events => {
+                    let todos = TodoRepositoryEventSourced::new(
+                        TodoEventStoreForDb::new(pool.clone()),
+                        labels.clone(),
+                    );
+                    cli::seed(&labels, &todos).await?
+                }
+            };
+            println!(
+                "created {} labels and {} todos",
+                summary.labels, summary.todos
+            );
+        }
+        Command::Export { format } => {
+            let mut out = std::io::stdout().lock();
+            match store {
+                TodoStore::Table => {
+                    cli::export(
+                        &TodoRepositoryForDb::<I>::with_ids(pool.clone()),
+                        format,
+                        &mut out,
+                    )
+                    .await?
+                }
+                TodoStore::Events => {
+                    let todos = TodoRepositoryEventSourced::new(
+                        TodoEventStoreForDb::new(pool.clone()),
+                        labels,
+                    );
+                    cli::export(&todos, format, &mut out).await?
+                }
+            };
+        }
+        Command::AddTodo { text } => {
+            let todo = match store {
+                TodoStore::Table => {
+                    cli::add_todo(&TodoRepositoryForDb::<I>::with_ids(pool.clone()), &text).await?
+                }
+                TodoStore::Events => {
+                    let todos = TodoRepositoryEventSourced::new(
+                        TodoEventStoreForDb::new(pool.clone()),
+                        labels,
+                    );
+                    cli::add_todo(&todos, &text).await?
+                }
+            };
+            println!("{}", serde_json::to_string(&todo)?);
+        }
+        Command::Serve | Command::Migrate | Command::CreateAdminUser { .. } => {
+            unreachable!("handled by run_command")
+        }
+    }
+    Ok(())
+}
 
-        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
-        let label_repository = LabelRepositoryForMemory::new();
+async fn listen(addr: SocketAddr, app: Router) -> Result<(), StartupError> {
+    tracing::debug!("listening on {}", addr);
+    axum::Server::try_bind(&addr)
+        .map_err(|e| StartupError::Serve {
+            addr,
+            source: e.into(),
+        })?
+        .serve(app.into_make_service_with_connect_info::<SocketAddr, _>())
+        .await
+        .map_err(|e| StartupError::Serve {
+            addr,
+            source: e.into(),
+        })
+}
 
-        todo_repository
-            .create(CreateTodo::new(
-                "should_get_all_todos".to_string(),
-                label_ids,
-            ))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos");
-        let res = create_app(todo_repository, label_repository)
-            .oneshot(req)
-            .await
-            .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: Vec<TodoEntity> = serde_json::from_str(&body)
-            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
-        assert_eq!(vec![expected], todo);
+// ADMIN_ADDR を指定すると、/metrics・/health・/admin/* をそのアドレスだけで待ち受ける
+// localhostや内部のネットワークのアドレスを指定し、公開する側からは運用のためのエンドポイントを外す
+fn admin_addr_from_env() -> anyhow::Result<Option<SocketAddr>> {
+    match env::var("ADMIN_ADDR") {
+        Ok(addr) if !addr.trim().is_empty() => Ok(Some(addr.trim().parse()?)),
+        _ => Ok(None),
     }
+}
 
-    #[tokio::test]
-    async fn should_update_todo() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "before_update_todos".to_string(), labels.clone());
-        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
-        let label_repository = LabelRepositoryForMemory::new();
+// todoの保存方式によらず、キャッシュとリマインダーを付けてアプリを組み立てる
+#[allow(clippy::too_many_arguments)]
+async fn build_app<I: EntityId, T: TodoRepository<I> + Clone>(
+    todo_repository: T,
+    pool: &PgPool,
+    store: TodoStore,
+    max_labels: usize,
+    breaker: Arc<CircuitBreaker>,
+    metrics: Arc<Metrics>,
+    config: SharedConfig,
+    api_keys: ApiKeys,
+    separate_admin: bool,
+) -> anyhow::Result<(Router, Option<Router>)> {
+    let todo_repository = CircuitBreaking::new(todo_repository, breaker.clone());
+    // 複数のインスタンスで動かす場合はRedisでキャッシュを共有する
+    #[cfg(feature = "redis")]
+    let redis_client = redis::Client::open(required_env("REDIS_URL")?)
+        .map_err(StartupError::invalid("REDIS_URL"))?;
+    // Redisに書いたキャッシュの有効期間は起動時の設定のまま変えない
+    #[cfg(feature = "redis")]
+    let cache_ttl = config.read().unwrap().cache_ttl();
+    #[cfg(feature = "redis")]
+    let todo_repository = redis_cache::RedisCached::connect(
+        &redis_client,
+        todo_repository,
+        cache_ttl,
+        metrics.clone(),
+    )
+    .await
+    .map_err(StartupError::connect("redis"))?;
+    // ポーリングされる一覧などの読み込みは短時間キャッシュする
+    let todo_repository = Cached::new(todo_repository, config, metrics);
+    #[cfg(feature = "redis")]
+    redis_cache::spawn_invalidation_listener(redis_client, todo_repository.clone());
+    let label_repository =
+        CircuitBreaking::new(LabelRepositoryForDb::<I>::with_ids(pool.clone()), breaker);
 
-        todo_repository
-            .create(CreateTodo::new(
-                "before_update_todos".to_string(),
-                label_ids,
-            ))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_json(
-            "/todos/1",
-            Method::PATCH,
-            r#"{
-        "id": 1,
-        "text": "before_update_todos",
-        "completed": false 
-        }"#
-            .to_string(),
-        );
-        let res = create_app(todo_repository, label_repository)
-            .oneshot(req)
-            .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    let notifier = notifier_from_env().map_err(StartupError::invalid("NOTIFIER"))?;
+    spawn_reminder_scheduler(
+        todo_repository.clone(),
+        notifier.clone(),
+        Duration::from_secs(60),
+    );
+    let digest_interval =
+        digest_interval_from_env().map_err(StartupError::invalid("DIGEST_INTERVAL_SECS"))?;
+    if let Some(period) = digest_interval {
+        spawn_digest_scheduler(todo_repository.clone(), notifier.clone(), period);
     }
 
-    #[tokio::test]
-    async fn should_delete_todo() {
-        let todo_repository = TodoRepositoryForMemory::new(vec![]);
-        let label_repository = LabelRepositoryForMemory::new();
-
-        let labels = vec![];
-        todo_repository
-            .create(CreateTodo::new("should_delete_todos".to_string(), labels))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(todo_repository, label_repository)
-            .oneshot(req)
-            .await
-            .unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    // 書き込みで起きたことを購読側へ配る
+    let events = EventBus::<I>::default();
+    spawn_assignment_notifier(&events, notifier.clone());
+    spawn_invalidation_subscriber(&events, todo_repository.clone());
+    match env::var("EVENT_WEBHOOK_URL") {
+        Ok(url) if !url.is_empty() => {
+            spawn_webhook_subscriber(&events, url);
+        }
+        _ => {}
     }
+
+    // 複数のリポジトリにまたがる書き込みを1つのトランザクションにまとめる
+    let transactions: Arc<dyn Transactional<I>> = Arc::new(
+        UnitOfWorkForDb::new(pool.clone(), store, events.clone()).with_max_labels(max_labels),
+    );
+    let (app, admin) = create_apps(
+        todo_repository,
+        label_repository,
+        AuditLogRepositoryForDb::<I>::with_ids(pool.clone()),
+        UserRepositoryForDb::new(pool.clone()),
+        ProjectRepositoryForDb::new(pool.clone()),
+        ViewRepositoryForDb::<I>::with_ids(pool.clone()),
+        TemplateRepositoryForDb::<I>::with_ids(pool.clone()),
+        ShareLinkRepositoryForDb::<I>::with_ids(pool.clone()),
+        events,
+        api_keys,
+        separate_admin,
+    );
+    Ok((
+        app.layer(Extension(transactions.clone()))
+            .layer(Extension(notifier.clone())),
+        admin.map(|admin| {
+            admin
+                .layer(Extension(transactions))
+                .layer(Extension(notifier))
+        }),
+    ))
 }
@@ -0,0 +1,448 @@
+use axum::extract::Extension;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{Method, StatusCode};
+use axum::response::{Headers, IntoResponse};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+// レイテンシのヒストグラムの境界(秒)
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= le {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// (method, route)
+type RouteKey = (String, String);
+
+// Prometheusのテキスト形式で公開するメトリクス
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    latencies: Mutex<BTreeMap<RouteKey, Histogram>>,
+    // (name, labels)
+    counters: Mutex<BTreeMap<(String, String), u64>>,
+    histograms: Mutex<BTreeMap<(String, String), Histogram>>,
+    gauges: Mutex<BTreeMap<(String, String), f64>>,
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Metrics {
+    pub fn observe_request(
+        &self,
+        method: &Method,
+        route: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let key = (method.to_string(), route.to_string());
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((key.0.clone(), key.1.clone(), status.as_u16()))
+            .or_default() += 1;
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        let labels = format_labels(labels);
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name.to_string(), labels))
+            .or_default() += 1;
+    }
+
+    // 境界はレイテンシと同じものを使う
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry((name.to_string(), format_labels(labels)))
+            .or_default()
+            .observe(value);
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.gauges
+            .lock()
+            .unwrap()
+            .insert((name.to_string(), format_labels(labels)), value);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, count
+            );
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds HTTP request latency by route in seconds.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in self.latencies.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        let mut current = None;
+        for ((name, labels), count) in self.counters.lock().unwrap().iter() {
+            if current != Some(name) {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                current = Some(name);
+            }
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, count);
+        }
+
+        let mut current = None;
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if current != Some(name) {
+                let _ = writeln!(out, "# TYPE {} histogram", name);
+                current = Some(name);
+            }
+            let prefix = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{},", labels)
+            };
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, prefix, le, count);
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"+Inf\"}} {}",
+                name, prefix, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+
+        let mut current = None;
+        for ((name, labels), value) in self.gauges.lock().unwrap().iter() {
+            if current != Some(name) {
+                let _ = writeln!(out, "# TYPE {} gauge", name);
+                current = Some(name);
+            }
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+        out
+    }
+}
+
+// コネクションプールの状態を記録する
+// sqlxは接続の空きを待っているタスクの数を公開しないので、すべての接続が使用中のときは
+// 自分でも1つ取得してみて、待った回数と時間を記録する
+pub async fn sample_pool(pool: &PgPool, max_connections: u32, metrics: &Metrics) {
+    let size = pool.size();
+    let idle = pool.num_idle();
+    metrics.set_gauge("db_pool_connections", &[], size as f64);
+    metrics.set_gauge("db_pool_idle_connections", &[], idle as f64);
+    metrics.set_gauge("db_pool_max_connections", &[], max_connections as f64);
+
+    let saturated = size >= max_connections && idle == 0;
+    let waited = if saturated {
+        let started_at = Instant::now();
+        if let Err(e) = pool.acquire().await {
+            tracing::warn!(
+                "failed to acquire a connection while sampling the pool: {}",
+                e
+            );
+        }
+        metrics.increment("db_pool_acquire_waits_total", &[]);
+        started_at.elapsed()
+    } else {
+        Duration::ZERO
+    };
+    metrics.set_gauge("db_pool_acquire_wait_seconds", &[], waited.as_secs_f64());
+}
+
+pub fn spawn_pool_sampler(
+    pool: PgPool,
+    max_connections: u32,
+    metrics: Arc<Metrics>,
+    period: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            sample_pool(&pool, max_connections, &metrics).await;
+        }
+    })
+}
+
+pub async fn metrics(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        Headers(vec![(CONTENT_TYPE, "text/plain; version=0.0.4")]),
+        metrics.render(),
+    )
+}
+
+// リポジトリのメソッドに付けたスパンから、呼び出しごとの所要時間を記録するレイヤー
+// どのリポジトリの呼び出しがレイテンシの大半を占めるかを見るために使う
+// スパンはRUST_LOGで絞り込まれるため、infoより上のレベルにすると記録されない
+pub struct RepositoryTimings {
+    metrics: Arc<Metrics>,
+}
+
+const REPOSITORIES_MODULE: &str = concat!(env!("CARGO_CRATE_NAME"), "::repositories::");
+
+impl RepositoryTimings {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        RepositoryTimings { metrics }
+    }
+}
+
+// スパンを開始した時刻
+struct StartedAt(Instant);
+
+impl<S> Layer<S> for RepositoryTimings
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.metadata().target().starts_with(REPOSITORIES_MODULE) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(StartedAt(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(StartedAt(started_at)) = span.extensions_mut().remove::<StartedAt>() else {
+            return;
+        };
+        let repository = span
+            .metadata()
+            .target()
+            .trim_start_matches(REPOSITORIES_MODULE);
+        self.metrics.observe(
+            "repository_call_duration_seconds",
+            &[("repository", repository), ("method", span.name())],
+            started_at.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_render_route_latency() {
+        let metrics = Metrics::default();
+        metrics.observe_request(
+            &Method::GET,
+            "/todos/:id",
+            StatusCode::OK,
+            Duration::from_millis(20),
+        );
+        metrics.observe_request(
+            &Method::GET,
+            "/todos/:id",
+            StatusCode::NOT_FOUND,
+            Duration::from_secs(3),
+        );
+
+        let text = metrics.render();
+        assert!(text
+            .contains("http_requests_total{method=\"GET\",route=\"/todos/:id\",status=\"200\"} 1"));
+        assert!(text
+            .contains("http_requests_total{method=\"GET\",route=\"/todos/:id\",status=\"404\"} 1"));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/todos/:id\",le=\"0.025\"} 1"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/todos/:id\",le=\"5\"} 2"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/todos/:id\"} 2"
+        ));
+    }
+
+    #[test]
+    fn should_render_counters() {
+        let metrics = Metrics::default();
+        metrics.increment(
+            "cache_requests_total",
+            &[("cache", "todos"), ("result", "hit")],
+        );
+        metrics.increment(
+            "cache_requests_total",
+            &[("cache", "todos"), ("result", "hit")],
+        );
+        metrics.increment(
+            "cache_requests_total",
+            &[("cache", "todos"), ("result", "miss")],
+        );
+
+        let text = metrics.render();
+        assert_eq!(
+            text.matches("# TYPE cache_requests_total counter").count(),
+            1
+        );
+        assert!(text.contains("cache_requests_total{cache=\"todos\",result=\"hit\"} 2"));
+        assert!(text.contains("cache_requests_total{cache=\"todos\",result=\"miss\"} 1"));
+    }
+
+    #[test]
+    fn should_render_histograms() {
+        let metrics = Metrics::default();
+        metrics.observe("chaos_injected_latency_seconds", &[], 0.2);
+        metrics.observe("chaos_injected_latency_seconds", &[], 3.0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE chaos_injected_latency_seconds histogram"));
+        assert!(text.contains("chaos_injected_latency_seconds_bucket{le=\"0.25\"} 1"));
+        assert!(text.contains("chaos_injected_latency_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(text.contains("chaos_injected_latency_seconds_sum{} 3.2"));
+    }
+
+    #[test]
+    fn should_render_gauges() {
+        let metrics = Metrics::default();
+        metrics.set_gauge("db_pool_connections", &[], 3.0);
+        metrics.set_gauge("db_pool_connections", &[], 5.0);
+        metrics.set_gauge("db_pool_idle_connections", &[], 1.0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE db_pool_connections gauge"));
+        assert!(text.contains("db_pool_connections{} 5"));
+        assert!(!text.contains("db_pool_connections{} 3"));
+        assert!(text.contains("db_pool_idle_connections{} 1"));
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_sample_pool_and_wait_when_saturated() {
+        use crate::repositories::test_db::TestDatabase;
+
+        let db = TestDatabase::new().await;
+        let metrics = Metrics::default();
+        sample_pool(&db.pool, 5, &metrics).await;
+        let text = metrics.render();
+        assert!(text.contains("db_pool_max_connections{} 5"));
+        assert!(text.contains("db_pool_acquire_wait_seconds{} 0"));
+        assert!(!text.contains("db_pool_acquire_waits_total"));
+
+        // すべての接続を使っている間は、空くまで待ったことを記録する
+        let mut held = vec![];
+        for _ in 0..5 {
+            held.push(db.pool.acquire().await.unwrap());
+        }
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(held);
+        });
+        sample_pool(&db.pool, 5, &metrics).await;
+        release.await.unwrap();
+        let text = metrics.render();
+        assert!(text.contains("db_pool_connections{} 5"));
+        assert!(text.contains("db_pool_idle_connections{} 0"));
+        assert!(text.contains("db_pool_acquire_waits_total{} 1"));
+        let waited: f64 = text
+            .lines()
+            .find_map(|line| line.strip_prefix("db_pool_acquire_wait_seconds{} "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(waited >= 0.1, "{}", waited);
+
+        db.teardown().await;
+    }
+
+    #[test]
+    fn should_time_repository_calls() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let metrics = Arc::new(Metrics::default());
+        let subscriber =
+            tracing_subscriber::registry().with(RepositoryTimings::new(metrics.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..2 {
+                tracing::info_span!(target: "rust_simple_api::repositories::todo", "all")
+                    .in_scope(|| {});
+            }
+            tracing::info_span!(target: "rust_simple_api::handlers::todo", "all_todos")
+                .in_scope(|| {});
+        });
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "repository_call_duration_seconds_count{repository=\"todo\",method=\"all\"} 2"
+        ));
+        assert!(!text.contains("all_todos"));
+    }
+}
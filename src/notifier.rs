@@ -0,0 +1,129 @@
+use axum::async_trait;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde::Serialize;
+use std::env;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Notification {
+    pub subject: String,
+    pub body: String,
+}
+
+// 通知の送信先を差し替えられるようにするためのトレイト
+#[async_trait]
+pub trait Notifier: Send + Sync + 'static {
+    async fn notify(&self, notification: &Notification) -> anyhow::Result<()>;
+}
+
+// NOTIFIER=log|webhook|email で送信先を選択する
+pub fn notifier_from_env() -> anyhow::Result<Arc<dyn Notifier>> {
+    let kind = env::var("NOTIFIER").unwrap_or_else(|_| "log".to_string());
+    let notifier: Arc<dyn Notifier> = match kind.as_str() {
+        "log" => Arc::new(LogNotifier),
+        "webhook" => Arc::new(WebhookNotifier::new(env::var("NOTIFIER_WEBHOOK_URL")?)),
+        "email" => Arc::new(EmailNotifier::new(
+            env::var("NOTIFIER_EMAIL_FROM")?,
+            env::var("NOTIFIER_EMAIL_TO")?,
+        )),
+        _ => anyhow::bail!("unknown notifier: [{}]", kind),
+    };
+    Ok(notifier)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: &Notification) -> anyhow::Result<()> {
+        tracing::info!("{}: {}", notification.subject, notification.body);
+        Ok(())
+    }
+}
+
+// 通知内容をJSONでPOSTする
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, notification: &Notification) -> anyhow::Result<()> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_vec(notification)?))?;
+        let res = Client::new().request(req).await?;
+        if !res.status().is_success() {
+            anyhow::bail!("webhook returned [{}]", res.status());
+        }
+        Ok(())
+    }
+}
+
+// ホストのsendmailを使ってメールを送信する
+#[derive(Debug, Clone)]
+pub struct EmailNotifier {
+    from: String,
+    to: String,
+}
+
+impl EmailNotifier {
+    pub fn new(from: String, to: String) -> Self {
+        Self { from, to }
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, notification: &Notification) -> anyhow::Result<()> {
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            self.from, self.to, notification.subject, notification.body
+        );
+        let mut child = Command::new("sendmail")
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(message.as_bytes()).await?;
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            anyhow::bail!("sendmail exited with [{}]", status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    pub struct NotifierForMemory {
+        pub sent: Mutex<Vec<Notification>>,
+    }
+
+    #[async_trait]
+    impl Notifier for NotifierForMemory {
+        async fn notify(&self, notification: &Notification) -> anyhow::Result<()> {
+            self.sent.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Deserializer};
+
+// PATCHで「指定しない」と「nullで外す」を区別して受け取る
+// 省略したらNone、nullならSome(None)、値があればSome(Some(value))になる
+// 省略時にこの関数は呼ばれないので、フィールドには#[serde(default)]も付ける
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,354 @@
+use chrono::{DateTime, Utc};
+
+// 印刷用のチェックリストをPDF(1.4)で書き出す
+// 標準の14フォントだけを使いフォントを埋め込まないので、WinAnsiEncodingにない文字は?にする
+
+pub const CONTENT_TYPE: &str = "application/pdf";
+
+// A4縦。単位はポイント
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const LINE_HEIGHT: f32 = 16.0;
+// 1ページに入る行数。下端はページ番号に使う
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - MARGIN * 2.0 - LINE_HEIGHT) / LINE_HEIGHT) as usize;
+// 最初のページはタイトルの分だけ少ない
+const HEADER_LINES: usize = 3;
+// 11ポイントのHelveticaで本文の幅に収まるおおよその文字数
+const MAX_LINE_CHARS: usize = 80;
+const TEXT_X: f32 = MARGIN + 16.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecklistItem {
+    pub text: String,
+    pub done: bool,
+    // 状態や期限など、項目の下に小さく添える文
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checklist {
+    pub title: String,
+    pub generated_at: DateTime<Utc>,
+    pub items: Vec<ChecklistItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Item { text: String, done: bool },
+    Continued(String),
+    Note(String),
+    Empty,
+}
+
+impl Checklist {
+    // オブジェクトごとに区切って返す。順につなげると1つのPDFになる
+    pub fn to_pdf(&self) -> Vec<Vec<u8>> {
+        let pages = self.pages();
+        let mut writer = Writer::default();
+        writer.object(1, "<< /Type /Catalog /Pages 2 0 R >>");
+        let kids: Vec<String> = (0..pages.len())
+            .map(|i| format!("{} 0 R", page_id(i)))
+            .collect();
+        writer.object(
+            2,
+            &format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                pages.len()
+            ),
+        );
+        writer.object(3, &font("Helvetica"));
+        writer.object(4, &font("Helvetica-Bold"));
+        for (i, lines) in pages.iter().enumerate() {
+            writer.object(
+                page_id(i),
+                &format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    page_id(i) + 1
+                ),
+            );
+            writer.stream(page_id(i) + 1, &self.content(i, pages.len(), lines));
+        }
+        writer.finish()
+    }
+
+    // 折り返した行をページごとに分ける。項目がなくても1ページは出す
+    fn pages(&self) -> Vec<Vec<Line>> {
+        let mut lines = Vec::new();
+        for item in self.items.iter() {
+            let mut wrapped = wrap(&item.text).into_iter();
+            lines.push(Line::Item {
+                text: wrapped.next().unwrap_or_default(),
+                done: item.done,
+            });
+            lines.extend(wrapped.map(Line::Continued));
+            if let Some(note) = &item.note {
+                lines.extend(wrap(note).into_iter().map(Line::Note));
+            }
+        }
+        if lines.is_empty() {
+            lines.push(Line::Empty);
+        }
+        let mut pages = vec![];
+        let mut rest = lines.as_slice();
+        let mut capacity = LINES_PER_PAGE - HEADER_LINES;
+        while !rest.is_empty() {
+            let (page, next) = rest.split_at(capacity.min(rest.len()));
+            pages.push(page.to_vec());
+            rest = next;
+            capacity = LINES_PER_PAGE;
+        }
+        pages
+    }
+
+    fn content(&self, index: usize, count: usize, lines: &[Line]) -> String {
+        let top = PAGE_HEIGHT - MARGIN;
+        let mut ops = Vec::new();
+        let mut y = top;
+        if index == 0 {
+            ops.push(text_op("F2", 16.0, MARGIN, y, &self.title));
+            let subtitle = format!(
+                "{} items - generated at {}",
+                self.items.len(),
+                self.generated_at.format("%Y-%m-%d %H:%M UTC")
+            );
+            ops.push(text_op("F1", 9.0, MARGIN, y - 18.0, &subtitle));
+            y -= LINE_HEIGHT * HEADER_LINES as f32;
+        }
+        for line in lines {
+            match line {
+                Line::Item { text, done } => {
+                    ops.push(format!("0.8 w {} {} 9 9 re S", MARGIN, y - 1.0));
+                    if *done {
+                        ops.push(format!(
+                            "{x0} {y0} m {x1} {y1} l {x0} {y1} m {x1} {y0} l S",
+                            x0 = MARGIN + 2.0,
+                            y0 = y + 1.0,
+                            x1 = MARGIN + 7.0,
+                            y1 = y + 6.0
+                        ));
+                    }
+                    ops.push(text_op("F1", 11.0, TEXT_X, y, text));
+                }
+                Line::Continued(text) => ops.push(text_op("F1", 11.0, TEXT_X, y, text)),
+                Line::Note(text) => {
+                    ops.push(format!(
+                        "q 0.4 g {} Q",
+                        text_op("F1", 9.0, TEXT_X, y + 3.0, text)
+                    ));
+                }
+                Line::Empty => ops.push(text_op("F1", 11.0, MARGIN, y, "No todos")),
+            }
+            y -= LINE_HEIGHT;
+        }
+        let footer = format!("Page {} / {}", index + 1, count);
+        ops.push(text_op("F1", 9.0, MARGIN, MARGIN - LINE_HEIGHT, &footer));
+        ops.join("\n")
+    }
+}
+
+fn page_id(index: usize) -> usize {
+    5 + index * 2
+}
+
+fn font(name: &str) -> String {
+    format!(
+        "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+        name
+    )
+}
+
+fn text_op(font: &str, size: f32, x: f32, y: f32, text: &str) -> String {
+    format!(
+        "BT /{} {} Tf {} {} Td ({}) Tj ET",
+        font,
+        size,
+        x,
+        y,
+        escape(text)
+    )
+}
+
+// 文字列の区切りになる文字をエスケープし、ASCII以外は8進数で書く
+// Latin-1の範囲はWinAnsiEncodingでも同じ文字になる
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            '\t' | '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+// 空白で折り返す。空白のない長い語は途中で切る
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while !word.is_empty() {
+            let used = line.chars().count();
+            let space = usize::from(used > 0);
+            if used + space + word.len() <= MAX_LINE_CHARS {
+                if space > 0 {
+                    line.push(' ');
+                }
+                line.extend(word.drain(..));
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line));
+            } else {
+                line = word.drain(..MAX_LINE_CHARS).collect();
+                lines.push(std::mem::take(&mut line));
+            }
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// オブジェクトの位置を数えながら書き、最後に相互参照表を付ける
+// オブジェクトは番号順に書く
+#[derive(Debug)]
+struct Writer {
+    chunks: Vec<Vec<u8>>,
+    offsets: Vec<usize>,
+    len: usize,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        let mut writer = Self {
+            chunks: vec![],
+            offsets: vec![],
+            len: 0,
+        };
+        // バイナリを含むファイルだと分かるよう、2行目にASCII以外の文字を置く
+        writer.push(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec());
+        writer
+    }
+}
+
+impl Writer {
+    fn push(&mut self, chunk: Vec<u8>) {
+        self.len += chunk.len();
+        self.chunks.push(chunk);
+    }
+
+    fn object(&mut self, id: usize, body: &str) {
+        debug_assert_eq!(id, self.offsets.len() + 1);
+        self.offsets.push(self.len);
+        self.push(format!("{} 0 obj\n{}\nendobj\n", id, body).into_bytes());
+    }
+
+    fn stream(&mut self, id: usize, content: &str) {
+        self.object(
+            id,
+            &format!(
+                "<< /Length {} >>\nstream\n{}\nendstream",
+                content.len(),
+                content
+            ),
+        );
+    }
+
+    fn finish(mut self) -> Vec<Vec<u8>> {
+        let xref_at = self.len;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in self.offsets.iter() {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            xref_at
+        ));
+        self.push(xref.into_bytes());
+        self.chunks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn checklist(count: usize) -> Checklist {
+        Checklist {
+            title: "Todos".to_string(),
+            generated_at: Utc.with_ymd_and_hms(2024, 7, 1, 9, 0, 0).unwrap(),
+            items: (0..count)
+                .map(|i| ChecklistItem {
+                    text: format!("item {}", i),
+                    done: i % 2 == 0,
+                    note: Some("status: done".to_string()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn should_write_cross_reference_to_each_object() {
+        let pdf = checklist(2).to_pdf().concat();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+
+        let startxref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|rest| rest.lines().next())
+            .and_then(|offset| offset.parse().ok())
+            .unwrap();
+        assert!(pdf[startxref..].starts_with(b"xref\n0 7\n"));
+        let entries = text[text.find("0000000000 65535 f \n").unwrap()..]
+            .lines()
+            .skip(1)
+            .take(6);
+        for (i, entry) in entries.enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            let header = format!("{} 0 obj\n", i + 1);
+            assert!(pdf[offset..].starts_with(header.as_bytes()), "{}", entry);
+        }
+        assert!(text.contains("(item 1) Tj"));
+        assert!(text.contains("(2 items - generated at 2024-07-01 09:00 UTC) Tj"));
+    }
+
+    #[test]
+    fn should_split_long_lists_into_pages() {
+        assert_eq!(checklist(0).pages(), vec![vec![Line::Empty]]);
+        // 1件で2行使うので、最初のページに入るのは21件
+        let pages = checklist(22).pages();
+        assert_eq!(
+            vec![LINES_PER_PAGE - HEADER_LINES, 2],
+            pages.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+        let text = String::from_utf8_lossy(&checklist(22).to_pdf().concat()).to_string();
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("(Page 2 / 2) Tj"));
+    }
+
+    #[test]
+    fn should_escape_and_wrap_text() {
+        assert_eq!(escape("a (b) \\ café 牛乳"), "a \\(b\\) \\\\ caf\\351 ??");
+        let long = format!("{} {}", "a".repeat(70), "b".repeat(100));
+        assert_eq!(
+            wrap(&long),
+            vec!["a".repeat(70), "b".repeat(80), "b".repeat(20)]
+        );
+        assert_eq!(wrap(""), vec![String::new()]);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::auth::Principal;
+use crate::handlers::ApiError;
+use crate::repositories::usage::UsageRepository;
+use axum::body::Body;
+use axum::extract::Extension;
+use axum::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+
+pub const X_RATELIMIT_LIMIT: &str = "x-ratelimit-limit";
+pub const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+pub const X_RATELIMIT_RESET: &str = "x-ratelimit-reset";
+
+// 使用量の確認は上限を超えていてもできるよう、数えずに通す
+pub const USAGE_PATH: &str = "/me/usage";
+
+// リクエスト主体ごとの1日のリクエスト数の上限
+// 主体単位で上書きでき、Noneの主体は上限なしで数えるだけにする
+#[derive(Debug, Clone, Default)]
+pub struct DailyQuotas {
+    default: Option<u64>,
+    principals: HashMap<String, Option<u64>>,
+}
+
+impl DailyQuotas {
+    pub fn new(default: Option<u64>) -> Self {
+        Self {
+            default,
+            principals: HashMap::new(),
+        }
+    }
+
+    // DAILY_QUOTA で既定の上限を、
+    // DAILY_QUOTA_PRINCIPALS="<name>=<requests>,..." で主体単位の上書きを指定する
+    // 0を指定すると上限なしにする
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut quotas = match env::var("DAILY_QUOTA") {
+            Ok(requests) if !requests.is_empty() => Self::new(limit(requests.parse()?)),
+            _ => Self::default(),
+        };
+        if let Ok(principals) = env::var("DAILY_QUOTA_PRINCIPALS") {
+            for entry in principals
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+            {
+                let (name, requests) = entry
+                    .trim()
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid principal quota: [{}]", entry))?;
+                quotas = quotas.principal(name, limit(requests.parse()?));
+            }
+        }
+        Ok(quotas)
+    }
+
+    pub fn principal(mut self, name: &str, limit: Option<u64>) -> Self {
+        self.principals.insert(name.to_string(), limit);
+        self
+    }
+
+    fn for_principal(&self, name: &str) -> Option<u64> {
+        self.principals.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+fn limit(requests: u64) -> Option<u64> {
+    Some(requests).filter(|requests| *requests > 0)
+}
+
+// 上限と数えた結果の保存先
+#[derive(Clone)]
+pub struct Quota {
+    quotas: DailyQuotas,
+    usage: Arc<dyn UsageRepository>,
+}
+
+impl Quota {
+    pub fn new(quotas: DailyQuotas, usage: impl UsageRepository) -> Self {
+        Self {
+            quotas,
+            usage: Arc::new(usage),
+        }
+    }
+}
+
+// GET /me/usage のレスポンス
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub principal: String,
+    pub day: NaiveDate,
+    pub used: u64,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl Usage {
+    fn new(principal: &str, now: DateTime<Utc>, used: u64, limit: Option<u64>) -> Self {
+        let day = now.date_naive();
+        let resets_at = (day + Duration::days(1))
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        Usage {
+            principal: principal.to_string(),
+            day,
+            used,
+            limit,
+            remaining: limit.map(|limit| limit.saturating_sub(used)),
+            resets_at,
+        }
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.limit.is_some_and(|limit| self.used > limit)
+    }
+
+    // 上限のない主体には付けない
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let (Some(limit), Some(remaining)) = (self.limit, self.remaining) {
+            for (name, value) in [
+                (X_RATELIMIT_LIMIT, limit),
+                (X_RATELIMIT_REMAINING, remaining),
+                (X_RATELIMIT_RESET, self.resets_at.timestamp() as u64),
+            ] {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+        }
+        headers
+    }
+}
+
+// 認証の後に適用し、リクエスト主体ごとにその日のリクエストを数える
+// 上限を超えたら429を返す。数えられなかった場合はリクエストを止めない
+pub async fn enforce_quota(req: Request<Body>, next: Next<Body>) -> Response {
+    let (Some(quota), Some(principal)) = (
+        req.extensions().get::<Arc<Quota>>().cloned(),
+        req.extensions().get::<Principal>().cloned(),
+    ) else {
+        return next.run(req).await;
+    };
+    if req.uri().path() == USAGE_PATH {
+        return next.run(req).await;
+    }
+
+    let now = Utc::now();
+    let used = match quota
+        .usage
+        .increment(&principal.name, now.date_naive())
+        .await
+    {
+        Ok(used) => used,
+        Err(e) => {
+            tracing::warn!("failed to count requests of [{}]: {}", principal.name, e);
+            return next.run(req).await;
+        }
+    };
+    let usage = Usage::new(
+        &principal.name,
+        now,
+        used,
+        quota.quotas.for_principal(&principal.name),
+    );
+    let mut res = if usage.is_exceeded() {
+        let retry_after = (usage.resets_at - now).num_seconds().max(1) as u64;
+        let mut res =
+            ApiError::new(StatusCode::TOO_MANY_REQUESTS, "quota.exceeded").into_response();
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        res
+    } else {
+        next.run(req).await
+    };
+    res.headers_mut().extend(usage.headers());
+    res
+}
+
+// 数えるだけで上限のない主体はlimitとremainingをnullで返す
+pub async fn my_usage(
+    quota: Option<Extension<Arc<Quota>>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Response, ApiError> {
+    let Some(Extension(quota)) = quota else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    let now = Utc::now();
+    let used = quota
+        .usage
+        .find(&principal.name, now.date_naive())
+        .await
+        .map_err(|_| ApiError::from(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let usage = Usage::new(
+        &principal.name,
+        now,
+        used,
+        quota.quotas.for_principal(&principal.name),
+    );
+    let headers = usage.headers();
+    let mut res = Json(usage).into_response();
+    res.headers_mut().extend(headers);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn should_override_quota_per_principal() {
+        let quotas = DailyQuotas::new(Some(1000))
+            .principal("batch", Some(50000))
+            .principal("ops", None);
+
+        assert_eq!(quotas.for_principal("alice"), Some(1000));
+        assert_eq!(quotas.for_principal("batch"), Some(50000));
+        assert_eq!(quotas.for_principal("ops"), None);
+        assert_eq!(DailyQuotas::default().for_principal("alice"), None);
+    }
+
+    #[test]
+    fn should_reset_at_next_midnight() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 5, 21, 30, 0).unwrap();
+        let usage = Usage::new("alice", now, 11, Some(10));
+
+        assert_eq!(
+            usage.resets_at,
+            Utc.with_ymd_and_hms(2024, 6, 6, 0, 0, 0).unwrap()
+        );
+        assert_eq!(usage.remaining, Some(0));
+        assert!(usage.is_exceeded());
+        assert_eq!(usage.headers()[X_RATELIMIT_RESET], "1717632000");
+        assert!(Usage::new("alice", now, 11, None).headers().is_empty());
+    }
+}
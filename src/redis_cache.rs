@@ -0,0 +1,419 @@
+use crate::cache::Cached;
+use crate::metrics::Metrics;
+use crate::repositories::todo::{
+    CompletedRange, CreateTodo, CycleTime, ListedTodo, Permission, Share, TodoChanges,
+    TodoDependencies, TodoEntity, TodoFilter, TodoPage, TodoRepository, TodoRevision, TodoStream,
+    TodoSuggestion, UpdateTodo, Visibility,
+};
+use crate::repositories::users::User;
+use crate::repositories::EntityId;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+// 書き込みのたびに進める世代番号。キャッシュのキーに含めて古いキャッシュを参照しないようにする
+const VERSION_KEY: &str = "todos:version";
+// todoの変更を各インスタンスへ知らせるチャンネル
+const CHANNEL: &str = "todos:changes";
+
+// pub/subで流す変更内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoChanged<I = i32> {
+    pub ids: Vec<I>,
+}
+
+// todoの読み込みをRedisにキャッシュするデコレーター
+// 複数のインスタンスで同じキャッシュを共有し、書き込みはpub/subで通知する
+#[derive(Clone)]
+pub struct RedisCached<R> {
+    inner: R,
+    redis: ConnectionManager,
+    ttl: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl<R> RedisCached<R> {
+    pub async fn connect(
+        client: &redis::Client,
+        inner: R,
+        ttl: Duration,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Self> {
+        let redis = client.get_connection_manager().await?;
+        Ok(Self {
+            inner,
+            redis,
+            ttl,
+            metrics,
+        })
+    }
+
+    fn record(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.metrics.increment(
+            "cache_requests_total",
+            &[("cache", "redis"), ("result", result)],
+        );
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<(String, Option<T>)> {
+        let mut redis = self.redis.clone();
+        let version: Option<u64> = redis.get(VERSION_KEY).await?;
+        let key = format!("todos:{}:{}", version.unwrap_or_default(), key);
+        let value: Option<String> = redis.get(&key).await?;
+        let value = value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?;
+        Ok((key, value))
+    }
+
+    async fn set<T: Serialize>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        let mut redis = self.redis.clone();
+        redis
+            .set_ex::<_, _, ()>(
+                key,
+                serde_json::to_string(value)?,
+                self.ttl.as_secs() as usize,
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Redisに障害があってもデータベースから読めるようにする
+    async fn read_through<T, F>(&self, key: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: std::future::Future<Output = anyhow::Result<T>>,
+    {
+        let key = match self.get::<T>(key).await {
+            Ok((_, Some(value))) => {
+                self.record(true);
+                return Ok(value);
+            }
+            Ok((key, None)) => Some(key),
+            Err(e) => {
+                tracing::warn!("failed to read todo cache: {}", e);
+                None
+            }
+        };
+        self.record(false);
+
+        let value = f.await?;
+        if let Some(key) = key {
+            if let Err(e) = self.set(&key, &value).await {
+                tracing::warn!("failed to write todo cache: {}", e);
+            }
+        }
+        Ok(value)
+    }
+
+    async fn publish<I: EntityId>(&self, ids: Vec<I>) {
+        let mut redis = self.redis.clone();
+        let result: anyhow::Result<()> = async {
+            redis.incr::<_, _, ()>(VERSION_KEY, 1).await?;
+            let message = serde_json::to_string(&TodoChanged { ids })?;
+            redis.publish::<_, _, ()>(CHANNEL, message).await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::error!("failed to publish todo change: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>> TodoRepository<I> for RedisCached<R> {
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.create(payload).await?;
+        self.publish(vec![todo.id]).await;
+        Ok(todo)
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.read_through(&format!("find:{}", id), self.inner.find(id))
+            .await
+    }
+
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_for_update(id).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        self.inner.find_by_text(text, visible_to).await
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        // 見える範囲はビューとして保存しない項目のため、JSONとは別にキーへ含める
+        let key = format!(
+            "all:{}:{:?}:{:?}",
+            serde_json::to_string(&filter)?,
+            filter.visible_to,
+            filter.shared_with
+        );
+        self.read_through(&key, self.inner.all(filter)).await
+    }
+
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        self.inner.stream(filter).await
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        self.inner.count(filter).await
+    }
+
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        self.inner.page(filter, page).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.update(id, payload).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        self.inner.delete(id).await?;
+        self.publish(vec![id]).await;
+        Ok(())
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        self.inner.history(id).await
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.set_reminder(id, remind_at).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.reminders().await
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let todos = self.inner.take_due_reminders(now).await?;
+        if !todos.is_empty() {
+            self.publish(todos.iter().map(|todo| todo.id).collect())
+                .await;
+        }
+        Ok(todos)
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.assign(id, assignee).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.move_to_project(id, project_id).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.set_parent(id, parent_id).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.pin(id, pinned).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self.inner.snooze(id, until).await?;
+        self.publish(vec![id]).await;
+        Ok(todo)
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let ids = self.inner.attach_label(label_id, todo_ids).await?;
+        self.publish(ids.clone()).await;
+        Ok(ids)
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let ids = self.inner.detach_label(label_id, todo_ids).await?;
+        self.publish(ids.clone()).await;
+        Ok(ids)
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.children(id).await
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.descendants(id).await
+    }
+
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        self.inner.add_dependency(blocker_id, blocked_id).await?;
+        self.publish(vec![blocked_id]).await;
+        Ok(())
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        self.inner.remove_dependency(blocker_id, blocked_id).await?;
+        self.publish(vec![blocked_id]).await;
+        Ok(())
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        self.inner.dependencies(id).await
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.dependents(id).await
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.modified_at(id).await
+    }
+
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.list_modified_at().await
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        self.inner.cycle_time(range).await
+    }
+
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        let share = self.inner.share(id, user_id, permission).await?;
+        self.publish(vec![id]).await;
+        Ok(share)
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        self.inner.unshare(id, user_id).await?;
+        self.publish(vec![id]).await;
+        Ok(())
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        self.inner.shares(id).await
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        self.inner.suggest(query, visible_to, limit).await
+    }
+
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        self.inner.changes(since, visible_to).await
+    }
+}
+
+// 他のインスタンスでの変更を受け取り、手元のキャッシュを破棄する
+pub fn spawn_invalidation_listener<I: EntityId, R: TodoRepository<I>>(
+    client: redis::Client,
+    cache: Cached<R, I>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&client, &cache).await {
+                tracing::error!("todo change subscription failed: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    })
+}
+
+async fn listen<R, I>(client: &redis::Client, cache: &Cached<R, I>) -> anyhow::Result<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(CHANNEL).await?;
+    // 購読していなかった間の変更は分からないので一度破棄する
+    cache.invalidate();
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        tracing::debug!("todo changed: {}", payload);
+        cache.invalidate();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use dotenv::dotenv;
+    use std::env;
+
+    // REDIS_URL のRedisに対して実行する
+    #[tokio::test]
+    async fn should_share_cache_between_instances() {
+        dotenv().ok();
+        let redis_url = env::var("REDIS_URL").expect("undefined [REDIS_URL]");
+        let client = redis::Client::open(redis_url).unwrap();
+        let metrics = Arc::new(Metrics::default());
+
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let first = RedisCached::connect(
+            &client,
+            inner.clone(),
+            Duration::from_secs(60),
+            metrics.clone(),
+        )
+        .await
+        .expect("fail connect redis");
+        let second = RedisCached::connect(
+            &client,
+            TodoRepositoryForMemory::new(vec![]),
+            Duration::from_secs(60),
+            metrics.clone(),
+        )
+        .await
+        .expect("fail connect redis");
+
+        let todo = first
+            .create(CreateTodo::new("shared".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(first.find(todo.id).await.unwrap(), todo);
+        // 2台目は自分のリポジトリに無いtodoもキャッシュから返す
+        assert_eq!(second.find(todo.id).await.unwrap(), todo);
+
+        // 書き込みで世代が進めば読み直す
+        first.delete(todo.id).await.unwrap();
+        assert!(second.find(todo.id).await.is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 外部のサービスから受け取るwebhookの署名と、盗み見たリクエストの再送の確認
+// 連携ごとの受け口で共通に使う
+
+// 署名した時刻と受け取った時刻の差の上限。時計のずれも含めて前後どちらも許す
+pub const MAX_SIGNATURE_AGE_SECS: i64 = 5 * 60;
+// 許容範囲の前後どちらの端で受け取った署名も、範囲を外れるまで覚えておく
+const REMEMBER_FOR: Duration = Duration::from_secs(2 * MAX_SIGNATURE_AGE_SECS as u64);
+
+// 秒単位のUNIX時刻が、今から許容範囲の中にあればtrue
+pub fn is_recent(signed_at: i64, now: i64) -> bool {
+    (now - signed_at).abs() <= MAX_SIGNATURE_AGE_SECS
+}
+
+// 16進数で表したHMAC-SHA256を、時間が一定になるように比べる
+// messageは順につなげて署名の対象にする
+pub fn verify_hmac_sha256(secret: &str, message: &[&[u8]], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    for part in message {
+        mac.update(part);
+    }
+    mac.verify_slice(&signature).is_ok()
+}
+
+// 一度受け付けた署名や配信IDを覚えておき、同じものをもう一度受け付けない
+// 許容範囲を過ぎたものは時刻の確認で拒否できるので忘れる。クローンは中身を共有する
+#[derive(Debug, Clone, Default)]
+pub struct ReplayCache {
+    seen: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl ReplayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 初めて受け取ったものならtrue
+    // 偽の署名で覚える量を増やされないよう、署名を確かめた後に呼ぶ
+    pub fn first_seen(&self, nonce: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, received_at| now.duration_since(*received_at) < REMEMBER_FOR);
+        if seen.contains_key(nonce) {
+            return false;
+        }
+        seen.insert(nonce.to_string(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_reject_replayed_nonce_until_forgotten() {
+        let cache = ReplayCache::new();
+        let now = Instant::now();
+        assert!(cache.first_seen("a", now));
+        assert!(!cache.first_seen("a", now + Duration::from_secs(60)));
+        assert!(cache.clone().first_seen("b", now));
+        assert!(!cache.first_seen("b", now + Duration::from_secs(1)));
+        assert!(cache.first_seen("a", now + REMEMBER_FOR));
+
+        assert!(is_recent(1_000, 1_000 + MAX_SIGNATURE_AGE_SECS));
+        assert!(is_recent(1_000 + MAX_SIGNATURE_AGE_SECS, 1_000));
+        assert!(!is_recent(1_000, 1_001 + MAX_SIGNATURE_AGE_SECS));
+    }
+
+    #[test]
+    fn should_verify_hmac_over_joined_message() {
+        // GitHubのドキュメントにある例
+        let signature = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let secret = "It's a Secret to Everybody";
+        assert!(verify_hmac_sha256(secret, &[b"Hello, World!"], signature));
+        assert!(verify_hmac_sha256(
+            secret,
+            &[b"Hello, ", b"World!"],
+            signature
+        ));
+        assert!(!verify_hmac_sha256(secret, &[b"Hello, World"], signature));
+        assert!(!verify_hmac_sha256("other", &[b"Hello, World!"], signature));
+        assert!(!verify_hmac_sha256(secret, &[b"Hello, World!"], "zz"));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::auth::Principal;
+use crate::logging::RequestId;
+use axum::body::Body;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use sentry::protocol::User;
+use sentry::{ClientInitGuard, Hub, Level, SentryFutureExt};
+use std::sync::Arc;
+
+// SENTRY_DSN が未定義の場合は送信しない
+// パニックはクライアントを初期化した時点から送られる
+pub fn init() -> ClientInitGuard {
+    sentry::init(sentry::ClientOptions {
+        release: sentry::release_name!(),
+        ..Default::default()
+    })
+}
+
+// リクエストごとにスコープを分け、リクエストIDと利用者を付けて処理する
+// 5xxを返した場合はそのリクエストをエラーとして送る
+pub async fn report_errors(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    hub.configure_scope(|scope| {
+        scope.set_tag("http.method", &method);
+        scope.set_tag("http.path", &path);
+        if let Some(request_id) = req.extensions().get::<RequestId>() {
+            scope.set_tag("request_id", &request_id.0);
+        }
+        if let Some(principal) = req.extensions().get::<Principal>() {
+            scope.set_user(Some(User {
+                username: Some(principal.name.clone()),
+                ..Default::default()
+            }));
+        }
+    });
+
+    let res = next.run(req).bind_hub(hub.clone()).await;
+    let status = res.status();
+    if status.is_server_error() {
+        hub.capture_message(&format!("{} {} {}", method, path, status), Level::Error);
+    }
+    res
+}
+
+// ルーティングで確定したパスをエラーの集計に使えるよう付ける
+pub fn set_route(route: &str) {
+    sentry::configure_scope(|scope| scope.set_tag("route", route));
+}
+
+// データベースの障害など想定外のエラーを送る
+pub fn capture_error(e: &anyhow::Error) {
+    sentry::integrations::anyhow::capture_anyhow(e);
+}
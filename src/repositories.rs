@@ -1,14 +1,459 @@
+use crate::circuit_breaker::CircuitBreaker;
+use crate::repositories::database::{cancellable, in_atomically, Database};
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sqlx::postgres::{PgConnectOptions, PgHasArrayType, PgPoolOptions};
+use sqlx::{ConnectOptions, PgPool, Postgres};
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use uuid::Uuid;
+use validator::ValidationError;
 
+pub mod audit;
+pub mod database;
+pub mod github_links;
 pub mod labels;
+pub mod projects;
+pub mod schema;
+pub mod share_links;
+pub mod templates;
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+pub mod test_db;
 pub mod todo;
+pub mod usage;
+pub mod users;
+pub mod views;
+
+// データベースへの接続の設定
+// 実行した文はdebugで、閾値を超えた文はwarnでSQLとともに出力する
+// SQLはプレースホルダーのまま出力し、バインドした値は出力しない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseOptions {
+    slow_statement: Duration,
+    statement_cache_capacity: usize,
+    max_connections: u32,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions {
+            slow_statement: Duration::from_millis(DEFAULT_SLOW_STATEMENT_MS),
+            statement_cache_capacity: DEFAULT_STATEMENT_CACHE_CAPACITY,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+const DEFAULT_SLOW_STATEMENT_MS: u64 = 500;
+const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 100;
+// sqlxの既定と同じ
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+impl DatabaseOptions {
+    // DATABASE_SLOW_STATEMENT_MS で遅い文とみなす時間を、
+    // DATABASE_STATEMENT_CACHE_CAPACITY で接続ごとに使い回すプリペアドステートメントの数を指定する
+    // キャッシュを0にすると文ごとに準備し直す
+    // DATABASE_MAX_CONNECTIONS で主への接続数の上限を指定する
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        if let Ok(ms) = env::var("DATABASE_SLOW_STATEMENT_MS") {
+            options.slow_statement = Duration::from_millis(ms.parse()?);
+        }
+        if let Ok(capacity) = env::var("DATABASE_STATEMENT_CACHE_CAPACITY") {
+            options.statement_cache_capacity = capacity.parse()?;
+        }
+        if let Ok(max) = env::var("DATABASE_MAX_CONNECTIONS") {
+            options.max_connections = max.parse()?;
+            anyhow::ensure!(options.max_connections > 0, "must be greater than 0");
+        }
+        Ok(options)
+    }
+
+    pub fn max_connections(&self) -> u32 {
+        self.max_connections
+    }
+
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new().max_connections(self.max_connections)
+    }
+
+    pub fn connect_options(&self, database_url: &str) -> anyhow::Result<PgConnectOptions> {
+        let mut options = database_url
+            .parse::<PgConnectOptions>()?
+            .statement_cache_capacity(self.statement_cache_capacity);
+        options
+            .log_statements(log::LevelFilter::Debug)
+            .log_slow_statements(log::LevelFilter::Warn, self.slow_statement);
+        Ok(options)
+    }
+}
+
+// 読み込み専用の複製への接続
+// 繋がらなくなったらしばらく使わず、その間の読み込みは主に送る
+#[derive(Debug, Clone)]
+pub struct Replica {
+    database: Database,
+    breaker: Arc<CircuitBreaker>,
+}
+
+const REPLICA_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const REPLICA_COOLDOWN: Duration = Duration::from_secs(10);
+
+impl Replica {
+    pub fn new(pool: PgPool) -> Self {
+        Replica {
+            database: Database::Pool(pool),
+            breaker: Arc::new(CircuitBreaker::new(1, REPLICA_COOLDOWN)),
+        }
+    }
+
+    // 起動時には接続せず、落ちていても主だけで動けるようにする
+    pub fn connect_lazy(options: PgConnectOptions) -> Self {
+        Self::new(
+            cancellable(PgPoolOptions::new(), &options)
+                .connect_timeout(REPLICA_CONNECT_TIMEOUT)
+                .connect_lazy_with(options),
+        )
+    }
+
+    // 複製で読み、繋がらなければ主で読み直す
+    // atomicallyの中では書き込みと同じトランザクションで読むため、主で読む
+    pub async fn read<'a, T, E, F, Fut>(
+        &'a self,
+        primary: &'a Database,
+        query: F,
+    ) -> anyhow::Result<T>
+    where
+        E: Into<anyhow::Error>,
+        F: Fn(&'a Database) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !in_atomically() && self.breaker.acquire().is_ok() {
+            match query(&self.database).await.map_err(Into::into) {
+                Err(e) if is_connection_error(&e) => {
+                    tracing::warn!("replica is unavailable, reading from primary: {}", e);
+                    self.breaker.record_failure();
+                }
+                result => {
+                    self.breaker.record_success();
+                    return result;
+                }
+            }
+        }
+        query(primary).await.map_err(Into::into)
+    }
+}
+
+// 問い合わせの誤りではなく、接続先に届かなかった場合
+fn is_connection_error(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(
+            sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+                | sqlx::Error::WorkerCrashed
+        )
+    )
+}
+
+// 一意インデックスに違反した場合は重複として扱う
+pub(crate) fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("23505"))
+}
+
+// LIKEのパターンとして使うため、ワイルドカードをエスケープする
+pub(crate) fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
     Unexpected(String),
     #[error("NotFound, id is {0}")]
     NotFound(i32),
+    #[error("NotFound, uuid is {0}")]
+    NotFoundUuid(Uuid),
+    #[error("NotFound, token is {0}")]
+    NotFoundToken(String),
     #[error("Duplicate data, id is {0}")]
-    Duplicate(i32),
+    Duplicate(Key),
+    #[error("Nothing to undo, id is {0}")]
+    NothingToUndo(Key),
+    #[error("Unavailable, retry after {0} secs")]
+    Unavailable(u64),
+    #[error("Too many labels, at most {0}")]
+    TooManyLabels(usize),
+}
+
+impl RepositoryError {
+    // エラーレスポンスで翻訳するメッセージキー
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            RepositoryError::Unexpected(_) => "repository.unexpected",
+            RepositoryError::NotFound(_)
+            | RepositoryError::NotFoundUuid(_)
+            | RepositoryError::NotFoundToken(_) => "repository.not_found",
+            RepositoryError::Duplicate(_) => "repository.duplicate",
+            RepositoryError::NothingToUndo(_) => "repository.nothing_to_undo",
+            RepositoryError::Unavailable(_) => "repository.unavailable",
+            RepositoryError::TooManyLabels(_) => "repository.too_many_labels",
+        }
+    }
+}
+
+// todoとラベルの識別子の型
+// 複数の地域のデータを合わせると連番のidは衝突するため、UUID v7も選べる
+// 最初にマイグレーションを適用するときに決め、後から切り替えることはできない
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdType {
+    #[default]
+    Serial,
+    Uuid,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Unknown id type: [{0}]")]
+pub struct UnknownIdType(String);
+
+impl FromStr for IdType {
+    type Err = UnknownIdType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "serial" => Ok(IdType::Serial),
+            "uuid" => Ok(IdType::Uuid),
+            _ => Err(UnknownIdType(s.to_string())),
+        }
+    }
+}
+
+impl IdType {
+    // TODO_ID_TYPE で serial か uuid を指定する
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("TODO_ID_TYPE") {
+            Ok(id_type) if !id_type.is_empty() => Ok(id_type.parse()?),
+            _ => Ok(IdType::default()),
+        }
+    }
+
+    // マイグレーションはこの値を見て主キーの型を決める
+    pub fn as_setting(self) -> &'static str {
+        match self {
+            IdType::Serial => "serial",
+            IdType::Uuid => "uuid",
+        }
+    }
+
+    // todos.idの列の型
+    pub fn column_type(self) -> &'static str {
+        match self {
+            IdType::Serial => "integer",
+            IdType::Uuid => "uuid",
+        }
+    }
+}
+
+// todoとラベルのidとして使える型
+// リポジトリやエンティティはこの型を引数に取り、連番とUUIDのどちらでも同じ実装で動かす
+pub trait EntityId:
+    Copy
+    + Default
+    + Eq
+    + Ord
+    + Hash
+    + fmt::Debug
+    + fmt::Display
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + Unpin
+    + 'static
+    + sqlx::Type<Postgres>
+    + for<'q> sqlx::Encode<'q, Postgres>
+    + for<'r> sqlx::Decode<'r, Postgres>
+    + PgHasArrayType
+{
+    const TYPE: IdType;
+
+    // パスの値をこの型のidとして読む。この型の値でなければNoneを返す
+    fn parse(s: &str) -> Result<Option<Self>, KeyError>;
+
+    // データベースを使わない実装で振るid。seqは1から振る連番
+    fn assign(seq: i32) -> Self;
+
+    // 型によらないエラーなどに載せる
+    fn key(self) -> Key;
+
+    fn from_key(key: Key) -> Option<Self>;
+
+    fn not_found(self) -> RepositoryError {
+        match self.key() {
+            Key::Id(id) => RepositoryError::NotFound(id),
+            Key::Uuid(uuid) => RepositoryError::NotFoundUuid(uuid),
+        }
+    }
+
+    // データベースを使わない実装でidとは別に持つUUID。UUIDのidではid自身になる
+    fn as_uuid(self) -> Uuid {
+        match self.key() {
+            Key::Id(id) => Uuid::from_u128(id as u128),
+            Key::Uuid(uuid) => uuid,
+        }
+    }
+}
+
+impl EntityId for i32 {
+    const TYPE: IdType = IdType::Serial;
+
+    fn parse(s: &str) -> Result<Option<Self>, KeyError> {
+        match s.parse::<i32>() {
+            Ok(id) if id > 0 => Ok(Some(id)),
+            Ok(_) => Err(KeyError::NotPositive),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn assign(seq: i32) -> Self {
+        seq
+    }
+
+    fn key(self) -> Key {
+        Key::Id(self)
+    }
+
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::Id(id) => Some(id),
+            Key::Uuid(_) => None,
+        }
+    }
+}
+
+impl EntityId for Uuid {
+    const TYPE: IdType = IdType::Uuid;
+
+    fn parse(s: &str) -> Result<Option<Self>, KeyError> {
+        Ok(Uuid::parse_str(s).ok())
+    }
+
+    // 連番をカウンターとして埋め、同じミリ秒に振っても振った順に並べる
+    fn assign(seq: i32) -> Self {
+        let mut bytes = new_v7().as_bytes().to_owned();
+        bytes[6..8].copy_from_slice(&(0x7000 | (seq as u16 & 0x0fff)).to_be_bytes());
+        bytes[8..12].copy_from_slice(&(0x8000_0000 | (seq as u32 >> 12)).to_be_bytes());
+        Uuid::from_bytes(bytes)
+    }
+
+    fn key(self) -> Key {
+        Key::Uuid(self)
+    }
+
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::Id(_) => None,
+            Key::Uuid(uuid) => Some(uuid),
+        }
+    }
+}
+
+// データベースのuuid_generate_v7と同じく時刻順に並ぶUUID v7を生成する
+pub fn new_v7() -> Uuid {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[..6].copy_from_slice(&Utc::now().timestamp_millis().to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+// クエリやボディで受け取るid。連番のidは1以上だけを受け付ける
+pub fn validate_id<I: EntityId>(id: &I) -> Result<(), ValidationError> {
+    match id.key() {
+        Key::Id(id) if id < 1 => {
+            let mut error = ValidationError::new("range");
+            error.message = Some("validation.positive".into());
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
+
+// パスで指定された識別子
+// 連番のidではUUIDでも受け付け、UUIDのidではidそのものになる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key<I = i32> {
+    Id(I),
+    Uuid(Uuid),
+}
+
+impl<I: fmt::Display> fmt::Display for Key<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Id(id) => id.fmt(f),
+            Key::Uuid(uuid) => uuid.fmt(f),
+        }
+    }
+}
+
+// 識別子として使えない値
+// レスポンスで翻訳できるよう、メッセージキーを文言にする
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum KeyError {
+    #[error("validation.positive")]
+    NotPositive,
+    #[error("validation.invalid_path")]
+    Invalid(#[from] uuid::Error),
+}
+
+impl<I: EntityId> FromStr for Key<I> {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match I::parse(s)? {
+            Some(id) => Ok(Key::Id(id)),
+            None => Ok(Key::Uuid(Uuid::parse_str(s)?)),
+        }
+    }
+}
+
+impl<'de, I: EntityId> Deserialize<'de> for Key<I> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+// /todos/:id/blocks/:blocked_id のように2つの識別子を含むパス
+pub type KeyPair<I = i32> = (Key<I>, Key<I>);
+
+// パスで指定された連番のid
+// 0以下の値は受け付けない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Id(pub i32);
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match i32::deserialize(deserializer)? {
+            id if id > 0 => Ok(Id(id)),
+            _ => Err(de::Error::custom(KeyError::NotPositive)),
+        }
+    }
 }
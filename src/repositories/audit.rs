@@ -0,0 +1,1327 @@
+use crate::auth::current_principal;
+use crate::repositories::database::{atomically, Database};
+use crate::repositories::labels::{
+    CreateLabel, Label, LabelFilter, LabelRepository, LabelsVersion, UpdateLabel,
+};
+use crate::repositories::todo::{
+    find_existing, CompletedRange, CreateTodo, CycleTime, ListedTodo, Permission, Share,
+    TodoChanges, TodoDependencies, TodoEntity, TodoFilter, TodoPage, TodoRepository, TodoRevision,
+    TodoStream, TodoSuggestion, UpdateTodo, Visibility, RESTORABLE_FIELDS,
+};
+use crate::repositories::users::User;
+use crate::repositories::{validate_id, EntityId, RepositoryError};
+use axum::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::FromRow;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Undo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum AuditEntity {
+    Todo,
+    Label,
+    // todoの共有。entity_idは共有したtodoのid
+    Share,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, FromRow)]
+pub struct AuditLog<I = i32> {
+    pub id: i32,
+    pub actor: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: I,
+    pub old_value: Option<Json<Value>>,
+    pub new_value: Option<Json<Value>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateAuditLog<I = i32> {
+    pub actor: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: I,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+}
+
+// GET /audit-logs のクエリパラメータ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Validate)]
+#[serde(bound(deserialize = ""))]
+pub struct AuditLogFilter<I: EntityId = i32> {
+    pub entity: Option<AuditEntity>,
+    #[validate(custom = "validate_id")]
+    pub entity_id: Option<I>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+// バーンダウンを集計するプロジェクトと期間。日付はUTCで区切り、toの日も含む
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BurndownRange {
+    pub project_id: i32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl BurndownRange {
+    fn days(&self) -> impl Iterator<Item = NaiveDate> {
+        let to = self.to;
+        self.from.iter_days().take_while(move |day| *day <= to)
+    }
+}
+
+// その日の終わりの時点で、完了も中止もしていないtodoの見積もりの合計
+// 見積もりのないtodoは件数にだけ数える
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, FromRow)]
+pub struct BurndownPoint {
+    pub date: NaiveDate,
+    pub remaining_minutes: i64,
+    pub open_todos: i64,
+}
+
+fn end_of_day(day: NaiveDate) -> DateTime<Utc> {
+    DateTime::from_naive_utc_and_offset(
+        (day + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap(),
+        Utc,
+    )
+}
+
+// 監査ログに残したtodoの変更後の値が、プロジェクトの残りの作業に含まれるか
+fn remaining_estimate(todo: &Value, project_id: i32) -> Option<i64> {
+    let open = !matches!(todo["status"].as_str(), Some("done" | "cancelled"));
+    (open && todo["project_id"].as_i64() == Some(project_id as i64))
+        .then(|| todo["estimate_minutes"].as_i64().unwrap_or(0))
+}
+
+// todoの記録は、取り消しで戻せる項目を変えたものだけを取り消せる
+fn is_restorable<I>(log: &AuditLog<I>) -> bool {
+    if log.entity != AuditEntity::Todo {
+        return true;
+    }
+    let (Some(Json(old)), Some(Json(new))) = (&log.old_value, &log.new_value) else {
+        return true;
+    };
+    RESTORABLE_FIELDS
+        .iter()
+        .any(|field| old.get(field) != new.get(field))
+}
+
+#[async_trait]
+pub trait AuditLogRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(&self, payload: CreateAuditLog<I>) -> anyhow::Result<AuditLog<I>>;
+    async fn all(&self, filter: AuditLogFilter<I>) -> anyhow::Result<Vec<AuditLog<I>>>;
+
+    // 日ごとに、その日の終わりの時点での各todoの最新の記録から残りの見積もりを求める
+    // 既定ではallの結果から求めるので、データベースで集計できる実装で上書きする
+    async fn burndown(&self, range: BurndownRange) -> anyhow::Result<Vec<BurndownPoint>> {
+        let logs = self
+            .all(AuditLogFilter {
+                entity: Some(AuditEntity::Todo),
+                to: Some(end_of_day(range.to)),
+                ..Default::default()
+            })
+            .await?;
+        let mut latest = BTreeMap::new();
+        let mut logs = logs.iter().peekable();
+        let points = range
+            .days()
+            .map(|date| {
+                while let Some(log) = logs.next_if(|log| log.created_at < end_of_day(date)) {
+                    latest.insert(log.entity_id, log.new_value.as_ref().map(|value| &value.0));
+                }
+                let remaining: Vec<i64> = latest
+                    .values()
+                    .flatten()
+                    .filter_map(|todo| remaining_estimate(todo, range.project_id))
+                    .collect();
+                BurndownPoint {
+                    date,
+                    remaining_minutes: remaining.iter().sum(),
+                    open_todos: remaining.len() as i64,
+                }
+            })
+            .collect();
+        Ok(points)
+    }
+
+    // 取り消し済みの変更と、取り消しても何も戻らない変更を除いた、最新の更新履歴を返す
+    async fn last_undoable(
+        &self,
+        entity: AuditEntity,
+        entity_id: I,
+    ) -> anyhow::Result<AuditLog<I>> {
+        let logs = self
+            .all(AuditLogFilter {
+                entity: Some(entity),
+                entity_id: Some(entity_id),
+                ..Default::default()
+            })
+            .await?;
+
+        let mut undone = 0;
+        for log in logs.into_iter().rev() {
+            match log.action {
+                AuditAction::Update if !is_restorable(&log) => continue,
+                AuditAction::Undo => undone += 1,
+                AuditAction::Update if undone > 0 => undone -= 1,
+                AuditAction::Update => return Ok(log),
+                AuditAction::Create | AuditAction::Delete => break,
+            }
+        }
+        Err(RepositoryError::NothingToUndo(entity_id.key()).into())
+    }
+}
+
+#[async_trait]
+pub trait UndoTodoRepository<I: EntityId = i32>: TodoRepository<I> {
+    async fn undo(&self, id: I) -> anyhow::Result<TodoEntity<I>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLogRepositoryForDb<I = i32> {
+    db: Database,
+    id: PhantomData<I>,
+}
+
+impl AuditLogRepositoryForDb {
+    pub fn new(db: impl Into<Database>) -> Self {
+        Self::with_ids(db)
+    }
+}
+
+impl<I: EntityId> AuditLogRepositoryForDb<I> {
+    pub fn with_ids(db: impl Into<Database>) -> Self {
+        Self {
+            db: db.into(),
+            id: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> AuditLogRepository<I> for AuditLogRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(&self, payload: CreateAuditLog<I>) -> anyhow::Result<AuditLog<I>> {
+        let log = sqlx::query_as::<_, AuditLog<I>>(
+            r#"
+insert into audit_logs (actor, action, entity, entity_id, old_value, new_value)
+values ($1, $2, $3, $4, $5, $6)
+returning *
+        "#,
+        )
+        .bind(payload.actor)
+        .bind(payload.action)
+        .bind(payload.entity)
+        .bind(payload.entity_id)
+        .bind(payload.old_value.map(Json))
+        .bind(payload.new_value.map(Json))
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(log)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self, filter: AuditLogFilter<I>) -> anyhow::Result<Vec<AuditLog<I>>> {
+        let logs = sqlx::query_as::<_, AuditLog<I>>(
+            r#"
+select * from audit_logs
+where ($1::text is null or entity = $1)
+  and ($2 is null or entity_id = $2)
+  and ($3::timestamptz is null or created_at >= $3)
+  and ($4::timestamptz is null or created_at < $4)
+order by id asc
+        "#,
+        )
+        .bind(filter.entity)
+        .bind(filter.entity_id)
+        .bind(filter.from)
+        .bind(filter.to)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(logs)
+    }
+
+    #[instrument(skip_all)]
+    async fn burndown(&self, range: BurndownRange) -> anyhow::Result<Vec<BurndownPoint>> {
+        let points = sqlx::query_as::<_, BurndownPoint>(
+            r#"
+select days.day::date as date,
+       coalesce(sum((latest.new_value ->> 'estimate_minutes')::integer), 0)::bigint as remaining_minutes,
+       count(latest.entity_id) as open_todos
+from generate_series($2::date, $3::date, interval '1 day') as days(day)
+left join lateral (
+    select distinct on (entity_id) entity_id, new_value
+    from audit_logs
+    where entity = 'todo'
+      and created_at < (days.day + interval '1 day') at time zone 'UTC'
+    order by entity_id, id desc
+) latest on (latest.new_value ->> 'project_id')::integer = $1
+       and latest.new_value ->> 'status' not in ('done', 'cancelled')
+group by days.day
+order by days.day
+        "#,
+        )
+        .bind(range.project_id)
+        .bind(range.from)
+        .bind(range.to)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(points)
+    }
+}
+
+// 更新系の操作を監査ログに記録するリポジトリのデコレータ
+// 変更と記録は1つのトランザクションで行い、記録できなければ変更も取り消す
+#[derive(Debug, Clone)]
+pub struct Audited<R, A> {
+    inner: R,
+    audit: A,
+}
+
+impl<R, A> Audited<R, A> {
+    pub fn new(inner: R, audit: A) -> Self {
+        Self { inner, audit }
+    }
+
+    async fn record<I: EntityId, T: Serialize>(
+        &self,
+        action: AuditAction,
+        entity: AuditEntity,
+        entity_id: I,
+        old_value: Option<&T>,
+        new_value: Option<&T>,
+    ) -> anyhow::Result<()>
+    where
+        A: AuditLogRepository<I>,
+    {
+        let actor = current_principal()
+            .map(|principal| principal.name)
+            .unwrap_or_else(|| String::from("system"));
+        self.audit
+            .create(CreateAuditLog {
+                actor,
+                action,
+                entity,
+                entity_id,
+                old_value: old_value.map(serde_json::to_value).transpose()?,
+                new_value: new_value.map(serde_json::to_value).transpose()?,
+            })
+            .await?;
+        Ok(())
+    }
+
+    // まとめて変更したtodoのうち、idsのものを記録する
+    async fn record_updates<I: EntityId>(
+        &self,
+        old_todos: Vec<TodoEntity<I>>,
+        ids: &[I],
+    ) -> anyhow::Result<()>
+    where
+        R: TodoRepository<I>,
+        A: AuditLogRepository<I>,
+    {
+        for old_todo in old_todos.iter().filter(|todo| ids.contains(&todo.id)) {
+            let todo = self.inner.find(old_todo.id).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                todo.id,
+                Some(old_todo),
+                Some(&todo),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn find_share<I: EntityId>(&self, id: I, user_id: i32) -> anyhow::Result<Option<Share<I>>>
+    where
+        R: TodoRepository<I>,
+    {
+        let shares = self.inner.shares(id).await?;
+        Ok(shares.into_iter().find(|share| share.user_id == user_id))
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>, A: AuditLogRepository<I>> TodoRepository<I>
+    for Audited<R, A>
+{
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let todo = self.inner.create(payload).await?;
+            self.record(
+                AuditAction::Create,
+                AuditEntity::Todo,
+                todo.id,
+                None,
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find(id).await
+    }
+
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_for_update(id).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        self.inner.find_by_text(text, visible_to).await
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.all(filter).await
+    }
+
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        self.inner.stream(filter).await
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        self.inner.count(filter).await
+    }
+
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        self.inner.page(filter, page).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.update(id, payload).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            self.inner.delete(id).await?;
+            self.record(
+                AuditAction::Delete,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                None,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        self.inner.history(id).await
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.set_reminder(id, remind_at).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.reminders().await
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.take_due_reminders(now).await
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.assign(id, assignee).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.move_to_project(id, project_id).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.set_parent(id, parent_id).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.pin(id, pinned).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            let old_todo = self.inner.find(id).await?;
+            let todo = self.inner.snooze(id, until).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        atomically(async {
+            let old_todos = find_existing(&self.inner, &todo_ids).await?;
+            let ids = self.inner.attach_label(label_id, todo_ids).await?;
+            self.record_updates(old_todos, &ids).await?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        atomically(async {
+            let old_todos = find_existing(&self.inner, &todo_ids).await?;
+            let ids = self.inner.detach_label(label_id, todo_ids).await?;
+            self.record_updates(old_todos, &ids).await?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        self.inner.children(id).await
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.descendants(id).await
+    }
+
+    // 依存関係はブロックされる側のtodoの変更として記録する
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        atomically(async {
+            let old_todo = self.inner.find(blocked_id).await?;
+            self.inner.add_dependency(blocker_id, blocked_id).await?;
+            let todo = self.inner.find(blocked_id).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                blocked_id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        atomically(async {
+            let old_todo = self.inner.find(blocked_id).await?;
+            self.inner.remove_dependency(blocker_id, blocked_id).await?;
+            let todo = self.inner.find(blocked_id).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Todo,
+                blocked_id,
+                Some(&old_todo),
+                Some(&todo),
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        self.inner.dependencies(id).await
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        self.inner.dependents(id).await
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.modified_at(id).await
+    }
+
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        self.inner.list_modified_at().await
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        self.inner.cycle_time(range).await
+    }
+
+    // 共有はtodoの変更と分けて、共有相手ごとの権限を記録する
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        atomically(async {
+            let old_share = self.find_share(id, user_id).await?;
+            let share = self.inner.share(id, user_id, permission).await?;
+            let action = match old_share {
+                Some(_) => AuditAction::Update,
+                None => AuditAction::Create,
+            };
+            self.record(
+                action,
+                AuditEntity::Share,
+                id,
+                old_share.as_ref(),
+                Some(&share),
+            )
+            .await?;
+            Ok(share)
+        })
+        .await
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        atomically(async {
+            let old_share = self.find_share(id, user_id).await?;
+            self.inner.unshare(id, user_id).await?;
+            self.record(
+                AuditAction::Delete,
+                AuditEntity::Share,
+                id,
+                old_share.as_ref(),
+                None,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        self.inner.shares(id).await
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        self.inner.suggest(query, visible_to, limit).await
+    }
+
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        self.inner.changes(since, visible_to).await
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: TodoRepository<I>, A: AuditLogRepository<I>> UndoTodoRepository<I>
+    for Audited<R, A>
+{
+    // 同時に取り消すと同じ変更を2度戻しかねないので、todoをロックしてから最新の記録を読む
+    async fn undo(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        atomically(async {
+            // 取り消せる記録がなければ、todoがなくても取り消せる変更がないと返す
+            let current = self.inner.find_for_update(id).await;
+            let log = self.audit.last_undoable(AuditEntity::Todo, id).await?;
+            let current = current?;
+            let Some(Json(old_value)) = log.old_value else {
+                return Err(RepositoryError::NothingToUndo(id.key()).into());
+            };
+            let old_todo: TodoEntity<I> = serde_json::from_value(old_value)?;
+
+            let todo = self.inner.update(id, old_todo.into()).await?;
+            self.record(
+                AuditAction::Undo,
+                AuditEntity::Todo,
+                id,
+                Some(&current),
+                Some(&todo),
+            )
+            .await?;
+            Ok(todo)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl<I: EntityId, R: LabelRepository<I>, A: AuditLogRepository<I>> LabelRepository<I>
+    for Audited<R, A>
+{
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>> {
+        atomically(async {
+            let label = self.inner.create(payload).await?;
+            self.record(
+                AuditAction::Create,
+                AuditEntity::Label,
+                label.id,
+                None,
+                Some(&label),
+            )
+            .await?;
+            Ok(label)
+        })
+        .await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Label<I>>> {
+        self.inner.all().await
+    }
+
+    async fn search(&self, filter: LabelFilter) -> anyhow::Result<Vec<Label<I>>> {
+        self.inner.search(filter).await
+    }
+
+    async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>> {
+        atomically(async {
+            let old_label = self
+                .inner
+                .all()
+                .await?
+                .into_iter()
+                .find(|label| label.id == id);
+            let label = self.inner.update(id, payload).await?;
+            self.record(
+                AuditAction::Update,
+                AuditEntity::Label,
+                id,
+                old_label.as_ref(),
+                Some(&label),
+            )
+            .await?;
+            Ok(label)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        atomically(async {
+            let old_label = self
+                .inner
+                .all()
+                .await?
+                .into_iter()
+                .find(|label| label.id == id);
+            self.inner.delete(id).await?;
+            self.record(
+                AuditAction::Delete,
+                AuditEntity::Label,
+                id,
+                old_label.as_ref(),
+                None,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64> {
+        atomically(async {
+            let old_label = self
+                .inner
+                .all()
+                .await?
+                .into_iter()
+                .find(|label| label.id == id);
+            let affected = self.inner.merge(id, target_id).await?;
+            self.record(
+                AuditAction::Delete,
+                AuditEntity::Label,
+                id,
+                old_label.as_ref(),
+                None,
+            )
+            .await?;
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>> {
+        self.inner.find_by_uuid(uuid).await
+    }
+
+    async fn version(&self) -> anyhow::Result<LabelsVersion> {
+        self.inner.version().await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+    use crate::repositories::todo::TodoRepositoryForDb;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
+
+        let repository = AuditLogRepositoryForDb::new(pool);
+        let started_at = Utc::now();
+
+        // create
+        let log = repository
+            .create(CreateAuditLog {
+                actor: String::from("tester"),
+                action: AuditAction::Update,
+                entity: AuditEntity::Label,
+                entity_id: -1,
+                old_value: Some(serde_json::json!({ "name": "old" })),
+                new_value: Some(serde_json::json!({ "name": "new" })),
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(log.action, AuditAction::Update);
+        assert_eq!(
+            log.old_value,
+            Some(Json(serde_json::json!({ "name": "old" })))
+        );
+
+        // all
+        let logs = repository
+            .all(AuditLogFilter {
+                entity: Some(AuditEntity::Label),
+                entity_id: Some(-1),
+                from: Some(started_at),
+                to: None,
+            })
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(logs.last(), Some(&log));
+
+        let logs = repository
+            .all(AuditLogFilter {
+                entity: Some(AuditEntity::Todo),
+                entity_id: Some(-1),
+                ..Default::default()
+            })
+            .await
+            .expect("[all] returned Err");
+        assert!(logs.is_empty());
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_aggregate_burndown_per_day() {
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
+        let repository = AuditLogRepositoryForDb::new(pool.clone());
+
+        let day = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+        let snapshots = [
+            (
+                1,
+                1,
+                serde_json::json!({ "project_id": 1, "status": "todo", "estimate_minutes": 30 }),
+            ),
+            (
+                2,
+                2,
+                serde_json::json!({ "project_id": 1, "status": "todo" }),
+            ),
+            (
+                2,
+                1,
+                serde_json::json!({ "project_id": 1, "status": "todo", "estimate_minutes": 20 }),
+            ),
+            (
+                2,
+                3,
+                serde_json::json!({ "project_id": 2, "status": "todo", "estimate_minutes": 45 }),
+            ),
+            (
+                3,
+                1,
+                serde_json::json!({ "project_id": 1, "status": "done", "estimate_minutes": 20 }),
+            ),
+        ];
+        for (offset, entity_id, snapshot) in snapshots {
+            let log = repository
+                .create(CreateAuditLog {
+                    actor: String::from("tester"),
+                    action: AuditAction::Update,
+                    entity: AuditEntity::Todo,
+                    entity_id,
+                    old_value: None,
+                    new_value: Some(snapshot),
+                })
+                .await
+                .expect("[create] returned Err");
+            // 記録した日時を、その日の正午に置き換える
+            sqlx::query("update audit_logs set created_at = $1 where id = $2")
+                .bind(end_of_day(day + Duration::days(offset - 1)) - Duration::hours(12))
+                .bind(log.id)
+                .execute(&pool)
+                .await
+                .expect("failed update created_at");
+        }
+
+        let points = repository
+            .burndown(BurndownRange {
+                project_id: 1,
+                from: day,
+                to: day + Duration::days(3),
+            })
+            .await
+            .expect("[burndown] returned Err");
+        let series: Vec<(i64, i64)> = points
+            .iter()
+            .map(|point| (point.remaining_minutes, point.open_todos))
+            .collect();
+        assert_eq!(vec![(30, 1), (20, 2), (0, 1), (0, 1)], series);
+        assert_eq!(day, points[0].date);
+
+        db.teardown().await;
+    }
+
+    // 記録だけが失敗する監査ログ
+    #[derive(Debug, Clone)]
+    struct FailingAuditLogRepository(AuditLogRepositoryForDb);
+
+    #[async_trait]
+    impl AuditLogRepository for FailingAuditLogRepository {
+        async fn create(&self, _payload: CreateAuditLog) -> anyhow::Result<AuditLog> {
+            anyhow::bail!("audit log is unavailable")
+        }
+
+        async fn all(&self, filter: AuditLogFilter) -> anyhow::Result<Vec<AuditLog>> {
+            self.0.all(filter).await
+        }
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_mutation_when_audit_fails() {
+        let db = TestDatabase::new().await;
+        let todos = TodoRepositoryForDb::new(db.pool.clone());
+        let todo = todos
+            .create(CreateTodo::new(String::from("unaudited"), vec![]))
+            .await
+            .expect("[create] returned Err");
+
+        let repository = Audited::new(
+            todos.clone(),
+            FailingAuditLogRepository(AuditLogRepositoryForDb::new(db.pool.clone())),
+        );
+        assert!(repository
+            .create(CreateTodo::new(String::from("rolled back"), vec![]))
+            .await
+            .is_err());
+        assert_eq!(
+            todos
+                .find_by_text("rolled back", Visibility::All)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(repository.pin(todo.id, true).await.is_err());
+        assert!(repository.delete(todo.id).await.is_err());
+        assert_eq!(todos.find(todo.id).await.unwrap(), todo);
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_undo_concurrent_requests_one_by_one() {
+        let db = TestDatabase::new().await;
+        let repository = Audited::new(
+            TodoRepositoryForDb::new(db.pool.clone()),
+            AuditLogRepositoryForDb::new(db.pool.clone()),
+        );
+        let todo = repository
+            .create(CreateTodo::new(String::from("first"), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let texts = ["second", "third", "fourth", "fifth"];
+        for text in texts {
+            repository
+                .update(
+                    todo.id,
+                    serde_json::from_value(serde_json::json!({ "text": text })).unwrap(),
+                )
+                .await
+                .expect("[update] returned Err");
+        }
+
+        // 同じ変更を2度戻さず、1つずつ前に戻す
+        let undone =
+            futures_util::future::join_all(texts.iter().map(|_| repository.undo(todo.id))).await;
+        let mut undone: Vec<String> = undone.into_iter().map(|todo| todo.unwrap().text).collect();
+        undone.sort();
+        assert_eq!(undone, vec!["first", "fourth", "second", "third"]);
+        assert_eq!(repository.find(todo.id).await.unwrap().text, "first");
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct AuditLogRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<Vec<AuditLog<I>>>>,
+    }
+
+    impl<I: EntityId> AuditLogFilter<I> {
+        fn matches(&self, log: &AuditLog<I>) -> bool {
+            self.entity.is_none_or(|entity| log.entity == entity)
+                && self.entity_id.is_none_or(|id| log.entity_id == id)
+                && self.from.is_none_or(|from| log.created_at >= from)
+                && self.to.is_none_or(|to| log.created_at < to)
+        }
+    }
+
+    impl AuditLogRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    impl<I: EntityId> AuditLogRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
+            Self {
+                store: Arc::default(),
+            }
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> AuditLogRepository<I> for AuditLogRepositoryForMemory<I> {
+        async fn create(&self, payload: CreateAuditLog<I>) -> anyhow::Result<AuditLog<I>> {
+            let mut store = self.store.write().unwrap();
+            let log = AuditLog {
+                id: (store.len() + 1) as i32,
+                actor: payload.actor,
+                action: payload.action,
+                entity: payload.entity,
+                entity_id: payload.entity_id,
+                old_value: payload.old_value.map(Json),
+                new_value: payload.new_value.map(Json),
+                created_at: Utc::now(),
+            };
+            store.push(log.clone());
+            Ok(log)
+        }
+
+        async fn all(&self, filter: AuditLogFilter<I>) -> anyhow::Result<Vec<AuditLog<I>>> {
+            let store = self.store.read().unwrap();
+            Ok(store
+                .iter()
+                .filter(|log| filter.matches(log))
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+
+        #[tokio::test]
+        async fn audited_todo_scenario() {
+            let audit = AuditLogRepositoryForMemory::new();
+            let repository = Audited::new(TodoRepositoryForMemory::new(vec![]), audit.clone());
+
+            let todo = repository
+                .create(CreateTodo::new(String::from("audited"), vec![]))
+                .await
+                .expect("failed create todo");
+            repository
+                .delete(todo.id)
+                .await
+                .expect("failed delete todo");
+
+            let logs = audit.all(AuditLogFilter::default()).await.unwrap();
+            let actions: Vec<AuditAction> = logs.iter().map(|log| log.action).collect();
+            assert_eq!(vec![AuditAction::Create, AuditAction::Delete], actions);
+            assert!(logs.iter().all(|log| log.actor == "system"));
+            assert_eq!(logs[1].old_value, logs[0].new_value);
+            assert_eq!(logs[1].new_value, None);
+        }
+
+        #[tokio::test]
+        async fn audited_share_scenario() {
+            let audit = AuditLogRepositoryForMemory::new();
+            let repository = Audited::new(TodoRepositoryForMemory::new(vec![]), audit.clone());
+            let todo = repository
+                .create(CreateTodo::new(String::from("shared"), vec![]))
+                .await
+                .expect("failed create todo");
+            audit.clear();
+
+            let read = repository
+                .share(todo.id, 2, Permission::Read)
+                .await
+                .unwrap();
+            let write = repository
+                .share(todo.id, 2, Permission::Write)
+                .await
+                .unwrap();
+            repository.unshare(todo.id, 2).await.unwrap();
+
+            // 共有はtodoの変更ではないので、取り消しの対象にならない
+            let logs = audit.all(AuditLogFilter::default()).await.unwrap();
+            assert!(logs.iter().all(|log| log.entity == AuditEntity::Share));
+            assert!(logs.iter().all(|log| log.entity_id == todo.id));
+            let changes: Vec<_> = logs
+                .iter()
+                .map(|log| (log.action, log.old_value.clone(), log.new_value.clone()))
+                .collect();
+            let json = |share: &Share| Some(Json(serde_json::to_value(share).unwrap()));
+            assert_eq!(
+                vec![
+                    (AuditAction::Create, None, json(&read)),
+                    (AuditAction::Update, json(&read), json(&write)),
+                    (AuditAction::Delete, json(&write), None),
+                ],
+                changes
+            );
+            assert!(repository.undo(todo.id).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn burndown_scenario() {
+            let repository = AuditLogRepositoryForMemory::new();
+            let day = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+            let snapshots = [
+                (
+                    1,
+                    1,
+                    Some(
+                        serde_json::json!({ "project_id": 1, "status": "todo", "estimate_minutes": 30 }),
+                    ),
+                ),
+                (
+                    2,
+                    2,
+                    Some(serde_json::json!({ "project_id": 1, "status": "todo" })),
+                ),
+                (
+                    2,
+                    1,
+                    Some(
+                        serde_json::json!({ "project_id": 1, "status": "todo", "estimate_minutes": 20 }),
+                    ),
+                ),
+                (3, 2, None),
+            ];
+            {
+                let mut store = repository.store.write().unwrap();
+                for (index, (offset, entity_id, snapshot)) in snapshots.into_iter().enumerate() {
+                    store.push(AuditLog {
+                        id: index as i32 + 1,
+                        actor: String::from("tester"),
+                        action: AuditAction::Update,
+                        entity: AuditEntity::Todo,
+                        entity_id,
+                        old_value: None,
+                        new_value: snapshot.map(Json),
+                        created_at: end_of_day(day + Duration::days(offset - 1))
+                            - Duration::hours(12),
+                    });
+                }
+            }
+
+            let points = repository
+                .burndown(BurndownRange {
+                    project_id: 1,
+                    from: day,
+                    to: day + Duration::days(2),
+                })
+                .await
+                .expect("failed burndown");
+            let series: Vec<(i64, i64)> = points
+                .iter()
+                .map(|point| (point.remaining_minutes, point.open_todos))
+                .collect();
+            assert_eq!(vec![(30, 1), (20, 2), (20, 1)], series);
+        }
+
+        fn update_text(text: &str) -> UpdateTodo {
+            serde_json::from_value(serde_json::json!({ "text": text })).unwrap()
+        }
+
+        #[tokio::test]
+        async fn undo_scenario() {
+            let repository = Audited::new(
+                TodoRepositoryForMemory::new(vec![]),
+                AuditLogRepositoryForMemory::new(),
+            );
+            let todo = repository
+                .create(CreateTodo::new(String::from("first"), vec![]))
+                .await
+                .expect("failed create todo");
+            assert!(repository.undo(todo.id).await.is_err());
+
+            let second = repository
+                .update(todo.id, update_text("second"))
+                .await
+                .unwrap();
+            let third = repository
+                .update(todo.id, update_text("third"))
+                .await
+                .unwrap();
+            assert_ne!(second, third);
+
+            // 最新の変更から順に取り消す
+            let undone = repository.undo(todo.id).await.expect("failed undo");
+            assert_eq!(second, undone);
+            let undone = repository.undo(todo.id).await.expect("failed undo");
+            assert_eq!(todo, undone);
+
+            let err = repository.undo(todo.id).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::NothingToUndo(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn should_skip_changes_undo_cannot_restore() {
+            let repository = Audited::new(
+                TodoRepositoryForMemory::new(vec![]),
+                AuditLogRepositoryForMemory::new(),
+            );
+            let todo = repository
+                .create(CreateTodo::new(String::from("first"), vec![]))
+                .await
+                .expect("failed create todo");
+            repository.pin(todo.id, true).await.unwrap();
+            // 固定だけでは取り消せる変更がない
+            let err = repository.undo(todo.id).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::NothingToUndo(_))
+            ));
+
+            repository
+                .update(todo.id, update_text("second"))
+                .await
+                .unwrap();
+            repository.pin(todo.id, false).await.unwrap();
+            // 固定の解除を飛ばして、本文の変更を取り消す
+            let undone = repository.undo(todo.id).await.expect("failed undo");
+            assert_eq!("first", undone.text);
+            assert!(!undone.pinned);
+            assert!(repository.undo(todo.id).await.is_err());
+        }
+    }
+}
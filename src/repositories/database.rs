@@ -0,0 +1,639 @@
+use crate::timeout::{current_deadline, in_request};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{
+    PgConnectOptions, PgPoolOptions, PgQueryResult, PgRow, PgStatement, PgTypeInfo,
+};
+use sqlx::{
+    Connection, Describe, Either, Execute, Executor, PgConnection, PgPool, Postgres, Statement,
+    Transaction,
+};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::time::Instant;
+
+// 作業単位の中で、リポジトリの呼び出しごとに置くセーブポイント
+const SAVEPOINT: &str = "repository";
+
+tokio::task_local! {
+    // atomicallyの中で、最初にプールへ問い合わせるときに始めるトランザクション
+    static AMBIENT: Arc<Mutex<Option<Arc<Mutex<SharedTransaction>>>>>;
+}
+
+// fの中でプールに発行する文を、すべて1つのトランザクションで実行する
+// fが成功したら確定し、失敗するか途中で破棄されたら取り消す。既に中にいれば外側にまとめる
+// デコレータが、包んだリポジトリの実装を知らずに前後の書き込みをまとめるために使う
+pub async fn atomically<T, F>(f: F) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    if in_atomically() {
+        return f.await;
+    }
+    let ambient = Arc::new(Mutex::new(None));
+    let value = AMBIENT.scope(ambient.clone(), f).await?;
+    let shared = ambient.lock().await.take();
+    if let Some(shared) = shared {
+        Database::Transaction(shared).commit_shared().await?;
+    }
+    Ok(value)
+}
+
+// atomicallyの中か
+// 中ではどのプールへの問い合わせも同じトランザクションで行うので、複製では読まない
+pub fn in_atomically() -> bool {
+    AMBIENT.try_with(|_| ()).is_ok()
+}
+
+// atomicallyの中なら、そのトランザクションを返す。まだ始めていなければpoolで始める
+async fn ambient(pool: &PgPool) -> Result<Option<Arc<Mutex<SharedTransaction>>>, sqlx::Error> {
+    let Ok(ambient) = AMBIENT.try_with(Arc::clone) else {
+        return Ok(None);
+    };
+    let mut ambient = ambient.lock().await;
+    if ambient.is_none() {
+        *ambient = Some(SharedTransaction::begin(pool).await?);
+    }
+    Ok(ambient.clone())
+}
+
+// リポジトリが読み書きする先
+// 作業単位の中では、同じトランザクションを複数のリポジトリで共有する
+#[derive(Debug, Clone)]
+pub enum Database {
+    Pool(PgPool),
+    Transaction(Arc<Mutex<SharedTransaction>>),
+}
+
+impl From<PgPool> for Database {
+    fn from(pool: PgPool) -> Self {
+        Database::Pool(pool)
+    }
+}
+
+impl Database {
+    // 作業単位のトランザクションを始める
+    pub async fn begin_shared(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        Ok(Database::Transaction(SharedTransaction::begin(pool).await?))
+    }
+
+    // 作業単位のトランザクションを確定する。プールに対しては何もしない
+    pub async fn commit_shared(&self) -> Result<(), sqlx::Error> {
+        match self {
+            Database::Pool(_) => Ok(()),
+            Database::Transaction(shared) => {
+                let mut shared = shared.lock().await;
+                shared.connection().await?;
+                shared
+                    .tx
+                    .take()
+                    .ok_or(sqlx::Error::PoolClosed)?
+                    .commit()
+                    .await
+            }
+        }
+    }
+
+    // 複数の文をまとめて確定する
+    // 作業単位やatomicallyの中ではセーブポイントとし、途中で失敗した場合はその呼び出しの変更だけを取り消す
+    pub async fn begin(&self) -> Result<DatabaseTransaction, sqlx::Error> {
+        let shared = match self {
+            Database::Pool(pool) => match ambient(pool).await? {
+                Some(shared) => shared,
+                None => return Ok(DatabaseTransaction::Own(Box::new(pool.begin().await?))),
+            },
+            Database::Transaction(shared) => shared.clone(),
+        };
+        let mut shared = shared.lock_owned().await;
+        shared
+            .connection()
+            .await?
+            .execute(format!("SAVEPOINT {}", SAVEPOINT).as_str())
+            .await?;
+        Ok(DatabaseTransaction::Savepoint(Savepoint {
+            shared,
+            released: false,
+        }))
+    }
+}
+
+// 作業単位で共有するトランザクション
+#[derive(Debug)]
+pub struct SharedTransaction {
+    // 確定した後はNone
+    tx: Option<Transaction<'static, Postgres>>,
+    // 破棄されたセーブポイントまで、次に使う前に戻す
+    rollback_pending: bool,
+}
+
+impl SharedTransaction {
+    async fn begin(pool: &PgPool) -> Result<Arc<Mutex<Self>>, sqlx::Error> {
+        let tx = pool.begin().await?;
+        Ok(Arc::new(Mutex::new(SharedTransaction {
+            tx: Some(tx),
+            rollback_pending: false,
+        })))
+    }
+
+    async fn connection(&mut self) -> Result<&mut PgConnection, sqlx::Error> {
+        let tx = self.tx.as_mut().ok_or(sqlx::Error::PoolClosed)?;
+        if self.rollback_pending {
+            tx.execute(
+                format!(
+                    "ROLLBACK TO SAVEPOINT {0}; RELEASE SAVEPOINT {0}",
+                    SAVEPOINT
+                )
+                .as_str(),
+            )
+            .await?;
+            self.rollback_pending = false;
+        }
+        Ok(&mut **tx)
+    }
+}
+
+// 確定せずに破棄すると、作業単位の残りを使う前にセーブポイントまで戻す
+#[derive(Debug)]
+pub struct Savepoint {
+    shared: OwnedMutexGuard<SharedTransaction>,
+    released: bool,
+}
+
+impl Drop for Savepoint {
+    fn drop(&mut self) {
+        if !self.released {
+            self.shared.rollback_pending = true;
+        }
+    }
+}
+
+// Database::beginで始めたトランザクション
+// sqlx::Transactionと同じく、確定せずに破棄すると取り消す
+#[derive(Debug)]
+pub enum DatabaseTransaction {
+    Own(Box<Transaction<'static, Postgres>>),
+    Savepoint(Savepoint),
+}
+
+impl DatabaseTransaction {
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        match self {
+            DatabaseTransaction::Own(tx) => (*tx).commit().await,
+            DatabaseTransaction::Savepoint(mut savepoint) => {
+                savepoint
+                    .execute(format!("RELEASE SAVEPOINT {}", SAVEPOINT).as_str())
+                    .await?;
+                savepoint.released = true;
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        match self {
+            DatabaseTransaction::Own(tx) => (*tx).rollback().await,
+            DatabaseTransaction::Savepoint(mut savepoint) => {
+                savepoint
+                    .execute(
+                        format!(
+                            "ROLLBACK TO SAVEPOINT {0}; RELEASE SAVEPOINT {0}",
+                            SAVEPOINT
+                        )
+                        .as_str(),
+                    )
+                    .await?;
+                savepoint.released = true;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Deref for DatabaseTransaction {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DatabaseTransaction::Own(tx) => tx,
+            DatabaseTransaction::Savepoint(savepoint) => savepoint,
+        }
+    }
+}
+
+impl DerefMut for DatabaseTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            DatabaseTransaction::Own(tx) => tx,
+            DatabaseTransaction::Savepoint(savepoint) => savepoint,
+        }
+    }
+}
+
+impl Deref for Savepoint {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.shared
+            .tx
+            .as_ref()
+            .expect("savepoint outlived transaction")
+    }
+}
+
+impl DerefMut for Savepoint {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.shared
+            .tx
+            .as_mut()
+            .expect("savepoint outlived transaction")
+    }
+}
+
+// pidを調べる文。接続したときに1度だけ実行し、準備した文は接続ごとのキャッシュに残る
+const BACKEND_PID: &str = "select pg_backend_pid()";
+
+// 取り消し先ごとに覚えておく接続の数。古いものから忘れ、忘れた接続の問い合わせは取り消さない
+const MAX_BACKENDS: usize = 1024;
+
+// 問い合わせを取り消すのに使う接続先
+// 取り消しはプールの外に持つ専用の接続から送り、プールを使い切っていても取り消せるようにする
+#[derive(Debug)]
+struct Canceller {
+    options: PgConnectOptions,
+    // 最初に取り消すときに繋ぎ、失敗したら次に繋ぎ直す
+    conn: Mutex<Option<PgConnection>>,
+    // プールの接続ごとに、キャッシュされたBACKEND_PIDの文とバックエンドのpid
+    // 文を持ち続けるので、閉じた接続の文があった場所をほかの接続の文が使うことはない
+    backends: std::sync::Mutex<VecDeque<(PgStatement<'static>, i32)>>,
+}
+
+impl Canceller {
+    fn remember(&self, statement: PgStatement<'static>, pid: i32) {
+        let mut backends = self.backends.lock().unwrap();
+        if backends.len() >= MAX_BACKENDS {
+            backends.pop_front();
+        }
+        backends.push_back((statement, pid));
+    }
+
+    // 文がキャッシュから追い出されて準備し直された接続では、pidが分からないのでNone
+    fn backend_pid(&self, statement: &PgStatement<'_>) -> Option<i32> {
+        let columns = statement.columns().as_ptr();
+        self.backends
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(remembered, _)| remembered.columns().as_ptr() == columns)
+            .map(|(_, pid)| *pid)
+    }
+
+    async fn cancel(&self, pid: i32) -> Result<(), sqlx::Error> {
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            *conn = Some(PgConnection::connect_with(&self.options).await?);
+        }
+        let result = sqlx::query("select pg_cancel_backend($1)")
+            .bind(pid)
+            .execute(conn.as_mut().expect("cancel connection is not established"))
+            .await;
+        if result.is_err() {
+            *conn = None;
+        }
+        result.map(|_| ())
+    }
+}
+
+// cancellableで作ったプールの取り消し先
+static CANCELLERS: std::sync::Mutex<Vec<Arc<Canceller>>> = std::sync::Mutex::new(Vec::new());
+
+// 実行中の問い合わせを取り消せるプールの設定にする
+// 接続したときに1度だけpidを調べて取り消し先に覚えさせ、問い合わせのたびには調べない
+// optionsには、プールと同じ接続先を渡す。起動時にプールごとに1度だけ呼ぶ
+pub fn cancellable(pool: PgPoolOptions, options: &PgConnectOptions) -> PgPoolOptions {
+    let canceller = Arc::new(Canceller {
+        options: options.clone(),
+        conn: Mutex::new(None),
+        backends: std::sync::Mutex::new(VecDeque::new()),
+    });
+    CANCELLERS.lock().unwrap().push(canceller.clone());
+    pool.after_connect(move |conn| {
+        let canceller = canceller.clone();
+        Box::pin(async move {
+            let pid: i32 = sqlx::query_scalar(BACKEND_PID)
+                .fetch_one(&mut *conn)
+                .await?;
+            let statement = conn.prepare(BACKEND_PID).await?;
+            canceller.remember(Statement::to_owned(&statement), pid);
+            Ok(())
+        })
+    })
+}
+
+// 接続の取り消し先とpid。cancellableで作っていないプールの接続ではNone
+// キャッシュされた文を読むだけなので、問い合わせはしない
+async fn cancel_key(conn: &mut PgConnection) -> Option<(Arc<Canceller>, i32)> {
+    let statement = conn.prepare(BACKEND_PID).await.ok()?;
+    CANCELLERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find_map(|canceller| Some((canceller.clone(), canceller.backend_pid(&statement)?)))
+}
+
+// リクエストの処理中に問い合わせに使う接続
+// タイムアウトやクライアントの切断で問い合わせの途中に破棄されたら、サーバー側でも取り消す
+// 取り消さずにプールへ返すと、返す前の確認が問い合わせの終わりを待ち、その間接続を使えない
+struct CancellableConnection {
+    conn: Option<PoolConnection<Postgres>>,
+    key: Option<(Arc<Canceller>, i32)>,
+    deadline: Option<Instant>,
+    finished: bool,
+}
+
+impl CancellableConnection {
+    async fn acquire(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        let mut conn = pool.acquire().await?;
+        let key = cancel_key(&mut conn).await;
+        Ok(Self {
+            conn: Some(conn),
+            key,
+            deadline: current_deadline(),
+            finished: false,
+        })
+    }
+
+    // 最後まで読んだので、取り消さずにプールへ返す
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Deref for CancellableConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("connection already released")
+    }
+}
+
+impl DerefMut for CancellableConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().expect("connection already released")
+    }
+}
+
+impl Drop for CancellableConnection {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take().filter(|_| !self.finished) else {
+            return;
+        };
+        let Some((canceller, pid)) = self.key.take() else {
+            return;
+        };
+        let reason = match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => "deadline exceeded",
+            _ => "request dropped",
+        };
+        // 取り消すまで接続は返さない。先に返すと次に使った問い合わせを取り消しかねない
+        tokio::spawn(async move {
+            match canceller.cancel(pid).await {
+                Ok(_) => tracing::debug!("cancelled query of backend [{}]: {}", pid, reason),
+                Err(e) => tracing::warn!("failed to cancel query of backend [{}]: {}", pid, e),
+            }
+            drop(conn);
+        });
+    }
+}
+
+// &PgPoolと同じように問い合わせに渡せるようにする
+// リクエストの処理中は、途中で破棄されても取り消せるよう結果をすべて読んでから返す
+// 作業単位やatomicallyの中では文ごとにセーブポイントを置くため、同じく結果はすべて読んでから返す
+impl<'c> Executor<'c> for &'c Database {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        match self {
+            Database::Pool(pool) if in_request() && !in_atomically() => stream::once(async move {
+                let mut conn = CancellableConnection::acquire(pool).await?;
+                let results: Vec<_> = (&mut *conn).fetch_many(query).collect().await;
+                conn.finish();
+                Ok::<_, sqlx::Error>(stream::iter(results))
+            })
+            .try_flatten()
+            .boxed(),
+            Database::Pool(pool) if !in_atomically() => pool.fetch_many(query),
+            _ => stream::once(async move {
+                let mut tx = self.begin().await?;
+                let results: Vec<_> = (&mut *tx).fetch_many(query).collect().await;
+                if results.iter().all(Result::is_ok) {
+                    tx.commit().await?;
+                }
+                Ok::<_, sqlx::Error>(stream::iter(results))
+            })
+            .try_flatten()
+            .boxed(),
+        }
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        match self {
+            Database::Pool(pool) if in_request() && !in_atomically() => Box::pin(async move {
+                let mut conn = CancellableConnection::acquire(pool).await?;
+                let row = (&mut *conn).fetch_optional(query).await?;
+                conn.finish();
+                Ok(row)
+            }),
+            Database::Pool(pool) if !in_atomically() => pool.fetch_optional(query),
+            _ => Box::pin(async move {
+                let mut tx = self.begin().await?;
+                let row = (&mut *tx).fetch_optional(query).await?;
+                tx.commit().await?;
+                Ok(row)
+            }),
+        }
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        match self {
+            Database::Pool(pool) => pool.prepare_with(sql, parameters),
+            Database::Transaction(shared) => Box::pin(async move {
+                let mut shared = shared.lock().await;
+                shared
+                    .connection()
+                    .await?
+                    .prepare_with(sql, parameters)
+                    .await
+            }),
+        }
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        match self {
+            Database::Pool(pool) => pool.describe(sql),
+            Database::Transaction(shared) => Box::pin(async move {
+                let mut shared = shared.lock().await;
+                shared.connection().await?.describe(sql).await
+            }),
+        }
+    }
+}
+
+// sqlx::Transactionと同じく&mut txで問い合わせに渡せるようにする
+impl<'c> Executor<'c> for &'c mut DatabaseTransaction {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        (&mut **self).fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        (&mut **self).fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut **self).prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut **self).describe(sql)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+    use crate::timeout::with_deadline;
+    use std::time::Duration;
+
+    const SLOW_QUERY: &str = "select pg_sleep(29.5)";
+
+    async fn running(conn: &mut PgConnection) -> i64 {
+        sqlx::query_scalar(
+            "select count(*) from pg_stat_activity where query = $1 and state = 'active'",
+        )
+        .bind(SLOW_QUERY)
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_cancel_query_when_request_is_dropped() {
+        let db = TestDatabase::new().await;
+        let database = Database::from(db.pool.clone());
+
+        // テスト用のプールは5つまで。残りの接続を使い切っても、プールの外の接続から取り消す
+        let mut held = Vec::new();
+        for _ in 0..4 {
+            held.push(db.pool.acquire().await.unwrap());
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let timed_out = tokio::time::timeout_at(
+            deadline,
+            with_deadline(Some(deadline), sqlx::query(SLOW_QUERY).execute(&database)),
+        )
+        .await;
+        assert!(timed_out.is_err());
+
+        let mut remaining = running(&mut held[0]).await;
+        for _ in 0..50 {
+            if remaining == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            remaining = running(&mut held[0]).await;
+        }
+        assert_eq!(remaining, 0);
+
+        drop(held);
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_remember_backend_pid_per_connection() {
+        let db = TestDatabase::new().await;
+        let mut first = db.pool.acquire().await.unwrap();
+        let mut second = db.pool.acquire().await.unwrap();
+
+        for conn in [&mut first, &mut second] {
+            let pid: i32 = sqlx::query_scalar("select pg_backend_pid()")
+                .fetch_one(&mut *conn)
+                .await
+                .unwrap();
+            let (_, remembered) = cancel_key(conn).await.expect("no cancel key");
+            assert_eq!(pid, remembered);
+        }
+        // pidはRust側に覚えるので、接続に一時ビューを作らない
+        let view: Option<String> =
+            sqlx::query_scalar("select to_regclass('pg_temp.cancel_key')::text")
+                .fetch_one(&mut first)
+                .await
+                .unwrap();
+        assert_eq!(view, None);
+
+        drop(first);
+        drop(second);
+        db.teardown().await;
+    }
+}
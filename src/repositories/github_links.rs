@@ -0,0 +1,232 @@
+use crate::repositories::EntityId;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::marker::PhantomData;
+use tracing::instrument;
+
+// todoとGitHubのissueの紐付け
+// リポジトリ名は大文字と小文字を区別せずに探せるよう、小文字にそろえて渡す
+#[async_trait]
+pub trait GithubLinkRepository<I: EntityId = i32>: Send + Sync + 'static {
+    // todoごとに1件まで。既に紐付けている場合は置き換える
+    async fn link(
+        &self,
+        todo_id: I,
+        repository: &str,
+        issue_number: i32,
+    ) -> anyhow::Result<GithubLink<I>>;
+    // issueに紐付いたtodo
+    async fn by_issue(
+        &self,
+        repository: &str,
+        issue_number: i32,
+    ) -> anyhow::Result<Vec<GithubLink<I>>>;
+    // webhookの配信IDを記録する。初めて受け取った場合だけtrue
+    async fn record_delivery(&self, delivery_id: &str) -> anyhow::Result<bool>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct GithubLink<I = i32> {
+    pub todo_id: I,
+    pub repository: String,
+    pub issue_number: i32,
+    pub linked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GithubLinkRepositoryForDb<I = i32> {
+    pool: PgPool,
+    id: PhantomData<I>,
+}
+
+impl GithubLinkRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_ids(pool)
+    }
+}
+
+impl<I: EntityId> GithubLinkRepositoryForDb<I> {
+    pub fn with_ids(pool: PgPool) -> Self {
+        Self {
+            pool,
+            id: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> GithubLinkRepository<I> for GithubLinkRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn link(
+        &self,
+        todo_id: I,
+        repository: &str,
+        issue_number: i32,
+    ) -> anyhow::Result<GithubLink<I>> {
+        let link = sqlx::query_as::<_, GithubLink<I>>(
+            r#"
+INSERT INTO github_links (todo_id, repository, issue_number) VALUES ($1, $2, $3)
+ON CONFLICT (todo_id) DO UPDATE
+SET repository = excluded.repository, issue_number = excluded.issue_number, linked_at = now()
+RETURNING *
+            "#,
+        )
+        .bind(todo_id)
+        .bind(repository)
+        .bind(issue_number)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    #[instrument(skip_all)]
+    async fn by_issue(
+        &self,
+        repository: &str,
+        issue_number: i32,
+    ) -> anyhow::Result<Vec<GithubLink<I>>> {
+        let links = sqlx::query_as::<_, GithubLink<I>>(
+            r#"SELECT * FROM github_links WHERE repository = $1 AND issue_number = $2 ORDER BY todo_id"#,
+        )
+        .bind(repository)
+        .bind(issue_number)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(links)
+    }
+
+    #[instrument(skip_all)]
+    async fn record_delivery(&self, delivery_id: &str) -> anyhow::Result<bool> {
+        let inserted = sqlx::query(
+            r#"INSERT INTO github_deliveries (delivery_id) VALUES ($1) ON CONFLICT DO NOTHING"#,
+        )
+        .bind(delivery_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(inserted == 1)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let repository = GithubLinkRepositoryForDb::new(db.pool.clone());
+
+        // link
+        let link = repository
+            .link(1, "octo/api", 7)
+            .await
+            .expect("[link] returned Err");
+        assert_eq!((link.todo_id, link.issue_number), (1, 7));
+        repository
+            .link(2, "octo/api", 7)
+            .await
+            .expect("[link] returned Err");
+
+        // by_issue
+        let links = repository
+            .by_issue("octo/api", 7)
+            .await
+            .expect("[by_issue] returned Err");
+        assert_eq!(
+            links.iter().map(|link| link.todo_id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(repository.by_issue("octo/web", 7).await.unwrap().is_empty());
+
+        // 紐付け直すと元のissueからは外れる
+        let relinked = repository
+            .link(1, "octo/web", 8)
+            .await
+            .expect("[link] returned Err");
+        assert_eq!(relinked.repository, "octo/web");
+        let links = repository.by_issue("octo/api", 7).await.unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].todo_id, 2);
+
+        // record_delivery
+        assert!(repository.record_delivery("delivery-1").await.unwrap());
+        assert!(!repository.record_delivery("delivery-1").await.unwrap());
+        assert!(repository.record_delivery("delivery-2").await.unwrap());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct GithubLinkRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<BTreeMap<I, GithubLink<I>>>>,
+        deliveries: Arc<RwLock<HashSet<String>>>,
+    }
+
+    impl GithubLinkRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    impl<I: EntityId> GithubLinkRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
+            Self {
+                store: Arc::default(),
+                deliveries: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> GithubLinkRepository<I> for GithubLinkRepositoryForMemory<I> {
+        async fn link(
+            &self,
+            todo_id: I,
+            repository: &str,
+            issue_number: i32,
+        ) -> anyhow::Result<GithubLink<I>> {
+            let link = GithubLink {
+                todo_id,
+                repository: repository.to_string(),
+                issue_number,
+                linked_at: Utc::now(),
+            };
+            let mut store = self.store.write().unwrap();
+            store.insert(todo_id, link.clone());
+            Ok(link)
+        }
+
+        async fn by_issue(
+            &self,
+            repository: &str,
+            issue_number: i32,
+        ) -> anyhow::Result<Vec<GithubLink<I>>> {
+            let store = self.store.read().unwrap();
+            Ok(store
+                .values()
+                .filter(|link| link.repository == repository && link.issue_number == issue_number)
+                .cloned()
+                .collect())
+        }
+
+        async fn record_delivery(&self, delivery_id: &str) -> anyhow::Result<bool> {
+            let mut deliveries = self.deliveries.write().unwrap();
+            Ok(deliveries.insert(delivery_id.to_string()))
+        }
+    }
+}
@@ -1,186 +1,851 @@
-use crate::repositories::RepositoryError;
+use crate::repositories::database::Database;
+use crate::repositories::{escape_like, is_unique_violation, EntityId, Key, RepositoryError};
 use axum::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::FromRow;
+use std::marker::PhantomData;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
 
 #[async_trait]
-pub trait LabelRepository: Clone + Send + Sync + 'static {
-    async fn create(&self, name: String) -> anyhow::Result<Label>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+pub trait LabelRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>>;
+    async fn all(&self) -> anyhow::Result<Vec<Label<I>>>;
+    async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>>;
+    async fn delete(&self, id: I) -> anyhow::Result<()>;
+    // idのラベルが付いたtodoをtarget_idのラベルに付け替えて削除し、付け替えたtodoの数を返す
+    async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64>;
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>>;
+    // 一覧が変わったかどうかを、一覧を読まずに判断するための版
+    async fn version(&self) -> anyhow::Result<LabelsVersion>;
+
+    // 名前の前方一致で絞り込み、指定された順に並べる
+    async fn search(&self, filter: LabelFilter) -> anyhow::Result<Vec<Label<I>>> {
+        let mut labels = self.all().await?;
+        if let Some(prefix) = &filter.prefix {
+            let prefix = prefix.to_lowercase();
+            labels.retain(|label| label.name.to_lowercase().starts_with(&prefix));
+        }
+        filter.sort.sort(&mut labels);
+        Ok(labels)
+    }
+
+    // パスで指定された識別子をidに解決する
+    async fn resolve(&self, key: Key<I>) -> anyhow::Result<I> {
+        match key {
+            Key::Id(id) => Ok(id),
+            Key::Uuid(uuid) => Ok(self.find_by_uuid(uuid).await?.id),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
-pub struct Label {
-    pub id: i32,
+pub struct Label<I = i32> {
+    pub id: I,
+    // 記録済みのtodoの版など、UUIDを持たない値も読めるようにする
+    #[serde(default)]
+    pub uuid: Uuid,
     pub name: String,
+    // 色や説明のない版も読めるようにする
+    #[serde(default = "default_color")]
+    pub color: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+// 最後の更新時刻と件数の組
+// 削除では最後の更新時刻が変わらないことがあるので、件数と合わせて版にする
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromRow)]
+pub struct LabelsVersion {
+    pub updated_at: Option<DateTime<Utc>>,
+    pub count: i64,
+}
+
+impl LabelsVersion {
+    // 強いETag
+    pub fn etag(&self) -> String {
+        let micros = self
+            .updated_at
+            .map(|updated_at| updated_at.timestamp_micros())
+            .unwrap_or_default();
+        format!("\"{}-{}\"", self.count, micros)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+// ラベルの一覧の絞り込み
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelFilter {
+    // 大文字と小文字を区別しない名前の前方一致
+    pub prefix: Option<String>,
+    pub sort: LabelSort,
+}
+
+// 一覧の並び順。先頭に-を付けると降順
+// 名前は大文字と小文字を区別せずに並べ、同じ名前はidの順にする
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum LabelSort {
+    #[default]
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "-name")]
+    NameDesc,
+}
+
+impl LabelSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            LabelSort::Id => "id ASC",
+            LabelSort::IdDesc => "id DESC",
+            LabelSort::Name => "lower(name) ASC, id ASC",
+            LabelSort::NameDesc => "lower(name) DESC, id DESC",
+        }
+    }
+
+    fn sort<I: EntityId>(self, labels: &mut [Label<I>]) {
+        match self {
+            LabelSort::Id => labels.sort_by_key(|label| label.id),
+            LabelSort::IdDesc => labels.sort_by_key(|label| std::cmp::Reverse(label.id)),
+            LabelSort::Name => labels.sort_by_key(|label| (label.name.to_lowercase(), label.id)),
+            LabelSort::NameDesc => {
+                labels.sort_by_key(|label| std::cmp::Reverse((label.name.to_lowercase(), label.id)))
+            }
+        }
+    }
+}
+
+fn default_color() -> String {
+    "#808080".to_string()
+}
+
+// #RGBか#RRGGBBの形式だけを受け付ける
+pub(crate) fn validate_color(color: &str) -> Result<(), ValidationError> {
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    if !matches!(hex.len(), 3 | 6) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        let mut error = ValidationError::new("color");
+        error.message = Some("validation.color".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// 保存する色は小文字の#RRGGBBにそろえる
+pub(crate) fn normalize_color(color: &str) -> String {
+    let hex = color.trim_start_matches('#').to_ascii_lowercase();
+    if hex.len() == 3 {
+        format!("#{}", hex.chars().flat_map(|c| [c, c]).collect::<String>())
+    } else {
+        format!("#{}", hex)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct CreateLabel {
+    // 大文字と小文字や空白の違いだけの名前は同じラベルとして扱う
+    #[serde(deserialize_with = "crate::trim::collapse")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    name: String,
+    #[serde(default = "default_color", deserialize_with = "crate::trim::string")]
+    #[validate(custom = "validate_color")]
+    color: String,
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(max = 200, message = "validation.description_too_long"))]
+    description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct UpdateLabel {
-    pub id: i32,
-    pub name: String,
+    #[serde(default, deserialize_with = "crate::trim::collapse_option")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    name: Option<String>,
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(custom = "validate_color")]
+    color: Option<String>,
+    // 空文字を送ると説明を消す
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(max = 200, message = "validation.description_too_long"))]
+    description: Option<String>,
 }
 
 #[derive(Debug, Clone)]
-pub struct LabelRepositoryForDb {
-    pool: PgPool,
+pub struct LabelRepositoryForDb<I = i32> {
+    db: Database,
+    id: PhantomData<I>,
 }
 
 impl LabelRepositoryForDb {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(db: impl Into<Database>) -> Self {
+        Self::with_ids(db)
+    }
+}
+
+impl<I: EntityId> LabelRepositoryForDb<I> {
+    // idの型を選んで作る。テーブルの主キーの型と合わせる
+    pub fn with_ids(db: impl Into<Database>) -> Self {
+        Self {
+            db: db.into(),
+            id: PhantomData,
+        }
+    }
+
+    // 大文字と小文字を区別せずに同じ名前のラベルを探す。exceptのラベルは除く
+    async fn find_by_name(
+        &self,
+        name: &str,
+        except: Option<I>,
+    ) -> anyhow::Result<Option<Label<I>>> {
+        let label = sqlx::query_as::<_, Label<I>>(
+            r#"SELECT * FROM labels WHERE lower(name) = lower($1) AND ($2 IS NULL OR id <> $2)"#,
+        )
+        .bind(name)
+        .bind(except)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(label)
     }
 }
 
 #[async_trait]
-impl LabelRepository for LabelRepositoryForDb {
-    async fn create(&self, name: String) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(r#"SELECT * FROM labels WHERE name = $1"#)
-            .bind(name.clone())
-            .fetch_optional(&self.pool)
-            .await?;
+impl<I: EntityId> LabelRepository<I> for LabelRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>> {
+        if let Some(label) = self.find_by_name(&payload.name, None).await? {
+            return Err(RepositoryError::Duplicate(label.id.key()).into());
+        }
 
-        if let Some(label) = optional_label {
-            return Err(RepositoryError::Duplicate(label.id).into());
+        let inserted = sqlx::query_as::<_, Label<I>>(
+            r#"INSERT INTO labels (name, color, description) VALUES ($1, $2, $3) RETURNING *"#,
+        )
+        .bind(&payload.name)
+        .bind(normalize_color(&payload.color))
+        .bind(
+            payload
+                .description
+                .filter(|description| !description.is_empty()),
+        )
+        .fetch_one(&self.db)
+        .await;
+
+        match inserted {
+            Ok(label) => Ok(label),
+            // 同時に作成された場合
+            Err(e) if is_unique_violation(&e) => {
+                match self.find_by_name(&payload.name, None).await? {
+                    Some(label) => Err(RepositoryError::Duplicate(label.id.key()).into()),
+                    None => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e.into()),
         }
+    }
 
-        let label =
-            sqlx::query_as::<_, Label>(r#"INSERT INTO labels (name) VALUES ($1) RETURNING *"#)
-                .bind(name.clone())
-                .fetch_one(&self.pool)
+    #[instrument(skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<Label<I>>> {
+        let labels =
+            sqlx::query_as::<_, Label<I>>(r#"SELECT * FROM labels ORDER BY labels.id ASC"#)
+                .fetch_all(&self.db)
                 .await?;
 
-        Ok(label)
+        Ok(labels)
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
-        let labels = sqlx::query_as::<_, Label>(r#"SELECT * FROM labels ORDER BY labels.id ASC"#)
-            .fetch_all(&self.pool)
+    #[instrument(skip_all)]
+    async fn search(&self, filter: LabelFilter) -> anyhow::Result<Vec<Label<I>>> {
+        let sql = format!(
+            r#"SELECT * FROM labels WHERE ($1::text IS NULL OR name ILIKE $1 || '%') ORDER BY {}"#,
+            filter.sort.order_by()
+        );
+        let labels = sqlx::query_as::<_, Label<I>>(&sql)
+            .bind(filter.prefix.as_deref().map(escape_like))
+            .fetch_all(&self.db)
             .await?;
 
         Ok(labels)
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+    #[instrument(skip_all)]
+    async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>> {
+        if let Some(name) = &payload.name {
+            if let Some(label) = self.find_by_name(name, Some(id)).await? {
+                return Err(RepositoryError::Duplicate(label.id.key()).into());
+            }
+        }
+
+        let updated = sqlx::query_as::<_, Label<I>>(
+            r#"
+UPDATE labels
+SET name = coalesce($1, name),
+    color = coalesce($2, color),
+    description = CASE WHEN $3::text IS NULL THEN description ELSE nullif($3, '') END
+WHERE id = $4
+RETURNING *
+            "#,
+        )
+        .bind(&payload.name)
+        .bind(payload.color.as_deref().map(normalize_color))
+        .bind(payload.description)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await;
+
+        match (updated, &payload.name) {
+            (Ok(label), _) => Ok(label.ok_or_else(|| id.not_found())?),
+            // 同時に同じ名前に変更された場合
+            (Err(e), Some(name)) if is_unique_violation(&e) => {
+                match self.find_by_name(name, Some(id)).await? {
+                    Some(label) => Err(RepositoryError::Duplicate(label.id.key()).into()),
+                    None => Err(e.into()),
+                }
+            }
+            (Err(e), _) => Err(e.into()),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
         sqlx::query(r#"DELETE FROM labels WHERE id=$1"#)
             .bind(id)
-            .execute(&self.pool)
+            .execute(&self.db)
             .await
             .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+                sqlx::Error::RowNotFound => id.not_found(),
                 _ => RepositoryError::Unexpected(e.to_string()),
             })?;
 
         Ok(())
     }
+
+    #[instrument(skip_all)]
+    async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64> {
+        let mut tx = self.db.begin().await?;
+
+        for label_id in [id, target_id] {
+            let found = sqlx::query(r#"SELECT id FROM labels WHERE id = $1 FOR UPDATE"#)
+                .bind(label_id)
+                .fetch_optional(&mut tx)
+                .await?;
+            if found.is_none() {
+                tx.rollback().await?;
+                return Err(label_id.not_found().into());
+            }
+        }
+
+        // 付け替えるtodoの更新時刻を進める
+        let affected = sqlx::query(
+            r#"
+UPDATE todos SET updated_at = clock_timestamp()
+WHERE id IN (SELECT todo_id FROM todo_labels WHERE label_id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+        // 両方のラベルが付いたtodoは付け替えずに外す
+        sqlx::query(
+            r#"
+DELETE FROM todo_labels source
+USING todo_labels target
+WHERE source.label_id = $1 AND target.label_id = $2 AND target.todo_id = source.todo_id
+            "#,
+        )
+        .bind(id)
+        .bind(target_id)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query(r#"UPDATE todo_labels SET label_id = $2 WHERE label_id = $1"#)
+            .bind(id)
+            .bind(target_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query(r#"DELETE FROM labels WHERE id = $1"#)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(affected)
+    }
+
+    #[instrument(skip_all)]
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>> {
+        let label = sqlx::query_as::<_, Label<I>>(r#"SELECT * FROM labels WHERE uuid = $1"#)
+            .bind(uuid)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(RepositoryError::NotFoundUuid(uuid))?;
+
+        Ok(label)
+    }
+
+    #[instrument(skip_all)]
+    async fn version(&self) -> anyhow::Result<LabelsVersion> {
+        let version = sqlx::query_as::<_, LabelsVersion>(
+            r#"SELECT max(updated_at) AS updated_at, count(*) AS count FROM labels"#,
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(version)
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "database-test")]
 mod test {
     use super::*;
-    use dotenv::dotenv;
-    use sqlx::PgPool;
-    use std::env;
+    use crate::repositories::test_db::TestDatabase;
+    use crate::repositories::todo::{CreateTodo, TodoRepository, TodoRepositoryForDb};
 
     #[tokio::test]
     async fn crud_scenario() {
-        dotenv().ok();
-
-        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-        let pool = PgPool::connect(database_url)
-            .await
-            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
 
         let repository = LabelRepositoryForDb::new(pool);
         let label_text = "test_label";
 
         // create
         let label = repository
-            .create(label_text.to_string())
+            .create(CreateLabel::new(label_text.to_string()))
             .await
             .expect("[create] returned Err");
         assert_eq!(label.name, label_text);
+        assert_eq!(label.color, "#808080");
+        assert_eq!(label.description, None);
+
+        // find by uuid
+        let found = repository
+            .find_by_uuid(label.uuid)
+            .await
+            .expect("[find_by_uuid] returned Err");
+        assert_eq!(found, label);
+
+        // update
+        let label = repository
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: None,
+                    color: Some("#1E90FF".to_string()),
+                    description: Some("shown as a chip".to_string()),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.name, label_text);
+        assert_eq!(label.color, "#1e90ff");
+        assert_eq!(label.description.as_deref(), Some("shown as a chip"));
+        let label = repository
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: None,
+                    color: Some("#abc".to_string()),
+                    description: Some(String::new()),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.color, "#aabbcc");
+        assert_eq!(label.description, None);
+
+        // version
+        let version = repository.version().await.expect("[version] returned Err");
+        assert_eq!(version.count, 1);
+        repository
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: None,
+                    color: Some("#000".to_string()),
+                    description: None,
+                },
+            )
+            .await
+            .unwrap();
+        let updated = repository.version().await.unwrap();
+        assert!(updated.updated_at > version.updated_at);
+        assert_ne!(updated.etag(), version.etag());
 
         // delete
         repository
             .delete(label.id)
             .await
             .expect("[delete] returned Err");
+        let version = repository.version().await.unwrap();
+        assert_eq!(version.count, 0);
+        assert_eq!(version.etag(), "\"0-0\"");
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_treat_names_case_insensitively() {
+        let db = TestDatabase::new().await;
+        let repository = LabelRepositoryForDb::new(db.pool.clone());
+        let work = repository
+            .create(CreateLabel::new("Work".to_string()))
+            .await
+            .unwrap();
+        let home = repository
+            .create(CreateLabel::new("home".to_string()))
+            .await
+            .unwrap();
+
+        let e = repository
+            .create(CreateLabel::new("wORK".to_string()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(key)) if *key == work.id.key()
+        ));
+        let e = repository
+            .update(
+                home.id,
+                UpdateLabel {
+                    name: Some("WORK".to_string()),
+                    color: None,
+                    description: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(key)) if *key == work.id.key()
+        ));
+
+        // 大文字と小文字だけを変える更新はできる
+        let renamed = repository
+            .update(
+                work.id,
+                UpdateLabel {
+                    name: Some("WORK".to_string()),
+                    color: None,
+                    description: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "WORK");
+
+        // 確認を通り抜けても一意インデックスで弾く
+        let inserted = sqlx::query(r#"INSERT INTO labels (name) VALUES ('work')"#)
+            .execute(&db.pool)
+            .await;
+        assert!(is_unique_violation(&inserted.unwrap_err()));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_merge_labels() {
+        let db = TestDatabase::new().await;
+        let repository = LabelRepositoryForDb::new(db.pool.clone());
+        let todos = TodoRepositoryForDb::new(db.pool.clone());
+
+        let source = repository
+            .create(CreateLabel::new("bugs".to_string()))
+            .await
+            .unwrap();
+        let target = repository
+            .create(CreateLabel::new("bug".to_string()))
+            .await
+            .unwrap();
+        let only_source = todos
+            .create(CreateTodo::new("only source".to_string(), vec![source.id]))
+            .await
+            .unwrap();
+        let both = todos
+            .create(CreateTodo::new(
+                "both".to_string(),
+                vec![source.id, target.id],
+            ))
+            .await
+            .unwrap();
+
+        let affected = repository
+            .merge(source.id, target.id)
+            .await
+            .expect("[merge] returned Err");
+        assert_eq!(affected, 2);
+        for todo in [only_source, both] {
+            let labels = todos.find(todo.id).await.unwrap().labels;
+            assert_eq!(labels, vec![target.clone()]);
+        }
+        assert_eq!(repository.all().await.unwrap(), vec![target.clone()]);
+
+        let err = repository.merge(source.id, target.id).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(id)) if *id == source.id
+        ));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_search_labels_by_prefix() {
+        let db = TestDatabase::new().await;
+        let repository = LabelRepositoryForDb::new(db.pool.clone());
+        for name in ["bugfix", "feature", "Bug", "50%off", "50 off"] {
+            repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .unwrap();
+        }
+        let names = |prefix: Option<&str>, sort: LabelSort| {
+            let repository = repository.clone();
+            let filter = LabelFilter {
+                prefix: prefix.map(str::to_string),
+                sort,
+            };
+            async move {
+                repository
+                    .search(filter)
+                    .await
+                    .expect("[search] returned Err")
+                    .into_iter()
+                    .map(|label| label.name)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            names(Some("BU"), LabelSort::Name).await,
+            vec!["Bug", "bugfix"]
+        );
+        assert_eq!(
+            names(Some("bu"), LabelSort::NameDesc).await,
+            vec!["bugfix", "Bug"]
+        );
+        // ワイルドカードは文字として扱う
+        assert_eq!(names(Some("50%"), LabelSort::Id).await, vec!["50%off"]);
+        assert_eq!(
+            names(None, LabelSort::IdDesc).await,
+            vec!["50 off", "50%off", "Bug", "feature", "bugfix"]
+        );
+
+        db.teardown().await;
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils {
-    use super::{Label, LabelRepository};
-    use crate::repositories::RepositoryError;
+    use super::{normalize_color, CreateLabel, Label, LabelRepository, LabelsVersion, UpdateLabel};
+    use crate::repositories::{EntityId, RepositoryError};
     use axum::async_trait;
+    use chrono::{DateTime, Utc};
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicI32, Ordering};
     use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+    use uuid::Uuid;
+
+    impl<I: EntityId> Label<I> {
+        // テストではidから決まるUUIDを振る
+        pub fn new(id: I, name: String) -> Self {
+            Label {
+                id,
+                uuid: id.as_uuid(),
+                name,
+                color: super::default_color(),
+                description: None,
+            }
+        }
+    }
 
-    impl Label {
-        pub fn new(id: i32, name: String) -> Self {
-            Label { id, name }
+    impl CreateLabel {
+        pub fn new(name: String) -> Self {
+            CreateLabel {
+                name,
+                color: super::default_color(),
+                description: None,
+            }
         }
     }
 
-    type LabelData = HashMap<i32, Label>;
+    type LabelData<I> = HashMap<I, Label<I>>;
 
     #[derive(Debug, Clone)]
-    pub struct LabelRepositoryForMemory {
-        store: Arc<RwLock<LabelData>>,
+    pub struct LabelRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<LabelData<I>>>,
+        next_id: Arc<AtomicI32>,
+        updated_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+    }
+
+    impl<I: EntityId> Default for LabelRepositoryForMemory<I> {
+        fn default() -> Self {
+            Self::with_ids()
+        }
     }
 
     impl LabelRepositoryForMemory {
         pub fn new() -> Self {
+            Self::with_ids()
+        }
+
+        // 既存のラベルから始める。新しいラベルにはそれより大きいidを振る
+        pub fn from_labels(labels: Vec<Label>) -> Self {
+            let next_id = labels.iter().map(|label| label.id).max().unwrap_or(0) + 1;
+            LabelRepositoryForMemory {
+                store: Arc::new(RwLock::new(
+                    labels.into_iter().map(|label| (label.id, label)).collect(),
+                )),
+                next_id: Arc::new(AtomicI32::new(next_id)),
+                updated_at: Arc::default(),
+            }
+        }
+    }
+
+    impl<I: EntityId> LabelRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
             LabelRepositoryForMemory {
                 store: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
+                updated_at: Arc::default(),
             }
         }
 
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.next_id.store(1, Ordering::SeqCst);
+            self.touch();
+        }
+
+        // todoのリポジトリがラベルを引くのに使う
+        pub(crate) fn get(&self, id: I) -> Option<Label<I>> {
+            self.read_store_ref().get(&id).cloned()
+        }
+
+        // 作成と更新で版の時刻を進める
+        fn touch(&self) {
+            *self.updated_at.write().unwrap() = Some(Utc::now());
+        }
+
         // HashMapに対してスレッドセーフに書き込む
-        fn write_store_ref(&self) -> RwLockWriteGuard<LabelData> {
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, LabelData<I>> {
             self.store.write().unwrap()
         }
 
         // HashMapからスレッドセーフに読み込む
-        fn read_store_ref(&self) -> RwLockReadGuard<LabelData> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, LabelData<I>> {
             self.store.read().unwrap()
         }
     }
 
     #[async_trait]
-    impl LabelRepository for LabelRepositoryForMemory {
-        async fn create(&self, name: String) -> anyhow::Result<Label> {
+    impl<I: EntityId> LabelRepository<I> for LabelRepositoryForMemory<I> {
+        async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label<I>> {
             let mut store = self.write_store_ref();
-            if let Some((_key, label)) = store.iter().find(|(_key, label)| label.name == name) {
-                return Ok(label.clone());
+            if let Some(label) = store
+                .values()
+                .find(|label| label.name.to_lowercase() == payload.name.to_lowercase())
+            {
+                return Err(RepositoryError::Duplicate(label.id.key()).into());
             };
 
-            let id = (store.len() + 1) as i32;
-            let label = Label::new(id, name.clone());
+            let id = I::assign(self.next_id.fetch_add(1, Ordering::SeqCst));
+            let label = Label {
+                color: normalize_color(&payload.color),
+                description: payload
+                    .description
+                    .filter(|description| !description.is_empty()),
+                ..Label::new(id, payload.name)
+            };
             store.insert(id, label.clone());
+            self.touch();
             Ok(label)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
+        async fn all(&self) -> anyhow::Result<Vec<Label<I>>> {
             let store = self.read_store_ref();
-            let labels = Vec::from_iter(store.values().cloned());
+            let mut labels = Vec::from_iter(store.values().cloned());
+            labels.sort_by_key(|label| label.id);
             Ok(labels)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn update(&self, id: I, payload: UpdateLabel) -> anyhow::Result<Label<I>> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            if let Some(name) = &payload.name {
+                if let Some(label) = store.values().find(|label| {
+                    label.name.to_lowercase() == name.to_lowercase() && label.id != id
+                }) {
+                    return Err(RepositoryError::Duplicate(label.id.key()).into());
+                }
+            }
+            let label = store.get_mut(&id).ok_or_else(|| id.not_found())?;
+            if let Some(name) = payload.name {
+                label.name = name;
+            }
+            if let Some(color) = payload.color {
+                label.color = normalize_color(&color);
+            }
+            if let Some(description) = payload.description {
+                label.description = Some(description).filter(|description| !description.is_empty());
+            }
+            self.touch();
+            Ok(label.clone())
+        }
+
+        async fn delete(&self, id: I) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            store.remove(&id).ok_or_else(|| id.not_found())?;
             Ok(())
         }
+
+        // 記憶領域のラベルはtodoとの関連を持たないので、付け替えるtodoはない
+        async fn merge(&self, id: I, target_id: I) -> anyhow::Result<u64> {
+            let mut store = self.write_store_ref();
+            if !store.contains_key(&target_id) {
+                return Err(target_id.not_found().into());
+            }
+            store.remove(&id).ok_or_else(|| id.not_found())?;
+            Ok(0)
+        }
+
+        async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<Label<I>> {
+            let store = self.read_store_ref();
+            let label = store
+                .values()
+                .find(|label| label.uuid == uuid)
+                .cloned()
+                .ok_or(RepositoryError::NotFoundUuid(uuid))?;
+            Ok(label)
+        }
+
+        async fn version(&self) -> anyhow::Result<LabelsVersion> {
+            let count = self.read_store_ref().len() as i64;
+            Ok(LabelsVersion {
+                updated_at: *self.updated_at.read().unwrap(),
+                count,
+            })
+        }
     }
 
+    #[cfg(test)]
     mod test {
         use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
-        use crate::repositories::labels::{Label, LabelRepository};
+        use crate::repositories::labels::{CreateLabel, Label, LabelRepository};
+        use crate::repositories::{EntityId, RepositoryError};
 
+        #[tokio::test]
         async fn label_curd_scenario() {
             let text = "label text".to_string();
             let id = 1;
@@ -189,7 +854,7 @@ pub mod test_utils {
             // create
             let repository = LabelRepositoryForMemory::new();
             let label = repository
-                .create(text.clone())
+                .create(CreateLabel::new(text.clone()))
                 .await
                 .expect("failed label create");
             assert_eq!(expected, label);
@@ -202,5 +867,45 @@ pub mod test_utils {
             let res = repository.delete(id).await;
             assert!(res.is_ok())
         }
+
+        #[tokio::test]
+        async fn should_reject_names_differing_only_in_case() {
+            let repository = LabelRepositoryForMemory::new();
+            let work = repository
+                .create(CreateLabel::new("Work".to_string()))
+                .await
+                .unwrap();
+            let e = repository
+                .create(CreateLabel::new("work".to_string()))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Duplicate(key)) if *key == work.id.key()
+            ));
+            assert_eq!(repository.all().await.unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_not_reuse_deleted_ids() {
+            let repository = LabelRepositoryForMemory::new();
+            let first = repository
+                .create(CreateLabel::new("first".to_string()))
+                .await
+                .unwrap();
+            let second = repository
+                .create(CreateLabel::new("second".to_string()))
+                .await
+                .unwrap();
+            repository.delete(first.id).await.unwrap();
+
+            let third = repository
+                .create(CreateLabel::new("third".to_string()))
+                .await
+                .unwrap();
+            assert_ne!(third.id, first.id);
+            assert_ne!(third.id, second.id);
+            assert_eq!(repository.all().await.unwrap().len(), 2);
+        }
     }
 }
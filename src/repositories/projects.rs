@@ -0,0 +1,245 @@
+use crate::repositories::RepositoryError;
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+
+#[async_trait]
+pub trait ProjectRepository: Send + Sync + 'static {
+    async fn create(&self, name: String) -> anyhow::Result<Project>;
+    async fn find(&self, id: i32) -> anyhow::Result<Project>;
+    async fn all(&self) -> anyhow::Result<Vec<Project>>;
+    async fn update(&self, id: i32, name: String) -> anyhow::Result<Project>;
+    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectRepositoryForDb {
+    pool: PgPool,
+}
+
+impl ProjectRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ProjectRepository for ProjectRepositoryForDb {
+    #[instrument(skip_all)]
+    async fn create(&self, name: String) -> anyhow::Result<Project> {
+        let project =
+            sqlx::query_as::<_, Project>(r#"INSERT INTO projects (name) VALUES ($1) RETURNING *"#)
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(project)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, id: i32) -> anyhow::Result<Project> {
+        let project = sqlx::query_as::<_, Project>(r#"SELECT * FROM projects WHERE id = $1"#)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(project)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<Project>> {
+        let projects =
+            sqlx::query_as::<_, Project>(r#"SELECT * FROM projects ORDER BY projects.id ASC"#)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(projects)
+    }
+
+    #[instrument(skip_all)]
+    async fn update(&self, id: i32, name: String) -> anyhow::Result<Project> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"UPDATE projects SET name = $1 WHERE id = $2 RETURNING *"#,
+        )
+        .bind(name)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(project)
+    }
+
+    #[instrument(skip_all)]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"DELETE FROM projects WHERE id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
+
+        let repository = ProjectRepositoryForDb::new(pool);
+
+        // create
+        let project = repository
+            .create("[crud_scenario] project".to_string())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(project.name, "[crud_scenario] project");
+
+        // find
+        let found = repository
+            .find(project.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(project, found);
+
+        // all
+        let projects = repository.all().await.expect("[all] returned Err");
+        assert!(projects.contains(&project));
+
+        // update
+        let updated = repository
+            .update(project.id, "[crud_scenario] renamed".to_string())
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(updated.name, "[crud_scenario] renamed");
+
+        // delete
+        repository
+            .delete(project.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repository.find(project.id).await.is_err());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone)]
+    pub struct ProjectRepositoryForMemory {
+        store: Arc<RwLock<BTreeMap<i32, Project>>>,
+        next_id: Arc<AtomicI32>,
+    }
+
+    impl Default for ProjectRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl ProjectRepositoryForMemory {
+        pub fn new() -> Self {
+            Self {
+                store: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
+            }
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.next_id.store(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl ProjectRepository for ProjectRepositoryForMemory {
+        async fn create(&self, name: String) -> anyhow::Result<Project> {
+            let mut store = self.store.write().unwrap();
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            let project = Project { id, name };
+            store.insert(id, project.clone());
+            Ok(project)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<Project> {
+            let store = self.store.read().unwrap();
+            let project = store
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(project)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<Project>> {
+            Ok(self.store.read().unwrap().values().cloned().collect())
+        }
+
+        async fn update(&self, id: i32, name: String) -> anyhow::Result<Project> {
+            let mut store = self.store.write().unwrap();
+            let project = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            project.name = name;
+            Ok(project.clone())
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn project_crud_scenario() {
+            let repository = ProjectRepositoryForMemory::new();
+            let project = repository.create("inbox".to_string()).await.unwrap();
+            assert_eq!(
+                Project {
+                    id: 1,
+                    name: "inbox".to_string()
+                },
+                project
+            );
+
+            let project = repository
+                .update(project.id, "backlog".to_string())
+                .await
+                .unwrap();
+            assert_eq!(vec![project.clone()], repository.all().await.unwrap());
+
+            repository.delete(project.id).await.unwrap();
+            assert!(repository.find(project.id).await.is_err());
+
+            // 最後のプロジェクトを削除してもidは再利用しない
+            let project = repository.create("next".to_string()).await.unwrap();
+            assert_eq!(2, project.id);
+        }
+    }
+}
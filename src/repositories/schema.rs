@@ -0,0 +1,195 @@
+use crate::repositories::IdType;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::fmt;
+use thiserror::Error;
+
+// 起動時に確かめるテーブルと列、それを作ったマイグレーション
+// 列を足すマイグレーションを書いたら、リポジトリが使う列をここにも足す
+const EXPECTED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("20240216143242_init", "todos", "text"),
+    ("20240221143957_label", "labels", "name"),
+    ("20240221143957_label", "todo_labels", "label_id"),
+    ("20240301120000_audit_log", "audit_logs", "actor"),
+    ("20240305120000_todo_revisions", "todo_revisions", "todo_id"),
+    ("20240310120000_todo_reminders", "todos", "remind_at"),
+    ("20240315120000_users", "users", "name"),
+    ("20240315120000_users", "todos", "assignee_id"),
+    ("20240320120000_projects", "projects", "name"),
+    ("20240320120000_projects", "todos", "project_id"),
+    ("20240325120000_todo_status", "todos", "status"),
+    ("20240325120000_todo_status", "todo_revisions", "status"),
+    ("20240330120000_uuid_keys", "todos", "uuid"),
+    ("20240330120000_uuid_keys", "labels", "uuid"),
+    ("20240405120000_todo_updated_at", "todos", "updated_at"),
+    ("20240410120000_todo_dedupe", "todos", "deduplicated"),
+    ("20240415120000_todo_tags", "todos", "tags"),
+    ("20240420120000_label_color", "labels", "color"),
+    ("20240420120000_label_color", "labels", "description"),
+    ("20240420130000_todo_parent", "todos", "parent_id"),
+    (
+        "20240425120000_todo_dependencies",
+        "todo_dependencies",
+        "blocker_id",
+    ),
+    ("20240430120000_views", "views", "filter"),
+    ("20240505120000_todo_completed_at", "todos", "created_at"),
+    ("20240505120000_todo_completed_at", "todos", "completed_at"),
+    ("20240510120000_todo_events", "todo_events", "events"),
+    ("20240515120000_templates", "templates", "text"),
+    ("20240515120000_templates", "template_labels", "template_id"),
+    ("20240520120000_todo_shares", "todos", "owner_id"),
+    ("20240520120000_todo_shares", "todo_shares", "permission"),
+    ("20240525120000_share_links", "share_links", "token"),
+    ("20240530120000_label_updated_at", "labels", "updated_at"),
+    ("20240605120000_api_usage", "api_usage", "requests"),
+    ("20240610120000_todo_changes", "todo_changes", "xact_id"),
+    ("20240615120000_todo_appearance", "todos", "icon"),
+    ("20240615120000_todo_appearance", "todos", "color"),
+    ("20240620120000_todo_pinned", "todos", "pinned"),
+    ("20240630120000_todo_snoozed", "todos", "snoozed_until"),
+    ("20240705120000_todo_estimates", "todos", "estimate_minutes"),
+    ("20240710120000_todo_due_at", "todos", "due_at"),
+    (
+        "20240715120000_user_preferences",
+        "user_preferences",
+        "preferences",
+    ),
+    (
+        "20240720120000_github_links",
+        "github_links",
+        "issue_number",
+    ),
+    ("20240725120000_todo_description", "todos", "description"),
+    (
+        "20240801120000_github_deliveries",
+        "github_deliveries",
+        "delivery_id",
+    ),
+];
+
+// 足りない列と、それを作るマイグレーション
+#[derive(Debug, Error, PartialEq, Eq)]
+pub struct OutdatedSchema {
+    pub migrations: Vec<&'static str>,
+    pub columns: Vec<String>,
+}
+
+impl fmt::Display for OutdatedSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "missing migrations [{}] (columns [{}] not found)",
+            self.migrations.join(", "),
+            self.columns.join(", ")
+        )
+    }
+}
+
+// 最初のリクエストで列がないと失敗する前に、起動時にスキーマを確かめる
+pub async fn verify_schema(pool: &PgPool) -> anyhow::Result<()> {
+    let columns: Vec<(String, String)> = sqlx::query_as(
+        r#"select table_name::text, column_name::text from information_schema.columns where table_schema = current_schema()"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    missing(&columns.into_iter().collect()).map_or(Ok(()), |outdated| Err(outdated.into()))
+}
+
+// マイグレーションで決めたidの型と、起動時に指定した型が違う
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("todos.id is [{actual}] but TODO_ID_TYPE expects [{expected}]")]
+pub struct IdTypeMismatch {
+    pub expected: &'static str,
+    pub actual: String,
+}
+
+// idの型はマイグレーションの適用後に変えられないので、起動時に指定と合っているか確かめる
+pub async fn verify_id_type(pool: &PgPool, id_type: IdType) -> anyhow::Result<()> {
+    let actual: String = sqlx::query_scalar(
+        r#"select data_type::text from information_schema.columns where table_schema = current_schema() and table_name = 'todos' and column_name = 'id'"#,
+    )
+    .fetch_one(pool)
+    .await?;
+    if actual != id_type.column_type() {
+        return Err(IdTypeMismatch {
+            expected: id_type.column_type(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn missing(columns: &HashSet<(String, String)>) -> Option<OutdatedSchema> {
+    let mut outdated = OutdatedSchema {
+        migrations: vec![],
+        columns: vec![],
+    };
+    for (migration, table, column) in EXPECTED_COLUMNS {
+        if columns.contains(&(table.to_string(), column.to_string())) {
+            continue;
+        }
+        if !outdated.migrations.contains(migration) {
+            outdated.migrations.push(migration);
+        }
+        outdated.columns.push(format!("{}.{}", table, column));
+    }
+    (!outdated.columns.is_empty()).then_some(outdated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_list_migrations_of_missing_columns() {
+        let mut columns: HashSet<(String, String)> = EXPECTED_COLUMNS
+            .iter()
+            .map(|(_, table, column)| (table.to_string(), column.to_string()))
+            .collect();
+        assert_eq!(missing(&columns), None);
+
+        columns.remove(&("labels".to_string(), "color".to_string()));
+        columns.remove(&("labels".to_string(), "description".to_string()));
+        columns.remove(&("todo_changes".to_string(), "xact_id".to_string()));
+        let outdated = missing(&columns).expect("missing columns are not found");
+        assert_eq!(
+            outdated.migrations,
+            vec!["20240420120000_label_color", "20240610120000_todo_changes"]
+        );
+        assert_eq!(
+            outdated.to_string(),
+            "missing migrations [20240420120000_label_color, 20240610120000_todo_changes] (columns [labels.color, labels.description, todo_changes.xact_id] not found)"
+        );
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_pass_after_all_migrations() {
+        let db = crate::repositories::test_db::TestDatabase::new().await;
+        verify_schema(&db.pool)
+            .await
+            .expect("[verify_schema] returned Err");
+
+        db.teardown().await;
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_reject_other_id_type() {
+        let db = crate::repositories::test_db::TestDatabase::with_id_type(IdType::Uuid).await;
+        verify_id_type(&db.pool, IdType::Uuid)
+            .await
+            .expect("[verify_id_type] returned Err");
+        let error = verify_id_type(&db.pool, IdType::Serial)
+            .await
+            .expect_err("[verify_id_type] returned Ok");
+        assert_eq!(
+            error.to_string(),
+            "todos.id is [uuid] but TODO_ID_TYPE expects [integer]"
+        );
+
+        db.teardown().await;
+    }
+}
@@ -0,0 +1,257 @@
+use crate::repositories::{EntityId, RepositoryError};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::marker::PhantomData;
+use tracing::instrument;
+
+// トークンの長さ。英数字62種類なので約190ビットになり推測できない
+const TOKEN_LENGTH: usize = 32;
+
+#[async_trait]
+pub trait ShareLinkRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(
+        &self,
+        todo_id: I,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<ShareLink<I>>;
+    async fn find(&self, token: &str) -> anyhow::Result<ShareLink<I>>;
+    async fn all(&self, todo_id: I) -> anyhow::Result<Vec<ShareLink<I>>>;
+    // 取り消したリンクは削除し、以降は存在しないものとして扱う
+    async fn revoke(&self, todo_id: I, token: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct ShareLink<I = i32> {
+    pub token: String,
+    pub todo_id: I,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl<I> ShareLink<I> {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct ShareLinkRepositoryForDb<I = i32> {
+    pool: PgPool,
+    id: PhantomData<I>,
+}
+
+impl ShareLinkRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_ids(pool)
+    }
+}
+
+impl<I: EntityId> ShareLinkRepositoryForDb<I> {
+    pub fn with_ids(pool: PgPool) -> Self {
+        Self {
+            pool,
+            id: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> ShareLinkRepository<I> for ShareLinkRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(
+        &self,
+        todo_id: I,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<ShareLink<I>> {
+        let link = sqlx::query_as::<_, ShareLink<I>>(
+            r#"INSERT INTO share_links (token, todo_id, expires_at) VALUES ($1, $2, $3) RETURNING *"#,
+        )
+        .bind(generate_token())
+        .bind(todo_id)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, token: &str) -> anyhow::Result<ShareLink<I>> {
+        let link =
+            sqlx::query_as::<_, ShareLink<I>>(r#"SELECT * FROM share_links WHERE token = $1"#)
+                .bind(token)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or_else(|| RepositoryError::NotFoundToken(token.to_string()))?;
+
+        Ok(link)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self, todo_id: I) -> anyhow::Result<Vec<ShareLink<I>>> {
+        let links = sqlx::query_as::<_, ShareLink<I>>(
+            r#"SELECT * FROM share_links WHERE todo_id = $1 ORDER BY created_at, token"#,
+        )
+        .bind(todo_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(links)
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke(&self, todo_id: I, token: &str) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"DELETE FROM share_links WHERE todo_id = $1 AND token = $2"#)
+            .bind(todo_id)
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFoundToken(token.to_string()).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let repository = ShareLinkRepositoryForDb::new(db.pool.clone());
+
+        // create
+        let link = repository
+            .create(1, None)
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(link.token.len(), TOKEN_LENGTH);
+        assert_eq!(link.todo_id, 1);
+        let other = repository
+            .create(1, Some(Utc::now()))
+            .await
+            .expect("[create] returned Err");
+        assert_ne!(link.token, other.token);
+        assert!(other.is_expired(Utc::now()));
+
+        // find
+        let found = repository
+            .find(&link.token)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(link, found);
+
+        // all
+        let links = repository.all(1).await.expect("[all] returned Err");
+        assert_eq!(links, vec![link.clone(), other]);
+        assert!(repository.all(2).await.unwrap().is_empty());
+
+        // revoke
+        assert!(repository.revoke(2, &link.token).await.is_err());
+        repository
+            .revoke(1, &link.token)
+            .await
+            .expect("[revoke] returned Err");
+        assert!(repository.find(&link.token).await.is_err());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct ShareLinkRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<BTreeMap<String, ShareLink<I>>>>,
+    }
+
+    impl ShareLinkRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    impl<I: EntityId> ShareLinkRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
+            Self {
+                store: Arc::default(),
+            }
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> ShareLinkRepository<I> for ShareLinkRepositoryForMemory<I> {
+        async fn create(
+            &self,
+            todo_id: I,
+            expires_at: Option<DateTime<Utc>>,
+        ) -> anyhow::Result<ShareLink<I>> {
+            let link = ShareLink {
+                token: generate_token(),
+                todo_id,
+                created_at: Utc::now(),
+                expires_at,
+            };
+            let mut store = self.store.write().unwrap();
+            store.insert(link.token.clone(), link.clone());
+            Ok(link)
+        }
+
+        async fn find(&self, token: &str) -> anyhow::Result<ShareLink<I>> {
+            let store = self.store.read().unwrap();
+            let link = store
+                .get(token)
+                .cloned()
+                .ok_or_else(|| RepositoryError::NotFoundToken(token.to_string()))?;
+            Ok(link)
+        }
+
+        async fn all(&self, todo_id: I) -> anyhow::Result<Vec<ShareLink<I>>> {
+            let store = self.store.read().unwrap();
+            let mut links: Vec<ShareLink<I>> = store
+                .values()
+                .filter(|link| link.todo_id == todo_id)
+                .cloned()
+                .collect();
+            links.sort_by(|a, b| (a.created_at, &a.token).cmp(&(b.created_at, &b.token)));
+            Ok(links)
+        }
+
+        async fn revoke(&self, todo_id: I, token: &str) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            match store.get(token) {
+                Some(link) if link.todo_id == todo_id => {
+                    store.remove(token);
+                    Ok(())
+                }
+                _ => Err(RepositoryError::NotFoundToken(token.to_string()).into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use crate::repositories::todo::CreateTodo;
+use crate::repositories::{EntityId, RepositoryError};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use tracing::instrument;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+#[async_trait]
+pub trait TemplateRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(&self, payload: TemplatePayload<I>) -> anyhow::Result<Template<I>>;
+    async fn find(&self, id: i32) -> anyhow::Result<Template<I>>;
+    async fn all(&self) -> anyhow::Result<Vec<Template<I>>>;
+    async fn update(&self, id: i32, payload: TemplatePayload<I>) -> anyhow::Result<Template<I>>;
+    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+}
+
+// 繰り返し作るtodoのひな形
+// 本文の {{name}} は作成時に置き換える
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct Template<I = i32> {
+    pub id: i32,
+    pub name: String,
+    pub text: String,
+    pub labels: Vec<I>,
+    pub tags: Vec<String>,
+}
+
+impl<I: EntityId> Template<I> {
+    // 置き換えたあとの本文でtodoの作成内容を作る
+    // 置き換えられなかったプレースホルダーが残る場合はエラーにする
+    pub fn instantiate(
+        &self,
+        substitutions: &BTreeMap<String, String>,
+    ) -> Result<CreateTodo<I>, ValidationErrors> {
+        let mut text = self.text.clone();
+        for (name, value) in substitutions {
+            text = text.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        if has_placeholder(&text) {
+            let mut error = ValidationError::new("unresolved_placeholder");
+            error.message = Some("validation.unresolved_placeholder".into());
+            let mut errors = ValidationErrors::new();
+            errors.add("substitutions", error);
+            return Err(errors);
+        }
+        let todo = CreateTodo::from_template(text, self.labels.clone(), self.tags.clone());
+        todo.validate()?;
+        Ok(todo)
+    }
+}
+
+fn has_placeholder(text: &str) -> bool {
+    text.split("{{")
+        .skip(1)
+        .any(|rest| rest.find("}}").is_some_and(|end| end > 0))
+}
+
+// 作成・更新どちらも全体を置き換える
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct TemplatePayload<I = i32> {
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
+    pub name: String,
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 1000, message = "validation.too_long"))]
+    pub text: String,
+    #[serde(default)]
+    pub labels: Vec<I>,
+    #[serde(default)]
+    #[validate(
+        length(max = 10, message = "validation.too_many_tags"),
+        custom = "crate::repositories::todo::validate_tags"
+    )]
+    pub tags: Vec<String>,
+}
+
+// ラベルはtodoと同じく中間テーブルに持ち、削除されたラベルは外れる
+const SELECT_TEMPLATES: &str = r#"
+select templates.*,
+       coalesce(array_agg(tl.label_id order by tl.label_id) filter (where tl.label_id is not null), '{}') as labels
+from templates
+left outer join template_labels tl on templates.id = tl.template_id
+"#;
+
+#[derive(Debug, Clone)]
+pub struct TemplateRepositoryForDb<I = i32> {
+    pool: PgPool,
+    id: PhantomData<I>,
+}
+
+impl TemplateRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_ids(pool)
+    }
+}
+
+impl<I: EntityId> TemplateRepositoryForDb<I> {
+    pub fn with_ids(pool: PgPool) -> Self {
+        Self {
+            pool,
+            id: PhantomData,
+        }
+    }
+}
+
+async fn insert_labels<I: EntityId>(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    labels: &[I],
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"insert into template_labels (template_id, label_id) select $1, id from unnest($2) as t(id) on conflict do nothing"#,
+    )
+    .bind(id)
+    .bind(labels)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl<I: EntityId> TemplateRepository<I> for TemplateRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(&self, payload: TemplatePayload<I>) -> anyhow::Result<Template<I>> {
+        let mut tx = self.pool.begin().await?;
+        let (id,) = sqlx::query_as::<_, (i32,)>(
+            r#"insert into templates (name, text, tags) values ($1, $2, $3) returning id"#,
+        )
+        .bind(payload.name)
+        .bind(payload.text)
+        .bind(payload.tags)
+        .fetch_one(&mut tx)
+        .await?;
+        if let Err(e) = insert_labels(&mut tx, id, &payload.labels).await {
+            tx.rollback().await?;
+            return Err(e);
+        }
+        tx.commit().await?;
+
+        self.find(id).await
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, id: i32) -> anyhow::Result<Template<I>> {
+        let template = sqlx::query_as::<_, Template<I>>(&format!(
+            "{} where templates.id = $1 group by templates.id",
+            SELECT_TEMPLATES
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(template)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<Template<I>>> {
+        let templates = sqlx::query_as::<_, Template<I>>(&format!(
+            "{} group by templates.id order by templates.id asc",
+            SELECT_TEMPLATES
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(templates)
+    }
+
+    #[instrument(skip_all)]
+    async fn update(&self, id: i32, payload: TemplatePayload<I>) -> anyhow::Result<Template<I>> {
+        let mut tx = self.pool.begin().await?;
+        let result =
+            sqlx::query(r#"update templates set name = $1, text = $2, tags = $3 where id = $4"#)
+                .bind(payload.name)
+                .bind(payload.text)
+                .bind(payload.tags)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+        if result.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Err(RepositoryError::NotFound(id).into());
+        }
+        sqlx::query(r#"delete from template_labels where template_id = $1"#)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        if let Err(e) = insert_labels(&mut tx, id, &payload.labels).await {
+            tx.rollback().await?;
+            return Err(e);
+        }
+        tx.commit().await?;
+
+        self.find(id).await
+    }
+
+    #[instrument(skip_all)]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"delete from templates where id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::labels::{CreateLabel, LabelRepository, LabelRepositoryForDb};
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let labels = LabelRepositoryForDb::new(db.pool.clone());
+        let label = labels
+            .create(CreateLabel::new("weekly".to_string()))
+            .await
+            .unwrap();
+        let repository = TemplateRepositoryForDb::new(db.pool.clone());
+        let payload = TemplatePayload {
+            name: "weekly review".to_string(),
+            text: "Review week {{week}}".to_string(),
+            labels: vec![label.id],
+            tags: vec!["review".to_string()],
+        };
+
+        // create
+        let template = repository
+            .create(payload.clone())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(template.text, "Review week {{week}}");
+        assert_eq!(template.labels, vec![label.id]);
+        assert_eq!(template.tags, vec!["review".to_string()]);
+
+        // find
+        let found = repository
+            .find(template.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(template, found);
+
+        // all
+        let templates = repository.all().await.expect("[all] returned Err");
+        assert_eq!(templates, vec![template.clone()]);
+
+        // update
+        let updated = repository
+            .update(
+                template.id,
+                TemplatePayload {
+                    labels: vec![],
+                    ..payload.clone()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(updated.labels, Vec::<i32>::new());
+
+        // ラベルを削除すると外れる
+        repository.update(template.id, payload).await.unwrap();
+        labels.delete(label.id).await.unwrap();
+        assert!(repository
+            .find(template.id)
+            .await
+            .unwrap()
+            .labels
+            .is_empty());
+
+        // delete
+        repository
+            .delete(template.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repository.find(template.id).await.is_err());
+        assert!(repository.delete(template.id).await.is_err());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone)]
+    pub struct TemplateRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<BTreeMap<i32, Template<I>>>>,
+        next_id: Arc<AtomicI32>,
+    }
+
+    impl<I: EntityId> Default for TemplateRepositoryForMemory<I> {
+        fn default() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    impl TemplateRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    impl<I: EntityId> TemplateRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
+            Self {
+                store: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
+            }
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.next_id.store(1, Ordering::SeqCst);
+        }
+    }
+
+    // データベースと同じくラベルはid順で重複なし
+    fn template<I: EntityId>(id: i32, payload: TemplatePayload<I>) -> Template<I> {
+        let mut labels = payload.labels;
+        labels.sort();
+        labels.dedup();
+        Template {
+            id,
+            name: payload.name,
+            text: payload.text,
+            labels,
+            tags: payload.tags,
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> TemplateRepository<I> for TemplateRepositoryForMemory<I> {
+        async fn create(&self, payload: TemplatePayload<I>) -> anyhow::Result<Template<I>> {
+            let mut store = self.store.write().unwrap();
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            let template = template(id, payload);
+            store.insert(id, template.clone());
+            Ok(template)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<Template<I>> {
+            let store = self.store.read().unwrap();
+            let template = store
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(template)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<Template<I>>> {
+            Ok(self.store.read().unwrap().values().cloned().collect())
+        }
+
+        async fn update(
+            &self,
+            id: i32,
+            payload: TemplatePayload<I>,
+        ) -> anyhow::Result<Template<I>> {
+            let mut store = self.store.write().unwrap();
+            let stored = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            *stored = template(id, payload);
+            Ok(stored.clone())
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn weekly() -> Template {
+            Template {
+                id: 1,
+                name: "weekly".to_string(),
+                text: "Review {{team}} week {{week}}".to_string(),
+                labels: vec![1],
+                tags: vec!["review".to_string()],
+            }
+        }
+
+        #[test]
+        fn should_substitute_placeholders() {
+            let substitutions = BTreeMap::from([
+                ("team".to_string(), "api".to_string()),
+                ("week".to_string(), "42".to_string()),
+            ]);
+            let todo = weekly().instantiate(&substitutions).unwrap();
+            assert_eq!(todo.text(), "Review api week 42");
+
+            let errors = weekly()
+                .instantiate(&BTreeMap::from([("team".to_string(), "api".to_string())]))
+                .unwrap_err();
+            assert!(errors.field_errors().contains_key("substitutions"));
+
+            // 置き換えた結果が長すぎる場合は本文のエラーにする
+            let errors = weekly()
+                .instantiate(&BTreeMap::from([
+                    ("team".to_string(), "a".repeat(100)),
+                    ("week".to_string(), "42".to_string()),
+                ]))
+                .unwrap_err();
+            assert!(errors.field_errors().contains_key("text"));
+        }
+
+        #[test]
+        fn should_ignore_empty_braces() {
+            assert!(has_placeholder("{{week}}"));
+            assert!(!has_placeholder("{{}} and {single}"));
+            assert!(!has_placeholder("{{unterminated"));
+        }
+    }
+}
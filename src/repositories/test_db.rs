@@ -0,0 +1,132 @@
+use crate::cli::migrate;
+use crate::repositories::database::cancellable;
+use crate::repositories::IdType;
+use dotenv::dotenv;
+use futures_util::FutureExt;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, Executor, PgPool};
+use std::env;
+use std::str::FromStr;
+
+// テストごとに専用のスキーマを作り、マイグレーションを適用したデータベース
+// 他のテストとデータを共有しないので並列に実行できる
+// テストの最後に teardown を呼んで接続を閉じ、スキーマごと削除する
+pub struct TestDatabase {
+    pub pool: PgPool,
+    options: PgConnectOptions,
+    schema: String,
+    cleaned: bool,
+}
+
+// 後片付けの接続。ロックを待ち続けず、削除できなければ失敗させる
+fn cleanup_options(options: &PgConnectOptions) -> PgConnectOptions {
+    options
+        .clone()
+        .options([("lock_timeout", "5s"), ("statement_timeout", "30s")])
+}
+
+async fn drop_schema(options: PgConnectOptions, schema: &str) -> Result<(), sqlx::Error> {
+    let mut conn = options.connect().await?;
+    conn.execute(format!("DROP SCHEMA {} CASCADE", schema).as_str())
+        .await?;
+    conn.close().await
+}
+
+impl TestDatabase {
+    pub async fn new() -> Self {
+        Self::with_id_type(IdType::Serial).await
+    }
+
+    // todoとラベルの主キーの型を指定してマイグレーションを適用する
+    pub async fn with_id_type(id_type: IdType) -> Self {
+        dotenv().ok();
+
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap_or_else(|_| panic!("invalid database url [{}]", database_url));
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+        let schema = format!("test_{}", suffix.to_lowercase());
+
+        let mut conn = options
+            .connect()
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        conn.execute(format!("CREATE SCHEMA {}", schema).as_str())
+            .await
+            .expect("fail create test schema");
+        conn.close().await.ok();
+
+        let schema_options = options.clone().options([("search_path", schema.as_str())]);
+        let pool = cancellable(PgPoolOptions::new(), &schema_options)
+            .max_connections(5)
+            .connect_with(schema_options)
+            .await
+            .expect("fail connect test schema");
+        migrate(&pool, id_type).await.expect("fail run migrations");
+
+        Self {
+            pool,
+            options,
+            schema,
+            cleaned: false,
+        }
+    }
+
+    // プールの接続をすべて閉じてからスキーマを削除する
+    // 閉じないとプールの接続が残り続け、削除もその接続のロックを待って止まる
+    pub async fn teardown(mut self) {
+        self.cleaned = true;
+        self.pool.close().await;
+        drop_schema(cleanup_options(&self.options), &self.schema)
+            .await
+            .expect("fail drop test schema");
+    }
+
+    // テーブルを空にし、連番も戻す。同じスキーマを複数のケースで使い回す場合に呼ぶ
+    pub async fn truncate(&self) {
+        let tables: Vec<(String,)> = sqlx::query_as(
+            "SELECT tablename::text FROM pg_tables WHERE schemaname = $1 AND tablename <> '_sqlx_migrations'",
+        )
+        .bind(&self.schema)
+        .fetch_all(&self.pool)
+        .await
+        .expect("fail list test tables");
+        if tables.is_empty() {
+            return;
+        }
+        let tables: Vec<String> = tables.into_iter().map(|(table,)| table).collect();
+        self.pool
+            .execute(format!("TRUNCATE {} RESTART IDENTITY CASCADE", tables.join(", ")).as_str())
+            .await
+            .expect("fail truncate test tables");
+    }
+}
+
+impl Drop for TestDatabase {
+    // teardown の前にテストが失敗した場合の後片付け
+    // ここでは待てないので、プールを閉じた印だけ付けて使っていない接続を手放し、
+    // 別スレッドのランタイムでスキーマを削除する
+    fn drop(&mut self) {
+        if self.cleaned {
+            return;
+        }
+        self.pool.close().now_or_never();
+        let options = cleanup_options(&self.options);
+        let schema = self.schema.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("fail build runtime");
+            runtime.block_on(drop_schema(options, &schema))
+        })
+        .join()
+        .ok();
+    }
+}
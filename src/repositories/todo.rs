@@ -1,530 +1,4733 @@
 use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use futures_util::TryStreamExt;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryAs;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgExecutor, Postgres};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::future::Future;
+use std::marker::PhantomData;
 
-use crate::repositories::labels::Label;
-use crate::repositories::RepositoryError;
-use validator::Validate;
+use crate::repositories::database::{Database, DatabaseTransaction};
+use crate::repositories::labels::{normalize_color, validate_color, Label};
+use crate::repositories::users::User;
+use crate::repositories::{
+    escape_like, is_unique_violation, EntityId, Key, Replica, RepositoryError,
+};
+use tokio::sync::mpsc;
+use tracing::instrument;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
 
-// TodoRepositoryトレイトを実装する型が、Clone、Send、Syncトレイトを実装していること
-// Cloneトレイとは型の値を複製する機能を提供することを示す
+pub mod event_sourced;
+
+// TodoRepositoryトレイトを実装する型が、Send、Syncトレイトを実装していること
+// Cloneを要求しないので、Arc<dyn TodoRepository>のようにトレイトオブジェクトとして扱える
 // Sendトレイトは、型の値がスレッド間で安全に送信できることを示す
 // Syncトレイトは、型の値が複数のスレッドから参照されることが安全であることを示す
 // 'staticライフタイムは、型がプログラムの実行期間中ずっと有効であることを示す
 #[async_trait]
-pub trait TodoRepository: Clone + Send + Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity>;
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity>;
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+pub trait TodoRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>>;
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>>;
+    // 読んでから書き戻すまでほかの変更を待たせるよう、ロックして読む
+    // atomicallyの中で呼ぶと確定するまでロックを持つ。ロックできない実装ではfindと同じ
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.find(id).await
+    }
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>>;
+    // 本文が同じ未完了のtodoを、見えるものの中から探す
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>>;
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>>;
+    // allと同じ条件のtodoを1件ずつ返す。件数が多い書き出しで全件を溜め込まないために使う
+    // 既定ではallの結果を順に返すだけなので、溜め込まずに返せる実装で上書きする
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        let todos = self.all(filter).await?;
+        Ok(stream::iter(todos.into_iter().map(Ok)).boxed())
+    }
+    // allと同じ条件に当てはまるtodoの数
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64>;
+    // allと同じ条件のtodoを並べ、pageの範囲だけを更新時刻と一緒に返す
+    // 既定ではallの結果から切り出すので、データベースで切り出せる実装で上書きする
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        let mut todos = self.all(filter).await?;
+        page.sort.sort(&mut todos);
+        let mut listed = vec![];
+        for todo in todos.into_iter().skip(page.offset).take(page.limit) {
+            let updated_at = self.modified_at(todo.id).await?;
+            listed.push(ListedTodo { todo, updated_at });
+        }
+        Ok(listed)
+    }
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>>;
+    async fn delete(&self, id: I) -> anyhow::Result<()>;
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>>;
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>>;
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>>;
+    // 通知時刻を過ぎたリマインダーを取り出し、同時に解除する
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>>;
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>>;
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>>;
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>>;
+    // 一覧の先頭に固定する。falseで外す
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>>;
+    // 指定した時刻まで一覧から外す。Noneで戻す
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>>;
+    // ラベルをまとめて付け、付けたtodoのidを返す
+    // 存在しないtodoと既に付いているtodoは飛ばす
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>>;
+    // ラベルをまとめて外し、外したtodoのidを返す
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>>;
+    // 直下の子todo
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>>;
+    // 子孫すべてのid
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>>;
+    // blocker_idのtodoがblocked_idのtodoをブロックする
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()>;
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()>;
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>>;
+    // 直接・間接にブロックしているtodoすべてのid
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>>;
+    // todoが最後に変更された時刻
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>>;
+    // 削除も含めて一覧が最後に変わった時刻。スヌーズが明けた時刻も含める
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>>;
+    // 期間内に完了したtodoの作成から完了までの時間
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime>;
+    // 所有者以外のユーザーに共有する。共有済みの場合は権限を置き換える
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>>;
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()>;
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>>;
+    // 入力途中の文字列に前方一致かあいまいに一致するtodoを、近い順にlimit件まで返す
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>>;
+    // sync_tokenより後に作成・変更・削除された、見えるtodo。0で最初からの変更を返す
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>>;
+
+    // パスで指定された識別子をidに解決する
+    async fn resolve(&self, key: Key<I>) -> anyhow::Result<I> {
+        match key {
+            Key::Id(id) => Ok(id),
+            Key::Uuid(uuid) => Ok(self.find_by_uuid(uuid).await?.id),
+        }
+    }
+}
+
+pub type TodoStream<I = i32> = BoxStream<'static, anyhow::Result<TodoEntity<I>>>;
+
+// 一覧の並び順。どの並び順でも固定したtodoを先にする
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoSort {
+    // 新しく作ったものから
+    #[default]
+    Newest,
+    Oldest,
+    // 期限の近いものから。期限のないものは最後にする
+    Due,
+}
+
+impl TodoSort {
+    pub fn sort<I: EntityId>(self, todos: &mut [TodoEntity<I>]) {
+        match self {
+            TodoSort::Newest => todos.sort_by_key(|todo| (Reverse(todo.pinned), Reverse(todo.id))),
+            TodoSort::Oldest => todos.sort_by_key(|todo| (Reverse(todo.pinned), todo.id)),
+            TodoSort::Due => todos.sort_by_key(|todo| {
+                (
+                    Reverse(todo.pinned),
+                    todo.due_at.is_none(),
+                    todo.due_at,
+                    todo.id,
+                )
+            }),
+        }
+    }
+
+    // sortと同じ並び順
+    fn order_by(self) -> &'static str {
+        match self {
+            TodoSort::Newest => "todos.pinned desc, todos.id desc",
+            TodoSort::Oldest => "todos.pinned desc, todos.id",
+            TodoSort::Due => "todos.pinned desc, todos.due_at is null, todos.due_at, todos.id",
+        }
+    }
+}
+
+// 一覧のうち、並び順でoffset件目からlimit件まで
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TodoPage {
+    pub sort: TodoSort,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+// 一覧のtodoと、そのtodoを最後に変更した時刻
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedTodo<I = i32> {
+    pub todo: TodoEntity<I>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 一覧取得時の絞り込み条件
+// 保存したビューの条件としても受け取る
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TodoFilter<I = i32> {
+    pub assignee_id: Option<i32>,
+    pub project_id: Option<i32>,
+    pub tag: Option<String>,
+    pub label_id: Option<I>,
+    // trueで完了したものだけ、falseで完了していないものだけにする
+    pub completed: Option<bool>,
+    // 小文字の#RRGGBBで指定する
+    pub color: Option<String>,
+    // trueでスヌーズ中のものだけ、falseでスヌーズ中のものを除く
+    pub snoozed: Option<bool>,
+    // 見る人によって変わるため、ビューの条件としては保存しない
+    #[serde(skip)]
+    pub visible_to: Visibility,
+    // このユーザーに共有されたtodoだけにする
+    #[serde(skip)]
+    pub shared_with: Option<i32>,
+}
+
+// 一覧で見えるtodoの範囲
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    // 管理者などはすべてのtodoを見られる
+    #[default]
+    All,
+    // 所有者のいないtodoと、このユーザーが所有するか共有されたtodo
+    User(i32),
+    // ユーザーとして登録されていない主体は所有者のいないtodoだけ
+    Unowned,
+}
+
+impl Visibility {
+    fn user_id(self) -> Option<i32> {
+        match self {
+            Visibility::User(user_id) => Some(user_id),
+            _ => None,
+        }
+    }
+
+    // sharesにはそのtodoの共有を渡す
+    pub fn access<I>(self, owner_id: Option<i32>, shares: &[Share<I>]) -> Option<Access> {
+        match (self, owner_id) {
+            (Visibility::All, _) => Some(Access::Owner),
+            // 所有者のいないtodoは誰でも所有者と同じ操作ができる
+            (_, None) => Some(Access::Owner),
+            (Visibility::User(user_id), Some(owner_id)) if user_id == owner_id => {
+                Some(Access::Owner)
+            }
+            (Visibility::User(user_id), _) => shares
+                .iter()
+                .find(|share| share.user_id == user_id)
+                .map(|share| share.permission.into()),
+            (Visibility::Unowned, Some(_)) => None,
+        }
+    }
+
+    fn allows<I>(self, owner_id: Option<i32>, shares: &[Share<I>]) -> bool {
+        self.access(owner_id, shares).is_some()
+    }
+}
+
+// 共有で与える権限
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write,
+}
+
+// todoに対してできること。共有できるのは所有者だけ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Owner,
+}
+
+impl From<Permission> for Access {
+    fn from(permission: Permission) -> Self {
+        match permission {
+            Permission::Read => Access::Read,
+            Permission::Write => Access::Write,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, FromRow)]
+pub struct Share<I = i32> {
+    pub todo_id: I,
+    pub user_id: i32,
+    pub permission: Permission,
+}
+
+// 候補表示用に、idと本文だけを返す
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoSuggestion<I = i32> {
+    pub id: I,
+    pub text: String,
+}
+
+// データベースを使わない実装の候補の並べ方
+// トライグラムの代わりに、前方一致、単語の前方一致、部分一致の順に並べる
+fn rank_suggestions<I: EntityId>(
+    query: &str,
+    todos: Vec<TodoEntity<I>>,
+    limit: i64,
+) -> Vec<TodoSuggestion<I>> {
+    let query = query.to_lowercase();
+    let mut ranked: Vec<(u8, TodoSuggestion<I>)> = todos
+        .into_iter()
+        .filter_map(|todo| {
+            let text = todo.text.to_lowercase();
+            let rank = if text.starts_with(&query) {
+                0
+            } else if text.split_whitespace().any(|word| word.starts_with(&query)) {
+                1
+            } else if text.contains(&query) {
+                2
+            } else {
+                return None;
+            };
+            Some((
+                rank,
+                TodoSuggestion {
+                    id: todo.id,
+                    text: todo.text,
+                },
+            ))
+        })
+        .collect();
+    ranked.sort_by_key(|(rank, suggestion)| (*rank, suggestion.text.len(), suggestion.id));
+    ranked
+        .into_iter()
+        .take(limit.max(0) as usize)
+        .map(|(_, suggestion)| suggestion)
+        .collect()
+}
+
+// 変更ログに記録する操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+enum TodoOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+// 変更ログの1件。owner_idは変更した時点の所有者
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct TodoChange<I = i32> {
+    todo_id: I,
+    uuid: Uuid,
+    operation: TodoOperation,
+    owner_id: Option<i32>,
+}
+
+impl<I: EntityId> TodoChange<I> {
+    fn new(todo: &TodoEntity<I>, operation: TodoOperation) -> Self {
+        TodoChange {
+            todo_id: todo.id,
+            uuid: todo.uuid,
+            operation,
+            owner_id: todo.owner_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ChangedTodo<I = i32> {
+    pub id: I,
+    pub uuid: Uuid,
+}
+
+// 同期トークンより後の変更
+// 次の差分はsync_tokenより後を求める
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TodoChanges<I = i32> {
+    pub created: Vec<ChangedTodo<I>>,
+    pub updated: Vec<ChangedTodo<I>>,
+    pub deleted: Vec<ChangedTodo<I>>,
+    pub sync_token: i64,
+}
+
+impl<I: EntityId> TodoChanges<I> {
+    // 古い順の変更をtodoごとにまとめる
+    // 作成してから削除したtodoはクライアントが知らないため返さない
+    fn collapse(changes: impl IntoIterator<Item = TodoChange<I>>, sync_token: i64) -> Self {
+        let mut positions: HashMap<I, usize> = HashMap::new();
+        let mut collapsed: Vec<(ChangedTodo<I>, TodoOperation, TodoOperation)> = vec![];
+        for change in changes {
+            match positions.get(&change.todo_id) {
+                Some(&position) => collapsed[position].2 = change.operation,
+                None => {
+                    positions.insert(change.todo_id, collapsed.len());
+                    let todo = ChangedTodo {
+                        id: change.todo_id,
+                        uuid: change.uuid,
+                    };
+                    collapsed.push((todo, change.operation, change.operation));
+                }
+            }
+        }
+        let mut result = TodoChanges {
+            sync_token,
+            ..TodoChanges::default()
+        };
+        for (todo, first, last) in collapsed {
+            match (first, last) {
+                (TodoOperation::Insert, TodoOperation::Delete) => {}
+                (_, TodoOperation::Delete) => result.deleted.push(todo),
+                (TodoOperation::Insert, _) => result.created.push(todo),
+                _ => result.updated.push(todo),
+            }
+        }
+        result
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
-struct TodoFromRow {
-    id: i32,
+struct TodoFromRow<I = i32> {
+    id: I,
     text: String,
-    completed: bool,
+    status: TodoStatus,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
-pub struct TodoWithLabelFromRow {
-    id: i32,
+pub struct TodoWithLabelFromRow<I = i32> {
+    id: I,
+    uuid: Uuid,
     text: String,
-    completed: bool,
-    label_id: Option<i32>,
+    status: TodoStatus,
+    remind_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+    assignee_id: Option<i32>,
+    assignee_name: Option<String>,
+    project_id: Option<i32>,
+    tags: Vec<String>,
+    icon: Option<String>,
+    color: Option<String>,
+    pinned: bool,
+    snoozed_until: Option<DateTime<Utc>>,
+    estimate_minutes: Option<i32>,
+    due_at: Option<DateTime<Utc>>,
+    description: Option<String>,
+    parent_id: Option<I>,
+    blocked: bool,
+    label_id: Option<I>,
+    label_uuid: Option<Uuid>,
     label_name: Option<String>,
+    label_color: Option<String>,
+    label_description: Option<String>,
+    owner_id: Option<i32>,
+    updated_at: DateTime<Utc>,
+}
+
+// カンバンの列に対応するtodoの状態
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum TodoStatus {
+    #[default]
+    Backlog,
+    InProgress,
+    Done,
+    Cancelled,
+}
+
+impl TodoStatus {
+    // 中止したtodoは一度作業中か未着手に戻してからでないと完了にできない
+    pub fn can_transition_to(self, next: TodoStatus) -> bool {
+        !matches!((self, next), (TodoStatus::Cancelled, TodoStatus::Done))
+    }
+
+    pub fn is_completed(self) -> bool {
+        self == TodoStatus::Done
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
-pub struct TodoEntity {
-    id: i32,
-    text: String,
+pub struct TodoEntity<I = i32> {
+    pub id: I,
+    // 記録済みの監査ログなど、UUIDを持たない値も読めるようにする
+    #[serde(default)]
+    pub uuid: Uuid,
+    pub text: String,
+    // 互換性のためstatusから導出した値も返す
     completed: bool,
-    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub status: TodoStatus,
+    pub labels: Vec<Label<I>>,
+    pub remind_at: Option<DateTime<Utc>>,
+    // 完了にした日時。完了以外に戻すと消える
+    pub completed_at: Option<DateTime<Utc>>,
+    pub assignee: Option<User>,
+    pub project_id: Option<i32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // 一覧で表示する絵文字と色
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    // 一覧で先頭に並べる
+    #[serde(default)]
+    pub pinned: bool,
+    // この時刻まで一覧から外す
+    #[serde(default)]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    // 終えるまでにかかると見積もった時間(分)
+    #[serde(default)]
+    pub estimate_minutes: Option<i32>,
+    // 期限。過ぎても完了していなければ期限切れ
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    // 本文に収まらない詳細
+    #[serde(default)]
+    pub description: Option<String>,
+    pub parent_id: Option<I>,
+    // ブロックしているtodoのうち、完了していないものがある
+    #[serde(default)]
+    pub blocked: bool,
+    // 作成したユーザー。いない場合は全員に見える
+    #[serde(default)]
+    pub owner_id: Option<i32>,
+}
+
+impl<I> TodoEntity<I> {
+    pub fn is_snoozed(&self, now: DateTime<Utc>) -> bool {
+        self.snoozed_until.is_some_and(|until| until > now)
+    }
+}
+
+// スヌーズは書き込みなしに明けて一覧に戻るので、明けた時刻も一覧の変更として数える
+pub fn latest_change<'a, I: EntityId>(
+    written_at: DateTime<Utc>,
+    todos: impl IntoIterator<Item = &'a TodoEntity<I>>,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    todos
+        .into_iter()
+        .filter_map(|todo| todo.snoozed_until)
+        .filter(|until| *until <= now)
+        .fold(written_at, DateTime::max)
+}
+
+// todoを直接ブロックしている・ブロックされているtodoのid
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TodoDependencies<I = i32> {
+    pub blocked_by: Vec<I>,
+    pub blocks: Vec<I>,
+}
+
+// 集計対象とする完了日時の範囲。toは含まない
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompletedRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl CompletedRange {
+    pub fn contains(&self, completed_at: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| from <= completed_at)
+            && self.to.is_none_or(|to| completed_at < to)
+    }
+}
+
+// 作成から完了までにかかった時間の集計（秒）
+// 対象がない場合は件数以外をnullで返す
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, FromRow)]
+pub struct CycleTime {
+    pub count: i64,
+    pub average_seconds: Option<f64>,
+    pub p50_seconds: Option<f64>,
+    pub p90_seconds: Option<f64>,
+    pub p95_seconds: Option<f64>,
+}
+
+impl CycleTime {
+    // データベースを使わずに集計する場合に、秒数の一覧から求める
+    pub fn from_seconds(mut seconds: Vec<f64>) -> Self {
+        if seconds.is_empty() {
+            return CycleTime::default();
+        }
+        seconds.sort_by(f64::total_cmp);
+        CycleTime {
+            count: seconds.len() as i64,
+            average_seconds: Some(seconds.iter().sum::<f64>() / seconds.len() as f64),
+            p50_seconds: Some(percentile(&seconds, 0.5)),
+            p90_seconds: Some(percentile(&seconds, 0.9)),
+            p95_seconds: Some(percentile(&seconds, 0.95)),
+        }
+    }
+}
+
+// percentile_contと同じく前後の値を線形に補間する
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+// 作成・更新のたびに記録されるtodoの版
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoRevision<I = i32> {
+    pub revision: i64,
+    pub text: String,
+    pub completed: bool,
+    pub status: TodoStatus,
+    pub labels: Json<Vec<Label<I>>>,
+    pub created_at: DateTime<Utc>,
 }
 
 // `TodoWithLabelFromRow`型のベクターを引数として受け取り、`TodoEntity`型のベクターを返す関数
-fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<TodoEntity> {
-    let mut rows = rows.iter();
-    let mut accum: Vec<TodoEntity> = vec![];
-    'outer: while let Some(row) = rows.next() {
-        let mut todos = accum.iter_mut();
-        while let Some(todo) = todos.next() {
-            if todo.id == row.id {
-                todo.labels.push(Label {
-                    id: row.label_id.unwrap(),
-                    name: row.label_name.clone().unwrap(),
-                });
-                continue 'outer;
-            }
+// ラベルの数だけ行が重なるので、todoのidでまとめる。行が並んでいなくても最初に現れた順を保つ
+fn fold_entities<I: EntityId>(rows: Vec<TodoWithLabelFromRow<I>>) -> Vec<TodoEntity<I>> {
+    let mut accum: Vec<TodoEntity<I>> = vec![];
+    let mut positions: HashMap<I, usize> = HashMap::new();
+    for row in rows {
+        let label = row.label_id.map(|id| Label {
+            id,
+            uuid: row.label_uuid.unwrap(),
+            name: row.label_name.clone().unwrap(),
+            color: row.label_color.clone().unwrap(),
+            description: row.label_description.clone(),
+        });
+        if let Some(&position) = positions.get(&row.id) {
+            accum[position].labels.extend(label);
+            continue;
         }
-        let labels = if row.label_id.is_some() {
-            vec![Label {
-                id: row.label_id.unwrap(),
-                name: row.label_name.clone().unwrap(),
-            }]
-        } else {
-            vec![]
-        };
 
+        positions.insert(row.id, accum.len());
         accum.push(TodoEntity {
             id: row.id,
-            text: row.text.clone(),
-            completed: row.completed,
-            labels,
+            uuid: row.uuid,
+            text: row.text,
+            completed: row.status.is_completed(),
+            status: row.status,
+            labels: label.into_iter().collect(),
+            remind_at: row.remind_at,
+            completed_at: row.completed_at,
+            assignee: row.assignee_id.map(|id| User {
+                id,
+                name: row.assignee_name.unwrap_or_default(),
+            }),
+            project_id: row.project_id,
+            tags: row.tags,
+            icon: row.icon,
+            color: row.color,
+            pinned: row.pinned,
+            snoozed_until: row.snoozed_until,
+            estimate_minutes: row.estimate_minutes,
+            due_at: row.due_at,
+            description: row.description,
+            parent_id: row.parent_id,
+            blocked: row.blocked,
+            owner_id: row.owner_id,
         })
     }
     accum
 }
 
-fn fold_entity(row: TodoWithLabelFromRow) -> TodoEntity {
-    let todo_entities = fold_entities(vec![row]);
-    let todo = todo_entities.first().expect("expect 1 todo");
-
-    todo.clone()
+// 行の更新時刻も残してまとめる
+fn fold_listed<I: EntityId>(rows: Vec<TodoWithLabelFromRow<I>>) -> Vec<ListedTodo<I>> {
+    let updated_at: HashMap<I, DateTime<Utc>> =
+        rows.iter().map(|row| (row.id, row.updated_at)).collect();
+    fold_entities(rows)
+        .into_iter()
+        .map(|todo| ListedTodo {
+            updated_at: updated_at[&todo.id],
+            todo,
+        })
+        .collect()
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct CreateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over test length"))]
+#[serde(deny_unknown_fields)]
+pub struct CreateTodo<I = i32> {
+    #[serde(deserialize_with = "crate::trim::string")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
     text: String,
-    labels: Vec<i32>,
+    // フォームでは空の配列を送れないので、省略したら空にする
+    #[serde(default)]
+    labels: Vec<I>,
+    project_id: Option<i32>,
+    #[serde(default)]
+    #[validate(
+        length(max = 10, message = "validation.too_many_tags"),
+        custom = "validate_tags"
+    )]
+    tags: Vec<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_icon")]
+    icon: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_color")]
+    color: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 10080, message = "validation.estimate_minutes"))]
+    estimate_minutes: Option<i32>,
+    #[serde(default)]
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(max = 5000, message = "validation.description_too_long"))]
+    description: Option<String>,
+    #[serde(default)]
+    parent_id: Option<I>,
+    // 本文が同じ未完了のtodoと重複させない
+    #[serde(skip)]
+    deduplicated: bool,
+    #[serde(skip)]
+    owner_id: Option<i32>,
+    // 同期するクライアントが振ったUUID。なければサーバーで振る
+    #[serde(skip)]
+    uuid: Option<Uuid>,
+}
+
+impl<I: EntityId> CreateTodo<I> {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn parent_id(&self) -> Option<I> {
+        self.parent_id
+    }
+
+    pub fn icon(&self) -> Option<String> {
+        self.icon.clone()
+    }
+
+    // 保存する色は小文字の#RRGGBBにそろえる
+    pub fn color(&self) -> Option<String> {
+        self.color.as_deref().map(normalize_color)
+    }
+
+    pub fn estimate_minutes(&self) -> Option<i32> {
+        self.estimate_minutes
+    }
+
+    pub fn due_at(&self) -> Option<DateTime<Utc>> {
+        self.due_at
+    }
+
+    // 空の説明は付けない
+    pub fn description(&self) -> Option<String> {
+        self.description
+            .clone()
+            .filter(|description| !description.is_empty())
+    }
+
+    pub fn described(self, description: Option<String>) -> Self {
+        CreateTodo {
+            description,
+            ..self
+        }
+    }
+
+    // テンプレートから作る場合。置き換え後の本文は呼び出し側で検証する
+    pub fn from_template(text: String, labels: Vec<I>, tags: Vec<String>) -> Self {
+        CreateTodo {
+            text,
+            labels,
+            project_id: None,
+            tags,
+            icon: None,
+            color: None,
+            estimate_minutes: None,
+            due_at: None,
+            description: None,
+            parent_id: None,
+            deduplicated: false,
+            owner_id: None,
+            uuid: None,
+        }
+    }
+
+    // クライアントで作られたtodoを同期する場合。本文などは呼び出し側で検証する
+    pub fn synced(uuid: Uuid, text: String, labels: Vec<I>, tags: Vec<String>) -> Self {
+        CreateTodo {
+            uuid: Some(uuid),
+            ..Self::from_template(text, labels, tags)
+        }
+    }
+
+    // 既存のtodoを複製する場合。作成者とリマインダーは呼び出し側で設定する
+    pub fn copy_of(todo: &TodoEntity<I>, parent_id: Option<I>) -> Self {
+        CreateTodo {
+            project_id: todo.project_id,
+            icon: todo.icon.clone(),
+            color: todo.color.clone(),
+            estimate_minutes: todo.estimate_minutes,
+            due_at: todo.due_at,
+            description: todo.description.clone(),
+            parent_id,
+            ..Self::from_template(
+                todo.text.clone(),
+                todo.labels.iter().map(|label| label.id).collect(),
+                todo.tags.clone(),
+            )
+        }
+    }
+
+    pub fn deduplicated(self) -> Self {
+        CreateTodo {
+            deduplicated: true,
+            ..self
+        }
+    }
+
+    pub fn owned_by(self, owner_id: Option<i32>) -> Self {
+        CreateTodo { owner_id, ..self }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct UpdateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over test length"))]
+#[serde(deny_unknown_fields)]
+pub struct UpdateTodo<I = i32> {
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(min = 1, message = "validation.empty"))]
+    #[validate(length(max = 100, message = "validation.too_long"))]
     text: Option<String>,
     completed: Option<bool>,
-    labels: Option<Vec<i32>>,
+    status: Option<TodoStatus>,
+    labels: Option<Vec<I>>,
+    #[validate(
+        length(max = 10, message = "validation.too_many_tags"),
+        custom = "validate_tags"
+    )]
+    tags: Option<Vec<String>>,
+    // nullか空文字列を指定すると外す
+    #[serde(
+        default,
+        deserialize_with = "crate::patch::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(custom = "validate_icon_or_empty")]
+    icon: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "crate::patch::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(custom = "validate_color_or_empty")]
+    color: Option<Option<String>>,
+    // nullを指定するとリマインダーを外す
+    #[serde(
+        default,
+        deserialize_with = "crate::patch::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    remind_at: Option<Option<DateTime<Utc>>>,
+    // nullを指定すると見積もりを外す
+    #[serde(
+        default,
+        deserialize_with = "crate::patch::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(range(min = 1, max = 10080, message = "validation.estimate_minutes"))]
+    estimate_minutes: Option<Option<i32>>,
+    // nullを指定すると期限を外す
+    #[serde(
+        default,
+        deserialize_with = "crate::patch::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    due_at: Option<Option<DateTime<Utc>>>,
+    // 空文字を送ると説明を消す
+    #[serde(default, deserialize_with = "crate::trim::option")]
+    #[validate(length(max = 5000, message = "validation.description_too_long"))]
+    description: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-pub struct TodoRepositoryForDb {
-    pool: PgPool,
+// タグはそれぞれ1文字以上30文字以下
+pub(crate) fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags
+        .iter()
+        .any(|tag| tag.is_empty() || tag.chars().count() > 30)
+    {
+        let mut error = ValidationError::new("tag_length");
+        error.message = Some("validation.tag_length".into());
+        return Err(error);
+    }
+    Ok(())
 }
 
-impl TodoRepositoryForDb {
-    pub fn new(pool: PgPool) -> Self {
-        TodoRepositoryForDb { pool }
+// 空白や制御文字ではない1書記素だけを受け付ける。肌の色や結合した絵文字も1文字として数える
+fn validate_icon(icon: &str) -> Result<(), ValidationError> {
+    let mut graphemes = icon.graphemes(true);
+    match (graphemes.next(), graphemes.next()) {
+        (Some(grapheme), None)
+            if !grapheme
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control()) =>
+        {
+            Ok(())
+        }
+        _ => {
+            let mut error = ValidationError::new("icon");
+            error.message = Some("validation.icon".into());
+            Err(error)
+        }
     }
 }
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
-        let row = sqlx::query_as::<_, TodoFromRow>(
-            r#"INSERT INTO todos (text, completed) VALUES ($1, false) RETURNING *"#,
-        )
-        .bind(payload.text.clone())
-        .fetch_one(&self.pool)
-        .await?;
+fn validate_icon_or_empty(icon: &str) -> Result<(), ValidationError> {
+    match icon {
+        "" => Ok(()),
+        icon => validate_icon(icon),
+    }
+}
 
-        sqlx::query(
-            r#"INSERT INTO todo_labels (todo_id, label_id) SELECT $1, id FROM UNNEST($2) AS t(id);"#,
-        )
-        .bind(row.id)
-        .bind(payload.labels)
-        .execute(&self.pool)
-        .await?;
-        tx.commit().await?;
+fn validate_color_or_empty(color: &str) -> Result<(), ValidationError> {
+    match color {
+        "" => Ok(()),
+        color => validate_color(color),
+    }
+}
 
-        let todo = self.find(row.id).await?;
+impl<I: EntityId> UpdateTodo<I> {
+    // すべての項目を置き換える更新内容
+    pub fn replace(text: String, status: TodoStatus, labels: Vec<I>, tags: Vec<String>) -> Self {
+        UpdateTodo {
+            text: Some(text),
+            completed: None,
+            status: Some(status),
+            labels: Some(labels),
+            tags: Some(tags),
+            icon: None,
+            color: None,
+            remind_at: None,
+            estimate_minutes: None,
+            due_at: None,
+            description: None,
+        }
+    }
 
-        Ok(todo)
+    pub fn status(status: TodoStatus) -> Self {
+        UpdateTodo {
+            text: None,
+            completed: None,
+            status: Some(status),
+            labels: None,
+            tags: None,
+            icon: None,
+            color: None,
+            remind_at: None,
+            estimate_minutes: None,
+            due_at: None,
+            description: None,
+        }
     }
 
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"SELECT todos.*, labels.id AS label_id, labels.name AS label_name FROM todos LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id LEFT OUTER JOIN labels ON labels.id = tl.label_id WHERE todos.id=$1"#,
-        )
-        .bind(id)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+    // 更新後のアイコンと色。指定がなければ今のまま、nullか空文字列なら外す
+    pub fn next_icon(&self, current: Option<String>) -> Option<String> {
+        match self.icon.as_ref().map(Option::as_deref) {
+            None => current,
+            Some(None | Some("")) => None,
+            Some(Some(icon)) => Some(icon.to_string()),
+        }
+    }
 
-        let todos = fold_entities(items);
-        let todo = todos.first().ok_or(RepositoryError::NotFound(id))?;
-        Ok(todo.clone())
+    pub fn next_color(&self, current: Option<String>) -> Option<String> {
+        match self.color.as_ref().map(Option::as_deref) {
+            None => current,
+            Some(None | Some("")) => None,
+            Some(Some(color)) => Some(normalize_color(color)),
+        }
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"SELECT todos.*, labels.id AS label_id, labels.name AS label_name FROM todos LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id LEFT OUTER JOIN labels ON labels.id = tl.label_id ORDER BY todos.id DESC;"#
-        ).fetch_all(&self.pool).await?;
+    // 更新後のリマインダー。指定がなければ今のまま、nullなら外す
+    pub fn next_remind_at(&self, current: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        self.remind_at.unwrap_or(current)
+    }
 
-        Ok(fold_entities(items))
+    // 更新後の見積もり。指定がなければ今のまま、nullなら外す
+    pub fn next_estimate_minutes(&self, current: Option<i32>) -> Option<i32> {
+        self.estimate_minutes.unwrap_or(current)
     }
 
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
+    // 更新後の期限。指定がなければ今のまま、nullなら外す
+    pub fn next_due_at(&self, current: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        self.due_at.unwrap_or(current)
+    }
 
-        let old_todo = self.find(id).await?;
-        sqlx::query(
-            r#"
-update todos set text=$1, completed=$2
-where id=$3
-returning *
-        "#,
-        )
-        .bind(payload.text.unwrap_or(old_todo.text))
-        .bind(payload.completed.unwrap_or(old_todo.completed))
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+    // 更新後の説明。指定がなければ今のまま、空文字なら消す
+    pub fn next_description(&self, current: Option<String>) -> Option<String> {
+        match self.description.as_deref() {
+            None => current,
+            Some("") => None,
+            Some(description) => Some(description.to_string()),
+        }
+    }
 
-        if let Some(labels) = payload.labels {
-            // todo's label update
-            // 一度関連するレコードを削除
-            sqlx::query(
-                r#"
-    delete from todo_labels where todo_id=$1
-            "#,
-            )
-            .bind(id)
-            .execute(&self.pool)
-            .await?;
+    // 更新後の状態
+    // statusの指定を優先し、なければ従来のcompletedから読み替える
+    pub fn next_status(&self, current: TodoStatus) -> TodoStatus {
+        match (self.status, self.completed) {
+            (Some(status), _) => status,
+            (None, Some(true)) => TodoStatus::Done,
+            (None, Some(false)) if current.is_completed() => TodoStatus::Backlog,
+            _ => current,
+        }
+    }
+}
 
-            sqlx::query(
-                r#"
-    insert into todo_labels (todo_id, label_id)
-    select $1, id
-    from unnest($2) as t(id);
-            "#,
-            )
-            .bind(id)
-            .bind(labels)
-            .execute(&self.pool)
-            .await?;
-        };
+// エンティティの現在の状態をそのまま再現する更新内容
+// UpdateTodoに変換して取り消しで戻せる項目
+// 固定やスヌーズ、担当者、プロジェクト、親、依存関係は戻せない
+pub const RESTORABLE_FIELDS: [&str; 10] = [
+    "text",
+    "status",
+    "labels",
+    "tags",
+    "icon",
+    "color",
+    "remind_at",
+    "estimate_minutes",
+    "due_at",
+    "description",
+];
 
-        tx.commit().await?;
-        let todo = self.find(id).await?;
-        Ok(todo)
+impl<I: EntityId> From<TodoEntity<I>> for UpdateTodo<I> {
+    fn from(todo: TodoEntity<I>) -> Self {
+        UpdateTodo {
+            text: Some(todo.text),
+            completed: None,
+            status: Some(todo.status),
+            labels: Some(todo.labels.iter().map(|label| label.id).collect()),
+            tags: Some(todo.tags),
+            icon: Some(todo.icon),
+            color: Some(todo.color),
+            remind_at: Some(todo.remind_at),
+            estimate_minutes: Some(todo.estimate_minutes),
+            due_at: Some(todo.due_at),
+            description: Some(todo.description.unwrap_or_default()),
+        }
     }
+}
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        let tx = self.pool.begin().await?;
+// ラベルと担当者を結合したtodoの取得クエリ
+// 条件や並び順は呼び出し側で付け足す
+const SELECT_TODOS: &str = r#"
+select todos.*, labels.id as label_id, labels.uuid as label_uuid, labels.name as label_name, labels.color as label_color, labels.description as label_description, users.name as assignee_name,
+       exists(select 1
+              from todo_dependencies d
+              join todos blocker on blocker.id = d.blocker_id
+              where d.blocked_id = todos.id and blocker.status <> 'done') as blocked
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+left outer join users on users.id = todos.assignee_id
+"#;
 
-        // todo's label delete
-        sqlx::query(
-            r#"
-delete from todo_labels where todo_id=$1
-        "#,
-        )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+// streamで読み終わっていないtodoを溜めておく件数
+const STREAM_BUFFER: usize = 64;
 
-        // todo delete
-        sqlx::query(
-            r#"
-delete from todos where id=$1
-        "#,
-        )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+// 一覧と件数で共通の絞り込み。bind_filterの順に値を渡す
+const FILTER_TODOS: &str = r#"
+where ($1::integer is null or todos.assignee_id = $1)
+  and ($2::integer is null or todos.project_id = $2)
+  and ($3::text is null or todos.tags @> array[$3])
+  and ($4 is null or todos.id in (select todo_id from todo_labels where label_id = $4))
+  and ($5::boolean or todos.owner_id is null or todos.owner_id = $6::integer
+       or todos.id in (select todo_id from todo_shares where user_id = $6::integer))
+  and ($7::integer is null or todos.id in (select todo_id from todo_shares where user_id = $7))
+  and ($8::boolean is null or (todos.status = 'done') = $8)
+  and ($9::text is null or todos.color = $9)
+  and ($10::boolean is null or coalesce(todos.snoozed_until > now(), false) = $10)
+"#;
 
-        tx.commit().await?;
+fn bind_filter<O, I: EntityId>(
+    query: QueryAs<'_, Postgres, O, PgArguments>,
+    filter: TodoFilter<I>,
+) -> QueryAs<'_, Postgres, O, PgArguments> {
+    query
+        .bind(filter.assignee_id)
+        .bind(filter.project_id)
+        .bind(filter.tag)
+        .bind(filter.label_id)
+        .bind(filter.visible_to == Visibility::All)
+        .bind(filter.visible_to.user_id())
+        .bind(filter.shared_with)
+        .bind(filter.completed)
+        .bind(filter.color)
+        .bind(filter.snoozed)
+}
 
-        Ok(())
+// 1つのtodoに付けられるラベルの数の既定値
+pub const DEFAULT_MAX_LABELS: usize = 20;
+
+// TODO_MAX_LABELS で1つのtodoに付けられるラベルの数を変える
+pub fn max_labels_from_env() -> anyhow::Result<usize> {
+    match env::var("TODO_MAX_LABELS") {
+        Ok(max) if !max.is_empty() => Ok(max.parse()?),
+        _ => Ok(DEFAULT_MAX_LABELS),
     }
 }
-#[cfg(test)]
-#[cfg(feature = "database-test")]
-mod test {
-    use super::*;
-    use dotenv::dotenv;
-    use sqlx::PgPool;
-    use std::env;
+
+// 同じラベルが重ねて指定されても1つと数える
+pub(crate) fn check_label_count<I: EntityId>(
+    labels: &[I],
+    max_labels: usize,
+) -> anyhow::Result<()> {
+    if labels.iter().collect::<HashSet<_>>().len() > max_labels {
+        return Err(RepositoryError::TooManyLabels(max_labels).into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForDb<I = i32> {
+    db: Database,
+    replica: Option<Replica>,
+    max_labels: usize,
+    id: PhantomData<I>,
+}
+
+impl TodoRepositoryForDb {
+    pub fn new(db: impl Into<Database>) -> Self {
+        Self::with_ids(db)
+    }
+}
+
+impl<I: EntityId> TodoRepositoryForDb<I> {
+    // idの型を選んで作る。テーブルの主キーの型と合わせる
+    pub fn with_ids(db: impl Into<Database>) -> Self {
+        TodoRepositoryForDb {
+            db: db.into(),
+            replica: None,
+            max_labels: DEFAULT_MAX_LABELS,
+            id: PhantomData,
+        }
+    }
+
+    pub fn with_max_labels(mut self, max_labels: usize) -> Self {
+        self.max_labels = max_labels;
+        self
+    }
+
+    // 一覧や集計などの読み込みを複製に送る
+    // 複製は遅れて反映されるため、書き込みの前後の読み込みや権限の確認は主で行う
+    pub fn with_replica(mut self, replica: Replica) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    async fn read<'a, T, E, F, Fut>(&'a self, query: F) -> anyhow::Result<T>
+    where
+        E: Into<anyhow::Error>,
+        F: Fn(&'a Database) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        match &self.replica {
+            Some(replica) => replica.read(&self.db, query).await,
+            None => query(&self.db).await.map_err(Into::into),
+        }
+    }
+
+    // 書き込んだ直後の状態を主から読む
+    async fn find_in<'e>(
+        &self,
+        executor: impl PgExecutor<'e>,
+        id: I,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        let sql = format!("{} where todos.id=$1", SELECT_TODOS);
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql)
+            .bind(id)
+            .fetch_all(executor)
+            .await?;
+
+        let todos = fold_entities(items);
+        let todo = todos.first().ok_or_else(|| id.not_found())?;
+        Ok(todo.clone())
+    }
+
+    async fn find_by_uuid_in(
+        &self,
+        db: &Database,
+        uuid: Uuid,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        let sql = format!("{} where todos.uuid=$1", SELECT_TODOS);
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql)
+            .bind(uuid)
+            .fetch_all(db)
+            .await?;
+        Ok(fold_entities(items).into_iter().next())
+    }
+}
+
+// トランザクション内の最新の状態を版として記録する
+async fn insert_revision<I: EntityId>(tx: &mut DatabaseTransaction, id: I) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+insert into todo_revisions (todo_id, text, status, labels)
+select todos.id, todos.text, todos.status,
+       coalesce((select jsonb_agg(jsonb_build_object('id', labels.id, 'uuid', labels.uuid, 'name', labels.name, 'color', labels.color, 'description', labels.description) order by labels.id)
+                 from todo_labels tl
+                 join labels on labels.id = tl.label_id
+                 where tl.todo_id = todos.id), '[]')
+from todos
+where todos.id = $1
+    "#,
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+// 存在しないラベルがあれば、外部キー違反で失敗する前にそのidをNotFoundとして返す
+async fn insert_labels<I: EntityId>(
+    tx: &mut DatabaseTransaction,
+    id: I,
+    labels: Vec<I>,
+) -> anyhow::Result<()> {
+    let unknown = sqlx::query_as::<_, (I,)>(
+        r#"select t.id from unnest($1) with ordinality as t(id, n) where not exists (select 1 from labels where labels.id = t.id) order by n limit 1"#,
+    )
+    .bind(&labels)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some((label_id,)) = unknown {
+        return Err(label_id.not_found().into());
+    }
+    sqlx::query(
+        r#"insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id)"#,
+    )
+    .bind(id)
+    .bind(labels)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+// idsのうち存在するtodoを読む。見つからないものは飛ばす
+pub(crate) async fn find_existing<I: EntityId, T: TodoRepository<I> + ?Sized>(
+    repository: &T,
+    ids: &[I],
+) -> anyhow::Result<Vec<TodoEntity<I>>> {
+    let mut todos = vec![];
+    for id in ids {
+        match repository.find(*id).await {
+            Ok(todo) => todos.push(todo),
+            Err(e) => match e.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::NotFound(_) | RepositoryError::NotFoundUuid(_)) => {}
+                _ => return Err(e),
+            },
+        }
+    }
+    Ok(todos)
+}
+
+// まとめて付け外しする間にラベルが削除されないようにする
+// ラベルがなければfalseを返す
+async fn lock_label<I: EntityId>(
+    tx: &mut DatabaseTransaction,
+    label_id: I,
+) -> anyhow::Result<bool> {
+    let found = sqlx::query(r#"select id from labels where id = $1 for share"#)
+        .bind(label_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    Ok(found.is_some())
+}
+
+#[async_trait]
+impl<I: EntityId> TodoRepository<I> for TodoRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        check_label_count(&payload.labels, self.max_labels)?;
+        let mut tx = self.db.begin().await?;
+        let row = sqlx::query_as::<_, TodoFromRow<I>>(
+            r#"INSERT INTO todos (text, project_id, deduplicated, tags, parent_id, owner_id, uuid, icon, color, estimate_minutes, due_at, description) VALUES ($1, $2, $3, $4, $5, $6, coalesce($7, uuid_generate_v7()), $8, $9, $10, $11, $12) RETURNING *"#,
+        )
+        .bind(payload.text.clone())
+        .bind(payload.project_id)
+        .bind(payload.deduplicated)
+        .bind(payload.tags.clone())
+        .bind(payload.parent_id)
+        .bind(payload.owner_id)
+        .bind(payload.uuid)
+        .bind(payload.icon())
+        .bind(payload.color())
+        .bind(payload.estimate_minutes())
+        .bind(payload.due_at())
+        .bind(payload.description())
+        .fetch_one(&mut tx)
+        .await;
+        let row = match row {
+            Err(e) if is_unique_violation(&e) => {
+                tx.rollback().await?;
+                // UUIDが重なった場合はそのtodoを、本文が重なった場合は同じ所有者の未完了のtodoを返す
+                let mut existing = None;
+                if let Some(uuid) = payload.uuid {
+                    existing = self.find_by_uuid_in(&self.db, uuid).await?;
+                }
+                if existing.is_none() {
+                    let owner = payload
+                        .owner_id
+                        .map_or(Visibility::Unowned, Visibility::User);
+                    existing = self.find_by_text(&payload.text, owner).await?;
+                }
+                return Err(RepositoryError::Duplicate(
+                    existing.map_or(Key::Id(0), |todo| todo.id.key()),
+                )
+                .into());
+            }
+            row => row?,
+        };
+
+        insert_labels(&mut tx, row.id, payload.labels).await?;
+        insert_revision(&mut tx, row.id).await?;
+        tx.commit().await?;
+
+        let todo = self.find_in(&self.db, row.id).await?;
+
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.read(|db| self.find_in(db, id)).await
+    }
+
+    #[instrument(skip_all)]
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query("select id from todos where id = $1 for update")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        self.find_in(&self.db, id).await
+    }
+
+    #[instrument(skip_all)]
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        let todo = self
+            .read(|db| self.find_by_uuid_in(db, uuid))
+            .await?
+            .ok_or(RepositoryError::NotFoundUuid(uuid))?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        let sql = format!(
+            r#"{}
+where todos.id = (
+    select id from todos
+    where text = $1 and status in ('backlog', 'in_progress')
+      and ($2::boolean or owner_id is null or owner_id = $3::integer
+           or id in (select todo_id from todo_shares where user_id = $3::integer))
+    order by id
+    limit 1
+)"#,
+            SELECT_TODOS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql)
+            .bind(text)
+            .bind(visible_to == Visibility::All)
+            .bind(visible_to.user_id())
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(fold_entities(items).into_iter().next())
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let sql = format!(
+            "{}{}order by todos.pinned desc, todos.id desc, labels.id",
+            SELECT_TODOS, FILTER_TODOS
+        );
+        let items = self
+            .read(|db| {
+                bind_filter(
+                    sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql),
+                    filter.clone(),
+                )
+                .fetch_all(db)
+            })
+            .await?;
+
+        Ok(fold_entities(items))
+    }
+
+    // 行を読みながら1件ずつ返す。ラベルの行はtodoごとに並ぶので、idが変わったところで区切る
+    // 読む側が追いつかない間は、チャンネルに収まる分より先を読まない
+    // 読み始めてから主に切り替えられないので、複製があっても主から読む
+    #[instrument(skip_all)]
+    async fn stream(&self, filter: TodoFilter<I>) -> anyhow::Result<TodoStream<I>> {
+        let db = self.db.clone();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let sql = format!(
+                "{}{}order by todos.pinned desc, todos.id desc, labels.id",
+                SELECT_TODOS, FILTER_TODOS
+            );
+            let mut rows =
+                bind_filter(sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql), filter).fetch(&db);
+            let mut group: Vec<TodoWithLabelFromRow<I>> = vec![];
+            loop {
+                let row = match rows.try_next().await {
+                    Ok(row) => row,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                let is_next_todo = match (&row, group.first()) {
+                    (Some(row), Some(first)) => row.id != first.id,
+                    _ => true,
+                };
+                if is_next_todo {
+                    for todo in fold_entities(std::mem::take(&mut group)) {
+                        // 読む側が切断した
+                        if sender.send(Ok(todo)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                match row {
+                    Some(row) => group.push(row),
+                    None => return,
+                }
+            }
+        });
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|todo| (todo, receiver))
+        })
+        .boxed())
+    }
+
+    // ラベルの行で件数がずれないよう、todoだけを並べて切り出してからラベルを結合する
+    #[instrument(skip_all)]
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        let order_by = page.sort.order_by();
+        let sql = format!(
+            "{}where todos.id in (select todos.id from todos{}order by {} limit $11 offset $12)\norder by {}, labels.id",
+            SELECT_TODOS, FILTER_TODOS, order_by, order_by
+        );
+        let limit = i64::try_from(page.limit)?;
+        let offset = i64::try_from(page.offset)?;
+        let items = self
+            .read(|db| {
+                bind_filter(
+                    sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql),
+                    filter.clone(),
+                )
+                .bind(limit)
+                .bind(offset)
+                .fetch_all(db)
+            })
+            .await?;
+
+        Ok(fold_listed(items))
+    }
+
+    #[instrument(skip_all)]
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        let sql = format!("select count(*) from todos{}", FILTER_TODOS);
+        let (count,) = self
+            .read(|db| bind_filter(sqlx::query_as::<_, (i64,)>(&sql), filter.clone()).fetch_one(db))
+            .await?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip_all)]
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let mut tx = self.db.begin().await?;
+
+        let old_todo = self.find_in(&mut tx, id).await?;
+        let status = payload.next_status(old_todo.status);
+        let icon = payload.next_icon(old_todo.icon);
+        let color = payload.next_color(old_todo.color);
+        let remind_at = payload.next_remind_at(old_todo.remind_at);
+        let estimate_minutes = payload.next_estimate_minutes(old_todo.estimate_minutes);
+        let due_at = payload.next_due_at(old_todo.due_at);
+        let description = payload.next_description(old_todo.description);
+        sqlx::query(
+            r#"
+update todos set text=$1, status=$2, tags=$3, icon=$4, color=$5, remind_at=$6, estimate_minutes=$7, due_at=$8, description=$9
+where id=$10
+returning *
+        "#,
+        )
+        .bind(payload.text.unwrap_or(old_todo.text))
+        .bind(status)
+        .bind(payload.tags.unwrap_or(old_todo.tags))
+        .bind(icon)
+        .bind(color)
+        .bind(remind_at)
+        .bind(estimate_minutes)
+        .bind(due_at)
+        .bind(description)
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| match e {
+            e if is_unique_violation(&e) => RepositoryError::Duplicate(id.key()),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        if let Some(labels) = payload.labels {
+            if let Err(e) = check_label_count(&labels, self.max_labels) {
+                tx.rollback().await?;
+                return Err(e);
+            }
+            // todo's label update
+            // 一度関連するレコードを削除
+            sqlx::query(
+                r#"
+    delete from todo_labels where todo_id=$1
+            "#,
+            )
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+            insert_labels(&mut tx, id, labels).await?;
+        };
+
+        insert_revision(&mut tx, id).await?;
+        tx.commit().await?;
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+delete from todo_revisions where todo_id=$1
+        "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+        // todo's label delete
+        sqlx::query(
+            r#"
+delete from todo_labels where todo_id=$1
+        "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        // todo delete
+        sqlx::query(
+            r#"
+delete from todos where id=$1
+        "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        let revisions = self
+            .read(|db| {
+                sqlx::query_as::<_, TodoRevision<I>>(
+                    r#"
+select row_number() over (order by id) as revision, text, status = 'done' as completed, status, labels, created_at
+from todo_revisions
+where todo_id=$1
+order by id
+                "#,
+                )
+                .bind(id)
+                .fetch_all(db)
+            })
+            .await?;
+        if revisions.is_empty() {
+            return Err(id.not_found().into());
+        }
+
+        Ok(revisions)
+    }
+
+    #[instrument(skip_all)]
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set remind_at=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(remind_at)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let sql = format!(
+            "{} where todos.remind_at is not null order by todos.remind_at, todos.id",
+            SELECT_TODOS
+        );
+        let items = self
+            .read(|db| sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql).fetch_all(db))
+            .await?;
+
+        Ok(fold_entities(items))
+    }
+
+    #[instrument(skip_all)]
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let mut tx = self.db.begin().await?;
+        let due = sqlx::query_as::<_, (I, DateTime<Utc>)>(
+            r#"
+with due as (
+    select id, remind_at from todos
+    where remind_at <= $1
+    for update skip locked
+)
+update todos set remind_at=null
+from due
+where todos.id = due.id
+returning todos.id, due.remind_at
+        "#,
+        )
+        .bind(now)
+        .fetch_all(&mut tx)
+        .await?;
+
+        let ids: Vec<I> = due.iter().map(|(id, _)| *id).collect();
+        let sql = format!(
+            "{} where todos.id = any($1) order by todos.id",
+            SELECT_TODOS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql)
+            .bind(ids)
+            .fetch_all(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        // 通知対象の時刻は解除前の値を返す
+        let mut todos = fold_entities(items);
+        for todo in todos.iter_mut() {
+            todo.remind_at = due
+                .iter()
+                .find(|(id, _)| *id == todo.id)
+                .map(|(_, remind_at)| *remind_at);
+        }
+        todos.sort_by_key(|todo| (todo.remind_at, todo.id));
+        Ok(todos)
+    }
+
+    #[instrument(skip_all)]
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set assignee_id=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(assignee.map(|user| user.id))
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set project_id=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(project_id)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set parent_id=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(parent_id)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set pinned=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(pinned)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        sqlx::query(
+            r#"
+update todos set snoozed_until=$1
+where id=$2
+returning *
+        "#,
+        )
+        .bind(until)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => id.not_found(),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        let todo = self.find_in(&self.db, id).await?;
+        Ok(todo)
+    }
+
+    #[instrument(skip_all)]
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let mut tx = self.db.begin().await?;
+        if !lock_label(&mut tx, label_id).await? {
+            tx.rollback().await?;
+            return Err(label_id.not_found().into());
+        }
+        // 同時に別のラベルが付けられて上限を超えないよう、対象のtodoをロックしてから数える
+        sqlx::query("select id from todos where id = any($1) order by id for update")
+            .bind(&todo_ids)
+            .execute(&mut tx)
+            .await?;
+        let full = sqlx::query_as::<_, (I,)>(
+            r#"
+select id from todos
+where id = any($2)
+  and not exists (select 1 from todo_labels where todo_id = todos.id and label_id = $1)
+  and (select count(*) from todo_labels where todo_id = todos.id) >= $3
+        "#,
+        )
+        .bind(label_id)
+        .bind(&todo_ids)
+        .bind(self.max_labels as i64)
+        .fetch_all(&mut tx)
+        .await?;
+        if !full.is_empty() {
+            tx.rollback().await?;
+            return Err(RepositoryError::TooManyLabels(self.max_labels).into());
+        }
+        let rows = sqlx::query_as::<_, (I,)>(
+            r#"
+with attached as (
+    insert into todo_labels (todo_id, label_id)
+    select id, $1 from todos
+    where id = any($2)
+      and not exists (select 1 from todo_labels where todo_id = todos.id and label_id = $1)
+    returning todo_id
+)
+update todos set updated_at = clock_timestamp()
+where id in (select todo_id from attached)
+returning id
+        "#,
+        )
+        .bind(label_id)
+        .bind(todo_ids)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let mut tx = self.db.begin().await?;
+        if !lock_label(&mut tx, label_id).await? {
+            tx.rollback().await?;
+            return Err(label_id.not_found().into());
+        }
+        let rows = sqlx::query_as::<_, (I,)>(
+            r#"
+with detached as (
+    delete from todo_labels
+    where label_id = $1 and todo_id = any($2)
+    returning todo_id
+)
+update todos set updated_at = clock_timestamp()
+where id in (select todo_id from detached)
+returning id
+        "#,
+        )
+        .bind(label_id)
+        .bind(todo_ids)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let sql = format!(
+            r#"{}
+where todos.parent_id = $1
+order by todos.id asc"#,
+            SELECT_TODOS
+        );
+        let items = self
+            .read(|db| {
+                sqlx::query_as::<_, TodoWithLabelFromRow<I>>(&sql)
+                    .bind(id)
+                    .fetch_all(db)
+            })
+            .await?;
+
+        Ok(fold_entities(items))
+    }
+
+    #[instrument(skip_all)]
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        // unionで重複を除くので、万一循環していても止まる
+        let ids = sqlx::query_scalar(
+            r#"
+with recursive descendants(id) as (
+    select id from todos where parent_id = $1
+    union
+    select todos.id from todos join descendants on todos.parent_id = descendants.id
+)
+select id from descendants order by id
+        "#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    #[instrument(skip_all)]
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+insert into todo_dependencies (blocker_id, blocked_id) values ($1, $2)
+on conflict do nothing
+        "#,
+        )
+        .bind(blocker_id)
+        .bind(blocked_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let result =
+            sqlx::query(r#"delete from todo_dependencies where blocker_id=$1 and blocked_id=$2"#)
+                .bind(blocker_id)
+                .bind(blocked_id)
+                .execute(&self.db)
+                .await?;
+        if result.rows_affected() == 0 {
+            return Err(blocked_id.not_found().into());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        let rows: Vec<(I, I)> = sqlx::query_as(
+            r#"
+select blocker_id, blocked_id from todo_dependencies
+where blocker_id=$1 or blocked_id=$1
+order by blocker_id, blocked_id
+        "#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut dependencies = TodoDependencies::default();
+        for (blocker_id, blocked_id) in rows {
+            if blocked_id == id {
+                dependencies.blocked_by.push(blocker_id);
+            } else {
+                dependencies.blocks.push(blocked_id);
+            }
+        }
+        Ok(dependencies)
+    }
+
+    #[instrument(skip_all)]
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        let ids = sqlx::query_scalar(
+            r#"
+with recursive dependents(id) as (
+    select blocked_id from todo_dependencies where blocker_id = $1
+    union
+    select d.blocked_id from todo_dependencies d join dependents on d.blocker_id = dependents.id
+)
+select id from dependents order by id
+        "#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    #[instrument(skip_all)]
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        let modified_at = self
+            .read(|db| {
+                sqlx::query_scalar(r#"select updated_at from todos where id=$1"#)
+                    .bind(id)
+                    .fetch_optional(db)
+            })
+            .await?
+            .ok_or_else(|| id.not_found())?;
+        Ok(modified_at)
+    }
+
+    #[instrument(skip_all)]
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        let modified_at = self
+            .read(|db| {
+                // todoの行の更新時刻に加え、行に残らない削除と共有の変更は変更ログから、
+                // 既定の並び順を変える設定の変更は設定の更新時刻から拾う
+                sqlx::query_scalar(
+                    r#"
+select coalesce(greatest(
+    (select max(updated_at) from todos),
+    (select max(changed_at) from todo_changes),
+    (select max(updated_at) from user_preferences),
+    (select max(snoozed_until) from todos where snoozed_until <= now())
+), 'epoch')
+                    "#,
+                )
+                .fetch_one(db)
+            })
+            .await?;
+        Ok(modified_at)
+    }
+
+    #[instrument(skip_all)]
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        let cycle_time = self
+            .read(|db| {
+                sqlx::query_as::<_, CycleTime>(
+                    r#"
+select count(*) as count,
+       avg(seconds) as average_seconds,
+       percentile_cont(0.5) within group (order by seconds) as p50_seconds,
+       percentile_cont(0.9) within group (order by seconds) as p90_seconds,
+       percentile_cont(0.95) within group (order by seconds) as p95_seconds
+from (select extract(epoch from completed_at - created_at)::float8 as seconds
+      from todos
+      where completed_at is not null
+        and ($1::timestamptz is null or completed_at >= $1)
+        and ($2::timestamptz is null or completed_at < $2)) as completed
+        "#,
+                )
+                .bind(range.from)
+                .bind(range.to)
+                .fetch_one(db)
+            })
+            .await?;
+        Ok(cycle_time)
+    }
+
+    #[instrument(skip_all)]
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        let share = sqlx::query_as::<_, Share<I>>(
+            r#"
+insert into todo_shares (todo_id, user_id, permission) values ($1, $2, $3)
+on conflict (todo_id, user_id) do update set permission = excluded.permission
+returning *
+        "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(permission)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.code().as_deref() == Some("23503") => id.not_found(),
+            e => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(share)
+    }
+
+    #[instrument(skip_all)]
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"delete from todo_shares where todo_id=$1 and user_id=$2"#)
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(id.not_found().into());
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        let shares = sqlx::query_as::<_, Share<I>>(
+            r#"select * from todo_shares where todo_id=$1 order by user_id"#,
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(shares)
+    }
+
+    // 前方一致を先に、続けて単語単位の類似度が高い順に並べる
+    // どちらの条件もtodos_text_trgm_idxで絞り込める
+    #[instrument(skip_all)]
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        let suggestions = self
+            .read(|db| {
+                sqlx::query_as::<_, TodoSuggestion<I>>(
+                    r#"
+select id, text from todos
+where (text ilike $1 || '%' or $2 operator(public.<%) text)
+  and ($3::boolean or owner_id is null or owner_id = $4::integer
+       or id in (select todo_id from todo_shares where user_id = $4::integer))
+order by text ilike $1 || '%' desc, public.word_similarity($2, text) desc, length(text), id
+limit $5
+            "#,
+                )
+                .bind(escape_like(query))
+                .bind(query)
+                .bind(visible_to == Visibility::All)
+                .bind(visible_to.user_id())
+                .bind(limit)
+                .fetch_all(db)
+            })
+            .await?;
+        Ok(suggestions)
+    }
+
+    // 同期トークンは、それより前のトランザクションがすべて終わっているトランザクションID
+    // 採番順とコミット順が前後しても取りこぼさないよう、前回と今回のトークンの間のトランザクションの変更を返す
+    // 複製の遅れでトークンが戻らないよう主で読む
+    #[instrument(skip_all)]
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        let sync_token: i64 =
+            sqlx::query_scalar(r#"select pg_snapshot_xmin(pg_current_snapshot())::text::bigint"#)
+                .fetch_one(&self.db)
+                .await?;
+        let changes = sqlx::query_as::<_, TodoChange<I>>(
+            r#"
+select todo_id, uuid, operation, owner_id from todo_changes
+where xact_id >= $1::bigint::text::xid8 and xact_id < $2::bigint::text::xid8
+  and ($3::boolean or owner_id is null or owner_id = $4::integer
+       or todo_id in (select todo_id from todo_shares where user_id = $4::integer))
+order by seq
+        "#,
+        )
+        .bind(since)
+        .bind(sync_token)
+        .bind(visible_to == Visibility::All)
+        .bind(visible_to.user_id())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(TodoChanges::collapse(changes, sync_token))
+    }
+}
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::labels::{CreateLabel, LabelRepository, LabelRepositoryForDb};
+    use crate::repositories::test_db::TestDatabase;
+    use crate::repositories::users::{UserRepository, UserRepositoryForDb};
+    use crate::repositories::{new_v7, IdType};
 
     #[test]
     fn fold_entities_test() {
-        let label_1 = Label {
-            id: 1,
-            name: String::from("label 1"),
+        let label_1 = Label::new(1, String::from("label 1"));
+        let label_2 = Label::new(2, String::from("label 2"));
+
+        let rows = vec![
+            TodoWithLabelFromRow {
+                id: 1,
+                uuid: Uuid::from_u128(1),
+                text: String::from("todo 1"),
+                status: TodoStatus::Backlog,
+                remind_at: None,
+                completed_at: None,
+                assignee_id: None,
+                assignee_name: None,
+                project_id: None,
+                tags: vec![],
+                icon: None,
+                color: None,
+                pinned: false,
+                snoozed_until: None,
+                estimate_minutes: None,
+                due_at: None,
+                description: None,
+                parent_id: None,
+                blocked: false,
+                owner_id: None,
+                updated_at: Utc::now(),
+                label_id: Some(label_1.id),
+                label_uuid: Some(label_1.uuid),
+                label_name: Some(label_1.name.clone()),
+                label_color: Some(label_1.color.clone()),
+                label_description: label_1.description.clone(),
+            },
+            TodoWithLabelFromRow {
+                id: 1,
+                uuid: Uuid::from_u128(1),
+                text: String::from("todo 1"),
+                status: TodoStatus::Backlog,
+                remind_at: None,
+                completed_at: None,
+                assignee_id: None,
+                assignee_name: None,
+                project_id: None,
+                tags: vec![],
+                icon: None,
+                color: None,
+                pinned: false,
+                snoozed_until: None,
+                estimate_minutes: None,
+                due_at: None,
+                description: None,
+                parent_id: None,
+                blocked: false,
+                owner_id: None,
+                updated_at: Utc::now(),
+                label_id: Some(label_2.id),
+                label_uuid: Some(label_2.uuid),
+                label_name: Some(label_2.name.clone()),
+                label_color: Some(label_2.color.clone()),
+                label_description: label_2.description.clone(),
+            },
+            TodoWithLabelFromRow {
+                id: 2,
+                uuid: Uuid::from_u128(2),
+                text: String::from("todo 2"),
+                status: TodoStatus::Backlog,
+                remind_at: None,
+                completed_at: None,
+                assignee_id: None,
+                assignee_name: None,
+                project_id: None,
+                tags: vec![],
+                icon: None,
+                color: None,
+                pinned: false,
+                snoozed_until: None,
+                estimate_minutes: None,
+                due_at: None,
+                description: None,
+                parent_id: None,
+                blocked: false,
+                owner_id: None,
+                updated_at: Utc::now(),
+                label_id: Some(label_1.id),
+                label_uuid: Some(label_1.uuid),
+                label_name: Some(label_1.name.clone()),
+                label_color: Some(label_1.color.clone()),
+                label_description: label_1.description.clone(),
+            },
+        ];
+        assert_eq!(
+            fold_entities(rows),
+            vec![
+                TodoEntity {
+                    id: 1,
+                    uuid: Uuid::from_u128(1),
+                    text: String::from("todo 1"),
+                    completed: false,
+                    status: TodoStatus::Backlog,
+                    labels: vec![label_1.clone(), label_2.clone()],
+                    remind_at: None,
+                    completed_at: None,
+                    assignee: None,
+                    project_id: None,
+                    tags: vec![],
+                    icon: None,
+                    color: None,
+                    pinned: false,
+                    snoozed_until: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                    parent_id: None,
+                    blocked: false,
+                    owner_id: None,
+                },
+                TodoEntity {
+                    id: 2,
+                    uuid: Uuid::from_u128(2),
+                    text: String::from("todo 2"),
+                    completed: false,
+                    status: TodoStatus::Backlog,
+                    labels: vec![label_1.clone()],
+                    remind_at: None,
+                    completed_at: None,
+                    assignee: None,
+                    project_id: None,
+                    tags: vec![],
+                    icon: None,
+                    color: None,
+                    pinned: false,
+                    snoozed_until: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                    parent_id: None,
+                    blocked: false,
+                    owner_id: None,
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn update_should_distinguish_null_from_absent() {
+        let update = |value| serde_json::from_value::<UpdateTodo>(value).unwrap();
+        let remind_at: DateTime<Utc> = "2030-01-01T09:00:00Z".parse().unwrap();
+        let current = Some(Utc::now());
+
+        let absent = update(serde_json::json!({}));
+        assert_eq!(absent.next_remind_at(current), current);
+        assert_eq!(
+            absent.next_icon(Some("🔥".to_string())).as_deref(),
+            Some("🔥")
+        );
+
+        let null = update(serde_json::json!({ "remind_at": null, "icon": null }));
+        assert_eq!(null.next_remind_at(current), None);
+        assert_eq!(null.next_icon(Some("🔥".to_string())), None);
+
+        let value = update(serde_json::json!({ "remind_at": remind_at }));
+        assert_eq!(value.next_remind_at(current), Some(remind_at));
+
+        // 値を指定した場合だけ検証する
+        assert!(null.validate().is_ok());
+        assert!(update(serde_json::json!({ "icon": "ab" }))
+            .validate()
+            .is_err());
+
+        // 省略したものはnullとして書き出さない
+        assert!(!serde_json::to_string(&absent)
+            .unwrap()
+            .contains("remind_at"));
+        assert_eq!(
+            serde_json::from_str::<UpdateTodo>(&serde_json::to_string(&null).unwrap()).unwrap(),
+            null
+        );
+    }
+
+    #[test]
+    fn fold_entities_should_group_rows_apart_from_each_other() {
+        let label_1 = Label::new(1, String::from("label 1"));
+        let label_2 = Label::new(2, String::from("label 2"));
+        let row = |id: i32, label: Option<&Label>| TodoWithLabelFromRow {
+            id,
+            uuid: Uuid::from_u128(id as u128),
+            text: format!("todo {}", id),
+            status: TodoStatus::Backlog,
+            remind_at: None,
+            completed_at: None,
+            assignee_id: None,
+            assignee_name: None,
+            project_id: None,
+            tags: vec![],
+            icon: None,
+            color: None,
+            pinned: false,
+            snoozed_until: None,
+            estimate_minutes: None,
+            due_at: None,
+            description: None,
+            parent_id: None,
+            blocked: false,
+            owner_id: None,
+            updated_at: Utc::now(),
+            label_id: label.map(|label| label.id),
+            label_uuid: label.map(|label| label.uuid),
+            label_name: label.map(|label| label.name.clone()),
+            label_color: label.map(|label| label.color.clone()),
+            label_description: label.and_then(|label| label.description.clone()),
+        };
+
+        let todos = fold_entities(vec![
+            row(2, Some(&label_1)),
+            row(1, Some(&label_2)),
+            row(3, None),
+            row(2, Some(&label_2)),
+            row(1, Some(&label_1)),
+        ]);
+
+        assert_eq!(
+            todos
+                .iter()
+                .map(|todo| (todo.id, todo.labels.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, vec![label_1.clone(), label_2.clone()]),
+                (1, vec![label_2, label_1]),
+                (3, vec![]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
+
+        let label_name = String::from("test label");
+        let optional_label = sqlx::query_as::<_, Label>(
+            r#"
+select * from labels where name = $1
+        "#,
+        )
+        .bind(label_name.clone())
+        .fetch_optional(&pool)
+        .await
+        .expect("Failed to prepare label data.");
+        let label_1 = if let Some(label) = optional_label {
+            label
+        } else {
+            let label = sqlx::query_as::<_, Label>(
+                r#"
+insert into labels ( name )
+values ( $1 )
+returning *
+            "#,
+            )
+            .bind(label_name)
+            .fetch_one(&pool)
+            .await
+            .expect("Failed to insert label data.");
+            label
+        };
+
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo_text = "[crud_scenario] text";
+
+        // create
+        let created = repository
+            .create(CreateTodo::new(todo_text.to_string(), vec![label_1.id]))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(created.text, todo_text);
+        assert!(!created.completed);
+        assert_eq!(*created.labels.first().unwrap(), label_1);
+
+        // find
+        let todo = repository
+            .find(created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(created, todo);
+        let todo = repository
+            .find_by_uuid(created.uuid)
+            .await
+            .expect("[find_by_uuid] returned Err");
+        assert_eq!(created, todo);
+        assert_eq!(
+            repository.resolve(Key::Uuid(created.uuid)).await.unwrap(),
+            created.id
+        );
+
+        // all
+        let todos = repository
+            .all(TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        let todo = todos.first().unwrap();
+        assert_eq!(created, *todo);
+        let todos = repository
+            .all(TodoFilter {
+                label_id: Some(label_1.id),
+                ..Default::default()
+            })
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(todos, vec![created.clone()]);
+
+        // update
+        let updated_text = "[crud_scenario] updated text";
+        let todo = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    text: Some(updated_text.to_string()),
+                    completed: Some(true),
+                    status: None,
+                    labels: Some(vec![]),
+                    tags: None,
+                    icon: None,
+                    color: None,
+                    remind_at: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(created.id, todo.id);
+        assert_eq!(todo.text, updated_text);
+        assert_eq!(todo.labels.len(), 0);
+        assert_eq!(todo.status, TodoStatus::Done);
+        assert!(todo.completed);
+
+        // history
+        let revisions = repository
+            .history(todo.id)
+            .await
+            .expect("[history] returned Err");
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].text, todo_text);
+        assert_eq!(revisions[0].labels.0, vec![label_1.clone()]);
+        assert_eq!(revisions[1].revision, 2);
+        assert_eq!(revisions[1].text, updated_text);
+        assert!(revisions[1].completed);
+        assert_eq!(revisions[1].status, TodoStatus::Done);
+
+        // status
+        let todo = repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::InProgress))
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.status, TodoStatus::InProgress);
+        assert!(!todo.completed);
+
+        // reminder
+        let remind_at = Utc::now() - chrono::Duration::minutes(1);
+        let todo = repository
+            .set_reminder(todo.id, Some(remind_at))
+            .await
+            .expect("[set_reminder] returned Err");
+        assert!(todo.remind_at.is_some());
+        let reminders = repository
+            .reminders()
+            .await
+            .expect("[reminders] returned Err");
+        assert!(reminders.iter().any(|reminder| reminder.id == todo.id));
+        let due = repository
+            .take_due_reminders(Utc::now())
+            .await
+            .expect("[take_due_reminders] returned Err");
+        assert!(due.iter().any(|reminder| reminder.id == todo.id));
+        let todo = repository.find(todo.id).await.unwrap();
+        assert_eq!(todo.remind_at, None);
+
+        // 更新では省略したら保ち、nullで外す
+        let remind_at: DateTime<Utc> = "2030-01-01T09:00:00Z".parse().unwrap();
+        let patch = |value| serde_json::from_value::<UpdateTodo>(value).unwrap();
+        let todo = repository
+            .update(
+                todo.id,
+                patch(serde_json::json!({ "remind_at": remind_at })),
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.remind_at, Some(remind_at));
+        let todo = repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::InProgress))
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.remind_at, Some(remind_at));
+        let todo = repository
+            .update(todo.id, patch(serde_json::json!({ "remind_at": null })))
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.remind_at, None);
+
+        // assign
+        let user = sqlx::query_as::<_, User>(
+            r#"
+insert into users (name) values ($1)
+on conflict (name) do update set name = excluded.name
+returning *
+        "#,
+        )
+        .bind("[crud_scenario] assignee")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to insert user data.");
+        let todo = repository
+            .assign(todo.id, Some(user.clone()))
+            .await
+            .expect("[assign] returned Err");
+        assert_eq!(todo.assignee, Some(user.clone()));
+        let todos = repository
+            .all(TodoFilter {
+                assignee_id: Some(user.id),
+                ..Default::default()
+            })
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().all(|t| t.assignee == Some(user.clone())));
+        assert!(todos.iter().any(|t| t.id == todo.id));
+        let todo = repository
+            .assign(todo.id, None)
+            .await
+            .expect("[assign] returned Err");
+        assert_eq!(todo.assignee, None);
+
+        // move to project
+        let project_id: i32 =
+            sqlx::query_scalar(r#"insert into projects (name) values ($1) returning id"#)
+                .bind("[crud_scenario] project")
+                .fetch_one(&pool)
+                .await
+                .expect("Failed to insert project data.");
+        let todo = repository
+            .move_to_project(todo.id, Some(project_id))
+            .await
+            .expect("[move_to_project] returned Err");
+        assert_eq!(todo.project_id, Some(project_id));
+        let todos = repository
+            .all(TodoFilter {
+                project_id: Some(project_id),
+                ..Default::default()
+            })
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(vec![todo.clone()], todos);
+
+        // modified at
+        let modified_at = repository
+            .modified_at(todo.id)
+            .await
+            .expect("[modified_at] returned Err");
+        let list_modified_at = repository
+            .list_modified_at()
+            .await
+            .expect("[list_modified_at] returned Err");
+        assert!(list_modified_at >= modified_at);
+
+        // delete
+        repository
+            .delete(todo.id)
+            .await
+            .expect("[delete] returned Err");
+        // 削除しても一覧の変更時刻は進む
+        assert!(repository.list_modified_at().await.unwrap() > list_modified_at);
+        assert!(repository.modified_at(todo.id).await.is_err());
+        let res = repository.find(created.id).await;
+        assert!(res.is_err());
+
+        let todo_rows = sqlx::query(
+            r#"
+select * from todos where id=$1
+        "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("[delete] todo_labels fetch error");
+        assert_eq!(todo_rows.len(), 0);
+
+        let rows = sqlx::query(
+            r#"
+select * from todo_labels where todo_id=$1
+        "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("[delete] todo_labels fetch error");
+        assert_eq!(rows.len(), 0);
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn crud_scenario_with_uuid_keys() {
+        let db = TestDatabase::with_id_type(IdType::Uuid).await;
+        let labels = LabelRepositoryForDb::<Uuid>::with_ids(db.pool.clone());
+        let repository = TodoRepositoryForDb::<Uuid>::with_ids(db.pool.clone());
+
+        // ラベルもtodoも主キーはUUIDで、uuid列と同じ値になる
+        let label = labels
+            .create(CreateLabel::new("uuid".to_string()))
+            .await
+            .expect("[create label] returned Err");
+        assert_eq!(label.id, label.uuid);
+        let parent = repository
+            .create(CreateTodo::new("parent".to_string(), vec![label.id]))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(parent.id, parent.uuid);
+        assert_eq!(parent.labels, vec![label.clone()]);
+        assert_eq!(repository.find(parent.id).await.unwrap(), parent);
+        assert_eq!(
+            repository.resolve(Key::Id(parent.id)).await.unwrap(),
+            parent.id
+        );
+
+        // 同期でクライアントが振ったUUIDは、そのままidになる
+        let uuid = new_v7();
+        let child = repository
+            .create(CreateTodo::synced(
+                uuid,
+                "child".to_string(),
+                vec![],
+                vec![],
+            ))
+            .await
+            .expect("[create synced] returned Err");
+        assert_eq!(child.id, uuid);
+        let child = repository
+            .set_parent(child.id, Some(parent.id))
+            .await
+            .expect("[set_parent] returned Err");
+        assert_eq!(child.parent_id, Some(parent.id));
+        repository
+            .add_dependency(parent.id, child.id)
+            .await
+            .expect("[add_dependency] returned Err");
+        assert_eq!(
+            repository.dependents(parent.id).await.unwrap(),
+            vec![child.id]
+        );
+
+        // ラベルの付け外しも変更ログにUUIDのidで残る
+        let changes = repository.changes(0, Visibility::All).await.unwrap();
+        repository
+            .detach_label(label.id, vec![parent.id])
+            .await
+            .expect("[detach_label] returned Err");
+        let detached = repository
+            .changes(changes.sync_token, Visibility::All)
+            .await
+            .unwrap();
+        assert_eq!(
+            detached.updated,
+            vec![ChangedTodo {
+                id: parent.id,
+                uuid: parent.uuid,
+            }]
+        );
+
+        repository
+            .delete(child.id)
+            .await
+            .expect("[delete] returned Err");
+        let error = repository.find(child.id).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFoundUuid(uuid)) if *uuid == child.id
+        ));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_open_todos() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let first = repository
+            .create(CreateTodo::new("duplicated".to_string(), vec![]).deduplicated())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(
+            repository
+                .find_by_text("duplicated", Visibility::All)
+                .await
+                .unwrap(),
+            Some(first.clone())
+        );
+
+        // 部分一意インデックスで弾かれ、既存のtodoのidを返す
+        let err = repository
+            .create(CreateTodo::new("duplicated".to_string(), vec![]).deduplicated())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == first.id.key()
+        ));
+        // 重複を許すモードでは作成できる
+        repository
+            .create(CreateTodo::new("duplicated".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+
+        // 完了したtodoとは重複しない
+        repository
+            .update(first.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        repository
+            .create(CreateTodo::new("duplicated".to_string(), vec![]).deduplicated())
+            .await
+            .expect("[create] returned Err");
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_open_todos_per_owner() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let users = UserRepositoryForDb::new(db.pool.clone());
+        let alice = users.create("alice".to_string()).await.unwrap();
+        let bob = users.create("bob".to_string()).await.unwrap();
+
+        let create = |owner_id: i32| {
+            repository.create(
+                CreateTodo::new("milk".to_string(), vec![])
+                    .owned_by(Some(owner_id))
+                    .deduplicated(),
+            )
+        };
+        let alices = create(alice.id).await.expect("[create] returned Err");
+        // 他のユーザーのtodoとは重複しない
+        let bobs = create(bob.id).await.expect("[create] returned Err");
+        let err = create(bob.id).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == bobs.id.key()
+        ));
+
+        // 見えないtodoは探さない
+        for (visible_to, expected) in [
+            (Visibility::User(alice.id), Some(alices.id)),
+            (Visibility::User(bob.id), Some(bobs.id)),
+            (Visibility::Unowned, None),
+        ] {
+            let found = repository.find_by_text("milk", visible_to).await.unwrap();
+            assert_eq!(found.map(|todo| todo.id), expected);
+        }
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_record_completed_at_and_cycle_time() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let todo = repository
+            .create(CreateTodo::new("cycle".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(todo.completed_at, None);
+        assert_eq!(
+            repository
+                .cycle_time(CompletedRange::default())
+                .await
+                .unwrap(),
+            CycleTime::default()
+        );
+
+        // 完了のまま更新しても最初に完了した日時を残す
+        let done = repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        let completed_at = done.completed_at.expect("completed_at is not recorded");
+        let updated = repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        assert_eq!(updated.completed_at, Some(completed_at));
+
+        let cycle_time = repository
+            .cycle_time(CompletedRange::default())
+            .await
+            .unwrap();
+        assert_eq!(cycle_time.count, 1);
+        assert!(cycle_time.average_seconds.unwrap() >= 0.0);
+        assert_eq!(cycle_time.p50_seconds, cycle_time.average_seconds);
+        let range = CompletedRange {
+            from: Some(completed_at + chrono::Duration::seconds(1)),
+            to: None,
+        };
+        assert_eq!(repository.cycle_time(range).await.unwrap().count, 0);
+
+        // 完了以外に戻すと消える
+        let reopened = repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::InProgress))
+            .await
+            .unwrap();
+        assert_eq!(reopened.completed_at, None);
+        assert_eq!(
+            repository
+                .cycle_time(CompletedRange::default())
+                .await
+                .unwrap()
+                .count,
+            0
+        );
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_tag() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let tagged = repository
+            .create(CreateTodo {
+                tags: vec!["home".to_string(), "urgent".to_string()],
+                ..CreateTodo::new("tagged".to_string(), vec![])
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(tagged.tags, vec!["home", "urgent"]);
+        repository
+            .create(CreateTodo::new("untagged".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+
+        let filter = |tag: &str| TodoFilter {
+            tag: Some(tag.to_string()),
+            ..Default::default()
+        };
+        let todos = repository.all(filter("urgent")).await.unwrap();
+        assert_eq!(todos, vec![tagged.clone()]);
+
+        // tagsを省略した更新では既存のタグを保つ
+        let todo = repository
+            .update(tagged.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        assert_eq!(todo.tags, tagged.tags);
+        let todo = repository
+            .update(
+                tagged.id,
+                UpdateTodo {
+                    tags: Some(vec!["work".to_string()]),
+                    ..UpdateTodo::status(TodoStatus::Done)
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(todo.tags, vec!["work"]);
+        assert!(repository.all(filter("urgent")).await.unwrap().is_empty());
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_suggest_todos_by_prefix_and_similarity() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let users = UserRepositoryForDb::new(db.pool.clone());
+        let alice = users.create("alice".to_string()).await.unwrap();
+
+        let mut created = vec![];
+        for text in [
+            "buy groceries",
+            "call the bank",
+            "go grocery shopping",
+            "100% done",
+        ] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("[create] returned Err");
+            created.push(todo);
+        }
+        repository
+            .create(
+                CreateTodo::new("groceries for alice".to_string(), vec![]).owned_by(Some(alice.id)),
+            )
+            .await
+            .expect("[create] returned Err");
+
+        // 前方一致が先に、本文の途中の単語に似ているものが後に来る
+        let suggestions = repository
+            .suggest("grocer", Visibility::Unowned, 10)
+            .await
+            .expect("[suggest] returned Err");
+        let texts: Vec<&str> = suggestions.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, vec!["buy groceries", "go grocery shopping"]);
+        let suggestions = repository
+            .suggest("go gro", Visibility::Unowned, 10)
+            .await
+            .unwrap();
+        assert_eq!(suggestions[0].id, created[2].id);
+
+        // 打ち間違いも拾う
+        let suggestions = repository
+            .suggest("grocerys", Visibility::Unowned, 10)
+            .await
+            .unwrap();
+        let ids: Vec<i32> = suggestions.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![created[2].id, created[0].id]);
+
+        // 所有者には自分のtodoも出し、件数を絞る
+        let suggestions = repository
+            .suggest("grocer", Visibility::User(alice.id), 10)
+            .await
+            .unwrap();
+        assert_eq!(suggestions.len(), 3);
+        let suggestions = repository
+            .suggest("grocer", Visibility::User(alice.id), 1)
+            .await
+            .unwrap();
+        assert_eq!(suggestions[0].text, "groceries for alice");
+
+        // LIKEのワイルドカードは文字として扱う
+        let suggestions = repository
+            .suggest("100%", Visibility::Unowned, 10)
+            .await
+            .unwrap();
+        assert_eq!(suggestions[0].id, created[3].id);
+        assert!(repository
+            .suggest("%", Visibility::Unowned, 10)
+            .await
+            .unwrap()
+            .iter()
+            .all(|s| s.text.contains('%')));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_count_todos_with_list_filter() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let label =
+            sqlx::query_as::<_, Label>(r#"insert into labels (name) values ($1) returning *"#)
+                .bind("badge")
+                .fetch_one(&db.pool)
+                .await
+                .expect("[insert label] returned Err");
+
+        for (text, labels) in [
+            ("first", vec![label.id]),
+            ("second", vec![label.id]),
+            ("third", vec![]),
+        ] {
+            repository
+                .create(CreateTodo::new(text.to_string(), labels))
+                .await
+                .expect("[create] returned Err");
+        }
+        repository
+            .update(1, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+
+        let count = |label_id: Option<i32>, completed: Option<bool>| TodoFilter {
+            label_id,
+            completed,
+            ..Default::default()
+        };
+        for filter in [
+            count(None, None),
+            count(Some(label.id), None),
+            count(Some(label.id), Some(false)),
+            count(None, Some(true)),
+        ] {
+            let expected = repository.all(filter.clone()).await.unwrap().len() as i64;
+            assert_eq!(
+                repository
+                    .count(filter.clone())
+                    .await
+                    .expect("[count] returned Err"),
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+        assert_eq!(
+            repository
+                .count(count(Some(label.id), Some(false)))
+                .await
+                .unwrap(),
+            1
+        );
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_page_todos_in_list_order() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let mut label_ids = vec![];
+        for name in ["first", "second"] {
+            let label =
+                sqlx::query_as::<_, Label>(r#"insert into labels (name) values ($1) returning *"#)
+                    .bind(name)
+                    .fetch_one(&db.pool)
+                    .await
+                    .expect("[insert label] returned Err");
+            label_ids.push(label.id);
+        }
+        for i in 1..=5 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i), label_ids.clone()))
+                .await
+                .expect("[create] returned Err");
+        }
+        repository.pin(2, true).await.unwrap();
+
+        for sort in [TodoSort::Newest, TodoSort::Oldest, TodoSort::Due] {
+            let mut expected = repository.all(TodoFilter::default()).await.unwrap();
+            sort.sort(&mut expected);
+            // ラベルの行の数ではなく、todoの件数で切り出す
+            let page = TodoPage {
+                sort,
+                offset: 1,
+                limit: 3,
+            };
+            let listed = repository
+                .page(TodoFilter::default(), page)
+                .await
+                .expect("[page] returned Err");
+            assert_eq!(
+                listed
+                    .iter()
+                    .map(|listed| listed.todo.clone())
+                    .collect::<Vec<_>>(),
+                expected[1..4].to_vec(),
+                "{:?}",
+                sort
+            );
+            for listed in listed {
+                assert_eq!(
+                    listed.updated_at,
+                    repository.modified_at(listed.todo.id).await.unwrap()
+                );
+            }
+        }
+        let page = TodoPage {
+            sort: TodoSort::Newest,
+            offset: 5,
+            limit: 3,
+        };
+        assert!(repository
+            .page(TodoFilter::default(), page)
+            .await
+            .unwrap()
+            .is_empty());
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_create_todo_with_client_uuid() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let uuid = Uuid::from_u128(42);
+        let create = || CreateTodo::synced(uuid, "offline".to_string(), vec![], vec![]);
+
+        let created = repository
+            .create(create())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(created.uuid, uuid);
+
+        let e = repository
+            .create(create())
+            .await
+            .expect_err("[create] with the same uuid returned Ok");
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == created.id.key()
+        ));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_labels() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let is_unknown_label = |e: anyhow::Error| {
+            matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::NotFound(12345))
+            )
+        };
+
+        let e = repository
+            .create(CreateTodo::new("unknown label".to_string(), vec![12345]))
+            .await
+            .expect_err("[create] with an unknown label returned Ok");
+        assert!(is_unknown_label(e));
+        assert_eq!(
+            repository
+                .find_by_text("unknown label", Visibility::All)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let created = repository
+            .create(CreateTodo::new("known label".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let e = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some("changed".to_string()),
+                    completed: None,
+                    status: None,
+                    labels: Some(vec![12345]),
+                    tags: None,
+                    icon: None,
+                    color: None,
+                    remind_at: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                },
+            )
+            .await
+            .expect_err("[update] with an unknown label returned Ok");
+        assert!(is_unknown_label(e));
+        let found = repository.find(created.id).await.unwrap();
+        assert_eq!(found.text, "known label");
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_read_from_primary_while_replica_is_down() {
+        let db = TestDatabase::new().await;
+        let replica = Replica::connect_lazy(
+            "postgres://admin@127.0.0.1:1/todos"
+                .parse()
+                .expect("invalid replica url"),
+        );
+        let repository = TodoRepositoryForDb::new(db.pool.clone()).with_replica(replica);
+        let created = repository
+            .create(CreateTodo::new("read me".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+
+        // 最初の読み込みで複製に繋がらないことが分かり、以降は主だけで読む
+        for _ in 0..2 {
+            assert_eq!(
+                repository
+                    .find(created.id)
+                    .await
+                    .expect("[find] returned Err"),
+                created
+            );
+        }
+        assert_eq!(
+            repository
+                .all(TodoFilter::default())
+                .await
+                .expect("[all] returned Err"),
+            vec![created]
+        );
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_stream_todos_like_all() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let mut label_ids = vec![];
+        for name in ["home", "work"] {
+            let label =
+                sqlx::query_as::<_, Label>(r#"insert into labels (name) values ($1) returning *"#)
+                    .bind(name)
+                    .fetch_one(&db.pool)
+                    .await
+                    .expect("[insert label] returned Err");
+            label_ids.push(label.id);
+        }
+        for i in 0..(STREAM_BUFFER * 2) {
+            let labels = match i % 3 {
+                0 => vec![],
+                1 => vec![label_ids[0]],
+                _ => label_ids.clone(),
+            };
+            repository
+                .create(CreateTodo::new(format!("todo {}", i), labels))
+                .await
+                .expect("[create] returned Err");
+        }
+
+        let streamed: Vec<TodoEntity> = repository
+            .stream(TodoFilter::default())
+            .await
+            .expect("[stream] returned Err")
+            .try_collect()
+            .await
+            .expect("[stream] yielded Err");
+        assert_eq!(streamed.len(), STREAM_BUFFER * 2);
+        assert_eq!(
+            streamed,
+            repository.all(TodoFilter::default()).await.unwrap()
+        );
+
+        let filter = TodoFilter {
+            label_id: Some(label_ids[1]),
+            ..Default::default()
         };
-        let label_2 = Label {
-            id: 2,
-            name: String::from("label 2"),
+        let streamed: Vec<TodoEntity> = repository
+            .stream(filter.clone())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(streamed, repository.all(filter).await.unwrap());
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_limit_todos_to_owner_and_shared_users() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let users = UserRepositoryForDb::new(db.pool.clone());
+        let alice = users.create("alice".to_string()).await.unwrap();
+        let bob = users.create("bob".to_string()).await.unwrap();
+
+        let private = repository
+            .create(CreateTodo::new("private".to_string(), vec![]).owned_by(Some(alice.id)))
+            .await
+            .expect("[create] returned Err");
+        let public = repository
+            .create(CreateTodo::new("public".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+
+        let visible_to = |visible_to: Visibility| TodoFilter {
+            visible_to,
+            ..Default::default()
         };
+        let todos = repository.all(visible_to(Visibility::User(bob.id))).await;
+        assert_eq!(todos.unwrap(), vec![public.clone()]);
+        let todos = repository.all(visible_to(Visibility::User(alice.id))).await;
+        assert_eq!(todos.unwrap(), vec![public.clone(), private.clone()]);
 
-        let rows = vec![
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_2.id),
-                label_name: Some(label_2.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 2,
-                text: String::from("todo 2"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-        ];
-        assert_eq!(
-            fold_entities(rows),
-            vec![
-                TodoEntity {
-                    id: 1,
-                    text: String::from("todo 1"),
-                    completed: false,
-                    labels: vec![label_1.clone(), label_2.clone()]
-                },
-                TodoEntity {
-                    id: 2,
-                    text: String::from("todo 2"),
-                    completed: false,
-                    labels: vec![label_1.clone()]
-                }
-            ]
-        )
+        let share = repository
+            .share(private.id, bob.id, Permission::Read)
+            .await
+            .unwrap();
+        assert_eq!(repository.shares(private.id).await.unwrap(), vec![share]);
+        let todos = repository
+            .all(TodoFilter {
+                shared_with: Some(bob.id),
+                ..visible_to(Visibility::User(bob.id))
+            })
+            .await;
+        assert_eq!(todos.unwrap(), vec![private.clone()]);
+
+        // 存在しないユーザーには共有できない
+        let res = repository.share(private.id, 999, Permission::Read).await;
+        assert!(res.is_err());
+
+        // 共有を外すとtodoの行は変わらないが、見えるtodoが変わるので一覧の変更時刻を進める
+        let shared_at = repository.list_modified_at().await.unwrap();
+        repository.unshare(private.id, bob.id).await.unwrap();
+        assert!(repository.unshare(private.id, bob.id).await.is_err());
+        assert!(repository.list_modified_at().await.unwrap() > shared_at);
+        let todos = repository.all(visible_to(Visibility::User(bob.id))).await;
+        assert_eq!(todos.unwrap(), vec![public]);
+
+        db.teardown().await;
+    }
+
+    // ほかのテストのトランザクションが終わるまで同期トークンは進まないため、直前の書き込みを含むまで待つ
+    async fn changes_after_writes(
+        repository: &TodoRepositoryForDb,
+        since: i64,
+        visible_to: Visibility,
+    ) -> TodoChanges {
+        let written: i64 = sqlx::query_scalar(r#"select pg_current_xact_id()::text::bigint"#)
+            .fetch_one(&repository.db)
+            .await
+            .unwrap();
+        loop {
+            let changes = repository.changes(since, visible_to).await.unwrap();
+            if changes.sync_token > written {
+                return changes;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
     }
 
     #[tokio::test]
-    async fn crud_scenario() {
-        dotenv().ok();
+    async fn should_return_visible_changes_since_sync_token() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let users = UserRepositoryForDb::new(db.pool.clone());
+        let alice = users.create("alice".to_string()).await.unwrap();
+        let bob = users.create("bob".to_string()).await.unwrap();
+        let since = changes_after_writes(&repository, 0, Visibility::All)
+            .await
+            .sync_token;
 
-        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-        let pool = PgPool::connect(database_url)
+        let private = repository
+            .create(CreateTodo::new("private".to_string(), vec![]).owned_by(Some(alice.id)))
+            .await
+            .unwrap();
+        let public = repository
+            .create(CreateTodo::new("public".to_string(), vec![]))
             .await
-            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+            .unwrap();
+        let changed = |todo: &TodoEntity| ChangedTodo {
+            id: todo.id,
+            uuid: todo.uuid,
+        };
+        let changes = changes_after_writes(&repository, since, Visibility::User(bob.id)).await;
+        assert_eq!(changes.created, vec![changed(&public)]);
+        let changes = changes_after_writes(&repository, since, Visibility::User(alice.id)).await;
+        assert_eq!(changes.created, vec![changed(&private), changed(&public)]);
 
-        let label_name = String::from("test label");
-        let optional_label = sqlx::query_as::<_, Label>(
-            r#"
-select * from labels where name = $1
-        "#,
-        )
-        .bind(label_name.clone())
-        .fetch_optional(&pool)
-        .await
-        .expect("Failed to prepare label data.");
-        let label_1 = if let Some(label) = optional_label {
-            label
-        } else {
-            let label = sqlx::query_as::<_, Label>(
-                r#"
-insert into labels ( name )
-values ( $1 )
-returning *
-            "#,
-            )
-            .bind(label_name)
-            .fetch_one(&pool)
+        repository
+            .update(public.id, UpdateTodo::status(TodoStatus::InProgress))
             .await
-            .expect("Failed to insert label data.");
-            label
+            .unwrap();
+        repository.delete(private.id).await.unwrap();
+        let delta =
+            changes_after_writes(&repository, changes.sync_token, Visibility::User(alice.id)).await;
+        assert!(delta.created.is_empty());
+        assert_eq!(delta.updated, vec![changed(&public)]);
+        assert_eq!(delta.deleted, vec![changed(&private)]);
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_build_todo_hierarchy() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let create = |text: &str, parent_id: Option<i32>| {
+            repository.create(CreateTodo {
+                parent_id,
+                ..CreateTodo::new(text.to_string(), vec![])
+            })
         };
+        let root = create("root", None).await.unwrap();
+        let child = create("child", Some(root.id)).await.unwrap();
+        let grandchild = create("grandchild", Some(child.id)).await.unwrap();
+        let other = create("other", None).await.unwrap();
+        assert_eq!(child.parent_id, Some(root.id));
 
-        let repository = TodoRepositoryForDb::new(pool.clone());
-        let todo_text = "[crud_scenario] text";
+        let children = repository.children(root.id).await.unwrap();
+        assert_eq!(children, vec![child.clone()]);
+        let descendants = repository.descendants(root.id).await.unwrap();
+        assert_eq!(descendants, vec![child.id, grandchild.id]);
 
-        // create
-        let created = repository
-            .create(CreateTodo::new(todo_text.to_string(), vec![label_1.id]))
+        // 付け替えると子孫ごと移る
+        let child = repository
+            .set_parent(child.id, Some(other.id))
+            .await
+            .unwrap();
+        assert_eq!(child.parent_id, Some(other.id));
+        assert!(repository.descendants(root.id).await.unwrap().is_empty());
+        assert_eq!(
+            repository.descendants(other.id).await.unwrap(),
+            vec![child.id, grandchild.id]
+        );
+
+        // 親を削除すると子は最上位に戻る
+        repository.delete(child.id).await.unwrap();
+        let grandchild = repository.find(grandchild.id).await.unwrap();
+        assert_eq!(grandchild.parent_id, None);
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_track_todo_dependencies() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let mut ids = vec![];
+        for text in ["design", "build", "ship"] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .unwrap();
+            ids.push(todo.id);
+        }
+        let (design, build, ship) = (ids[0], ids[1], ids[2]);
+        repository.add_dependency(design, build).await.unwrap();
+        repository.add_dependency(build, ship).await.unwrap();
+
+        assert!(repository.find(build).await.unwrap().blocked);
+        assert!(!repository.find(design).await.unwrap().blocked);
+        assert_eq!(
+            repository.dependencies(build).await.unwrap(),
+            TodoDependencies {
+                blocked_by: vec![design],
+                blocks: vec![ship],
+            }
+        );
+        assert_eq!(
+            repository.dependents(design).await.unwrap(),
+            vec![build, ship]
+        );
+
+        // ブロックしているtodoを完了すると、ブロックされていた側の更新時刻も進む
+        let modified_at = repository.modified_at(build).await.unwrap();
+        repository
+            .update(design, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        assert!(!repository.find(build).await.unwrap().blocked);
+        assert!(repository.modified_at(build).await.unwrap() > modified_at);
+
+        repository.remove_dependency(build, ship).await.unwrap();
+        assert!(!repository.find(ship).await.unwrap().blocked);
+        assert!(repository.remove_dependency(build, ship).await.is_err());
+
+        // todoを削除すると依存関係も消える
+        repository.delete(build).await.unwrap();
+        assert_eq!(
+            repository.dependencies(design).await.unwrap(),
+            TodoDependencies::default()
+        );
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_color() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+
+        let colored = repository
+            .create(CreateTodo {
+                icon: Some("🔥".to_string()),
+                color: Some("#1E90FF".to_string()),
+                ..CreateTodo::new("colored".to_string(), vec![])
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(colored.icon.as_deref(), Some("🔥"));
+        assert_eq!(colored.color.as_deref(), Some("#1e90ff"));
+        repository
+            .create(CreateTodo::new("plain".to_string(), vec![]))
             .await
             .expect("[create] returned Err");
-        assert_eq!(created.text, todo_text);
-        assert!(!created.completed);
-        assert_eq!(*created.labels.first().unwrap(), label_1);
 
-        // find
+        let filter = TodoFilter {
+            color: Some("#1e90ff".to_string()),
+            ..Default::default()
+        };
+        let todos = repository.all(filter.clone()).await.unwrap();
+        assert_eq!(todos, vec![colored.clone()]);
+        assert_eq!(repository.count(filter.clone()).await.unwrap(), 1);
+
+        // 省略すると保ち、nullで外す
         let todo = repository
-            .find(created.id)
+            .update(colored.id, UpdateTodo::status(TodoStatus::Done))
             .await
-            .expect("[find] returned Err");
-        assert_eq!(created, todo);
+            .unwrap();
+        assert_eq!(
+            (todo.icon.as_deref(), todo.color.as_deref()),
+            (Some("🔥"), Some("#1e90ff"))
+        );
+        let todo = repository
+            .update(
+                colored.id,
+                UpdateTodo {
+                    icon: Some(Some("✅".to_string())),
+                    color: Some(None),
+                    ..UpdateTodo::status(TodoStatus::Done)
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!((todo.icon.as_deref(), todo.color), (Some("✅"), None));
+        assert!(repository.all(filter).await.unwrap().is_empty());
 
-        // all
-        let todos = repository.all().await.expect("[all] returned Err");
-        let todo = todos.first().unwrap();
-        assert_eq!(created, *todo);
+        db.teardown().await;
+    }
 
-        // update
-        let updated_text = "[crud_scenario] updated text";
-        let todo = repository
+    #[tokio::test]
+    async fn should_list_pinned_todos_first() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let mut ids = vec![];
+        for text in ["first", "second", "third"] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let texts =
+            |todos: Vec<TodoEntity>| todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>();
+
+        let pinned = repository.pin(ids[0], true).await.unwrap();
+        assert!(pinned.pinned);
+        let todos = repository.all(TodoFilter::default()).await.unwrap();
+        assert_eq!(texts(todos), vec!["first", "third", "second"]);
+        let todos: Vec<TodoEntity> = repository
+            .stream(TodoFilter::default())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(texts(todos), vec!["first", "third", "second"]);
+
+        repository.pin(ids[0], false).await.unwrap();
+        let todos = repository.all(TodoFilter::default()).await.unwrap();
+        assert_eq!(texts(todos), vec!["third", "second", "first"]);
+
+        let e = repository
+            .pin(ids[2] + 1, true)
+            .await
+            .expect_err("[pin] of a missing todo returned Ok");
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_attach_and_detach_label_in_bulk() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let label =
+            sqlx::query_as::<_, Label>(r#"insert into labels (name) values ($1) returning *"#)
+                .bind("bulk")
+                .fetch_one(&db.pool)
+                .await
+                .expect("[insert label] returned Err");
+        let labelled = repository
+            .create(CreateTodo::new("labelled".to_string(), vec![label.id]))
+            .await
+            .expect("[create] returned Err");
+        let unlabelled = repository
+            .create(CreateTodo::new("unlabelled".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let missing = unlabelled.id + 1;
+
+        // 既に付いているものと存在しないものは飛ばす
+        let ids = repository
+            .attach_label(
+                label.id,
+                vec![labelled.id, unlabelled.id, unlabelled.id, missing],
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![unlabelled.id]);
+        let todo = repository.find(unlabelled.id).await.unwrap();
+        assert_eq!(todo.labels, vec![label.clone()]);
+
+        let mut ids = repository
+            .detach_label(label.id, vec![labelled.id, unlabelled.id, missing])
+            .await
+            .unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![labelled.id, unlabelled.id]);
+        let todo = repository.find(labelled.id).await.unwrap();
+        assert_eq!(todo.labels, vec![]);
+
+        let e = repository
+            .attach_label(label.id + 1, vec![labelled.id])
+            .await
+            .expect_err("[attach_label] with an unknown label returned Ok");
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(id)) if *id == label.id + 1
+        ));
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_too_many_labels() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone()).with_max_labels(2);
+        let mut label_ids = vec![];
+        for name in ["first", "second", "third"] {
+            let label =
+                sqlx::query_as::<_, Label>(r#"insert into labels (name) values ($1) returning *"#)
+                    .bind(name)
+                    .fetch_one(&db.pool)
+                    .await
+                    .expect("[insert label] returned Err");
+            label_ids.push(label.id);
+        }
+        let is_too_many = |e: anyhow::Error| {
+            matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::TooManyLabels(2))
+            )
+        };
+
+        let e = repository
+            .create(CreateTodo::new(
+                "three labels".to_string(),
+                label_ids.clone(),
+            ))
+            .await
+            .expect_err("[create] with too many labels returned Ok");
+        assert!(is_too_many(e));
+        assert_eq!(
+            repository
+                .find_by_text("three labels", Visibility::All)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let full = repository
+            .create(CreateTodo::new(
+                "two labels".to_string(),
+                label_ids[..2].to_vec(),
+            ))
+            .await
+            .expect("[create] returned Err");
+        let e = repository
             .update(
-                todo.id,
+                full.id,
                 UpdateTodo {
-                    text: Some(updated_text.to_string()),
-                    completed: Some(true),
-                    labels: Some(vec![]),
+                    labels: Some(label_ids.clone()),
+                    ..UpdateTodo::status(TodoStatus::InProgress)
                 },
             )
             .await
-            .expect("[update] returned Err");
-        assert_eq!(created.id, todo.id);
-        assert_eq!(todo.text, updated_text);
-        assert_eq!(todo.labels.len(), 0);
+            .expect_err("[update] with too many labels returned Ok");
+        assert!(is_too_many(e));
+        let found = repository.find(full.id).await.unwrap();
+        assert_eq!(found.status, TodoStatus::Backlog);
+        assert_eq!(found.labels.len(), 2);
+
+        // 1つでも上限に達したtodoがあれば、どのtodoにも付けない
+        let empty = repository
+            .create(CreateTodo::new("no labels".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let e = repository
+            .attach_label(label_ids[2], vec![empty.id, full.id])
+            .await
+            .expect_err("[attach_label] beyond the limit returned Ok");
+        assert!(is_too_many(e));
+        assert_eq!(repository.find(empty.id).await.unwrap().labels, vec![]);
+
+        // 既に付いているラベルは上限に達していても付け直せる
+        let ids = repository
+            .attach_label(label_ids[0], vec![empty.id, full.id])
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![empty.id]);
+
+        db.teardown().await;
+    }
+
+    #[tokio::test]
+    async fn should_filter_snoozed_todos() {
+        let db = TestDatabase::new().await;
+        let repository = TodoRepositoryForDb::new(db.pool.clone());
+        let mut ids = vec![];
+        for text in ["snoozed", "elapsed", "awake"] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let until = Utc::now() + chrono::Duration::hours(1);
+        let todo = repository.snooze(ids[0], Some(until)).await.unwrap();
+        assert_eq!(
+            todo.snoozed_until.map(|t| t.timestamp()),
+            Some(until.timestamp())
+        );
+        // 時刻を過ぎたものはスヌーズ中として扱わない
+        repository
+            .snooze(ids[1], Some(Utc::now() - chrono::Duration::hours(1)))
+            .await
+            .unwrap();
+        let repository = &repository;
+        let texts = |snoozed| async move {
+            let filter = TodoFilter {
+                snoozed,
+                ..Default::default()
+            };
+            let todos = repository.all(filter.clone()).await.unwrap();
+            assert_eq!(repository.count(filter).await.unwrap(), todos.len() as i64);
+            todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts(Some(true)).await, vec!["snoozed"]);
+        assert_eq!(texts(Some(false)).await, vec!["awake", "elapsed"]);
+        assert_eq!(texts(None).await.len(), 3);
+
+        repository.snooze(ids[0], None).await.unwrap();
+        assert!(texts(Some(true)).await.is_empty());
+
+        // 書き込みがなくても、スヌーズが明けたら一覧の変更時刻を進める
+        let until = Utc::now() + chrono::Duration::milliseconds(200);
+        repository.snooze(ids[2], Some(until)).await.unwrap();
+        let written_at = repository.list_modified_at().await.unwrap();
+        assert!(written_at < until);
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(repository.list_modified_at().await.unwrap() > written_at);
+
+        let e = repository
+            .snooze(ids[2] + 1, Some(until))
+            .await
+            .expect_err("[snooze] of a missing todo returned Ok");
+        assert!(matches!(
+            e.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
+
+        db.teardown().await;
+    }
+
+    // 生成した操作列を記憶領域とデータベースの両方に適用し、結果が一致することを確かめる
+    mod equivalence {
+        use super::super::test_utils::TodoRepositoryForMemory;
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+        use proptest::sample::subsequence;
+        use proptest::test_runner::TestRunner;
+
+        const LABEL_COUNT: usize = 3;
+
+        #[derive(Debug, Clone)]
+        enum Op {
+            Create {
+                text: String,
+                labels: Vec<usize>,
+            },
+            // indexは既存のtodoを作成順に並べたときの位置
+            Update {
+                index: usize,
+                text: Option<String>,
+                completed: Option<bool>,
+                status: Option<TodoStatus>,
+                labels: Option<Vec<usize>>,
+            },
+            Delete {
+                index: usize,
+            },
+        }
+
+        fn status_strategy() -> impl Strategy<Value = TodoStatus> {
+            prop_oneof![
+                Just(TodoStatus::Backlog),
+                Just(TodoStatus::InProgress),
+                Just(TodoStatus::Done),
+                Just(TodoStatus::Cancelled),
+            ]
+        }
 
-        // delete
-        repository
-            .delete(todo.id)
-            .await
-            .expect("[delete] returned Err");
-        let res = repository.find(created.id).await;
-        assert!(res.is_err());
+        fn labels_strategy() -> impl Strategy<Value = Vec<usize>> {
+            subsequence((0..LABEL_COUNT).collect::<Vec<_>>(), 0..=LABEL_COUNT)
+        }
 
-        let todo_rows = sqlx::query(
-            r#"
-select * from todos where id=$1
-        "#,
-        )
-        .bind(todo.id)
-        .fetch_all(&pool)
-        .await
-        .expect("[delete] todo_labels fetch error");
-        assert_eq!(todo_rows.len(), 0);
+        fn op_strategy() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                ("[a-z]{1,10}", labels_strategy())
+                    .prop_map(|(text, labels)| Op::Create { text, labels }),
+                (
+                    any::<usize>(),
+                    proptest::option::of("[a-z]{1,10}"),
+                    proptest::option::of(any::<bool>()),
+                    proptest::option::of(status_strategy()),
+                    proptest::option::of(labels_strategy()),
+                )
+                    .prop_map(|(index, text, completed, status, labels)| {
+                        Op::Update {
+                            index,
+                            text,
+                            completed,
+                            status,
+                            labels,
+                        }
+                    }),
+                any::<usize>().prop_map(|index| Op::Delete { index }),
+            ]
+        }
 
-        let rows = sqlx::query(
-            r#"
-select * from todo_labels where todo_id=$1
-        "#,
-        )
-        .bind(todo.id)
-        .fetch_all(&pool)
-        .await
-        .expect("[delete] todo_labels fetch error");
-        assert_eq!(rows.len(), 0);
+        // idは採番方法が異なるため比較せず、作成順に並べて中身を比べる
+        // 完了日時は時刻そのものではなく記録されているかを比べる
+        type Observed = Vec<(String, bool, TodoStatus, Vec<Label>, bool)>;
+
+        async fn sorted<T: TodoRepository>(repository: &T) -> Vec<TodoEntity> {
+            let mut todos = repository.all(TodoFilter::default()).await.unwrap();
+            todos.sort_by_key(|todo| todo.id);
+            todos
+        }
+
+        async fn apply<T: TodoRepository>(
+            repository: &T,
+            labels: &[Label],
+            ops: &[Op],
+        ) -> Observed {
+            let label_ids =
+                |indexes: &[usize]| indexes.iter().map(|i| labels[*i].id).collect::<Vec<_>>();
+            for op in ops {
+                let todos = sorted(repository).await;
+                match op {
+                    Op::Create { text, labels } => {
+                        repository
+                            .create(CreateTodo::new(text.clone(), label_ids(labels)))
+                            .await
+                            .unwrap();
+                    }
+                    Op::Update {
+                        index,
+                        text,
+                        completed,
+                        status,
+                        labels,
+                    } => {
+                        if todos.is_empty() {
+                            continue;
+                        }
+                        let todo = &todos[index % todos.len()];
+                        let payload = UpdateTodo {
+                            text: text.clone(),
+                            completed: *completed,
+                            status: *status,
+                            labels: labels.as_deref().map(label_ids),
+                            tags: None,
+                            icon: None,
+                            color: None,
+                            remind_at: None,
+                            estimate_minutes: None,
+                            due_at: None,
+                            description: None,
+                        };
+                        repository.update(todo.id, payload).await.unwrap();
+                    }
+                    Op::Delete { index } => {
+                        if todos.is_empty() {
+                            continue;
+                        }
+                        let todo = &todos[index % todos.len()];
+                        repository.delete(todo.id).await.unwrap();
+                    }
+                }
+            }
+
+            sorted(repository)
+                .await
+                .into_iter()
+                .map(|mut todo| {
+                    todo.labels.sort_by_key(|label| label.id);
+                    let completed = todo.completed_at.is_some();
+                    (
+                        todo.text,
+                        todo.completed,
+                        todo.status,
+                        todo.labels,
+                        completed,
+                    )
+                })
+                .collect()
+        }
+
+        // ケースごとにランタイムとスキーマを作ると接続を使い切るので、
+        // 1回の実行で1つずつ作り、ケースの間はテーブルを空にして使い回す
+        #[test]
+        fn memory_and_database_agree() {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let db = runtime.block_on(TestDatabase::new());
+            let mut runner = TestRunner::new(ProptestConfig {
+                source_file: Some(file!()),
+                ..ProptestConfig::with_cases(16)
+            });
+            let result = runner.run(&vec(op_strategy(), 1..20), |ops| {
+                let (memory, database) = runtime.block_on(async {
+                    db.truncate().await;
+                    let mut labels = vec![];
+                    for i in 0..LABEL_COUNT {
+                        let label = sqlx::query_as::<_, Label>(
+                            r#"insert into labels (name) values ($1) returning *"#,
+                        )
+                        .bind(format!("label {}", i))
+                        .fetch_one(&db.pool)
+                        .await
+                        .expect("Failed to insert label data.");
+                        labels.push(label);
+                    }
+
+                    let memory =
+                        apply(&TodoRepositoryForMemory::new(labels.clone()), &labels, &ops).await;
+                    let database =
+                        apply(&TodoRepositoryForDb::new(db.pool.clone()), &labels, &ops).await;
+                    (memory, database)
+                });
+                prop_assert_eq!(memory, database);
+                Ok(())
+            });
+            runtime.block_on(db.teardown());
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils {
     use super::*;
-    use crate::repositories::RepositoryError;
+    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
+    use crate::repositories::{EntityId, RepositoryError};
     use anyhow::Context;
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::sync::atomic::{AtomicI32, Ordering};
     use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
     #[cfg(test)]
-    impl CreateTodo {
-        pub fn new(text: String, labels: Vec<i32>) -> Self {
-            Self { text, labels }
+    impl<I: EntityId> CreateTodo<I> {
+        pub fn new(text: String, labels: Vec<I>) -> Self {
+            Self {
+                text,
+                labels,
+                project_id: None,
+                tags: vec![],
+                icon: None,
+                color: None,
+                estimate_minutes: None,
+                due_at: None,
+                description: None,
+                parent_id: None,
+                deduplicated: false,
+                owner_id: None,
+                uuid: None,
+            }
         }
     }
 
-    impl TodoEntity {
-        pub fn new(id: i32, text: String, labels: Vec<Label>) -> Self {
+    impl<I: EntityId> TodoEntity<I> {
+        // テストではidから決まるUUIDを振る
+        pub fn new(id: I, text: String, labels: Vec<Label<I>>) -> Self {
             Self {
                 id,
+                uuid: id.as_uuid(),
                 text,
                 completed: false,
+                status: TodoStatus::Backlog,
                 labels,
+                remind_at: None,
+                completed_at: None,
+                assignee: None,
+                project_id: None,
+                tags: vec![],
+                icon: None,
+                color: None,
+                pinned: false,
+                snoozed_until: None,
+                estimate_minutes: None,
+                due_at: None,
+                description: None,
+                parent_id: None,
+                blocked: false,
+                owner_id: None,
             }
         }
     }
 
-    type TodoDatas = HashMap<i32, TodoEntity>;
+    // 未着手か作業中
+    fn is_open<I>(todo: &TodoEntity<I>) -> bool {
+        matches!(todo.status, TodoStatus::Backlog | TodoStatus::InProgress)
+    }
+
+    type TodoDatas<I> = HashMap<I, TodoEntity<I>>;
+    type TodoRevisions<I> = HashMap<I, Vec<TodoRevision<I>>>;
 
     #[derive(Debug, Clone)]
-    pub struct TodoRepositoryForMemory {
-        store: Arc<RwLock<TodoDatas>>,
-        labels: Vec<Label>,
+    pub struct TodoRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<TodoDatas<I>>>,
+        revisions: Arc<RwLock<TodoRevisions<I>>>,
+        // 削除済みのidを再利用しないよう、件数とは別に採番する
+        next_id: Arc<AtomicI32>,
+        labels: LabelRepositoryForMemory<I>,
+        updated_at: Arc<RwLock<HashMap<I, DateTime<Utc>>>>,
+        list_modified_at: Arc<RwLock<DateTime<Utc>>>,
+        // (blocker_id, blocked_id)
+        dependencies: Arc<RwLock<BTreeSet<(I, I)>>>,
+        // (todo_id, user_id)
+        shares: Arc<RwLock<BTreeMap<(I, i32), Permission>>>,
+        // 添字+1を同期トークンにする
+        changes: Arc<RwLock<Vec<TodoChange<I>>>>,
+        max_labels: usize,
     }
 
     impl TodoRepositoryForMemory {
         pub fn new(labels: Vec<Label>) -> Self {
+            Self::with_labels(LabelRepositoryForMemory::from_labels(labels))
+        }
+    }
+
+    impl<I: EntityId> TodoRepositoryForMemory<I> {
+        // 渡したラベルのリポジトリで作ったラベルを付けられる
+        pub fn with_labels(labels: LabelRepositoryForMemory<I>) -> Self {
             TodoRepositoryForMemory {
                 store: Arc::default(),
+                revisions: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
                 labels,
+                updated_at: Arc::default(),
+                list_modified_at: Arc::new(RwLock::new(Utc::now())),
+                dependencies: Arc::default(),
+                shares: Arc::default(),
+                changes: Arc::default(),
+                max_labels: DEFAULT_MAX_LABELS,
+            }
+        }
+
+        pub fn with_max_labels(mut self, max_labels: usize) -> Self {
+            self.max_labels = max_labels;
+            self
+        }
+
+        // 作ったものをすべて捨てて空に戻す。クローンとも共有している中身を消す
+        // ラベルは共有しているリポジトリの側で消す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.revisions.write().unwrap().clear();
+            self.next_id.store(1, Ordering::SeqCst);
+            self.updated_at.write().unwrap().clear();
+            self.dependencies.write().unwrap().clear();
+            self.shares.write().unwrap().clear();
+            self.changes.write().unwrap().clear();
+            *self.list_modified_at.write().unwrap() = Utc::now();
+        }
+
+        fn shares_of(&self, id: I) -> Vec<Share<I>> {
+            self.shares
+                .read()
+                .unwrap()
+                .range((id, i32::MIN)..=(id, i32::MAX))
+                .map(|(&(todo_id, user_id), &permission)| Share {
+                    todo_id,
+                    user_id,
+                    permission,
+                })
+                .collect()
+        }
+
+        // 依存関係と状態からblockedを導き直し、変わったtodoの更新時刻を進める
+        fn refresh_blocked(&self, store: &mut TodoDatas<I>) {
+            let dependencies = self.dependencies.read().unwrap();
+            let blocked: Vec<(I, bool)> = store
+                .values()
+                .map(|todo| {
+                    let blocked = dependencies.iter().any(|(blocker_id, blocked_id)| {
+                        *blocked_id == todo.id
+                            && store
+                                .get(blocker_id)
+                                .is_some_and(|blocker| !blocker.status.is_completed())
+                    });
+                    (todo.id, blocked)
+                })
+                .collect();
+            for (id, blocked) in blocked {
+                let todo = store.get_mut(&id).unwrap();
+                if todo.blocked != blocked {
+                    todo.blocked = blocked;
+                    self.touch(todo, false);
+                }
             }
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<TodoDatas> {
+        // 変更時刻と変更ログを記録する。削除した場合は一覧の時刻だけを進める
+        fn touch(&self, todo: &TodoEntity<I>, deleted: bool) {
+            let now = Utc::now();
+            let mut updated_at = self.updated_at.write().unwrap();
+            let operation = if deleted {
+                updated_at.remove(&todo.id);
+                TodoOperation::Delete
+            } else {
+                match updated_at.insert(todo.id, now) {
+                    Some(_) => TodoOperation::Update,
+                    None => TodoOperation::Insert,
+                }
+            };
+            self.changes
+                .write()
+                .unwrap()
+                .push(TodoChange::new(todo, operation));
+            *self.list_modified_at.write().unwrap() = now;
+        }
+
+        fn insert_revision(&self, todo: &TodoEntity<I>) {
+            let mut revisions = self.revisions.write().unwrap();
+            let revisions = revisions.entry(todo.id).or_default();
+            revisions.push(TodoRevision {
+                revision: revisions.len() as i64 + 1,
+                text: todo.text.clone(),
+                completed: todo.completed,
+                status: todo.status,
+                labels: Json(todo.labels.clone()),
+                created_at: Utc::now(),
+            });
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, TodoDatas<I>> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<TodoDatas> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, TodoDatas<I>> {
             self.store.read().unwrap()
         }
 
-        fn resolve_labels(&self, labels: Vec<i32>) -> Vec<Label> {
-            let mut label_list = self.labels.iter().cloned();
-            let labels = labels
-                .iter()
-                .map(|id| label_list.find(|label| label.id == *id).unwrap())
-                .collect();
+        // 存在しないラベルがあれば、そのidをNotFoundとして返す
+        fn resolve_labels(&self, labels: Vec<I>) -> anyhow::Result<Vec<Label<I>>> {
             labels
+                .into_iter()
+                .map(|id| Ok(self.labels.get(id).ok_or_else(|| id.not_found())?))
+                .collect()
         }
     }
 
     #[async_trait]
-    impl TodoRepository for TodoRepositoryForMemory {
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
+    impl<I: EntityId> TodoRepository<I> for TodoRepositoryForMemory<I> {
+        async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let labels = self.resolve_labels(payload.labels);
-            let todo = TodoEntity::new(id, payload.text.clone(), labels);
+            if payload.deduplicated {
+                if let Some(todo) = store.values().find(|todo| {
+                    is_open(todo) && todo.text == payload.text && todo.owner_id == payload.owner_id
+                }) {
+                    return Err(RepositoryError::Duplicate(todo.id.key()).into());
+                }
+            }
+            if let Some(todo) = store.values().find(|todo| Some(todo.uuid) == payload.uuid) {
+                return Err(RepositoryError::Duplicate(todo.id.key()).into());
+            }
+            check_label_count(&payload.labels, self.max_labels)?;
+            // UUIDのidではクライアントが振ったUUIDをidにする
+            let id = payload
+                .uuid
+                .and_then(|uuid| I::from_key(Key::Uuid(uuid)))
+                .unwrap_or_else(|| I::assign(self.next_id.fetch_add(1, Ordering::SeqCst)));
+            let labels = self.resolve_labels(payload.labels.clone())?;
+            let default = TodoEntity::new(id, payload.text.clone(), labels);
+            let todo = TodoEntity {
+                uuid: payload.uuid.unwrap_or(default.uuid),
+                project_id: payload.project_id,
+                icon: payload.icon(),
+                color: payload.color(),
+                estimate_minutes: payload.estimate_minutes(),
+                due_at: payload.due_at(),
+                description: payload.description(),
+                tags: payload.tags,
+                parent_id: payload.parent_id,
+                owner_id: payload.owner_id,
+                ..default
+            };
             store.insert(id, todo.clone());
+            self.insert_revision(&todo);
+            self.touch(&todo, false);
             Ok(todo)
         }
 
-        async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
+        async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+            let store = self.read_store_ref();
+            let todo = store.get(&id).cloned().ok_or_else(|| id.not_found())?;
+            Ok(todo)
+        }
+
+        async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
             let store = self.read_store_ref();
             let todo = store
-                .get(&id)
+                .values()
+                .find(|todo| todo.uuid == uuid)
                 .cloned()
-                .ok_or(RepositoryError::NotFound(id))?;
+                .ok_or(RepositoryError::NotFoundUuid(uuid))?;
+            Ok(todo)
+        }
+
+        async fn find_by_text(
+            &self,
+            text: &str,
+            visible_to: Visibility,
+        ) -> anyhow::Result<Option<TodoEntity<I>>> {
+            let store = self.read_store_ref();
+            let todo = store
+                .values()
+                .filter(|todo| is_open(todo) && todo.text == text)
+                .filter(|todo| visible_to.allows(todo.owner_id, &self.shares_of(todo.id)))
+                .min_by_key(|todo| todo.id)
+                .cloned();
             Ok(todo)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
+        async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
             let store = self.read_store_ref();
-            Ok(Vec::from_iter(store.values().cloned()))
+            let now = Utc::now();
+            let mut todos: Vec<TodoEntity<I>> = store
+                .values()
+                .filter(|todo| {
+                    filter.assignee_id.is_none_or(|assignee_id| {
+                        todo.assignee.as_ref().map(|user| user.id) == Some(assignee_id)
+                    })
+                })
+                .filter(|todo| {
+                    filter
+                        .project_id
+                        .is_none_or(|project_id| todo.project_id == Some(project_id))
+                })
+                .filter(|todo| {
+                    filter
+                        .tag
+                        .as_ref()
+                        .is_none_or(|tag| todo.tags.contains(tag))
+                })
+                .filter(|todo| {
+                    filter
+                        .label_id
+                        .is_none_or(|label_id| todo.labels.iter().any(|label| label.id == label_id))
+                })
+                .filter(|todo| {
+                    filter
+                        .completed
+                        .is_none_or(|completed| todo.status.is_completed() == completed)
+                })
+                .filter(|todo| {
+                    filter
+                        .color
+                        .as_ref()
+                        .is_none_or(|color| todo.color.as_ref() == Some(color))
+                })
+                .filter(|todo| {
+                    filter
+                        .snoozed
+                        .is_none_or(|snoozed| todo.is_snoozed(now) == snoozed)
+                })
+                .filter(|todo| {
+                    let shares = self.shares_of(todo.id);
+                    filter.visible_to.allows(todo.owner_id, &shares)
+                        && filter.shared_with.is_none_or(|user_id| {
+                            shares.iter().any(|share| share.user_id == user_id)
+                        })
+                })
+                .cloned()
+                .collect();
+            // データベースと同じく固定したものを先に、新しい順に並べる
+            todos.sort_by_key(|todo| (!todo.pinned, std::cmp::Reverse(todo.id)));
+            Ok(todos)
+        }
+
+        async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+            Ok(self.all(filter).await?.len() as i64)
         }
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
+        async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
             let mut store = self.write_store_ref();
-            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
+            let todo = store.get(&id).context(id.not_found())?;
+            let status = payload.next_status(todo.status);
+            let icon = payload.next_icon(todo.icon.clone());
+            let color = payload.next_color(todo.color.clone());
+            let remind_at = payload.next_remind_at(todo.remind_at);
+            let estimate_minutes = payload.next_estimate_minutes(todo.estimate_minutes);
+            let due_at = payload.next_due_at(todo.due_at);
+            let description = payload.next_description(todo.description.clone());
             let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed);
             let labels = match payload.labels {
-                Some(label_ids) => self.resolve_labels(label_ids),
+                Some(label_ids) => {
+                    check_label_count(&label_ids, self.max_labels)?;
+                    self.resolve_labels(label_ids)?
+                }
                 None => todo.labels.clone(),
             };
+            let completed_at = match (todo.status.is_completed(), status.is_completed()) {
+                (_, false) => None,
+                (true, true) => todo.completed_at,
+                (false, true) => Some(Utc::now()),
+            };
             let todo = TodoEntity {
-                id,
                 text,
-                completed,
+                completed: status.is_completed(),
+                status,
+                completed_at,
                 labels,
+                tags: payload.tags.unwrap_or(todo.tags.clone()),
+                icon,
+                color,
+                remind_at,
+                estimate_minutes,
+                due_at,
+                description,
+                ..todo.clone()
             };
             store.insert(id, todo.clone());
+            self.insert_revision(&todo);
+            self.touch(&todo, false);
+            self.refresh_blocked(&mut store);
             Ok(todo)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn delete(&self, id: I) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            let todo = store.remove(&id).ok_or_else(|| id.not_found())?;
+            // 子は最上位に戻す
+            for todo in store.values_mut().filter(|todo| todo.parent_id == Some(id)) {
+                todo.parent_id = None;
+            }
+            self.dependencies
+                .write()
+                .unwrap()
+                .retain(|(blocker_id, blocked_id)| *blocker_id != id && *blocked_id != id);
+            self.revisions.write().unwrap().remove(&id);
+            self.shares
+                .write()
+                .unwrap()
+                .retain(|(todo_id, _), _| *todo_id != id);
+            self.touch(&todo, true);
+            self.refresh_blocked(&mut store);
+            Ok(())
+        }
+
+        async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+            let revisions = self.revisions.read().unwrap();
+            let revisions = revisions.get(&id).cloned().ok_or_else(|| id.not_found())?;
+            Ok(revisions)
+        }
+
+        async fn set_reminder(
+            &self,
+            id: I,
+            remind_at: Option<DateTime<Utc>>,
+        ) -> anyhow::Result<TodoEntity<I>> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.remind_at = remind_at;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<TodoEntity<I>> = store
+                .values()
+                .filter(|todo| todo.remind_at.is_some())
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| (todo.remind_at, todo.id));
+            Ok(todos)
+        }
+
+        async fn take_due_reminders(
+            &self,
+            now: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<TodoEntity<I>>> {
+            let mut store = self.write_store_ref();
+            let mut due: Vec<TodoEntity<I>> = vec![];
+            for todo in store.values_mut() {
+                if todo.remind_at.is_some_and(|remind_at| remind_at <= now) {
+                    due.push(todo.clone());
+                    todo.remind_at = None;
+                    self.touch(todo, false);
+                }
+            }
+            due.sort_by_key(|todo| (todo.remind_at, todo.id));
+            Ok(due)
+        }
+
+        async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.assignee = assignee;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn move_to_project(
+            &self,
+            id: I,
+            project_id: Option<i32>,
+        ) -> anyhow::Result<TodoEntity<I>> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.project_id = project_id;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.parent_id = parent_id;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.pinned = pinned;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn snooze(
+            &self,
+            id: I,
+            until: Option<DateTime<Utc>>,
+        ) -> anyhow::Result<TodoEntity<I>> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            let todo = store.get_mut(&id).context(id.not_found())?;
+            todo.snoozed_until = until;
+            self.touch(todo, false);
+            Ok(todo.clone())
+        }
+
+        async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+            let label = self
+                .labels
+                .get(label_id)
+                .ok_or_else(|| label_id.not_found())?;
+            let mut store = self.write_store_ref();
+            let full = todo_ids.iter().filter_map(|id| store.get(id)).any(|todo| {
+                !todo.labels.iter().any(|label| label.id == label_id)
+                    && todo.labels.len() >= self.max_labels
+            });
+            if full {
+                return Err(RepositoryError::TooManyLabels(self.max_labels).into());
+            }
+            let mut attached = vec![];
+            for id in todo_ids {
+                let Some(todo) = store.get_mut(&id) else {
+                    continue;
+                };
+                if todo.labels.iter().any(|label| label.id == label_id) {
+                    continue;
+                }
+                todo.labels.push(label.clone());
+                todo.labels.sort_by_key(|label| label.id);
+                self.touch(todo, false);
+                attached.push(id);
+            }
+            Ok(attached)
+        }
+
+        async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+            self.labels
+                .get(label_id)
+                .ok_or_else(|| label_id.not_found())?;
+            let mut store = self.write_store_ref();
+            let mut detached = vec![];
+            for id in todo_ids {
+                let Some(todo) = store.get_mut(&id) else {
+                    continue;
+                };
+                if !todo.labels.iter().any(|label| label.id == label_id) {
+                    continue;
+                }
+                todo.labels.retain(|label| label.id != label_id);
+                self.touch(todo, false);
+                detached.push(id);
+            }
+            Ok(detached)
+        }
+
+        async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<TodoEntity<I>> = store
+                .values()
+                .filter(|todo| todo.parent_id == Some(id))
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            Ok(todos)
+        }
+
+        async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+            // 子を辿り、一度見たidには戻らない
+            fn collect<I: EntityId>(store: &TodoDatas<I>, id: I, ids: &mut Vec<I>) {
+                for todo in store.values().filter(|todo| todo.parent_id == Some(id)) {
+                    if !ids.contains(&todo.id) {
+                        ids.push(todo.id);
+                        collect(store, todo.id, ids);
+                    }
+                }
+            }
+            let mut ids = vec![];
+            collect(&self.read_store_ref(), id, &mut ids);
+            ids.sort();
+            Ok(ids)
+        }
+
+        async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            for id in [blocker_id, blocked_id] {
+                store.get(&id).context(id.not_found())?;
+            }
+            self.dependencies
+                .write()
+                .unwrap()
+                .insert((blocker_id, blocked_id));
+            self.refresh_blocked(&mut store);
+            Ok(())
+        }
+
+        async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            if !self
+                .dependencies
+                .write()
+                .unwrap()
+                .remove(&(blocker_id, blocked_id))
+            {
+                return Err(blocked_id.not_found().into());
+            }
+            self.refresh_blocked(&mut store);
+            Ok(())
+        }
+
+        async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+            let mut dependencies = TodoDependencies::default();
+            for (blocker_id, blocked_id) in self.dependencies.read().unwrap().iter() {
+                if *blocked_id == id {
+                    dependencies.blocked_by.push(*blocker_id);
+                } else if *blocker_id == id {
+                    dependencies.blocks.push(*blocked_id);
+                }
+            }
+            Ok(dependencies)
+        }
+
+        async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+            let dependencies = self.dependencies.read().unwrap();
+            let mut ids = vec![];
+            let mut pending = vec![id];
+            while let Some(id) = pending.pop() {
+                for (_, blocked_id) in dependencies
+                    .iter()
+                    .filter(|(blocker_id, _)| *blocker_id == id)
+                {
+                    if !ids.contains(blocked_id) {
+                        ids.push(*blocked_id);
+                        pending.push(*blocked_id);
+                    }
+                }
+            }
+            ids.sort();
+            Ok(ids)
+        }
+
+        async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+            let updated_at = self.updated_at.read().unwrap();
+            let modified_at = updated_at.get(&id).copied().ok_or_else(|| id.not_found())?;
+            Ok(modified_at)
+        }
+
+        async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+            let written_at = *self.list_modified_at.read().unwrap();
+            let store = self.read_store_ref();
+            Ok(latest_change(written_at, store.values(), Utc::now()))
+        }
+
+        // 最初の版の時刻を作成日時とする
+        async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+            let store = self.read_store_ref();
+            let revisions = self.revisions.read().unwrap();
+            let seconds = store
+                .values()
+                .filter_map(|todo| {
+                    let completed_at = todo.completed_at.filter(|at| range.contains(*at))?;
+                    let created_at = revisions.get(&todo.id)?.first()?.created_at;
+                    Some((completed_at - created_at).num_milliseconds() as f64 / 1000.0)
+                })
+                .collect();
+            Ok(CycleTime::from_seconds(seconds))
+        }
+
+        async fn share(
+            &self,
+            id: I,
+            user_id: i32,
+            permission: Permission,
+        ) -> anyhow::Result<Share<I>> {
+            self.read_store_ref().get(&id).context(id.not_found())?;
+            self.shares
+                .write()
+                .unwrap()
+                .insert((id, user_id), permission);
+            *self.list_modified_at.write().unwrap() = Utc::now();
+            Ok(Share {
+                todo_id: id,
+                user_id,
+                permission,
+            })
+        }
+
+        async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+            self.shares
+                .write()
+                .unwrap()
+                .remove(&(id, user_id))
+                .context(id.not_found())?;
+            *self.list_modified_at.write().unwrap() = Utc::now();
             Ok(())
         }
+
+        async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+            Ok(self.shares_of(id))
+        }
+
+        async fn suggest(
+            &self,
+            query: &str,
+            visible_to: Visibility,
+            limit: i64,
+        ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+            let todos = self
+                .all(TodoFilter {
+                    visible_to,
+                    ..TodoFilter::default()
+                })
+                .await?;
+            Ok(rank_suggestions(query, todos, limit))
+        }
+
+        async fn changes(
+            &self,
+            since: i64,
+            visible_to: Visibility,
+        ) -> anyhow::Result<TodoChanges<I>> {
+            let changes = self.changes.read().unwrap();
+            let visible: Vec<TodoChange<I>> = changes
+                .iter()
+                .skip(since.max(0) as usize)
+                .filter(|change| {
+                    visible_to.allows(change.owner_id, &self.shares_of(change.todo_id))
+                })
+                .cloned()
+                .collect();
+            Ok(TodoChanges::collapse(visible, changes.len() as i64))
+        }
     }
 
     #[cfg(test)]
@@ -535,23 +4738,12 @@ pub mod test_utils {
         async fn todo_crud_scenario() {
             let text = "todo text".to_string();
             let id = 1;
-            let label_data = Label {
-                id: 1,
-                name: String::from("test label"),
-            };
+            let label_data = Label::new(1, String::from("test label"));
             let labels = vec![label_data.clone()];
-            let expected = TodoEntity {
-                id,
-                text: text.clone(),
-                completed: false,
-                labels: labels.clone(),
-            };
+            let expected = TodoEntity::new(id, text.clone(), labels.clone());
 
             // create
-            let label_data = Label {
-                id: 1,
-                name: String::from("test label"),
-            };
+            let label_data = Label::new(1, String::from("test label"));
             let labels = vec![label_data.clone()];
             let repository = TodoRepositoryForMemory::new(labels.clone());
             let todo = repository
@@ -563,9 +4755,15 @@ pub mod test_utils {
             // find
             let todo = repository.find(todo.id).await.unwrap();
             assert_eq!(expected, todo);
+            let todo = repository.find_by_uuid(todo.uuid).await.unwrap();
+            assert_eq!(expected, todo);
+            assert!(repository.find_by_uuid(Uuid::nil()).await.is_err());
 
             // all
-            let todo = repository.all().await.expect("failed get all todo");
+            let todo = repository
+                .all(TodoFilter::default())
+                .await
+                .expect("failed get all todo");
             assert_eq!(vec![expected], todo);
 
             // update
@@ -576,7 +4774,15 @@ pub mod test_utils {
                     UpdateTodo {
                         text: Some(text.clone()),
                         completed: Some(true),
+                        status: None,
                         labels: Some(vec![]),
+                        tags: None,
+                        icon: None,
+                        color: None,
+                        remind_at: None,
+                        estimate_minutes: None,
+                        due_at: None,
+                        description: None,
                     },
                 )
                 .await
@@ -584,16 +4790,238 @@ pub mod test_utils {
             assert_eq!(
                 TodoEntity {
                     id,
+                    uuid: Uuid::from_u128(id as u128),
                     text,
                     completed: true,
+                    status: TodoStatus::Done,
                     labels: vec![],
+                    remind_at: None,
+                    completed_at: todo.completed_at,
+                    assignee: None,
+                    project_id: None,
+                    tags: vec![],
+                    icon: None,
+                    color: None,
+                    pinned: false,
+                    snoozed_until: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                    parent_id: None,
+                    blocked: false,
+                    owner_id: None,
                 },
                 todo
             );
+            assert!(todo.completed_at.is_some());
+
+            // history
+            let revisions = repository.history(id).await.expect("failed get history");
+            assert_eq!(revisions.len(), 2);
+            assert_eq!(revisions[0].labels.0, labels);
+            assert!(revisions[1].completed);
+
+            // reminder
+            let now = Utc::now();
+            repository
+                .set_reminder(id, Some(now))
+                .await
+                .expect("failed set reminder");
+            assert_eq!(repository.reminders().await.unwrap().len(), 1);
+            let due = repository
+                .take_due_reminders(now - chrono::Duration::seconds(1))
+                .await
+                .unwrap();
+            assert!(due.is_empty());
+            let due = repository.take_due_reminders(now).await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert!(repository.reminders().await.unwrap().is_empty());
+
+            // 更新では省略したら保ち、nullで外す
+            let patch = |value| serde_json::from_value::<UpdateTodo>(value).unwrap();
+            let todo = repository
+                .update(id, patch(serde_json::json!({ "remind_at": now })))
+                .await
+                .unwrap();
+            assert_eq!(todo.remind_at, Some(now));
+            let todo = repository
+                .update(id, patch(serde_json::json!({ "text": "kept" })))
+                .await
+                .unwrap();
+            assert_eq!(todo.remind_at, Some(now));
+            let todo = repository
+                .update(id, patch(serde_json::json!({ "remind_at": null })))
+                .await
+                .unwrap();
+            assert_eq!(todo.remind_at, None);
 
             // delete
             let res = repository.delete(id).await;
             assert!(res.is_ok())
         }
+
+        #[tokio::test]
+        async fn should_rank_suggestions() {
+            let repository = TodoRepositoryForMemory::new(vec![]);
+            for text in [
+                "read the manual",
+                "Manual testing",
+                "check email",
+                "remanufacture",
+            ] {
+                repository
+                    .create(CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .unwrap();
+            }
+
+            let suggestions = repository
+                .suggest("manu", Visibility::All, 10)
+                .await
+                .unwrap();
+            let texts: Vec<&str> = suggestions.iter().map(|s| s.text.as_str()).collect();
+            assert_eq!(
+                texts,
+                vec!["Manual testing", "read the manual", "remanufacture"]
+            );
+            let suggestions = repository
+                .suggest("manu", Visibility::All, 1)
+                .await
+                .unwrap();
+            assert_eq!(suggestions.len(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_not_reuse_deleted_ids() {
+            let repository = TodoRepositoryForMemory::new(vec![]);
+            let first = repository
+                .create(CreateTodo::new("first".to_string(), vec![]))
+                .await
+                .unwrap();
+            let second = repository
+                .create(CreateTodo::new("second".to_string(), vec![]))
+                .await
+                .unwrap();
+            repository.delete(first.id).await.unwrap();
+
+            // 件数から採番すると2件目を上書きしてしまう
+            let third = repository
+                .create(CreateTodo::new("third".to_string(), vec![]))
+                .await
+                .unwrap();
+            assert_ne!(third.id, first.id);
+            assert_ne!(third.id, second.id);
+            assert_eq!(repository.find(second.id).await.unwrap(), second);
+            assert_eq!(
+                repository.all(TodoFilter::default()).await.unwrap().len(),
+                2
+            );
+        }
+
+        #[tokio::test]
+        async fn should_reject_too_many_labels() {
+            let labels = (1..=3)
+                .map(|id| Label::new(id, format!("label {}", id)))
+                .collect();
+            let repository = TodoRepositoryForMemory::new(labels).with_max_labels(2);
+            let is_too_many = |e: anyhow::Error| {
+                matches!(
+                    e.downcast_ref::<RepositoryError>(),
+                    Some(RepositoryError::TooManyLabels(2))
+                )
+            };
+
+            let e = repository
+                .create(CreateTodo::new("three labels".to_string(), vec![1, 2, 3]))
+                .await
+                .expect_err("[create] with too many labels returned Ok");
+            assert!(is_too_many(e));
+            let full = repository
+                .create(CreateTodo::new("two labels".to_string(), vec![1, 2]))
+                .await
+                .unwrap();
+            let e = repository
+                .update(
+                    full.id,
+                    UpdateTodo::replace(
+                        "three labels".to_string(),
+                        TodoStatus::Backlog,
+                        vec![1, 2, 3],
+                        vec![],
+                    ),
+                )
+                .await
+                .expect_err("[update] with too many labels returned Ok");
+            assert!(is_too_many(e));
+
+            let empty = repository
+                .create(CreateTodo::new("no labels".to_string(), vec![]))
+                .await
+                .unwrap();
+            let e = repository
+                .attach_label(3, vec![empty.id, full.id])
+                .await
+                .expect_err("[attach_label] beyond the limit returned Ok");
+            assert!(is_too_many(e));
+            assert_eq!(repository.find(empty.id).await.unwrap().labels, vec![]);
+            assert_eq!(repository.find(full.id).await.unwrap(), full);
+        }
+
+        #[tokio::test]
+        async fn should_count_woken_snoozes_as_list_changes() {
+            let repository = TodoRepositoryForMemory::new(vec![]);
+            let todo = repository
+                .create(CreateTodo::new("snoozed".to_string(), vec![]))
+                .await
+                .unwrap();
+            let until = Utc::now() + chrono::Duration::milliseconds(100);
+            repository.snooze(todo.id, Some(until)).await.unwrap();
+            assert!(repository.list_modified_at().await.unwrap() < until);
+            tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+            assert_eq!(repository.list_modified_at().await.unwrap(), until);
+        }
+
+        #[tokio::test]
+        async fn should_collapse_changes_since_sync_token() {
+            let repository = TodoRepositoryForMemory::new(vec![]);
+            let create = |text: &str| repository.create(CreateTodo::new(text.to_string(), vec![]));
+            let kept = create("kept").await.unwrap();
+            let removed = create("removed").await.unwrap();
+            let changes = repository.changes(0, Visibility::All).await.unwrap();
+            let refs = |todos: &[&TodoEntity]| -> Vec<ChangedTodo> {
+                todos
+                    .iter()
+                    .map(|todo| ChangedTodo {
+                        id: todo.id,
+                        uuid: todo.uuid,
+                    })
+                    .collect()
+            };
+            assert_eq!(changes.created, refs(&[&kept, &removed]));
+
+            repository
+                .update(kept.id, UpdateTodo::status(TodoStatus::InProgress))
+                .await
+                .unwrap();
+            repository.delete(removed.id).await.unwrap();
+            // トークンより後に作って消したtodoは返さない
+            let transient = create("transient").await.unwrap();
+            repository.delete(transient.id).await.unwrap();
+            let added = create("added").await.unwrap();
+
+            let delta = repository
+                .changes(changes.sync_token, Visibility::All)
+                .await
+                .unwrap();
+            assert_eq!(delta.created, refs(&[&added]));
+            assert_eq!(delta.updated, refs(&[&kept]));
+            assert_eq!(delta.deleted, refs(&[&removed]));
+            assert!(repository
+                .changes(delta.sync_token, Visibility::All)
+                .await
+                .unwrap()
+                .created
+                .is_empty());
+        }
     }
 }
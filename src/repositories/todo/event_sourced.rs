@@ -0,0 +1,1457 @@
+use super::*;
+use crate::repositories::labels::LabelRepository;
+use crate::repositories::new_v7;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::str::FromStr;
+use thiserror::Error;
+
+// todoの保存方式
+// TODO_STORE="table|events" で指定し、未定義の場合は従来のテーブルに保存する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TodoStore {
+    #[default]
+    Table,
+    Events,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Unknown todo store: [{0}]")]
+pub struct UnknownTodoStore(String);
+
+impl FromStr for TodoStore {
+    type Err = UnknownTodoStore;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(TodoStore::Table),
+            "events" => Ok(TodoStore::Events),
+            _ => Err(UnknownTodoStore(s.to_string())),
+        }
+    }
+}
+
+impl TodoStore {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("TODO_STORE") {
+            Ok(store) if !store.is_empty() => Ok(store.parse()?),
+            _ => Ok(TodoStore::default()),
+        }
+    }
+}
+
+// todoに起きた変更
+// 追記するだけで書き換えず、現在の状態はこれを順に適用して求める
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TodoEvent<I = i32> {
+    Created {
+        uuid: Uuid,
+        text: String,
+        labels: Vec<I>,
+        project_id: Option<i32>,
+        tags: Vec<String>,
+        parent_id: Option<I>,
+        #[serde(default)]
+        owner_id: Option<i32>,
+        #[serde(default)]
+        icon: Option<String>,
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default)]
+        estimate_minutes: Option<i32>,
+        #[serde(default)]
+        due_at: Option<DateTime<Utc>>,
+        #[serde(default)]
+        description: Option<String>,
+    },
+    TextChanged {
+        text: String,
+    },
+    StatusChanged {
+        status: TodoStatus,
+    },
+    LabelAttached {
+        label_id: I,
+    },
+    LabelDetached {
+        label_id: I,
+    },
+    TagsChanged {
+        tags: Vec<String>,
+    },
+    IconChanged {
+        icon: Option<String>,
+    },
+    ColorChanged {
+        color: Option<String>,
+    },
+    Pinned {
+        pinned: bool,
+    },
+    Snoozed {
+        until: Option<DateTime<Utc>>,
+    },
+    EstimateChanged {
+        estimate_minutes: Option<i32>,
+    },
+    DueChanged {
+        due_at: Option<DateTime<Utc>>,
+    },
+    DescriptionChanged {
+        description: Option<String>,
+    },
+    ReminderSet {
+        remind_at: Option<DateTime<Utc>>,
+    },
+    Assigned {
+        assignee: Option<User>,
+    },
+    MovedToProject {
+        project_id: Option<i32>,
+    },
+    ParentChanged {
+        parent_id: Option<I>,
+    },
+    // このtodoがblocked_idのtodoをブロックする
+    DependencyAdded {
+        blocked_id: I,
+    },
+    DependencyRemoved {
+        blocked_id: I,
+    },
+    Shared {
+        user_id: i32,
+        permission: Permission,
+    },
+    Unshared {
+        user_id: i32,
+    },
+    Deleted,
+}
+
+impl<I> TodoEvent<I> {
+    // 版として記録する変更か
+    fn is_revision(&self) -> bool {
+        matches!(
+            self,
+            TodoEvent::Created { .. }
+                | TodoEvent::TextChanged { .. }
+                | TodoEvent::StatusChanged { .. }
+                | TodoEvent::LabelAttached { .. }
+                | TodoEvent::LabelDetached { .. }
+                | TodoEvent::TagsChanged { .. }
+        )
+    }
+}
+
+// 1回の操作で1つのtodoに起きた変更をまとめたもの
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct TodoCommit<I = i32> {
+    pub seq: i64,
+    pub todo_id: I,
+    pub events: Json<Vec<TodoEvent<I>>>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+// イベントの保存先
+#[async_trait]
+pub trait TodoEventStore<I: EntityId = i32>: Clone + Send + Sync + 'static {
+    // 新しいtodoのidを採番する
+    async fn next_id(&self) -> anyhow::Result<I>;
+    async fn append(&self, todo_id: I, events: Vec<TodoEvent<I>>) -> anyhow::Result<TodoCommit<I>>;
+    // 記録した順にすべて読み込む
+    async fn load(&self) -> anyhow::Result<Vec<TodoCommit<I>>>;
+    // todoの最新の記録をロックし、同じtodoに続けて記録するほかの処理を待たせる
+    async fn lock(&self, _todo_id: I) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoEventStoreForDb {
+    db: Database,
+}
+
+impl TodoEventStoreForDb {
+    pub fn new(db: impl Into<Database>) -> Self {
+        TodoEventStoreForDb { db: db.into() }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> TodoEventStore<I> for TodoEventStoreForDb {
+    // UUIDのidは問い合わせずに振る
+    #[instrument(skip_all)]
+    async fn next_id(&self) -> anyhow::Result<I> {
+        if let Some(id) = I::from_key(Key::Uuid(new_v7())) {
+            return Ok(id);
+        }
+        let id = sqlx::query_scalar(r#"select nextval('todo_event_ids')::int4"#)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(I::assign(id))
+    }
+
+    #[instrument(skip_all)]
+    async fn append(&self, todo_id: I, events: Vec<TodoEvent<I>>) -> anyhow::Result<TodoCommit<I>> {
+        let commit = sqlx::query_as::<_, TodoCommit<I>>(
+            r#"insert into todo_events (todo_id, events) values ($1, $2) returning *"#,
+        )
+        .bind(todo_id)
+        .bind(Json(events))
+        .fetch_one(&self.db)
+        .await?;
+        Ok(commit)
+    }
+
+    #[instrument(skip_all)]
+    async fn load(&self) -> anyhow::Result<Vec<TodoCommit<I>>> {
+        let commits =
+            sqlx::query_as::<_, TodoCommit<I>>(r#"select * from todo_events order by seq"#)
+                .fetch_all(&self.db)
+                .await?;
+        Ok(commits)
+    }
+
+    #[instrument(skip_all)]
+    async fn lock(&self, todo_id: I) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"select seq from todo_events where todo_id = $1 order by seq desc limit 1 for update"#,
+        )
+        .bind(todo_id)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+// 版ごとの本文・状態・ラベルと記録日時
+type Revision<I> = (String, TodoStatus, Vec<I>, DateTime<Utc>);
+
+// イベントを適用して求めた現在の状態
+// ラベルはidだけを持ち、読み出すときにラベルのリポジトリから引く
+#[derive(Debug, Default)]
+struct Projection<I> {
+    todos: BTreeMap<I, TodoEntity<I>>,
+    label_ids: HashMap<I, Vec<I>>,
+    // (blocker_id, blocked_id)
+    dependencies: BTreeSet<(I, I)>,
+    created_at: HashMap<I, DateTime<Utc>>,
+    modified_at: HashMap<I, DateTime<Utc>>,
+    list_modified_at: Option<DateTime<Utc>>,
+    revisions: HashMap<I, Vec<Revision<I>>>,
+    // (todo_id, user_id)
+    shares: BTreeMap<(I, i32), Permission>,
+    // 最後に適用したコミットの番号。同期トークンにする
+    seq: i64,
+    // (seq, 変更)
+    changes: Vec<(i64, TodoChange<I>)>,
+}
+
+impl<I: EntityId> Projection<I> {
+    fn replay(commits: &[TodoCommit<I>]) -> Self {
+        let mut projection = Projection::default();
+        for commit in commits {
+            projection.apply(commit);
+        }
+        projection
+    }
+
+    fn apply(&mut self, commit: &TodoCommit<I>) {
+        let id = commit.todo_id;
+        let at = commit.recorded_at;
+        self.seq = commit.seq;
+        let before = self.todos.get(&id).cloned();
+        for event in commit.events.iter() {
+            match event {
+                TodoEvent::Created {
+                    uuid,
+                    text,
+                    labels,
+                    project_id,
+                    tags,
+                    parent_id,
+                    owner_id,
+                    icon,
+                    color,
+                    estimate_minutes,
+                    due_at,
+                    description,
+                } => {
+                    self.todos.insert(
+                        id,
+                        TodoEntity {
+                            id,
+                            uuid: *uuid,
+                            text: text.clone(),
+                            completed: false,
+                            status: TodoStatus::Backlog,
+                            labels: vec![],
+                            remind_at: None,
+                            completed_at: None,
+                            assignee: None,
+                            project_id: *project_id,
+                            tags: tags.clone(),
+                            icon: icon.clone(),
+                            color: color.clone(),
+                            pinned: false,
+                            snoozed_until: None,
+                            estimate_minutes: *estimate_minutes,
+                            due_at: *due_at,
+                            description: description.clone(),
+                            parent_id: *parent_id,
+                            blocked: false,
+                            owner_id: *owner_id,
+                        },
+                    );
+                    self.label_ids.insert(id, labels.clone());
+                    self.created_at.insert(id, at);
+                }
+                TodoEvent::Deleted => self.delete(id, at),
+                event => {
+                    let Some(todo) = self.todos.get_mut(&id) else {
+                        continue;
+                    };
+                    match event {
+                        TodoEvent::TextChanged { text } => todo.text = text.clone(),
+                        TodoEvent::StatusChanged { status } => {
+                            todo.completed_at = match (todo.completed, status.is_completed()) {
+                                (_, false) => None,
+                                (true, true) => todo.completed_at,
+                                (false, true) => Some(at),
+                            };
+                            todo.status = *status;
+                            todo.completed = status.is_completed();
+                        }
+                        TodoEvent::LabelAttached { label_id } => {
+                            self.label_ids.entry(id).or_default().push(*label_id)
+                        }
+                        TodoEvent::LabelDetached { label_id } => self
+                            .label_ids
+                            .entry(id)
+                            .or_default()
+                            .retain(|attached| attached != label_id),
+                        TodoEvent::TagsChanged { tags } => todo.tags = tags.clone(),
+                        TodoEvent::IconChanged { icon } => todo.icon = icon.clone(),
+                        TodoEvent::ColorChanged { color } => todo.color = color.clone(),
+                        TodoEvent::Pinned { pinned } => todo.pinned = *pinned,
+                        TodoEvent::Snoozed { until } => todo.snoozed_until = *until,
+                        TodoEvent::EstimateChanged { estimate_minutes } => {
+                            todo.estimate_minutes = *estimate_minutes
+                        }
+                        TodoEvent::DueChanged { due_at } => todo.due_at = *due_at,
+                        TodoEvent::DescriptionChanged { description } => {
+                            todo.description = description.clone()
+                        }
+                        TodoEvent::ReminderSet { remind_at } => todo.remind_at = *remind_at,
+                        TodoEvent::Assigned { assignee } => todo.assignee = assignee.clone(),
+                        TodoEvent::MovedToProject { project_id } => todo.project_id = *project_id,
+                        TodoEvent::ParentChanged { parent_id } => todo.parent_id = *parent_id,
+                        TodoEvent::DependencyAdded { blocked_id } => {
+                            self.dependencies.insert((id, *blocked_id));
+                        }
+                        TodoEvent::DependencyRemoved { blocked_id } => {
+                            self.dependencies.remove(&(id, *blocked_id));
+                        }
+                        TodoEvent::Shared {
+                            user_id,
+                            permission,
+                        } => {
+                            self.shares.insert((id, *user_id), *permission);
+                        }
+                        TodoEvent::Unshared { user_id } => {
+                            self.shares.remove(&(id, *user_id));
+                        }
+                        TodoEvent::Created { .. } | TodoEvent::Deleted => unreachable!(),
+                    }
+                }
+            }
+        }
+
+        let change = match (before, self.todos.get(&id)) {
+            (None, Some(todo)) => Some(TodoChange::new(todo, TodoOperation::Insert)),
+            (Some(_), Some(todo)) => Some(TodoChange::new(todo, TodoOperation::Update)),
+            (Some(todo), None) => Some(TodoChange::new(&todo, TodoOperation::Delete)),
+            (None, None) => None,
+        };
+        self.changes
+            .extend(change.map(|change| (commit.seq, change)));
+        if let Some(todo) = self.todos.get(&id) {
+            self.modified_at.insert(id, at);
+            if commit.events.iter().any(TodoEvent::is_revision) {
+                let revision = (
+                    todo.text.clone(),
+                    todo.status,
+                    self.label_ids.get(&id).cloned().unwrap_or_default(),
+                    at,
+                );
+                self.revisions.entry(id).or_default().push(revision);
+            }
+        }
+        self.list_modified_at = Some(at);
+        self.refresh_blocked(at);
+    }
+
+    // 子は最上位に戻し、依存関係も外す
+    fn delete(&mut self, id: I, at: DateTime<Utc>) {
+        self.todos.remove(&id);
+        self.label_ids.remove(&id);
+        self.created_at.remove(&id);
+        self.modified_at.remove(&id);
+        self.revisions.remove(&id);
+        self.shares.retain(|(todo_id, _), _| *todo_id != id);
+        self.dependencies
+            .retain(|(blocker_id, blocked_id)| *blocker_id != id && *blocked_id != id);
+        for child in self
+            .todos
+            .values_mut()
+            .filter(|todo| todo.parent_id == Some(id))
+        {
+            child.parent_id = None;
+            self.modified_at.insert(child.id, at);
+            self.changes
+                .push((self.seq, TodoChange::new(child, TodoOperation::Update)));
+        }
+    }
+
+    // 依存関係と状態からblockedを導き直し、変わったtodoの更新時刻を進める
+    fn refresh_blocked(&mut self, at: DateTime<Utc>) {
+        let blocked: Vec<(I, bool)> = self
+            .todos
+            .values()
+            .map(|todo| {
+                let blocked = self.dependencies.iter().any(|(blocker_id, blocked_id)| {
+                    *blocked_id == todo.id
+                        && self
+                            .todos
+                            .get(blocker_id)
+                            .is_some_and(|blocker| !blocker.status.is_completed())
+                });
+                (todo.id, blocked)
+            })
+            .collect();
+        for (id, blocked) in blocked {
+            let todo = self.todos.get_mut(&id).unwrap();
+            if todo.blocked != blocked {
+                todo.blocked = blocked;
+                self.modified_at.insert(id, at);
+                self.changes
+                    .push((self.seq, TodoChange::new(todo, TodoOperation::Update)));
+            }
+        }
+    }
+
+    fn shares_of(&self, id: I) -> Vec<Share<I>> {
+        self.shares
+            .range((id, i32::MIN)..=(id, i32::MAX))
+            .map(|(&(todo_id, user_id), &permission)| Share {
+                todo_id,
+                user_id,
+                permission,
+            })
+            .collect()
+    }
+
+    fn labels(&self, id: I, labels: &[Label<I>]) -> Vec<Label<I>> {
+        resolve_labels(self.label_ids.get(&id).map_or(&[], Vec::as_slice), labels)
+    }
+
+    fn find(&self, id: I, labels: &[Label<I>]) -> Option<TodoEntity<I>> {
+        let todo = self.todos.get(&id)?;
+        Some(TodoEntity {
+            labels: self.labels(id, labels),
+            ..todo.clone()
+        })
+    }
+
+    fn find_all<P: Fn(&TodoEntity<I>) -> bool>(
+        &self,
+        labels: &[Label<I>],
+        predicate: P,
+    ) -> Vec<TodoEntity<I>> {
+        self.todos
+            .values()
+            .map(|todo| TodoEntity {
+                labels: self.labels(todo.id, labels),
+                ..todo.clone()
+            })
+            .filter(predicate)
+            .collect()
+    }
+}
+
+// 削除されたラベルは除く
+fn resolve_labels<I: EntityId>(ids: &[I], labels: &[Label<I>]) -> Vec<Label<I>> {
+    ids.iter()
+        .filter_map(|id| labels.iter().find(|label| label.id == *id).cloned())
+        .collect()
+}
+
+// 変更をイベントとして追記し、現在の状態はイベントから求めるtodoのリポジトリ
+// 版の履歴はイベントそのものから導くため、記録漏れがない
+// ラベルの統合はイベントに残らないため、統合元のラベルは付け替えられずに外れる
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryEventSourced<E, L, I = i32> {
+    events: E,
+    labels: L,
+    max_labels: usize,
+    id: PhantomData<I>,
+}
+
+impl<I: EntityId, E: TodoEventStore<I>, L: LabelRepository<I>> TodoRepositoryEventSourced<E, L, I> {
+    pub fn new(events: E, labels: L) -> Self {
+        TodoRepositoryEventSourced {
+            events,
+            labels,
+            max_labels: DEFAULT_MAX_LABELS,
+            id: PhantomData,
+        }
+    }
+
+    pub fn with_max_labels(mut self, max_labels: usize) -> Self {
+        self.max_labels = max_labels;
+        self
+    }
+
+    async fn project(&self) -> anyhow::Result<(Projection<I>, Vec<Label<I>>)> {
+        let commits = self.events.load().await?;
+        let labels = self.labels.all().await?;
+        Ok((Projection::replay(&commits), labels))
+    }
+
+    async fn existing(&self, id: I) -> anyhow::Result<(Projection<I>, Vec<Label<I>>)> {
+        let (projection, labels) = self.project().await?;
+        if !projection.todos.contains_key(&id) {
+            return Err(id.not_found().into());
+        }
+        Ok((projection, labels))
+    }
+
+    // 変更がなければ何も記録しない
+    async fn record(&self, id: I, events: Vec<TodoEvent<I>>) -> anyhow::Result<TodoEntity<I>> {
+        if !events.is_empty() {
+            self.events.append(id, events).await?;
+        }
+        self.find(id).await
+    }
+
+    fn check_labels(ids: &[I], labels: &[Label<I>]) -> anyhow::Result<()> {
+        match ids
+            .iter()
+            .find(|id| !labels.iter().any(|label| label.id == **id))
+        {
+            Some(id) => Err(id.not_found().into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_open<I>(todo: &TodoEntity<I>) -> bool {
+    matches!(todo.status, TodoStatus::Backlog | TodoStatus::InProgress)
+}
+
+#[async_trait]
+impl<I: EntityId, E: TodoEventStore<I>, L: LabelRepository<I>> TodoRepository<I>
+    for TodoRepositoryEventSourced<E, L, I>
+{
+    async fn create(&self, payload: CreateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let (projection, labels) = self.project().await?;
+        if payload.deduplicated {
+            if let Some(todo) = projection.todos.values().find(|todo| {
+                is_open(todo) && todo.text == payload.text && todo.owner_id == payload.owner_id
+            }) {
+                return Err(RepositoryError::Duplicate(todo.id.key()).into());
+            }
+        }
+        if let Some(todo) = projection
+            .todos
+            .values()
+            .find(|todo| Some(todo.uuid) == payload.uuid)
+        {
+            return Err(RepositoryError::Duplicate(todo.id.key()).into());
+        }
+        Self::check_labels(&payload.labels, &labels)?;
+        check_label_count(&payload.labels, self.max_labels)?;
+        // UUIDのidではクライアントが振ったUUIDをidにする
+        let id = match payload.uuid.and_then(|uuid| I::from_key(Key::Uuid(uuid))) {
+            Some(id) => id,
+            None => self.events.next_id().await?,
+        };
+        let uuid = match id.key() {
+            Key::Uuid(uuid) => uuid,
+            Key::Id(_) => payload.uuid.unwrap_or_else(new_v7),
+        };
+        let (icon, color) = (payload.icon(), payload.color());
+        let estimate_minutes = payload.estimate_minutes();
+        let due_at = payload.due_at();
+        let description = payload.description();
+        let created = TodoEvent::Created {
+            uuid,
+            text: payload.text,
+            labels: payload.labels,
+            project_id: payload.project_id,
+            tags: payload.tags,
+            parent_id: payload.parent_id,
+            owner_id: payload.owner_id,
+            icon,
+            color,
+            estimate_minutes,
+            due_at,
+            description,
+        };
+        self.record(id, vec![created]).await
+    }
+
+    async fn find(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        let (projection, labels) = self.project().await?;
+        let todo = projection.find(id, &labels).ok_or_else(|| id.not_found())?;
+        Ok(todo)
+    }
+
+    async fn find_for_update(&self, id: I) -> anyhow::Result<TodoEntity<I>> {
+        self.events.lock(id).await?;
+        self.find(id).await
+    }
+
+    async fn find_by_uuid(&self, uuid: Uuid) -> anyhow::Result<TodoEntity<I>> {
+        let (projection, labels) = self.project().await?;
+        let todo = projection
+            .find_all(&labels, |todo| todo.uuid == uuid)
+            .pop()
+            .ok_or(RepositoryError::NotFoundUuid(uuid))?;
+        Ok(todo)
+    }
+
+    async fn find_by_text(
+        &self,
+        text: &str,
+        visible_to: Visibility,
+    ) -> anyhow::Result<Option<TodoEntity<I>>> {
+        let (projection, labels) = self.project().await?;
+        let todo = projection
+            .find_all(&labels, |todo| {
+                is_open(todo)
+                    && todo.text == text
+                    && visible_to.allows(todo.owner_id, &projection.shares_of(todo.id))
+            })
+            .into_iter()
+            .next();
+        Ok(todo)
+    }
+
+    async fn all(&self, filter: TodoFilter<I>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let (projection, labels) = self.project().await?;
+        let now = Utc::now();
+        let mut todos = projection.find_all(&labels, |todo| {
+            filter.assignee_id.is_none_or(|assignee_id| {
+                todo.assignee.as_ref().map(|user| user.id) == Some(assignee_id)
+            }) && filter
+                .project_id
+                .is_none_or(|project_id| todo.project_id == Some(project_id))
+                && filter
+                    .tag
+                    .as_ref()
+                    .is_none_or(|tag| todo.tags.contains(tag))
+                && filter
+                    .label_id
+                    .is_none_or(|label_id| todo.labels.iter().any(|label| label.id == label_id))
+                && filter
+                    .completed
+                    .is_none_or(|completed| todo.status.is_completed() == completed)
+                && filter
+                    .color
+                    .as_ref()
+                    .is_none_or(|color| todo.color.as_ref() == Some(color))
+                && filter
+                    .snoozed
+                    .is_none_or(|snoozed| todo.is_snoozed(now) == snoozed)
+                && {
+                    let shares = projection.shares_of(todo.id);
+                    filter.visible_to.allows(todo.owner_id, &shares)
+                        && filter.shared_with.is_none_or(|user_id| {
+                            shares.iter().any(|share| share.user_id == user_id)
+                        })
+                }
+        });
+        // データベースと同じく固定したものを先に、新しい順に並べる
+        todos.reverse();
+        todos.sort_by_key(|todo| !todo.pinned);
+        Ok(todos)
+    }
+
+    async fn count(&self, filter: TodoFilter<I>) -> anyhow::Result<i64> {
+        Ok(self.all(filter).await?.len() as i64)
+    }
+
+    // 更新時刻は切り出したtodoの分だけ1回の再生から読む
+    async fn page(
+        &self,
+        filter: TodoFilter<I>,
+        page: TodoPage,
+    ) -> anyhow::Result<Vec<ListedTodo<I>>> {
+        let mut todos = self.all(filter).await?;
+        page.sort.sort(&mut todos);
+        let (projection, _) = self.project().await?;
+        todos
+            .into_iter()
+            .skip(page.offset)
+            .take(page.limit)
+            .map(|todo| {
+                let updated_at = projection
+                    .modified_at
+                    .get(&todo.id)
+                    .copied()
+                    .ok_or_else(|| todo.id.not_found())?;
+                Ok(ListedTodo { todo, updated_at })
+            })
+            .collect()
+    }
+
+    async fn update(&self, id: I, payload: UpdateTodo<I>) -> anyhow::Result<TodoEntity<I>> {
+        let (projection, labels) = self.existing(id).await?;
+        let todo = &projection.todos[&id];
+        let mut events = vec![];
+        if let Some(text) = payload.text.clone().filter(|text| *text != todo.text) {
+            events.push(TodoEvent::TextChanged { text });
+        }
+        let status = payload.next_status(todo.status);
+        if status != todo.status {
+            events.push(TodoEvent::StatusChanged { status });
+        }
+        let icon = payload.next_icon(todo.icon.clone());
+        if icon != todo.icon {
+            events.push(TodoEvent::IconChanged { icon });
+        }
+        let color = payload.next_color(todo.color.clone());
+        if color != todo.color {
+            events.push(TodoEvent::ColorChanged { color });
+        }
+        let remind_at = payload.next_remind_at(todo.remind_at);
+        if remind_at != todo.remind_at {
+            events.push(TodoEvent::ReminderSet { remind_at });
+        }
+        let estimate_minutes = payload.next_estimate_minutes(todo.estimate_minutes);
+        if estimate_minutes != todo.estimate_minutes {
+            events.push(TodoEvent::EstimateChanged { estimate_minutes });
+        }
+        let due_at = payload.next_due_at(todo.due_at);
+        if due_at != todo.due_at {
+            events.push(TodoEvent::DueChanged { due_at });
+        }
+        let description = payload.next_description(todo.description.clone());
+        if description != todo.description {
+            events.push(TodoEvent::DescriptionChanged { description });
+        }
+        if let Some(label_ids) = payload.labels {
+            Self::check_labels(&label_ids, &labels)?;
+            check_label_count(&label_ids, self.max_labels)?;
+            let attached = projection.label_ids.get(&id).cloned().unwrap_or_default();
+            for label_id in attached.iter().filter(|id| !label_ids.contains(id)) {
+                events.push(TodoEvent::LabelDetached {
+                    label_id: *label_id,
+                });
+            }
+            for label_id in label_ids.iter().filter(|id| !attached.contains(id)) {
+                events.push(TodoEvent::LabelAttached {
+                    label_id: *label_id,
+                });
+            }
+        }
+        if let Some(tags) = payload.tags.filter(|tags| *tags != todo.tags) {
+            events.push(TodoEvent::TagsChanged { tags });
+        }
+        self.record(id, events).await
+    }
+
+    async fn delete(&self, id: I) -> anyhow::Result<()> {
+        self.existing(id).await?;
+        self.events.append(id, vec![TodoEvent::Deleted]).await?;
+        Ok(())
+    }
+
+    async fn history(&self, id: I) -> anyhow::Result<Vec<TodoRevision<I>>> {
+        let (projection, labels) = self.existing(id).await?;
+        let revisions = projection.revisions[&id]
+            .iter()
+            .enumerate()
+            .map(
+                |(index, (text, status, label_ids, created_at))| TodoRevision {
+                    revision: index as i64 + 1,
+                    text: text.clone(),
+                    completed: status.is_completed(),
+                    status: *status,
+                    labels: Json(resolve_labels(label_ids, &labels)),
+                    created_at: *created_at,
+                },
+            )
+            .collect();
+        Ok(revisions)
+    }
+
+    async fn set_reminder(
+        &self,
+        id: I,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::ReminderSet { remind_at }])
+            .await
+    }
+
+    async fn reminders(&self) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let (projection, labels) = self.project().await?;
+        let mut todos = projection.find_all(&labels, |todo| todo.remind_at.is_some());
+        todos.sort_by_key(|todo| (todo.remind_at, todo.id));
+        Ok(todos)
+    }
+
+    async fn take_due_reminders(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let (projection, labels) = self.project().await?;
+        let mut due = projection.find_all(&labels, |todo| {
+            todo.remind_at.is_some_and(|remind_at| remind_at <= now)
+        });
+        for todo in due.iter() {
+            self.events
+                .append(todo.id, vec![TodoEvent::ReminderSet { remind_at: None }])
+                .await?;
+        }
+        due.sort_by_key(|todo| (todo.remind_at, todo.id));
+        Ok(due)
+    }
+
+    async fn assign(&self, id: I, assignee: Option<User>) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::Assigned { assignee }])
+            .await
+    }
+
+    async fn move_to_project(
+        &self,
+        id: I,
+        project_id: Option<i32>,
+    ) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::MovedToProject { project_id }])
+            .await
+    }
+
+    async fn set_parent(&self, id: I, parent_id: Option<I>) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::ParentChanged { parent_id }])
+            .await
+    }
+
+    async fn pin(&self, id: I, pinned: bool) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::Pinned { pinned }]).await
+    }
+
+    async fn snooze(&self, id: I, until: Option<DateTime<Utc>>) -> anyhow::Result<TodoEntity<I>> {
+        self.existing(id).await?;
+        self.record(id, vec![TodoEvent::Snoozed { until }]).await
+    }
+
+    async fn attach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let (projection, labels) = self.project().await?;
+        Self::check_labels(&[label_id], &labels)?;
+        // 1つでも上限に達したtodoがあれば、どのtodoにも付けない
+        let full = todo_ids
+            .iter()
+            .any(|id| match projection.label_ids.get(id) {
+                Some(ids) => !ids.contains(&label_id) && ids.len() >= self.max_labels,
+                None => false,
+            });
+        if full {
+            return Err(RepositoryError::TooManyLabels(self.max_labels).into());
+        }
+        let mut attached = vec![];
+        for id in todo_ids {
+            let has_label = match projection.label_ids.get(&id) {
+                Some(ids) => ids.contains(&label_id),
+                None => false,
+            };
+            if !projection.todos.contains_key(&id) || has_label || attached.contains(&id) {
+                continue;
+            }
+            self.events
+                .append(id, vec![TodoEvent::LabelAttached { label_id }])
+                .await?;
+            attached.push(id);
+        }
+        Ok(attached)
+    }
+
+    async fn detach_label(&self, label_id: I, todo_ids: Vec<I>) -> anyhow::Result<Vec<I>> {
+        let (projection, labels) = self.project().await?;
+        Self::check_labels(&[label_id], &labels)?;
+        let mut detached = vec![];
+        for id in todo_ids {
+            let has_label = match projection.label_ids.get(&id) {
+                Some(ids) => ids.contains(&label_id),
+                None => false,
+            };
+            if !projection.todos.contains_key(&id) || !has_label || detached.contains(&id) {
+                continue;
+            }
+            self.events
+                .append(id, vec![TodoEvent::LabelDetached { label_id }])
+                .await?;
+            detached.push(id);
+        }
+        Ok(detached)
+    }
+
+    async fn children(&self, id: I) -> anyhow::Result<Vec<TodoEntity<I>>> {
+        let (projection, labels) = self.project().await?;
+        Ok(projection.find_all(&labels, |todo| todo.parent_id == Some(id)))
+    }
+
+    async fn descendants(&self, id: I) -> anyhow::Result<Vec<I>> {
+        let (projection, _) = self.project().await?;
+        let mut ids = vec![];
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            for todo in projection
+                .todos
+                .values()
+                .filter(|todo| todo.parent_id == Some(id))
+            {
+                if !ids.contains(&todo.id) {
+                    ids.push(todo.id);
+                    pending.push(todo.id);
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn add_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let (projection, _) = self.existing(blocker_id).await?;
+        if !projection.todos.contains_key(&blocked_id) {
+            return Err(blocked_id.not_found().into());
+        }
+        if !projection.dependencies.contains(&(blocker_id, blocked_id)) {
+            self.events
+                .append(blocker_id, vec![TodoEvent::DependencyAdded { blocked_id }])
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_dependency(&self, blocker_id: I, blocked_id: I) -> anyhow::Result<()> {
+        let (projection, _) = self.project().await?;
+        if !projection.dependencies.contains(&(blocker_id, blocked_id)) {
+            return Err(blocked_id.not_found().into());
+        }
+        self.events
+            .append(
+                blocker_id,
+                vec![TodoEvent::DependencyRemoved { blocked_id }],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn dependencies(&self, id: I) -> anyhow::Result<TodoDependencies<I>> {
+        let (projection, _) = self.project().await?;
+        let mut dependencies = TodoDependencies::default();
+        for (blocker_id, blocked_id) in projection.dependencies.iter() {
+            if *blocked_id == id {
+                dependencies.blocked_by.push(*blocker_id);
+            } else if *blocker_id == id {
+                dependencies.blocks.push(*blocked_id);
+            }
+        }
+        Ok(dependencies)
+    }
+
+    async fn dependents(&self, id: I) -> anyhow::Result<Vec<I>> {
+        let (projection, _) = self.project().await?;
+        let mut ids = vec![];
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            for (_, blocked_id) in projection
+                .dependencies
+                .iter()
+                .filter(|(blocker_id, _)| *blocker_id == id)
+            {
+                if !ids.contains(blocked_id) {
+                    ids.push(*blocked_id);
+                    pending.push(*blocked_id);
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn modified_at(&self, id: I) -> anyhow::Result<DateTime<Utc>> {
+        let (projection, _) = self.project().await?;
+        let modified_at = projection
+            .modified_at
+            .get(&id)
+            .copied()
+            .ok_or_else(|| id.not_found())?;
+        Ok(modified_at)
+    }
+
+    // イベントがまだなければ一覧は変わっていないものとしてUNIX時間の起点を返す
+    async fn list_modified_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        let (projection, _) = self.project().await?;
+        let written_at = projection
+            .list_modified_at
+            .unwrap_or_else(|| DateTime::<Utc>::from(std::time::UNIX_EPOCH));
+        Ok(latest_change(
+            written_at,
+            projection.todos.values(),
+            Utc::now(),
+        ))
+    }
+
+    async fn share(&self, id: I, user_id: i32, permission: Permission) -> anyhow::Result<Share<I>> {
+        let (projection, _) = self.existing(id).await?;
+        if projection.shares.get(&(id, user_id)) != Some(&permission) {
+            self.events
+                .append(
+                    id,
+                    vec![TodoEvent::Shared {
+                        user_id,
+                        permission,
+                    }],
+                )
+                .await?;
+        }
+        Ok(Share {
+            todo_id: id,
+            user_id,
+            permission,
+        })
+    }
+
+    async fn unshare(&self, id: I, user_id: i32) -> anyhow::Result<()> {
+        let (projection, _) = self.project().await?;
+        if !projection.shares.contains_key(&(id, user_id)) {
+            return Err(id.not_found().into());
+        }
+        self.events
+            .append(id, vec![TodoEvent::Unshared { user_id }])
+            .await?;
+        Ok(())
+    }
+
+    async fn shares(&self, id: I) -> anyhow::Result<Vec<Share<I>>> {
+        let (projection, _) = self.project().await?;
+        Ok(projection.shares_of(id))
+    }
+
+    async fn suggest(
+        &self,
+        query: &str,
+        visible_to: Visibility,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TodoSuggestion<I>>> {
+        let todos = self
+            .all(TodoFilter {
+                visible_to,
+                ..TodoFilter::default()
+            })
+            .await?;
+        Ok(rank_suggestions(query, todos, limit))
+    }
+
+    async fn cycle_time(&self, range: CompletedRange) -> anyhow::Result<CycleTime> {
+        let (projection, _) = self.project().await?;
+        let seconds = projection
+            .todos
+            .values()
+            .filter_map(|todo| {
+                let completed_at = todo.completed_at.filter(|at| range.contains(*at))?;
+                let created_at = projection.created_at.get(&todo.id)?;
+                Some((completed_at - *created_at).num_milliseconds() as f64 / 1000.0)
+            })
+            .collect();
+        Ok(CycleTime::from_seconds(seconds))
+    }
+
+    // コミットの番号を同期トークンにする
+    async fn changes(&self, since: i64, visible_to: Visibility) -> anyhow::Result<TodoChanges<I>> {
+        let (projection, _) = self.project().await?;
+        let changes = projection
+            .changes
+            .iter()
+            .filter(|(seq, change)| {
+                *seq > since
+                    && visible_to.allows(change.owner_id, &projection.shares_of(change.todo_id))
+            })
+            .map(|(_, change)| change.clone());
+        Ok(TodoChanges::collapse(changes, projection.seq))
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone)]
+    pub struct TodoEventStoreForMemory<I = i32> {
+        commits: Arc<RwLock<Vec<TodoCommit<I>>>>,
+        next_id: Arc<AtomicI32>,
+    }
+
+    impl TodoEventStoreForMemory {
+        pub fn new() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    impl<I: EntityId> TodoEventStoreForMemory<I> {
+        pub fn with_ids() -> Self {
+            TodoEventStoreForMemory {
+                commits: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
+            }
+        }
+    }
+
+    impl<I: EntityId> Default for TodoEventStoreForMemory<I> {
+        fn default() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> TodoEventStore<I> for TodoEventStoreForMemory<I> {
+        async fn next_id(&self) -> anyhow::Result<I> {
+            Ok(I::assign(self.next_id.fetch_add(1, Ordering::SeqCst)))
+        }
+
+        async fn append(
+            &self,
+            todo_id: I,
+            events: Vec<TodoEvent<I>>,
+        ) -> anyhow::Result<TodoCommit<I>> {
+            let mut commits = self.commits.write().unwrap();
+            let commit = TodoCommit {
+                seq: commits.len() as i64 + 1,
+                todo_id,
+                events: Json(events),
+                recorded_at: Utc::now(),
+            };
+            commits.push(commit.clone());
+            Ok(commit)
+        }
+
+        async fn load(&self) -> anyhow::Result<Vec<TodoCommit<I>>> {
+            Ok(self.commits.read().unwrap().clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::TodoEventStoreForMemory;
+    use super::*;
+    use crate::repositories::labels::test_utils::LabelRepositoryForMemory;
+    use crate::repositories::labels::CreateLabel;
+
+    fn repository() -> TodoRepositoryEventSourced<TodoEventStoreForMemory, LabelRepositoryForMemory>
+    {
+        TodoRepositoryEventSourced::new(
+            TodoEventStoreForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn should_project_todos_from_events() {
+        let repository = repository();
+        let label = repository
+            .labels
+            .create(CreateLabel::new("urgent".to_string()))
+            .await
+            .unwrap();
+
+        let todo = repository
+            .create(CreateTodo::new("first".to_string(), vec![label.id]))
+            .await
+            .unwrap();
+        assert_eq!(todo.labels, vec![label.clone()]);
+        assert_eq!(repository.find_by_uuid(todo.uuid).await.unwrap(), todo);
+
+        let done = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    text: Some("first, edited".to_string()),
+                    completed: None,
+                    status: Some(TodoStatus::Done),
+                    labels: Some(vec![]),
+                    tags: None,
+                    icon: None,
+                    color: None,
+                    remind_at: None,
+                    estimate_minutes: None,
+                    due_at: None,
+                    description: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(done.text, "first, edited");
+        assert!(done.completed);
+        assert!(done.completed_at.is_some());
+        assert_eq!(done.labels, vec![]);
+
+        // 変更のない更新は版を増やさない
+        repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        let revisions = repository.history(todo.id).await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].labels.0, vec![label]);
+        assert_eq!(revisions[1].text, "first, edited");
+        assert_eq!(
+            repository
+                .cycle_time(CompletedRange::default())
+                .await
+                .unwrap()
+                .count,
+            1
+        );
+
+        let second = repository
+            .create(CreateTodo::new("second".to_string(), vec![]))
+            .await
+            .unwrap();
+        let ids: Vec<i32> = repository
+            .all(TodoFilter::default())
+            .await
+            .unwrap()
+            .iter()
+            .map(|todo| todo.id)
+            .collect();
+        assert_eq!(ids, vec![second.id, todo.id]);
+
+        repository.delete(todo.id).await.unwrap();
+        assert!(repository.find(todo.id).await.is_err());
+        assert!(repository.history(todo.id).await.is_err());
+        assert!(repository.delete(todo.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_derive_blocked_from_dependencies() {
+        let repository = repository();
+        let blocker = repository
+            .create(CreateTodo::new("blocker".to_string(), vec![]))
+            .await
+            .unwrap();
+        let blocked = repository
+            .create(CreateTodo::new("blocked".to_string(), vec![]))
+            .await
+            .unwrap();
+        repository
+            .set_parent(blocked.id, Some(blocker.id))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.descendants(blocker.id).await.unwrap(),
+            vec![blocked.id]
+        );
+
+        repository
+            .add_dependency(blocker.id, blocked.id)
+            .await
+            .unwrap();
+        assert!(repository.find(blocked.id).await.unwrap().blocked);
+        assert_eq!(
+            repository.dependents(blocker.id).await.unwrap(),
+            vec![blocked.id]
+        );
+
+        repository
+            .update(blocker.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+        assert!(!repository.find(blocked.id).await.unwrap().blocked);
+
+        // 削除すると子は最上位に戻り、依存関係も外れる
+        repository.delete(blocker.id).await.unwrap();
+        let blocked = repository.find(blocked.id).await.unwrap();
+        assert_eq!(blocked.parent_id, None);
+        assert_eq!(
+            repository.dependencies(blocked.id).await.unwrap(),
+            TodoDependencies::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_clear_fields_with_explicit_null() {
+        let repository = repository();
+        let todo = repository
+            .create(CreateTodo::new("patch".to_string(), vec![]))
+            .await
+            .unwrap();
+        let patch = |value| serde_json::from_value::<UpdateTodo>(value).unwrap();
+        let remind_at: DateTime<Utc> = "2030-01-01T09:00:00Z".parse().unwrap();
+        let todo = repository
+            .update(
+                todo.id,
+                patch(serde_json::json!({ "remind_at": remind_at, "icon": "🔥" })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            (todo.remind_at, todo.icon.as_deref()),
+            (Some(remind_at), Some("🔥"))
+        );
+
+        let todo = repository
+            .update(todo.id, patch(serde_json::json!({ "text": "kept" })))
+            .await
+            .unwrap();
+        assert_eq!(
+            (todo.remind_at, todo.icon.as_deref()),
+            (Some(remind_at), Some("🔥"))
+        );
+
+        let todo = repository
+            .update(
+                todo.id,
+                patch(serde_json::json!({ "remind_at": null, "icon": null })),
+            )
+            .await
+            .unwrap();
+        assert_eq!((todo.remind_at, todo.icon.as_deref()), (None, None));
+        assert_eq!(repository.find(todo.id).await.unwrap(), todo);
+    }
+
+    #[tokio::test]
+    async fn should_reject_too_many_labels() {
+        let repository = repository().with_max_labels(1);
+        let mut label_ids = vec![];
+        for name in ["first", "second"] {
+            let label = repository
+                .labels
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .unwrap();
+            label_ids.push(label.id);
+        }
+        let is_too_many = |e: anyhow::Error| {
+            matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::TooManyLabels(1))
+            )
+        };
+
+        let e = repository
+            .create(CreateTodo::new("two labels".to_string(), label_ids.clone()))
+            .await
+            .expect_err("[create] with too many labels returned Ok");
+        assert!(is_too_many(e));
+        let todo = repository
+            .create(CreateTodo::new("one label".to_string(), vec![label_ids[0]]))
+            .await
+            .unwrap();
+        let e = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    labels: Some(label_ids.clone()),
+                    ..UpdateTodo::status(TodoStatus::Backlog)
+                },
+            )
+            .await
+            .expect_err("[update] with too many labels returned Ok");
+        assert!(is_too_many(e));
+        let e = repository
+            .attach_label(label_ids[1], vec![todo.id])
+            .await
+            .expect_err("[attach_label] beyond the limit returned Ok");
+        assert!(is_too_many(e));
+        assert_eq!(repository.find(todo.id).await.unwrap(), todo);
+    }
+
+    #[tokio::test]
+    async fn should_take_due_reminders_once() {
+        let repository = repository();
+        let todo = repository
+            .create(CreateTodo::new("remind".to_string(), vec![]))
+            .await
+            .unwrap();
+        let remind_at = Utc::now() - chrono::Duration::minutes(1);
+        repository
+            .set_reminder(todo.id, Some(remind_at))
+            .await
+            .unwrap();
+        assert_eq!(repository.reminders().await.unwrap().len(), 1);
+
+        let due = repository.take_due_reminders(Utc::now()).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].remind_at, Some(remind_at));
+        assert!(repository
+            .take_due_reminders(Utc::now())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod database_test {
+    use super::*;
+    use crate::repositories::labels::{CreateLabel, LabelRepositoryForDb};
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn should_replay_events_from_database() {
+        let db = TestDatabase::new().await;
+        let labels = LabelRepositoryForDb::new(db.pool.clone());
+        let label = labels
+            .create(CreateLabel::new("event label".to_string()))
+            .await
+            .unwrap();
+        let repository =
+            TodoRepositoryEventSourced::new(TodoEventStoreForDb::new(db.pool.clone()), labels);
+
+        let todo = repository
+            .create(CreateTodo::new("event todo".to_string(), vec![label.id]))
+            .await
+            .unwrap();
+        assert_eq!(todo.uuid.get_version_num(), 7);
+        assert_eq!(todo.labels, vec![label]);
+        repository
+            .update(todo.id, UpdateTodo::status(TodoStatus::Done))
+            .await
+            .unwrap();
+
+        // 別のリポジトリから読み直しても同じ状態になる
+        let replayed = TodoRepositoryEventSourced::new(
+            TodoEventStoreForDb::new(db.pool.clone()),
+            LabelRepositoryForDb::new(db.pool.clone()),
+        );
+        let found = replayed.find(todo.id).await.unwrap();
+        assert_eq!(found.status, TodoStatus::Done);
+        assert!(found.completed_at.is_some());
+        assert_eq!(replayed.history(todo.id).await.unwrap().len(), 2);
+        assert_eq!(
+            replayed
+                .cycle_time(CompletedRange::default())
+                .await
+                .unwrap()
+                .count,
+            1
+        );
+
+        replayed.delete(todo.id).await.unwrap();
+        assert!(repository.find(todo.id).await.is_err());
+
+        db.teardown().await;
+    }
+}
@@ -0,0 +1,168 @@
+use axum::async_trait;
+use chrono::NaiveDate;
+use sqlx::PgPool;
+use tracing::instrument;
+
+// リクエスト主体ごとの1日のリクエスト数
+// 日付はUTCで区切る
+#[async_trait]
+pub trait UsageRepository: Send + Sync + 'static {
+    // 1件数えて、数えたあとのその日のリクエスト数を返す
+    async fn increment(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64>;
+    async fn find(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64>;
+}
+
+#[derive(Debug, Clone)]
+pub struct UsageRepositoryForDb {
+    pool: PgPool,
+}
+
+impl UsageRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UsageRepository for UsageRepositoryForDb {
+    #[instrument(skip_all)]
+    async fn increment(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+        let (requests,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+INSERT INTO api_usage (principal, day, requests) VALUES ($1, $2, 1)
+ON CONFLICT (principal, day) DO UPDATE SET requests = api_usage.requests + 1
+RETURNING requests
+            "#,
+        )
+        .bind(principal)
+        .bind(day)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(requests as u64)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+        let requests = sqlx::query_as::<_, (i64,)>(
+            r#"SELECT requests FROM api_usage WHERE principal = $1 AND day = $2"#,
+        )
+        .bind(principal)
+        .bind(day)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|(requests,)| requests as u64)
+        .unwrap_or_default();
+        Ok(requests)
+    }
+}
+
+// 複数のインスタンスで数を共有する場合はRedisに置く
+// 翌日には使わないので、キーは2日で消えるようにする
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct UsageRepositoryForRedis {
+    redis: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl UsageRepositoryForRedis {
+    const TTL_SECS: usize = 2 * 24 * 60 * 60;
+
+    pub async fn connect(client: &redis::Client) -> anyhow::Result<Self> {
+        let redis = client.get_connection_manager().await?;
+        Ok(Self { redis })
+    }
+
+    fn key(principal: &str, day: NaiveDate) -> String {
+        format!("usage:{}:{}", day, principal)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl UsageRepository for UsageRepositoryForRedis {
+    async fn increment(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+        let key = Self::key(principal, day);
+        let mut redis = self.redis.clone();
+        let (requests,): (u64,) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .expire(&key, Self::TTL_SECS)
+            .ignore()
+            .query_async(&mut redis)
+            .await?;
+        Ok(requests)
+    }
+
+    async fn find(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+        let mut redis = self.redis.clone();
+        let requests: Option<u64> =
+            redis::AsyncCommands::get(&mut redis, Self::key(principal, day)).await?;
+        Ok(requests.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn should_count_requests_per_principal_and_day() {
+        let db = TestDatabase::new().await;
+        let repository = UsageRepositoryForDb::new(db.pool.clone());
+        let today = NaiveDate::from_ymd_opt(2024, 6, 5).unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+
+        assert_eq!(repository.find("alice", today).await.unwrap(), 0);
+        for expected in 1..=3 {
+            let requests = repository
+                .increment("alice", today)
+                .await
+                .expect("[increment] returned Err");
+            assert_eq!(requests, expected);
+        }
+        assert_eq!(repository.increment("bob", today).await.unwrap(), 1);
+        assert_eq!(repository.increment("alice", tomorrow).await.unwrap(), 1);
+        assert_eq!(repository.find("alice", today).await.unwrap(), 3);
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct UsageRepositoryForMemory {
+        store: Arc<Mutex<HashMap<(String, NaiveDate), u64>>>,
+    }
+
+    impl UsageRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    #[async_trait]
+    impl UsageRepository for UsageRepositoryForMemory {
+        async fn increment(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+            let mut store = self.store.lock().unwrap();
+            let requests = store.entry((principal.to_string(), day)).or_default();
+            *requests += 1;
+            Ok(*requests)
+        }
+
+        async fn find(&self, principal: &str, day: NaiveDate) -> anyhow::Result<u64> {
+            let store = self.store.lock().unwrap();
+            Ok(store
+                .get(&(principal.to_string(), day))
+                .copied()
+                .unwrap_or_default())
+        }
+    }
+}
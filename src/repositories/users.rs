@@ -0,0 +1,289 @@
+use crate::i18n::Locale;
+use crate::repositories::todo::TodoSort;
+use crate::repositories::{Key, RepositoryError};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+
+#[async_trait]
+pub trait UserRepository: Send + Sync + 'static {
+    async fn create(&self, name: String) -> anyhow::Result<User>;
+    async fn find(&self, id: i32) -> anyhow::Result<User>;
+    async fn find_by_name(&self, name: &str) -> anyhow::Result<User>;
+    async fn all(&self) -> anyhow::Result<Vec<User>>;
+    // まだ保存していないユーザーには既定の設定を返す
+    async fn preferences(&self, id: i32) -> anyhow::Result<Preferences>;
+    async fn update_preferences(
+        &self,
+        id: i32,
+        preferences: Preferences,
+    ) -> anyhow::Result<Preferences>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+}
+
+// ユーザーごとの設定。指定していない項目はnullで、サーバーの既定に従う
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Preferences {
+    // sortを指定しないときの一覧の並び順
+    pub default_sort: Option<TodoSort>,
+    // Asia/Tokyo などのIANAのタイムゾーン名
+    pub timezone: Option<String>,
+    pub locale: Option<Locale>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForDb {
+    pool: PgPool,
+}
+
+impl UserRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForDb {
+    #[instrument(skip_all)]
+    async fn create(&self, name: String) -> anyhow::Result<User> {
+        let optional_user = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE name = $1"#)
+            .bind(name.clone())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        if let Some(user) = optional_user {
+            return Err(RepositoryError::Duplicate(Key::Id(user.id)).into());
+        }
+
+        let user = sqlx::query_as::<_, User>(r#"INSERT INTO users (name) VALUES ($1) RETURNING *"#)
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, id: i32) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE id = $1"#)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(user)
+    }
+
+    #[instrument(skip_all)]
+    async fn find_by_name(&self, name: &str) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM users WHERE name = $1"#)
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| RepositoryError::Unexpected(format!("unknown user [{}]", name)))?;
+
+        Ok(user)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(r#"SELECT * FROM users ORDER BY users.id ASC"#)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(users)
+    }
+
+    #[instrument(skip_all)]
+    async fn preferences(&self, id: i32) -> anyhow::Result<Preferences> {
+        let preferences = sqlx::query_scalar::<_, Json<Preferences>>(
+            r#"SELECT preferences FROM user_preferences WHERE user_id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(preferences
+            .map(|Json(preferences)| preferences)
+            .unwrap_or_default())
+    }
+
+    #[instrument(skip_all)]
+    async fn update_preferences(
+        &self,
+        id: i32,
+        preferences: Preferences,
+    ) -> anyhow::Result<Preferences> {
+        let Json(preferences) = sqlx::query_scalar::<_, Json<Preferences>>(
+            r#"
+INSERT INTO user_preferences (user_id, preferences)
+SELECT id, $2 FROM users WHERE id = $1
+ON CONFLICT (user_id) DO UPDATE SET preferences = excluded.preferences, updated_at = now()
+RETURNING preferences
+            "#,
+        )
+        .bind(id)
+        .bind(Json(preferences))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(preferences)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let pool = db.pool.clone();
+
+        let repository = UserRepositoryForDb::new(pool);
+        let name = "[users crud_scenario] user";
+
+        // create
+        let user = match repository.find_by_name(name).await {
+            Ok(user) => user,
+            Err(_) => repository
+                .create(name.to_string())
+                .await
+                .expect("[create] returned Err"),
+        };
+        assert_eq!(user.name, name);
+        assert!(repository.create(name.to_string()).await.is_err());
+
+        // find
+        let found = repository.find(user.id).await.expect("[find] returned Err");
+        assert_eq!(user, found);
+
+        // all
+        let users = repository.all().await.expect("[all] returned Err");
+        assert!(users.contains(&user));
+
+        // preferences
+        let preferences = repository
+            .preferences(user.id)
+            .await
+            .expect("[preferences] returned Err");
+        assert_eq!(Preferences::default(), preferences);
+        let expected = Preferences {
+            default_sort: Some(TodoSort::Due),
+            timezone: Some(String::from("Asia/Tokyo")),
+            locale: Some(Locale::Ja),
+        };
+        for _ in 0..2 {
+            let updated = repository
+                .update_preferences(user.id, expected.clone())
+                .await
+                .expect("[update_preferences] returned Err");
+            assert_eq!(expected, updated);
+        }
+        let preferences = repository
+            .preferences(user.id)
+            .await
+            .expect("[preferences] returned Err");
+        assert_eq!(expected, preferences);
+        assert!(repository
+            .update_preferences(-1, Preferences::default())
+            .await
+            .is_err());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone, Default)]
+    pub struct UserRepositoryForMemory {
+        store: Arc<RwLock<Vec<User>>>,
+        preferences: Arc<RwLock<HashMap<i32, Preferences>>>,
+    }
+
+    impl UserRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.preferences.write().unwrap().clear();
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for UserRepositoryForMemory {
+        async fn create(&self, name: String) -> anyhow::Result<User> {
+            let mut store = self.store.write().unwrap();
+            if let Some(user) = store.iter().find(|user| user.name == name) {
+                return Err(RepositoryError::Duplicate(Key::Id(user.id)).into());
+            }
+            let user = User {
+                id: (store.len() + 1) as i32,
+                name,
+            };
+            store.push(user.clone());
+            Ok(user)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<User> {
+            let store = self.store.read().unwrap();
+            let user = store
+                .iter()
+                .find(|user| user.id == id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(user)
+        }
+
+        async fn find_by_name(&self, name: &str) -> anyhow::Result<User> {
+            let store = self.store.read().unwrap();
+            let user = store
+                .iter()
+                .find(|user| user.name == name)
+                .cloned()
+                .ok_or_else(|| RepositoryError::Unexpected(format!("unknown user [{}]", name)))?;
+            Ok(user)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<User>> {
+            Ok(self.store.read().unwrap().clone())
+        }
+
+        async fn preferences(&self, id: i32) -> anyhow::Result<Preferences> {
+            let preferences = self.preferences.read().unwrap();
+            Ok(preferences.get(&id).cloned().unwrap_or_default())
+        }
+
+        async fn update_preferences(
+            &self,
+            id: i32,
+            preferences: Preferences,
+        ) -> anyhow::Result<Preferences> {
+            self.find(id).await?;
+            self.preferences
+                .write()
+                .unwrap()
+                .insert(id, preferences.clone());
+            Ok(preferences)
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::repositories::todo::TodoFilter;
+use crate::repositories::{EntityId, RepositoryError};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use std::marker::PhantomData;
+use tracing::instrument;
+
+#[async_trait]
+pub trait ViewRepository<I: EntityId = i32>: Send + Sync + 'static {
+    async fn create(&self, name: String, filter: TodoFilter<I>) -> anyhow::Result<View<I>>;
+    async fn find(&self, id: i32) -> anyhow::Result<View<I>>;
+    async fn all(&self) -> anyhow::Result<Vec<View<I>>>;
+    async fn update(&self, id: i32, name: String, filter: TodoFilter<I>)
+        -> anyhow::Result<View<I>>;
+    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+#[serde(bound(deserialize = "I: EntityId"))]
+pub struct View<I = i32> {
+    pub id: i32,
+    pub name: String,
+    pub filter: Json<TodoFilter<I>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ViewRepositoryForDb<I = i32> {
+    pool: PgPool,
+    id: PhantomData<I>,
+}
+
+impl ViewRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_ids(pool)
+    }
+}
+
+impl<I: EntityId> ViewRepositoryForDb<I> {
+    pub fn with_ids(pool: PgPool) -> Self {
+        Self {
+            pool,
+            id: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> ViewRepository<I> for ViewRepositoryForDb<I> {
+    #[instrument(skip_all)]
+    async fn create(&self, name: String, filter: TodoFilter<I>) -> anyhow::Result<View<I>> {
+        let view = sqlx::query_as::<_, View<I>>(
+            r#"INSERT INTO views (name, filter) VALUES ($1, $2) RETURNING *"#,
+        )
+        .bind(name)
+        .bind(Json(filter))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(view)
+    }
+
+    #[instrument(skip_all)]
+    async fn find(&self, id: i32) -> anyhow::Result<View<I>> {
+        let view = sqlx::query_as::<_, View<I>>(r#"SELECT * FROM views WHERE id = $1"#)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(view)
+    }
+
+    #[instrument(skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<View<I>>> {
+        let views = sqlx::query_as::<_, View<I>>(r#"SELECT * FROM views ORDER BY views.id ASC"#)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(views)
+    }
+
+    #[instrument(skip_all)]
+    async fn update(
+        &self,
+        id: i32,
+        name: String,
+        filter: TodoFilter<I>,
+    ) -> anyhow::Result<View<I>> {
+        let view = sqlx::query_as::<_, View<I>>(
+            r#"UPDATE views SET name = $1, filter = $2 WHERE id = $3 RETURNING *"#,
+        )
+        .bind(name)
+        .bind(Json(filter))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(view)
+    }
+
+    #[instrument(skip_all)]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"DELETE FROM views WHERE id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::test_db::TestDatabase;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let db = TestDatabase::new().await;
+        let repository = ViewRepositoryForDb::new(db.pool.clone());
+        let filter = TodoFilter {
+            tag: Some("urgent".to_string()),
+            ..Default::default()
+        };
+
+        // create
+        let view = repository
+            .create("[crud_scenario] urgent".to_string(), filter.clone())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(view.name, "[crud_scenario] urgent");
+        assert_eq!(view.filter.0, filter);
+
+        // find
+        let found = repository.find(view.id).await.expect("[find] returned Err");
+        assert_eq!(view, found);
+
+        // all
+        let views = repository.all().await.expect("[all] returned Err");
+        assert_eq!(views, vec![view.clone()]);
+
+        // update
+        let updated = repository
+            .update(
+                view.id,
+                "[crud_scenario] mine".to_string(),
+                TodoFilter {
+                    assignee_id: Some(1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(updated.name, "[crud_scenario] mine");
+        assert_eq!(updated.filter.assignee_id, Some(1));
+        assert_eq!(updated.filter.tag, None);
+
+        // delete
+        repository
+            .delete(view.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repository.find(view.id).await.is_err());
+
+        db.teardown().await;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    #[derive(Debug, Clone)]
+    pub struct ViewRepositoryForMemory<I = i32> {
+        store: Arc<RwLock<BTreeMap<i32, View<I>>>>,
+        next_id: Arc<AtomicI32>,
+    }
+
+    impl<I: EntityId> Default for ViewRepositoryForMemory<I> {
+        fn default() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    impl ViewRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::with_ids()
+        }
+    }
+
+    impl<I: EntityId> ViewRepositoryForMemory<I> {
+        pub fn with_ids() -> Self {
+            Self {
+                store: Arc::default(),
+                next_id: Arc::new(AtomicI32::new(1)),
+            }
+        }
+
+        // 作ったものをすべて捨てて空に戻す
+        pub fn clear(&self) {
+            self.store.write().unwrap().clear();
+            self.next_id.store(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl<I: EntityId> ViewRepository<I> for ViewRepositoryForMemory<I> {
+        async fn create(&self, name: String, filter: TodoFilter<I>) -> anyhow::Result<View<I>> {
+            let mut store = self.store.write().unwrap();
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            let view = View {
+                id,
+                name,
+                filter: Json(filter),
+            };
+            store.insert(id, view.clone());
+            Ok(view)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<View<I>> {
+            let store = self.store.read().unwrap();
+            let view = store
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(view)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<View<I>>> {
+            Ok(self.store.read().unwrap().values().cloned().collect())
+        }
+
+        async fn update(
+            &self,
+            id: i32,
+            name: String,
+            filter: TodoFilter<I>,
+        ) -> anyhow::Result<View<I>> {
+            let mut store = self.store.write().unwrap();
+            let view = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            view.name = name;
+            view.filter = Json(filter);
+            Ok(view.clone())
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::notifier::{Notification, Notifier};
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::EntityId;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+// 通知時刻を過ぎたリマインダーを通知し、送信した件数を返す
+pub async fn dispatch_due_reminders<I: EntityId, T: TodoRepository<I>>(
+    repository: &T,
+    notifier: &dyn Notifier,
+) -> anyhow::Result<usize> {
+    let todos = repository.take_due_reminders(Utc::now()).await?;
+    for todo in todos.iter() {
+        let notification = Notification {
+            subject: format!("Reminder: todo #{}", todo.id),
+            body: todo.text.clone(),
+        };
+        if let Err(e) = notifier.notify(&notification).await {
+            tracing::error!("failed to send reminder for todo #{}: {}", todo.id, e);
+        }
+    }
+    Ok(todos.len())
+}
+
+pub fn spawn_reminder_scheduler<I: EntityId, T: TodoRepository<I>>(
+    repository: T,
+    notifier: Arc<dyn Notifier>,
+    period: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match dispatch_due_reminders(&repository, notifier.as_ref()).await {
+                Ok(0) => {}
+                Ok(count) => tracing::debug!("dispatched {} reminders", count),
+                Err(e) => tracing::error!("failed to scan reminders: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notifier::test_utils::NotifierForMemory;
+    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
+    use crate::repositories::todo::CreateTodo;
+
+    #[tokio::test]
+    async fn should_dispatch_due_reminders_once() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let notifier = NotifierForMemory::default();
+        let todo = repository
+            .create(CreateTodo::new("remind me".to_string(), vec![]))
+            .await
+            .unwrap();
+        repository
+            .create(CreateTodo::new("later".to_string(), vec![]))
+            .await
+            .unwrap();
+        repository
+            .set_reminder(todo.id, Some(Utc::now()))
+            .await
+            .unwrap();
+        repository
+            .set_reminder(2, Some(Utc::now() + chrono::Duration::hours(1)))
+            .await
+            .unwrap();
+
+        let count = dispatch_due_reminders(&repository, &notifier)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        let count = dispatch_due_reminders(&repository, &notifier)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let sent = notifier.sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![Notification {
+                subject: format!("Reminder: todo #{}", todo.id),
+                body: "remind me".to_string(),
+            }]
+        );
+    }
+}
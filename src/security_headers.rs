@@ -0,0 +1,183 @@
+use axum::http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use axum::http::{HeaderMap, HeaderValue, Request, Response};
+use futures_util::future::BoxFuture;
+use std::env;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+// JSONしか返さないので、既定ではどこからも読み込ませず、埋め込ませない
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'";
+
+// すべての応答に付けるセキュリティ関連のヘッダー
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: HeaderMap,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        headers.insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        Self { headers }.content_security_policy(Some(HeaderValue::from_static(
+            DEFAULT_CONTENT_SECURITY_POLICY,
+        )))
+    }
+}
+
+impl SecurityHeaders {
+    // CONTENT_SECURITY_POLICY で既定のCSPを置き換える。空にするとCSPを付けない
+    pub fn from_env() -> anyhow::Result<Self> {
+        let headers = Self::default();
+        let Ok(policy) = env::var("CONTENT_SECURITY_POLICY") else {
+            return Ok(headers);
+        };
+        let policy = policy.trim();
+        if policy.is_empty() {
+            return Ok(headers.content_security_policy(None));
+        }
+        Ok(headers.content_security_policy(Some(policy.parse()?)))
+    }
+
+    pub fn content_security_policy(mut self, policy: Option<HeaderValue>) -> Self {
+        match policy {
+            Some(policy) => self.headers.insert(CONTENT_SECURITY_POLICY, policy),
+            None => self.headers.remove(CONTENT_SECURITY_POLICY),
+        };
+        self
+    }
+
+    // ハンドラが自分で付けたヘッダーはそのままにする
+    fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.headers {
+            if !headers.contains_key(name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// ルーティングできなかった応答やCORSのプリフライトにも付けるため、一番外側に重ねる
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeadersLayer {
+    headers: Arc<SecurityHeaders>,
+}
+
+impl SecurityHeadersLayer {
+    pub fn new(headers: SecurityHeaders) -> Self {
+        Self {
+            headers: Arc::new(headers),
+        }
+    }
+}
+
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecurityHeadersService {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersService<S> {
+    inner: S,
+    headers: Arc<SecurityHeaders>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for SecurityHeadersService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let headers = self.headers.clone();
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = future.await?;
+            headers.apply(res.headers_mut());
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::response::Headers;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn app(headers: SecurityHeaders) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/framed",
+                get(|| async { (Headers(vec![(X_FRAME_OPTIONS, "SAMEORIGIN")]), "framed") }),
+            )
+            .layer(SecurityHeadersLayer::new(headers))
+    }
+
+    fn get_req(path: &str) -> Request<Body> {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_set_security_headers_on_every_response() {
+        for path in ["/", "/missing"] {
+            let res = app(SecurityHeaders::default())
+                .oneshot(get_req(path))
+                .await
+                .unwrap();
+            let headers = res.headers();
+            assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+            assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+            assert_eq!(headers[REFERRER_POLICY], "no-referrer");
+            assert_eq!(
+                headers[CONTENT_SECURITY_POLICY],
+                DEFAULT_CONTENT_SECURITY_POLICY
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_headers_set_by_handler() {
+        let res = app(SecurityHeaders::default())
+            .oneshot(get_req("/framed"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[X_FRAME_OPTIONS], "SAMEORIGIN");
+    }
+
+    #[tokio::test]
+    async fn should_replace_or_remove_content_security_policy() {
+        let headers = SecurityHeaders::default()
+            .content_security_policy(Some(HeaderValue::from_static("default-src 'self'")));
+        let res = app(headers).oneshot(get_req("/")).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_SECURITY_POLICY], "default-src 'self'");
+
+        let headers = SecurityHeaders::default().content_security_policy(None);
+        let res = app(headers).oneshot(get_req("/")).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_SECURITY_POLICY));
+        assert_eq!(res.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
@@ -0,0 +1,198 @@
+use crate::handlers::ApiError;
+use crate::replay::{is_recent, verify_hmac_sha256, ReplayCache};
+use crate::repositories::todo::{CreateTodo, TodoRepository};
+use crate::repositories::users::UserRepository;
+use crate::state::State;
+use axum::body::Bytes;
+use axum::extract::Extension;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+use validator::Validate;
+
+// Slackのスラッシュコマンドからtodoを作る連携
+// `/todo add Buy milk` のように送られたコマンドを受け取り、送った本人にだけ見える返信をする
+pub const SLACK_COMMAND_PATH: &str = "/integrations/slack/command";
+
+const X_SLACK_SIGNATURE: &str = "x-slack-signature";
+const X_SLACK_REQUEST_TIMESTAMP: &str = "x-slack-request-timestamp";
+
+// Slackアプリの署名シークレットと、SlackのユーザーIDからAPIのユーザー名への対応表
+// 対応表にないSlackのユーザーからのコマンドは受け付けない
+#[derive(Debug, Clone)]
+pub struct SlackIntegration {
+    signing_secret: String,
+    users: HashMap<String, String>,
+    requests: ReplayCache,
+}
+
+impl SlackIntegration {
+    pub fn new(signing_secret: impl Into<String>) -> Self {
+        Self {
+            signing_secret: signing_secret.into(),
+            users: HashMap::new(),
+            requests: ReplayCache::new(),
+        }
+    }
+
+    // SLACK_SIGNING_SECRET でSlackアプリの署名シークレットを、
+    // SLACK_USERS="<Slack user id>=<user name>,..." でユーザーの対応を指定する
+    // シークレットを指定しない場合は連携しない
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let mut slack = match env::var("SLACK_SIGNING_SECRET") {
+            Ok(secret) if !secret.is_empty() => Self::new(secret),
+            _ => return Ok(None),
+        };
+        if let Ok(users) = env::var("SLACK_USERS") {
+            for entry in users.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let (slack_user_id, name) = entry
+                    .trim()
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid slack user: [{}]", entry))?;
+                slack = slack.user(slack_user_id, name);
+            }
+        }
+        Ok(Some(slack))
+    }
+
+    pub fn user(mut self, slack_user_id: &str, name: &str) -> Self {
+        self.users
+            .insert(slack_user_id.to_string(), name.to_string());
+        self
+    }
+
+    // 署名は "v0:<timestamp>:<本文>" に対するHMAC-SHA256
+    // 古い時刻の署名と、一度受け付けた署名は拒否する
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), ApiError> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let unauthorized = ApiError::new(StatusCode::UNAUTHORIZED, "auth.unauthorized");
+        let timestamp = header(X_SLACK_REQUEST_TIMESTAMP).unwrap_or_default();
+        let signature = header(X_SLACK_SIGNATURE).unwrap_or_default();
+        let hex = signature.strip_prefix("v0=").unwrap_or_default();
+        let message: [&[u8]; 4] = [b"v0:", timestamp.as_bytes(), b":", body];
+        if !verify_hmac_sha256(&self.signing_secret, &message, hex) {
+            return Err(unauthorized);
+        }
+        match timestamp.parse() {
+            Ok(signed_at) if is_recent(signed_at, Utc::now().timestamp()) => {}
+            _ => return Err(unauthorized),
+        }
+        if !self.requests.first_seen(signature, Instant::now()) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "integration.replayed"));
+        }
+        Ok(())
+    }
+}
+
+// Slackがフォームで送るペイロードのうち、使う項目だけ
+#[derive(Debug, Deserialize)]
+struct SlashCommand {
+    user_id: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SubCommand<'a> {
+    Add(&'a str),
+    Help,
+}
+
+impl<'a> SubCommand<'a> {
+    fn parse(text: &'a str) -> Self {
+        match text.trim().split_once(char::is_whitespace) {
+            Some(("add", text)) if !text.trim().is_empty() => SubCommand::Add(text.trim()),
+            _ => SubCommand::Help,
+        }
+    }
+}
+
+const HELP: &str = "Usage: `/todo add <text>`";
+
+// Slackに返すメッセージ。送った本人にだけ表示する
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SlackReply {
+    pub response_type: String,
+    pub text: String,
+}
+
+impl SlackReply {
+    fn ephemeral(text: impl Into<String>) -> Json<Self> {
+        Json(SlackReply {
+            response_type: String::from("ephemeral"),
+            text: text.into(),
+        })
+    }
+}
+
+// POST /integrations/slack/command
+// 署名を確かめられない場合はエラーにし、コマンドの誤りはSlackに表示する返信で伝える
+pub async fn slack_command<S: State>(
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(state): Extension<S>,
+    slack: Option<Extension<Arc<SlackIntegration>>>,
+) -> Result<Json<SlackReply>, ApiError> {
+    let Some(Extension(slack)) = slack else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    slack.verify(&headers, &body)?;
+    let command: SlashCommand =
+        serde_urlencoded::from_bytes(&body).or(Err(StatusCode::BAD_REQUEST))?;
+
+    let Some(name) = slack.users.get(&command.user_id) else {
+        return Ok(SlackReply::ephemeral(
+            "Your Slack account is not linked to a todo user.",
+        ));
+    };
+    let Ok(user) = state.users().find_by_name(name).await else {
+        return Ok(SlackReply::ephemeral(format!(
+            "User [{}] is not found.",
+            name
+        )));
+    };
+    let text = match SubCommand::parse(&command.text) {
+        SubCommand::Add(text) => text,
+        SubCommand::Help => return Ok(SlackReply::ephemeral(HELP)),
+    };
+    let payload =
+        CreateTodo::from_template(text.to_string(), vec![], vec![]).owned_by(Some(user.id));
+    if payload.validate().is_err() {
+        return Ok(SlackReply::ephemeral("The todo text is too long."));
+    }
+    let todo = state
+        .todos()
+        .create(payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(SlackReply::ephemeral(format!(
+        "Added todo #{}: {}",
+        todo.id, todo.text
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_add_command() {
+        assert_eq!(
+            SubCommand::parse("add Buy milk"),
+            SubCommand::Add("Buy milk")
+        );
+        assert_eq!(
+            SubCommand::parse("  add   Buy milk  "),
+            SubCommand::Add("Buy milk")
+        );
+        assert_eq!(SubCommand::parse("add"), SubCommand::Help);
+        assert_eq!(SubCommand::parse("add   "), SubCommand::Help);
+        assert_eq!(SubCommand::parse("list"), SubCommand::Help);
+        assert_eq!(SubCommand::parse(""), SubCommand::Help);
+    }
+}
@@ -0,0 +1,48 @@
+use std::env;
+use thiserror::Error;
+
+// 起動時のエラー
+// 設定の誤りか接続先の問題かで対処が変わるため区別する
+#[derive(Debug, Error)]
+pub enum StartupError {
+    #[error("[{0}] is undefined, set it in the environment or .env")]
+    MissingConfig(&'static str),
+    #[error("[{name}] is invalid, fix the value and restart")]
+    InvalidConfig {
+        name: &'static str,
+        source: anyhow::Error,
+    },
+    #[error("failed to connect {service}, check that it is running and reachable")]
+    Connect {
+        service: &'static str,
+        source: anyhow::Error,
+    },
+    #[error("database schema is out of date, run the missing migrations and restart")]
+    OutdatedSchema { source: anyhow::Error },
+    #[error("failed to serve on {addr}, check that the port is free")]
+    Serve {
+        addr: std::net::SocketAddr,
+        source: anyhow::Error,
+    },
+}
+
+impl StartupError {
+    pub fn invalid<E: Into<anyhow::Error>>(name: &'static str) -> impl FnOnce(E) -> Self {
+        move |source| StartupError::InvalidConfig {
+            name,
+            source: source.into(),
+        }
+    }
+
+    pub fn connect<E: Into<anyhow::Error>>(service: &'static str) -> impl FnOnce(E) -> Self {
+        move |source| StartupError::Connect {
+            service,
+            source: source.into(),
+        }
+    }
+}
+
+// 必須の環境変数を読み込む
+pub fn required_env(name: &'static str) -> Result<String, StartupError> {
+    env::var(name).map_err(|_| StartupError::MissingConfig(name))
+}
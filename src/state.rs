@@ -0,0 +1,126 @@
+use crate::events::EventBus;
+use crate::repositories::audit::{AuditLogRepository, UndoTodoRepository};
+use crate::repositories::labels::LabelRepository;
+use crate::repositories::projects::ProjectRepository;
+use crate::repositories::share_links::ShareLinkRepository;
+use crate::repositories::templates::TemplateRepository;
+use crate::repositories::users::UserRepository;
+use crate::repositories::views::ViewRepository;
+use crate::repositories::EntityId;
+use std::sync::Arc;
+
+// ハンドラーから参照する依存をまとめたもの
+// axum 0.4 には State がないため、Extension で1つだけ渡して各ハンドラーはここから取り出す
+// リポジトリはトレイトオブジェクトでもよい
+pub trait State: Clone + Send + Sync + 'static {
+    // todoとラベルのidの型
+    type Id: EntityId;
+    type Todo: UndoTodoRepository<Self::Id> + ?Sized;
+    type Label: LabelRepository<Self::Id> + ?Sized;
+    type Audit: AuditLogRepository<Self::Id> + ?Sized;
+    type User: UserRepository + ?Sized;
+    type Project: ProjectRepository + ?Sized;
+    type View: ViewRepository<Self::Id> + ?Sized;
+    type Template: TemplateRepository<Self::Id> + ?Sized;
+    type ShareLink: ShareLinkRepository<Self::Id> + ?Sized;
+
+    fn todos(&self) -> &Self::Todo;
+    fn labels(&self) -> &Self::Label;
+    fn audit_logs(&self) -> &Self::Audit;
+    fn users(&self) -> &Self::User;
+    fn projects(&self) -> &Self::Project;
+    fn views(&self) -> &Self::View;
+    fn templates(&self) -> &Self::Template;
+    fn share_links(&self) -> &Self::ShareLink;
+    fn events(&self) -> &EventBus<Self::Id>;
+}
+
+// リポジトリをトレイトオブジェクトとして持つので、リポジトリごとの型引数を持たない
+// リポジトリを増やすときもフィールドを足すだけでよい。型引数はidの型だけ
+#[derive(Clone)]
+pub struct AppState<I: EntityId = i32> {
+    todos: Arc<dyn UndoTodoRepository<I>>,
+    labels: Arc<dyn LabelRepository<I>>,
+    audit_logs: Arc<dyn AuditLogRepository<I>>,
+    users: Arc<dyn UserRepository>,
+    projects: Arc<dyn ProjectRepository>,
+    views: Arc<dyn ViewRepository<I>>,
+    templates: Arc<dyn TemplateRepository<I>>,
+    share_links: Arc<dyn ShareLinkRepository<I>>,
+    events: EventBus<I>,
+}
+
+impl<I: EntityId> AppState<I> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        todos: impl UndoTodoRepository<I>,
+        labels: impl LabelRepository<I>,
+        audit_logs: impl AuditLogRepository<I>,
+        users: impl UserRepository,
+        projects: impl ProjectRepository,
+        views: impl ViewRepository<I>,
+        templates: impl TemplateRepository<I>,
+        share_links: impl ShareLinkRepository<I>,
+        events: EventBus<I>,
+    ) -> Self {
+        Self {
+            todos: Arc::new(todos),
+            labels: Arc::new(labels),
+            audit_logs: Arc::new(audit_logs),
+            users: Arc::new(users),
+            projects: Arc::new(projects),
+            views: Arc::new(views),
+            templates: Arc::new(templates),
+            share_links: Arc::new(share_links),
+            events,
+        }
+    }
+}
+
+impl<I: EntityId> State for AppState<I> {
+    type Id = I;
+    type Todo = dyn UndoTodoRepository<I>;
+    type Label = dyn LabelRepository<I>;
+    type Audit = dyn AuditLogRepository<I>;
+    type User = dyn UserRepository;
+    type Project = dyn ProjectRepository;
+    type View = dyn ViewRepository<I>;
+    type Template = dyn TemplateRepository<I>;
+    type ShareLink = dyn ShareLinkRepository<I>;
+
+    fn todos(&self) -> &Self::Todo {
+        self.todos.as_ref()
+    }
+
+    fn labels(&self) -> &Self::Label {
+        self.labels.as_ref()
+    }
+
+    fn audit_logs(&self) -> &Self::Audit {
+        self.audit_logs.as_ref()
+    }
+
+    fn users(&self) -> &Self::User {
+        self.users.as_ref()
+    }
+
+    fn projects(&self) -> &Self::Project {
+        self.projects.as_ref()
+    }
+
+    fn views(&self) -> &Self::View {
+        self.views.as_ref()
+    }
+
+    fn templates(&self) -> &Self::Template {
+        self.templates.as_ref()
+    }
+
+    fn share_links(&self) -> &Self::ShareLink {
+        self.share_links.as_ref()
+    }
+
+    fn events(&self) -> &EventBus<I> {
+        &self.events
+    }
+}
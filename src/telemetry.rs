@@ -0,0 +1,80 @@
+use crate::trace_context::TraceContext;
+use axum::http::HeaderMap;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use std::env;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+// スパンをOTLPで送るレイヤー
+// OTEL_EXPORTER_OTLP_ENDPOINT が未定義の場合は送らない
+pub fn layer<S>() -> anyhow::Result<Option<OpenTelemetryLayer<S, Tracer>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) if !endpoint.is_empty() => endpoint,
+        _ => return Ok(None),
+    };
+    let service_name =
+        env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_string());
+
+    // 呼び出し元のトレースを引き継げるようにW3C Trace Contextを使う
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name,
+            )])),
+        )
+        .install_batch(runtime::Tokio)?;
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+// 終了前に送りきれていないスパンを送る
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// traceparentヘッダーがあれば、そのトレースの子としてリクエストのスパンを記録する
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(context);
+}
+
+// スパンをOTLPで送る場合は、送ったスパンのIDを外へのリクエストに付ける
+pub fn use_span_ids(span: &Span, trace: &mut TraceContext) {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        trace.trace_id = u128::from_be_bytes(span_context.trace_id().to_bytes());
+        trace.span_id = u64::from_be_bytes(span_context.span_id().to_bytes());
+        trace.flags = span_context.trace_flags().to_u8();
+    }
+}
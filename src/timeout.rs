@@ -0,0 +1,135 @@
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    // 処理中のリクエストの期限。タイムアウトしないルートではNone
+    static DEADLINE: Option<Instant>;
+}
+
+// 期限付きで処理する。期限を過ぎるかクライアントが切断すると、futureごと破棄される
+pub async fn with_deadline<F: Future>(deadline: Option<Instant>, f: F) -> F::Output {
+    DEADLINE.scope(deadline, f).await
+}
+
+pub fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok().flatten()
+}
+
+// リクエストを処理中のタスクか
+// 途中で破棄されうるので、リポジトリは実行中の問い合わせを取り消せるようにする
+pub fn in_request() -> bool {
+    DEADLINE.try_with(|_| ()).is_ok()
+}
+
+// リクエストのタイムアウト設定
+// ルート単位で上書きでき、Noneを指定したルートはタイムアウトしない
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    default: Duration,
+    routes: HashMap<String, Option<Duration>>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT)
+    }
+}
+
+impl Timeouts {
+    pub fn new(default: Duration) -> Self {
+        Self {
+            default,
+            routes: HashMap::new(),
+        }
+    }
+
+    // REQUEST_TIMEOUT_SECS で既定のタイムアウトを、
+    // REQUEST_TIMEOUT_ROUTES="<path>=<secs>,..." でルート単位の上書きを指定する
+    // 秒数に0を指定したルートはタイムアウトしない
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut timeouts = match env::var("REQUEST_TIMEOUT_SECS") {
+            Ok(secs) if !secs.is_empty() => Self::new(Duration::from_secs(secs.parse()?)),
+            _ => Self::default(),
+        };
+        if let Ok(routes) = env::var("REQUEST_TIMEOUT_ROUTES") {
+            for entry in routes.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let (path, secs) = entry
+                    .trim()
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid route timeout: [{}]", entry))?;
+                let timeout = match secs.parse()? {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs)),
+                };
+                timeouts = timeouts.route(path, timeout);
+            }
+        }
+        Ok(timeouts)
+    }
+
+    pub fn route(mut self, path: &str, timeout: Option<Duration>) -> Self {
+        self.routes.insert(path.to_string(), timeout);
+        self
+    }
+
+    fn for_route(&self, path: &str) -> Option<Duration> {
+        self.routes.get(path).copied().unwrap_or(Some(self.default))
+    }
+}
+
+// ルーティング後に適用し、時間内に応答できなければ504を返す
+pub async fn enforce_timeout(req: Request<Body>, next: Next<Body>) -> impl IntoResponse {
+    let timeouts = req
+        .extensions()
+        .get::<Arc<Timeouts>>()
+        .cloned()
+        .unwrap_or_default();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+
+    match timeouts.for_route(&route) {
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            match tokio::time::timeout_at(deadline, with_deadline(Some(deadline), next.run(req)))
+                .await
+            {
+                Ok(res) => res,
+                Err(_) => {
+                    tracing::warn!("{} timed out after {:?}", route, timeout);
+                    StatusCode::GATEWAY_TIMEOUT.into_response()
+                }
+            }
+        }
+        None => with_deadline(None, next.run(req)).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_override_timeout_per_route() {
+        let timeouts = Timeouts::new(Duration::from_secs(3))
+            .route("/flaky", Some(Duration::from_secs(1)))
+            .route("/todos/export", None);
+
+        assert_eq!(timeouts.for_route("/todos"), Some(Duration::from_secs(3)));
+        assert_eq!(timeouts.for_route("/flaky"), Some(Duration::from_secs(1)));
+        assert_eq!(timeouts.for_route("/todos/export"), None);
+    }
+}
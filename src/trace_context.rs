@@ -0,0 +1,193 @@
+use axum::http::{HeaderMap, HeaderValue};
+use std::fmt;
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+// 対応しているtraceparentの版
+const VERSION: &str = "00";
+// 呼び出し元がサンプリングしたか
+const SAMPLED: u8 = 0x01;
+
+tokio::task_local! {
+    static CURRENT_TRACE: TraceContext;
+}
+
+// W3C Trace Contextのうち、このサービスでの処理を表す部分
+// span_idはこのサービスのスパンで、外へのリクエストにはこれを親として付ける
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub flags: u8,
+    // 呼び出し元のベンダー固有の情報。中身は解釈せずに引き継ぐ
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    // traceparentがなければ新しいトレースを始める
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(parent) = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent)
+        else {
+            return Self::generate();
+        };
+        let tracestate = headers
+            .get_all(TRACESTATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        TraceContext {
+            trace_id: parent.0,
+            span_id: generate_span_id(),
+            flags: parent.1,
+            tracestate: Some(tracestate).filter(|state| !state.is_empty()),
+        }
+    }
+
+    pub fn generate() -> Self {
+        TraceContext {
+            trace_id: loop {
+                let id = rand::random();
+                if id != 0 {
+                    break id;
+                }
+            },
+            span_id: generate_span_id(),
+            flags: SAMPLED,
+            tracestate: None,
+        }
+    }
+
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    // 外へのリクエストにこのサービスのスパンを親として付ける
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.to_string()) {
+            headers.insert(TRACEPARENT, value);
+        }
+        if let Some(value) = self
+            .tracestate
+            .as_deref()
+            .and_then(|state| HeaderValue::from_str(state).ok())
+        {
+            headers.insert(TRACESTATE, value);
+        }
+    }
+
+    // 処理中はcurrent_traceで取り出せるようにする
+    pub async fn scope<F: std::future::Future>(self, f: F) -> F::Output {
+        CURRENT_TRACE.scope(self, f).await
+    }
+}
+
+// traceparentヘッダーの値
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{:032x}-{:016x}-{:02x}",
+            VERSION, self.trace_id, self.span_id, self.flags
+        )
+    }
+}
+
+// リクエスト処理中のタスクであれば、そのトレースを返す
+pub fn current_trace() -> Option<TraceContext> {
+    CURRENT_TRACE.try_with(|trace| trace.clone()).ok()
+}
+
+// 読めない値や無効なIDの場合は、呼び出し元のトレースを引き継がない
+fn parse_traceparent(value: &str) -> Option<(u128, u8)> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    // 未知の版は後ろに項目が増えていてもよい
+    if version.len() != 2 || version == "ff" || (version == VERSION && parts.next().is_some()) {
+        return None;
+    }
+    if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+    let is_hex = |s: &str| s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    if ![version, trace_id, parent_id, flags]
+        .into_iter()
+        .all(is_hex)
+    {
+        return None;
+    }
+    let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+    let parent_id = u64::from_str_radix(parent_id, 16).ok()?;
+    if trace_id == 0 || parent_id == 0 {
+        return None;
+    }
+    Some((trace_id, u8::from_str_radix(flags, 16).ok()?))
+}
+
+fn generate_span_id() -> u64 {
+    loop {
+        let id = rand::random();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_continue_incoming_trace() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        headers.append(TRACESTATE, HeaderValue::from_static("congo=t61rcWkgMzE"));
+        headers.append(
+            TRACESTATE,
+            HeaderValue::from_static("rojo=00f067aa0ba902b7"),
+        );
+        let trace = TraceContext::from_headers(&headers);
+
+        assert_eq!(trace.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(trace.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(trace.flags, SAMPLED);
+
+        let mut outbound = HeaderMap::new();
+        trace.inject(&mut outbound);
+        let traceparent = outbound[TRACEPARENT].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(traceparent.ends_with(&format!("-{:016x}-01", trace.span_id)));
+        assert_eq!(
+            outbound[TRACESTATE],
+            "congo=t61rcWkgMzE,rojo=00f067aa0ba902b7"
+        );
+    }
+
+    #[test]
+    fn should_start_new_trace_for_invalid_traceparent() {
+        for value in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+        ] {
+            assert_eq!(parse_traceparent(value), None, "{}", value);
+        }
+        assert_eq!(
+            parse_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"),
+            Some((0x4bf92f3577b34da6a3ce929d0e0e4736, 0))
+        );
+    }
+}
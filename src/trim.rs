@@ -0,0 +1,26 @@
+use serde::{Deserialize, Deserializer};
+
+// 前後の空白を取り除いてから受け取る
+// 空白だけの入力は空文字としてバリデーションで弾く
+pub fn string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(String::deserialize(deserializer)?.trim().to_string())
+}
+
+pub fn option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.map(|s| s.trim().to_string()))
+}
+
+// 前後の空白を取り除き、続いた空白を1つにまとめてから受け取る
+pub fn collapse<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(collapse_whitespace(&String::deserialize(deserializer)?))
+}
+
+pub fn collapse_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.map(|s| collapse_whitespace(&s)))
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
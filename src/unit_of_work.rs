@@ -0,0 +1,214 @@
+use crate::events::{EventBus, Publishing};
+use crate::repositories::audit::{AuditLogRepository, AuditLogRepositoryForDb, Audited};
+use crate::repositories::database::Database;
+use crate::repositories::labels::{LabelRepository, LabelRepositoryForDb};
+use crate::repositories::todo::event_sourced::{
+    TodoEventStoreForDb, TodoRepositoryEventSourced, TodoStore,
+};
+use crate::repositories::todo::{TodoRepository, TodoRepositoryForDb, DEFAULT_MAX_LABELS};
+use crate::repositories::EntityId;
+use axum::async_trait;
+use sqlx::PgPool;
+
+// 1つのトランザクションを共有するリポジトリの組
+// commitするまで書き込みはほかから見えず、確定せずに破棄するとまとめて取り消す
+// 監査ログはtodoやラベルと同じトランザクションに書き、イベントは確定してから発行する
+#[async_trait]
+pub trait UnitOfWork<I: EntityId = i32>: Send + Sync {
+    fn todos(&self) -> &dyn TodoRepository<I>;
+    fn labels(&self) -> &dyn LabelRepository<I>;
+    fn audit_logs(&self) -> &dyn AuditLogRepository<I>;
+    async fn commit(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+// 作業単位を始める
+// 使えない構成ではハンドラーに渡さず、ハンドラーはリポジトリを1つずつ呼ぶ
+#[async_trait]
+pub trait Transactional<I: EntityId = i32>: Send + Sync + 'static {
+    async fn begin(&self) -> anyhow::Result<Box<dyn UnitOfWork<I>>>;
+}
+
+// 作業単位のリポジトリは、create_appと同じく監査ログとイベントの発行を付けて組み立てる
+// キャッシュは通さないので、確定後に発行したイベントで破棄する
+#[derive(Debug, Clone)]
+pub struct UnitOfWorkForDb<I = i32> {
+    pool: PgPool,
+    store: TodoStore,
+    events: EventBus<I>,
+    max_labels: usize,
+}
+
+impl<I: EntityId> UnitOfWorkForDb<I> {
+    pub fn new(pool: PgPool, store: TodoStore, events: EventBus<I>) -> Self {
+        Self {
+            pool,
+            store,
+            events,
+            max_labels: DEFAULT_MAX_LABELS,
+        }
+    }
+
+    pub fn with_max_labels(mut self, max_labels: usize) -> Self {
+        self.max_labels = max_labels;
+        self
+    }
+}
+
+#[async_trait]
+impl<I: EntityId> Transactional<I> for UnitOfWorkForDb<I> {
+    async fn begin(&self) -> anyhow::Result<Box<dyn UnitOfWork<I>>> {
+        let db = Database::begin_shared(&self.pool).await?;
+        let events = self.events.deferred();
+        let audit_logs = AuditLogRepositoryForDb::with_ids(db.clone());
+        let todos: Box<dyn TodoRepository<I>> = match self.store {
+            TodoStore::Table => Box::new(Publishing::new(
+                Audited::new(
+                    TodoRepositoryForDb::with_ids(db.clone()).with_max_labels(self.max_labels),
+                    audit_logs.clone(),
+                ),
+                events.clone(),
+            )),
+            TodoStore::Events => Box::new(Publishing::new(
+                Audited::new(
+                    TodoRepositoryEventSourced::new(
+                        TodoEventStoreForDb::new(db.clone()),
+                        LabelRepositoryForDb::with_ids(db.clone()),
+                    )
+                    .with_max_labels(self.max_labels),
+                    audit_logs.clone(),
+                ),
+                events.clone(),
+            )),
+        };
+        let labels = Publishing::new(
+            Audited::new(
+                LabelRepositoryForDb::with_ids(db.clone()),
+                audit_logs.clone(),
+            ),
+            events.clone(),
+        );
+        Ok(Box::new(DbUnitOfWork {
+            db,
+            events,
+            todos,
+            labels: Box::new(labels),
+            audit_logs: Box::new(audit_logs),
+        }))
+    }
+}
+
+struct DbUnitOfWork<I> {
+    db: Database,
+    events: EventBus<I>,
+    todos: Box<dyn TodoRepository<I>>,
+    labels: Box<dyn LabelRepository<I>>,
+    audit_logs: Box<dyn AuditLogRepository<I>>,
+}
+
+#[async_trait]
+impl<I: EntityId> UnitOfWork<I> for DbUnitOfWork<I> {
+    fn todos(&self) -> &dyn TodoRepository<I> {
+        self.todos.as_ref()
+    }
+
+    fn labels(&self) -> &dyn LabelRepository<I> {
+        self.labels.as_ref()
+    }
+
+    fn audit_logs(&self) -> &dyn AuditLogRepository<I> {
+        self.audit_logs.as_ref()
+    }
+
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        self.db.commit_shared().await?;
+        self.events.flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::audit::{AuditEntity, AuditLogFilter};
+    use crate::repositories::labels::CreateLabel;
+    use crate::repositories::test_db::TestDatabase;
+    use crate::repositories::todo::{CreateTodo, Visibility};
+
+    #[tokio::test]
+    async fn should_commit_or_roll_back_together() {
+        let db = TestDatabase::new().await;
+        let events = EventBus::default();
+        let mut received = events.subscribe();
+        let transactions = UnitOfWorkForDb::new(db.pool.clone(), TodoStore::Table, events);
+        let todos = TodoRepositoryForDb::new(db.pool.clone());
+        let audit_logs = AuditLogRepositoryForDb::new(db.pool.clone());
+
+        // 確定せずに破棄すると、todoもラベルも監査ログも残らない
+        let unit = transactions.begin().await.expect("[begin] returned Err");
+        let label = unit
+            .labels()
+            .create(CreateLabel::new("rolled back".to_string()))
+            .await
+            .expect("[create label] returned Err");
+        unit.todos()
+            .create(CreateTodo::new("rolled back".to_string(), vec![label.id]))
+            .await
+            .expect("[create todo] returned Err");
+        drop(unit);
+        assert_eq!(
+            todos
+                .find_by_text("rolled back", Visibility::All)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(audit_logs
+            .all(AuditLogFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(received.try_recv().is_err());
+
+        // 失敗した呼び出しだけを取り消し、残りはまとめて確定する
+        let unit = transactions.begin().await.expect("[begin] returned Err");
+        let label = unit
+            .labels()
+            .create(CreateLabel::new("committed".to_string()))
+            .await
+            .expect("[create label] returned Err");
+        let todo = unit
+            .todos()
+            .create(CreateTodo::new("committed".to_string(), vec![label.id]))
+            .await
+            .expect("[create todo] returned Err");
+        // UUIDが重なる
+        assert!(unit
+            .todos()
+            .create(CreateTodo::synced(
+                todo.uuid,
+                "duplicated".to_string(),
+                vec![],
+                vec![],
+            ))
+            .await
+            .is_err());
+        assert!(todos.find(todo.id).await.is_err());
+        unit.commit().await.expect("[commit] returned Err");
+
+        let found = todos.find(todo.id).await.expect("[find] returned Err");
+        assert_eq!(found.labels, vec![label]);
+        let logs = audit_logs.all(AuditLogFilter::default()).await.unwrap();
+        assert_eq!(
+            logs.iter()
+                .map(|log| log.entity)
+                .collect::<Vec<AuditEntity>>(),
+            vec![AuditEntity::Label, AuditEntity::Todo]
+        );
+        assert_eq!(received.try_recv().unwrap().event.name(), "label_created");
+        assert_eq!(received.try_recv().unwrap().event.name(), "todo_created");
+        assert!(received.try_recv().is_err());
+
+        db.teardown().await;
+    }
+}
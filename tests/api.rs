@@ -0,0 +1,270 @@
+#![cfg(feature = "test-utils")]
+
+use axum::body::Body;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{Method, Request, StatusCode};
+use reqwest::header::{
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+    ORIGIN,
+};
+use rust_simple_api::auth::ApiKeys;
+use rust_simple_api::create_app;
+use rust_simple_api::events::EventBus;
+use rust_simple_api::repositories::audit::test_utils::AuditLogRepositoryForMemory;
+use rust_simple_api::repositories::labels::test_utils::LabelRepositoryForMemory;
+use rust_simple_api::repositories::labels::Label;
+use rust_simple_api::repositories::projects::test_utils::ProjectRepositoryForMemory;
+use rust_simple_api::repositories::share_links::test_utils::ShareLinkRepositoryForMemory;
+use rust_simple_api::repositories::templates::test_utils::TemplateRepositoryForMemory;
+use rust_simple_api::repositories::todo::test_utils::TodoRepositoryForMemory;
+use rust_simple_api::repositories::users::test_utils::UserRepositoryForMemory;
+use rust_simple_api::repositories::views::test_utils::ViewRepositoryForMemory;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use tower::ServiceExt;
+
+// メモリ上のtodoリポジトリはラベルを初期値からしか引けないため、
+// APIで最初に作るラベルと同じidのものを渡しておく
+fn app() -> axum::Router {
+    create_app(
+        TodoRepositoryForMemory::new(vec![Label::new(1, "urgent".to_string())]),
+        LabelRepositoryForMemory::new(),
+        AuditLogRepositoryForMemory::new(),
+        UserRepositoryForMemory::new(),
+        ProjectRepositoryForMemory::new(),
+        ViewRepositoryForMemory::new(),
+        TemplateRepositoryForMemory::new(),
+        ShareLinkRepositoryForMemory::new(),
+        EventBus::default(),
+        ApiKeys::default(),
+    )
+}
+
+// 空いているポートでサーバーを起動し、そのURLを返す
+fn spawn_app() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app().into_make_service());
+    tokio::spawn(server);
+    format!("http://{}", addr)
+}
+
+async fn to_json(res: axum::response::Response) -> Value {
+    let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+// バイナリを起動せずにプロセス内でAPIを動かせる
+#[tokio::test]
+async fn should_serve_api_in_process() {
+    let app = app();
+    let req = Request::builder()
+        .uri("/todos")
+        .method(Method::POST)
+        .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .body(Body::from(r#"{ "text": "embedded", "labels": [] }"#))
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let created = to_json(res).await;
+    assert_eq!(created["text"], "embedded");
+
+    let req = Request::builder()
+        .uri(format!("/todos/{}", created["id"]))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_json(res).await, created);
+}
+
+#[tokio::test]
+async fn should_handle_todo_flow_over_http() {
+    let base = spawn_app();
+    let client = reqwest::Client::new();
+
+    // create
+    let res = client
+        .post(format!("{}/todos", base))
+        .json(&json!({ "text": "write tests", "labels": [] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let todo: Value = res.json().await.unwrap();
+    let id = todo["id"].as_i64().unwrap();
+
+    // list
+    let res = client.get(format!("{}/todos", base)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-total-count"], "1");
+    let todos: Vec<Value> = res.json().await.unwrap();
+    assert_eq!(todos, vec![todo]);
+
+    // update
+    let res = client
+        .patch(format!("{}/todos/{}", base, id))
+        .json(&json!({ "text": "write more tests", "completed": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated: Value = res.json().await.unwrap();
+    assert_eq!(updated["text"], "write more tests");
+    assert_eq!(updated["completed"], true);
+
+    // labels
+    let res = client
+        .post(format!("{}/labels", base))
+        .json(&json!({ "name": "urgent" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let label: Value = res.json().await.unwrap();
+    let label_id = label["id"].as_i64().unwrap();
+    let res = client
+        .patch(format!("{}/todos/{}", base, id))
+        .json(&json!({ "labels": [label_id] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = client
+        .get(format!("{}/labels/{}/todos", base, label_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let labeled: Vec<Value> = res.json().await.unwrap();
+    assert_eq!(labeled.len(), 1);
+    assert_eq!(labeled[0]["id"], id);
+    assert_eq!(labeled[0]["labels"][0]["name"], "urgent");
+
+    // delete
+    let res = client
+        .delete(format!("{}/todos/{}", base, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let res = client
+        .get(format!("{}/todos/{}", base, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn should_answer_cors_preflight() {
+    let base = spawn_app();
+    let res = reqwest::Client::new()
+        .request(Method::OPTIONS, format!("{}/todos", base))
+        .header(ORIGIN, "http://localhost:5173")
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://localhost:5173"
+    );
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/todos", base))
+        .header(ORIGIN, "http://localhost:5173")
+        .send()
+        .await
+        .unwrap();
+    let exposed = res.headers()[ACCESS_CONTROL_EXPOSE_HEADERS]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(exposed.contains("x-total-count"), "{}", exposed);
+}
+
+#[tokio::test]
+async fn should_return_errors_over_http() {
+    let base = spawn_app();
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("{}/todos", base))
+        .json(&json!({ "text": "", "labels": [] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let res = client
+        .post(format!("{}/todos", base))
+        .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .body("{ not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = client
+        .get(format!("{}/todos/0", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = client
+        .get(format!("{}/todos/999", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = client
+        .get(format!("{}/todos?page=0", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn should_stream_events_over_sse() {
+    let base = spawn_app();
+    let client = reqwest::Client::new();
+
+    // レスポンスが返った時点で購読が始まっている
+    let mut events = client.get(format!("{}/events", base)).send().await.unwrap();
+    assert_eq!(events.status(), StatusCode::OK);
+    assert_eq!(events.headers()[CONTENT_TYPE], "text/event-stream");
+
+    let res = client
+        .post(format!("{}/todos", base))
+        .json(&json!({ "text": "streamed", "labels": [] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let mut received = String::new();
+    while !received.ends_with("\n\n") {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), events.chunk())
+            .await
+            .expect("no event within timeout")
+            .unwrap()
+            .unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    assert!(received.starts_with("event: todo_created\n"));
+    let data = received
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))
+        .unwrap();
+    let event: Value = serde_json::from_str(data).unwrap();
+    assert_eq!(event["type"], "todo_created");
+    assert_eq!(event["actor"], "anonymous");
+    assert_eq!(event["todo"]["text"], "streamed");
+}